@@ -0,0 +1,46 @@
+//! Minimal browser fetch through webtor
+//!
+//! Exposes a single `torFetch(url)` function to JavaScript. The first call
+//! connects to Snowflake and builds a circuit; later calls reuse the client.
+//!
+//! Build with:
+//!   wasm-pack build examples/browser-fetch --target web --out-dir pkg
+//! then serve `examples/browser-fetch/` and open `index.html`.
+
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use webtor::{TorClient, TorClientOptions};
+
+thread_local! {
+    // WASM is single-threaded, so one client per page is enough
+    static CLIENT: RefCell<Option<TorClient>> = const { RefCell::new(None) };
+}
+
+/// Return the shared client, creating it on first use
+async fn client() -> Result<TorClient, JsValue> {
+    if let Some(client) = CLIENT.with(|c| c.borrow().clone()) {
+        return Ok(client);
+    }
+
+    let options = TorClientOptions::snowflake()
+        .with_create_circuit_early(true)
+        .with_connection_timeout(30_000)
+        .with_circuit_timeout(120_000);
+    let client = TorClient::new(options).await.map_err(to_js)?;
+    client.wait_for_circuit().await.map_err(to_js)?;
+
+    CLIENT.with(|c| *c.borrow_mut() = Some(client.clone()));
+    Ok(client)
+}
+
+/// Fetch `url` through Tor and return the response body as text
+#[wasm_bindgen(js_name = torFetch)]
+pub async fn tor_fetch(url: String) -> Result<String, JsValue> {
+    let client = client().await?;
+    let response = client.fetch(&url).await.map_err(to_js)?;
+    response.text().map_err(to_js)
+}
+
+fn to_js(e: webtor::TorError) -> JsValue {
+    JsValue::from_str(&format!("{} ({})", e, e.code()))
+}
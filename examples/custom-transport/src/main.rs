@@ -0,0 +1,58 @@
+//! Drive webtor over a transport implemented outside the crate
+//!
+//! The transport here is the simplest one possible: a plain TCP connection to
+//! a bridge's ORPort. Any byte stream works the same way - implement
+//! `webtor::Transport` by opening the stream and putting Tor link TLS on top
+//! with `TorLinkStream::handshake`, then register it under a name and select
+//! it with `TorClientOptions::custom`. The client reconnects through it like
+//! through a built-in transport.
+//!
+//! Usage:
+//!   WEBTOR_ORPORT=203.0.113.5:443 WEBTOR_BRIDGE_FINGERPRINT=... \
+//!     cargo run -p webtor-example-custom-transport
+
+use std::sync::Arc;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use webtor::transport::TorLinkStream;
+use webtor::{LinkStream, TorClient, TorClientOptions, TorError, Transport};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A direct TCP connection to a bridge's ORPort
+struct OrPort {
+    addr: String,
+}
+
+#[async_trait::async_trait]
+impl Transport for OrPort {
+    async fn connect(&self) -> webtor::Result<Box<dyn LinkStream>> {
+        let tcp = tokio::net::TcpStream::connect(&self.addr)
+            .await
+            .map_err(|e| TorError::Network(format!("Failed to connect to ORPort: {}", e)))?;
+        Ok(Box::new(TorLinkStream::handshake(tcp.compat()).await?))
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), BoxError> {
+    tracing_subscriber::fmt()
+        .with_env_filter("webtor=info")
+        .init();
+
+    let addr = std::env::var("WEBTOR_ORPORT").map_err(|_| "WEBTOR_ORPORT is not set")?;
+    let fingerprint = std::env::var("WEBTOR_BRIDGE_FINGERPRINT")
+        .map_err(|_| "WEBTOR_BRIDGE_FINGERPRINT is not set")?;
+
+    println!("Connecting to ORPort {}", addr);
+    let options = TorClientOptions::custom("orport".to_string(), fingerprint)
+        .with_transport("orport", Arc::new(OrPort { addr }))
+        .with_connection_timeout(30_000)
+        .with_circuit_timeout(120_000);
+    let client = TorClient::new(options).await?;
+
+    let response = client.get("https://check.torproject.org/api/ip").await?;
+    println!("HTTP {}: {}", response.status, response.text()?);
+
+    client.close().await;
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! Fetch a .onion URL with webtor
+//!
+//! Onion services are not routed yet: the request currently fails and the
+//! example shows how to inspect the typed error. Once hidden-service support
+//! lands, the same code returns the page.
+//!
+//! Usage:
+//!   cargo run -p webtor-example-onion-fetch -- http://<56-char-address>.onion/
+
+use webtor::{TorClient, TorClientOptions};
+
+/// DuckDuckGo's v3 onion service
+const DEFAULT_URL: &str = "https://duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion/";
+
+const DEFAULT_BRIDGE_URL: &str = "https://fdmf.ch/QCjqMFJumKjWgB7BFaOc04dN";
+const DEFAULT_BRIDGE_FINGERPRINT: &str = "58DA67BD879E9239FCD4A590E25118BB2118CB3C";
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter("webtor=info")
+        .init();
+
+    let url = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_URL.to_string());
+    let bridge_url =
+        std::env::var("WEBTOR_BRIDGE_URL").unwrap_or_else(|_| DEFAULT_BRIDGE_URL.to_string());
+    let fingerprint = std::env::var("WEBTOR_BRIDGE_FINGERPRINT")
+        .unwrap_or_else(|_| DEFAULT_BRIDGE_FINGERPRINT.to_string());
+
+    let options = TorClientOptions::webtunnel(bridge_url, fingerprint)
+        .with_create_circuit_early(true)
+        .with_connection_timeout(30_000)
+        .with_circuit_timeout(120_000);
+
+    let client = match TorClient::new(options).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Failed to create Tor client: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Fetching {}", url);
+    match client.fetch(&url).await {
+        Ok(response) => {
+            println!("HTTP {}", response.status);
+            match response.text() {
+                Ok(body) => println!("{}", body),
+                Err(e) => eprintln!("Body is not UTF-8: {}", e),
+            }
+        }
+        Err(e) => {
+            eprintln!(
+                "Fetch failed: {} (kind: {:?}, code: {}, retryable: {})",
+                e,
+                e.kind(),
+                e.code(),
+                e.is_retryable()
+            );
+        }
+    }
+
+    client.close().await;
+}
@@ -0,0 +1,86 @@
+//! Service Worker that torifies cross-origin fetches
+//!
+//! `sw.js` loads this module and passes every cross-origin `Request` to
+//! `handleFetch`, which replays it through webtor and converts the result back
+//! into a `Response`. Same-origin requests (the page and its assets) bypass Tor.
+//!
+//! Build with:
+//!   wasm-pack build examples/service-worker --target no-modules --out-dir pkg
+//! then serve `examples/service-worker/` and open `index.html`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Headers, Request, Response, ResponseInit};
+use webtor::{TorClient, TorClientOptions};
+
+thread_local! {
+    // Each worker has its own isolate, so one client per worker
+    static CLIENT: RefCell<Option<TorClient>> = const { RefCell::new(None) };
+}
+
+/// Return the worker's client, creating it on first use
+async fn client() -> Result<TorClient, JsValue> {
+    if let Some(client) = CLIENT.with(|c| c.borrow().clone()) {
+        return Ok(client);
+    }
+
+    let options = TorClientOptions::snowflake()
+        .with_create_circuit_early(true)
+        .with_connection_timeout(30_000)
+        .with_circuit_timeout(120_000);
+    let client = TorClient::new(options).await.map_err(to_js)?;
+
+    CLIENT.with(|c| *c.borrow_mut() = Some(client.clone()));
+    Ok(client)
+}
+
+/// Replay `request` through Tor and return the response
+#[wasm_bindgen(js_name = handleFetch)]
+pub async fn handle_fetch(request: Request) -> Result<Response, JsValue> {
+    let method = http::Method::from_bytes(request.method().as_bytes())
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+    let mut headers = HashMap::new();
+    if let Some(entries) = js_sys::try_iter(&request.headers())? {
+        for entry in entries {
+            let pair: js_sys::Array = entry?.unchecked_into();
+            if let (Some(name), Some(value)) = (pair.get(0).as_string(), pair.get(1).as_string()) {
+                headers.insert(name, value);
+            }
+        }
+    }
+
+    let body = if method == http::Method::GET || method == http::Method::HEAD {
+        None
+    } else {
+        let buffer = JsFuture::from(request.array_buffer()?).await?;
+        Some(js_sys::Uint8Array::new(&buffer).to_vec())
+    };
+
+    let client = client().await?;
+    let response = client
+        .request(method, &request.url(), headers, body, None)
+        .await
+        .map_err(to_js)?;
+
+    let response_headers = Headers::new()?;
+    for (name, value) in &response.headers {
+        if let Ok(value) = value.to_str() {
+            response_headers.append(name.as_str(), value)?;
+        }
+    }
+    let init = ResponseInit::new();
+    init.set_status(response.status);
+    init.set_status_text(&response.status_text);
+    init.set_headers(&response_headers);
+
+    let mut body = response.body;
+    Response::new_with_opt_u8_array_and_init(Some(&mut body), &init)
+}
+
+fn to_js(e: webtor::TorError) -> JsValue {
+    JsValue::from_str(&format!("{} ({})", e, e.code()))
+}
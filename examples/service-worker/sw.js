@@ -0,0 +1,16 @@
+// Service Worker that routes cross-origin requests through webtor.
+// Built with `wasm-pack --target no-modules`, which defines `wasm_bindgen`.
+importScripts('./pkg/webtor_example_service_worker.js');
+
+const ready = wasm_bindgen('./pkg/webtor_example_service_worker_bg.wasm');
+
+self.addEventListener('install', () => self.skipWaiting());
+self.addEventListener('activate', (event) => event.waitUntil(self.clients.claim()));
+
+self.addEventListener('fetch', (event) => {
+    const url = new URL(event.request.url);
+    if (url.origin === self.location.origin) {
+        return; // Serve the page and its assets directly
+    }
+    event.respondWith(ready.then(() => wasm_bindgen.handleFetch(event.request)));
+});
@@ -0,0 +1,234 @@
+//! Local SOCKS5 proxy backed by webtor
+//!
+//! Listens on 127.0.0.1:9150 (like Tor Browser) and forwards every CONNECT
+//! request through a Tor circuit using `TorClient::connect`. Only the
+//! no-authentication method and the CONNECT command are supported.
+//!
+//! Usage:
+//!   WEBTOR_BRIDGE_URL=https://... WEBTOR_BRIDGE_FINGERPRINT=... \
+//!     cargo run -p webtor-example-socks-proxy
+//!   curl --socks5-hostname 127.0.0.1:9150 https://check.torproject.org/api/ip
+//!
+//! To use an obfs4 bridge instead, pass its bridge line arguments:
+//!   WEBTOR_OBFS4_ADDR=203.0.113.5:443 WEBTOR_OBFS4_CERT=... \
+//!     WEBTOR_OBFS4_IAT_MODE=0 WEBTOR_BRIDGE_FINGERPRINT=... \
+//!     cargo run -p webtor-example-socks-proxy
+//!
+//! Or paste whole bridge lines (webtunnel, obfs4, snowflake or meek_lite),
+//! one per line; later lines are fallbacks for the first:
+//!   WEBTOR_BRIDGE_LINE="obfs4 203.0.113.5:443 FINGERPRINT cert=... iat-mode=0" \
+//!     cargo run -p webtor-example-socks-proxy
+//!
+//! `WEBTOR_TLS_FINGERPRINT=chrome-order` (or `firefox-order`) lists the
+//! cipher suites and signature algorithms offered to a WebTunnel bridge in
+//! that browser's order. The ClientHello is still rustls's.
+//!
+//! Where Tor isn't blocked, `WEBTOR_DIRECT=1` skips the bridge and connects
+//! straight to guard relays.
+//!
+//! An existing torrc can be used instead: `WEBTOR_TORRC=/etc/tor/torrc` takes
+//! its `Bridge`, `UseBridges`, `ExitNodes`, `ExcludeNodes` and
+//! `MaxCircuitDirtiness` lines, and listens on its `SocksPort` addresses.
+
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tracing::{info, warn};
+use webtor::torrc::Torrc;
+use webtor::{TlsFingerprint, TorClient, TorClientOptions};
+
+const LISTEN_ADDR: &str = "127.0.0.1:9150";
+
+const DEFAULT_BRIDGE_URL: &str = "https://fdmf.ch/QCjqMFJumKjWgB7BFaOc04dN";
+const DEFAULT_BRIDGE_FINGERPRINT: &str = "58DA67BD879E9239FCD4A590E25118BB2118CB3C";
+
+const SOCKS_VERSION: u8 = 0x05;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xFF;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), BoxError> {
+    tracing_subscriber::fmt()
+        .with_env_filter("webtor=info,webtor_example_socks_proxy=info")
+        .init();
+
+    let fingerprint = std::env::var("WEBTOR_BRIDGE_FINGERPRINT")
+        .unwrap_or_else(|_| DEFAULT_BRIDGE_FINGERPRINT.to_string());
+
+    let tls_fingerprint = match std::env::var("WEBTOR_TLS_FINGERPRINT") {
+        Ok(name) => name.parse()?,
+        Err(_) => TlsFingerprint::default(),
+    };
+
+    let mut listen_addrs = vec![LISTEN_ADDR.parse::<SocketAddr>()?];
+    let options = match (
+        std::env::var("WEBTOR_TORRC"),
+        std::env::var("WEBTOR_BRIDGE_LINE"),
+        std::env::var("WEBTOR_OBFS4_ADDR"),
+        std::env::var("WEBTOR_OBFS4_CERT"),
+    ) {
+        (Ok(path), _, _, _) => {
+            let torrc: Torrc = std::fs::read_to_string(&path)?.parse()?;
+            if !torrc.ignored.is_empty() {
+                info!("Ignoring torrc directives: {}", torrc.ignored.join(", "));
+            }
+            if !torrc.socks_ports.is_empty() {
+                listen_addrs = torrc.socks_ports;
+            }
+            torrc.options
+        }
+        (_, Ok(lines), _, _) => {
+            let lines: Vec<&str> = lines.lines().filter(|l| !l.trim().is_empty()).collect();
+            TorClientOptions::from_bridge_lines(&lines)?
+        }
+        (_, _, Ok(addr), Ok(cert)) => {
+            let iat_mode = std::env::var("WEBTOR_OBFS4_IAT_MODE")
+                .ok()
+                .map(|mode| mode.parse())
+                .transpose()?
+                .unwrap_or(0);
+            TorClientOptions::obfs4(addr, fingerprint, cert, iat_mode)
+        }
+        _ => {
+            let bridge_url = std::env::var("WEBTOR_BRIDGE_URL")
+                .unwrap_or_else(|_| DEFAULT_BRIDGE_URL.to_string());
+            TorClientOptions::webtunnel(bridge_url, fingerprint)
+        }
+    }
+    .with_bridge_tls_fingerprint(tls_fingerprint)
+    .with_create_circuit_early(true)
+    .with_connection_timeout(30_000)
+    .with_circuit_timeout(120_000);
+    let options = if std::env::var("WEBTOR_DIRECT").is_ok_and(|v| v == "1") {
+        options.with_direct(true)
+    } else {
+        options
+    };
+
+    info!("Bootstrapping Tor client...");
+    let client = TorClient::new(options).await?;
+    client.wait_for_circuit().await?;
+
+    let mut listeners = Vec::new();
+    for addr in listen_addrs {
+        let listener = TcpListener::bind(addr).await?;
+        info!("SOCKS5 proxy listening on {}", listener.local_addr()?);
+        listeners.push(serve(listener, client.clone()));
+    }
+    futures::future::try_join_all(listeners).await?;
+    Ok(())
+}
+
+/// Accept SOCKS5 connections on `listener` until it fails
+async fn serve(listener: TcpListener, client: TorClient) -> Result<(), BoxError> {
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let client = client.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(&client, socket).await {
+                warn!("Connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Run the SOCKS5 handshake and relay bytes between the socket and Tor
+async fn handle_connection(client: &TorClient, socket: TcpStream) -> Result<(), BoxError> {
+    let mut socket = socket.compat();
+
+    // Method negotiation: VER NMETHODS METHODS...
+    let mut header = [0u8; 2];
+    socket.read_exact(&mut header).await?;
+    if header[0] != SOCKS_VERSION {
+        return Err(format!("unsupported SOCKS version {}", header[0]).into());
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    socket.read_exact(&mut methods).await?;
+    if !methods.contains(&METHOD_NO_AUTH) {
+        socket
+            .write_all(&[SOCKS_VERSION, METHOD_NONE_ACCEPTABLE])
+            .await?;
+        return Err("client does not offer no-auth method".into());
+    }
+    socket.write_all(&[SOCKS_VERSION, METHOD_NO_AUTH]).await?;
+
+    // Request: VER CMD RSV ATYP DST.ADDR DST.PORT
+    let mut request = [0u8; 4];
+    socket.read_exact(&mut request).await?;
+    if request[1] != CMD_CONNECT {
+        send_reply(&mut socket, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(format!("unsupported SOCKS command {}", request[1]).into());
+    }
+
+    let host = match request[3] {
+        ATYP_IPV4 => {
+            let mut addr = [0u8; 4];
+            socket.read_exact(&mut addr).await?;
+            Ipv4Addr::from(addr).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut addr = [0u8; 16];
+            socket.read_exact(&mut addr).await?;
+            Ipv6Addr::from(addr).to_string()
+        }
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            socket.read_exact(&mut len).await?;
+            let mut name = vec![0u8; len[0] as usize];
+            socket.read_exact(&mut name).await?;
+            String::from_utf8(name)?
+        }
+        other => {
+            send_reply(&mut socket, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(format!("unsupported address type {}", other).into());
+        }
+    };
+    let mut port = [0u8; 2];
+    socket.read_exact(&mut port).await?;
+    let port = u16::from_be_bytes(port);
+
+    info!("CONNECT {}:{}", host, port);
+    let tor_stream = match client.connect(&host, port).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            send_reply(&mut socket, REPLY_GENERAL_FAILURE).await?;
+            return Err(e.into());
+        }
+    };
+    send_reply(&mut socket, REPLY_SUCCEEDED).await?;
+
+    let (mut socket_read, mut socket_write) = socket.split();
+    let (mut tor_read, mut tor_write) = tor_stream.split();
+    let upstream = async {
+        futures::io::copy(&mut socket_read, &mut tor_write).await?;
+        tor_write.close().await
+    };
+    let downstream = async {
+        futures::io::copy(&mut tor_read, &mut socket_write).await?;
+        socket_write.close().await
+    };
+    futures::future::try_join(upstream, downstream).await?;
+
+    Ok(())
+}
+
+/// Send a reply with an all-zero bound address
+async fn send_reply<W>(socket: &mut W, reply: u8) -> std::io::Result<()>
+where
+    W: futures::io::AsyncWrite + Unpin,
+{
+    socket
+        .write_all(&[SOCKS_VERSION, reply, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
@@ -33,6 +33,7 @@ for AUTH in "${AUTHORITIES[@]}"; do
         if head -1 "$OUTPUT_DIR/consensus.txt" | grep -q "network-status-version"; then
             echo " Fetched consensus from $HOST"
             CONSENSUS_FETCHED=true
+            CONSENSUS_SOURCE="$AUTH"
             break
         else
             echo "   Invalid response from $HOST"
@@ -53,6 +54,18 @@ CONSENSUS_SIZE=$(wc -c < "$OUTPUT_DIR/consensus.txt" | tr -d ' ')
 RELAY_COUNT=$(grep -c "^r " "$OUTPUT_DIR/consensus.txt" || echo "0")
 echo "   Consensus size: $CONSENSUS_SIZE bytes, $RELAY_COUNT relays"
 
+# The authorities' key certificates, which clients check the consensus
+# signatures with
+echo " Fetching authority certificates..."
+if ! curl -s --connect-timeout 10 --max-time 60 -o "$OUTPUT_DIR/authority-certs.txt" \
+    "http://${CONSENSUS_SOURCE}/tor/keys/all" \
+    || ! grep -q "^dir-key-certificate-version" "$OUTPUT_DIR/authority-certs.txt"; then
+    echo " Failed to fetch authority certificates from $CONSENSUS_SOURCE"
+    exit 1
+fi
+CERT_COUNT=$(grep -c "^dir-key-certificate-version" "$OUTPUT_DIR/authority-certs.txt")
+echo "   Fetched $CERT_COUNT certificates"
+
 # Now fetch microdescriptors for the first ~500 relays (we only need a subset)
 echo " Fetching microdescriptors..."
 
@@ -123,6 +136,7 @@ echo "  Compressing with Brotli..."
 if command -v brotli &> /dev/null; then
     brotli -9 -k -f "$OUTPUT_DIR/consensus.txt"
     brotli -9 -k -f "$OUTPUT_DIR/microdescriptors.txt"
+    brotli -9 -k -f "$OUTPUT_DIR/authority-certs.txt"
 else
     echo "⚠️  brotli not found, installing..."
     if command -v apt-get &> /dev/null; then
@@ -135,6 +149,7 @@ else
     fi
     brotli -9 -k -f "$OUTPUT_DIR/consensus.txt"
     brotli -9 -k -f "$OUTPUT_DIR/microdescriptors.txt"
+    brotli -9 -k -f "$OUTPUT_DIR/authority-certs.txt"
 fi
 
 COMPRESSED_CONSENSUS=$(wc -c < "$OUTPUT_DIR/consensus.txt.br" | tr -d ' ')
@@ -5,6 +5,7 @@
 
 use crate::error::{Result, TlsError};
 use crate::trust_store::TrustStore;
+use crate::TlsConfig;
 use js_sys::{Array, Object, Reflect, Uint8Array};
 use tracing::{debug, info, trace, warn};
 use wasm_bindgen::prelude::*;
@@ -30,6 +31,8 @@ pub struct CertificateVerifier {
     skip_verification: bool,
     /// Trust store with root CAs
     trust_store: Option<TrustStore>,
+    /// Reject chains that don't terminate at a trusted root
+    strict: bool,
 }
 
 impl CertificateVerifier {
@@ -45,15 +48,35 @@ impl CertificateVerifier {
             server_name: server_name.to_string(),
             skip_verification,
             trust_store,
+            strict: false,
         }
     }
 
+    /// Create a verifier following `config`'s verification and root settings
+    ///
+    /// With `only_custom_roots`, chains must terminate at one of the
+    /// configured roots.
+    pub fn from_config(server_name: &str, config: &TlsConfig) -> Result<Self> {
+        if config.skip_verification
+            || (config.root_certificates.is_empty() && !config.only_custom_roots)
+        {
+            return Ok(Self::new(server_name, config.skip_verification));
+        }
+        let trust_store =
+            TrustStore::with_roots(&config.root_certificates, !config.only_custom_roots)?;
+        Ok(Self {
+            strict: config.only_custom_roots,
+            ..Self::with_trust_store(server_name, trust_store)
+        })
+    }
+
     /// Create a verifier with a custom trust store
     pub fn with_trust_store(server_name: &str, trust_store: TrustStore) -> Self {
         Self {
             server_name: server_name.to_string(),
             skip_verification: false,
             trust_store: Some(trust_store),
+            strict: false,
         }
     }
 
@@ -259,8 +282,14 @@ impl CertificateVerifier {
                 return Ok(());
             }
 
-            // Not in trust store - warn but continue (for now)
-            // In strict mode, this should be an error
+            // Not in trust store - warn but continue, unless only
+            // configured roots may be trusted
+            if self.strict {
+                return Err(TlsError::certificate(format!(
+                    "Certificate chain does not terminate at a configured root. Last cert: {}",
+                    last_cert.subject()
+                )));
+            }
             warn!(
                 "Certificate chain does not terminate at a trusted root. Last cert: {}",
                 last_cert.subject()
@@ -762,6 +791,7 @@ fn convert_ecdsa_signature_from_der_sized(der_sig: &[u8], coord_size: usize) ->
 
 /// Convert ECDSA signature from DER format to raw (r || s) format for P-256
 /// (Legacy wrapper for backward compatibility)
+#[cfg(test)]
 fn convert_ecdsa_signature_from_der(der_sig: &[u8]) -> Result<Vec<u8>> {
     convert_ecdsa_signature_from_der_sized(der_sig, 32)
 }
@@ -928,7 +958,7 @@ mod tests {
 
         assert!(verifier.matches_hostname("example.com"));
         assert!(verifier.matches_hostname("EXAMPLE.COM"));
-        assert!(verifier.matches_hostname("*.example.com") == false); // Wildcard doesn't match apex
+        assert!(!verifier.matches_hostname("*.example.com")); // Wildcard doesn't match apex
         assert!(!verifier.matches_hostname("other.com"));
     }
 
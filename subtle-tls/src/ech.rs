@@ -0,0 +1,239 @@
+//! Encrypted Client Hello (RFC 9849)
+//!
+//! Without ECH the server name travels in the clear in the ClientHello,
+//! where anything on the path (a Tor exit included) can read it. With ECH
+//! the visible ClientHello names only the configuration's public name; the
+//! real server name, with ALPN and any session ticket, is in an inner
+//! ClientHello sealed with HPKE (RFC 9180) to the server's key.
+//!
+//! Configurations come out of band, usually from the `ech` parameter of the
+//! server's DNS HTTPS record. Only the suites [`crate::hpke`] implements are
+//! supported, which covers the configurations large providers publish.
+
+use crate::crypto::{self, Hkdf};
+use crate::error::{Result, TlsError};
+use crate::hpke::{self, Aead, Suite, KDF_HKDF_SHA256, KEM_X25519_HKDF_SHA256};
+
+/// The encrypted_client_hello extension
+pub const EXT_ENCRYPTED_CLIENT_HELLO: u16 = 0xfe0d;
+
+/// The ECHConfig version of RFC 9849
+const ECH_VERSION: u16 = 0xfe0d;
+
+/// ECHClientHello type of the outer ClientHello's extension
+pub const ECH_OUTER: u8 = 0;
+/// ECHClientHello type of the inner ClientHello's extension
+pub const ECH_INNER: u8 = 1;
+
+/// Alert a client sends after authenticating a server that rejected ECH
+pub const ALERT_ECH_REQUIRED: u8 = 121;
+
+/// An ECHConfig this client can use, from an ECHConfigList
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EchConfig {
+    /// The whole encoded ECHConfig, which the HPKE context is bound to
+    raw: Vec<u8>,
+    config_id: u8,
+    /// The server's X25519 public key
+    public_key: Vec<u8>,
+    aead: Aead,
+    /// Longest server name the configuration hides; shorter ones are padded
+    maximum_name_length: u8,
+    public_name: String,
+}
+
+impl EchConfig {
+    /// Pick the first configuration in the ECHConfigList `list` that this
+    /// client supports
+    pub fn select(list: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(list);
+        let mut configs = Reader::new(reader.vec16()?);
+        reader.finish()?;
+        if configs.is_empty() {
+            return Err(TlsError::protocol("Empty ECHConfigList"));
+        }
+
+        while !configs.is_empty() {
+            let start = configs.pos;
+            let version = configs.u16()?;
+            let contents = configs.vec16()?;
+            let raw = &configs.data[start..configs.pos];
+            if version != ECH_VERSION {
+                continue;
+            }
+            if let Some(config) = Self::parse_contents(raw, contents)? {
+                return Ok(config);
+            }
+        }
+        Err(TlsError::protocol(
+            "No supported configuration in ECHConfigList",
+        ))
+    }
+
+    /// Parse ECHConfigContents, or `None` if this client can't use them
+    fn parse_contents(raw: &[u8], contents: &[u8]) -> Result<Option<Self>> {
+        let mut reader = Reader::new(contents);
+        let config_id = reader.u8()?;
+        let kem_id = reader.u16()?;
+        let public_key = reader.vec16()?.to_vec();
+        let mut suites = Reader::new(reader.vec16()?);
+        let maximum_name_length = reader.u8()?;
+        let public_name = reader.vec8()?;
+        let mut extensions = Reader::new(reader.vec16()?);
+        reader.finish()?;
+
+        let mut aead = None;
+        while !suites.is_empty() {
+            let (kdf, aead_id) = (suites.u16()?, suites.u16()?);
+            if kdf == KDF_HKDF_SHA256 && aead.is_none() {
+                aead = Aead::from_id(aead_id);
+            }
+        }
+        // An extension with the high bit set is mandatory, and none are known
+        let mut mandatory = false;
+        while !extensions.is_empty() {
+            mandatory |= extensions.u16()? & 0x8000 != 0;
+            extensions.vec16()?;
+        }
+
+        let public_name = std::str::from_utf8(public_name)
+            .ok()
+            .filter(|name| !name.is_empty() && name.is_ascii())
+            .ok_or_else(|| TlsError::protocol("Invalid ECH public name"))?;
+        match aead {
+            Some(aead)
+                if kem_id == KEM_X25519_HKDF_SHA256
+                    && public_key.len() == hpke::KEY_LEN
+                    && !mandatory =>
+            {
+                Ok(Some(Self {
+                    raw: raw.to_vec(),
+                    config_id,
+                    public_key,
+                    aead,
+                    maximum_name_length,
+                    public_name: public_name.to_ascii_lowercase(),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The name the outer ClientHello carries, whose certificate the server
+    /// presents if it can't decrypt the inner one
+    pub fn public_name(&self) -> &str {
+        &self.public_name
+    }
+
+    /// Pad the body of an inner ClientHello naming `server_name` so its
+    /// length reveals as little as possible about the name
+    pub fn pad_inner(&self, mut encoded: Vec<u8>, server_name: &str) -> Vec<u8> {
+        let max = self.maximum_name_length as usize;
+        let mut padding = max.saturating_sub(server_name.len());
+        padding += 31 - ((encoded.len() + padding + 31) % 32);
+        encoded.resize(encoded.len() + padding, 0);
+        encoded
+    }
+
+    /// Length of the payload sealing an inner ClientHello body of `len` bytes
+    pub fn payload_len(len: usize) -> usize {
+        len + hpke::TAG_LEN
+    }
+
+    /// The outer ClientHello's encrypted_client_hello extension body for an
+    /// encapsulated key `enc`, with a zeroed payload of `payload_len` bytes
+    pub fn outer_extension(&self, enc: &[u8], payload_len: usize) -> Vec<u8> {
+        let mut ext = vec![ECH_OUTER];
+        ext.extend_from_slice(&KDF_HKDF_SHA256.to_be_bytes());
+        ext.extend_from_slice(&self.aead.id().to_be_bytes());
+        ext.push(self.config_id);
+        ext.extend_from_slice(&(enc.len() as u16).to_be_bytes());
+        ext.extend_from_slice(enc);
+        ext.extend_from_slice(&(payload_len as u16).to_be_bytes());
+        ext.resize(ext.len() + payload_len, 0);
+        ext
+    }
+
+    /// Set up an HPKE context sealing to this configuration's key; returns
+    /// the encapsulated key the server needs to open it
+    pub fn setup_sealer(&self) -> Result<(Vec<u8>, hpke::Context)> {
+        let mut info = b"tls ech\0".to_vec();
+        info.extend_from_slice(&self.raw);
+        Suite::new(self.aead).setup_sender(&self.public_key, &info)
+    }
+}
+
+/// The 8 bytes a server that accepted ECH puts at the end of its random:
+/// derived from the inner ClientHello's random and the transcript of the
+/// inner ClientHello and `server_hello` (a whole message) with those
+/// bytes zeroed
+pub async fn accept_confirmation(
+    inner_random: &[u8],
+    inner_hello: &[u8],
+    server_hello: &[u8],
+) -> Result<Vec<u8>> {
+    // Handshake header (4), legacy_version (2), then the random's last 8
+    const CONFIRMATION: std::ops::Range<usize> = 4 + 2 + 24..4 + 2 + 32;
+    let mut server_hello = server_hello.to_vec();
+    server_hello
+        .get_mut(CONFIRMATION)
+        .ok_or_else(|| TlsError::handshake("ServerHello too short"))?
+        .fill(0);
+    let mut transcript = inner_hello.to_vec();
+    transcript.extend_from_slice(&server_hello);
+    let transcript_hash = crypto::sha256(&transcript).await?;
+
+    let prk = Hkdf::extract(&[], inner_random).await?;
+    Hkdf::expand_label(&prk, "ech accept confirmation", &transcript_hash, 8).await
+}
+
+/// Bounds-checked reader over TLS vectors
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| TlsError::protocol("ECHConfigList truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn vec8(&mut self) -> Result<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    fn vec16(&mut self) -> Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn finish(&self) -> Result<()> {
+        if !self.is_empty() {
+            return Err(TlsError::protocol("Trailing bytes in ECHConfigList"));
+        }
+        Ok(())
+    }
+}
@@ -35,6 +35,12 @@ pub enum TlsError {
 
     #[error("SubtleCrypto error: {0}")]
     SubtleCrypto(String),
+
+    /// The server couldn't decrypt the encrypted ClientHello. It proved it
+    /// holds the ECH public name's certificate, so `retry_configs` (an
+    /// ECHConfigList) may be used for a new connection
+    #[error("Server rejected Encrypted Client Hello")]
+    EchRejected { retry_configs: Option<Vec<u8>> },
 }
 
 impl TlsError {
@@ -9,7 +9,9 @@
 //! 6. Finished
 
 use crate::crypto::{self, EcdhKeyPair, Hkdf, X25519KeyPair};
+use crate::ech::{self, EchConfig, ECH_INNER, EXT_ENCRYPTED_CLIENT_HELLO};
 use crate::error::{Result, TlsError};
+use crate::session::SessionTicket;
 use tracing::{debug, trace};
 
 // TLS 1.3 constants
@@ -41,8 +43,17 @@ pub const EXT_SUPPORTED_GROUPS: u16 = 10;
 pub const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
 pub const EXT_ALPN: u16 = 16;
 pub const EXT_SUPPORTED_VERSIONS: u16 = 43;
+pub const EXT_PRE_SHARED_KEY: u16 = 41;
+pub const EXT_PSK_KEY_EXCHANGE_MODES: u16 = 45;
 pub const EXT_KEY_SHARE: u16 = 51;
 
+// PSK key exchange modes
+pub const PSK_DHE_KE: u8 = 1;
+
+/// Length of the PSK binders list ClientHello ends with when resuming: the
+/// list length, then one SHA-256 binder with its length
+const PSK_BINDERS_LEN: usize = 2 + 1 + 32;
+
 // Cipher suites
 pub const TLS_AES_128_GCM_SHA256: u16 = 0x1301;
 pub const TLS_AES_256_GCM_SHA384: u16 = 0x1302;
@@ -93,6 +104,38 @@ pub struct HandshakeState {
     pub server_app_secret: Option<Vec<u8>>,
     /// Exporter master secret for RFC 8446 key export
     pub exporter_master_secret: Option<Vec<u8>>,
+    /// ALPN protocols offered in ClientHello, most preferred first
+    pub alpn_protocols: Vec<String>,
+    /// ALPN protocol the server selected
+    pub alpn_protocol: Option<String>,
+    /// Session offered for resumption
+    pub psk_offer: Option<PskOffer>,
+    /// Whether the server accepted the offered session
+    pub psk_accepted: bool,
+    /// Master secret, for deriving the resumption secret
+    pub master_secret: Option<Vec<u8>>,
+    /// Encrypted Client Hello offered, if the server has a configuration
+    pub ech: Option<EchOffer>,
+    /// Whether the server accepted the encrypted ClientHello
+    pub ech_accepted: bool,
+    /// ECHConfigList a server that rejected ECH sent to use instead
+    pub ech_retry_configs: Option<Vec<u8>>,
+}
+
+/// An Encrypted Client Hello offer
+pub struct EchOffer {
+    pub config: EchConfig,
+    /// The inner ClientHello message, whose transcript the handshake
+    /// follows if the server accepts it
+    pub inner_hello: Vec<u8>,
+}
+
+/// A session ticket offered in ClientHello
+pub struct PskOffer {
+    pub ticket: SessionTicket,
+    /// The PSK the ticket stands for
+    pub psk: Vec<u8>,
+    pub obfuscated_age: u32,
 }
 
 impl HandshakeState {
@@ -117,11 +160,109 @@ impl HandshakeState {
             client_app_secret: None,
             server_app_secret: None,
             exporter_master_secret: None,
+            alpn_protocols: Vec::new(),
+            alpn_protocol: None,
+            psk_offer: None,
+            psk_accepted: false,
+            master_secret: None,
+            ech: None,
+            ech_accepted: false,
+            ech_retry_configs: None,
         })
     }
 
-    /// Build ClientHello message
+    /// Hide the server name behind `config`'s public name
+    pub fn offer_ech(&mut self, config: EchConfig) {
+        self.ech = Some(EchOffer {
+            config,
+            inner_hello: Vec::new(),
+        });
+    }
+
+    /// Offer `ticket` for resumption in the ClientHello
+    pub async fn offer_session(&mut self, ticket: SessionTicket, now_ms: f64) -> Result<()> {
+        let psk = ticket.psk().await?;
+        self.psk_offer = Some(PskOffer {
+            obfuscated_age: ticket.obfuscated_age(now_ms),
+            ticket,
+            psk,
+        });
+        Ok(())
+    }
+
+    /// Build ClientHello message; with ECH offered this is the inner one,
+    /// for [`encrypt_client_hello`](Self::encrypt_client_hello)
     pub fn build_client_hello(&self) -> Vec<u8> {
+        let ech = self.ech.as_ref().map(|_| [ECH_INNER]);
+        self.encode_client_hello(
+            &self.client_random,
+            &self.server_name,
+            ech.as_ref().map(|ech| ech.as_slice()),
+            true,
+        )
+    }
+
+    /// Seal `client_hello` (from build_client_hello, binder filled in) in
+    /// an outer ClientHello naming the ECH public name, if ECH is offered
+    pub async fn encrypt_client_hello(&mut self, client_hello: Vec<u8>) -> Result<Vec<u8>> {
+        let Some(offer) = &mut self.ech else {
+            return Ok(client_hello);
+        };
+        let config = offer.config.clone();
+        let encoded = config.pad_inner(client_hello[4..].to_vec(), &self.server_name);
+        offer.inner_hello = client_hello;
+
+        let (enc, mut sealer) = config.setup_sealer()?;
+        let payload_len = EchConfig::payload_len(encoded.len());
+        let extension = config.outer_extension(&enc, payload_len);
+        // The outer hello gets its own random and never offers the session,
+        // which would link it to the connection the ticket came from
+        let outer_random = crypto::random_bytes(32)?;
+        let mut outer =
+            self.encode_client_hello(&outer_random, config.public_name(), Some(&extension), false);
+
+        // The payload ends the hello: its extension is the last one. It is
+        // sealed over the hello with the payload still zeroed
+        let payload = sealer.seal(&outer[4..], &encoded)?;
+        let payload_start = outer.len() - payload_len;
+        outer[payload_start..].copy_from_slice(&payload);
+        Ok(outer)
+    }
+
+    /// Work out from `server_hello` (the whole message) whether the server
+    /// accepted ECH, switching to the inner ClientHello's transcript if so
+    /// and to authenticating the public name if not
+    pub async fn resolve_ech(&mut self, server_hello: &[u8]) -> Result<()> {
+        let Some(offer) = &self.ech else {
+            return Ok(());
+        };
+        let confirmation =
+            ech::accept_confirmation(&self.client_random, &offer.inner_hello, server_hello).await?;
+        if self.server_random.get(24..) == Some(confirmation.as_slice()) {
+            debug!("Server accepted Encrypted Client Hello");
+            self.ech_accepted = true;
+            self.transcript = offer.inner_hello.clone();
+            self.transcript.extend_from_slice(server_hello);
+            return Ok(());
+        }
+
+        if self.psk_accepted {
+            return Err(TlsError::handshake(
+                "Server resumed a session offered only in the encrypted ClientHello",
+            ));
+        }
+        debug!("Server rejected Encrypted Client Hello");
+        self.server_name = offer.config.public_name().to_string();
+        Ok(())
+    }
+
+    fn encode_client_hello(
+        &self,
+        random: &[u8],
+        server_name: &str,
+        ech: Option<&[u8]>,
+        offer_psk: bool,
+    ) -> Vec<u8> {
         let mut hello = Vec::new();
 
         // Legacy version (TLS 1.2 for compatibility)
@@ -129,7 +270,7 @@ impl HandshakeState {
         hello.push(TLS_VERSION_1_2 as u8);
 
         // Random (32 bytes)
-        hello.extend_from_slice(&self.client_random);
+        hello.extend_from_slice(random);
 
         // Legacy session ID (empty)
         hello.push(0);
@@ -148,7 +289,7 @@ impl HandshakeState {
         hello.push(0);
 
         // Extensions
-        let extensions = self.build_extensions();
+        let extensions = self.build_extensions(server_name, ech, offer_psk);
         hello.push((extensions.len() >> 8) as u8);
         hello.push(extensions.len() as u8);
         hello.extend_from_slice(&extensions);
@@ -164,11 +305,11 @@ impl HandshakeState {
         message
     }
 
-    fn build_extensions(&self) -> Vec<u8> {
+    fn build_extensions(&self, server_name: &str, ech: Option<&[u8]>, offer_psk: bool) -> Vec<u8> {
         let mut extensions = Vec::new();
 
         // Server Name Indication (SNI)
-        let sni = self.build_sni_extension();
+        let sni = build_sni_extension(server_name);
         extensions.push((EXT_SERVER_NAME >> 8) as u8);
         extensions.push(EXT_SERVER_NAME as u8);
         extensions.push((sni.len() >> 8) as u8);
@@ -212,59 +353,80 @@ impl HandshakeState {
         extensions.push(sig_algs.len() as u8);
         extensions.extend_from_slice(&sig_algs);
 
-        // ALPN (Application-Layer Protocol Negotiation) - advertise http/1.1
-        let alpn = self.build_alpn_extension();
-        extensions.push((EXT_ALPN >> 8) as u8);
-        extensions.push(EXT_ALPN as u8);
-        extensions.push((alpn.len() >> 8) as u8);
-        extensions.push(alpn.len() as u8);
-        extensions.extend_from_slice(&alpn);
-
-        extensions
-    }
-
-    fn build_alpn_extension(&self) -> Vec<u8> {
-        // ALPN protocol list - only advertise http/1.1
-        // We don't support HTTP/2, so advertising h2 causes servers to send
-        // HTTP/2 binary frames that we can't parse.
-        // Format: length(2) + [ length(1) + protocol_name ]*
-        let protocols: &[&[u8]] = &[b"http/1.1"];
-        let mut ext = Vec::new();
+        // ALPN (Application-Layer Protocol Negotiation), only if configured
+        if !self.alpn_protocols.is_empty() {
+            let alpn = build_alpn_extension(&self.alpn_protocols);
+            extensions.push((EXT_ALPN >> 8) as u8);
+            extensions.push(EXT_ALPN as u8);
+            extensions.push((alpn.len() >> 8) as u8);
+            extensions.push(alpn.len() as u8);
+            extensions.extend_from_slice(&alpn);
+        }
 
-        // Calculate total list length
-        let list_len: usize = protocols.iter().map(|p| 1 + p.len()).sum();
-        ext.push((list_len >> 8) as u8);
-        ext.push(list_len as u8);
+        // Encrypted Client Hello: a marker in the inner hello, the sealed
+        // inner hello in the outer one
+        if let Some(ech) = ech {
+            extensions.push((EXT_ENCRYPTED_CLIENT_HELLO >> 8) as u8);
+            extensions.push(EXT_ENCRYPTED_CLIENT_HELLO as u8);
+            extensions.push((ech.len() >> 8) as u8);
+            extensions.push(ech.len() as u8);
+            extensions.extend_from_slice(ech);
+        }
 
-        // Add each protocol entry
-        for protocol in protocols {
-            ext.push(protocol.len() as u8);
-            ext.extend_from_slice(protocol);
+        // Resumption: PSK with (EC)DHE only, so a resumed session still gets
+        // forward secrecy. pre_shared_key must be the last extension; its
+        // binder is filled in by fill_psk_binder
+        if let Some(offer) = self.psk_offer.as_ref().filter(|_| offer_psk) {
+            extensions.push((EXT_PSK_KEY_EXCHANGE_MODES >> 8) as u8);
+            extensions.push(EXT_PSK_KEY_EXCHANGE_MODES as u8);
+            extensions.push(0);
+            extensions.push(2); // Length
+            extensions.push(1); // Modes length
+            extensions.push(PSK_DHE_KE);
+
+            let identity = offer.ticket.identity();
+            let identities_len = 2 + identity.len() + 4;
+            let ext_len = 2 + identities_len + PSK_BINDERS_LEN;
+            extensions.push((EXT_PRE_SHARED_KEY >> 8) as u8);
+            extensions.push(EXT_PRE_SHARED_KEY as u8);
+            extensions.push((ext_len >> 8) as u8);
+            extensions.push(ext_len as u8);
+            extensions.push((identities_len >> 8) as u8);
+            extensions.push(identities_len as u8);
+            extensions.push((identity.len() >> 8) as u8);
+            extensions.push(identity.len() as u8);
+            extensions.extend_from_slice(identity);
+            extensions.extend_from_slice(&offer.obfuscated_age.to_be_bytes());
+            extensions.push(0);
+            extensions.push(33); // Binders length
+            extensions.push(32); // Binder length
+            extensions.extend_from_slice(&[0u8; 32]);
         }
 
-        ext
+        extensions
     }
 
-    fn build_sni_extension(&self) -> Vec<u8> {
-        let name_bytes = self.server_name.as_bytes();
-        let mut ext = Vec::new();
-
-        // Server name list length
-        let list_len = 3 + name_bytes.len();
-        ext.push((list_len >> 8) as u8);
-        ext.push(list_len as u8);
-
-        // Server name type (host_name = 0)
-        ext.push(0);
-
-        // Server name length
-        ext.push((name_bytes.len() >> 8) as u8);
-        ext.push(name_bytes.len() as u8);
+    /// Fill in the PSK binder of `client_hello` (from build_client_hello)
+    /// if a session is offered
+    ///
+    /// The binder is an HMAC over the ClientHello up to the binders list,
+    /// keyed from the PSK, proving the client holds it.
+    pub async fn fill_psk_binder(&self, client_hello: &mut [u8]) -> Result<()> {
+        let Some(offer) = &self.psk_offer else {
+            return Ok(());
+        };
+        let truncated_len = client_hello.len() - PSK_BINDERS_LEN;
 
-        // Server name
-        ext.extend_from_slice(name_bytes);
+        let early_secret = Hkdf::extract(&[], &offer.psk).await?;
+        let empty_hash = crypto::sha256(&[]).await?;
+        let binder_key = Hkdf::derive_secret(&early_secret, "res binder", &empty_hash).await?;
+        let finished_key = Hkdf::expand_label(&binder_key, "finished", &[], 32).await?;
+        let truncated_hash = crypto::sha256(&client_hello[..truncated_len]).await?;
+        let binder = hmac_sha256(&finished_key, &truncated_hash).await?;
 
-        ext
+        let binder_start = client_hello.len() - 32;
+        client_hello[binder_start..].copy_from_slice(&binder);
+        Ok(())
     }
 
     fn build_key_share_extension(&self) -> Vec<u8> {
@@ -410,6 +572,16 @@ impl HandshakeState {
                         }
                     }
                 }
+                EXT_PRE_SHARED_KEY => {
+                    // selected_identity; only one identity is ever offered
+                    if self.psk_offer.is_none() || ext_data != [0, 0] {
+                        return Err(TlsError::handshake(
+                            "Server selected a PSK that was not offered",
+                        ));
+                    }
+                    debug!("Server accepted session resumption");
+                    self.psk_accepted = true;
+                }
                 _ => {
                     trace!("Ignoring extension 0x{:04x}", ext_type);
                 }
@@ -419,6 +591,28 @@ impl HandshakeState {
         server_key_share.ok_or_else(|| TlsError::handshake("No key_share in ServerHello"))
     }
 
+    /// Parse EncryptedExtensions, recording the ALPN protocol the server selected
+    pub fn parse_encrypted_extensions(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() < 2 {
+            return Err(TlsError::handshake("EncryptedExtensions too short"));
+        }
+        let ext_len = ((data[0] as usize) << 8) | (data[1] as usize);
+        if 2 + ext_len > data.len() {
+            return Err(TlsError::handshake("EncryptedExtensions truncated"));
+        }
+        if let Some(alpn) = find_extension(&data[2..2 + ext_len], EXT_ALPN)? {
+            let protocol = parse_alpn_extension(alpn, &self.alpn_protocols)?;
+            debug!("Server selected ALPN protocol: {}", protocol);
+            self.alpn_protocol = Some(protocol);
+        }
+        if self.ech.is_some() && !self.ech_accepted {
+            self.ech_retry_configs =
+                find_extension(&data[2..2 + ext_len], EXT_ENCRYPTED_CLIENT_HELLO)?
+                    .map(<[u8]>::to_vec);
+        }
+        Ok(())
+    }
+
     /// Derive handshake keys after receiving ServerHello
     pub async fn derive_handshake_keys(&mut self, server_key_share: &[u8]) -> Result<()> {
         tracing::info!(
@@ -464,10 +658,14 @@ impl HandshakeState {
         tracing::info!("Transcript hash computed: {} bytes", transcript_hash.len());
 
         // TLS 1.3 key schedule
-        // Early Secret = HKDF-Extract(salt=0, IKM=0)
+        // Early Secret = HKDF-Extract(salt=0, IKM=PSK or 0)
         tracing::info!("Starting TLS 1.3 key schedule");
         let zero_key = vec![0u8; 32];
-        let early_secret = Hkdf::extract(&[], &zero_key).await?;
+        let ikm = match &self.psk_offer {
+            Some(offer) if self.psk_accepted => &offer.psk,
+            _ => &zero_key,
+        };
+        let early_secret = Hkdf::extract(&[], ikm).await?;
         tracing::info!("Early secret derived");
 
         // Derive-Secret(early_secret, "derived", "")
@@ -527,11 +725,23 @@ impl HandshakeState {
         self.client_app_secret = Some(client_app_secret);
         self.server_app_secret = Some(server_app_secret);
         self.exporter_master_secret = Some(exporter_master_secret);
+        self.master_secret = Some(master_secret);
 
         debug!("Derived application traffic secrets and exporter master secret");
         Ok(())
     }
 
+    /// Derive resumption_master_secret, once the client Finished is in the
+    /// transcript
+    pub async fn derive_resumption_secret(&self) -> Result<Vec<u8>> {
+        let master_secret = self
+            .master_secret
+            .as_ref()
+            .ok_or_else(|| TlsError::handshake("Missing master secret"))?;
+        let transcript_hash = crypto::sha256(&self.transcript).await?;
+        Hkdf::derive_secret(master_secret, "res master", &transcript_hash).await
+    }
+
     /// Compute Finished message verify data
     pub async fn compute_finished(&self, is_client: bool) -> Result<Vec<u8>> {
         let base_key = if is_client {
@@ -682,6 +892,86 @@ async fn hmac_sha256(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
     Ok(uint8_array.to_vec())
 }
 
+/// The server_name extension body naming `server_name`
+fn build_sni_extension(server_name: &str) -> Vec<u8> {
+    let name_bytes = server_name.as_bytes();
+    let mut ext = Vec::new();
+
+    // Server name list length
+    let list_len = 3 + name_bytes.len();
+    ext.push((list_len >> 8) as u8);
+    ext.push(list_len as u8);
+
+    // Server name type (host_name = 0)
+    ext.push(0);
+
+    // Server name length
+    ext.push((name_bytes.len() >> 8) as u8);
+    ext.push(name_bytes.len() as u8);
+
+    // Server name
+    ext.extend_from_slice(name_bytes);
+
+    ext
+}
+
+/// Build the body of an ALPN extension offering `protocols`
+///
+/// Format: length(2) + [ length(1) + protocol_name ]*
+pub fn build_alpn_extension(protocols: &[String]) -> Vec<u8> {
+    let list_len: usize = protocols.iter().map(|p| 1 + p.len()).sum();
+    let mut ext = Vec::with_capacity(2 + list_len);
+    ext.push((list_len >> 8) as u8);
+    ext.push(list_len as u8);
+    for protocol in protocols {
+        ext.push(protocol.len() as u8);
+        ext.extend_from_slice(protocol.as_bytes());
+    }
+    ext
+}
+
+/// Parse a server's ALPN extension body, which must select exactly one of
+/// the `offered` protocols
+pub fn parse_alpn_extension(data: &[u8], offered: &[String]) -> Result<String> {
+    if data.len() < 3 {
+        return Err(TlsError::handshake("ALPN extension too short"));
+    }
+    let list_len = ((data[0] as usize) << 8) | (data[1] as usize);
+    let name_len = data[2] as usize;
+    if list_len != data.len() - 2 || name_len != list_len - 1 || name_len == 0 {
+        return Err(TlsError::handshake(
+            "ALPN extension must select exactly one protocol",
+        ));
+    }
+    let protocol = String::from_utf8_lossy(&data[3..]).into_owned();
+    if !offered.contains(&protocol) {
+        return Err(TlsError::handshake(format!(
+            "Server selected ALPN protocol {:?}, which was not offered",
+            protocol
+        )));
+    }
+    Ok(protocol)
+}
+
+/// The data of the extension `wanted` in a list of extensions, if present
+pub fn find_extension(mut data: &[u8], wanted: u16) -> Result<Option<&[u8]>> {
+    while !data.is_empty() {
+        if data.len() < 4 {
+            return Err(TlsError::handshake("Extension header truncated"));
+        }
+        let ext_type = ((data[0] as u16) << 8) | (data[1] as u16);
+        let ext_len = ((data[2] as usize) << 8) | (data[3] as usize);
+        if 4 + ext_len > data.len() {
+            return Err(TlsError::handshake("Extension data overflow"));
+        }
+        if ext_type == wanted {
+            return Ok(Some(&data[4..4 + ext_len]));
+        }
+        data = &data[4 + ext_len..];
+    }
+    Ok(None)
+}
+
 /// Parse a handshake message header
 pub fn parse_handshake_header(data: &[u8]) -> Result<(u8, usize)> {
     if data.len() < 4 {
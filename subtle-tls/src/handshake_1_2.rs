@@ -12,6 +12,7 @@
 
 use crate::crypto::{self, EcdhKeyPair};
 use crate::error::{Result, TlsError};
+use crate::handshake::{build_alpn_extension, find_extension, parse_alpn_extension, EXT_ALPN};
 use crate::prf::{self, KeyMaterial};
 use tracing::debug;
 
@@ -168,6 +169,10 @@ pub struct Handshake12State {
     pub server_public_key: Option<Vec<u8>>,
     /// Server's chosen curve
     pub server_curve: Option<u16>,
+    /// ALPN protocols offered in ClientHello, most preferred first
+    pub alpn_protocols: Vec<String>,
+    /// ALPN protocol the server selected
+    pub alpn_protocol: Option<String>,
 }
 
 impl Handshake12State {
@@ -187,6 +192,8 @@ impl Handshake12State {
             key_material: None,
             server_public_key: None,
             server_curve: None,
+            alpn_protocols: Vec::new(),
+            alpn_protocol: None,
         })
     }
 
@@ -286,6 +293,16 @@ impl Handshake12State {
         extensions.push(1); // Length
         extensions.push(0); // Empty renegotiated_connection
 
+        // ALPN, only if configured
+        if !self.alpn_protocols.is_empty() {
+            let alpn = build_alpn_extension(&self.alpn_protocols);
+            extensions.push((EXT_ALPN >> 8) as u8);
+            extensions.push(EXT_ALPN as u8);
+            extensions.push((alpn.len() >> 8) as u8);
+            extensions.push(alpn.len() as u8);
+            extensions.extend_from_slice(&alpn);
+        }
+
         extensions
     }
 
@@ -373,6 +390,21 @@ impl Handshake12State {
         if compression != 0 {
             return Err(TlsError::handshake("Server selected non-null compression"));
         }
+        pos += 1;
+
+        // Extensions (optional in TLS 1.2)
+        if pos + 2 <= data.len() {
+            let ext_len = ((data[pos] as usize) << 8) | (data[pos + 1] as usize);
+            pos += 2;
+            if pos + ext_len > data.len() {
+                return Err(TlsError::handshake("ServerHello extensions truncated"));
+            }
+            if let Some(alpn) = find_extension(&data[pos..pos + ext_len], EXT_ALPN)? {
+                let protocol = parse_alpn_extension(alpn, &self.alpn_protocols)?;
+                debug!("Server selected ALPN protocol: {}", protocol);
+                self.alpn_protocol = Some(protocol);
+            }
+        }
 
         Ok(())
     }
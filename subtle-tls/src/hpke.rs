@@ -0,0 +1,389 @@
+//! Hybrid Public Key Encryption (RFC 9180), base mode
+//!
+//! The one HPKE both TLS stacks use for Encrypted Client Hello: the
+//! subtle-tls handshake seals its inner ClientHello with it, and webtor's
+//! native build hands it to rustls. It is synchronous pure Rust (x25519-dalek,
+//! hkdf, aes-gcm, chacha20poly1305), so it behaves the same in a browser and
+//! natively.
+//!
+//! Only DHKEM(X25519, HKDF-SHA256) with HKDF-SHA256 is implemented, with
+//! AES-128-GCM or ChaCha20-Poly1305; the tests check it against the test
+//! vectors of RFC 9180 Appendix A.1.
+
+use crate::error::{Result, TlsError};
+use aes_gcm::Aes128Gcm;
+use chacha20poly1305::aead::{Aead as _, KeyInit, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{x25519, X25519_BASEPOINT_BYTES};
+
+/// KEM ID of DHKEM(X25519, HKDF-SHA256)
+pub const KEM_X25519_HKDF_SHA256: u16 = 0x0020;
+/// KDF ID of HKDF-SHA256
+pub const KDF_HKDF_SHA256: u16 = 0x0001;
+/// AEAD ID of AES-128-GCM
+pub const AEAD_AES_128_GCM: u16 = 0x0001;
+/// AEAD ID of ChaCha20-Poly1305
+pub const AEAD_CHACHA20_POLY1305: u16 = 0x0003;
+
+/// Length of an X25519 key and of the encapsulated key
+pub const KEY_LEN: usize = 32;
+/// Length of the AEAD tag on every sealed message
+pub const TAG_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// AEAD of an HPKE suite
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aead {
+    Aes128Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Aead {
+    /// The AEAD with IANA ID `id`, if supported
+    pub fn from_id(id: u16) -> Option<Self> {
+        match id {
+            AEAD_AES_128_GCM => Some(Self::Aes128Gcm),
+            AEAD_CHACHA20_POLY1305 => Some(Self::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    /// The AEAD's IANA ID
+    pub fn id(self) -> u16 {
+        match self {
+            Self::Aes128Gcm => AEAD_AES_128_GCM,
+            Self::ChaCha20Poly1305 => AEAD_CHACHA20_POLY1305,
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            Self::Aes128Gcm => 16,
+            Self::ChaCha20Poly1305 => 32,
+        }
+    }
+}
+
+/// DHKEM(X25519, HKDF-SHA256) and HKDF-SHA256 with an AEAD
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suite {
+    pub aead: Aead,
+}
+
+impl Suite {
+    pub fn new(aead: Aead) -> Self {
+        Self { aead }
+    }
+
+    /// A fresh X25519 key pair, as (private, public)
+    pub fn generate_key_pair() -> Result<([u8; KEY_LEN], [u8; KEY_LEN])> {
+        let sk = random_key()?;
+        Ok((sk, x25519(sk, X25519_BASEPOINT_BYTES)))
+    }
+
+    /// SetupBaseS: a context sealing to `pk_r`, and the encapsulated key the
+    /// recipient needs to open it
+    pub fn setup_sender(&self, pk_r: &[u8], info: &[u8]) -> Result<(Vec<u8>, Context)> {
+        self.setup_sender_with_ephemeral(random_key()?, pk_r, info)
+    }
+
+    /// [`setup_sender`](Self::setup_sender) with the ephemeral private key
+    /// `sk_e` instead of a random one, for test vectors
+    pub fn setup_sender_with_ephemeral(
+        &self,
+        sk_e: [u8; KEY_LEN],
+        pk_r: &[u8],
+        info: &[u8],
+    ) -> Result<(Vec<u8>, Context)> {
+        let pk_r = key(pk_r, "HPKE: bad X25519 public key")?;
+        let enc = x25519(sk_e, X25519_BASEPOINT_BYTES);
+        let shared_secret = kem_shared_secret(&x25519(sk_e, pk_r), &enc, &pk_r)?;
+        Ok((enc.to_vec(), self.key_schedule(&shared_secret, info)?))
+    }
+
+    /// SetupBaseR: the context opening what was sealed to the key pair of
+    /// `sk_r` under the encapsulated key `enc`
+    pub fn setup_receiver(&self, enc: &[u8], sk_r: &[u8], info: &[u8]) -> Result<Context> {
+        let pk_e = key(enc, "HPKE: bad encapsulated key")?;
+        let sk_r = key(sk_r, "HPKE: bad X25519 private key")?;
+        let pk_r = x25519(sk_r, X25519_BASEPOINT_BYTES);
+        let shared_secret = kem_shared_secret(&x25519(sk_r, pk_e), &pk_e, &pk_r)?;
+        self.key_schedule(&shared_secret, info)
+    }
+
+    fn id(&self) -> Vec<u8> {
+        [
+            b"HPKE".as_slice(),
+            &KEM_X25519_HKDF_SHA256.to_be_bytes(),
+            &KDF_HKDF_SHA256.to_be_bytes(),
+            &self.aead.id().to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    /// KeySchedule for mode_base, without a PSK
+    fn key_schedule(&self, shared_secret: &[u8], info: &[u8]) -> Result<Context> {
+        let suite = self.id();
+        let psk_id_hash = labeled_extract(&suite, &[], "psk_id_hash", &[]);
+        let info_hash = labeled_extract(&suite, &[], "info_hash", info);
+        let context = [&[0u8][..], &psk_id_hash, &info_hash].concat();
+        let secret = labeled_extract(&suite, shared_secret, "secret", &[]);
+
+        let key = labeled_expand(&suite, &secret, "key", &context, self.aead.key_len())?;
+        let base_nonce = labeled_expand(&suite, &secret, "base_nonce", &context, NONCE_LEN)?;
+        let exporter_secret = labeled_expand(&suite, &secret, "exp", &context, 32)?;
+        let cipher = match self.aead {
+            Aead::Aes128Gcm => Cipher::Aes128Gcm(Box::new(
+                Aes128Gcm::new_from_slice(&key).map_err(|_| TlsError::crypto("HPKE: bad key"))?,
+            )),
+            Aead::ChaCha20Poly1305 => Cipher::ChaCha20Poly1305(
+                ChaCha20Poly1305::new_from_slice(&key)
+                    .map_err(|_| TlsError::crypto("HPKE: bad key"))?,
+            ),
+        };
+        Ok(Context {
+            suite,
+            cipher,
+            base_nonce: base_nonce.try_into().expect("12-byte nonce"),
+            seq: 0,
+            exporter_secret,
+        })
+    }
+}
+
+enum Cipher {
+    Aes128Gcm(Box<Aes128Gcm>),
+    ChaCha20Poly1305(ChaCha20Poly1305),
+}
+
+/// A sender or receiver context; successive messages use successive nonces
+pub struct Context {
+    suite: Vec<u8>,
+    cipher: Cipher,
+    base_nonce: [u8; NONCE_LEN],
+    seq: u64,
+    exporter_secret: Vec<u8>,
+}
+
+impl Context {
+    /// Seal the context's next message
+    pub fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        match &self.cipher {
+            Cipher::Aes128Gcm(cipher) => cipher.encrypt((&nonce).into(), payload),
+            Cipher::ChaCha20Poly1305(cipher) => cipher.encrypt((&nonce).into(), payload),
+        }
+        .map_err(|_| TlsError::crypto("HPKE: seal failed"))
+    }
+
+    /// Open the context's next message
+    pub fn open(&mut self, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = self.next_nonce()?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        match &self.cipher {
+            Cipher::Aes128Gcm(cipher) => cipher.decrypt((&nonce).into(), payload),
+            Cipher::ChaCha20Poly1305(cipher) => cipher.decrypt((&nonce).into(), payload),
+        }
+        .map_err(|_| TlsError::crypto("HPKE: open failed"))
+    }
+
+    /// Export `len` bytes of secret bound to `exporter_context`
+    pub fn export(&self, exporter_context: &[u8], len: usize) -> Result<Vec<u8>> {
+        labeled_expand(
+            &self.suite,
+            &self.exporter_secret,
+            "sec",
+            exporter_context,
+            len,
+        )
+    }
+
+    /// The nonce for the current sequence number, which then advances
+    fn next_nonce(&mut self) -> Result<[u8; NONCE_LEN]> {
+        let mut nonce = self.base_nonce;
+        for (byte, seq) in nonce[NONCE_LEN - 8..]
+            .iter_mut()
+            .zip(self.seq.to_be_bytes())
+        {
+            *byte ^= seq;
+        }
+        self.seq = self
+            .seq
+            .checked_add(1)
+            .ok_or_else(|| TlsError::crypto("HPKE: message limit reached"))?;
+        Ok(nonce)
+    }
+}
+
+impl std::fmt::Debug for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Context")
+            .field("seq", &self.seq)
+            .finish_non_exhaustive()
+    }
+}
+
+/// DHKEM ExtractAndExpand of the Diffie-Hellman result `dh`
+fn kem_shared_secret(dh: &[u8; KEY_LEN], enc: &[u8], pk_r: &[u8]) -> Result<Vec<u8>> {
+    // RFC 9180 7.1.4: an all-zero result means a low-order point
+    if dh.iter().all(|&b| b == 0) {
+        return Err(TlsError::crypto("HPKE: low-order X25519 point"));
+    }
+    let suite = [b"KEM".as_slice(), &KEM_X25519_HKDF_SHA256.to_be_bytes()].concat();
+    let eae_prk = labeled_extract(&suite, &[], "eae_prk", dh);
+    let kem_context = [enc, pk_r].concat();
+    labeled_expand(&suite, &eae_prk, "shared_secret", &kem_context, 32)
+}
+
+fn labeled_extract(suite: &[u8], salt: &[u8], label: &str, ikm: &[u8]) -> Vec<u8> {
+    let labeled = [b"HPKE-v1".as_slice(), suite, label.as_bytes(), ikm].concat();
+    Hkdf::<Sha256>::extract(Some(salt), &labeled).0.to_vec()
+}
+
+fn labeled_expand(
+    suite: &[u8],
+    prk: &[u8],
+    label: &str,
+    info: &[u8],
+    len: usize,
+) -> Result<Vec<u8>> {
+    let labeled = [
+        (len as u16).to_be_bytes().as_slice(),
+        b"HPKE-v1",
+        suite,
+        label.as_bytes(),
+        info,
+    ]
+    .concat();
+    let hkdf = Hkdf::<Sha256>::from_prk(prk).map_err(|_| TlsError::crypto("HPKE: bad PRK"))?;
+    let mut okm = vec![0; len];
+    hkdf.expand(&labeled, &mut okm)
+        .map_err(|_| TlsError::crypto("HPKE: output too long"))?;
+    Ok(okm)
+}
+
+fn key(bytes: &[u8], error: &str) -> Result<[u8; KEY_LEN]> {
+    bytes.try_into().map_err(|_| TlsError::crypto(error))
+}
+
+fn random_key() -> Result<[u8; KEY_LEN]> {
+    let mut key = [0u8; KEY_LEN];
+    getrandom::getrandom(&mut key)
+        .map_err(|e| TlsError::crypto(format!("Failed to generate random bytes: {}", e)))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// RFC 9180 A.1.1: DHKEM(X25519, HKDF-SHA256), HKDF-SHA256, AES-128-GCM,
+    /// base mode
+    #[test]
+    fn test_rfc9180_a1_1_base_vectors() {
+        let info = hex("4f6465206f6e2061204772656369616e2055726e");
+        let sk_em = hex("52c4a758a802cd8b936eceea314432798d5baf2d7e9235dc084ab1b9cfa2f736");
+        let sk_rm = hex("4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8");
+        let pk_rm = x25519(key(&sk_rm, "").unwrap(), X25519_BASEPOINT_BYTES);
+        let suite = Suite::new(Aead::Aes128Gcm);
+
+        let (enc, mut sender) = suite
+            .setup_sender_with_ephemeral(key(&sk_em, "").unwrap(), &pk_rm, &info)
+            .unwrap();
+        assert_eq!(
+            enc,
+            hex("37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431")
+        );
+        assert_eq!(
+            kem_shared_secret(&x25519(key(&sk_em, "").unwrap(), pk_rm), &enc, &pk_rm).unwrap(),
+            hex("fe0e18c9f024ce43799ae393c7e8fe8fce9d218875e8227b0187c04e7d2ea1fc")
+        );
+        assert_eq!(sender.base_nonce.to_vec(), hex("56d890e5accaaf011cff4b7d"));
+        assert_eq!(
+            sender.exporter_secret,
+            hex("45ff1c2e220db587171952c0592d5f5ebe103f1561a2614e38f2ffd47e99e3f8")
+        );
+
+        let mut receiver = suite.setup_receiver(&enc, &sk_rm, &info).unwrap();
+        let pt = hex("4265617574792069732074727574682c20747275746820626561757479");
+        let vectors = [
+            (
+                "436f756e742d30",
+                "f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218a355a9\
+                 6d8770ac83d07bea87e13c512a",
+            ),
+            (
+                "436f756e742d31",
+                "af2d7e9ac9ae7e270f46ba1f975be53c09f8d875bdc8535458c2494e8a6eab25\
+                 1c03d0c22a56b8ca42c2063b84",
+            ),
+            (
+                "436f756e742d32",
+                "498dfcabd92e8acedc281e85af1cb4e3e31c7dc394a1ca20e173cb7251649158\
+                 8d96a19ad4a683518973dcc180",
+            ),
+        ];
+        for (aad, ct) in vectors {
+            let (aad, ct) = (hex(aad), hex(ct));
+            assert_eq!(sender.seal(&aad, &pt).unwrap(), ct);
+            assert_eq!(receiver.open(&aad, &ct).unwrap(), pt);
+        }
+
+        let exports = [
+            (
+                "",
+                "3853fe2b4035195a573ffc53856e77058e15d9ea064de3e59f4961d0095250ee",
+            ),
+            (
+                "00",
+                "2e8f0b54673c7029649d4eb9d5e33bf1872cf76d623ff164ac185da9e88c21a5",
+            ),
+            (
+                "54657374436f6e74657874",
+                "e9e43065102c3836401bed8c3c3c75ae46be1639869391d62c61f1ec7af54931",
+            ),
+        ];
+        for (context, exported) in exports {
+            assert_eq!(receiver.export(&hex(context), 32).unwrap(), hex(exported));
+        }
+    }
+
+    #[test]
+    fn test_round_trip_and_rejections() {
+        for aead in [Aead::Aes128Gcm, Aead::ChaCha20Poly1305] {
+            let suite = Suite::new(aead);
+            let (sk, pk) = Suite::generate_key_pair().unwrap();
+            let (enc, mut sender) = suite.setup_sender(&pk, b"info").unwrap();
+            let first = sender.seal(b"aad", b"hello").unwrap();
+            let second = sender.seal(b"aad", b"hello").unwrap();
+            assert_ne!(first, second);
+            assert_eq!(first.len(), 5 + TAG_LEN);
+
+            let mut receiver = suite.setup_receiver(&enc, &sk, b"info").unwrap();
+            assert_eq!(receiver.open(b"aad", &first).unwrap(), b"hello");
+            assert!(receiver.open(b"other aad", &second).is_err());
+
+            let mut other_info = suite.setup_receiver(&enc, &sk, b"other").unwrap();
+            assert!(other_info.open(b"aad", &first).is_err());
+            // The all-zero point makes every shared secret zero
+            assert!(suite.setup_receiver(&[0; 32], &sk, b"info").is_err());
+            assert!(suite.setup_sender(&[0; 31], b"info").is_err());
+        }
+    }
+}
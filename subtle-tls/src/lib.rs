@@ -11,6 +11,9 @@
 //! - AES-128-GCM, AES-256-GCM, and ChaCha20-Poly1305 encryption
 //! - AES-CBC encryption (TLS 1.2 only)
 //! - Certificate chain validation
+//! - TLS 1.3 session resumption (PSK with ECDHE)
+//! - Encrypted Client Hello (RFC 9849), given the server's ECHConfigList
+//! - HPKE (RFC 9180) base mode, also usable on its own
 //! - AsyncRead/AsyncWrite interface
 //!
 //! # Example
@@ -25,9 +28,12 @@
 
 pub mod cert;
 pub mod crypto;
+pub mod ech;
 pub mod error;
 pub mod handshake;
+pub mod hpke;
 pub mod record;
+pub mod session;
 pub mod stream;
 pub mod trust_store;
 
@@ -40,7 +46,9 @@ pub mod record_1_2;
 #[cfg(feature = "tls12")]
 pub mod stream_1_2;
 
+pub use ech::EchConfig;
 pub use error::{Result, TlsError};
+pub use session::{SessionCache, SessionTicket};
 pub use stream::TlsStream;
 
 #[cfg(feature = "tls12")]
@@ -55,9 +63,10 @@ pub struct TlsConnector {
 }
 
 /// TLS version preference
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum TlsVersion {
     /// TLS 1.3 only
+    #[default]
     Tls13,
     /// TLS 1.2 only
     #[cfg(feature = "tls12")]
@@ -74,12 +83,6 @@ pub enum TlsVersion {
     Prefer13,
 }
 
-impl Default for TlsVersion {
-    fn default() -> Self {
-        TlsVersion::Tls13
-    }
-}
-
 /// TLS configuration
 #[derive(Clone)]
 pub struct TlsConfig {
@@ -89,6 +92,17 @@ pub struct TlsConfig {
     pub alpn_protocols: Vec<String>,
     /// TLS version preference
     pub version: TlsVersion,
+    /// Additional trust anchors (DER-encoded root certificates)
+    pub root_certificates: Vec<Vec<u8>>,
+    /// Trust only `root_certificates`, not the embedded roots, and reject
+    /// chains that don't terminate at one of them
+    pub only_custom_roots: bool,
+    /// Where TLS 1.3 session tickets are taken from and stored for
+    /// resumption; none disables it
+    pub session_cache: Option<SessionCache>,
+    /// The server's ECHConfigList, to hide the server name from the path
+    /// with Encrypted Client Hello (TLS 1.3 only)
+    pub ech_config_list: Option<Vec<u8>>,
 }
 
 impl Default for TlsConfig {
@@ -97,6 +111,10 @@ impl Default for TlsConfig {
             skip_verification: false,
             alpn_protocols: vec!["http/1.1".to_string()],
             version: TlsVersion::default(),
+            root_certificates: Vec::new(),
+            only_custom_roots: false,
+            session_cache: None,
+            ech_config_list: None,
         }
     }
 }
@@ -144,6 +162,22 @@ where
             TlsStreamWrapper::Tls12(s) => s.peer_certificate(),
         }
     }
+
+    /// Get the certificate chain the peer presented (DER-encoded, leaf first)
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        match self {
+            TlsStreamWrapper::Tls13(s) => s.peer_certificates(),
+            TlsStreamWrapper::Tls12(s) => s.peer_certificates(),
+        }
+    }
+
+    /// Get the ALPN protocol the server selected, if any
+    pub fn alpn_protocol(&self) -> Option<&str> {
+        match self {
+            TlsStreamWrapper::Tls13(s) => s.alpn_protocol(),
+            TlsStreamWrapper::Tls12(s) => s.alpn_protocol(),
+        }
+    }
 }
 
 impl TlsConnector {
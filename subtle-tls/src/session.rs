@@ -0,0 +1,200 @@
+//! TLS 1.3 session resumption
+//!
+//! After a full handshake a server may send NewSessionTicket messages. A
+//! ticket, with the resumption secret of the connection that received it,
+//! yields a pre-shared key (RFC 8446 section 4.6.1) a later connection to
+//! the same server can offer. When the server accepts it, the handshake
+//! skips the certificate chain and its verification, which is most of the
+//! handshake's bytes and, with SubtleCrypto, most of its time.
+//!
+//! Tickets are single-use: a ticket offered twice would let the server link
+//! the two connections.
+
+use crate::crypto::Hkdf;
+use crate::error::{Result, TlsError};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Tickets kept per server; servers usually send two after a handshake
+const MAX_TICKETS_PER_SERVER: usize = 4;
+
+/// Servers tickets are kept for; the least recently stored are dropped
+const MAX_SERVERS: usize = 256;
+
+/// Longest ticket lifetime honoured (RFC 8446 caps it at 7 days)
+const MAX_TICKET_LIFETIME_SECS: u32 = 7 * 24 * 60 * 60;
+
+/// A ticket from a NewSessionTicket message
+#[derive(Clone)]
+pub struct SessionTicket {
+    /// Opaque ticket the server issued; the PSK identity
+    ticket: Vec<u8>,
+    /// Per-ticket nonce mixed into the PSK
+    nonce: Vec<u8>,
+    /// resumption_master_secret of the connection that received the ticket
+    resumption_secret: Vec<u8>,
+    /// Added to the ticket age to obfuscate it
+    age_add: u32,
+    lifetime_secs: u32,
+    /// When the ticket arrived, in milliseconds since the Unix epoch
+    received_at_ms: f64,
+    /// Certificate chain the server presented in the full handshake
+    peer_certificates: Vec<Vec<u8>>,
+}
+
+impl std::fmt::Debug for SessionTicket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionTicket")
+            .field("ticket_len", &self.ticket.len())
+            .field("lifetime_secs", &self.lifetime_secs)
+            .field("received_at_ms", &self.received_at_ms)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionTicket {
+    /// Parse a NewSessionTicket message body received at `now_ms` on a
+    /// connection with the given resumption secret and certificate chain
+    pub fn parse(
+        body: &[u8],
+        resumption_secret: &[u8],
+        peer_certificates: &[Vec<u8>],
+        now_ms: f64,
+    ) -> Result<Self> {
+        let truncated = || TlsError::handshake("NewSessionTicket truncated");
+        if body.len() < 9 {
+            return Err(truncated());
+        }
+        let lifetime_secs = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+        let age_add = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+        let nonce_len = body[8] as usize;
+        let mut pos = 9;
+        let nonce = body
+            .get(pos..pos + nonce_len)
+            .ok_or_else(truncated)?
+            .to_vec();
+        pos += nonce_len;
+        let ticket_len = body
+            .get(pos..pos + 2)
+            .map(|len| ((len[0] as usize) << 8) | len[1] as usize)
+            .ok_or_else(truncated)?;
+        pos += 2;
+        let ticket = body
+            .get(pos..pos + ticket_len)
+            .ok_or_else(truncated)?
+            .to_vec();
+        if ticket.is_empty() {
+            return Err(TlsError::handshake("NewSessionTicket has an empty ticket"));
+        }
+
+        Ok(Self {
+            ticket,
+            nonce,
+            resumption_secret: resumption_secret.to_vec(),
+            age_add,
+            lifetime_secs: lifetime_secs.min(MAX_TICKET_LIFETIME_SECS),
+            received_at_ms: now_ms,
+            peer_certificates: peer_certificates.to_vec(),
+        })
+    }
+
+    /// The opaque ticket, sent as the PSK identity
+    pub fn identity(&self) -> &[u8] {
+        &self.ticket
+    }
+
+    /// Certificate chain the server presented in the full handshake
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    /// Whether the ticket's lifetime has run out at `now_ms`
+    pub fn is_expired(&self, now_ms: f64) -> bool {
+        now_ms - self.received_at_ms >= self.lifetime_secs as f64 * 1000.0
+    }
+
+    /// The `obfuscated_ticket_age` to send at `now_ms`
+    pub fn obfuscated_age(&self, now_ms: f64) -> u32 {
+        let age_ms = (now_ms - self.received_at_ms).max(0.0) as u64;
+        (age_ms as u32).wrapping_add(self.age_add)
+    }
+
+    /// The PSK: HKDF-Expand-Label(resumption_master_secret, "resumption",
+    /// ticket_nonce, Hash.length)
+    pub async fn psk(&self) -> Result<Vec<u8>> {
+        Hkdf::expand_label(&self.resumption_secret, "resumption", &self.nonce, 32).await
+    }
+}
+
+/// Session tickets shared by connections, by server name
+///
+/// Cloning shares the tickets. Connections given the same cache may resume
+/// each other's sessions, so callers that keep identities apart should give
+/// each identity its own cache.
+#[derive(Clone, Default)]
+pub struct SessionCache {
+    inner: Arc<Mutex<CacheInner>>,
+}
+
+#[derive(Default)]
+struct CacheInner {
+    tickets: HashMap<String, VecDeque<SessionTicket>>,
+    /// Server names, least recently stored first
+    order: VecDeque<String>,
+}
+
+impl SessionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep `ticket` for a later connection to `server_name`
+    pub fn insert(&self, server_name: &str, ticket: SessionTicket) {
+        let server_name = server_name.to_ascii_lowercase();
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.order.retain(|name| *name != server_name);
+        inner.order.push_back(server_name.clone());
+        let tickets = inner.tickets.entry(server_name).or_default();
+        tickets.push_back(ticket);
+        while tickets.len() > MAX_TICKETS_PER_SERVER {
+            tickets.pop_front();
+        }
+        while inner.order.len() > MAX_SERVERS {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.tickets.remove(&oldest);
+            }
+        }
+    }
+
+    /// Remove and return the newest ticket for `server_name` still valid at
+    /// `now_ms`, dropping expired ones
+    pub fn take(&self, server_name: &str, now_ms: f64) -> Option<SessionTicket> {
+        let server_name = server_name.to_ascii_lowercase();
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let tickets = inner.tickets.get_mut(&server_name)?;
+        tickets.retain(|ticket| !ticket.is_expired(now_ms));
+        let ticket = tickets.pop_back();
+        if tickets.is_empty() {
+            inner.tickets.remove(&server_name);
+            inner.order.retain(|name| *name != server_name);
+        }
+        ticket
+    }
+
+    /// Number of tickets held
+    pub fn len(&self) -> usize {
+        let inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.tickets.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget every ticket
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.tickets.clear();
+        inner.order.clear();
+    }
+}
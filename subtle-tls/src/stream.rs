@@ -4,14 +4,16 @@
 
 use crate::cert::CertificateVerifier;
 use crate::crypto;
+use crate::ech::{EchConfig, ALERT_ECH_REQUIRED};
 use crate::error::{Result, TlsError};
 use crate::handshake::{
     self, HandshakeState, CONTENT_TYPE_ALERT, CONTENT_TYPE_APPLICATION_DATA,
     CONTENT_TYPE_CHANGE_CIPHER_SPEC, CONTENT_TYPE_HANDSHAKE, HANDSHAKE_CERTIFICATE,
     HANDSHAKE_CERTIFICATE_VERIFY, HANDSHAKE_ENCRYPTED_EXTENSIONS, HANDSHAKE_FINISHED,
-    HANDSHAKE_SERVER_HELLO,
+    HANDSHAKE_NEW_SESSION_TICKET, HANDSHAKE_SERVER_HELLO,
 };
 use crate::record::RecordLayer;
+use crate::session::{SessionCache, SessionTicket};
 use crate::TlsConfig;
 use futures::io::{AsyncRead, AsyncWrite};
 use std::io;
@@ -29,8 +31,14 @@ pub struct TlsStream<S> {
     read_buffer: Vec<u8>,
     /// Position in read buffer
     read_pos: usize,
-    /// DER-encoded peer certificate (for CertifiedConn)
-    peer_certificate: Option<Vec<u8>>,
+    /// DER-encoded certificate chain the peer presented, leaf first
+    peer_certificates: Vec<Vec<u8>>,
+    /// ALPN protocol the server selected
+    alpn_protocol: Option<String>,
+    /// Whether the handshake resumed an earlier session
+    resumed: bool,
+    /// Where session tickets the server sends are kept, if resumption is on
+    resumption: Option<Resumption>,
     /// TLS keying material (for export_keying_material)
     keying_material: Option<KeyingMaterial>,
     /// Buffer for accumulating encrypted record data being read
@@ -39,6 +47,14 @@ pub struct TlsStream<S> {
     record_write_buffer: Vec<u8>,
 }
 
+/// What a NewSessionTicket needs to become a resumable session
+struct Resumption {
+    cache: SessionCache,
+    server_name: String,
+    /// resumption_master_secret of this connection
+    secret: Vec<u8>,
+}
+
 /// Stored keying material for RFC 8446 key export
 struct KeyingMaterial {
     /// Exporter master secret derived during handshake
@@ -54,10 +70,30 @@ where
         info!("Starting TLS 1.3 handshake with {}", server_name);
 
         let mut handshake = HandshakeState::new(server_name).await?;
+        handshake.alpn_protocols = config.alpn_protocols.clone();
+        if let Some(list) = &config.ech_config_list {
+            let ech = EchConfig::select(list)?;
+            debug!(
+                "Offering ECH to {} behind {}",
+                server_name,
+                ech.public_name()
+            );
+            handshake.offer_ech(ech);
+        }
         let mut record_layer = RecordLayer::new();
 
-        // Step 1: Send ClientHello
-        let client_hello = handshake.build_client_hello();
+        // Step 1: Send ClientHello, offering a stored session if there is one
+        if let Some(ticket) = config
+            .session_cache
+            .as_ref()
+            .and_then(|cache| cache.take(server_name, js_sys::Date::now()))
+        {
+            debug!("Offering a stored session to {}", server_name);
+            handshake.offer_session(ticket, js_sys::Date::now()).await?;
+        }
+        let mut client_hello = handshake.build_client_hello();
+        handshake.fill_psk_binder(&mut client_hello).await?;
+        let client_hello = handshake.encrypt_client_hello(client_hello).await?;
         handshake.update_transcript(&client_hello);
         record_layer
             .write_record(&mut stream, CONTENT_TYPE_HANDSHAKE, &client_hello)
@@ -79,11 +115,16 @@ where
         handshake.update_transcript(&server_hello_msg);
 
         let server_key_share = handshake.parse_server_hello(&server_hello_data)?;
+        handshake.resolve_ech(&server_hello_msg).await?;
         info!(
             "Parsed ServerHello, cipher suite: 0x{:04x}",
             handshake.cipher_suite
         );
 
+        if handshake.psk_offer.is_some() && !handshake.psk_accepted {
+            debug!("{} declined session resumption", server_name);
+        }
+
         // Set cipher suite on record layer
         record_layer.set_cipher_suite(handshake.cipher_suite);
 
@@ -100,7 +141,7 @@ where
         // Step 4: Receive encrypted handshake messages
         // (EncryptedExtensions, Certificate, CertificateVerify, Finished)
         info!("Processing encrypted handshake messages...");
-        let peer_certificate = match Self::process_encrypted_handshake(
+        let peer_certificates = match Self::process_encrypted_handshake(
             &mut stream,
             &mut record_layer,
             &mut handshake,
@@ -108,9 +149,9 @@ where
         )
         .await
         {
-            Ok(cert) => {
+            Ok(chain) => {
                 info!("process_encrypted_handshake returned Ok");
-                cert
+                chain
             }
             Err(e) => {
                 tracing::error!("process_encrypted_handshake returned Err: {}", e);
@@ -119,6 +160,22 @@ where
         };
         info!("Encrypted handshake complete");
 
+        // A server that rejected ECH has now authenticated as the public
+        // name; abort as RFC 9849 requires, handing back its new configs
+        if handshake.ech.is_some() && !handshake.ech_accepted {
+            let (write_key, write_iv) = handshake.get_handshake_keys(true).await?;
+            record_layer.set_write_cipher(&write_key, &write_iv).await?;
+            if let Err(e) = record_layer
+                .write_record(&mut stream, CONTENT_TYPE_ALERT, &[2, ALERT_ECH_REQUIRED])
+                .await
+            {
+                debug!("Failed to send ech_required alert: {}", e);
+            }
+            return Err(TlsError::EchRejected {
+                retry_configs: handshake.ech_retry_configs.take(),
+            });
+        }
+
         // Step 5: Derive application keys
         handshake.derive_application_keys().await?;
 
@@ -143,7 +200,24 @@ where
             .set_write_cipher(&app_write_key, &app_write_iv)
             .await?;
 
-        info!("TLS 1.3 handshake completed with {}", server_name);
+        info!(
+            "TLS 1.3 handshake completed with {}{}",
+            server_name,
+            if handshake.psk_accepted {
+                " (resumed)"
+            } else {
+                ""
+            }
+        );
+
+        let resumption = match &config.session_cache {
+            Some(cache) => Some(Resumption {
+                cache: cache.clone(),
+                server_name: server_name.to_string(),
+                secret: handshake.derive_resumption_secret().await?,
+            }),
+            None => None,
+        };
 
         // Store exporter master secret for RFC 8446 key export
         let keying_material = handshake
@@ -157,7 +231,10 @@ where
             record_layer,
             read_buffer: Vec::new(),
             read_pos: 0,
-            peer_certificate,
+            peer_certificates,
+            alpn_protocol: handshake.alpn_protocol.take(),
+            resumed: handshake.psk_accepted,
+            resumption,
             keying_material,
             record_read_buffer: Vec::new(),
             record_write_buffer: Vec::new(),
@@ -166,7 +243,57 @@ where
 
     /// Get the peer certificate (DER-encoded)
     pub fn peer_certificate(&self) -> Option<&[u8]> {
-        self.peer_certificate.as_deref()
+        self.peer_certificates.first().map(Vec::as_slice)
+    }
+
+    /// Get the certificate chain the peer presented (DER-encoded, leaf first)
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    /// Get the ALPN protocol the server selected, if any
+    pub fn alpn_protocol(&self) -> Option<&str> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// Whether the handshake resumed a session from the session cache
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Handle post-handshake messages in a decrypted handshake record,
+    /// keeping session tickets if resumption is on
+    fn process_post_handshake(&self, data: &[u8]) {
+        let mut rest = data;
+        while rest.len() >= 4 {
+            let (msg_type, len) = match handshake::parse_handshake_header(rest) {
+                Ok(header) => header,
+                Err(_) => break,
+            };
+            let Some(body) = rest.get(4..4 + len) else {
+                // Messages split across records carry nothing we need
+                trace!("Ignoring fragmented post-handshake message");
+                break;
+            };
+            match (msg_type, &self.resumption) {
+                (HANDSHAKE_NEW_SESSION_TICKET, Some(resumption)) => {
+                    match SessionTicket::parse(
+                        body,
+                        &resumption.secret,
+                        &self.peer_certificates,
+                        js_sys::Date::now(),
+                    ) {
+                        Ok(ticket) => {
+                            debug!("Stored a session ticket for {}", resumption.server_name);
+                            resumption.cache.insert(&resumption.server_name, ticket);
+                        }
+                        Err(e) => warn!("Ignoring malformed NewSessionTicket: {}", e),
+                    }
+                }
+                _ => trace!("Ignoring post-handshake message type {}", msg_type),
+            }
+            rest = &rest[4 + len..];
+        }
     }
 
     async fn read_server_hello(stream: &mut S, record_layer: &mut RecordLayer) -> Result<Vec<u8>> {
@@ -218,13 +345,13 @@ where
         }
     }
 
-    /// Process encrypted handshake messages and return the peer certificate (if any)
+    /// Process encrypted handshake messages and return the peer's certificate chain
     async fn process_encrypted_handshake(
         stream: &mut S,
         record_layer: &mut RecordLayer,
         handshake: &mut HandshakeState,
         config: &TlsConfig,
-    ) -> Result<Option<Vec<u8>>> {
+    ) -> Result<Vec<Vec<u8>>> {
         let mut got_encrypted_extensions = false;
         let mut got_certificate = false;
         let mut got_certificate_verify = false;
@@ -307,8 +434,16 @@ where
                             HANDSHAKE_ENCRYPTED_EXTENSIONS => {
                                 debug!("Received EncryptedExtensions");
                                 handshake.update_transcript(&msg_data);
+                                handshake.parse_encrypted_extensions(msg_body)?;
                                 got_encrypted_extensions = true;
                             }
+                            HANDSHAKE_CERTIFICATE | HANDSHAKE_CERTIFICATE_VERIFY
+                                if handshake.psk_accepted =>
+                            {
+                                return Err(TlsError::handshake(
+                                    "Server sent a certificate in a resumed handshake",
+                                ));
+                            }
                             HANDSHAKE_CERTIFICATE => {
                                 debug!("Received Certificate ({} bytes)", msg_body.len());
                                 handshake.update_transcript(&msg_data);
@@ -357,6 +492,13 @@ where
             return Err(TlsError::handshake("Missing EncryptedExtensions"));
         }
 
+        // A resumed session was authenticated by the handshake that issued
+        // its ticket; the PSK binds this one to it
+        if let (true, Some(offer)) = (handshake.psk_accepted, &handshake.psk_offer) {
+            debug!("Encrypted handshake phase completed (resumed)");
+            return Ok(offer.ticket.peer_certificates().to_vec());
+        }
+
         // Validate certificates if not skipping verification
         if !config.skip_verification {
             if !got_certificate {
@@ -367,7 +509,7 @@ where
             }
 
             // Verify certificate chain
-            let verifier = CertificateVerifier::new(&handshake.server_name, false);
+            let verifier = CertificateVerifier::from_config(&handshake.server_name, config)?;
             verifier.verify_chain(&cert_chain).await?;
 
             // Verify CertificateVerify signature
@@ -387,8 +529,7 @@ where
         }
 
         debug!("Encrypted handshake phase completed");
-        // Return the leaf certificate (first in chain)
-        Ok(cert_chain.into_iter().next())
+        Ok(cert_chain)
     }
 
     fn parse_alert(data: &[u8]) -> TlsError {
@@ -423,6 +564,7 @@ where
                 115 => "unknown_psk_identity",
                 116 => "certificate_required",
                 120 => "no_application_protocol",
+                121 => "ech_required",
                 _ => "unknown",
             };
             TlsError::Alert(format!(
@@ -452,7 +594,7 @@ where
                 }
                 CONTENT_TYPE_HANDSHAKE => {
                     // Post-handshake messages (e.g., NewSessionTicket)
-                    trace!("Ignoring post-handshake message");
+                    self.process_post_handshake(&data);
                     continue;
                 }
                 _ => {
@@ -585,8 +727,8 @@ where
                             )));
                         }
                         CONTENT_TYPE_HANDSHAKE => {
-                            // Post-handshake message (e.g., NewSessionTicket), skip it
-                            trace!("Ignoring post-handshake message");
+                            // Post-handshake message (e.g., NewSessionTicket)
+                            self.process_post_handshake(&plaintext);
                             continue;
                         }
                         CONTENT_TYPE_CHANGE_CIPHER_SPEC => {
@@ -821,7 +963,7 @@ where
     S: AsyncRead + AsyncWrite + Unpin,
 {
     fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
-        Ok(self.peer_certificate.clone())
+        Ok(self.peer_certificates.first().cloned())
     }
 
     fn export_keying_material(
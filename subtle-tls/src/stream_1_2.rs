@@ -26,8 +26,10 @@ pub struct TlsStream12<S> {
     read_buffer: Vec<u8>,
     /// Position in read buffer
     read_pos: usize,
-    /// DER-encoded peer certificate
-    peer_certificate: Option<Vec<u8>>,
+    /// DER-encoded certificate chain the peer presented, leaf first
+    peer_certificates: Vec<Vec<u8>>,
+    /// ALPN protocol the server selected
+    alpn_protocol: Option<String>,
 }
 
 impl<S> TlsStream12<S>
@@ -37,8 +39,15 @@ where
     /// Perform TLS 1.2 handshake and return encrypted stream
     pub async fn connect(mut stream: S, server_name: &str, config: TlsConfig) -> Result<Self> {
         info!("Starting TLS 1.2 handshake with {}", server_name);
+        if config.ech_config_list.is_some() {
+            // TLS 1.2 has no ECH; its ClientHello would name the server
+            return Err(TlsError::handshake(
+                "Encrypted Client Hello requires TLS 1.3",
+            ));
+        }
 
         let mut handshake = Handshake12State::new(server_name).await?;
+        handshake.alpn_protocols = config.alpn_protocols.clone();
         let mut record_layer = RecordLayer12::new();
 
         // Step 1: Send ClientHello
@@ -48,7 +57,7 @@ where
         debug!("Sent TLS 1.2 ClientHello");
 
         // Step 2: Receive ServerHello, Certificate, ServerKeyExchange, ServerHelloDone
-        let peer_certificate =
+        let peer_certificates =
             Self::process_server_messages(&mut stream, &mut handshake, &config).await?;
 
         // Step 3: Generate ECDH key pair and compute shared secret
@@ -151,7 +160,8 @@ where
             record_layer,
             read_buffer: Vec::new(),
             read_pos: 0,
-            peer_certificate,
+            peer_certificates,
+            alpn_protocol: handshake.alpn_protocol.take(),
         })
     }
 
@@ -160,8 +170,7 @@ where
         stream: &mut S,
         handshake: &mut Handshake12State,
         config: &TlsConfig,
-    ) -> Result<Option<Vec<u8>>> {
-        let mut peer_certificate = None;
+    ) -> Result<Vec<Vec<u8>>> {
         let mut cert_chain: Vec<Vec<u8>> = Vec::new();
         let mut _got_server_hello = false;
         let mut _got_certificate = false;
@@ -198,9 +207,6 @@ where
                             }
                             HANDSHAKE_CERTIFICATE => {
                                 cert_chain = handshake_1_2::parse_certificate(msg_data)?;
-                                if !cert_chain.is_empty() {
-                                    peer_certificate = Some(cert_chain[0].clone());
-                                }
                                 handshake.update_transcript(full_msg);
                                 _got_certificate = true;
                                 debug!("Received Certificate ({} certs)", cert_chain.len());
@@ -239,12 +245,12 @@ where
 
         // Verify certificate chain
         if !config.skip_verification && !cert_chain.is_empty() {
-            let verifier = CertificateVerifier::new(&handshake.server_name, false);
+            let verifier = CertificateVerifier::from_config(&handshake.server_name, config)?;
             verifier.verify_chain(&cert_chain).await?;
             debug!("Certificate chain verified");
         }
 
-        Ok(peer_certificate)
+        Ok(cert_chain)
     }
 
     /// Read a TLS record (unencrypted, for handshake)
@@ -289,7 +295,17 @@ where
 
     /// Get the peer certificate (DER-encoded)
     pub fn peer_certificate(&self) -> Option<&[u8]> {
-        self.peer_certificate.as_deref()
+        self.peer_certificates.first().map(Vec::as_slice)
+    }
+
+    /// Get the certificate chain the peer presented (DER-encoded, leaf first)
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    /// Get the ALPN protocol the server selected, if any
+    pub fn alpn_protocol(&self) -> Option<&str> {
+        self.alpn_protocol.as_deref()
     }
 
     /// Read application data
@@ -374,7 +390,7 @@ where
     S: AsyncRead + AsyncWrite + Unpin,
 {
     fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
-        Ok(self.peer_certificate.clone())
+        Ok(self.peer_certificates.first().cloned())
     }
 
     fn export_keying_material(
@@ -99,6 +99,17 @@ pub struct RootCertificate {
 }
 
 impl RootCertificate {
+    /// Parse a DER-encoded certificate
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        let (_, cert) = X509Certificate::from_der(der)
+            .map_err(|e| TlsError::certificate(format!("Failed to parse certificate: {}", e)))?;
+
+        Ok(Self {
+            der: der.to_vec(),
+            subject: cert.subject().to_string(),
+        })
+    }
+
     /// Parse a PEM-encoded certificate
     pub fn from_pem(pem: &str) -> Result<Self> {
         let pem_bytes = pem.as_bytes();
@@ -130,12 +141,12 @@ pub struct TrustStore {
 impl TrustStore {
     /// Create a new trust store with embedded Let's Encrypt roots
     pub fn new() -> Result<Self> {
-        let mut embedded_roots = Vec::new();
-
         // Parse embedded root certificates
-        embedded_roots.push(RootCertificate::from_pem(ISRG_ROOT_X1_PEM)?);
-        embedded_roots.push(RootCertificate::from_pem(ISRG_ROOT_X2_PEM)?);
-        embedded_roots.push(RootCertificate::from_pem(DIGICERT_GLOBAL_ROOT_G2_PEM)?);
+        let embedded_roots = vec![
+            RootCertificate::from_pem(ISRG_ROOT_X1_PEM)?,
+            RootCertificate::from_pem(ISRG_ROOT_X2_PEM)?,
+            RootCertificate::from_pem(DIGICERT_GLOBAL_ROOT_G2_PEM)?,
+        ];
 
         info!(
             "Initialized trust store with {} embedded root CAs",
@@ -149,6 +160,19 @@ impl TrustStore {
         })
     }
 
+    /// Create a trust store holding the DER-encoded `roots`, plus the
+    /// embedded roots if `include_embedded`
+    pub fn with_roots(roots: &[Vec<u8>], include_embedded: bool) -> Result<Self> {
+        let mut store = Self::new()?;
+        if !include_embedded {
+            store.embedded_roots.clear();
+        }
+        for der in roots {
+            store.embedded_roots.push(RootCertificate::from_der(der)?);
+        }
+        Ok(store)
+    }
+
     /// Create a trust store with a custom CA bundle URL
     pub fn with_ca_bundle_url(mut self, url: &str) -> Self {
         self.ca_bundle_url = url.to_string();
@@ -300,7 +324,7 @@ impl Default for TrustStore {
 
 // Global trust store instance
 thread_local! {
-    static TRUST_STORE: RefCell<Option<TrustStore>> = RefCell::new(None);
+    static TRUST_STORE: RefCell<Option<TrustStore>> = const { RefCell::new(None) };
 }
 
 /// Get or initialize the global trust store
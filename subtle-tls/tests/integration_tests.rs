@@ -321,9 +321,9 @@ mod crypto_tests {
 mod handshake_tests {
     use super::*;
     use subtle_tls::handshake::{
-        parse_certificate_verify, parse_finished, parse_handshake_header, HandshakeState,
-        HANDSHAKE_CLIENT_HELLO, TLS_AES_128_GCM_SHA256, TLS_AES_256_GCM_SHA384,
-        TLS_CHACHA20_POLY1305_SHA256, TLS_VERSION_1_2,
+        build_alpn_extension, parse_alpn_extension, parse_certificate_verify, parse_finished,
+        parse_handshake_header, HandshakeState, HANDSHAKE_CLIENT_HELLO, TLS_AES_128_GCM_SHA256,
+        TLS_AES_256_GCM_SHA384, TLS_CHACHA20_POLY1305_SHA256, TLS_VERSION_1_2,
     };
 
     #[wasm_bindgen_test]
@@ -432,11 +432,12 @@ mod handshake_tests {
     async fn test_parse_certificate_verify() {
         // Build a minimal CertificateVerify message
         // Format: algorithm(2) + signature_length(2) + signature(N)
-        let mut data = Vec::new();
-        data.push(0x04); // Algorithm high byte (RSA_PSS_RSAE_SHA256 = 0x0804)
-        data.push(0x04); // Algorithm low byte
-        data.push(0x00); // Signature length high byte
-        data.push(0x10); // Signature length low byte (16 bytes)
+        let mut data = vec![
+            0x04, // Algorithm high byte (RSA_PSS_RSAE_SHA256 = 0x0804)
+            0x04, // Algorithm low byte
+            0x00, // Signature length high byte
+            0x10, // Signature length low byte (16 bytes)
+        ];
         data.extend_from_slice(&[0xAA; 16]); // Signature
 
         let (algorithm, signature) = parse_certificate_verify(&data).unwrap();
@@ -456,6 +457,33 @@ mod handshake_tests {
         state.update_transcript(&[4, 5]);
         assert_eq!(state.transcript, vec![1, 2, 3, 4, 5]);
     }
+
+    #[wasm_bindgen_test]
+    async fn test_alpn_negotiation() {
+        let offered = vec!["h2".to_string(), "http/1.1".to_string()];
+        assert_eq!(
+            build_alpn_extension(&offered),
+            b"\x00\x0c\x02h2\x08http/1.1".to_vec()
+        );
+
+        // The server selects one protocol it was offered
+        let selected = build_alpn_extension(&offered[..1]);
+        assert_eq!(parse_alpn_extension(&selected, &offered).unwrap(), "h2");
+        let other = build_alpn_extension(&["spdy/3".to_string()]);
+        assert!(parse_alpn_extension(&other, &offered).is_err());
+        assert!(parse_alpn_extension(&build_alpn_extension(&offered), &offered).is_err());
+        assert!(parse_alpn_extension(&[0, 1], &offered).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_client_hello_alpn_is_configurable() {
+        let mut state = HandshakeState::new("example.com").await.unwrap();
+        let without = state.build_client_hello();
+        state.alpn_protocols = vec!["h2".to_string()];
+        let with = state.build_client_hello();
+        assert_eq!(with.len(), without.len() + 4 + 5);
+        assert!(with.windows(3).any(|w| w == b"\x02h2"));
+    }
 }
 
 mod record_tests {
@@ -647,6 +675,128 @@ mod record_tests {
     }
 }
 
+mod session_tests {
+    use super::*;
+    use subtle_tls::{SessionCache, SessionTicket};
+
+    /// NewSessionTicket body: lifetime 60s, age_add 5, nonce [1], ticket "tkt"
+    const TICKET: &[u8] = &[0, 0, 0, 60, 0, 0, 0, 5, 1, 1, 0, 3, b't', b'k', b't', 0, 0];
+
+    fn ticket(now_ms: f64) -> SessionTicket {
+        SessionTicket::parse(TICKET, &[0x11; 32], &[vec![0xAA]], now_ms).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_parse_session_ticket() {
+        let ticket = ticket(1000.0);
+        assert_eq!(ticket.identity(), b"tkt");
+        assert_eq!(ticket.peer_certificates(), &[vec![0xAA]]);
+        assert_eq!(ticket.obfuscated_age(3000.0), 2005);
+        assert!(!ticket.is_expired(60_999.0));
+        assert!(ticket.is_expired(61_000.0));
+
+        assert!(SessionTicket::parse(&TICKET[..12], &[], &[], 0.0).is_err());
+        // An empty ticket can't be offered
+        let empty = [0, 0, 0, 60, 0, 0, 0, 5, 0, 0, 0, 0, 0];
+        assert!(SessionTicket::parse(&empty, &[], &[], 0.0).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_session_cache_tickets_are_single_use() {
+        let cache = SessionCache::new();
+        cache.insert("Example.com", ticket(0.0));
+        cache.insert("example.com", ticket(500.0));
+        assert_eq!(cache.len(), 2);
+
+        // Newest first, each handed out once
+        let first = cache.take("example.com", 1000.0).unwrap();
+        assert_eq!(first.obfuscated_age(1000.0), 505);
+        assert!(cache.take("other.example", 1000.0).is_none());
+        assert!(cache.take("example.com", 1000.0).is_some());
+        assert!(cache.take("example.com", 1000.0).is_none());
+
+        // Expired tickets are dropped rather than offered
+        cache.insert("example.com", ticket(0.0));
+        assert!(cache.take("example.com", 60_000.0).is_none());
+        assert!(cache.is_empty());
+    }
+}
+
+mod ech_tests {
+    use super::*;
+    use subtle_tls::handshake::HandshakeState;
+    use subtle_tls::EchConfig;
+
+    /// An ECHConfig (version 0xfe0d) with the given KEM, one HKDF-SHA256
+    /// suite with `aead`, and an optional extension
+    fn ech_config(kem: u16, aead: u16, extension: Option<u16>) -> Vec<u8> {
+        let mut contents = vec![7];
+        contents.extend_from_slice(&kem.to_be_bytes());
+        contents.extend_from_slice(&[0, 32]);
+        contents.extend_from_slice(&[0x42; 32]);
+        contents.extend_from_slice(&[0, 4, 0, 1]);
+        contents.extend_from_slice(&aead.to_be_bytes());
+        contents.push(40); // maximum_name_length
+        contents.push(18);
+        contents.extend_from_slice(b"public.example.net");
+        match extension {
+            Some(ext) => contents.extend_from_slice(&[0, 4, (ext >> 8) as u8, ext as u8, 0, 0]),
+            None => contents.extend_from_slice(&[0, 0]),
+        }
+        let mut config = vec![0xfe, 0x0d];
+        config.extend_from_slice(&(contents.len() as u16).to_be_bytes());
+        config.extend_from_slice(&contents);
+        config
+    }
+
+    fn ech_config_list(configs: &[Vec<u8>]) -> Vec<u8> {
+        let configs = configs.concat();
+        let mut list = (configs.len() as u16).to_be_bytes().to_vec();
+        list.extend_from_slice(&configs);
+        list
+    }
+
+    #[wasm_bindgen_test]
+    fn test_ech_config_selection() {
+        // P-256 KEM and an unknown mandatory extension are skipped
+        let list = ech_config_list(&[
+            ech_config(0x0010, 1, None),
+            ech_config(0x0020, 1, Some(0xfa00)),
+            ech_config(0x0020, 3, Some(0x0a00)),
+        ]);
+        let config = EchConfig::select(&list).unwrap();
+        assert_eq!(config.public_name(), "public.example.net");
+        let ext = config.outer_extension(&[0x55; 32], 100);
+        assert_eq!(&ext[..6], &[0, 0, 1, 0, 3, 7]);
+        assert_eq!(ext.len(), 6 + 2 + 32 + 2 + 100);
+
+        assert!(EchConfig::select(&ech_config_list(&[ech_config(0x0010, 1, None)])).is_err());
+        assert!(EchConfig::select(&list[..list.len() - 1]).is_err());
+        assert!(EchConfig::select(&[0, 0]).is_err());
+
+        // Padding hides the name's length up to maximum_name_length
+        let short = config.pad_inner(vec![1; 100], "a.example");
+        let long = config.pad_inner(vec![1; 100], "abcdefghijklmnopqrstuvwxyz.example");
+        assert_eq!(short.len() % 32, 0);
+        assert_eq!(short.len(), long.len());
+    }
+
+    #[wasm_bindgen_test]
+    async fn test_outer_client_hello_hides_server_name() {
+        let list = ech_config_list(&[ech_config(0x0020, 1, None)]);
+        let mut handshake = HandshakeState::new("secret.example.com").await.unwrap();
+        handshake.offer_ech(EchConfig::select(&list).unwrap());
+        let inner = handshake.build_client_hello();
+        let outer = handshake.encrypt_client_hello(inner.clone()).await.unwrap();
+
+        let contains = |hello: &[u8], name: &[u8]| hello.windows(name.len()).any(|w| w == name);
+        assert!(contains(&inner, b"secret.example.com"));
+        assert!(!contains(&outer, b"secret.example.com"));
+        assert!(contains(&outer, b"public.example.net"));
+        assert_eq!(handshake.ech.as_ref().unwrap().inner_hello, inner);
+    }
+}
+
 mod trust_store_tests {
     use super::*;
     use subtle_tls::trust_store::{TrustStore, EMBEDDED_ROOT_COUNT};
@@ -781,6 +931,7 @@ mod tls_config_tests {
             skip_verification: true,
             alpn_protocols: vec!["h2".to_string(), "http/1.1".to_string()],
             version: TlsVersion::Tls12,
+            ..Default::default()
         };
         assert!(config.skip_verification);
         assert_eq!(config.alpn_protocols.len(), 2);
@@ -802,6 +953,7 @@ mod tls_config_tests {
             skip_verification: false,
             alpn_protocols: vec!["http/1.1".to_string()],
             version: TlsVersion::Prefer13,
+            ..Default::default()
         };
         assert_eq!(config.version, TlsVersion::Prefer13);
     }
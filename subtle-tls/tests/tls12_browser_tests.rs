@@ -205,7 +205,7 @@ mod tls12_handshake_tests {
 
         for (suite, expected_key_len, expected_iv_len, is_aead) in suites {
             let params = CipherSuiteParams::for_suite(suite)
-                .expect(&format!("Suite 0x{:04x} should be supported", suite));
+                .unwrap_or_else(|e| panic!("Suite 0x{:04x} should be supported: {}", suite, e));
 
             assert_eq!(
                 params.key_len, expected_key_len,
@@ -391,9 +391,9 @@ mod tls_version_negotiation_tests {
         assert_ne!(TlsVersion::Tls13, TlsVersion::Prefer13);
         assert_ne!(TlsVersion::Tls12, TlsVersion::Prefer13);
 
-        // Test clone produces equal values
+        // Test copy produces equal values
         let v1 = TlsVersion::Tls13;
-        let v2 = v1.clone();
+        let v2 = v1;
         assert_eq!(v1, v2);
     }
 
@@ -403,6 +403,7 @@ mod tls_version_negotiation_tests {
             skip_verification: false,
             alpn_protocols: vec!["http/1.1".to_string()],
             version: TlsVersion::Prefer13,
+            ..Default::default()
         };
 
         assert_eq!(config.version, TlsVersion::Prefer13);
@@ -414,6 +415,7 @@ mod tls_version_negotiation_tests {
             skip_verification: false,
             alpn_protocols: vec!["http/1.1".to_string()],
             version: TlsVersion::Tls12,
+            ..Default::default()
         };
 
         assert_eq!(config.version, TlsVersion::Tls12);
@@ -27,6 +27,7 @@ pub struct DemoApp {
 #[wasm_bindgen]
 impl DemoApp {
     #[wasm_bindgen(constructor)]
+    #[allow(clippy::arc_with_non_send_sync)] // WASM is single-threaded
     pub fn new() -> Result<DemoApp, JsValue> {
         console::log_1(&"DemoApp created".into());
         Ok(DemoApp {
@@ -133,7 +134,8 @@ impl DemoApp {
         future_to_promise(async move {
             app.stop_status_polling()?;
 
-            if let Some(mut client) = lock_or_recover(&app.tor_client).take() {
+            let client = lock_or_recover(&app.tor_client).take();
+            if let Some(mut client) = client {
                 client.close_rust().await;
             }
 
@@ -155,7 +157,7 @@ impl DemoApp {
             let response = client
                 .fetch_rust(&url)
                 .await
-                .map_err(|e| JsValue::from_str(&format!("{}", e)))?;
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
             let text = response
                 .text()
@@ -173,7 +175,7 @@ impl DemoApp {
 
             let response = TorClient::fetch_one_time_rust(snowflake_url, &url, None, None)
                 .await
-                .map_err(|e| JsValue::from_str(&format!("{}", e)))?;
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
             let text = response
                 .text()
@@ -198,7 +200,7 @@ impl DemoApp {
             client
                 .update_circuit_rust(30000)
                 .await
-                .map_err(|e| JsValue::from_str(&format!("{}", e)))?;
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
             app.update_status("Ready")?;
             Ok(JsValue::UNDEFINED)
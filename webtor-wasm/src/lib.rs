@@ -2,11 +2,21 @@
 
 use gloo_console::{error as console_error, log as console_log, warn as console_warn};
 use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Duration;
+use tracing::level_filters::LevelFilter;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::future_to_promise;
-use webtor::{TorClient as NativeTorClient, TorClientOptions as NativeTorClientOptions, TorError};
+use webtor::bootstrap::BootstrapReport;
+use webtor::http::HttpRequest;
+use webtor::logging::{LogCapture, LogFilter};
+use webtor::snowflake_broker::Rendezvous;
+use webtor::{
+    with_cancellation, CancellationToken, IsolationToken, TorClient as NativeTorClient,
+    TorClientOptions as NativeTorClientOptions, TorError,
+};
 
 /// Structured error for JavaScript consumption
 /// Provides machine-readable error classification for UX and retry decisions
@@ -20,6 +30,18 @@ pub struct JsTorError {
     pub message: String,
     /// Whether this error is likely transient and the operation could succeed on retry
     pub retryable: bool,
+    /// ID of the request the error belongs to, for correlating with logs
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// How far bootstrap got, when the bootstrap deadline expired
+    #[serde(rename = "bootstrapReport", skip_serializing_if = "Option::is_none")]
+    pub bootstrap_report: Option<BootstrapReport>,
+    /// Why the exit closed the stream (e.g. "EXITPOLICY", "CONNECTREFUSED")
+    #[serde(rename = "endReason", skip_serializing_if = "Option::is_none")]
+    pub end_reason: Option<String>,
+    /// Which request limit expired: "connect", "firstByte" or "total"
+    #[serde(rename = "timeoutPhase", skip_serializing_if = "Option::is_none")]
+    pub timeout_phase: Option<String>,
 }
 
 impl From<TorError> for JsTorError {
@@ -29,6 +51,10 @@ impl From<TorError> for JsTorError {
             kind: e.kind().as_code().to_lowercase(),
             message: e.to_string(),
             retryable: e.is_retryable(),
+            request_id: e.request_id().map(str::to_string),
+            bootstrap_report: e.bootstrap_report().cloned(),
+            end_reason: e.end_reason().map(|reason| reason.as_str().to_string()),
+            timeout_phase: e.timeout_phase().map(|phase| phase.as_str().to_string()),
         }
     }
 }
@@ -40,6 +66,10 @@ impl From<&TorError> for JsTorError {
             kind: e.kind().as_code().to_lowercase(),
             message: e.to_string(),
             retryable: e.is_retryable(),
+            request_id: e.request_id().map(str::to_string),
+            bootstrap_report: e.bootstrap_report().cloned(),
+            end_reason: e.end_reason().map(|reason| reason.as_str().to_string()),
+            timeout_phase: e.timeout_phase().map(|phase| phase.as_str().to_string()),
         }
     }
 }
@@ -57,6 +87,10 @@ impl JsTorError {
             kind: kind.to_string(),
             message: message.to_string(),
             retryable,
+            request_id: None,
+            bootstrap_report: None,
+            end_reason: None,
+            timeout_phase: None,
         }
     }
 
@@ -81,13 +115,255 @@ fn headers_to_js(headers: &std::collections::HashMap<String, String>) -> JsValue
     serde_wasm_bindgen::to_value(headers).unwrap_or_else(|_| js_sys::Object::new().into())
 }
 
+/// Helper to read an optional isolation token (string or non-negative integer)
+fn isolation_token_from_js(value: &JsValue) -> Result<Option<IsolationToken>, JsValue> {
+    if value.is_undefined() || value.is_null() {
+        return Ok(None);
+    }
+    if let Some(token) = value.as_string() {
+        return Ok(Some(IsolationToken::from(token)));
+    }
+    match value.as_f64() {
+        Some(n) if n >= 0.0 && n.fract() == 0.0 && n <= u64::MAX as f64 => {
+            Ok(Some(IsolationToken::from(n as u64)))
+        }
+        _ => Err(JsTorError::from_str(
+            "INVALID_ISOLATION_TOKEN",
+            "configuration",
+            "Isolation token must be a string or a non-negative integer",
+            false,
+        )
+        .into_js_value()),
+    }
+}
+
+/// Attach a JS request body: strings go as text, `Uint8Array`s and
+/// `ArrayBuffer`s as bytes, like the body of `window.fetch`
+fn with_js_body(request: HttpRequest, body: &JsValue) -> Result<HttpRequest, JsValue> {
+    if body.is_undefined() || body.is_null() {
+        return Ok(request);
+    }
+    if let Some(text) = body.as_string() {
+        return Ok(request.with_text_body(text));
+    }
+    if let Some(bytes) = body.dyn_ref::<js_sys::Uint8Array>() {
+        return Ok(request.with_body(bytes.to_vec()));
+    }
+    if let Some(buffer) = body.dyn_ref::<js_sys::ArrayBuffer>() {
+        return Ok(request.with_body(js_sys::Uint8Array::new(buffer).to_vec()));
+    }
+    Err(JsTorError::from_str(
+        "INVALID_BODY",
+        "configuration",
+        "Request body must be a string, Uint8Array or ArrayBuffer",
+        false,
+    )
+    .into_js_value())
+}
+
+/// A cancellation token tripped when the optional JS `AbortSignal` aborts
+fn cancellation_from_signal(signal: &JsValue) -> Result<Option<CancellationToken>, JsValue> {
+    if signal.is_undefined() || signal.is_null() {
+        return Ok(None);
+    }
+    let signal = signal.dyn_ref::<web_sys::AbortSignal>().ok_or_else(|| {
+        JsTorError::from_str(
+            "INVALID_SIGNAL",
+            "configuration",
+            "signal must be an AbortSignal",
+            false,
+        )
+        .into_js_value()
+    })?;
+    let token = CancellationToken::new();
+    if signal.aborted() {
+        token.cancel();
+    } else {
+        let on_abort = token.clone();
+        let listener = Closure::once_into_js(move || on_abort.cancel());
+        signal.add_event_listener_with_callback("abort", listener.unchecked_ref())?;
+    }
+    Ok(Some(token))
+}
+
+/// Build a request from a `window.fetch`-style init object
+fn request_from_init(url: &str, init: &JsValue) -> Result<HttpRequest, JsValue> {
+    let url = webtor::Url::parse(url).map_err(|e| tor_error_to_js(e.into()))?;
+    let mut request = HttpRequest::new(url);
+    if init.is_undefined() || init.is_null() {
+        return Ok(request);
+    }
+    let field = |name: &str| js_sys::Reflect::get(init, &JsValue::from_str(name));
+
+    if let Some(method) = field("method")?.as_string() {
+        let method = method.to_ascii_uppercase().parse().map_err(|_| {
+            tor_error_to_js(TorError::configuration(format!(
+                "Invalid HTTP method: {}",
+                method
+            )))
+        })?;
+        request = request.with_method(method);
+    }
+    request = request.with_headers(headers_from_js(field("headers")?)?);
+    request = with_js_body(request, &field("body")?)?;
+    let millis = |name: &str| -> Result<Option<Duration>, JsValue> {
+        Ok(field(name)?
+            .as_f64()
+            .map(|ms| Duration::from_millis(ms.max(0.0) as u64)))
+    };
+    if let Some(timeout) = millis("timeoutMs")? {
+        request = request.with_timeout(timeout);
+    }
+    if let Some(timeout) = millis("connectTimeoutMs")? {
+        request = request.with_connect_timeout(timeout);
+    }
+    if let Some(timeout) = millis("firstByteTimeoutMs")? {
+        request = request.with_first_byte_timeout(timeout);
+    }
+    if let Some(bytes) = field("maxResponseSize")?.as_f64() {
+        request = request.with_max_response_size(bytes.max(0.0) as usize);
+    }
+    if let Some(retries) = field("retries")?.as_f64() {
+        request = request.with_retries(retries.max(0.0) as u32);
+    }
+    if field("newCircuit")?.is_truthy() {
+        request = request.with_new_circuit(true);
+    }
+    if let Some(token) = isolation_token_from_js(&field("isolation")?)? {
+        request = request.with_isolation_token(token);
+    }
+    if let Some(token) = cancellation_from_signal(&field("signal")?)? {
+        request = request.with_cancellation(token);
+    }
+    let range = field("range")?;
+    if !range.is_undefined() && !range.is_null() {
+        let bound = |name: &str| -> Result<Option<u64>, JsValue> {
+            Ok(js_sys::Reflect::get(&range, &JsValue::from_str(name))?
+                .as_f64()
+                .map(|byte| byte.max(0.0) as u64))
+        };
+        let start = bound("start")?.unwrap_or_default();
+        request = request.with_range(start, bound("end")?);
+    }
+    if let Some(validator) = field("ifRange")?.as_string() {
+        request = request.with_if_range(&validator);
+    }
+    let pins = field("pins")?;
+    if !pins.is_undefined() && !pins.is_null() {
+        let pins: std::collections::HashMap<String, Vec<String>> =
+            serde_wasm_bindgen::from_value(pins)
+                .map_err(|e| JsValue::from_str(&format!("Invalid pins object: {}", e)))?;
+        for (host, host_pins) in pins {
+            for pin in host_pins {
+                let pin = webtor::CertPin::parse(&pin).map_err(tor_error_to_js)?;
+                request = request.with_cert_pin(&host, pin);
+            }
+        }
+    }
+    Ok(request)
+}
+
+/// Build a WebSocket request from an optional `{ protocols, headers,
+/// timeoutMs, maxMessageSize, isolation, pins }` object
+fn websocket_request_from_init(
+    url: &str,
+    init: &JsValue,
+) -> Result<webtor::WebSocketRequest, JsValue> {
+    let url = webtor::Url::parse(url).map_err(|e| tor_error_to_js(e.into()))?;
+    let mut request = webtor::WebSocketRequest::new(url);
+    if init.is_undefined() || init.is_null() {
+        return Ok(request);
+    }
+    let field = |name: &str| js_sys::Reflect::get(init, &JsValue::from_str(name));
+
+    let protocols = field("protocols")?;
+    if !protocols.is_undefined() && !protocols.is_null() {
+        let protocols: Vec<String> = serde_wasm_bindgen::from_value(protocols)
+            .map_err(|e| JsValue::from_str(&format!("Invalid protocols array: {}", e)))?;
+        request.protocols = protocols;
+    }
+    request.headers = headers_from_js(field("headers")?)?;
+    if let Some(ms) = field("timeoutMs")?.as_f64() {
+        request = request.with_timeout(Duration::from_millis(ms.max(0.0) as u64));
+    }
+    if let Some(bytes) = field("maxMessageSize")?.as_f64() {
+        request = request.with_max_message_size(bytes.max(0.0) as usize);
+    }
+    if let Some(token) = isolation_token_from_js(&field("isolation")?)? {
+        request = request.with_isolation(token);
+    }
+    let pins = field("pins")?;
+    if !pins.is_undefined() && !pins.is_null() {
+        let pins: Vec<String> = serde_wasm_bindgen::from_value(pins)
+            .map_err(|e| JsValue::from_str(&format!("Invalid pins array: {}", e)))?;
+        for pin in pins {
+            request = request.with_cert_pin(webtor::CertPin::parse(&pin).map_err(tor_error_to_js)?);
+        }
+    }
+    Ok(request)
+}
+
+/// Build an event source request from an optional `{ headers, timeoutMs,
+/// maxEventSize, isolation, pins, lastEventId, maxReconnects }` object
+fn event_source_request_from_init(
+    url: &str,
+    init: &JsValue,
+) -> Result<webtor::EventSourceRequest, JsValue> {
+    let url = webtor::Url::parse(url).map_err(|e| tor_error_to_js(e.into()))?;
+    let mut request = webtor::EventSourceRequest::new(url);
+    if init.is_undefined() || init.is_null() {
+        return Ok(request);
+    }
+    let field = |name: &str| js_sys::Reflect::get(init, &JsValue::from_str(name));
+
+    request.headers = headers_from_js(field("headers")?)?;
+    if let Some(ms) = field("timeoutMs")?.as_f64() {
+        request = request.with_timeout(Duration::from_millis(ms.max(0.0) as u64));
+    }
+    if let Some(bytes) = field("maxEventSize")?.as_f64() {
+        request = request.with_max_event_size(bytes.max(0.0) as usize);
+    }
+    if let Some(token) = isolation_token_from_js(&field("isolation")?)? {
+        request = request.with_isolation(token);
+    }
+    let pins = field("pins")?;
+    if !pins.is_undefined() && !pins.is_null() {
+        let pins: Vec<String> = serde_wasm_bindgen::from_value(pins)
+            .map_err(|e| JsValue::from_str(&format!("Invalid pins array: {}", e)))?;
+        for pin in pins {
+            request = request.with_cert_pin(webtor::CertPin::parse(&pin).map_err(tor_error_to_js)?);
+        }
+    }
+    if let Some(id) = field("lastEventId")?.as_string() {
+        request = request.with_last_event_id(id);
+    }
+    if let Some(attempts) = field("maxReconnects")?.as_f64() {
+        request = request.with_max_reconnects(attempts.max(0.0) as u32);
+    }
+    Ok(request)
+}
+
+/// Read an optional plain object of request headers
+fn headers_from_js(headers: JsValue) -> Result<std::collections::HashMap<String, String>, JsValue> {
+    if headers.is_undefined() || headers.is_null() {
+        return Ok(Default::default());
+    }
+    serde_wasm_bindgen::from_value(headers)
+        .map_err(|e| JsValue::from_str(&format!("Invalid headers object: {}", e)))
+}
+
 // Thread-local log callback for forwarding logs to JavaScript (WASM is single-threaded)
 thread_local! {
-    static LOG_CALLBACK: RefCell<Option<js_sys::Function>> = RefCell::new(None);
-    static DEBUG_ENABLED: RefCell<bool> = RefCell::new(false);
+    static LOG_CALLBACK: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+    static LOG_CAPTURE: LogCapture = LogCapture::new(LogFilter::default(), |record| {
+        forward_log(&record.level, &record.target, &record.message)
+    });
 }
 
 /// Set the log callback function for receiving tracing logs in JavaScript
+///
+/// The callback is called as `callback(level, target, message)` for each
+/// record the log filter lets through.
 #[wasm_bindgen(js_name = setLogCallback)]
 pub fn set_log_callback(callback: js_sys::Function) {
     LOG_CALLBACK.with(|cb| {
@@ -96,28 +372,57 @@ pub fn set_log_callback(callback: js_sys::Function) {
 }
 
 /// Enable or disable debug-level logging
+///
+/// Shorthand for `setLogLevel("trace")` and `setLogLevel("info")`.
 #[wasm_bindgen(js_name = setDebugEnabled)]
 pub fn set_debug_enabled(enabled: bool) {
-    DEBUG_ENABLED.with(|debug| {
-        *debug.borrow_mut() = enabled;
-    });
+    let level = if enabled {
+        LevelFilter::TRACE
+    } else {
+        LevelFilter::INFO
+    };
+    LOG_CAPTURE.with(|capture| capture.set_level(level));
     console_log!(format!(
         "Debug logging {}",
         if enabled { "enabled" } else { "disabled" }
     ));
 }
 
+/// Set the level of records passed to the log callback
+///
+/// One of `"off"`, `"error"`, `"warn"`, `"info"`, `"debug"` or `"trace"`.
+/// Module filters set with `setLogFilter` are kept.
+#[wasm_bindgen(js_name = setLogLevel)]
+pub fn set_log_level(level: &str) -> Result<(), JsValue> {
+    let level = level.parse::<LevelFilter>().map_err(|_| {
+        tor_error_to_js(TorError::configuration(format!(
+            "Unknown log level: {:?}",
+            level
+        )))
+    })?;
+    LOG_CAPTURE.with(|capture| capture.set_level(level));
+    Ok(())
+}
+
+/// Set the level and per-module filters of the log callback
+///
+/// Takes comma-separated directives, e.g.
+/// `"warn,webtor::circuit=debug,tor_proto=off"`.
+#[wasm_bindgen(js_name = setLogFilter)]
+pub fn set_log_filter(filter: &str) -> Result<(), JsValue> {
+    let filter: LogFilter = filter.parse().map_err(tor_error_to_js)?;
+    LOG_CAPTURE.with(|capture| capture.set_filter(filter));
+    Ok(())
+}
+
+/// The current log filter, in the form `setLogFilter` takes
+#[wasm_bindgen(js_name = getLogFilter)]
+pub fn get_log_filter() -> String {
+    LOG_CAPTURE.with(|capture| capture.filter().to_string())
+}
+
 /// Internal function to forward a log message to JS
 fn forward_log(level: &str, target: &str, message: &str) {
-    // Check if we should log based on level
-    let is_debug = level == "DEBUG" || level == "TRACE";
-    if is_debug {
-        let debug_enabled = DEBUG_ENABLED.with(|debug| *debug.borrow());
-        if !debug_enabled {
-            return;
-        }
-    }
-
     LOG_CALLBACK.with(|cb| {
         if let Some(ref callback) = *cb.borrow() {
             let this = JsValue::NULL;
@@ -164,6 +469,77 @@ impl TorClientOptions {
         }
     }
 
+    /// Create options for a meek bridge (the server must allow CORS)
+    #[wasm_bindgen(js_name = meek)]
+    pub fn meek(url: String, fingerprint: String) -> Self {
+        console_log!(format!("Creating TorClientOptions with meek URL: {}", url));
+
+        Self {
+            inner: NativeTorClientOptions::meek(url, fingerprint),
+        }
+    }
+
+    /// Create options for a bridge reached through the transport registered
+    /// as `name` with `withTransport`
+    #[wasm_bindgen(js_name = custom)]
+    pub fn custom(name: String, fingerprint: String) -> Self {
+        console_log!(format!(
+            "Creating TorClientOptions with custom transport: {}",
+            name
+        ));
+
+        Self {
+            inner: NativeTorClientOptions::custom(name, fingerprint),
+        }
+    }
+
+    /// Create options from a JSON string with the fields of the Rust
+    /// `TorClientOptions` (snake_case; durations in ms); missing fields take
+    /// their defaults and the `with*` methods still apply on top
+    #[wasm_bindgen(js_name = fromJson)]
+    pub fn from_json(json: &str) -> Result<TorClientOptions, JsValue> {
+        Ok(Self {
+            inner: NativeTorClientOptions::from_json(json).map_err(tor_error_to_js)?,
+        })
+    }
+
+    /// Create options from a torrc (`Bridge`, `UseBridges`, `ExitNodes`,
+    /// `ExcludeNodes` and `MaxCircuitDirtiness`; other directives are ignored)
+    #[wasm_bindgen(js_name = fromTorrc)]
+    pub fn from_torrc(text: &str) -> Result<TorClientOptions, JsValue> {
+        Ok(Self {
+            inner: NativeTorClientOptions::from_torrc(text).map_err(tor_error_to_js)?,
+        })
+    }
+
+    /// Create options from a bridge line as handed out by BridgeDB or Tor Browser
+    #[wasm_bindgen(js_name = fromBridgeLine)]
+    pub fn from_bridge_line(line: &str) -> Result<TorClientOptions, JsValue> {
+        console_log!(format!(
+            "Creating TorClientOptions from bridge line: {}",
+            line
+        ));
+
+        Ok(Self {
+            inner: NativeTorClientOptions::from_bridge_line(line).map_err(tor_error_to_js)?,
+        })
+    }
+
+    /// Create options from several bridge lines (e.g. from
+    /// `MoatClient.bridgeLines`): the first is used until it fails, then
+    /// the others in order
+    #[wasm_bindgen(js_name = fromBridgeLines)]
+    pub fn from_bridge_lines(lines: Vec<String>) -> Result<TorClientOptions, JsValue> {
+        console_log!(format!(
+            "Creating TorClientOptions from {} bridge lines",
+            lines.len()
+        ));
+
+        Ok(Self {
+            inner: NativeTorClientOptions::from_bridge_lines(&lines).map_err(tor_error_to_js)?,
+        })
+    }
+
     /// Create options for Snowflake bridge via WebRTC (more censorship resistant)
     #[wasm_bindgen(js_name = snowflakeWebRtc)]
     pub fn snowflake_webrtc() -> Self {
@@ -199,17 +575,499 @@ impl TorClientOptions {
         self
     }
 
+    /// Milliseconds after first use during which a circuit takes new streams (default 10 minutes)
+    #[wasm_bindgen(js_name = withMaxCircuitDirtiness)]
+    pub fn with_max_circuit_dirtiness(mut self, dirtiness: u32) -> Self {
+        self.inner = self.inner.with_max_circuit_dirtiness(dirtiness as u64);
+        self
+    }
+
+    #[wasm_bindgen(js_name = withMaintenanceInterval)]
+    pub fn with_maintenance_interval(mut self, interval: Option<u32>) -> Self {
+        let interval_ms = interval.map(|i| i as u64);
+        self.inner = self.inner.with_maintenance_interval(interval_ms);
+        self
+    }
+
+    /// How often failing fallback bridges are probed, in ms (null disables probing)
+    #[wasm_bindgen(js_name = withBridgeProbeInterval)]
+    pub fn with_bridge_probe_interval(mut self, interval: Option<u32>) -> Self {
+        let interval_ms = interval.map(|i| i as u64);
+        self.inner = self.inner.with_bridge_probe_interval(interval_ms);
+        self
+    }
+
+    /// Race all configured bridges, starting each `stagger` ms after the
+    /// previous one, and keep the first to connect (null tries them in turn)
+    #[wasm_bindgen(js_name = withBridgeRacing)]
+    pub fn with_bridge_racing(mut self, stagger: Option<u32>) -> Self {
+        let stagger_ms = stagger.map(|s| s as u64);
+        self.inner = self.inner.with_bridge_race_stagger(stagger_ms);
+        self
+    }
+
+    /// Close the connection after `idleMs` without requests or open streams,
+    /// keeping the directory and guards; the next request reconnects (null
+    /// stays connected)
+    #[wasm_bindgen(js_name = withDormantAfter)]
+    pub fn with_dormant_after(mut self, idle_ms: Option<u32>) -> Self {
+        self.inner = self.inner.with_dormant_after(idle_ms.map(|ms| ms as u64));
+        self
+    }
+
+    /// What to do while the page is hidden: "ignore" (default), "suspend"
+    /// (pause padding and bridge probing) or "dormant" (also close the
+    /// connection unless streams are open)
+    #[wasm_bindgen(js_name = withVisibilityPolicy)]
+    pub fn with_visibility_policy(mut self, policy: &str) -> Result<TorClientOptions, JsValue> {
+        let policy = policy.parse().map_err(tor_error_to_js)?;
+        self.inner = self.inner.with_visibility_policy(policy);
+        Ok(self)
+    }
+
+    /// Persist state such as guard selection in `localStorage` under `prefix`
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen(js_name = withLocalStorage)]
+    ///
+    /// Without `localStorage` (e.g. blocked by privacy settings) state stays
+    /// in memory, as `getCapabilities()` reports.
+    pub fn with_local_storage(mut self, prefix: String) -> Self {
+        if !webtor::capabilities::Capabilities::detect().persistent_state() {
+            console_warn!("localStorage unavailable; keeping client state in memory");
+            return self;
+        }
+        self.inner = self
+            .inner
+            .with_state_store(webtor::storage::LocalStorageStore::new(prefix));
+        self
+    }
+
+    /// Overall deadline for connecting, loading the directory and building the
+    /// first circuit; on expiry the error carries a `bootstrapReport`
+    #[wasm_bindgen(js_name = withBootstrapTimeout)]
+    pub fn with_bootstrap_timeout(mut self, timeout: Option<u32>) -> Self {
+        let timeout_ms = timeout.map(|t| t as u64);
+        self.inner = self.inner.with_bootstrap_timeout(timeout_ms);
+        self
+    }
+
+    /// Call `callback` with `{ phase, progress, summary }` whenever the
+    /// bootstrap phase or percentage changes, from the bootstrap the
+    /// constructor runs on; `phase` is tor's tag (`conn_pt`, `handshake`,
+    /// `loading_descriptors`, `circuit_create`, `done`, ...) and `progress`
+    /// runs from 0 to 100
+    #[wasm_bindgen(js_name = withOnBootstrapProgress)]
+    pub fn with_on_bootstrap_progress(mut self, callback: js_sys::Function) -> Self {
+        use futures::StreamExt;
+
+        let progress = self.inner.bootstrap_progress.clone().unwrap_or_default();
+        let mut statuses = progress.subscribe();
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Some(status) = statuses.next().await {
+                let value = serde_wasm_bindgen::to_value(&status).unwrap_or(JsValue::NULL);
+                if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                    console_warn!(format!("Bootstrap progress callback threw: {:?}", e));
+                }
+            }
+        });
+        self.inner = self.inner.with_bootstrap_progress(progress);
+        self
+    }
+
+    /// Share the consensus and microdescriptors with every client given
+    /// the same `cache`, so the page downloads them once
+    #[wasm_bindgen(js_name = withDirectoryCache)]
+    pub fn with_directory_cache(mut self, cache: &JsDirectoryCache) -> Self {
+        self.inner = self.inner.with_directory_cache(cache.inner.clone());
+        self
+    }
+
+    /// Avoid relays for `blacklistMs` (doubling per repeat, up to `maxBlacklistMs`)
+    /// after `failureThreshold` consecutive extension failures; 0 disables it
+    #[wasm_bindgen(js_name = withReachability)]
+    pub fn with_reachability(
+        mut self,
+        failure_threshold: u32,
+        blacklist_ms: u32,
+        max_blacklist_ms: u32,
+    ) -> Self {
+        self.inner = self
+            .inner
+            .with_reachability(webtor::reachability::ReachabilityConfig {
+                failure_threshold,
+                blacklist_ms: blacklist_ms as u64,
+                max_blacklist_ms: max_blacklist_ms as u64,
+            });
+        self
+    }
+
+    /// Reconnect as soon as the bridge drops the channel, waiting
+    /// `initialDelayMs` before the first attempt and doubling the wait up to
+    /// `maxDelayMs`; `maxAttempts` (null for no limit) bounds the attempts.
+    /// With `enabled` false the next request reconnects instead.
+    #[wasm_bindgen(js_name = withReconnect)]
+    pub fn with_reconnect(
+        mut self,
+        enabled: bool,
+        initial_delay_ms: u32,
+        max_delay_ms: u32,
+        max_attempts: Option<u32>,
+    ) -> Self {
+        self.inner = self
+            .inner
+            .with_reconnect(webtor::reconnect::ReconnectConfig {
+                enabled,
+                initial_delay_ms: initial_delay_ms as u64,
+                max_delay_ms: max_delay_ms as u64,
+                max_attempts,
+            });
+        self
+    }
+
+    /// Tune the learned circuit build timeout: the timeout used until enough
+    /// builds are seen (also the ceiling), the floor, and the share of builds
+    /// (percent) that should finish in time
+    #[wasm_bindgen(js_name = withBuildTimeout)]
+    pub fn with_build_timeout(
+        mut self,
+        initial_ms: u32,
+        min_ms: u32,
+        quantile_percent: u8,
+    ) -> Self {
+        self.inner = self
+            .inner
+            .with_build_timeout(webtor::build_timeout::BuildTimeoutConfig {
+                initial_ms: initial_ms as u64,
+                min_ms: min_ms as u64,
+                quantile_percent,
+                ..Default::default()
+            });
+        self
+    }
+
+    /// Negotiate congestion control with exits that support it (on by default)
+    #[wasm_bindgen(js_name = withCongestionControl)]
+    pub fn with_congestion_control(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_congestion_control(enabled);
+        self
+    }
+
+    /// Build exit circuits as two linked legs (conflux) where the exit supports it
+    #[wasm_bindgen(js_name = withConflux)]
+    pub fn with_conflux(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_conflux(enabled);
+        self
+    }
+
+    /// Times a request moves to a new circuit after a retriable failure:
+    /// circuit closed, exit refusal, connection reset or connect/first-byte
+    /// timeout (default 2)
+    #[wasm_bindgen(js_name = withStreamRetries)]
+    pub fn with_stream_retries(mut self, retries: u32) -> Self {
+        self.inner = self.inner.with_stream_retries(retries);
+        self
+    }
+
+    /// Send request bytes before the exit confirms the connection (on by default)
+    #[wasm_bindgen(js_name = withOptimisticData)]
+    pub fn with_optimistic_data(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_optimistic_data(enabled);
+        self
+    }
+
+    /// Open streams per circuit before new ones use another circuit (default 64, 0 for no cap)
+    #[wasm_bindgen(js_name = withMaxStreamsPerCircuit)]
+    pub fn with_max_streams_per_circuit(mut self, max: u32) -> Self {
+        self.inner = self.inner.with_max_streams_per_circuit(max as usize);
+        self
+    }
+
+    /// Circuit builds raced when a request has no circuit; the first to finish wins (default 1)
+    #[wasm_bindgen(js_name = withParallelCircuitBuilds)]
+    pub fn with_parallel_circuit_builds(mut self, builds: u32) -> Self {
+        self.inner = self.inner.with_parallel_circuit_builds(builds as usize);
+        self
+    }
+
+    /// Connections opened to the bridge, with circuits spread across them (default 1)
+    #[wasm_bindgen(js_name = withChannelsPerBridge)]
+    pub fn with_channels_per_bridge(mut self, channels: u32) -> Self {
+        self.inner = self.inner.with_channels_per_bridge(channels as usize);
+        self
+    }
+
+    /// Padding on channels: "normal" (default, like a regular Tor client), "reduced" or "none"
+    #[wasm_bindgen(js_name = withChannelPadding)]
+    pub fn with_channel_padding(mut self, padding: &str) -> Result<TorClientOptions, JsValue> {
+        let padding = padding.parse().map_err(tor_error_to_js)?;
+        self.inner = self.inner.with_channel_padding(padding);
+        Ok(self)
+    }
+
+    /// Seed relay selection so circuit paths are reproducible (tests and simulations only)
+    #[wasm_bindgen(js_name = withPathSelectionSeed)]
+    pub fn with_path_selection_seed(mut self, seed: u32) -> Self {
+        self.inner = self.inner.with_path_selection_seed(seed as u64);
+        self
+    }
+
+    /// Pin the middle hop of long-lived circuits to layer-2 guards (on by default)
+    #[wasm_bindgen(js_name = withVanguardsLite)]
+    pub fn with_vanguards_lite(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_vanguards_lite(enabled);
+        self
+    }
+
     #[wasm_bindgen(js_name = withCircuitUpdateAdvance)]
     pub fn with_circuit_update_advance(mut self, advance: u32) -> Self {
         self.inner = self.inner.with_circuit_update_advance(advance as u64);
         self
     }
 
+    /// Fall back to another Snowflake broker URL if the previous ones fail (WebRTC only)
+    #[wasm_bindgen(js_name = withSnowflakeBroker)]
+    pub fn with_snowflake_broker(mut self, broker_url: String) -> Self {
+        self.inner = self
+            .inner
+            .with_snowflake_fallback(Rendezvous::http(&broker_url));
+        self
+    }
+
+    /// Fall back to reaching the broker through an AMP cache (default
+    /// `https://cdn.ampproject.org/`) if the previous methods fail (WebRTC only)
+    #[wasm_bindgen(js_name = withSnowflakeAmpCache)]
+    pub fn with_snowflake_amp_cache(
+        mut self,
+        cache_url: Option<String>,
+        broker_url: Option<String>,
+    ) -> Self {
+        let mut rendezvous = Rendezvous::amp_cache().with_fronts(Vec::new());
+        if let Rendezvous::AmpCache {
+            broker_url: broker,
+            cache_url: cache,
+            ..
+        } = &mut rendezvous
+        {
+            if let Some(url) = cache_url {
+                *cache = url;
+            }
+            if let Some(url) = broker_url {
+                *broker = url;
+            }
+        }
+        self.inner = self.inner.with_snowflake_fallback(rendezvous);
+        self
+    }
+
     #[wasm_bindgen(js_name = withBridgeFingerprint)]
     pub fn with_bridge_fingerprint(mut self, fingerprint: String) -> Self {
         self.inner = self.inner.with_bridge_fingerprint(fingerprint);
         self
     }
+
+    /// The bridge's ed25519 identity (hex or base64), checked in the
+    /// channel handshake along with the fingerprint
+    #[wasm_bindgen(js_name = withBridgeEd25519Id)]
+    pub fn with_bridge_ed25519_id(mut self, id: String) -> Self {
+        self.inner = self.inner.with_bridge_ed25519_id(id);
+        self
+    }
+
+    /// The bridge's ntor onion key (hex or base64), for an ntor first hop
+    #[wasm_bindgen(js_name = withBridgeNtorKey)]
+    pub fn with_bridge_ntor_key(mut self, key: String) -> Self {
+        self.inner = self.inner.with_bridge_ntor_key(key);
+        self
+    }
+
+    /// How internationalized hostnames are handled: "convert" (default), "reject" or "passThrough"
+    #[wasm_bindgen(js_name = withHostnamePolicy)]
+    pub fn with_hostname_policy(mut self, policy: &str) -> Result<TorClientOptions, JsValue> {
+        use webtor::hostname::HostnamePolicy;
+        let policy = match policy {
+            "convert" => HostnamePolicy::Convert,
+            "reject" => HostnamePolicy::Reject,
+            "passThrough" => HostnamePolicy::PassThrough,
+            other => {
+                return Err(tor_error_to_js(TorError::configuration(format!(
+                    "Unknown hostname policy: {}",
+                    other
+                ))))
+            }
+        };
+        self.inner = self.inner.with_hostname_policy(policy);
+        Ok(self)
+    }
+
+    /// Keep cookies from responses and send them back to the same site, per
+    /// isolation key (default false)
+    #[wasm_bindgen(js_name = withCookies)]
+    pub fn with_cookies(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_cookies(enabled);
+        self
+    }
+
+    /// Ask for gzip/deflate/brotli responses and decode them (default true);
+    /// false returns bodies exactly as the server sent them
+    #[wasm_bindgen(js_name = withDecompression)]
+    pub fn with_decompression(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_decompress_responses(enabled);
+        self
+    }
+
+    /// Bytes a response may take before the request rejects with
+    /// `RESPONSE_TOO_LARGE` (default 10 MiB); 0 means no cap
+    #[wasm_bindgen(js_name = withMaxResponseSize)]
+    pub fn with_max_response_size(mut self, bytes: usize) -> Self {
+        self.inner = self.inner.with_max_response_size(bytes);
+        self
+    }
+
+    /// Redirects followed per request before it fails (default 20); 0 returns
+    /// redirect responses as they are
+    #[wasm_bindgen(js_name = withMaxRedirects)]
+    pub fn with_max_redirects(mut self, max: u32) -> Self {
+        let mut policy = self.inner.redirect_policy.clone();
+        policy.follow = max > 0;
+        policy.max_redirects = max;
+        self.inner = self.inner.with_redirect_policy(policy);
+        self
+    }
+
+    /// Follow redirects to other origins (default true); when false the
+    /// redirect response is returned instead
+    #[wasm_bindgen(js_name = withCrossOriginRedirects)]
+    pub fn with_cross_origin_redirects(mut self, allowed: bool) -> Self {
+        let policy = self
+            .inner
+            .redirect_policy
+            .clone()
+            .with_cross_origin(allowed);
+        self.inner = self.inner.with_redirect_policy(policy);
+        self
+    }
+
+    /// Follow redirects from HTTPS to HTTP (default false: such redirects fail)
+    #[wasm_bindgen(js_name = withDowngradeRedirects)]
+    pub fn with_downgrade_redirects(mut self, allowed: bool) -> Self {
+        let policy = self.inner.redirect_policy.clone().with_downgrade(allowed);
+        self.inner = self.inner.with_redirect_policy(policy);
+        self
+    }
+
+    /// Load a GeoIP table in Tor's `geoip` file format for country-aware selection
+    #[wasm_bindgen(js_name = withGeoIp)]
+    pub fn with_geoip(mut self, table: &str) -> Result<TorClientOptions, JsValue> {
+        let geoip = webtor::geoip::GeoIpDb::parse(table).map_err(tor_error_to_js)?;
+        self.inner = self.inner.with_geoip(geoip);
+        Ok(self)
+    }
+
+    /// Never use exits in these countries (e.g. `["US", "GB"]`; `"??"` for unknown)
+    #[wasm_bindgen(js_name = withExcludeExitCountries)]
+    pub fn with_exclude_exit_countries(mut self, countries: Vec<String>) -> Self {
+        self.inner = self.inner.with_exclude_exit_countries(countries);
+        self
+    }
+
+    /// Only use exits in this torrc-style node list (fingerprints, `{cc}`
+    /// country codes or nicknames)
+    #[wasm_bindgen(js_name = withExitNodes)]
+    pub fn with_exit_nodes(mut self, nodes: Vec<String>) -> Self {
+        self.inner = self.inner.with_exit_nodes(nodes);
+        self
+    }
+
+    /// Never use relays in this torrc-style node list
+    #[wasm_bindgen(js_name = withExcludeNodes)]
+    pub fn with_exclude_nodes(mut self, nodes: Vec<String>) -> Self {
+        self.inner = self.inner.with_exclude_nodes(nodes);
+        self
+    }
+
+    /// Trust the PEM root certificates in `pem` for HTTPS and `wss://`, on
+    /// top of the built-in roots or, with `replaceBuiltin`, instead of them
+    #[wasm_bindgen(js_name = withRootCertificates)]
+    pub fn with_root_certificates(
+        mut self,
+        pem: &str,
+        replace_builtin: bool,
+    ) -> Result<TorClientOptions, JsValue> {
+        let roots = self
+            .inner
+            .tls_roots
+            .clone()
+            .with_pem(pem)
+            .map_err(tor_error_to_js)?
+            .with_replace_builtin(replace_builtin);
+        self.inner = self.inner.with_tls_roots(roots);
+        Ok(self)
+    }
+
+    /// Resume TLS 1.3 sessions with servers seen before, per isolation key
+    /// (default true); skips the certificate chain on repeat connections
+    #[wasm_bindgen(js_name = withTlsSessionResumption)]
+    pub fn with_tls_session_resumption(mut self, enabled: bool) -> Self {
+        self.inner = self.inner.with_tls_session_resumption(enabled);
+        self
+    }
+
+    /// Encrypt the ClientHello to `host` with its base64 ECHConfigList, the
+    /// `ech` parameter of its DNS HTTPS record
+    #[wasm_bindgen(js_name = withEchConfig)]
+    pub fn with_ech_config(
+        mut self,
+        host: &str,
+        ech_config_list: &str,
+    ) -> Result<TorClientOptions, JsValue> {
+        let configs = self
+            .inner
+            .ech_configs
+            .clone()
+            .with_base64(host, ech_config_list)
+            .map_err(tor_error_to_js)?;
+        self.inner = self.inner.with_ech_configs(configs);
+        Ok(self)
+    }
+
+    /// Write requests as `"webtor"` (the default) or `"tor-browser"` does;
+    /// the latter sends Tor Browser's headers in its order and never
+    /// resumes TLS sessions
+    #[wasm_bindgen(js_name = withRequestProfile)]
+    pub fn with_request_profile(mut self, profile: &str) -> Result<TorClientOptions, JsValue> {
+        let profile = profile.parse().map_err(tor_error_to_js)?;
+        self.inner = self.inner.with_request_profile(profile);
+        Ok(self)
+    }
+
+    /// Add a bridge, given as a bridge line, to fail over to when the
+    /// configured one can't be reached or its channel is lost
+    #[wasm_bindgen(js_name = withFallbackBridgeLine)]
+    pub fn with_fallback_bridge_line(mut self, line: &str) -> Result<TorClientOptions, JsValue> {
+        self.inner = self
+            .inner
+            .with_fallback_bridge_line(line)
+            .map_err(tor_error_to_js)?;
+        Ok(self)
+    }
+
+    /// Register a transport implemented in JavaScript as `name`
+    ///
+    /// `transport.connect()` returns (a promise of) `{ readable, writable }`
+    /// byte streams to the bridge's ORPort, e.g. a WebTransport
+    /// bidirectional stream. Options created with `custom(name, ...)` connect
+    /// through it.
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen(js_name = withTransport)]
+    pub fn with_transport(
+        mut self,
+        name: String,
+        transport: JsValue,
+    ) -> Result<TorClientOptions, JsValue> {
+        let transport = webtor::transport::JsTransport::new(transport).map_err(tor_error_to_js)?;
+        self.inner = self.inner.with_transport(name, Arc::new(transport));
+        Ok(self)
+    }
 }
 
 /// JavaScript-friendly TorClient
@@ -217,11 +1075,21 @@ impl TorClientOptions {
 #[derive(Clone)]
 pub struct TorClient {
     inner: Option<Arc<NativeTorClient>>,
+    listeners: Rc<RefCell<Vec<EventListener>>>,
+}
+
+/// A callback registered with `addEventListener`
+struct EventListener {
+    category: String,
+    callback: js_sys::Function,
+    /// Stops the task delivering to the callback
+    cancel: CancellationToken,
 }
 
 #[wasm_bindgen]
 impl TorClient {
     #[wasm_bindgen(constructor)]
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(options: TorClientOptions) -> js_sys::Promise {
         console_log!("Creating new TorClient");
 
@@ -233,6 +1101,7 @@ impl TorClient {
                     console_log!("TorClient created successfully");
                     Ok(JsValue::from(TorClient {
                         inner: Some(Arc::new(client)),
+                        listeners: Rc::default(),
                     }))
                 }
                 Err(e) => {
@@ -243,31 +1112,78 @@ impl TorClient {
         })
     }
 
-    /// Make a fetch (GET) request through Tor
-    #[wasm_bindgen(js_name = fetch)]
-    pub fn fetch(&self, url: String) -> js_sys::Promise {
-        console_log!(format!("Starting fetch request to: {}", url));
+    /// Create a TorClient over an RTCDataChannel the application negotiated
+    ///
+    /// The far end must speak the Snowflake server protocol (Turbo, KCP,
+    /// SMUX, TLS), and `options` must set the bridge fingerprint. The page
+    /// keeps ownership of the peer connection.
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen(js_name = withDataChannel)]
+    pub fn with_data_channel(
+        options: TorClientOptions,
+        data_channel: web_sys::RtcDataChannel,
+    ) -> js_sys::Promise {
+        console_log!("Creating TorClient over application DataChannel");
 
-        let client = match &self.inner {
-            Some(client) => client.clone(),
-            None => {
-                return future_to_promise(async move {
+        let options = options.inner;
+
+        future_to_promise(async move {
+            let result = async {
+                let stream = webtor::datachannel::connect_data_channel(
+                    data_channel,
+                    webtor::datachannel::DataChannelConfig::default(),
+                )
+                .await?;
+                NativeTorClient::with_stream(options, stream).await
+            }
+            .await;
+            match result {
+                Ok(client) => {
+                    console_log!("TorClient created successfully");
+                    Ok(JsValue::from(TorClient {
+                        inner: Some(Arc::new(client)),
+                        listeners: Rc::default(),
+                    }))
+                }
+                Err(e) => {
+                    console_error!(format!("Failed to create TorClient: {}", e));
+                    Err(tor_error_to_js(e))
+                }
+            }
+        })
+    }
+
+    /// Make a fetch (GET) request through Tor
+    ///
+    /// `headers` is an optional object of extra request headers; they replace
+    /// the defaults of the same name, except hop-critical ones like `Host`.
+    /// Aborting the optional `signal` (an `AbortSignal`) rejects the promise
+    /// with a `CANCELLED` error and closes the request's Tor stream.
+    #[wasm_bindgen(js_name = fetch)]
+    pub fn fetch(&self, url: String, headers: JsValue, signal: JsValue) -> js_sys::Promise {
+        console_log!(format!("Starting fetch request to: {}", url));
+
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
                     Err(JsTorError::not_initialized().into_js_value())
                 });
             }
         };
 
         future_to_promise(async move {
-            match client.fetch(&url).await {
+            let url_parsed = webtor::Url::parse(&url)
+                .map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+            let mut request = HttpRequest::new(url_parsed).with_headers(headers_from_js(headers)?);
+            if let Some(token) = cancellation_from_signal(&signal)? {
+                request = request.with_cancellation(token);
+            }
+            match client.send(request).await {
                 Ok(response) => {
                     console_log!("Fetch request completed successfully");
 
-                    let js_response = JsHttpResponse {
-                        status: response.status,
-                        headers: headers_to_js(&response.headers),
-                        body: response.body,
-                        url: response.url.to_string(),
-                    };
+                    let js_response = JsHttpResponse::from(response);
 
                     Ok(JsValue::from(js_response))
                 }
@@ -279,6 +1195,313 @@ impl TorClient {
         })
     }
 
+    /// Open a raw TCP connection to `host:port` through Tor
+    ///
+    /// Resolves to a `TorSocket` for protocols other than HTTP. The exit
+    /// resolves `host` and must allow `port`; `isolation` (optional string
+    /// or integer) keeps the connection on circuits reserved for that token.
+    pub fn connect(&self, host: String, port: u16, isolation: JsValue) -> js_sys::Promise {
+        console_log!(format!("Connecting to {}:{}", host, port));
+
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let result = match isolation_token_from_js(&isolation)? {
+                Some(token) => client.connect_isolated(&host, port, token).await,
+                None => client.connect(&host, port).await,
+            };
+            match result {
+                Ok(stream) => Ok(JsValue::from(JsTorSocket::new(stream, None))),
+                Err(e) => {
+                    console_error!(format!("Connection to {}:{} failed: {}", host, port, e));
+                    Err(tor_error_to_js(e))
+                }
+            }
+        })
+    }
+
+    /// Open a TLS connection to `host:port` through Tor
+    ///
+    /// Like `connect`, with TLS to `host` on top. `alpn` (optional array,
+    /// most preferred first, e.g. `["h2", "http/1.1"]`) is offered to the
+    /// server; the resulting `TorSocket`'s `alpnProtocol` is the one it chose.
+    #[wasm_bindgen(js_name = connectTls)]
+    pub fn connect_tls(
+        &self,
+        host: String,
+        port: u16,
+        alpn: Option<Vec<String>>,
+    ) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let alpn = alpn.unwrap_or_default();
+            let alpn: Vec<&str> = alpn.iter().map(String::as_str).collect();
+            match client.connect_tls(&host, port, &alpn).await {
+                Ok(stream) => {
+                    let protocol = stream.alpn_protocol().map(str::to_string);
+                    Ok(JsValue::from(JsTorSocket::new(stream, protocol)))
+                }
+                Err(e) => {
+                    console_error!(format!("TLS connection to {}:{} failed: {}", host, port, e));
+                    Err(tor_error_to_js(e))
+                }
+            }
+        })
+    }
+
+    /// Host an onion service reachable at a new `.onion` address
+    ///
+    /// Resolves to a `TorOnionService` once its descriptor is published.
+    /// `ports` (optional array) limits the virtual ports clients may
+    /// connect to; any port is allowed without it. The address lasts only
+    /// as long as the service. `singleOnion: true` opts into a single onion
+    /// service, reached over fewer hops but without hiding where it runs.
+    #[wasm_bindgen(js_name = launchOnionService)]
+    pub fn launch_onion_service(
+        &self,
+        ports: Option<Vec<u16>>,
+        single_onion: Option<bool>,
+    ) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let config = ports
+                .unwrap_or_default()
+                .into_iter()
+                .fold(webtor::OnionServiceConfig::default(), |config, port| {
+                    config.with_port(port)
+                })
+                .with_single_onion(single_onion.unwrap_or(false));
+            match client.launch_onion_service(config).await {
+                Ok(service) => Ok(JsValue::from(JsOnionService {
+                    address: service.address().to_string(),
+                    inner: Rc::new(futures::lock::Mutex::new(Some(service))),
+                    closing: CancellationToken::new(),
+                })),
+                Err(e) => {
+                    console_error!(format!("Failed to launch onion service: {}", e));
+                    Err(tor_error_to_js(e))
+                }
+            }
+        })
+    }
+
+    /// Open a WebSocket to a `ws://` or `wss://` URL through Tor
+    ///
+    /// Resolves to a `TorWebSocket`. `options` may hold `protocols` (array
+    /// of subprotocols to offer), `headers`, `timeoutMs`, `maxMessageSize`,
+    /// `isolation` and `pins` (array of `"sha256/<base64>"` SPKI hashes or
+    /// PEM certificates the server's chain must contain).
+    pub fn websocket(&self, url: String, options: JsValue) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let request = websocket_request_from_init(&url, &options)?;
+            match client.websocket(request).await {
+                Ok(socket) => Ok(JsValue::from(JsTorWebSocket {
+                    inner: Rc::new(socket),
+                })),
+                Err(e) => {
+                    console_error!(format!("WebSocket to {} failed: {}", url, e));
+                    Err(tor_error_to_js(e))
+                }
+            }
+        })
+    }
+
+    /// Open a Server-Sent Events stream to an `http://` or `https://` URL
+    /// through Tor
+    ///
+    /// Returns a `TorEventSource` at once, like `new EventSource(url)`; it
+    /// connects in the background. `options` may hold `headers`,
+    /// `timeoutMs`, `maxEventSize`, `isolation`, `pins`, `lastEventId` (to
+    /// resume an earlier stream) and `maxReconnects` (failed reconnections
+    /// in a row before giving up, default 5).
+    #[wasm_bindgen(js_name = eventSource)]
+    pub fn event_source(&self, url: String, options: JsValue) -> Result<JsTorEventSource, JsValue> {
+        let client = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| JsTorError::not_initialized().into_js_value())?
+            .clone();
+        let request = event_source_request_from_init(&url, &options)?;
+        let shared = Rc::new(EventSourceShared {
+            url: request.url.to_string(),
+            ..Default::default()
+        });
+
+        let target = shared.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let source = match client.event_source(request).await {
+                Ok(source) => Rc::new(source),
+                Err(e) => {
+                    console_error!(format!("Event source {} failed: {}", target.url, e));
+                    target.closed.set(true);
+                    target.dispatch_error(e);
+                    return;
+                }
+            };
+            target.source.replace(Some(source.clone()));
+            if target.closed.get() {
+                source.close();
+                return;
+            }
+            target.dispatch("open", &js_sys::Object::new());
+            loop {
+                match source.recv().await {
+                    Ok(Some(event)) => target.dispatch_message(event),
+                    Ok(None) => break,
+                    Err(e) => {
+                        console_warn!(format!("Event source {} closed: {}", target.url, e));
+                        target.closed.set(true);
+                        target.dispatch_error(e);
+                        break;
+                    }
+                }
+            }
+            target.closed.set(true);
+        });
+        Ok(JsTorEventSource { shared })
+    }
+
+    /// Resolve `hostname` through a Tor exit
+    ///
+    /// Resolves to an array of IPv4/IPv6 address strings. The lookup never
+    /// touches the browser's or system's DNS.
+    pub fn resolve(&self, hostname: String) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let addrs = client.resolve(&hostname).await.map_err(tor_error_to_js)?;
+            let addrs: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+            Ok(serde_wasm_bindgen::to_value(&addrs)?)
+        })
+    }
+
+    /// Reverse-resolve the IP address `address` through a Tor exit
+    ///
+    /// Resolves to an array of hostnames.
+    #[wasm_bindgen(js_name = resolvePtr)]
+    pub fn resolve_ptr(&self, address: String) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let addr = address.parse().map_err(|_| {
+                JsTorError::from_str(
+                    "INVALID_ADDRESS",
+                    "configuration",
+                    &format!("Not an IP address: {}", address),
+                    false,
+                )
+                .into_js_value()
+            })?;
+            let names = client.resolve_ptr(addr).await.map_err(tor_error_to_js)?;
+            Ok(serde_wasm_bindgen::to_value(&names)?)
+        })
+    }
+
+    /// A client sharing this one's connection and directory whose requests
+    /// and streams never share a circuit, cookies or TLS sessions with any
+    /// other client (e.g. one per account)
+    #[wasm_bindgen(js_name = isolatedClient)]
+    pub fn isolated_client(&self) -> Result<TorClient, JsValue> {
+        let client = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| JsTorError::not_initialized().into_js_value())?;
+        Ok(TorClient {
+            inner: Some(Arc::new(client.isolated_client())),
+            listeners: Rc::default(),
+        })
+    }
+
+    /// Make a fetch (GET) request on circuits reserved for `isolation`
+    ///
+    /// `isolation` is a string or integer; requests with different tokens
+    /// never share a circuit.
+    #[wasm_bindgen(js_name = fetchIsolated)]
+    pub fn fetch_isolated(&self, url: String, isolation: JsValue) -> js_sys::Promise {
+        console_log!(format!("Starting isolated fetch request to: {}", url));
+
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let token = isolation_token_from_js(&isolation)?.ok_or_else(|| {
+                JsTorError::from_str(
+                    "INVALID_ISOLATION_TOKEN",
+                    "configuration",
+                    "An isolation token is required",
+                    false,
+                )
+                .into_js_value()
+            })?;
+
+            match client.fetch_isolated(&url, token).await {
+                Ok(response) => {
+                    console_log!("Isolated fetch request completed successfully");
+
+                    let js_response = JsHttpResponse::from(response);
+
+                    Ok(JsValue::from(js_response))
+                }
+                Err(e) => {
+                    console_error!(format!("Isolated fetch request failed: {}", e));
+                    Err(tor_error_to_js(e))
+                }
+            }
+        })
+    }
+
     /// Make a POST request through Tor
     #[wasm_bindgen(js_name = post)]
     pub fn post(&self, url: String, body: Vec<u8>) -> js_sys::Promise {
@@ -298,12 +1521,7 @@ impl TorClient {
                 Ok(response) => {
                     console_log!("POST request completed successfully");
 
-                    let js_response = JsHttpResponse {
-                        status: response.status,
-                        headers: headers_to_js(&response.headers),
-                        body: response.body,
-                        url: response.url.to_string(),
-                    };
+                    let js_response = JsHttpResponse::from(response);
 
                     Ok(JsValue::from(js_response))
                 }
@@ -346,12 +1564,7 @@ impl TorClient {
                 Ok(response) => {
                     console_log!("POST JSON request completed successfully");
 
-                    let js_response = JsHttpResponse {
-                        status: response.status,
-                        headers: headers_to_js(&response.headers),
-                        body: response.body,
-                        url: response.url.to_string(),
-                    };
+                    let js_response = JsHttpResponse::from(response);
 
                     Ok(JsValue::from(js_response))
                 }
@@ -364,14 +1577,20 @@ impl TorClient {
     }
 
     /// Make a generic HTTP request with full control over method, headers, body, and timeout
+    ///
+    /// `body` may be a string (sent as `text/plain` unless `headers` set a
+    /// `Content-Type`), a `Uint8Array` or an `ArrayBuffer`. An optional
+    /// `isolation` token (string or integer) keeps the request off circuits
+    /// used by other tokens. See `fetchWithOptions` for an abortable variant.
     #[wasm_bindgen(js_name = request)]
     pub fn request(
         &self,
         method: String,
         url: String,
         headers: JsValue,
-        body: Option<Vec<u8>>,
+        body: JsValue,
         timeout_ms: Option<u32>,
+        isolation: JsValue,
     ) -> js_sys::Promise {
         console_log!(format!("Starting {} request to: {}", method, url));
 
@@ -389,29 +1608,24 @@ impl TorClient {
                 .parse()
                 .map_err(|e| JsValue::from_str(&format!("Invalid HTTP method: {}", e)))?;
 
-            let headers_map: std::collections::HashMap<String, String> =
-                if headers.is_undefined() || headers.is_null() {
-                    std::collections::HashMap::new()
-                } else {
-                    serde_wasm_bindgen::from_value(headers)
-                        .map_err(|e| JsValue::from_str(&format!("Invalid headers object: {}", e)))?
-                };
-
-            let timeout = timeout_ms.map(|ms| std::time::Duration::from_millis(ms as u64));
+            let url_parsed = webtor::Url::parse(&url)
+                .map_err(|e| JsValue::from_str(&format!("Invalid URL: {}", e)))?;
+            let request = HttpRequest::new(url_parsed)
+                .with_method(method_parsed)
+                .with_headers(headers_from_js(headers)?);
+            let mut request = with_js_body(request, &body)?;
+            if let Some(ms) = timeout_ms {
+                request = request.with_timeout(std::time::Duration::from_millis(ms as u64));
+            }
+            if let Some(token) = isolation_token_from_js(&isolation)? {
+                request = request.with_isolation_token(token);
+            }
 
-            match client
-                .request(method_parsed, &url, headers_map, body, timeout)
-                .await
-            {
+            match client.send(request).await {
                 Ok(response) => {
                     console_log!("Request completed successfully");
 
-                    let js_response = JsHttpResponse {
-                        status: response.status,
-                        headers: headers_to_js(&response.headers),
-                        body: response.body,
-                        url: response.url.to_string(),
-                    };
+                    let js_response = JsHttpResponse::from(response);
 
                     Ok(JsValue::from(js_response))
                 }
@@ -423,6 +1637,83 @@ impl TorClient {
         })
     }
 
+    /// Make a request described by a `window.fetch`-style `init` object
+    ///
+    /// Recognized fields, all optional: `method` (default `GET`), `headers`
+    /// (plain object), `body` (string, `Uint8Array` or `ArrayBuffer`),
+    /// `timeoutMs` (whole request), `connectTimeoutMs` (circuit, stream and
+    /// TLS setup), `firstByteTimeoutMs` (wait for the response to start; the
+    /// rejection's `timeoutPhase` says which expired), `maxResponseSize`
+    /// (bytes; 0 for no cap), `newCircuit` (send it on a circuit never used
+    /// before or after), `retries` (retry budget on other circuits),
+    /// `isolation` (string or integer token), `signal` (an `AbortSignal`;
+    /// aborting rejects with `CANCELLED` and closes the request's Tor stream),
+    /// `pins` (`{ host: [pin, ...] }`, each pin a `"sha256/<base64>"`
+    /// SPKI hash or a PEM certificate; a chain without one rejects with
+    /// `CERT_PIN_MISMATCH`), `range` (`{ start, end }`, `end` inclusive and
+    /// optional) and `ifRange` (an ETag or date the range depends on).
+    #[wasm_bindgen(js_name = fetchWithOptions)]
+    pub fn fetch_with_options(&self, url: String, init: JsValue) -> js_sys::Promise {
+        console_log!(format!("Starting request to: {}", url));
+
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let request = request_from_init(&url, &init)?;
+            match client.send(request).await {
+                Ok(response) => {
+                    console_log!("Request completed successfully");
+                    Ok(JsValue::from(JsHttpResponse::from(response)))
+                }
+                Err(e) => {
+                    console_error!(format!("Request failed: {}", e));
+                    Err(tor_error_to_js(e))
+                }
+            }
+        })
+    }
+
+    /// Download a resource, resuming on a new circuit from the last byte
+    /// received when the transfer is cut off
+    ///
+    /// `init` takes the `fetchWithOptions` fields plus `maxResumes` (default
+    /// 3). Resumptions use `Range` with `If-Range`, so a resource that
+    /// changed meanwhile is fetched whole again.
+    pub fn download(&self, url: String, init: JsValue) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let request = request_from_init(&url, &init)?;
+            let max_resumes = if init.is_undefined() || init.is_null() {
+                None
+            } else {
+                js_sys::Reflect::get(&init, &JsValue::from_str("maxResumes"))?.as_f64()
+            };
+            let max_resumes = max_resumes.map_or(3, |n| n.max(0.0) as u32);
+            match client.download(request, max_resumes).await {
+                Ok(response) => Ok(JsValue::from(JsHttpResponse::from(response))),
+                Err(e) => {
+                    console_error!(format!("Download of {} failed: {}", url, e));
+                    Err(tor_error_to_js(e))
+                }
+            }
+        })
+    }
+
     /// Make a one-time fetch request (static method)
     #[wasm_bindgen(js_name = fetchOneTime)]
     pub fn fetch_one_time(
@@ -448,12 +1739,7 @@ impl TorClient {
                 Ok(response) => {
                     console_log!("One-time fetch request completed successfully");
 
-                    let js_response = JsHttpResponse {
-                        status: response.status,
-                        headers: headers_to_js(&response.headers),
-                        body: response.body,
-                        url: response.url.to_string(),
-                    };
+                    let js_response = JsHttpResponse::from(response);
 
                     Ok(JsValue::from(js_response))
                 }
@@ -546,12 +1832,41 @@ impl TorClient {
                 failed_circuits: status.failed_circuits as u32,
                 has_ready_circuits: status.has_ready_circuits(),
                 is_healthy: status.is_healthy(),
+                entry_mode: status.entry_mode.map(|mode| mode.as_str().to_string()),
+                conflux_circuits: status.conflux_circuits as u32,
+                circuit_metrics: serde_wasm_bindgen::to_value(&status.circuit_metrics)
+                    .unwrap_or(JsValue::NULL),
+                build_failures: serde_wasm_bindgen::to_value(&status.build_failures)
+                    .unwrap_or(JsValue::NULL),
             };
 
             Ok(JsValue::from(js_status))
         })
     }
 
+    /// Every circuit and the totals
+    ///
+    /// Resolves to `{ summary, totalCircuits, readyCircuits,
+    /// creatingCircuits, failedCircuits, entryMode, circuits: [{ id,
+    /// purpose, state, hops: [{ role, nickname, address, fingerprint,
+    /// country }], ageMs, idleMs, bytesRead, bytesWritten, openStreams }] }`.
+    #[wasm_bindgen(js_name = getCircuitStatusReport)]
+    pub fn get_circuit_status_report(&self) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let report = client.circuit_status_report().await;
+            Ok(serde_wasm_bindgen::to_value(&report)?)
+        })
+    }
+
     /// Get circuit status string
     #[wasm_bindgen(js_name = getCircuitStatusString)]
     pub fn get_circuit_status_string(&self) -> js_sys::Promise {
@@ -588,6 +1903,7 @@ impl TorClient {
                             nickname: r.nickname,
                             address: r.address,
                             fingerprint: r.fingerprint,
+                            country: r.country,
                         })
                         .collect();
                     Ok(serde_wasm_bindgen::to_value(&js_relays).unwrap_or(JsValue::NULL))
@@ -597,216 +1913,1341 @@ impl TorClient {
         })
     }
 
-    /// Close the Tor client
-    #[wasm_bindgen(js_name = close)]
-    pub fn close(&mut self) -> js_sys::Promise {
-        console_log!("Closing TorClient");
+    /// Find relays in the loaded consensus, highest bandwidth first
+    ///
+    /// `query` is an object with optional `fingerprint`, `nickname` (substring),
+    /// `flags` (all required), `minBandwidth`, `country` and `limit` fields.
+    #[wasm_bindgen(js_name = queryRelays)]
+    pub fn query_relays(&self, query: JsValue) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+        let query: webtor::relay::RelayQuery = if query.is_undefined() || query.is_null() {
+            Default::default()
+        } else {
+            match serde_wasm_bindgen::from_value(query) {
+                Ok(query) => query,
+                Err(e) => {
+                    let err = TorError::configuration(format!("Invalid relay query: {}", e));
+                    return future_to_promise(async move { Err(tor_error_to_js(err)) });
+                }
+            }
+        };
+
+        future_to_promise(async move {
+            let relays: Vec<JsRelay> = client
+                .query_relays(&query)
+                .await
+                .into_iter()
+                .map(JsRelay::from)
+                .collect();
+            Ok(serde_wasm_bindgen::to_value(&relays).unwrap_or(JsValue::NULL))
+        })
+    }
+
+    /// Install a microdescriptor consensus and its microdescriptors (as text)
+    /// mirrored by the application, with the directory authorities' key
+    /// certificates that sign the consensus; resolves to `{ consensusRelays,
+    /// microdescriptors, relaysLoaded, missing }`, or rejects without changing
+    /// anything if the consensus is stale or not signed by most authorities,
+    /// or a microdescriptor doesn't match it
+    #[wasm_bindgen(js_name = injectDirectory)]
+    pub fn inject_directory(
+        &self,
+        consensus: String,
+        microdescriptors: String,
+        authority_certs: String,
+    ) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            match client
+                .inject_directory(&consensus, &microdescriptors, &authority_certs)
+                .await
+            {
+                Ok(injection) => {
+                    Ok(serde_wasm_bindgen::to_value(&injection).unwrap_or(JsValue::NULL))
+                }
+                Err(e) => Err(tor_error_to_js(e)),
+            }
+        })
+    }
+
+    /// Number of relays in the loaded consensus carrying each flag, e.g. `{ Exit: 1500, ... }`
+    #[wasm_bindgen(js_name = getRelayCountsByFlag)]
+    pub fn get_relay_counts_by_flag(&self) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move { Ok(JsValue::NULL) });
+            }
+        };
+
+        future_to_promise(async move {
+            let counts = client.relay_counts_by_flag().await;
+            Ok(serde_wasm_bindgen::to_value(&counts).unwrap_or(JsValue::NULL))
+        })
+    }
+
+    /// Get the country code of the current exit relay (null if unknown)
+    #[wasm_bindgen(js_name = getExitCountry)]
+    pub fn get_exit_country(&self) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move { Ok(JsValue::NULL) });
+            }
+        };
+
+        future_to_promise(async move {
+            match client.get_exit_country().await {
+                Some(country) => Ok(JsValue::from_str(&country)),
+                None => Ok(JsValue::NULL),
+            }
+        })
+    }
+
+    /// Retire all circuits so later requests use fresh ones (like Tor's NEWNYM)
+    ///
+    /// Resolves to the number of circuits retired. With `avoidPreviousExits`,
+    /// new circuits avoid the exits used so far where possible.
+    #[wasm_bindgen(js_name = newIdentity)]
+    pub fn new_identity(&self, avoid_previous_exits: Option<bool>) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let retired = client
+                .new_identity(avoid_previous_exits.unwrap_or(false))
+                .await;
+            Ok(JsValue::from(retired as u32))
+        })
+    }
+
+    /// Health of each configured bridge (null before the client is created)
+    #[wasm_bindgen(js_name = getBridgeStatus)]
+    pub fn get_bridge_status(&self) -> JsValue {
+        match &self.inner {
+            Some(client) => {
+                serde_wasm_bindgen::to_value(&client.bridge_status()).unwrap_or(JsValue::NULL)
+            }
+            None => JsValue::NULL,
+        }
+    }
+
+    /// How far the latest bootstrap attempt got (null before the client is created)
+    #[wasm_bindgen(js_name = getBootstrapReport)]
+    pub fn get_bootstrap_report(&self) -> JsValue {
+        match &self.inner {
+            Some(client) => {
+                serde_wasm_bindgen::to_value(&client.bootstrap_report()).unwrap_or(JsValue::NULL)
+            }
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Bootstrap phase and percentage as `{ phase, progress, summary }`
+    /// (null before the client is created)
+    #[wasm_bindgen(js_name = getBootstrapStatus)]
+    pub fn get_bootstrap_status(&self) -> JsValue {
+        match &self.inner {
+            Some(client) => {
+                serde_wasm_bindgen::to_value(&client.bootstrap_status()).unwrap_or(JsValue::NULL)
+            }
+            None => JsValue::NULL,
+        }
+    }
+
+    /// Whether the client closed its connection after being idle; the next
+    /// request reconnects
+    #[wasm_bindgen(js_name = isDormant)]
+    pub fn is_dormant(&self) -> bool {
+        self.inner
+            .as_ref()
+            .is_some_and(|client| client.is_dormant())
+    }
+
+    /// Put the client in the background, as the visibility policy does when
+    /// the page is hidden; with `dormant` the connection is also closed
+    /// unless streams are open
+    pub fn suspend(&self, dormant: bool) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            client.suspend(dormant).await;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Bring the client back from `suspend()`, reconnecting and rebuilding
+    /// circuits that didn't survive
+    pub fn resume(&self) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            client.resume().await.map_err(tor_error_to_js)?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// What the client has learned (guards, bridge health, build times and
+    /// a still-valid consensus) as a JSON string for `importState()` after
+    /// a reload; it can be a few megabytes, so prefer IndexedDB to
+    /// localStorage
+    #[wasm_bindgen(js_name = exportState)]
+    pub fn export_state(&self) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let json = async { client.export_state().await?.to_json() }
+                .await
+                .map_err(tor_error_to_js)?;
+            Ok(JsValue::from_str(&json))
+        })
+    }
+
+    /// Take over state from `exportState()`; call before the first request
+    /// to skip the consensus download
+    #[wasm_bindgen(js_name = importState)]
+    pub fn import_state(&self, state: String) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let result = async {
+                let state = webtor::ClientState::from_json(&state)?;
+                client.import_state(&state).await
+            };
+            result.await.map_err(tor_error_to_js)?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Current circuit build timeout in milliseconds (null before the client is created)
+    #[wasm_bindgen(js_name = getCircuitBuildTimeout)]
+    pub fn get_circuit_build_timeout(&self) -> Option<u32> {
+        self.inner
+            .as_ref()
+            .map(|client| client.circuit_build_timeout().as_millis() as u32)
+    }
+
+    /// Call `callback` with every event of `category` from now on, like a
+    /// DOM `addEventListener`
+    ///
+    /// `category` is `bootstrap`, `circuit`, `stream`, `onion`, `channel`,
+    /// `consensus`, `error` or `all`. Events are the objects the `on*Event`
+    /// callbacks get, with their `category` added; `bootstrap` events are
+    /// `{ phase, progress, summary }`, `consensus` events `{ relays, source }`
+    /// and `error` events `{ operation, code, message }` for failed requests
+    /// and streams. Adding the same callback twice has no effect.
+    #[wasm_bindgen(js_name = addEventListener)]
+    pub fn add_event_listener(
+        &self,
+        category: String,
+        callback: js_sys::Function,
+    ) -> Result<(), JsValue> {
+        use futures::StreamExt;
+
+        let client = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| JsTorError::not_initialized().into_js_value())?;
+        if category != "all" && !webtor::TorEvent::CATEGORIES.contains(&category.as_str()) {
+            return Err(tor_error_to_js(TorError::configuration(format!(
+                "Unknown event category: {}",
+                category
+            ))));
+        }
+        let mut listeners = self.listeners.borrow_mut();
+        if listeners
+            .iter()
+            .any(|l| l.category == category && l.callback == callback)
+        {
+            return Ok(());
+        }
+
+        let mut events = client.subscribe();
+        let cancel = CancellationToken::new();
+        listeners.push(EventListener {
+            category: category.clone(),
+            callback: callback.clone(),
+            cancel: cancel.clone(),
+        });
+        wasm_bindgen_futures::spawn_local(async move {
+            loop {
+                let next = with_cancellation(&cancel, async { Ok(events.next().await) });
+                let Ok(Some(event)) = next.await else {
+                    break;
+                };
+                if category != "all" && event.category() != category {
+                    continue;
+                }
+                let value = serde_wasm_bindgen::to_value(&event).unwrap_or(JsValue::NULL);
+                if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                    console_warn!(format!("Event listener threw: {:?}", e));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Stop calling `callback` for `category`, as added by `addEventListener`
+    #[wasm_bindgen(js_name = removeEventListener)]
+    pub fn remove_event_listener(&self, category: String, callback: js_sys::Function) {
+        self.listeners.borrow_mut().retain(|l| {
+            let matches = l.category == category && l.callback == callback;
+            if matches {
+                l.cancel.cancel();
+            }
+            !matches
+        });
+    }
+
+    /// Call `callback` with each step of connecting to an onion service
+    ///
+    /// Events are objects with an `address` and a `type` of
+    /// `DescriptorFetched` (`cached`, `introPoints`), `RendezvousEstablished`
+    /// (`circuitId`, `rendezvousPoint`, `attempt`), `IntroductionSent`
+    /// (`introPoint`, `attempt`), `Connected` (`circuitId`) or `Failed`
+    /// (`error`). Delivery stops when the client closes.
+    #[wasm_bindgen(js_name = onOnionEvent)]
+    pub fn on_onion_event(&self, callback: js_sys::Function) -> Result<(), JsValue> {
+        use futures::StreamExt;
+
+        let client = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| JsTorError::not_initialized().into_js_value())?;
+        let mut events = client.onion_events();
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Some(event) = events.next().await {
+                let value = serde_wasm_bindgen::to_value(&event).unwrap_or(JsValue::NULL);
+                if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                    console_warn!(format!("Onion event callback threw: {:?}", e));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Call `callback` when the bridge drops the channel and at each step of
+    /// reconnecting
+    ///
+    /// Events are objects with a `type` of `Lost` (`transport`, `endpoint`,
+    /// `circuitsClosed`), `Reconnecting` (`attempt`, `delayMs`),
+    /// `ReconnectFailed` (`attempt`, `error`), `Reconnected` (`transport`,
+    /// `endpoint`, `attempts`, `circuits`) or `GaveUp` (`attempts`).
+    /// Delivery stops when the client closes.
+    #[wasm_bindgen(js_name = onChannelEvent)]
+    pub fn on_channel_event(&self, callback: js_sys::Function) -> Result<(), JsValue> {
+        use futures::StreamExt;
+
+        let client = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| JsTorError::not_initialized().into_js_value())?;
+        let mut events = client.channel_events();
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Some(event) = events.next().await {
+                let value = serde_wasm_bindgen::to_value(&event).unwrap_or(JsValue::NULL);
+                if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                    console_warn!(format!("Channel event callback threw: {:?}", e));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Call `callback` with each circuit and stream lifecycle event
+    ///
+    /// Events are objects with a `type` of `CircuitBuilt`, `CircuitExtended`,
+    /// `CircuitClosed`, `StreamAttached` or `StreamClosed`, plus a `circuitId`
+    /// and the relay or stream details; a request's streams carry its
+    /// `requestId`. Delivery stops when the client closes.
+    #[wasm_bindgen(js_name = onCircuitEvent)]
+    pub fn on_circuit_event(&self, callback: js_sys::Function) -> Result<(), JsValue> {
+        use futures::StreamExt;
+
+        let client = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| JsTorError::not_initialized().into_js_value())?;
+        let mut events = client.circuit_events();
+        wasm_bindgen_futures::spawn_local(async move {
+            while let Some(event) = events.next().await {
+                let value = serde_wasm_bindgen::to_value(&event).unwrap_or(JsValue::NULL);
+                if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                    console_warn!(format!("Circuit event callback threw: {:?}", e));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Bytes read and written through Tor, in total and per current circuit
+    ///
+    /// Resolves to `{ bytesRead, bytesWritten, circuits: [{ circuitId,
+    /// bytesRead, bytesWritten }] }`.
+    #[wasm_bindgen(js_name = getTrafficStats)]
+    pub fn get_traffic_stats(&self) -> js_sys::Promise {
+        let client = match &self.inner {
+            Some(client) => client.clone(),
+            None => {
+                return future_to_promise(async move {
+                    Err(JsTorError::not_initialized().into_js_value())
+                });
+            }
+        };
+
+        future_to_promise(async move {
+            let stats = client.traffic_stats().await;
+            Ok(serde_wasm_bindgen::to_value(&stats).unwrap_or(JsValue::NULL))
+        })
+    }
+
+    /// Traffic and round-trip time to the bridge for each transport used
+    ///
+    /// Returns `[{ transport, connections, bytesSent, bytesReceived,
+    /// cellsSent, cellsReceived, rttSamples, srttMs, minRttMs, lastRttMs }]`;
+    /// a high `srttMs` means the bridge connection, not the circuit, is slow.
+    #[wasm_bindgen(js_name = getTransportStats)]
+    pub fn get_transport_stats(&self) -> Result<JsValue, JsValue> {
+        let client = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| JsTorError::not_initialized().into_js_value())?;
+        Ok(serde_wasm_bindgen::to_value(&client.transport_stats()).unwrap_or(JsValue::NULL))
+    }
+
+    /// Forget every cookie stored by the client (see `withCookies`)
+    #[wasm_bindgen(js_name = clearCookies)]
+    pub fn clear_cookies(&self) -> Result<(), JsValue> {
+        let client = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| JsTorError::not_initialized().into_js_value())?;
+        client.clear_cookies();
+        Ok(())
+    }
+
+    /// Call `callback` with the traffic stats every `interval_ms` milliseconds
+    /// until the client closes
+    #[wasm_bindgen(js_name = onTrafficStats)]
+    pub fn on_traffic_stats(
+        &self,
+        interval_ms: u32,
+        callback: js_sys::Function,
+    ) -> Result<(), JsValue> {
+        use futures::StreamExt;
+
+        let client = self
+            .inner
+            .as_ref()
+            .ok_or_else(|| JsTorError::not_initialized().into_js_value())?;
+        let updates = client.traffic_updates(Duration::from_millis(u64::from(interval_ms.max(1))));
+        wasm_bindgen_futures::spawn_local(async move {
+            let mut updates = std::pin::pin!(updates);
+            while let Some(stats) = updates.next().await {
+                let value = serde_wasm_bindgen::to_value(&stats).unwrap_or(JsValue::NULL);
+                if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                    console_warn!(format!("Traffic stats callback threw: {:?}", e));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Close the Tor client
+    #[wasm_bindgen(js_name = close)]
+    pub fn close(&mut self) -> js_sys::Promise {
+        console_log!("Closing TorClient");
+
+        if let Some(client) = self.inner.take() {
+            future_to_promise(async move {
+                // Create a copy of the client for the async block
+                let client_copy = (*client).clone();
+                client_copy.close().await;
+                console_log!("TorClient closed successfully");
+                Ok(JsValue::UNDEFINED)
+            })
+        } else {
+            future_to_promise(async move {
+                console_warn!("TorClient was already closed");
+                Ok(JsValue::UNDEFINED)
+            })
+        }
+    }
+
+    /// Abort all in-flight operations.
+    ///
+    /// This cancels long-running operations like circuit creation and HTTP requests.
+    /// Operations will reject with a "CANCELLED" error code.
+    /// Unlike `close()`, this does not clean up resources - the client can still be used.
+    #[wasm_bindgen(js_name = abort)]
+    pub fn abort(&self) {
+        console_log!("Aborting TorClient operations");
+
+        if let Some(client) = &self.inner {
+            client.abort();
+            console_log!("TorClient abort signal sent");
+        } else {
+            console_warn!("TorClient is not initialized");
+        }
+    }
+
+    /// Check if the client has been aborted.
+    #[wasm_bindgen(js_name = isAborted)]
+    pub fn is_aborted(&self) -> bool {
+        if let Some(client) = &self.inner {
+            client.is_aborted()
+        } else {
+            true
+        }
+    }
+
+    // --- Rust-friendly methods (not exposed to JS, but usable by other Rust crates) ---
+
+    pub async fn create(options: TorClientOptions) -> Result<Self, String> {
+        match NativeTorClient::new(options.inner).await {
+            Ok(client) => Ok(TorClient {
+                inner: Some(Arc::new(client)),
+                listeners: Rc::default(),
+            }),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub async fn fetch_rust(&self, url: &str) -> Result<JsHttpResponse, String> {
+        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
+        match client.fetch(url).await {
+            Ok(response) => Ok(JsHttpResponse::from(response)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub async fn post_rust(&self, url: &str, body: Vec<u8>) -> Result<JsHttpResponse, String> {
+        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
+        match client.post(url, body).await {
+            Ok(response) => Ok(JsHttpResponse::from(response)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub async fn wait_for_circuit_rust(&self) -> Result<(), String> {
+        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
+        client.wait_for_circuit().await.map_err(|e| e.to_string())
+    }
+
+    pub async fn close_rust(&mut self) {
+        if let Some(client) = self.inner.take() {
+            // We need to clone the Arc because close takes self/&self but we are taking ownership of the Arc from Option
+            // Actually client.close() takes &self.
+            // But we removed it from Option, so we own the Arc.
+            // NativeTorClient::close is async.
+            client.close().await;
+        }
+    }
+
+    pub async fn get_circuit_status_string_rust(&self) -> Result<String, String> {
+        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
+        Ok(client.get_circuit_status_string().await)
+    }
+
+    pub async fn fetch_one_time_rust(
+        snowflake_url: &str,
+        url: &str,
+        connection_timeout: Option<u64>,
+        circuit_timeout: Option<u64>,
+    ) -> Result<JsHttpResponse, String> {
+        match NativeTorClient::fetch_one_time(
+            snowflake_url,
+            url,
+            connection_timeout,
+            circuit_timeout,
+        )
+        .await
+        {
+            Ok(response) => Ok(JsHttpResponse::from(response)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub async fn update_circuit_rust(&self, deadline_ms: u64) -> Result<(), String> {
+        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
+        client
+            .update_circuit(Duration::from_millis(deadline_ms))
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Simple circuit relay info for internal use (not WASM-bound)
+pub struct CircuitRelayInfoSimple {
+    pub role: String,
+    pub nickname: String,
+    pub address: String,
+    pub fingerprint: String,
+    pub country: Option<String>,
+}
+
+// Non-wasm_bindgen impl block for methods that return non-WASM types
+impl TorClient {
+    pub async fn get_circuit_relays_rust(&self) -> Option<Vec<CircuitRelayInfoSimple>> {
+        let client = self.inner.as_ref()?;
+        client.get_circuit_relays().await.map(|relays| {
+            relays
+                .into_iter()
+                .map(|r| CircuitRelayInfoSimple {
+                    role: r.role,
+                    nickname: r.nickname,
+                    address: r.address,
+                    fingerprint: r.fingerprint,
+                    country: r.country,
+                })
+                .collect()
+        })
+    }
+}
+
+/// JavaScript-friendly HTTP response
+#[wasm_bindgen]
+pub struct JsHttpResponse {
+    status: u16,
+    status_text: String,
+    http_version: String,
+    headers: JsValue,
+    /// Every header line as (lowercase name, value), in order
+    header_list: Vec<(String, String)>,
+    trailers: JsValue,
+    body: Vec<u8>,
+    url: String,
+    request_id: String,
+    retries: u32,
+}
+
+impl From<webtor::HttpResponse> for JsHttpResponse {
+    fn from(response: webtor::HttpResponse) -> Self {
+        let header_list = response
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let trailers = response
+            .trailers
+            .keys()
+            .filter_map(|name| {
+                Some((
+                    name.to_string(),
+                    response.trailer(name.as_str())?.to_string(),
+                ))
+            })
+            .collect();
+        Self {
+            status: response.status,
+            status_text: response.status_text.clone(),
+            http_version: format!("{:?}", response.version),
+            headers: headers_to_js(&response.headers_map()),
+            header_list,
+            trailers: headers_to_js(&trailers),
+            body: response.body,
+            url: response.url.to_string(),
+            request_id: response.request_id,
+            retries: response.retries,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl JsHttpResponse {
+    #[wasm_bindgen(getter)]
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Reason phrase from the status line, e.g. `Not Found`
+    #[wasm_bindgen(getter, js_name = statusText)]
+    pub fn status_text(&self) -> String {
+        self.status_text.clone()
+    }
+
+    /// `HTTP/1.1` or `HTTP/1.0`
+    #[wasm_bindgen(getter, js_name = httpVersion)]
+    pub fn http_version(&self) -> String {
+        self.http_version.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn ok(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Headers as an object with lowercase names; repeated headers are
+    /// joined with `, `
+    #[wasm_bindgen(getter)]
+    pub fn headers(&self) -> JsValue {
+        self.headers.clone()
+    }
+
+    /// Value of header `name` in any case, repeated values joined with `, `
+    #[wasm_bindgen(js_name = getHeader)]
+    pub fn get_header(&self, name: &str) -> Option<String> {
+        let values = self.get_all_headers(name);
+        (!values.is_empty()).then(|| values.join(", "))
+    }
+
+    /// Every value of header `name`, e.g. each `Set-Cookie`
+    #[wasm_bindgen(js_name = getAllHeaders)]
+    pub fn get_all_headers(&self, name: &str) -> Vec<String> {
+        self.header_list
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    /// Trailer fields sent after a chunked body, with lowercase names
+    #[wasm_bindgen(getter)]
+    pub fn trailers(&self) -> JsValue {
+        self.trailers.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn body(&self) -> Vec<u8> {
+        self.body.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// ID of the request, matching `requestId` on errors and in logs
+    #[wasm_bindgen(getter, js_name = requestId)]
+    pub fn request_id(&self) -> String {
+        self.request_id.clone()
+    }
+
+    /// Times the request was retried on another circuit before succeeding
+    #[wasm_bindgen(getter)]
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    #[wasm_bindgen(js_name = text)]
+    pub fn text(&self) -> Result<String, JsValue> {
+        String::from_utf8(self.body.clone())
+            .map_err(|e| JsValue::from_str(&format!("Invalid UTF-8: {}", e)))
+    }
+
+    #[wasm_bindgen(js_name = json)]
+    pub fn json(&self) -> Result<JsValue, JsValue> {
+        let text = self.text()?;
+        serde_json::from_str::<serde_json::Value>(&text)
+            .map_err(|e| JsValue::from_str(&format!("Invalid JSON: {}", e)))
+            .and_then(|v| {
+                serde_wasm_bindgen::to_value(&v)
+                    .map_err(|e| JsValue::from_str(&format!("JSON serialization failed: {}", e)))
+            })
+    }
+}
+
+/// Bytes a `TorSocket` read returns when the caller doesn't ask for a size
+const DEFAULT_SOCKET_READ: u32 = 16 * 1024;
+
+type SocketHalf<T> = Rc<futures::lock::Mutex<Option<T>>>;
+
+/// A plain or TLS stream behind a `TorSocket`
+trait SocketIo: futures::io::AsyncRead + futures::io::AsyncWrite + Unpin {}
+
+impl<T: futures::io::AsyncRead + futures::io::AsyncWrite + Unpin> SocketIo for T {}
+
+/// A raw TCP connection through Tor, from `TorClient.connect` or
+/// `TorClient.connectTls`
+///
+/// Reads and writes may be in flight at the same time. `read` resolves to
+/// an empty array once the other side has closed the connection.
+#[wasm_bindgen(js_name = TorSocket)]
+pub struct JsTorSocket {
+    reader: SocketHalf<futures::io::ReadHalf<Box<dyn SocketIo>>>,
+    writer: SocketHalf<futures::io::WriteHalf<Box<dyn SocketIo>>>,
+    /// Cancelled by `close` to end a read that is waiting for data
+    closing: CancellationToken,
+    alpn_protocol: Option<String>,
+}
+
+impl JsTorSocket {
+    fn new(stream: impl SocketIo + 'static, alpn_protocol: Option<String>) -> Self {
+        use futures::io::AsyncReadExt;
+        let stream: Box<dyn SocketIo> = Box::new(stream);
+        let (reader, writer) = stream.split();
+        Self {
+            reader: Rc::new(futures::lock::Mutex::new(Some(reader))),
+            writer: Rc::new(futures::lock::Mutex::new(Some(writer))),
+            closing: CancellationToken::new(),
+            alpn_protocol,
+        }
+    }
+
+    fn closed() -> JsValue {
+        JsTorError::from_str("SOCKET_CLOSED", "network", "Socket is closed", false).into_js_value()
+    }
+}
+
+#[wasm_bindgen(js_class = TorSocket)]
+impl JsTorSocket {
+    /// The protocol the server selected by ALPN, for `connectTls` sockets
+    #[wasm_bindgen(getter = alpnProtocol)]
+    pub fn alpn_protocol(&self) -> Option<String> {
+        self.alpn_protocol.clone()
+    }
+
+    /// Read up to `maxBytes` (default 16 KiB) as soon as any arrive
+    pub fn read(&self, max_bytes: Option<u32>) -> js_sys::Promise {
+        use futures::io::AsyncReadExt;
+        let reader = self.reader.clone();
+        let closing = self.closing.clone();
+        let max_bytes = max_bytes.unwrap_or(DEFAULT_SOCKET_READ).max(1) as usize;
+        future_to_promise(async move {
+            let mut reader = reader.lock().await;
+            let reader = reader.as_mut().ok_or_else(Self::closed)?;
+            let mut buf = vec![0u8; max_bytes];
+            let n = with_cancellation(&closing, async {
+                reader.read(&mut buf).await.map_err(TorError::from)
+            })
+            .await
+            .map_err(|e| match e {
+                TorError::Cancelled => Self::closed(),
+                e => tor_error_to_js(e),
+            })?;
+            Ok(js_sys::Uint8Array::from(&buf[..n]).into())
+        })
+    }
+
+    /// Write all of `data`; resolves once it is handed to the circuit
+    pub fn write(&self, data: Vec<u8>) -> js_sys::Promise {
+        use futures::io::AsyncWriteExt;
+        let writer = self.writer.clone();
+        future_to_promise(async move {
+            let mut writer = writer.lock().await;
+            let writer = writer.as_mut().ok_or_else(Self::closed)?;
+            writer
+                .write_all(&data)
+                .await
+                .map_err(|e| tor_error_to_js(e.into()))?;
+            writer
+                .flush()
+                .await
+                .map_err(|e| tor_error_to_js(e.into()))?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Close the connection; a pending read and later calls reject with
+    /// `SOCKET_CLOSED`
+    pub fn close(&self) -> js_sys::Promise {
+        use futures::io::AsyncWriteExt;
+        let reader = self.reader.clone();
+        let writer = self.writer.clone();
+        self.closing.cancel();
+        future_to_promise(async move {
+            let writer = writer.lock().await.take();
+            if let Some(mut writer) = writer {
+                // Tor has no half-close; this flushes and sends END
+                let _ = writer.close().await;
+            }
+            reader.lock().await.take();
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+}
+
+/// An onion service hosted by this tab, from `TorClient.launchOnionService`
+///
+/// `accept` resolves to `{ port, socket }` for the next client stream,
+/// with `socket` a `TorSocket`, or to `null` once the service is closed.
+#[wasm_bindgen(js_name = TorOnionService)]
+pub struct JsOnionService {
+    address: String,
+    inner: Rc<futures::lock::Mutex<Option<webtor::OnionService>>>,
+    /// Cancelled by `close` to end an accept that is waiting for a client
+    closing: CancellationToken,
+}
+
+#[wasm_bindgen(js_class = TorOnionService)]
+impl JsOnionService {
+    /// The service's `.onion` address
+    #[wasm_bindgen(getter)]
+    pub fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    /// Wait for a client to open a stream, and accept it
+    pub fn accept(&self) -> js_sys::Promise {
+        let inner = self.inner.clone();
+        let closing = self.closing.clone();
+        future_to_promise(async move {
+            let mut service = inner.lock().await;
+            let Some(service) = service.as_mut() else {
+                return Ok(JsValue::NULL);
+            };
+            let request = with_cancellation(&closing, async { Ok(service.next_request().await) })
+                .await
+                .unwrap_or(None);
+            let Some(request) = request else {
+                return Ok(JsValue::NULL);
+            };
+            let port = request.port();
+            let stream = request.accept().await.map_err(tor_error_to_js)?;
+            let object = js_sys::Object::new();
+            js_sys::Reflect::set(&object, &"port".into(), &port.into())?;
+            js_sys::Reflect::set(
+                &object,
+                &"socket".into(),
+                &JsTorSocket::new(stream, None).into(),
+            )?;
+            Ok(object.into())
+        })
+    }
+
+    /// Take the service down; a pending `accept` resolves to `null`
+    pub fn close(&self) -> js_sys::Promise {
+        let inner = self.inner.clone();
+        self.closing.cancel();
+        future_to_promise(async move {
+            // Dropping the service shuts it down
+            inner.lock().await.take();
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+}
+
+/// A validated v3 `.onion` address
+///
+/// `OnionAddress.parse(host)` accepts any case and subdomains, and throws
+/// an `INVALID_HOSTNAME` error for a malformed address; `toString()` gives
+/// the canonical `<id>.onion` form.
+#[wasm_bindgen(js_name = OnionAddress)]
+pub struct JsOnionAddress {
+    inner: webtor::OnionAddress,
+}
+
+#[wasm_bindgen(js_class = OnionAddress)]
+impl JsOnionAddress {
+    pub fn parse(host: &str) -> Result<JsOnionAddress, JsValue> {
+        host.parse()
+            .map(|inner| JsOnionAddress { inner })
+            .map_err(tor_error_to_js)
+    }
+
+    /// Whether `host` is a valid v3 onion address
+    #[wasm_bindgen(js_name = isValid)]
+    pub fn is_valid(host: &str) -> bool {
+        host.parse::<webtor::OnionAddress>().is_ok()
+    }
+
+    /// The address of the service with the 32-byte ed25519 identity key
+    /// `publicKey`
+    #[wasm_bindgen(js_name = fromPublicKey)]
+    pub fn from_public_key(public_key: Vec<u8>) -> Result<JsOnionAddress, JsValue> {
+        webtor::OnionAddress::try_from(public_key.as_slice())
+            .map(|inner| JsOnionAddress { inner })
+            .map_err(tor_error_to_js)
+    }
+
+    /// The service's ed25519 identity key
+    #[wasm_bindgen(getter = publicKey)]
+    pub fn public_key(&self) -> Vec<u8> {
+        self.inner.public_key_bytes().to_vec()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn version(&self) -> u8 {
+        self.inner.version()
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_js_string(&self) -> String {
+        self.inner.to_string()
+    }
+}
+
+/// A WebSocket through Tor, from `TorClient.websocket`
+///
+/// `send` and `receive` may be in flight at the same time. `receive`
+/// resolves to `{ type: "text", data: string }`, `{ type: "binary", data:
+/// Uint8Array }` or, once the server closes, `{ type: "close", code,
+/// reason }`.
+#[wasm_bindgen(js_name = TorWebSocket)]
+pub struct JsTorWebSocket {
+    inner: Rc<webtor::TorWebSocket>,
+}
+
+#[wasm_bindgen(js_class = TorWebSocket)]
+impl JsTorWebSocket {
+    /// The subprotocol the server picked, if any
+    #[wasm_bindgen(getter)]
+    pub fn protocol(&self) -> Option<String> {
+        self.inner.protocol().map(str::to_string)
+    }
+
+    /// Send a string as a text message, or a `Uint8Array`/`ArrayBuffer` as
+    /// a binary one
+    pub fn send(&self, data: JsValue) -> js_sys::Promise {
+        let socket = self.inner.clone();
+        future_to_promise(async move {
+            let result = if let Some(text) = data.as_string() {
+                socket.send_text(&text).await
+            } else if let Some(bytes) = data.dyn_ref::<js_sys::Uint8Array>() {
+                socket.send_binary(&bytes.to_vec()).await
+            } else if let Some(buffer) = data.dyn_ref::<js_sys::ArrayBuffer>() {
+                socket
+                    .send_binary(&js_sys::Uint8Array::new(buffer).to_vec())
+                    .await
+            } else {
+                return Err(JsTorError::from_str(
+                    "INVALID_BODY",
+                    "configuration",
+                    "WebSocket data must be a string, Uint8Array or ArrayBuffer",
+                    false,
+                )
+                .into_js_value());
+            };
+            result.map_err(tor_error_to_js)?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Wait for the next message
+    pub fn receive(&self) -> js_sys::Promise {
+        let socket = self.inner.clone();
+        future_to_promise(async move {
+            let message = socket.recv().await.map_err(tor_error_to_js)?;
+            let object = js_sys::Object::new();
+            let set = |name: &str, value: JsValue| {
+                js_sys::Reflect::set(&object, &JsValue::from_str(name), &value)
+            };
+            match message {
+                webtor::WsMessage::Text(text) => {
+                    set("type", "text".into())?;
+                    set("data", text.into())?;
+                }
+                webtor::WsMessage::Binary(data) => {
+                    set("type", "binary".into())?;
+                    set("data", js_sys::Uint8Array::from(&data[..]).into())?;
+                }
+                webtor::WsMessage::Close(frame) => {
+                    set("type", "close".into())?;
+                    if let Some(frame) = frame {
+                        set("code", frame.code.into())?;
+                        set("reason", frame.reason.into())?;
+                    }
+                }
+            }
+            Ok(object.into())
+        })
+    }
+
+    /// Start the closing handshake with `code` (default 1000) and `reason`;
+    /// `receive` then resolves to the server's close
+    pub fn close(&self, code: Option<u16>, reason: Option<String>) -> js_sys::Promise {
+        let socket = self.inner.clone();
+        future_to_promise(async move {
+            socket
+                .close(
+                    code.unwrap_or(webtor::ws::CLOSE_NORMAL),
+                    reason.as_deref().unwrap_or_default(),
+                )
+                .await
+                .map_err(tor_error_to_js)?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+}
+
+/// An `EventSource` through Tor, from `TorClient.eventSource`
+///
+/// Handlers set with `onopen`, `onmessage` and `onerror` or added with
+/// `addEventListener(type, listener)` get `{ type, data, lastEventId,
+/// origin }` objects; events with an `event:` field go to listeners for
+/// that type. Lost connections are re-established with `Last-Event-ID`;
+/// `error` fires when the stream fails for good.
+#[wasm_bindgen(js_name = TorEventSource)]
+pub struct JsTorEventSource {
+    shared: Rc<EventSourceShared>,
+}
 
-        if let Some(client) = self.inner.take() {
-            future_to_promise(async move {
-                // Create a copy of the client for the async block
-                let client_copy = (*client).clone();
-                client_copy.close().await;
-                console_log!("TorClient closed successfully");
-                Ok(JsValue::UNDEFINED)
-            })
-        } else {
-            future_to_promise(async move {
-                console_warn!("TorClient was already closed");
-                Ok(JsValue::UNDEFINED)
-            })
+#[derive(Default)]
+struct EventSourceShared {
+    url: String,
+    /// The Rust event source, once its first connection is open
+    source: RefCell<Option<Rc<webtor::TorEventSource>>>,
+    closed: std::cell::Cell<bool>,
+    onopen: RefCell<Option<js_sys::Function>>,
+    onmessage: RefCell<Option<js_sys::Function>>,
+    onerror: RefCell<Option<js_sys::Function>>,
+    listeners: RefCell<Vec<(String, js_sys::Function)>>,
+}
+
+impl EventSourceShared {
+    /// Call the handler and listeners for `kind` with `event`
+    fn dispatch(&self, kind: &str, event: &js_sys::Object) {
+        let _ = js_sys::Reflect::set(event, &"type".into(), &kind.into());
+        let handler = match kind {
+            "open" => self.onopen.borrow().clone(),
+            "message" => self.onmessage.borrow().clone(),
+            "error" => self.onerror.borrow().clone(),
+            _ => None,
+        };
+        let listeners: Vec<js_sys::Function> = self
+            .listeners
+            .borrow()
+            .iter()
+            .filter(|(name, _)| name == kind)
+            .map(|(_, listener)| listener.clone())
+            .collect();
+        for callback in handler.iter().chain(&listeners) {
+            if let Err(e) = callback.call1(&JsValue::NULL, event) {
+                console_warn!(format!("Event source {} listener threw: {:?}", kind, e));
+            }
         }
     }
 
-    /// Abort all in-flight operations.
-    ///
-    /// This cancels long-running operations like circuit creation and HTTP requests.
-    /// Operations will reject with a "CANCELLED" error code.
-    /// Unlike `close()`, this does not clean up resources - the client can still be used.
-    #[wasm_bindgen(js_name = abort)]
-    pub fn abort(&self) {
-        console_log!("Aborting TorClient operations");
-
-        if let Some(client) = &self.inner {
-            client.abort();
-            console_log!("TorClient abort signal sent");
-        } else {
-            console_warn!("TorClient is not initialized");
+    fn dispatch_message(&self, event: webtor::SseEvent) {
+        if self.closed.get() {
+            return;
         }
+        let object = js_sys::Object::new();
+        let origin = webtor::Url::parse(&self.url)
+            .map(|url| url.origin().ascii_serialization())
+            .unwrap_or_default();
+        let _ = js_sys::Reflect::set(&object, &"data".into(), &event.data.into());
+        let _ = js_sys::Reflect::set(&object, &"lastEventId".into(), &event.last_event_id.into());
+        let _ = js_sys::Reflect::set(&object, &"origin".into(), &origin.into());
+        self.dispatch(&event.event, &object);
     }
 
-    /// Check if the client has been aborted.
-    #[wasm_bindgen(js_name = isAborted)]
-    pub fn is_aborted(&self) -> bool {
-        if let Some(client) = &self.inner {
-            client.is_aborted()
-        } else {
-            true
-        }
+    fn dispatch_error(&self, error: TorError) {
+        let object = js_sys::Object::new();
+        let _ = js_sys::Reflect::set(&object, &"error".into(), &tor_error_to_js(error));
+        self.dispatch("error", &object);
     }
+}
 
-    // --- Rust-friendly methods (not exposed to JS, but usable by other Rust crates) ---
+#[wasm_bindgen(js_class = TorEventSource)]
+impl JsTorEventSource {
+    #[wasm_bindgen(getter)]
+    pub fn url(&self) -> String {
+        self.shared.url.clone()
+    }
 
-    pub async fn create(options: TorClientOptions) -> Result<Self, String> {
-        match NativeTorClient::new(options.inner).await {
-            Ok(client) => Ok(TorClient {
-                inner: Some(Arc::new(client)),
-            }),
-            Err(e) => Err(e.to_string()),
+    /// 0 (connecting), 1 (open) or 2 (closed)
+    #[wasm_bindgen(getter, js_name = readyState)]
+    pub fn ready_state(&self) -> u8 {
+        if self.shared.closed.get() {
+            return webtor::ReadyState::Closed as u8;
+        }
+        match &*self.shared.source.borrow() {
+            Some(source) => source.ready_state() as u8,
+            None => webtor::ReadyState::Connecting as u8,
         }
     }
 
-    pub async fn fetch_rust(&self, url: &str) -> Result<JsHttpResponse, String> {
-        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
-        match client.fetch(url).await {
-            Ok(response) => Ok(JsHttpResponse {
-                status: response.status,
-                headers: headers_to_js(&response.headers),
-                body: response.body,
-                url: response.url.to_string(),
-            }),
-            Err(e) => Err(e.to_string()),
-        }
+    /// Always false: requests carry no browser credentials
+    #[wasm_bindgen(getter, js_name = withCredentials)]
+    pub fn with_credentials(&self) -> bool {
+        false
     }
 
-    pub async fn post_rust(&self, url: &str, body: Vec<u8>) -> Result<JsHttpResponse, String> {
-        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
-        match client.post(url, body).await {
-            Ok(response) => Ok(JsHttpResponse {
-                status: response.status,
-                headers: headers_to_js(&response.headers),
-                body: response.body,
-                url: response.url.to_string(),
-            }),
-            Err(e) => Err(e.to_string()),
-        }
+    #[wasm_bindgen(getter, js_name = CONNECTING)]
+    pub fn connecting(&self) -> u8 {
+        webtor::ReadyState::Connecting as u8
     }
 
-    pub async fn wait_for_circuit_rust(&self) -> Result<(), String> {
-        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
-        client.wait_for_circuit().await.map_err(|e| e.to_string())
+    #[wasm_bindgen(getter, js_name = OPEN)]
+    pub fn open(&self) -> u8 {
+        webtor::ReadyState::Open as u8
     }
 
-    pub async fn close_rust(&mut self) {
-        if let Some(client) = self.inner.take() {
-            // We need to clone the Arc because close takes self/&self but we are taking ownership of the Arc from Option
-            // Actually client.close() takes &self.
-            // But we removed it from Option, so we own the Arc.
-            // NativeTorClient::close is async.
-            client.close().await;
-        }
+    #[wasm_bindgen(getter, js_name = CLOSED)]
+    pub fn closed(&self) -> u8 {
+        webtor::ReadyState::Closed as u8
     }
 
-    pub async fn get_circuit_status_string_rust(&self) -> Result<String, String> {
-        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
-        Ok(client.get_circuit_status_string().await)
+    #[wasm_bindgen(getter)]
+    pub fn onopen(&self) -> Option<js_sys::Function> {
+        self.shared.onopen.borrow().clone()
     }
 
-    pub async fn fetch_one_time_rust(
-        snowflake_url: &str,
-        url: &str,
-        connection_timeout: Option<u64>,
-        circuit_timeout: Option<u64>,
-    ) -> Result<JsHttpResponse, String> {
-        match NativeTorClient::fetch_one_time(
-            snowflake_url,
-            url,
-            connection_timeout,
-            circuit_timeout,
-        )
-        .await
+    #[wasm_bindgen(setter)]
+    pub fn set_onopen(&self, handler: Option<js_sys::Function>) {
+        self.shared.onopen.replace(handler);
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn onmessage(&self) -> Option<js_sys::Function> {
+        self.shared.onmessage.borrow().clone()
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_onmessage(&self, handler: Option<js_sys::Function>) {
+        self.shared.onmessage.replace(handler);
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn onerror(&self) -> Option<js_sys::Function> {
+        self.shared.onerror.borrow().clone()
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_onerror(&self, handler: Option<js_sys::Function>) {
+        self.shared.onerror.replace(handler);
+    }
+
+    #[wasm_bindgen(js_name = addEventListener)]
+    pub fn add_event_listener(&self, kind: String, listener: js_sys::Function) {
+        let mut listeners = self.shared.listeners.borrow_mut();
+        if !listeners
+            .iter()
+            .any(|(name, existing)| *name == kind && *existing == listener)
         {
-            Ok(response) => Ok(JsHttpResponse {
-                status: response.status,
-                headers: headers_to_js(&response.headers),
-                body: response.body,
-                url: response.url.to_string(),
-            }),
-            Err(e) => Err(e.to_string()),
+            listeners.push((kind, listener));
         }
     }
 
-    pub async fn update_circuit_rust(&self, deadline_ms: u64) -> Result<(), String> {
-        let client = self.inner.as_ref().ok_or("TorClient is not initialized")?;
-        client
-            .update_circuit(Duration::from_millis(deadline_ms))
-            .await
-            .map_err(|e| e.to_string())
+    #[wasm_bindgen(js_name = removeEventListener)]
+    pub fn remove_event_listener(&self, kind: String, listener: js_sys::Function) {
+        self.shared
+            .listeners
+            .borrow_mut()
+            .retain(|(name, existing)| !(*name == kind && *existing == listener));
+    }
+
+    /// Stop the stream; no events are delivered after this
+    pub fn close(&self) {
+        self.shared.closed.set(true);
+        if let Some(source) = &*self.shared.source.borrow() {
+            source.close();
+        }
     }
 }
 
-/// Simple circuit relay info for internal use (not WASM-bound)
-pub struct CircuitRelayInfoSimple {
-    pub role: String,
-    pub nickname: String,
-    pub address: String,
-    pub fingerprint: String,
+/// Network documents shared by the clients of a page
+///
+/// Pass one instance to `withDirectoryCache` on each client's options.
+#[wasm_bindgen(js_name = DirectoryCache)]
+#[derive(Default)]
+pub struct JsDirectoryCache {
+    inner: webtor::directory::DirectoryCache,
 }
 
-// Non-wasm_bindgen impl block for methods that return non-WASM types
-impl TorClient {
-    pub async fn get_circuit_relays_rust(&self) -> Option<Vec<CircuitRelayInfoSimple>> {
-        let client = self.inner.as_ref()?;
-        client.get_circuit_relays().await.map(|relays| {
-            relays
-                .into_iter()
-                .map(|r| CircuitRelayInfoSimple {
-                    role: r.role,
-                    nickname: r.nickname,
-                    address: r.address,
-                    fingerprint: r.fingerprint,
-                })
-                .collect()
-        })
+#[wasm_bindgen(js_class = DirectoryCache)]
+impl JsDirectoryCache {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
     }
-}
 
-/// JavaScript-friendly HTTP response
-#[wasm_bindgen]
-pub struct JsHttpResponse {
-    status: u16,
-    headers: JsValue,
-    body: Vec<u8>,
-    url: String,
-}
+    /// Number of relays cached, 0 before any client loaded a directory
+    #[wasm_bindgen(getter, js_name = relayCount)]
+    pub fn relay_count(&self) -> usize {
+        self.inner.relay_count()
+    }
 
-#[wasm_bindgen]
-impl JsHttpResponse {
-    #[wasm_bindgen(getter)]
-    pub fn status(&self) -> u16 {
-        self.status
+    /// Forget the cached documents
+    pub fn clear(&self) {
+        self.inner.clear();
     }
+}
 
-    #[wasm_bindgen(getter)]
-    pub fn headers(&self) -> JsValue {
-        self.headers.clone()
+/// A `multipart/form-data` body for form uploads
+///
+/// Send it with `fetchWithOptions(url, { method: "POST", body: form.body(),
+/// headers: { "Content-Type": form.contentType } })`.
+#[wasm_bindgen(js_name = MultipartForm)]
+#[derive(Default)]
+pub struct JsMultipartForm {
+    inner: webtor::Multipart,
+}
+
+#[wasm_bindgen(js_class = MultipartForm)]
+impl JsMultipartForm {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    #[wasm_bindgen(getter)]
-    pub fn body(&self) -> Vec<u8> {
-        self.body.clone()
+    /// Add a text field
+    pub fn append(&mut self, name: String, value: String) {
+        self.inner = std::mem::take(&mut self.inner).text(name, value);
     }
 
-    #[wasm_bindgen(getter)]
-    pub fn url(&self) -> String {
-        self.url.clone()
+    /// Add a file part; `contentType` defaults to `application/octet-stream`
+    #[wasm_bindgen(js_name = appendFile)]
+    pub fn append_file(
+        &mut self,
+        name: String,
+        filename: String,
+        data: Vec<u8>,
+        content_type: Option<String>,
+    ) {
+        self.inner =
+            std::mem::take(&mut self.inner).file(name, filename, content_type.as_deref(), data);
     }
 
-    #[wasm_bindgen(js_name = text)]
-    pub fn text(&self) -> Result<String, JsValue> {
-        String::from_utf8(self.body.clone())
-            .map_err(|e| JsValue::from_str(&format!("Invalid UTF-8: {}", e)))
+    /// Value for the request's `Content-Type` header, boundary included
+    #[wasm_bindgen(getter, js_name = contentType)]
+    pub fn content_type(&self) -> String {
+        self.inner.content_type()
     }
 
-    #[wasm_bindgen(js_name = json)]
-    pub fn json(&self) -> Result<JsValue, JsValue> {
-        let text = self.text()?;
-        serde_json::from_str::<serde_json::Value>(&text)
-            .map_err(|e| JsValue::from_str(&format!("Invalid JSON: {}", e)))
-            .and_then(|v| {
-                serde_wasm_bindgen::to_value(&v)
-                    .map_err(|e| JsValue::from_str(&format!("JSON serialization failed: {}", e)))
-            })
+    /// The encoded body
+    pub fn body(&self) -> Vec<u8> {
+        self.inner.to_bytes()
     }
 }
 
@@ -819,6 +3260,10 @@ pub struct JsCircuitStatus {
     failed_circuits: u32,
     has_ready_circuits: bool,
     is_healthy: bool,
+    entry_mode: Option<String>,
+    conflux_circuits: u32,
+    circuit_metrics: JsValue,
+    build_failures: JsValue,
 }
 
 #[wasm_bindgen]
@@ -852,6 +3297,31 @@ impl JsCircuitStatus {
     pub fn is_healthy(&self) -> bool {
         self.is_healthy
     }
+
+    /// `"bridge"` for an unlisted bridge, `"guard"` if the bridge is a listed
+    /// relay, or undefined before the channel is up
+    #[wasm_bindgen(getter)]
+    pub fn entry_mode(&self) -> Option<String> {
+        self.entry_mode.clone()
+    }
+
+    /// Ready circuits running over two linked conflux legs
+    #[wasm_bindgen(getter)]
+    pub fn conflux_circuits(&self) -> u32 {
+        self.conflux_circuits
+    }
+
+    /// Per-circuit `{ circuitId, hopBuildMs, rttMs, streamsOpened, streamsFailed, lastError }`
+    #[wasm_bindgen(getter)]
+    pub fn circuit_metrics(&self) -> JsValue {
+        self.circuit_metrics.clone()
+    }
+
+    /// Failed builds since startup, keyed by the step that failed
+    #[wasm_bindgen(getter)]
+    pub fn build_failures(&self) -> JsValue {
+        self.build_failures.clone()
+    }
 }
 
 /// JavaScript-friendly circuit relay info
@@ -861,53 +3331,34 @@ pub struct JsCircuitRelay {
     pub nickname: String,
     pub address: String,
     pub fingerprint: String,
+    pub country: Option<String>,
 }
 
-/// Custom tracing layer that forwards logs to JavaScript
-struct JsLogLayer;
-
-impl<S> tracing_subscriber::Layer<S> for JsLogLayer
-where
-    S: tracing::Subscriber,
-{
-    fn on_event(
-        &self,
-        event: &tracing::Event<'_>,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
-    ) {
-        let metadata = event.metadata();
-        let level = metadata.level().as_str();
-        let target = metadata.target();
-
-        // Extract the message from the event
-        let mut visitor = MessageVisitor(String::new());
-        event.record(&mut visitor);
-        let message = visitor.0;
-
-        forward_log(level, target, &message);
-    }
+/// JavaScript-friendly relay from the consensus
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsRelay {
+    pub fingerprint: String,
+    pub nickname: String,
+    pub address: String,
+    pub or_port: u16,
+    pub flags: Vec<String>,
+    pub bandwidth: u64,
+    pub country: Option<String>,
 }
 
-struct MessageVisitor(String);
-
-impl tracing::field::Visit for MessageVisitor {
-    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
-        if field.name() == "message" {
-            self.0 = format!("{:?}", value);
-        } else if !self.0.is_empty() {
-            self.0.push_str(&format!(" {}={:?}", field.name(), value));
-        } else {
-            self.0 = format!("{}={:?}", field.name(), value);
-        }
-    }
-
-    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
-        if field.name() == "message" {
-            self.0 = value.to_string();
-        } else if !self.0.is_empty() {
-            self.0.push_str(&format!(" {}={}", field.name(), value));
-        } else {
-            self.0 = format!("{}={}", field.name(), value);
+impl From<webtor::relay::Relay> for JsRelay {
+    fn from(relay: webtor::relay::Relay) -> Self {
+        let mut flags: Vec<String> = relay.flags.into_iter().collect();
+        flags.sort();
+        JsRelay {
+            fingerprint: relay.fingerprint,
+            nickname: relay.nickname,
+            address: relay.address,
+            or_port: relay.or_port,
+            flags,
+            bandwidth: relay.bandwidth,
+            country: relay.country,
         }
     }
 }
@@ -950,7 +3401,7 @@ pub fn init() -> Result<(), JsValue> {
     use tracing_subscriber::util::SubscriberInitExt;
 
     let wasm_layer = tracing_wasm::WASMLayer::new(tracing_wasm::WASMLayerConfig::default());
-    let js_layer = JsLogLayer;
+    let js_layer = LOG_CAPTURE.with(LogCapture::layer);
 
     tracing_subscriber::registry()
         .with(wasm_layer)
@@ -961,12 +3412,124 @@ pub fn init() -> Result<(), JsValue> {
     Ok(())
 }
 
+/// Client for Moat, the Tor Project's bridge distribution API
+///
+/// For users without a bridge line: `bridgeLines(country)` resolves to lines
+/// for `TorClientOptions.fromBridgeLines`. When the settings only offer
+/// private bridges (`settings` entries with `source: "bridgedb"` and no
+/// lines), show the image
+/// from `fetchChallenge()` to the user and pass their answer to
+/// `checkSolution`, which resolves to bridge lines or to `null` if the
+/// answer was wrong.
+#[wasm_bindgen(js_name = MoatClient)]
+pub struct JsMoatClient {
+    inner: webtor::moat::MoatClient,
+}
+
+#[wasm_bindgen(js_class = MoatClient)]
+impl JsMoatClient {
+    /// Client for the Moat API at `url`, or the Tor Project's if omitted
+    /// (the server must allow CORS from the page's origin)
+    #[wasm_bindgen(constructor)]
+    pub fn new(url: Option<String>) -> Self {
+        let inner = match url {
+            Some(url) => webtor::moat::MoatClient::new(&url),
+            None => webtor::moat::MoatClient::default(),
+        };
+        Self { inner }
+    }
+
+    /// Recommended bridges for `country` (a two-letter code, or the
+    /// apparent country if omitted): an array of `{ transport, source,
+    /// bridgeLines }`
+    pub fn settings(&self, country: Option<String>) -> js_sys::Promise {
+        let moat = self.inner.clone();
+        future_to_promise(async move {
+            let settings = moat
+                .settings(country.as_deref(), &moat_transports())
+                .await
+                .map_err(tor_error_to_js)?;
+            Ok(serde_wasm_bindgen::to_value(&settings)?)
+        })
+    }
+
+    /// Tor Browser's built-in bridges: an object mapping transports to lines
+    #[wasm_bindgen(js_name = builtinBridges)]
+    pub fn builtin_bridges(&self) -> js_sys::Promise {
+        let moat = self.inner.clone();
+        future_to_promise(async move {
+            let bridges = moat
+                .builtin_bridges(&moat_transports())
+                .await
+                .map_err(tor_error_to_js)?;
+            // A plain object rather than a `Map`
+            let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+            Ok(serde::Serialize::serialize(&bridges, &serializer)?)
+        })
+    }
+
+    /// Bridge lines usable in this browser, from the settings for `country`
+    /// or else the built-in bridges
+    #[wasm_bindgen(js_name = bridgeLines)]
+    pub fn bridge_lines(&self, country: Option<String>) -> js_sys::Promise {
+        let moat = self.inner.clone();
+        future_to_promise(async move {
+            let lines = moat
+                .bridge_lines(country.as_deref())
+                .await
+                .map_err(tor_error_to_js)?;
+            Ok(serde_wasm_bindgen::to_value(&lines)?)
+        })
+    }
+
+    /// A captcha for private bridges: `{ transport, image, challenge }`,
+    /// with `image` a base64 JPEG
+    #[wasm_bindgen(js_name = fetchChallenge)]
+    pub fn fetch_challenge(&self) -> js_sys::Promise {
+        let moat = self.inner.clone();
+        future_to_promise(async move {
+            let challenge = moat
+                .fetch_challenge(&moat_transports())
+                .await
+                .map_err(tor_error_to_js)?;
+            Ok(serde_wasm_bindgen::to_value(&challenge)?)
+        })
+    }
+
+    /// Answer a challenge from `fetchChallenge`
+    #[wasm_bindgen(js_name = checkSolution)]
+    pub fn check_solution(&self, challenge: JsValue, solution: String) -> js_sys::Promise {
+        let moat = self.inner.clone();
+        future_to_promise(async move {
+            let challenge: webtor::moat::MoatChallenge = serde_wasm_bindgen::from_value(challenge)
+                .map_err(|e| JsValue::from_str(&format!("Invalid challenge: {}", e)))?;
+            let lines = moat
+                .check_solution(&challenge, &solution)
+                .await
+                .map_err(tor_error_to_js)?;
+            Ok(serde_wasm_bindgen::to_value(&lines)?)
+        })
+    }
+}
+
+/// Moat names of the transports this browser can use
+fn moat_transports() -> Vec<String> {
+    webtor::moat::supported_transports(&webtor::capabilities::Capabilities::detect())
+}
+
 /// Test function for WASM
 #[wasm_bindgen]
 pub fn test_wasm() -> String {
     "Webtor WASM is working!".to_string()
 }
 
+/// Report which browser features are available and what is disabled without them
+#[wasm_bindgen(js_name = getCapabilities)]
+pub fn get_capabilities() -> JsValue {
+    let report = webtor::capabilities::Capabilities::detect().report();
+    serde_wasm_bindgen::to_value(&report).unwrap_or(JsValue::NULL)
+}
+
 /// Get version information for display in UI
 #[wasm_bindgen(js_name = getVersionInfo)]
 pub fn get_version_info() -> JsValue {
@@ -0,0 +1,89 @@
+//! Browser teardown leak tests
+//!
+//! Browser counterpart of `webtor/tests/teardown.rs`: start and cancel many
+//! background tasks on the browser event loop and check that they exit, drop
+//! what they captured and leave linear memory flat.
+//!
+//! Run with:
+//!   wasm-pack test --headless --chrome webtor-wasm
+
+#![cfg(target_arch = "wasm32")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_test::*;
+use webtor::maintenance::{Maintenance, TtlCache};
+use webtor::retry::sleep;
+use webtor::CancellationToken;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+const TASKS: usize = 200;
+
+/// Current size of the module's linear memory in bytes
+fn memory_bytes() -> u32 {
+    wasm_bindgen::memory()
+        .unchecked_into::<js_sys::WebAssembly::Memory>()
+        .buffer()
+        .unchecked_into::<js_sys::ArrayBuffer>()
+        .byte_length()
+}
+
+/// Spawn `TASKS` maintenance tasks sharing `passes`, let them tick, then cancel them all
+async fn churn_maintenance(passes: &Arc<AtomicUsize>) {
+    let tokens: Vec<CancellationToken> = (0..TASKS)
+        .map(|_| {
+            let maintenance = Maintenance::new();
+            let mut cache: TtlCache<u32, Vec<u8>> = TtlCache::new(Duration::from_millis(1));
+            cache.insert(1, vec![0; 256]);
+            maintenance.register_cache("cache", Arc::new(Mutex::new(cache)));
+
+            let passes = passes.clone();
+            maintenance.register("passes", move || {
+                passes.fetch_add(1, Ordering::Relaxed);
+                async { 0 }
+            });
+
+            let token = CancellationToken::new();
+            maintenance.spawn(Duration::from_millis(5), token.clone());
+            token
+        })
+        .collect();
+
+    sleep(Duration::from_millis(50)).await;
+    for token in &tokens {
+        token.cancel();
+    }
+    // Let every task observe the cancellation and exit
+    sleep(Duration::from_millis(50)).await;
+}
+
+#[wasm_bindgen_test]
+async fn cancelled_tasks_release_captured_state() {
+    let passes = Arc::new(AtomicUsize::new(0));
+    churn_maintenance(&passes).await;
+
+    assert!(passes.load(Ordering::Relaxed) >= TASKS);
+    // Each task held a sweeper capturing `passes`; only ours should remain
+    assert_eq!(Arc::strong_count(&passes), 1);
+
+    let after_cancel = passes.load(Ordering::Relaxed);
+    sleep(Duration::from_millis(30)).await;
+    assert_eq!(passes.load(Ordering::Relaxed), after_cancel);
+}
+
+#[wasm_bindgen_test]
+async fn task_churn_keeps_memory_flat() {
+    let passes = Arc::new(AtomicUsize::new(0));
+
+    // Linear memory only grows, so after warm-up any growth means retained state
+    churn_maintenance(&passes).await;
+    let before = memory_bytes();
+    for _ in 0..5 {
+        churn_maintenance(&passes).await;
+    }
+    // Allow one 64 KiB page for allocator fragmentation
+    assert!(memory_bytes() <= before + 65_536);
+}
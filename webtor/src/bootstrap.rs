@@ -0,0 +1,576 @@
+//! Bootstrap progress tracking and the watchdog failure report
+//!
+//! Bootstrapping runs a fixed sequence: reach the bridge, finish the channel
+//! handshake, download the consensus and microdescriptors, then extend the
+//! first circuit hop by hop. [`BootstrapProgress`] is shared with the
+//! subsystems that perform each step and records how far they got. When the
+//! overall deadline (`TorClientOptions::bootstrap_timeout`) expires, the
+//! client fails with [`TorError::BootstrapTimeout`] carrying a
+//! [`BootstrapReport`] that says where bootstrap stalled, rather than a bare
+//! timeout.
+//!
+//! For progress bars, [`BootstrapStatus`] condenses the report into one of
+//! tor's bootstrap phases (`conn_pt`, `handshake`, `loading_descriptors`,
+//! `circuit_create`, `done`, ...) and a percentage, as tor reports them in
+//! its `BOOTSTRAP PROGRESS=` events. [`BootstrapProgress::subscribe`] streams
+//! every change; a new attempt starts over from the first phase.
+
+use crate::error::{Result, TorError};
+use crate::events::{Subscribers, TorEvent};
+use crate::retry::sleep;
+use crate::time::Instant;
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+/// Number of hops in a full circuit (bridge, middle, exit)
+pub const CIRCUIT_HOPS: u8 = 3;
+
+/// Bootstrap steps, in the order they happen
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "camelCase")]
+pub enum BootstrapStage {
+    #[default]
+    NotStarted,
+    /// Opening the transport to the bridge
+    ConnectingBridge,
+    /// Transport is up; running the Tor link handshake
+    Handshaking,
+    /// Downloading the consensus over the channel
+    FetchingConsensus,
+    /// Downloading microdescriptors for the relays in the consensus
+    FetchingDescriptors,
+    /// Extending the first circuit
+    BuildingCircuit,
+    /// A circuit is ready
+    Done,
+}
+
+impl BootstrapStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BootstrapStage::NotStarted => "not started",
+            BootstrapStage::ConnectingBridge => "connecting to bridge",
+            BootstrapStage::Handshaking => "channel handshake",
+            BootstrapStage::FetchingConsensus => "fetching consensus",
+            BootstrapStage::FetchingDescriptors => "fetching descriptors",
+            BootstrapStage::BuildingCircuit => "building circuit",
+            BootstrapStage::Done => "done",
+        }
+    }
+}
+
+impl fmt::Display for BootstrapStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Bootstrap phases as tor names them in its `BOOTSTRAP` status events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootstrapPhase {
+    Starting,
+    /// Connecting to the bridge's pluggable transport
+    ConnPt,
+    /// Running the link handshake with the bridge
+    Handshake,
+    /// Consensus requested, nothing received yet
+    RequestingStatus,
+    /// Receiving the consensus
+    LoadingStatus,
+    /// Receiving microdescriptors
+    LoadingDescriptors,
+    /// Building the first circuit
+    CircuitCreate,
+    Done,
+}
+
+impl BootstrapPhase {
+    /// Tag as in tor's status events, e.g. `conn_pt`
+    pub fn tag(&self) -> &'static str {
+        match self {
+            BootstrapPhase::Starting => "starting",
+            BootstrapPhase::ConnPt => "conn_pt",
+            BootstrapPhase::Handshake => "handshake",
+            BootstrapPhase::RequestingStatus => "requesting_status",
+            BootstrapPhase::LoadingStatus => "loading_status",
+            BootstrapPhase::LoadingDescriptors => "loading_descriptors",
+            BootstrapPhase::CircuitCreate => "circuit_create",
+            BootstrapPhase::Done => "done",
+        }
+    }
+
+    /// Human-readable summary, worded like tor's
+    pub fn summary(&self) -> &'static str {
+        match self {
+            BootstrapPhase::Starting => "Starting",
+            BootstrapPhase::ConnPt => "Connecting to pluggable transport",
+            BootstrapPhase::Handshake => "Handshaking with a relay",
+            BootstrapPhase::RequestingStatus => "Asking for networkstatus consensus",
+            BootstrapPhase::LoadingStatus => "Loading networkstatus consensus",
+            BootstrapPhase::LoadingDescriptors => "Loading relay descriptors",
+            BootstrapPhase::CircuitCreate => "Establishing a Tor circuit",
+            BootstrapPhase::Done => "Done",
+        }
+    }
+
+    /// Percentage at which the phase begins
+    pub fn percent(&self) -> u8 {
+        match self {
+            BootstrapPhase::Starting => 0,
+            BootstrapPhase::ConnPt => 1,
+            BootstrapPhase::Handshake => 14,
+            BootstrapPhase::RequestingStatus => 25,
+            BootstrapPhase::LoadingStatus => 40,
+            BootstrapPhase::LoadingDescriptors => 45,
+            BootstrapPhase::CircuitCreate => 90,
+            BootstrapPhase::Done => 100,
+        }
+    }
+}
+
+impl fmt::Display for BootstrapPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.tag())
+    }
+}
+
+/// Bootstrap phase and overall percentage, for a progress bar
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootstrapStatus {
+    pub phase: BootstrapPhase,
+    /// 0 to 100; within `loading_descriptors` and `circuit_create` it moves
+    /// with the descriptors received and hops completed
+    pub progress: u8,
+    pub summary: &'static str,
+}
+
+impl fmt::Display for BootstrapStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}% ({}): {}", self.progress, self.phase, self.summary)
+    }
+}
+
+/// How far a bootstrap attempt got
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootstrapReport {
+    /// Furthest step reached
+    pub stage: BootstrapStage,
+    /// Time since the attempt started
+    pub elapsed_ms: u64,
+    /// Whether the transport to the bridge connected
+    pub bridge_connected: bool,
+    /// Whether the Tor channel handshake with the bridge completed
+    pub channel_established: bool,
+    /// Consensus bytes received so far (including the HTTP header)
+    pub consensus_bytes: u64,
+    /// Whether a complete, timely consensus was parsed
+    pub consensus_valid: bool,
+    /// Microdescriptors requested for the relays in the consensus
+    pub descriptors_requested: usize,
+    /// Microdescriptors received so far, counted per finished request
+    #[serde(default)]
+    pub descriptors_received: usize,
+    /// Microdescriptor bytes received so far
+    pub descriptor_bytes: u64,
+    /// Relays usable for path selection
+    pub relays_loaded: usize,
+    /// Hops of the first circuit completed, out of [`CIRCUIT_HOPS`]
+    pub circuit_hops: u8,
+    /// The last error seen, if any
+    pub last_error: Option<String>,
+}
+
+impl BootstrapReport {
+    /// The tor bootstrap phase and percentage this report amounts to
+    pub fn status(&self) -> BootstrapStatus {
+        let phase = match self.stage {
+            BootstrapStage::NotStarted => BootstrapPhase::Starting,
+            BootstrapStage::ConnectingBridge => BootstrapPhase::ConnPt,
+            BootstrapStage::Handshaking => BootstrapPhase::Handshake,
+            BootstrapStage::FetchingConsensus if self.consensus_bytes == 0 => {
+                BootstrapPhase::RequestingStatus
+            }
+            BootstrapStage::FetchingConsensus => BootstrapPhase::LoadingStatus,
+            BootstrapStage::FetchingDescriptors => BootstrapPhase::LoadingDescriptors,
+            BootstrapStage::BuildingCircuit => BootstrapPhase::CircuitCreate,
+            BootstrapStage::Done => BootstrapPhase::Done,
+        };
+        let progress = match phase {
+            // Descriptors take tor's 45-80% span
+            BootstrapPhase::LoadingDescriptors if self.descriptors_requested > 0 => {
+                let received = self.descriptors_received.min(self.descriptors_requested);
+                phase.percent() + (35 * received / self.descriptors_requested) as u8
+            }
+            BootstrapPhase::CircuitCreate => {
+                phase.percent() + 3 * self.circuit_hops.min(CIRCUIT_HOPS)
+            }
+            _ => phase.percent(),
+        };
+        BootstrapStatus {
+            phase,
+            progress,
+            summary: phase.summary(),
+        }
+    }
+
+    /// One-line explanation of where bootstrap stopped and what to check
+    pub fn diagnosis(&self) -> String {
+        match self.stage {
+            BootstrapStage::NotStarted => "bootstrap has not started".to_string(),
+            BootstrapStage::ConnectingBridge => {
+                "could not reach the bridge; check network access and the bridge URL, \
+                 or try another bridge"
+                    .to_string()
+            }
+            BootstrapStage::Handshaking => {
+                "reached the bridge but the Tor handshake did not complete; \
+                 check the bridge fingerprint"
+                    .to_string()
+            }
+            BootstrapStage::FetchingConsensus if self.consensus_bytes == 0 => {
+                "channel is up but no consensus data arrived; the bridge may be overloaded"
+                    .to_string()
+            }
+            BootstrapStage::FetchingConsensus => format!(
+                "consensus download stalled after {} bytes; the connection is slow or dropping",
+                self.consensus_bytes
+            ),
+            BootstrapStage::FetchingDescriptors => format!(
+                "consensus received but only {} bytes of {} relay descriptors arrived",
+                self.descriptor_bytes, self.descriptors_requested
+            ),
+            BootstrapStage::BuildingCircuit => format!(
+                "directory loaded ({} relays) but the circuit only completed {} of {} hops; \
+                 the chosen relays may be unreachable",
+                self.relays_loaded, self.circuit_hops, CIRCUIT_HOPS
+            ),
+            BootstrapStage::Done => "bootstrap completed".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for BootstrapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (stage: {}, after {}ms",
+            self.diagnosis(),
+            self.stage,
+            self.elapsed_ms
+        )?;
+        if let Some(error) = &self.last_error {
+            write!(f, ", last error: {}", error)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Shared, updatable record of bootstrap progress
+#[derive(Clone, Default)]
+pub struct BootstrapProgress {
+    state: Arc<Mutex<ProgressState>>,
+    subscribers: Arc<Subscribers<BootstrapStatus>>,
+    /// Clients' unified event subscribers
+    forwards: Arc<Subscribers<TorEvent>>,
+}
+
+#[derive(Default)]
+struct ProgressState {
+    report: BootstrapReport,
+    started: Option<Instant>,
+    /// Status last sent to subscribers
+    status: Option<BootstrapStatus>,
+}
+
+impl ProgressState {
+    /// The current status, if it changed since last reported
+    fn changed_status(&mut self) -> Option<BootstrapStatus> {
+        let status = self.report.status();
+        if self.status.as_ref() == Some(&status) {
+            return None;
+        }
+        self.status = Some(status.clone());
+        Some(status)
+    }
+}
+
+impl fmt::Debug for BootstrapProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BootstrapProgress")
+            .field(&self.report().stage)
+            .finish()
+    }
+}
+
+impl BootstrapProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut BootstrapReport)) {
+        let changed = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut state.report);
+            state.changed_status()
+        };
+        if let Some(status) = changed {
+            self.publish(status);
+        }
+    }
+
+    fn publish(&self, status: BootstrapStatus) {
+        self.forwards
+            .emit_with(|| TorEvent::Bootstrap(status.clone()));
+        self.subscribers.emit(status);
+    }
+
+    /// Receive the status each time the phase or percentage changes from now on
+    pub fn subscribe(&self) -> UnboundedReceiver<BootstrapStatus> {
+        self.subscribers.subscribe()
+    }
+
+    /// Send each status change from now on to `tx` as a [`TorEvent`]
+    pub(crate) fn forward(&self, tx: UnboundedSender<TorEvent>) {
+        self.forwards.add(tx);
+    }
+
+    /// Phase and percentage of the current attempt
+    pub fn status(&self) -> BootstrapStatus {
+        self.report().status()
+    }
+
+    /// Begin a new attempt, keeping only the relay count from earlier ones
+    pub fn start(&self) {
+        let changed = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.report = BootstrapReport {
+                stage: BootstrapStage::ConnectingBridge,
+                relays_loaded: state.report.relays_loaded,
+                ..Default::default()
+            };
+            state.started = Some(Instant::now());
+            state.changed_status()
+        };
+        if let Some(status) = changed {
+            self.publish(status);
+        }
+    }
+
+    /// Move to `stage` unless bootstrap is already further along
+    pub fn advance(&self, stage: BootstrapStage) {
+        self.update(|r| r.stage = r.stage.max(stage));
+    }
+
+    pub fn bridge_connected(&self) {
+        self.update(|r| r.bridge_connected = true);
+        self.advance(BootstrapStage::Handshaking);
+    }
+
+    pub fn channel_established(&self) {
+        self.update(|r| r.channel_established = true);
+        self.advance(BootstrapStage::FetchingConsensus);
+    }
+
+    pub fn add_consensus_bytes(&self, bytes: usize) {
+        self.update(|r| r.consensus_bytes += bytes as u64);
+    }
+
+    /// The consensus parsed; `descriptors` microdescriptors are about to be fetched
+    pub fn consensus_valid(&self, descriptors: usize) {
+        self.update(|r| {
+            r.consensus_valid = true;
+            r.descriptors_requested = descriptors;
+        });
+        self.advance(BootstrapStage::FetchingDescriptors);
+    }
+
+    pub fn add_descriptor_bytes(&self, bytes: usize) {
+        self.update(|r| r.descriptor_bytes += bytes as u64);
+    }
+
+    pub fn add_descriptors_received(&self, count: usize) {
+        self.update(|r| r.descriptors_received += count);
+    }
+
+    pub fn relays_loaded(&self, count: usize) {
+        self.update(|r| r.relays_loaded = count);
+    }
+
+    /// A circuit completed `hops` hops (the furthest any circuit got is kept)
+    pub fn circuit_hop(&self, hops: u8) {
+        self.advance(BootstrapStage::BuildingCircuit);
+        self.update(|r| r.circuit_hops = r.circuit_hops.max(hops));
+    }
+
+    /// Record the end of an attempt
+    pub fn finish<T>(&self, result: &Result<T>) {
+        match result {
+            Ok(_) => self.advance(BootstrapStage::Done),
+            Err(e) => self.update(|r| r.last_error = Some(e.to_string())),
+        }
+    }
+
+    /// Snapshot of the current attempt
+    pub fn report(&self) -> BootstrapReport {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report = state.report.clone();
+        if let Some(started) = state.started {
+            report.elapsed_ms = started.elapsed().as_millis() as u64;
+        }
+        report
+    }
+
+    /// Run `future`, failing with a [`TorError::BootstrapTimeout`] report if
+    /// it hasn't finished within `deadline`
+    pub async fn watch<F, T>(&self, deadline: Duration, future: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        use futures::future::{select, Either};
+        use std::pin::pin;
+
+        match select(pin!(future), pin!(sleep(deadline))).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => {
+                let report = self.report();
+                warn!("Bootstrap deadline of {:?} expired: {}", deadline, report);
+                Err(TorError::BootstrapTimeout(Box::new(report)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_only_moves_forward() {
+        let progress = BootstrapProgress::new();
+        progress.relays_loaded(100);
+        progress.start();
+        progress.bridge_connected();
+        progress.channel_established();
+        progress.add_consensus_bytes(1000);
+        progress.add_consensus_bytes(500);
+        progress.consensus_valid(42);
+        progress.circuit_hop(1);
+        progress.circuit_hop(2);
+        // Circuits built in parallel must not regress the report
+        progress.circuit_hop(1);
+        progress.advance(BootstrapStage::Handshaking);
+
+        let report = progress.report();
+        assert_eq!(report.stage, BootstrapStage::BuildingCircuit);
+        assert!(report.bridge_connected && report.channel_established);
+        assert_eq!(report.consensus_bytes, 1500);
+        assert_eq!(report.descriptors_requested, 42);
+        assert_eq!(report.relays_loaded, 100);
+        assert_eq!(report.circuit_hops, 2);
+        assert!(report.diagnosis().contains("2 of 3 hops"));
+
+        // A new attempt starts from scratch but remembers the loaded relays
+        progress.start();
+        let report = progress.report();
+        assert_eq!(report.stage, BootstrapStage::ConnectingBridge);
+        assert_eq!(report.consensus_bytes, 0);
+        assert_eq!(report.relays_loaded, 100);
+    }
+
+    #[test]
+    fn test_diagnosis_distinguishes_stalled_consensus() {
+        let mut report = BootstrapReport {
+            stage: BootstrapStage::FetchingConsensus,
+            ..Default::default()
+        };
+        assert!(report.diagnosis().contains("no consensus data"));
+        report.consensus_bytes = 4096;
+        report.last_error = Some("reset".to_string());
+        assert!(report.diagnosis().contains("stalled after 4096 bytes"));
+        assert!(report.to_string().ends_with("last error: reset)"));
+    }
+
+    #[test]
+    fn test_status_phases_and_percentages() {
+        let progress = BootstrapProgress::new();
+        assert_eq!(progress.status().phase, BootstrapPhase::Starting);
+        progress.start();
+        assert_eq!(progress.status().phase.tag(), "conn_pt");
+        progress.bridge_connected();
+        progress.channel_established();
+        assert_eq!(progress.status().phase, BootstrapPhase::RequestingStatus);
+        progress.add_consensus_bytes(100);
+        assert_eq!(progress.status().progress, 40);
+
+        progress.consensus_valid(1000);
+        assert_eq!(progress.status().progress, 45);
+        progress.add_descriptors_received(500);
+        let status = progress.status();
+        assert_eq!(
+            (status.phase.tag(), status.progress),
+            ("loading_descriptors", 62)
+        );
+
+        progress.circuit_hop(2);
+        assert_eq!(progress.status().progress, 96);
+        progress.finish(&Ok(()));
+        let status = progress.status();
+        assert_eq!(status.progress, 100);
+        assert_eq!(status.to_string(), "100% (done): Done");
+
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json["phase"], "done");
+        assert_eq!(json["progress"], 100);
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_see_each_change_once() {
+        use futures::StreamExt;
+
+        let progress = BootstrapProgress::new();
+        let statuses = progress.subscribe();
+        progress.start();
+        progress.bridge_connected();
+        // Byte counts alone don't move the phase or the percentage
+        progress.add_consensus_bytes(10);
+        progress.add_consensus_bytes(10);
+        progress.finish(&Ok(()));
+        drop(progress);
+
+        let tags: Vec<&str> = statuses.map(|s| s.phase.tag()).collect().await;
+        assert_eq!(tags, ["conn_pt", "handshake", "done"]);
+    }
+
+    #[tokio::test]
+    async fn test_watch_reports_on_expiry() {
+        let progress = BootstrapProgress::new();
+        progress.start();
+        progress.bridge_connected();
+
+        let err = progress
+            .watch(Duration::from_millis(10), async {
+                sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        let report = err.bootstrap_report().unwrap();
+        assert_eq!(report.stage, BootstrapStage::Handshaking);
+        assert_eq!(err.code(), "BOOTSTRAP_TIMEOUT");
+
+        let ok = progress
+            .watch(Duration::from_secs(5), async { Ok(7) })
+            .await;
+        assert_eq!(ok.unwrap(), 7);
+    }
+}
@@ -0,0 +1,318 @@
+//! Bridge lines as handed out by BridgeDB and used in Tor Browser
+//!
+//! A bridge line names a transport, an address, an optional fingerprint and
+//! transport arguments:
+//!
+//! ```text
+//! webtunnel [2001:db8::1]:443 <FINGERPRINT> url=https://example.com/path ver=0.0.1
+//! obfs4 192.0.2.1:443 <FINGERPRINT> cert=... iat-mode=0
+//! snowflake 192.0.2.3:80 <FINGERPRINT> url=https://broker.example/ front=cdn.example ampcache=https://cdn.ampproject.org/
+//! meek_lite 192.0.2.18:80 <FINGERPRINT> url=https://meek.example/ front=cdn.example
+//! ```
+//!
+//! A leading `Bridge` keyword (as in torrc) is accepted. The address of
+//! WebTunnel, Snowflake and meek lines is a placeholder that only tells tor
+//! which bridge is which; those transports connect to their `url` instead.
+
+use crate::config::BridgeType;
+use crate::error::{Result, TorError};
+use crate::snowflake_broker::{Rendezvous, BROKER_URL};
+use crate::websocket::WebSocketOptions;
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed bridge line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeLine {
+    /// Pluggable transport name, e.g. `obfs4`
+    pub transport: String,
+    /// Bridge address (`host:port`, IPv6 hosts in brackets)
+    pub addr: String,
+    /// RSA identity fingerprint (40 hex chars), if the line has one
+    pub fingerprint: Option<String>,
+    /// `key=value` transport arguments in line order
+    pub args: Vec<(String, String)>,
+}
+
+impl BridgeLine {
+    /// Value of the transport argument `key`
+    pub fn arg(&self, key: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn required_arg(&self, key: &str) -> Result<&str> {
+        self.arg(key).ok_or_else(|| {
+            TorError::configuration(format!(
+                "{} bridge line is missing its {}= argument",
+                self.transport, key
+            ))
+        })
+    }
+
+    /// The transport configuration this line describes
+    pub fn bridge_type(&self) -> Result<BridgeType> {
+        match self.transport.as_str() {
+            "webtunnel" => Ok(BridgeType::WebTunnel {
+                url: self.required_arg("url")?.to_string(),
+                server_name: self.arg("servername").map(str::to_string),
+                handshake: WebSocketOptions::default(),
+            }),
+            "obfs4" => {
+                let iat_mode = match self.arg("iat-mode") {
+                    Some(mode) => mode.parse().map_err(|_| {
+                        TorError::configuration(format!("Invalid obfs4 iat-mode: {}", mode))
+                    })?,
+                    None => 0,
+                };
+                Ok(BridgeType::Obfs4 {
+                    addr: self.addr.clone(),
+                    cert: self.required_arg("cert")?.to_string(),
+                    iat_mode,
+                })
+            }
+            "snowflake" => Ok(self.snowflake_bridge_type()),
+            "meek" | "meek_lite" => Ok(BridgeType::Meek {
+                url: self.required_arg("url")?.to_string(),
+                front: self.arg("front").map(str::to_string),
+            }),
+            other => Err(TorError::configuration(format!(
+                "Unsupported bridge transport: {}",
+                other
+            ))),
+        }
+    }
+
+    /// Direct broker first, then through the line's fronts, then its AMP cache
+    fn snowflake_bridge_type(&self) -> BridgeType {
+        let broker_url = self.arg("url").unwrap_or(BROKER_URL).to_string();
+        let fronts: Vec<String> = self
+            .args
+            .iter()
+            .filter(|(k, _)| k == "front" || k == "fronts")
+            .flat_map(|(_, v)| v.split(','))
+            .filter(|front| !front.is_empty())
+            .map(str::to_string)
+            .collect();
+
+        let mut fallbacks = Vec::new();
+        if !fronts.is_empty() {
+            fallbacks.push(Rendezvous::http(&broker_url).with_fronts(fronts.clone()));
+        }
+        if let Some(cache_url) = self.arg("ampcache") {
+            fallbacks.push(Rendezvous::AmpCache {
+                broker_url: broker_url.clone(),
+                cache_url: cache_url.to_string(),
+                fronts,
+            });
+        }
+        BridgeType::SnowflakeWebRtc {
+            broker_url,
+            fallbacks,
+        }
+    }
+
+    /// The fingerprint from the line, or from its `fingerprint=` argument
+    pub fn identity(&self) -> Option<&str> {
+        self.fingerprint
+            .as_deref()
+            .or_else(|| self.arg("fingerprint"))
+    }
+}
+
+impl FromStr for BridgeLine {
+    type Err = TorError;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace().peekable();
+        if words
+            .peek()
+            .is_some_and(|w| w.eq_ignore_ascii_case("bridge"))
+        {
+            words.next();
+        }
+
+        let transport = words
+            .next()
+            .ok_or_else(|| TorError::configuration("Empty bridge line"))?;
+        if transport.contains(':') || transport.parse::<std::net::IpAddr>().is_ok() {
+            return Err(TorError::configuration(
+                "Bridge lines without a pluggable transport are not supported",
+            ));
+        }
+        let addr = words.next().ok_or_else(|| {
+            TorError::configuration(format!("{} bridge line has no address", transport))
+        })?;
+        if addr.contains('=') || !addr.contains(':') {
+            return Err(TorError::configuration(format!(
+                "Invalid bridge address: {}",
+                addr
+            )));
+        }
+
+        let fingerprint = match words.peek() {
+            Some(word) if !word.contains('=') => {
+                let word = words.next().unwrap_or_default();
+                if word.len() != 40 || !word.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(TorError::configuration(format!(
+                        "Invalid bridge fingerprint: {}",
+                        word
+                    )));
+                }
+                Some(word.to_ascii_uppercase())
+            }
+            _ => None,
+        };
+
+        let args = words
+            .map(|word| {
+                word.split_once('=')
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .ok_or_else(|| {
+                        TorError::configuration(format!("Invalid bridge line argument: {}", word))
+                    })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            transport: transport.to_ascii_lowercase(),
+            addr: addr.to_string(),
+            fingerprint,
+            args,
+        })
+    }
+}
+
+impl fmt::Display for BridgeLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.transport, self.addr)?;
+        if let Some(fingerprint) = &self.fingerprint {
+            write!(f, " {}", fingerprint)?;
+        }
+        for (k, v) in &self.args {
+            write!(f, " {}={}", k, v)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FP: &str = "2B280B23E1107BB62ABFC40DDCC8824814F80A72";
+
+    #[test]
+    fn test_parse_webtunnel() {
+        let line: BridgeLine = format!(
+            "webtunnel [2001:db8::1]:443 {} url=https://example.com/secret ver=0.0.1",
+            FP.to_ascii_lowercase()
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(line.addr, "[2001:db8::1]:443");
+        assert_eq!(line.fingerprint.as_deref(), Some(FP));
+        assert_eq!(line.arg("ver"), Some("0.0.1"));
+        match line.bridge_type().unwrap() {
+            BridgeType::WebTunnel {
+                url, server_name, ..
+            } => {
+                assert_eq!(url, "https://example.com/secret");
+                assert_eq!(server_name, None);
+            }
+            other => panic!("unexpected bridge type {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_obfs4_with_bridge_keyword() {
+        let line: BridgeLine =
+            format!("Bridge obfs4 192.0.2.1:443 {} cert=c2VjcmV0 iat-mode=1", FP)
+                .parse()
+                .unwrap();
+        match line.bridge_type().unwrap() {
+            BridgeType::Obfs4 {
+                addr,
+                cert,
+                iat_mode,
+            } => {
+                assert_eq!(addr, "192.0.2.1:443");
+                assert_eq!(cert, "c2VjcmV0");
+                assert_eq!(iat_mode, 1);
+            }
+            other => panic!("unexpected bridge type {:?}", other),
+        }
+        assert_eq!(
+            line.to_string(),
+            format!("obfs4 192.0.2.1:443 {} cert=c2VjcmV0 iat-mode=1", FP)
+        );
+    }
+
+    #[test]
+    fn test_parse_snowflake_fallbacks() {
+        let line: BridgeLine = format!(
+            "snowflake 192.0.2.3:80 {fp} fingerprint={fp} url=https://broker.example/ \
+             ampcache=https://cdn.ampproject.org/ front=a.example,b.example \
+             ice=stun:stun.example:3478 utls-imitate=hellorandomizedalpn",
+            fp = FP
+        )
+        .parse()
+        .unwrap();
+        match line.bridge_type().unwrap() {
+            BridgeType::SnowflakeWebRtc {
+                broker_url,
+                fallbacks,
+            } => {
+                assert_eq!(broker_url, "https://broker.example/");
+                let fronts = vec!["a.example".to_string(), "b.example".to_string()];
+                assert_eq!(
+                    fallbacks,
+                    vec![
+                        Rendezvous::http("https://broker.example/").with_fronts(fronts.clone()),
+                        Rendezvous::AmpCache {
+                            broker_url: "https://broker.example/".to_string(),
+                            cache_url: "https://cdn.ampproject.org/".to_string(),
+                            fronts,
+                        },
+                    ]
+                );
+            }
+            other => panic!("unexpected bridge type {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_meek_without_fingerprint() {
+        let line: BridgeLine =
+            "meek_lite 192.0.2.18:80 url=https://meek.example/ front=cdn.example"
+                .parse()
+                .unwrap();
+        assert_eq!(line.fingerprint, None);
+        assert_eq!(line.identity(), None);
+        match line.bridge_type().unwrap() {
+            BridgeType::Meek { url, front } => {
+                assert_eq!(url, "https://meek.example/");
+                assert_eq!(front.as_deref(), Some("cdn.example"));
+            }
+            other => panic!("unexpected bridge type {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_invalid_lines() {
+        assert!("".parse::<BridgeLine>().is_err());
+        assert!("192.0.2.1:443".parse::<BridgeLine>().is_err());
+        assert!("obfs4".parse::<BridgeLine>().is_err());
+        assert!("obfs4 192.0.2.1:443 NOTHEX".parse::<BridgeLine>().is_err());
+        assert!(format!("obfs4 192.0.2.1:443 {} cert", FP)
+            .parse::<BridgeLine>()
+            .is_err());
+
+        let missing_cert: BridgeLine = format!("obfs4 192.0.2.1:443 {}", FP).parse().unwrap();
+        assert!(missing_cert.bridge_type().is_err());
+        let unknown: BridgeLine = "scramblesuit 192.0.2.1:443".parse().unwrap();
+        assert!(unknown.bridge_type().is_err());
+    }
+}
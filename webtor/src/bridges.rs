@@ -0,0 +1,492 @@
+//! Failover between configured bridges, and their health
+//!
+//! A client may be given several bridges, possibly of different transports.
+//! [`BridgeSet`] decides the order in which they are tried: bridges that
+//! haven't failed recently come first, historically healthier ones (by
+//! success rate) before others and configuration order among equals, then
+//! the ones backing off, soonest retry first. Every failure (a failed
+//! connection or a lost channel) doubles the bridge's backoff up to a cap,
+//! and a successful connection clears it. A bridge backing off is still
+//! tried when nothing else is left, so a client with a single bridge keeps
+//! retrying it.
+//!
+//! Each bridge's [`BridgeHealth`] (successes, failures, connection latency,
+//! last success and failure) is kept across sessions in the client's
+//! [`StateStore`]. While a channel is up, the client probes failing bridges
+//! in the background every
+//! [`bridge_probe_interval`](crate::TorClientOptions::bridge_probe_interval),
+//! so a bridge that recovers is preferred again before it is needed.
+
+use crate::config::BridgeConfig;
+use crate::error::Result;
+use crate::guard::now_secs;
+use crate::storage::StateStore;
+use crate::time::Instant;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Backoff after a bridge's first failure
+pub const INITIAL_BRIDGE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Longest backoff of a failing bridge
+pub const MAX_BRIDGE_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// Storage key for the persisted bridge health
+pub(crate) const STATE_KEY: &str = "bridge_health";
+
+/// Weight of the newest sample in the smoothed connection latency
+const LATENCY_SMOOTHING: f64 = 0.25;
+
+/// Connection history of one bridge (times are Unix seconds)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BridgeHealth {
+    pub successes: u32,
+    pub failures: u32,
+    /// Smoothed time to open a channel, in milliseconds
+    #[serde(default)]
+    pub latency_ms: Option<f64>,
+    #[serde(default)]
+    pub last_success: Option<u64>,
+    #[serde(default)]
+    pub last_failure: Option<u64>,
+}
+
+impl BridgeHealth {
+    /// Fraction of attempts that succeeded, if the bridge was ever tried
+    pub fn success_rate(&self) -> Option<f64> {
+        let attempts = self.successes + self.failures;
+        (attempts > 0).then(|| self.successes as f64 / attempts as f64)
+    }
+
+    /// Success rate pulled towards one half while there are few attempts
+    fn score(&self) -> f64 {
+        (self.successes as f64 + 1.0) / ((self.successes + self.failures) as f64 + 2.0)
+    }
+
+    fn record_success(&mut self, latency: Duration, now: u64) {
+        self.successes = self.successes.saturating_add(1);
+        self.last_success = Some(now);
+        let sample = latency.as_secs_f64() * 1000.0;
+        self.latency_ms = Some(match self.latency_ms {
+            Some(old) => old + LATENCY_SMOOTHING * (sample - old),
+            None => sample,
+        });
+    }
+
+    fn record_failure(&mut self, now: u64) {
+        self.failures = self.failures.saturating_add(1);
+        self.last_failure = Some(now);
+    }
+}
+
+/// A configured bridge and how it has been doing
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BridgeStatus {
+    /// Position in the configuration (0 is the primary bridge)
+    pub index: usize,
+    pub transport: String,
+    pub endpoint: String,
+    pub fingerprint: Option<String>,
+    /// Whether the current channel goes through this bridge
+    pub current: bool,
+    /// Failures since the bridge last worked
+    pub consecutive_failures: u32,
+    /// Time left before the bridge is tried again ahead of others
+    pub retry_in_ms: Option<u64>,
+    pub health: BridgeHealth,
+}
+
+#[derive(Debug, Clone)]
+struct BridgeState {
+    config: BridgeConfig,
+    /// Failures since the last successful connection
+    failures: u32,
+    retry_at: Option<Instant>,
+    health: BridgeHealth,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    bridges: Vec<BridgeState>,
+    /// Index of the bridge the current channel goes through
+    current: Option<usize>,
+}
+
+/// The configured bridges and their failure backoff, shared by client handles
+#[derive(Clone, Default)]
+pub struct BridgeSet {
+    inner: Arc<Mutex<Inner>>,
+    store: Option<Arc<dyn StateStore>>,
+}
+
+impl BridgeSet {
+    pub fn new(bridges: Vec<BridgeConfig>) -> Self {
+        let bridges = bridges
+            .into_iter()
+            .map(|config| BridgeState {
+                config,
+                failures: 0,
+                retry_at: None,
+                health: BridgeHealth::default(),
+            })
+            .collect();
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                bridges,
+                current: None,
+            })),
+            store: None,
+        }
+    }
+
+    /// Load the bridges' health from `store` and persist it there
+    pub fn with_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = Some(store);
+        self.reload();
+        self
+    }
+
+    /// Load the bridges' health from the store again, e.g. after state was
+    /// imported into it
+    pub fn reload(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let saved: HashMap<String, BridgeHealth> = match store.load(STATE_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Discarding unreadable bridge health: {}", e);
+                HashMap::new()
+            }),
+            Ok(None) => HashMap::new(),
+            Err(e) => {
+                warn!("Failed to load bridge health: {}", e);
+                HashMap::new()
+            }
+        };
+        {
+            let mut inner = self.lock();
+            for state in &mut inner.bridges {
+                if let Some(health) = saved.get(&health_key(&state.config)) {
+                    state.health = health.clone();
+                }
+            }
+            debug!("Loaded health of {} bridges", saved.len());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().bridges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bridges in the order to try them, with their indices
+    pub fn connect_order(&self) -> Vec<(usize, BridgeConfig)> {
+        self.connect_order_at(Instant::now())
+    }
+
+    fn connect_order_at(&self, now: Instant) -> Vec<(usize, BridgeConfig)> {
+        let inner = self.lock();
+        let mut order: Vec<(usize, &BridgeState)> = inner.bridges.iter().enumerate().collect();
+        // Stable sort keeps configuration order among equally healthy bridges
+        order.sort_by(|(_, a), (_, b)| {
+            remaining(a, now)
+                .cmp(&remaining(b, now))
+                .then(b.health.score().total_cmp(&a.health.score()))
+        });
+        order
+            .into_iter()
+            .map(|(index, state)| (index, state.config.clone()))
+            .collect()
+    }
+
+    /// Failing bridges other than the current one that are due for a probe
+    pub fn probe_candidates(&self) -> Vec<(usize, BridgeConfig)> {
+        let now = Instant::now();
+        let inner = self.lock();
+        inner
+            .bridges
+            .iter()
+            .enumerate()
+            .filter(|(index, state)| {
+                Some(*index) != inner.current
+                    && state.failures > 0
+                    && remaining(state, now).is_zero()
+            })
+            .map(|(index, state)| (index, state.config.clone()))
+            .collect()
+    }
+
+    /// Record that a channel came up through bridge `index` after `latency`
+    pub fn record_success(&self, index: usize, latency: Duration) {
+        self.record_probe_success(index, latency);
+        let mut inner = self.lock();
+        if index < inner.bridges.len() {
+            inner.current = Some(index);
+        }
+    }
+
+    /// Record that a probe opened a channel through bridge `index`
+    pub fn record_probe_success(&self, index: usize, latency: Duration) {
+        {
+            let mut inner = self.lock();
+            let Some(state) = inner.bridges.get_mut(index) else {
+                return;
+            };
+            if state.failures > 0 {
+                info!("Bridge {} works again", index);
+            }
+            state.failures = 0;
+            state.retry_at = None;
+            state.health.record_success(latency, now_secs());
+        }
+        self.persist();
+    }
+
+    /// Record that connecting through bridge `index` failed or its channel was lost
+    pub fn record_failure(&self, index: usize) {
+        self.record_failure_at(index, Instant::now());
+    }
+
+    fn record_failure_at(&self, index: usize, now: Instant) {
+        {
+            let mut inner = self.lock();
+            if inner.current == Some(index) {
+                inner.current = None;
+            }
+            let Some(state) = inner.bridges.get_mut(index) else {
+                return;
+            };
+            let backoff = backoff_for(state.failures);
+            state.failures += 1;
+            state.retry_at = Some(now + backoff);
+            state.health.record_failure(now_secs());
+            info!(
+                "Bridge {} failed {} times, backing off for {:?}",
+                index, state.failures, backoff
+            );
+        }
+        self.persist();
+    }
+
+    /// The bridge the current channel goes through, if any
+    pub fn current(&self) -> Option<(usize, BridgeConfig)> {
+        let inner = self.lock();
+        let index = inner.current?;
+        Some((index, inner.bridges.get(index)?.config.clone()))
+    }
+
+    /// Status of every configured bridge, in configuration order
+    pub fn status(&self) -> Vec<BridgeStatus> {
+        let now = Instant::now();
+        let inner = self.lock();
+        inner
+            .bridges
+            .iter()
+            .enumerate()
+            .map(|(index, state)| {
+                let retry_in = remaining(state, now);
+                BridgeStatus {
+                    index,
+                    transport: state.config.bridge.transport().to_string(),
+                    endpoint: state.config.bridge.endpoint().to_string(),
+                    fingerprint: state.config.identity().ok(),
+                    current: inner.current == Some(index),
+                    consecutive_failures: state.failures,
+                    retry_in_ms: (!retry_in.is_zero()).then_some(retry_in.as_millis() as u64),
+                    health: state.health.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Write the bridges' health to the store
+    pub fn save(&self) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let health: HashMap<String, BridgeHealth> = self
+            .lock()
+            .bridges
+            .iter()
+            .map(|state| (health_key(&state.config), state.health.clone()))
+            .collect();
+        store.store(STATE_KEY, &serde_json::to_string(&health)?)
+    }
+
+    fn persist(&self) {
+        // Losing bridge health only costs a worse first choice next session
+        if let Err(e) = self.save() {
+            warn!("Failed to persist bridge health: {}", e);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Time until a backing-off bridge may be tried again
+fn remaining(state: &BridgeState, now: Instant) -> Duration {
+    state
+        .retry_at
+        .map_or(Duration::ZERO, |at| at.duration_since(now))
+}
+
+/// Backoff after a bridge has failed `failures` times before
+fn backoff_for(failures: u32) -> Duration {
+    INITIAL_BRIDGE_BACKOFF
+        .saturating_mul(1u32 << failures.min(16))
+        .min(MAX_BRIDGE_BACKOFF)
+}
+
+/// Key of a bridge's health in the store: its transport, endpoint and identity
+fn health_key(config: &BridgeConfig) -> String {
+    format!(
+        "{} {} {}",
+        config.bridge.transport(),
+        config.bridge.endpoint(),
+        config.fingerprint.as_deref().unwrap_or("-")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BridgeType;
+    use crate::storage::MemoryStore;
+
+    const LATENCY: Duration = Duration::from_millis(400);
+
+    fn bridge(url: &str) -> BridgeConfig {
+        BridgeConfig::new(
+            BridgeType::WebTunnel {
+                url: url.to_string(),
+                server_name: None,
+                handshake: Default::default(),
+            },
+            Some("AA".repeat(20)),
+        )
+    }
+
+    fn indices(order: &[(usize, BridgeConfig)]) -> Vec<usize> {
+        order.iter().map(|(index, _)| *index).collect()
+    }
+
+    #[test]
+    fn test_failed_bridges_move_to_the_back() {
+        let set = BridgeSet::new(vec![
+            bridge("https://a"),
+            bridge("https://b"),
+            bridge("https://c"),
+        ]);
+        let t0 = Instant::now();
+        assert_eq!(indices(&set.connect_order_at(t0)), vec![0, 1, 2]);
+
+        set.record_failure_at(0, t0);
+        set.record_failure_at(1, t0);
+        set.record_failure_at(1, t0);
+        // Both back off; the one with the shorter backoff is retried first
+        assert_eq!(indices(&set.connect_order_at(t0)), vec![2, 0, 1]);
+
+        // Once the backoff has passed, the untried bridge still comes first
+        let later = t0 + INITIAL_BRIDGE_BACKOFF;
+        assert_eq!(indices(&set.connect_order_at(later)), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn test_success_clears_backoff_and_sets_current() {
+        let set = BridgeSet::new(vec![bridge("https://a"), bridge("https://b")]);
+        let t0 = Instant::now();
+        set.record_failure_at(0, t0);
+        assert_eq!(indices(&set.connect_order_at(t0)), vec![1, 0]);
+
+        set.record_success(0, LATENCY);
+        assert_eq!(set.current().map(|(index, _)| index), Some(0));
+        assert_eq!(indices(&set.connect_order_at(t0)), vec![0, 1]);
+
+        // Losing the channel clears the current bridge
+        set.record_failure_at(0, t0);
+        assert!(set.current().is_none());
+    }
+
+    #[test]
+    fn test_healthy_bridges_are_preferred() {
+        let set = BridgeSet::new(vec![bridge("https://a"), bridge("https://b")]);
+        set.record_success(1, LATENCY);
+        assert_eq!(indices(&set.connect_order()), vec![1, 0]);
+
+        let status = set.status();
+        assert!(status[1].current);
+        assert_eq!(status[1].health.success_rate(), Some(1.0));
+        assert_eq!(status[1].health.latency_ms, Some(400.0));
+        assert_eq!(status[0].health.success_rate(), None);
+
+        set.record_probe_success(1, Duration::from_millis(800));
+        assert_eq!(set.status()[1].health.latency_ms, Some(500.0));
+    }
+
+    #[test]
+    fn test_probe_candidates_are_failing_bridges() {
+        let set = BridgeSet::new(vec![bridge("https://a"), bridge("https://b")]);
+        set.record_success(0, LATENCY);
+        let t0 = Instant::now() - INITIAL_BRIDGE_BACKOFF;
+        set.record_failure_at(1, t0);
+        assert_eq!(indices(&set.probe_candidates()), vec![1]);
+
+        // A working probe clears the failure without taking over the channel
+        set.record_probe_success(1, LATENCY);
+        assert!(set.probe_candidates().is_empty());
+        assert_eq!(set.current().map(|(index, _)| index), Some(0));
+    }
+
+    #[test]
+    fn test_health_persists() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        let set = BridgeSet::new(vec![bridge("https://a"), bridge("https://b")])
+            .with_store(store.clone());
+        set.record_success(1, LATENCY);
+        set.record_failure(0);
+
+        // The configuration order may change between sessions
+        let reloaded =
+            BridgeSet::new(vec![bridge("https://b"), bridge("https://a")]).with_store(store);
+        let status = reloaded.status();
+        assert_eq!(status[0].health.successes, 1);
+        assert_eq!(status[1].health.failures, 1);
+        // Backoff and the current bridge don't outlive the session
+        assert_eq!(status[1].retry_in_ms, None);
+        assert!(!status[0].current);
+        assert_eq!(indices(&reloaded.connect_order()), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_options_list_primary_first() {
+        let fp = "2B280B23E1107BB62ABFC40DDCC8824814F80A72";
+        let options = crate::TorClientOptions::from_bridge_lines(&[
+            format!("webtunnel [2001:db8::1]:443 {} url=https://a.example/x", fp),
+            format!("obfs4 192.0.2.1:443 {} cert=abc iat-mode=0", fp),
+        ])
+        .unwrap();
+        let bridges = options.bridges();
+        assert_eq!(bridges.len(), 2);
+        assert!(matches!(bridges[0].bridge, BridgeType::WebTunnel { .. }));
+        assert!(matches!(bridges[1].bridge, BridgeType::Obfs4 { .. }));
+        assert_eq!(bridges[1].identity().unwrap(), fp);
+        assert!(crate::TorClientOptions::from_bridge_lines::<&str>(&[]).is_err());
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap() {
+        assert_eq!(backoff_for(0), INITIAL_BRIDGE_BACKOFF);
+        assert_eq!(backoff_for(1), INITIAL_BRIDGE_BACKOFF * 2);
+        assert_eq!(backoff_for(30), MAX_BRIDGE_BACKOFF);
+    }
+}
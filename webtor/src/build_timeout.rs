@@ -0,0 +1,336 @@
+//! Adaptive circuit build timeout
+//!
+//! A fixed build timeout is either far too long for a fast bridge or too
+//! short for a slow Snowflake proxy. Following Tor's circuit build timeout
+//! algorithm (path-spec §2.4), [`BuildTimeoutEstimator`] records how long
+//! circuits took to build, fits a Pareto distribution to those times and
+//! sets the timeout where a given share of builds (80% by default) would
+//! have completed. Until enough builds have been observed the initial
+//! timeout applies.
+//!
+//! Builds that time out are kept as censored samples at the timeout they
+//! hit. When nearly every recent build times out the network has probably
+//! changed (a new bridge, a different uplink), so the history is dropped and
+//! the estimate starts over. Samples are persisted through a [`StateStore`]
+//! when one is attached, so the estimate carries across sessions.
+
+use crate::storage::StateStore;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tor_proto::client::circuit::TimeoutEstimator;
+use tracing::{debug, info, warn};
+
+/// Histogram bin width in milliseconds
+const BIN_WIDTH_MS: u32 = 10;
+
+/// Number of most frequent bins averaged to estimate the Pareto mode
+const NUM_MODES: usize = 10;
+
+/// Window of recent builds checked for a changed network
+const RECENT_BUILDS: usize = 20;
+
+/// Timeouts within the recent window that reset the history
+const MAX_RECENT_TIMEOUTS: usize = 18;
+
+/// Storage key for the persisted build times
+pub(crate) const STATE_KEY: &str = "build_times";
+
+/// Parameters of the build timeout estimate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BuildTimeoutConfig {
+    /// Timeout in milliseconds until enough builds are observed; also the ceiling
+    pub initial_ms: u64,
+    /// Shortest timeout in milliseconds the estimate may produce
+    pub min_ms: u64,
+    /// Percentage of builds that should complete within the timeout
+    pub quantile_percent: u8,
+    /// Builds observed before the estimate replaces the initial timeout
+    pub min_samples: usize,
+    /// Builds kept in the history
+    pub max_samples: usize,
+}
+
+impl Default for BuildTimeoutConfig {
+    fn default() -> Self {
+        Self {
+            initial_ms: 60_000,
+            min_ms: 1_500,
+            quantile_percent: 80,
+            // Tor waits for 100, but browser sessions build far fewer circuits
+            min_samples: 20,
+            max_samples: 1_000,
+        }
+    }
+}
+
+/// One observed build: its duration, or the timeout it hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct BuildRecord {
+    ms: u32,
+    timed_out: bool,
+}
+
+struct State {
+    records: VecDeque<BuildRecord>,
+    timeout: Duration,
+}
+
+/// Shared circuit build timeout, adapted to observed build times
+#[derive(Clone)]
+pub struct BuildTimeoutEstimator {
+    config: BuildTimeoutConfig,
+    state: Arc<Mutex<State>>,
+    store: Option<Arc<dyn StateStore>>,
+}
+
+impl Default for BuildTimeoutEstimator {
+    fn default() -> Self {
+        Self::new(BuildTimeoutConfig::default())
+    }
+}
+
+impl BuildTimeoutEstimator {
+    pub fn new(config: BuildTimeoutConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(State {
+                records: VecDeque::new(),
+                timeout: Duration::from_millis(config.initial_ms),
+            })),
+            store: None,
+        }
+    }
+
+    /// Load earlier build times from `store` and persist new ones there
+    pub fn with_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = Some(store);
+        self.reload();
+        self
+    }
+
+    /// Load the build times from the store again, replacing the history,
+    /// e.g. after state was imported into it
+    pub fn reload(&self) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        let records: Vec<BuildRecord> = match store.load(STATE_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Discarding unreadable build time history: {}", e);
+                Vec::new()
+            }),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                warn!("Failed to load build time history: {}", e);
+                Vec::new()
+            }
+        };
+        {
+            let mut state = self.lock();
+            state.records = records.into_iter().collect();
+            while state.records.len() > self.config.max_samples {
+                state.records.pop_front();
+            }
+            state.timeout = estimate(&self.config, &state.records);
+            debug!(
+                "Loaded {} build times; timeout {:?}",
+                state.records.len(),
+                state.timeout
+            );
+        }
+    }
+
+    pub fn config(&self) -> &BuildTimeoutConfig {
+        &self.config
+    }
+
+    /// Current timeout for building a full circuit
+    pub fn timeout(&self) -> Duration {
+        self.lock().timeout
+    }
+
+    /// Number of builds in the history
+    pub fn sample_count(&self) -> usize {
+        self.lock().records.len()
+    }
+
+    /// Record a circuit that finished building after `elapsed`
+    pub fn record_success(&self, elapsed: Duration) {
+        let ms = elapsed.as_millis().min(u32::MAX as u128) as u32;
+        self.record(BuildRecord {
+            ms,
+            timed_out: false,
+        });
+    }
+
+    /// Record a circuit that hit the current timeout
+    pub fn record_timeout(&self) {
+        let ms = self.timeout().as_millis().min(u32::MAX as u128) as u32;
+        self.record(BuildRecord {
+            ms,
+            timed_out: true,
+        });
+    }
+
+    fn record(&self, record: BuildRecord) {
+        let records = {
+            let mut state = self.lock();
+            state.records.push_back(record);
+            while state.records.len() > self.config.max_samples {
+                state.records.pop_front();
+            }
+
+            let recent_timeouts = state
+                .records
+                .iter()
+                .rev()
+                .take(RECENT_BUILDS)
+                .filter(|r| r.timed_out)
+                .count();
+            if recent_timeouts >= MAX_RECENT_TIMEOUTS {
+                info!(
+                    "{} of the last {} circuit builds timed out; resetting build timeout",
+                    recent_timeouts, RECENT_BUILDS
+                );
+                state.records.clear();
+            }
+
+            let timeout = estimate(&self.config, &state.records);
+            if timeout != state.timeout {
+                debug!("Circuit build timeout now {:?}", timeout);
+                state.timeout = timeout;
+            }
+            state.records.iter().copied().collect::<Vec<_>>()
+        };
+        self.persist(&records);
+    }
+
+    fn persist(&self, records: &[BuildRecord]) {
+        let Some(store) = &self.store else {
+            return;
+        };
+        // A lost history only means re-learning, so a failing store isn't fatal
+        let result = serde_json::to_string(records)
+            .map_err(Into::into)
+            .and_then(|json| store.store(STATE_KEY, &json));
+        if let Err(e) = result {
+            warn!("Failed to persist build time history: {}", e);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl TimeoutEstimator for BuildTimeoutEstimator {
+    fn circuit_build_timeout(&self, length: usize) -> Duration {
+        // The estimate is for three hops; shorter circuits get a share of it
+        self.timeout() * length.clamp(1, 3) as u32 / 3
+    }
+}
+
+/// Timeout for `records` under `config`, or the initial timeout if too few
+fn estimate(config: &BuildTimeoutConfig, records: &VecDeque<BuildRecord>) -> Duration {
+    let initial = Duration::from_millis(config.initial_ms);
+    if records.len() < config.min_samples.max(1) {
+        return initial;
+    }
+    match fit_pareto(records) {
+        Some((xm, alpha)) => {
+            let quantile = f64::from(config.quantile_percent.min(99)) / 100.0;
+            let ms = xm / (1.0 - quantile).powf(1.0 / alpha);
+            if !ms.is_finite() {
+                return initial;
+            }
+            Duration::from_millis(ms as u64).clamp(
+                Duration::from_millis(config.min_ms.min(config.initial_ms)),
+                initial,
+            )
+        }
+        None => initial,
+    }
+}
+
+/// Fit a Pareto distribution, returning its mode `Xm` (ms) and shape `alpha`
+///
+/// `Xm` is the count-weighted mean of the most frequent histogram bins, and
+/// `alpha` the maximum likelihood estimate with timed-out builds censored
+/// at the timeout they hit.
+fn fit_pareto(records: &VecDeque<BuildRecord>) -> Option<(f64, f64)> {
+    let mut bins: HashMap<u32, usize> = HashMap::new();
+    for record in records.iter().filter(|r| !r.timed_out) {
+        *bins.entry(record.ms / BIN_WIDTH_MS).or_default() += 1;
+    }
+    let mut bins: Vec<(u32, usize)> = bins.into_iter().collect();
+    bins.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    bins.truncate(NUM_MODES);
+
+    let weight: usize = bins.iter().map(|(_, count)| count).sum();
+    if weight == 0 {
+        return None;
+    }
+    let xm = bins
+        .iter()
+        .map(|(bin, count)| {
+            (f64::from(*bin * BIN_WIDTH_MS) + f64::from(BIN_WIDTH_MS) / 2.0) * *count as f64
+        })
+        .sum::<f64>()
+        / weight as f64;
+
+    let n = records.len() as f64;
+    let log_sum: f64 = records.iter().map(|r| f64::from(r.ms).max(xm).ln()).sum();
+    // No spread at all gives an infinite alpha, i.e. a timeout of exactly Xm
+    let alpha = n / (log_sum - n * xm.ln()).max(0.0);
+    (alpha > 0.0).then_some((xm, alpha))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+
+    #[test]
+    fn test_timeout_adapts_to_observed_builds() {
+        let estimator = BuildTimeoutEstimator::default();
+        for _ in 0..19 {
+            estimator.record_success(Duration::from_millis(1_000));
+        }
+        assert_eq!(estimator.timeout(), Duration::from_secs(60));
+
+        for _ in 0..61 {
+            estimator.record_success(Duration::from_millis(1_000));
+        }
+        for _ in 0..20 {
+            estimator.record_success(Duration::from_millis(5_000));
+        }
+        let timeout = estimator.timeout();
+        assert!(timeout > Duration::from_millis(1_500), "{:?}", timeout);
+        assert!(timeout < Duration::from_millis(5_000), "{:?}", timeout);
+        assert_eq!(estimator.circuit_build_timeout(3), timeout);
+    }
+
+    #[test]
+    fn test_repeated_timeouts_reset_and_history_persists() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        let estimator = BuildTimeoutEstimator::default().with_store(store.clone());
+        for _ in 0..30 {
+            estimator.record_success(Duration::from_millis(2_000));
+        }
+        let adapted = estimator.timeout();
+        assert!(adapted < Duration::from_secs(60));
+
+        let reloaded = BuildTimeoutEstimator::default().with_store(store);
+        assert_eq!(reloaded.sample_count(), 30);
+        assert_eq!(reloaded.timeout(), adapted);
+
+        for _ in 0..MAX_RECENT_TIMEOUTS {
+            reloaded.record_timeout();
+        }
+        assert_eq!(reloaded.sample_count(), 0);
+        assert_eq!(reloaded.timeout(), Duration::from_secs(60));
+    }
+}
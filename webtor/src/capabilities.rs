@@ -0,0 +1,240 @@
+//! Detection of the browser features the client depends on
+//!
+//! The wasm build runs in whatever environment loads it: a browser with
+//! WebRTC disabled by policy, a worker without `localStorage`, an embedded
+//! webview without `RTCPeerConnection`. Rather than failing deep inside a
+//! transport, the client probes the environment up front. [`Capabilities`]
+//! records which APIs exist and [`CapabilityReport`] lists the features that
+//! are unavailable because of it, with a reason an app can show to users.
+
+use crate::config::BridgeType;
+use crate::error::{Result, TorError};
+use serde::Serialize;
+
+/// Web APIs present in the current environment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    /// Running as the browser (wasm) build
+    pub browser: bool,
+    pub websocket: bool,
+    /// `RTCPeerConnection`
+    pub webrtc: bool,
+    pub local_storage: bool,
+    pub indexed_db: bool,
+    /// `SharedArrayBuffer`, which also needs a cross-origin isolated page
+    pub shared_array_buffer: bool,
+    /// Origin private file system (`navigator.storage.getDirectory`)
+    pub opfs: bool,
+}
+
+/// A client feature that can't be used here, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnavailableFeature {
+    pub feature: String,
+    pub reason: String,
+}
+
+/// Detected capabilities plus the features they rule out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityReport {
+    pub capabilities: Capabilities,
+    pub unavailable: Vec<UnavailableFeature>,
+}
+
+impl Capabilities {
+    /// Probe the current environment
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "wasm32")]
+        {
+            Self::detect_browser()
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            Self::native()
+        }
+    }
+
+    /// Native builds use sockets and files directly instead of Web APIs
+    pub fn native() -> Self {
+        Self {
+            browser: false,
+            websocket: true,
+            webrtc: false,
+            local_storage: false,
+            indexed_db: false,
+            shared_array_buffer: false,
+            opfs: false,
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn detect_browser() -> Self {
+        let global = js_sys::global();
+        let local_storage = web_sys::window()
+            .and_then(|window| window.local_storage().ok().flatten())
+            .is_some();
+        let cross_origin_isolated = js_sys::Reflect::get(&global, &"crossOriginIsolated".into())
+            .map(|v| v.is_truthy())
+            .unwrap_or(false);
+        let opfs = js_sys::Reflect::get(&global, &"navigator".into())
+            .and_then(|navigator| js_sys::Reflect::get(&navigator, &"storage".into()))
+            .and_then(|storage| js_sys::Reflect::get(&storage, &"getDirectory".into()))
+            .map(|get_directory| get_directory.is_function())
+            .unwrap_or(false);
+
+        Self {
+            browser: true,
+            websocket: has_global(&global, "WebSocket"),
+            webrtc: has_global(&global, "RTCPeerConnection"),
+            local_storage,
+            indexed_db: has_global(&global, "indexedDB"),
+            shared_array_buffer: cross_origin_isolated && has_global(&global, "SharedArrayBuffer"),
+            opfs,
+        }
+    }
+
+    /// Why `bridge` can't be used here, if it can't
+    pub fn bridge_unavailable(&self, bridge: &BridgeType) -> Option<String> {
+        match bridge {
+            BridgeType::Snowflake { .. } => self.snowflake_unavailable(),
+            BridgeType::SnowflakeWebRtc { .. } => self.snowflake_webrtc_unavailable(),
+            BridgeType::WebTunnel { .. } => self.webtunnel_unavailable(),
+            BridgeType::Obfs4 { .. } => self.obfs4_unavailable(),
+            BridgeType::Meek { .. } | BridgeType::Custom { .. } => None,
+        }
+    }
+
+    /// Fail with a configuration error naming the missing API if `bridge` can't be used
+    pub fn check_bridge(&self, bridge: &BridgeType) -> Result<()> {
+        match self.bridge_unavailable(bridge) {
+            Some(reason) => Err(TorError::Configuration(reason)),
+            None => Ok(()),
+        }
+    }
+
+    /// Report every feature this environment rules out
+    pub fn report(&self) -> CapabilityReport {
+        let checks = [
+            ("snowflake", self.snowflake_unavailable()),
+            ("snowflakeWebRtc", self.snowflake_webrtc_unavailable()),
+            ("webTunnel", self.webtunnel_unavailable()),
+            ("obfs4", self.obfs4_unavailable()),
+            ("direct", self.direct_unavailable()),
+            ("persistentState", self.persistent_state_unavailable()),
+        ];
+        let unavailable = checks
+            .into_iter()
+            .filter_map(|(feature, reason)| {
+                reason.map(|reason| UnavailableFeature {
+                    feature: feature.to_string(),
+                    reason,
+                })
+            })
+            .collect();
+        CapabilityReport {
+            capabilities: *self,
+            unavailable,
+        }
+    }
+
+    fn snowflake_unavailable(&self) -> Option<String> {
+        (!self.websocket)
+            .then(|| "Snowflake needs WebSocket, which this environment lacks".to_string())
+    }
+
+    fn snowflake_webrtc_unavailable(&self) -> Option<String> {
+        if !self.browser {
+            Some("Snowflake over WebRTC is only available in the browser build".to_string())
+        } else if !self.webrtc {
+            Some(
+                "Snowflake over WebRTC needs RTCPeerConnection, which is missing or disabled"
+                    .to_string(),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn webtunnel_unavailable(&self) -> Option<String> {
+        self.browser
+            .then(|| "WebTunnel is not supported in the browser build yet".to_string())
+    }
+
+    fn obfs4_unavailable(&self) -> Option<String> {
+        if self.browser {
+            Some("obfs4 needs raw TCP sockets, which browsers don't provide".to_string())
+        } else if !cfg!(feature = "obfs4") {
+            Some("obfs4 support was not compiled in (enable the `obfs4` feature)".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Why connecting straight to relays isn't possible here, if it isn't
+    pub fn direct_unavailable(&self) -> Option<String> {
+        self.browser.then(|| {
+            "Direct relay connections need raw TCP sockets, which browsers don't provide"
+                .to_string()
+        })
+    }
+
+    /// Whether state such as guard selection can outlive the page
+    pub fn persistent_state(&self) -> bool {
+        self.persistent_state_unavailable().is_none()
+    }
+
+    fn persistent_state_unavailable(&self) -> Option<String> {
+        (self.browser && !self.local_storage).then(|| {
+            "localStorage is unavailable, so guard selection is kept in memory only".to_string()
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn has_global(global: &js_sys::Object, name: &str) -> bool {
+    js_sys::Reflect::get(global, &name.into())
+        .map(|v| !v.is_undefined() && !v.is_null())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_explains_missing_webrtc() {
+        let caps = Capabilities {
+            browser: true,
+            websocket: true,
+            webrtc: false,
+            local_storage: true,
+            indexed_db: true,
+            shared_array_buffer: false,
+            opfs: false,
+        };
+        let webrtc = BridgeType::SnowflakeWebRtc {
+            broker_url: "https://broker.example/".to_string(),
+            fallbacks: Vec::new(),
+        };
+        assert!(caps.check_bridge(&webrtc).is_err());
+        assert!(caps
+            .check_bridge(&BridgeType::Snowflake {
+                url: "wss://snowflake.example/".to_string(),
+            })
+            .is_ok());
+
+        let features: Vec<String> = caps
+            .report()
+            .unavailable
+            .into_iter()
+            .map(|f| f.feature)
+            .collect();
+        assert_eq!(
+            features,
+            vec!["snowflakeWebRtc", "webTunnel", "obfs4", "direct"]
+        );
+    }
+}
@@ -1,29 +1,47 @@
 //! Tor circuit management
 
-use crate::config::MAX_CIRCUITS_PER_ISOLATION_KEY;
-use crate::error::{Result, TorError};
+use crate::bootstrap::BootstrapProgress;
+use crate::build_timeout::BuildTimeoutEstimator;
+use crate::config::{
+    DEFAULT_MAX_CIRCUIT_DIRTINESS, DEFAULT_MAX_STREAMS_PER_CIRCUIT, MAX_CIRCUITS_PER_ISOLATION_KEY,
+    PREBUILD_EXIT_PORT,
+};
+use crate::error::{Result, StreamEndReason, TorError, TorErrorKind};
+use crate::events::{CircuitEvent, CircuitEvents};
+use crate::guard::GuardCandidate;
+use crate::hs_cache::HsDescCache;
 use crate::isolation::IsolationKey;
-use crate::relay::{Relay, RelayManager};
+use crate::metrics::Metrics;
+use crate::reachability::ReachabilityTracker;
+use crate::relay::{flags, NodeSet, Relay, RelayCriteria, RelayManager, RelayQuery};
+use crate::retry::with_timeout;
+use crate::storage::MemoryStore;
 use crate::time::Instant;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use crate::traffic::{CircuitTraffic, CountedStream, TorStream, TrafficCounter, TrafficStats};
+use crate::vanguards::VanguardManager;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::Duration;
 use tokio::sync::RwLock;
-use tor_linkspec::HasRelayIds;
+use tor_linkspec::{HasRelayIds, OwnedCircTarget};
 use tor_proto::ccparams::{
     Algorithm, CongestionControlParamsBuilder, CongestionWindowParamsBuilder,
-    FixedWindowParamsBuilder, RoundTripEstimatorParamsBuilder,
+    FixedWindowParamsBuilder, RoundTripEstimatorParamsBuilder, VegasParamsBuilder,
 };
 use tor_proto::channel::Channel;
 use tor_proto::circuit::CircParameters;
 use tor_proto::client::circuit::TimeoutEstimator;
-use tor_proto::client::stream::DataStream;
+use tor_proto::client::stream::StreamParameters;
 use tor_proto::{CellCount, ClientTunnel, FlowCtrlParameters};
 use tor_units::Percentage;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 /// Circuit status
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum CircuitStatus {
     Creating,
     Ready,
@@ -32,6 +50,38 @@ pub enum CircuitStatus {
     Closed,
 }
 
+/// What a circuit is for
+///
+/// Only general circuits take streams to the Internet; the others end at a
+/// relay the client talks to itself, or at an onion service.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CircuitPurpose {
+    /// Exit traffic, including pinned and reserved circuits
+    #[default]
+    General,
+    /// Fetching onion service descriptors from an HSDir
+    HsDir,
+    /// Introducing the client to an onion service at one of its
+    /// introduction points
+    Introduction,
+    /// Meeting an onion service at a rendezvous point, then carrying the
+    /// streams to it
+    Rendezvous,
+}
+
+impl CircuitPurpose {
+    /// What the last hop of such a circuit is
+    fn last_hop_role(self) -> &'static str {
+        match self {
+            Self::General => "Exit",
+            Self::HsDir => "HSDir",
+            Self::Introduction => "Introduction point",
+            Self::Rendezvous => "Rendezvous point",
+        }
+    }
+}
+
 /// Tor circuit information
 pub struct Circuit {
     pub id: String,
@@ -39,13 +89,38 @@ pub struct Circuit {
     pub created_at: Instant,
     pub last_used: Instant,
     pub relays: Vec<Relay>,
+    pub purpose: CircuitPurpose,
     pub(crate) internal_circuit: Option<Arc<ClientTunnel>>,
     /// Stream isolation key - circuits are bound to a single isolation key
     /// None means the circuit is unassigned and can be bound to any key
     pub isolation_key: Option<IsolationKey>,
+    /// When the circuit was first handed out for a stream
+    pub dirty_since: Option<Instant>,
+    /// Middles of the extra conflux legs to the same exit (empty for a
+    /// single-path circuit)
+    pub conflux_middles: Vec<Relay>,
+    /// Time taken to add each hop, in path order
+    pub hop_build_times: Vec<Duration>,
+    /// Streams send data right after BEGIN, without waiting for CONNECTED
+    pub optimistic_data: bool,
+    /// Counts as too dirty for new streams whatever its age
+    retired: bool,
+    stream_stats: Mutex<StreamStats>,
+    /// Bytes moved by this circuit's streams
+    pub(crate) traffic: TrafficCounter,
     _private: (),
 }
 
+/// Stream outcomes on one circuit, updated through a shared reference
+#[derive(Debug, Default)]
+struct StreamStats {
+    opened: u64,
+    failed: u64,
+    /// Smoothed BEGIN to CONNECTED time
+    rtt: Option<Duration>,
+    last_error: Option<String>,
+}
+
 impl std::fmt::Debug for Circuit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Circuit")
@@ -54,8 +129,14 @@ impl std::fmt::Debug for Circuit {
             .field("created_at", &self.created_at)
             .field("last_used", &self.last_used)
             .field("relays", &self.relays)
+            .field("purpose", &self.purpose)
             .field("internal_circuit", &self.internal_circuit.is_some())
             .field("isolation_key", &self.isolation_key)
+            .field("dirty_since", &self.dirty_since)
+            .field("conflux_middles", &self.conflux_middles)
+            .field("hop_build_times", &self.hop_build_times)
+            .field("optimistic_data", &self.optimistic_data)
+            .field("retired", &self.retired)
             .finish()
     }
 }
@@ -69,8 +150,16 @@ impl Circuit {
             created_at: now,
             last_used: now,
             relays: Vec::new(),
+            purpose: CircuitPurpose::General,
             internal_circuit,
             isolation_key: None,
+            dirty_since: None,
+            conflux_middles: Vec::new(),
+            hop_build_times: Vec::new(),
+            optimistic_data: false,
+            retired: false,
+            stream_stats: Mutex::default(),
+            traffic: TrafficCounter::new(),
             _private: (),
         }
     }
@@ -90,8 +179,23 @@ impl Circuit {
         self.last_used.elapsed()
     }
 
+    /// Record that the circuit was handed out for a stream
     pub fn update_last_used(&mut self) {
         self.last_used = Instant::now();
+        self.dirty_since.get_or_insert(self.last_used);
+    }
+
+    /// Whether the circuit first carried traffic at least `max_dirtiness` ago
+    pub fn is_dirty_for(&self, max_dirtiness: Duration) -> bool {
+        self.retired
+            || self
+                .dirty_since
+                .is_some_and(|since| since.elapsed() >= max_dirtiness)
+    }
+
+    /// Take no new streams from now on, leaving open ones be
+    pub fn retire(&mut self) {
+        self.retired = true;
     }
 
     pub fn is_ready(&self) -> bool {
@@ -102,15 +206,54 @@ impl Circuit {
         self.status == CircuitStatus::Failed
     }
 
+    /// Whether the exit relay's policy allows streams to `port`
+    pub fn exit_allows_port(&self, port: u16) -> bool {
+        self.relays
+            .last()
+            .is_some_and(|exit| exit.allows_exit_port(port))
+    }
+
     pub fn is_closed(&self) -> bool {
         self.status == CircuitStatus::Closed
     }
 
+    /// Whether the underlying tunnel is gone (DESTROY received or channel lost)
+    pub fn tunnel_closed(&self) -> bool {
+        self.internal_circuit
+            .as_ref()
+            .is_some_and(|tunnel| tunnel.is_closed())
+    }
+
+    /// Display information for each hop, in path order
+    pub fn relay_info(&self) -> Vec<CircuitRelayInfo> {
+        let last = self.relays.len().saturating_sub(1);
+        self.relays
+            .iter()
+            .enumerate()
+            .map(|(idx, relay)| {
+                let mut info = CircuitRelayInfo::for_hop(idx, relay);
+                if idx > 0 {
+                    let role = if idx == last {
+                        self.purpose.last_hop_role()
+                    } else {
+                        "Middle"
+                    };
+                    info.role = role.to_string();
+                }
+                info
+            })
+            .collect()
+    }
+
     /// Begin a TCP stream to the given host and port through this circuit.
     ///
     /// The hostname resolution is performed by the exit relay, so you can
     /// pass hostnames instead of IP addresses.
-    pub async fn begin_stream(&self, host: &str, port: u16) -> Result<DataStream> {
+    /// With [`optimistic_data`](Self::optimistic_data) the stream is returned
+    /// as soon as BEGIN is sent, and a refusal by the exit surfaces on the
+    /// first read instead. The stream's traffic is counted towards this
+    /// circuit's.
+    pub async fn begin_stream(&self, host: &str, port: u16) -> Result<TorStream> {
         let tunnel = self
             .internal_circuit
             .as_ref()
@@ -118,18 +261,219 @@ impl Circuit {
 
         debug!("Beginning stream to {}:{}", host, port);
 
-        let stream = tunnel
-            .begin_stream(host, port, None)
+        let mut params = StreamParameters::new();
+        params.optimistic(self.optimistic_data);
+        let started = Instant::now();
+        let result = tunnel
+            .begin_stream(host, port, Some(params))
             .await
-            .map_err(|e| TorError::Internal(format!("Failed to begin stream: {}", e)))?;
+            .map_err(|e| self.stream_error(tunnel, "beginning stream", e));
+        // An optimistic stream doesn't wait for CONNECTED, so there's no round trip to time
+        let rtt = (!self.optimistic_data).then(|| started.elapsed());
+        self.record_stream(rtt, &result);
+        let stream = result?;
 
         info!("Stream established to {}:{}", host, port);
-        Ok(stream)
+        Ok(CountedStream::new(stream, &self.traffic))
+    }
+
+    /// Open a directory stream to the last hop (BEGIN_DIR), which answers
+    /// HTTP requests for directory documents itself
+    pub async fn begin_dir_stream(&self) -> Result<TorStream> {
+        let tunnel = self
+            .internal_circuit
+            .as_ref()
+            .ok_or_else(|| TorError::Internal("No internal circuit available".to_string()))?;
+
+        debug!("Beginning directory stream on {}", self.id);
+        let started = Instant::now();
+        let result = tunnel
+            .clone()
+            .begin_dir_stream()
+            .await
+            .map_err(|e| self.stream_error(tunnel, "beginning directory stream", e));
+        self.record_stream(Some(started.elapsed()), &result);
+        Ok(CountedStream::new(result?, &self.traffic))
+    }
+
+    /// Look up `host`'s A/AAAA records with a RESOLVE cell to the exit
+    ///
+    /// The exit does the lookup, so nothing reaches the local resolver.
+    pub async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let tunnel = self
+            .internal_circuit
+            .as_ref()
+            .ok_or_else(|| TorError::Internal("No internal circuit available".to_string()))?;
+
+        debug!("Resolving {} on {}", host, self.id);
+        let addrs = tunnel
+            .resolve(host)
+            .await
+            .map_err(|e| self.stream_error(tunnel, "resolving", e))?;
+        if addrs.is_empty() {
+            return Err(TorError::StreamEnded(StreamEndReason::ResolveFailed));
+        }
+        Ok(addrs)
+    }
+
+    /// Look up the hostnames for `addr` (a PTR query) via the exit
+    pub async fn resolve_ptr(&self, addr: IpAddr) -> Result<Vec<String>> {
+        let tunnel = self
+            .internal_circuit
+            .as_ref()
+            .ok_or_else(|| TorError::Internal("No internal circuit available".to_string()))?;
+
+        debug!("Reverse-resolving {} on {}", addr, self.id);
+        tunnel
+            .resolve_ptr(addr)
+            .await
+            .map_err(|e| self.stream_error(tunnel, "reverse-resolving", e))
+    }
+
+    fn stream_error(&self, tunnel: &ClientTunnel, action: &str, e: tor_proto::Error) -> TorError {
+        if tunnel.is_closed() {
+            TorError::circuit_closed(format!("{} while {}: {}", self.id, action, e))
+        } else if let Some(reason) = StreamEndReason::from_proto(&e) {
+            TorError::StreamEnded(reason)
+        } else if matches!(e, tor_proto::Error::ResolveError(_)) {
+            // The exit answered RESOLVED with an error instead of addresses
+            TorError::StreamEnded(StreamEndReason::ResolveFailed)
+        } else {
+            TorError::Internal(format!("Failed {}: {}", action, e))
+        }
+    }
+
+    fn record_stream<T>(&self, rtt: Option<Duration>, result: &Result<T>) {
+        let mut stats = self
+            .stream_stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match result {
+            Ok(_) => {
+                stats.opened += 1;
+                // Smoothed like TCP's SRTT, weighting each new sample 1/8
+                if let Some(sample) = rtt {
+                    stats.rtt = Some(match stats.rtt {
+                        Some(rtt) => (rtt * 7 + sample) / 8,
+                        None => sample,
+                    });
+                }
+            }
+            Err(e) => {
+                stats.failed += 1;
+                stats.last_error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Bytes moved by this circuit's streams so far
+    pub fn traffic(&self) -> CircuitTraffic {
+        let traffic = self.traffic.snapshot();
+        CircuitTraffic {
+            circuit_id: self.id.clone(),
+            bytes_read: traffic.bytes_read,
+            bytes_written: traffic.bytes_written,
+            open_streams: self.traffic.open_streams(),
+        }
+    }
+
+    /// Streams on this circuit that are still open
+    pub fn open_streams(&self) -> usize {
+        self.traffic.open_streams()
+    }
+
+    /// Build timing and stream counters for this circuit
+    pub fn metrics(&self) -> CircuitMetrics {
+        let stats = self
+            .stream_stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        CircuitMetrics {
+            circuit_id: self.id.clone(),
+            purpose: self.purpose,
+            hop_build_ms: self
+                .hop_build_times
+                .iter()
+                .map(|time| time.as_millis() as u64)
+                .collect(),
+            rtt_ms: stats.rtt.map(|rtt| rtt.as_millis() as u64),
+            streams_opened: stats.opened,
+            streams_failed: stats.failed,
+            last_error: stats.last_error.clone(),
+        }
+    }
+
+    /// What the circuit is, where it goes and what it carried
+    pub fn report(&self) -> CircuitReport {
+        let traffic = self.traffic.snapshot();
+        CircuitReport {
+            id: self.id.clone(),
+            purpose: self.purpose,
+            state: self.status,
+            hops: self.relay_info(),
+            age_ms: self.age().as_millis() as u64,
+            idle_ms: self.time_since_last_use().as_millis() as u64,
+            bytes_read: traffic.bytes_read,
+            bytes_written: traffic.bytes_written,
+            open_streams: self.traffic.open_streams(),
+        }
+    }
+}
+
+/// A circuit build that has announced hops; reports the circuit closed if
+/// the build is dropped midway (it lost a build race, or the caller gave up)
+struct PendingBuild<'a> {
+    events: &'a CircuitEvents,
+    circuit_id: &'a str,
+    done: bool,
+}
+
+impl PendingBuild<'_> {
+    /// The build ended and reported its outcome itself
+    fn finish(mut self) {
+        self.done = true;
+    }
+}
+
+impl Drop for PendingBuild<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.events
+                .circuit_closed(self.circuit_id, "build abandoned");
+        }
     }
 }
 
+/// Performance counters for one circuit
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitMetrics {
+    pub circuit_id: String,
+    pub purpose: CircuitPurpose,
+    /// Milliseconds taken to add each hop (create, then each extend)
+    pub hop_build_ms: Vec<u64>,
+    /// Smoothed milliseconds from BEGIN to CONNECTED; a round trip to the
+    /// exit plus its connect to the destination, so an upper bound on RTT.
+    /// Optimistic streams don't wait for CONNECTED and aren't sampled
+    pub rtt_ms: Option<u64>,
+    pub streams_opened: u64,
+    pub streams_failed: u64,
+    /// Most recent stream failure
+    pub last_error: Option<String>,
+}
+
 // Helper to create default circuit parameters
 pub fn make_circ_params() -> Result<CircParameters> {
+    circ_params(true)
+}
+
+/// Circuit parameters, with Vegas congestion control (prop 324) unless
+/// `congestion_control` is false
+///
+/// Values are the consensus defaults. Vegas is only negotiated, over ntor v3,
+/// with hops that advertise `FlowCtrl=2`; every other hop falls back to the
+/// fixed SENDME window.
+pub fn circ_params(congestion_control: bool) -> Result<CircParameters> {
     // 1. Fixed Window Params (Fallback)
     let fixed_window_params = FixedWindowParamsBuilder::default()
         .circ_window_start(1000)
@@ -140,12 +484,12 @@ pub fn make_circ_params() -> Result<CircParameters> {
 
     // 2. Congestion Window Params
     let cwnd_params = CongestionWindowParamsBuilder::default()
-        .cwnd_init(1000)
-        .cwnd_inc_pct_ss(Percentage::new(100))
-        .cwnd_inc(1)
+        .cwnd_init(4 * 31)
+        .cwnd_inc_pct_ss(Percentage::new(50))
+        .cwnd_inc(31)
         .cwnd_inc_rate(1)
-        .cwnd_min(100)
-        .cwnd_max(1000)
+        .cwnd_min(31)
+        .cwnd_max(i32::MAX as u32)
         .sendme_inc(31)
         .build()
         .map_err(|e| TorError::Internal(format!("Failed to build cwnd params: {}", e)))?;
@@ -154,14 +498,28 @@ pub fn make_circ_params() -> Result<CircParameters> {
     let rtt_params = RoundTripEstimatorParamsBuilder::default()
         .ewma_cwnd_pct(Percentage::new(50))
         .ewma_max(10)
-        .ewma_ss_max(10)
-        .rtt_reset_pct(Percentage::new(50))
+        .ewma_ss_max(2)
+        .rtt_reset_pct(Percentage::new(100))
         .build()
         .map_err(|e| TorError::Internal(format!("Failed to build rtt params: {}", e)))?;
 
     // 4. Congestion Control Params
+    let alg = if congestion_control {
+        let vegas_params = VegasParamsBuilder::default()
+            // alpha, beta, delta, gamma and the slow start cap for exit circuits
+            .cell_in_queue_params((3 * 62, 4 * 62, 5 * 62, 3 * 62, 600).into())
+            .ss_cwnd_max(5000)
+            .cwnd_full_gap(4444)
+            .cwnd_full_min_pct(Percentage::new(25))
+            .cwnd_full_per_cwnd(1)
+            .build()
+            .map_err(|e| TorError::Internal(format!("Failed to build vegas params: {}", e)))?;
+        Algorithm::Vegas(vegas_params)
+    } else {
+        Algorithm::FixedWindow(fixed_window_params)
+    };
     let ccontrol = CongestionControlParamsBuilder::default()
-        .alg(Algorithm::FixedWindow(fixed_window_params.clone()))
+        .alg(alg)
         .fixed_window_params(fixed_window_params)
         .cwnd_params(cwnd_params)
         .rtt_params(rtt_params)
@@ -180,20 +538,363 @@ pub fn make_circ_params() -> Result<CircParameters> {
     Ok(CircParameters::new(true, ccontrol, flow_ctrl))
 }
 
-pub(crate) struct SimpleTimeoutEstimator;
-impl TimeoutEstimator for SimpleTimeoutEstimator {
-    fn circuit_build_timeout(&self, _length: usize) -> Duration {
-        Duration::from_secs(60)
+/// How the first hop (the bridge) relates to the consensus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryMode {
+    /// The bridge is unlisted, so it can't be held to the Guard flag; the
+    /// second hop must carry it instead
+    Bridge,
+    /// The bridge is also a listed relay and acts as an ordinary guard
+    Guard,
+}
+
+impl EntryMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryMode::Bridge => "bridge",
+            EntryMode::Guard => "guard",
+        }
+    }
+}
+
+impl std::fmt::Display for EntryMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
+/// The next of `items` in turn, counting turns with `cursor`
+fn take_turn<T: Clone>(items: &[T], cursor: &AtomicUsize) -> T {
+    let turn = cursor.fetch_add(1, Ordering::Relaxed);
+    items[turn % items.len()].clone()
+}
+
+/// Placeholder relay for the bridge at the far end of `channel`
+///
+/// The channel target has the bridge's identity but not a usable address
+/// (Snowflake has none) or ntor key, so only the fingerprint is real.
+fn bridge_placeholder(channel: &Channel) -> Relay {
+    let bridge_fingerprint = channel
+        .target()
+        .rsa_identity()
+        .map(|id| hex::encode(id.as_bytes()))
+        .unwrap_or_else(|| "0000000000000000000000000000000000000000".to_string());
+
+    Relay::new(
+        bridge_fingerprint,
+        "Snowflake".to_string(), // More meaningful name for the proxy
+        "0.0.0.0".to_string(),   // Placeholder - will be shown as "Snowflake (WebRTC)" in UI
+        0,
+        std::collections::HashSet::new(),
+        "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+    )
+}
+
+/// Look the bridge up in the consensus to decide the entry mode
+///
+/// A listed bridge is replaced by its consensus entry so the subnet and
+/// family checks see its real address and family.
+pub fn resolve_entry(relay_manager: &RelayManager, bridge: Relay) -> (Relay, EntryMode) {
+    match relay_manager.get_relay(&bridge.fingerprint) {
+        Some(listed) => {
+            if !listed.flags.contains(flags::GUARD) {
+                warn!(
+                    "Bridge {} is a listed relay without the Guard flag",
+                    listed.nickname
+                );
+            }
+            (listed.clone(), EntryMode::Guard)
+        }
+        None => (bridge, EntryMode::Bridge),
+    }
+}
+
+/// Target for an ntor handshake with the first hop, if its onion key is known
+///
+/// A listed entry has its key from the microdescriptor; an unlisted bridge
+/// only has one if it was configured. Without a key the first hop falls back
+/// to CREATE_FAST, which relies on the link TLS alone.
+pub fn first_hop_target(
+    entry: &Relay,
+    mode: EntryMode,
+    bridge_ntor_key: Option<&str>,
+) -> Result<Option<OwnedCircTarget>> {
+    let mut entry = entry.clone();
+    match (mode, bridge_ntor_key) {
+        (EntryMode::Guard, _) => {}
+        (EntryMode::Bridge, Some(key)) => entry.ntor_onion_key = Some(key.to_string()),
+        (EntryMode::Bridge, None) => return Ok(None),
+    }
+    entry.as_circ_target().map(Some)
+}
+
+/// Relays eligible as layer-2 guards (Fast, Stable and Guard)
+fn layer2_candidates() -> RelayQuery {
+    RelayQuery::new()
+        .with_flag(flags::FAST)
+        .with_flag(flags::STABLE)
+        .with_flag(flags::GUARD)
+}
+
+/// Choose the middle and exit relays for a circuit entering at `first_hop`
+///
+/// The exit is picked first since its criteria are the most restrictive. No
+/// two hops may share a /16 (IPv6 /32) network or a declared family. Behind
+/// an unlisted bridge the middle must have the Guard flag.
+pub fn select_path(
+    relay_manager: &RelayManager,
+    first_hop: &Relay,
+    mode: EntryMode,
+    exit_criteria: RelayCriteria,
+) -> Result<(Relay, Relay)> {
+    select_path_with_layer2(relay_manager, first_hop, mode, exit_criteria, &[])
+}
+
+/// Like [`select_path`], but with the middle pinned to one of `layer2` if non-empty
+///
+/// The middle is picked first here, since the pinned set is small and the
+/// exit must then avoid it.
+pub fn select_path_with_layer2(
+    relay_manager: &RelayManager,
+    first_hop: &Relay,
+    mode: EntryMode,
+    exit_criteria: RelayCriteria,
+    layer2: &[String],
+) -> Result<(Relay, Relay)> {
+    if !layer2.is_empty() {
+        let middle_criteria = crate::relay::selection::middle_relays()
+            .among_fingerprints(layer2)
+            .not_related_to(first_hop);
+        let middle = relay_manager
+            .select_relay(&middle_criteria)
+            .map_err(|_| TorError::relay_selection("No usable layer-2 guard for this path"))?;
+        let exit_criteria = exit_criteria
+            .not_related_to(first_hop)
+            .not_related_to(&middle);
+        debug!("Exit relay criteria: {:?}", exit_criteria);
+        let exit = relay_manager.select_relay(&exit_criteria)?;
+        return Ok((middle, exit));
+    }
+
+    let exit_criteria = exit_criteria.not_related_to(first_hop);
+    debug!("Exit relay criteria: {:?}", exit_criteria);
+    let exit = relay_manager.select_relay(&exit_criteria)?;
+
+    let mut middle_criteria = crate::relay::selection::middle_relays()
+        .not_related_to(first_hop)
+        .not_related_to(&exit);
+    if mode == EntryMode::Bridge {
+        middle_criteria = middle_criteria.with_flag(flags::GUARD);
+    }
+    debug!("Middle relay criteria: {:?}", middle_criteria);
+    let middle = relay_manager.select_relay(&middle_criteria)?;
+
+    Ok((middle, exit))
+}
+
+/// Pick the middle of a circuit to `last_hop`, one of `layer2` if non-empty
+///
+/// `last_hop` is where the circuit ends rather than an exit, so only its
+/// relation to the other hops is checked.
+pub fn select_middle_to(
+    relay_manager: &RelayManager,
+    first_hop: &Relay,
+    mode: EntryMode,
+    last_hop: &Relay,
+    layer2: &[String],
+) -> Result<Relay> {
+    check_direct_path(first_hop, last_hop)?;
+    let mut criteria = crate::relay::selection::middle_relays()
+        .not_related_to(first_hop)
+        .not_related_to(last_hop);
+    if !layer2.is_empty() {
+        criteria = criteria.among_fingerprints(layer2);
+    } else if mode == EntryMode::Bridge {
+        criteria = criteria.with_flag(flags::GUARD);
+    }
+    relay_manager.select_relay(&criteria).map_err(|_| {
+        TorError::relay_selection(format!(
+            "No usable middle for a path to {}",
+            last_hop.nickname
+        ))
+    })
+}
+
+/// Check a circuit from `first_hop` straight to `last_hop`, with no middle
+///
+/// There is nothing to select, so the two hops' relation is the whole
+/// check.
+pub fn check_direct_path(first_hop: &Relay, last_hop: &Relay) -> Result<()> {
+    if last_hop.is_related_to(first_hop) {
+        return Err(TorError::relay_selection(format!(
+            "{} shares a subnet or family with the first hop",
+            last_hop.nickname
+        )));
+    }
+    Ok(())
+}
+
+/// Pick the middle of a second conflux leg to the same exit
+///
+/// Legs share the first hop and exit but need distinct middles, unrelated
+/// to every other hop, so they take different paths through the network.
+pub fn select_conflux_middle(
+    relay_manager: &RelayManager,
+    first_hop: &Relay,
+    mode: EntryMode,
+    middle: &Relay,
+    exit: &Relay,
+) -> Result<Relay> {
+    let mut criteria = crate::relay::selection::middle_relays()
+        .not_related_to(first_hop)
+        .not_related_to(middle)
+        .not_related_to(exit);
+    if mode == EntryMode::Bridge {
+        criteria = criteria.with_flag(flags::GUARD);
+    }
+    relay_manager.select_relay(&criteria)
+}
+
+/// Look up a caller-chosen path and check it is one Tor would build
+///
+/// `fingerprints` is the middle and exit, or the first hop, middle and exit;
+/// a first hop must be the bridge the client is connected to. `$` prefixes
+/// and lowercase hex are accepted. The middle needs the Fast flag, the exit
+/// the Exit flag and not BadExit, both need a microdescriptor, and no two
+/// hops may share a /16 or a family.
+pub fn resolve_explicit_path(
+    relay_manager: &RelayManager,
+    first_hop: &Relay,
+    fingerprints: &[String],
+) -> Result<(Relay, Relay)> {
+    let normalized: Vec<&str> = fingerprints
+        .iter()
+        .map(|fp| fp.trim().trim_start_matches('$'))
+        .collect();
+    let (middle_fp, exit_fp) = match normalized.as_slice() {
+        [middle, exit] => (*middle, *exit),
+        [entry, middle, exit] => {
+            if !entry.eq_ignore_ascii_case(&first_hop.fingerprint) {
+                return Err(TorError::configuration(format!(
+                    "Circuits start at the connected bridge {}, not {}",
+                    first_hop.fingerprint, entry
+                )));
+            }
+            (*middle, *exit)
+        }
+        _ => {
+            return Err(TorError::configuration(format!(
+                "A circuit path names 2 or 3 relays, got {}",
+                fingerprints.len()
+            )))
+        }
+    };
+
+    let lookup = |fingerprint: &str| {
+        relay_manager
+            .relays
+            .iter()
+            .find(|relay| relay.fingerprint.eq_ignore_ascii_case(fingerprint))
+            .cloned()
+            .ok_or_else(|| {
+                TorError::relay_selection(format!("Relay {} is not in the consensus", fingerprint))
+            })
+    };
+    let middle = lookup(middle_fp)?;
+    let exit = lookup(exit_fp)?;
+
+    if !middle.flags.contains(flags::FAST) {
+        return Err(TorError::relay_selection(format!(
+            "Middle {} lacks the Fast flag",
+            middle.nickname
+        )));
+    }
+    if !exit.flags.contains(flags::EXIT) || exit.flags.contains(flags::BAD_EXIT) {
+        return Err(TorError::relay_selection(format!(
+            "{} is not a usable exit (needs Exit, not BadExit)",
+            exit.nickname
+        )));
+    }
+    for relay in [&middle, &exit] {
+        if relay.ntor_onion_key.is_none() {
+            return Err(TorError::relay_selection(format!(
+                "No microdescriptor for {}, so it can't be extended to",
+                relay.nickname
+            )));
+        }
+    }
+    for (a, b) in [(first_hop, &middle), (first_hop, &exit), (&middle, &exit)] {
+        if a.is_related_to(b) {
+            return Err(TorError::relay_selection(format!(
+                "{} and {} are the same relay, share a subnet or are in one family",
+                a.nickname, b.nickname
+            )));
+        }
+    }
+    Ok((middle, exit))
+}
+
+/// How [`CircuitManager`] picks the hops after the bridge
+#[derive(Clone, Copy)]
+enum PathSpec<'a> {
+    /// A middle and an exit chosen at random by weight
+    Random,
+    /// The middle and exit a caller named; see [`resolve_explicit_path`]
+    Explicit(&'a [String]),
+    /// A random middle, then this relay, which needn't be an exit, for a
+    /// circuit with this purpose
+    EndingAt(&'a Relay, CircuitPurpose),
+    /// This relay right after the bridge, with no middle, for a single
+    /// onion service that doesn't hide its location
+    Direct(&'a Relay, CircuitPurpose),
+}
+
 /// Circuit manager for handling multiple circuits
 #[derive(Clone)]
 pub struct CircuitManager {
     circuits: Arc<RwLock<Vec<Arc<RwLock<Circuit>>>>>,
     relay_manager: Arc<RwLock<RelayManager>>,
     channel: Arc<RwLock<Option<Arc<Channel>>>>,
+    /// Further channels to the same bridge; new circuits take them in turn
+    /// with the primary channel
+    extra_channels: Arc<Mutex<Vec<Arc<Channel>>>>,
+    /// Turn counter choosing the channel of the next circuit
+    next_channel: Arc<AtomicUsize>,
     prebuild_in_progress: Arc<AtomicBool>,
+    metrics: Metrics,
+    exclude_exit_countries: HashSet<String>,
+    /// If set, exits are only chosen among these (`ExitNodes`)
+    exit_nodes: Option<NodeSet>,
+    progress: BootstrapProgress,
+    reachability: ReachabilityTracker,
+    vanguards: Option<Arc<RwLock<VanguardManager>>>,
+    /// Onion service descriptors fetched over circuits from this manager
+    hs_descriptors: Arc<RwLock<HsDescCache>>,
+    bridge_ntor_key: Option<String>,
+    /// Bumped by [`new_identity`](Self::new_identity); circuits whose build
+    /// started under an older identity are retired as soon as they finish
+    identity: Arc<AtomicU64>,
+    /// Exits of retired circuits that new circuits should avoid if they can
+    avoided_exits: Arc<Mutex<HashSet<String>>>,
+    /// How long after first use a circuit still takes new streams
+    max_dirtiness: Duration,
+    build_timeouts: BuildTimeoutEstimator,
+    /// Negotiate congestion control with hops that support it
+    congestion_control: bool,
+    /// Add a second leg to circuits whose exit supports conflux
+    conflux: bool,
+    /// Open streams optimistically on circuits whose exit supports it
+    optimistic_data: bool,
+    /// Open streams a circuit may carry before new ones go elsewhere (0: no cap)
+    max_streams_per_circuit: usize,
+    /// Builds raced when a stream finds no usable circuit
+    parallel_builds: usize,
+    events: CircuitEvents,
+    /// Failed circuit (and conflux leg) builds, by the step that failed
+    build_failures: Arc<Mutex<BTreeMap<String, usize>>>,
+    /// Parent of every circuit's traffic counter
+    traffic: TrafficCounter,
 }
 
 impl CircuitManager {
@@ -205,8 +906,176 @@ impl CircuitManager {
             circuits: Arc::new(RwLock::new(Vec::new())),
             relay_manager,
             channel,
+            extra_channels: Arc::default(),
+            next_channel: Arc::default(),
             prebuild_in_progress: Arc::new(AtomicBool::new(false)),
+            metrics: Metrics::default(),
+            exclude_exit_countries: HashSet::new(),
+            exit_nodes: None,
+            progress: BootstrapProgress::default(),
+            reachability: ReachabilityTracker::default(),
+            vanguards: None,
+            hs_descriptors: Arc::new(RwLock::new(HsDescCache::load(Arc::new(MemoryStore::new())))),
+            bridge_ntor_key: None,
+            identity: Arc::new(AtomicU64::new(0)),
+            avoided_exits: Arc::default(),
+            max_dirtiness: Duration::from_millis(DEFAULT_MAX_CIRCUIT_DIRTINESS),
+            build_timeouts: BuildTimeoutEstimator::default(),
+            congestion_control: true,
+            conflux: false,
+            optimistic_data: true,
+            max_streams_per_circuit: DEFAULT_MAX_STREAMS_PER_CIRCUIT,
+            parallel_builds: 1,
+            events: CircuitEvents::default(),
+            build_failures: Arc::default(),
+            traffic: TrafficCounter::default(),
+        }
+    }
+
+    /// Never pick exits in these countries (`"??"` also excludes unknown ones)
+    pub fn with_excluded_exit_countries<I, S>(mut self, countries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.exclude_exit_countries = RelayCriteria::new()
+            .without_countries(countries)
+            .exclude_countries;
+        self
+    }
+
+    /// Only pick exits in `nodes`; an empty list leaves exits unrestricted
+    pub fn with_exit_nodes(mut self, nodes: NodeSet) -> Self {
+        self.exit_nodes = (!nodes.is_empty()).then_some(nodes);
+        self
+    }
+
+    /// Exit selection criteria shared by the early port check and the exit hop
+    fn exit_criteria(&self, exit_port: Option<u16>) -> RelayCriteria {
+        let mut criteria =
+            crate::relay::selection::exit_relays().without_countries(&self.exclude_exit_countries);
+        if let Some(nodes) = &self.exit_nodes {
+            criteria = criteria.within_nodes(nodes.clone());
         }
+        if let Some(port) = exit_port {
+            criteria = criteria.with_exit_port(port);
+        }
+        criteria
+    }
+
+    /// Record circuit build outcomes into a shared metrics registry
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Report extension outcomes to a tracker shared with the relay manager
+    pub fn with_reachability(mut self, tracker: ReachabilityTracker) -> Self {
+        self.reachability = tracker;
+        self
+    }
+
+    /// Pin the middle hop of reserved (long-lived) circuits to layer-2 guards
+    pub fn with_vanguards(mut self, vanguards: Arc<RwLock<VanguardManager>>) -> Self {
+        self.vanguards = Some(vanguards);
+        self
+    }
+
+    /// Keep fetched onion service descriptors in `cache`
+    pub fn with_hs_descriptor_cache(mut self, cache: HsDescCache) -> Self {
+        self.hs_descriptors = Arc::new(RwLock::new(cache));
+        self
+    }
+
+    /// Use ntor with an unlisted bridge whose onion key (hex) is known
+    pub fn with_bridge_ntor_key(mut self, key: String) -> Self {
+        self.bridge_ntor_key = Some(key);
+        self
+    }
+
+    /// Stop attaching new streams to a circuit this long after its first use
+    pub fn with_max_dirtiness(mut self, max_dirtiness: Duration) -> Self {
+        self.max_dirtiness = max_dirtiness;
+        self
+    }
+
+    /// Bound builds by a timeout learned from earlier build times
+    pub fn with_build_timeouts(mut self, build_timeouts: BuildTimeoutEstimator) -> Self {
+        self.build_timeouts = build_timeouts;
+        self
+    }
+
+    /// Negotiate congestion control (prop 324) with hops advertising
+    /// `FlowCtrl=2`, or use fixed SENDME windows everywhere when disabled
+    pub fn with_congestion_control(mut self, enabled: bool) -> Self {
+        self.congestion_control = enabled;
+        self
+    }
+
+    /// Build exit circuits as two linked legs through different middles
+    /// (conflux, prop 329) when the exit supports it
+    ///
+    /// Cells are scheduled across both legs and reordered by sequence number
+    /// before they reach a stream, so a slow or collapsed leg doesn't stall
+    /// the circuit. Needs congestion control.
+    pub fn with_conflux(mut self, enabled: bool) -> Self {
+        self.conflux = enabled;
+        self
+    }
+
+    /// Attach new streams elsewhere once a circuit has `max` open (0 for no cap)
+    ///
+    /// Requests go to another circuit for the same isolation key, built if
+    /// needed. Streams opening concurrently may overshoot the cap slightly.
+    pub fn with_max_streams_per_circuit(mut self, max: usize) -> Self {
+        self.max_streams_per_circuit = max;
+        self
+    }
+
+    /// Race `builds` circuit builds whenever a stream has no usable circuit,
+    /// keeping the first to finish and abandoning the rest
+    ///
+    /// Only the winner's build time is learned, which leans the adaptive
+    /// build timeout towards faster builds.
+    pub fn with_parallel_builds(mut self, builds: usize) -> Self {
+        self.parallel_builds = builds.max(1);
+        self
+    }
+
+    /// Send stream data before CONNECTED on circuits whose exit accepts it
+    pub fn with_optimistic_data(mut self, enabled: bool) -> Self {
+        self.optimistic_data = enabled;
+        self
+    }
+
+    /// Whether streams on a circuit ending at `exit` open optimistically
+    fn optimistic_data_on(&self, exit: &Relay) -> bool {
+        self.optimistic_data && exit.supports_optimistic_data()
+    }
+
+    /// Emit circuit lifecycle events to subscribers of a shared handle
+    pub fn with_events(mut self, events: CircuitEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Whether new streams may be attached to `circuit`
+    fn takes_new_streams(&self, circuit: &Circuit) -> bool {
+        circuit.purpose == CircuitPurpose::General
+            && circuit.is_ready()
+            && !circuit.is_dirty_for(self.max_dirtiness)
+            && !self.is_full(circuit)
+    }
+
+    /// Whether `circuit` carries as many open streams as it may
+    fn is_full(&self, circuit: &Circuit) -> bool {
+        self.max_streams_per_circuit != 0 && circuit.open_streams() >= self.max_streams_per_circuit
+    }
+
+    /// Report hops reached to the client's bootstrap watchdog
+    pub fn with_bootstrap_progress(mut self, progress: BootstrapProgress) -> Self {
+        self.progress = progress;
+        self
     }
 
     /// Create a new circuit, optionally binding it to an isolation key
@@ -214,12 +1083,62 @@ impl CircuitManager {
     /// If an isolation key is provided, the circuit will be bound to it
     /// BEFORE being added to the circuit list, preventing races where
     /// another request could steal the unassigned circuit.
+    ///
+    /// If `exit_port` is given, only exits whose policy allows that port are
+    /// considered, and the call fails before building anything if none does.
     pub async fn create_circuit_with_isolation(
         &self,
         isolation_key: Option<IsolationKey>,
+        exit_port: Option<u16>,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        let result = self
+            .build_circuit(isolation_key, exit_port, false, PathSpec::Random)
+            .await;
+        self.metrics.record_circuit(result.is_ok());
+        result
+    }
+
+    /// Build a circuit for a stream that is waiting on it, racing
+    /// [`parallel_builds`](Self::with_parallel_builds) builds
+    ///
+    /// Fails only if every build fails, with the last error.
+    async fn create_circuit_for_stream(
+        &self,
+        isolation_key: Option<IsolationKey>,
+        exit_port: u16,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        if self.parallel_builds <= 1 {
+            return self
+                .create_circuit_with_isolation(isolation_key, Some(exit_port))
+                .await;
+        }
+
+        info!("Racing {} circuit builds", self.parallel_builds);
+        let builds = (0..self.parallel_builds).map(|_| {
+            Box::pin(self.create_circuit_with_isolation(isolation_key.clone(), Some(exit_port)))
+        });
+        // Dropping the losers cancels their builds and closes their tunnels
+        let (circuit, losers) = futures::future::select_ok(builds).await?;
+        debug!(
+            "Circuit {} won the build race; abandoning {} builds",
+            circuit.read().await.id,
+            losers.len()
+        );
+        Ok(circuit)
+    }
+
+    /// Build a circuit from the connected bridge, with the middle and last
+    /// hop chosen as `spec` says
+    async fn build_circuit(
+        &self,
+        isolation_key: Option<IsolationKey>,
+        exit_port: Option<u16>,
+        use_layer2: bool,
+        spec: PathSpec<'_>,
     ) -> Result<Arc<RwLock<Circuit>>> {
         let circuit_id = format!("circuit_{}", uuid::Uuid::new_v4());
         info!("Creating new circuit: {}", circuit_id);
+        let identity = self.identity.load(Ordering::SeqCst);
 
         // Log relay manager state
         let relay_manager = self.relay_manager.read().await;
@@ -232,149 +1151,193 @@ impl CircuitManager {
             return Err(TorError::Internal("No relays available".to_string()));
         }
 
-        let channel_guard = self.channel.read().await;
-        let channel = channel_guard
-            .as_ref()
-            .ok_or_else(|| TorError::Internal("Channel not established".to_string()))?
-            .clone();
-        drop(channel_guard);
-
-        // Create pending tunnel
-        let (pending_tunnel, reactor) = channel
-            .new_tunnel(Arc::new(SimpleTimeoutEstimator) as Arc<dyn TimeoutEstimator>)
-            .await
-            .map_err(|e| TorError::Internal(format!("Failed to create pending tunnel: {}", e)))?;
-
-        #[cfg(target_arch = "wasm32")]
-        wasm_bindgen_futures::spawn_local(async move {
-            if let Err(e) = reactor.run().await {
-                error!("Circuit reactor finished with error: {}", e);
+        if let Some(port) = exit_port {
+            let relay_manager = self.relay_manager.read().await;
+            if relay_manager
+                .select_relays(&self.exit_criteria(Some(port)))
+                .is_err()
+            {
+                let reason = match (&self.exit_nodes, self.exclude_exit_countries.is_empty()) {
+                    (Some(_), _) => " among the allowed exit nodes".to_string(),
+                    (None, false) => " outside the excluded countries".to_string(),
+                    (None, true) => String::new(),
+                };
+                return Err(TorError::relay_selection(format!(
+                    "No exit relay{} allows connections to port {}",
+                    reason, port
+                )));
             }
-        });
-
-        #[cfg(not(target_arch = "wasm32"))]
-        tokio::spawn(async move {
-            if let Err(e) = reactor.run().await {
-                error!("Circuit reactor finished with error: {}", e);
-            }
-        });
+        }
 
-        // First hop (Bridge) - FAST handshake
-        let params = make_circ_params()?;
-        let tunnel = pending_tunnel
-            .create_firsthop_fast(params)
-            .await
-            .map_err(|e| TorError::Internal(format!("Failed to create first hop: {}", e)))?;
-
-        info!("First hop created (FAST)");
-
-        // Construct Relay object for the bridge from the channel target
-        // Note: The channel target might not have all relay info (like ntor key for fast handshake),
-        // but we construct a best-effort representation for the circuit info.
-        let channel_target = channel.target();
-        let bridge_fingerprint = channel_target
-            .rsa_identity()
-            .map(|id| hex::encode(id.as_bytes()))
-            .unwrap_or_else(|| "0000000000000000000000000000000000000000".to_string());
-
-        // We don't have the real address easily accessible in string format from OwnedChanTarget without some work,
-        // or the ntor key if it wasn't known.
-        // For visual consistency in the circuit list, we create a placeholder if needed.
-        // For Snowflake, we use WebRTC so there's no traditional IP address.
-        let bridge_relay = Relay::new(
-            bridge_fingerprint.clone(),
-            "Snowflake".to_string(), // More meaningful name for the proxy
-            "0.0.0.0".to_string(),   // Placeholder - will be shown as "Snowflake (WebRTC)" in UI
-            0,
-            std::collections::HashSet::new(),
-            "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
-        );
+        let channel = self.next_channel().await?;
 
-        // Select relays
+        // Select the whole path before building, so every hop can be checked
+        // against the others for shared subnets and families, and the first
+        // hop's handshake can depend on whether the bridge is listed
         let relay_manager = self.relay_manager.read().await;
+        let (bridge_relay, entry_mode) =
+            resolve_entry(&relay_manager, bridge_placeholder(&channel));
         info!(
-            "Selecting relays from {} available",
-            relay_manager.relays.len()
+            "Selecting relays from {} available ({} entry)",
+            relay_manager.relays.len(),
+            entry_mode
         );
-
-        // Middle
-        // Ensure we don't select the bridge as middle
-        let middle_criteria =
-            crate::relay::selection::middle_relays().without_fingerprint(&bridge_fingerprint);
-        debug!("Middle relay criteria: {:?}", middle_criteria);
-
-        let middle = match relay_manager.select_relay(&middle_criteria) {
-            Ok(r) => r,
+        let layer2 = match (&self.vanguards, use_layer2) {
+            (Some(vanguards), true) => {
+                let candidates: Vec<GuardCandidate> = relay_manager
+                    .query(&layer2_candidates())
+                    .into_iter()
+                    .map(GuardCandidate::from)
+                    .collect();
+                let mut vanguards = vanguards.write().await;
+                vanguards.update(&candidates);
+                vanguards.guards().fingerprints()
+            }
+            _ => Vec::new(),
+        };
+        let selected = match spec {
+            PathSpec::Explicit(fingerprints) => {
+                resolve_explicit_path(&relay_manager, &bridge_relay, fingerprints)
+                    .map(|(middle, exit)| (Some(middle), exit))
+            }
+            PathSpec::EndingAt(last_hop, _) => {
+                select_middle_to(&relay_manager, &bridge_relay, entry_mode, last_hop, &layer2)
+                    .map(|middle| (Some(middle), last_hop.clone()))
+            }
+            PathSpec::Direct(last_hop, _) => {
+                check_direct_path(&bridge_relay, last_hop).map(|()| (None, last_hop.clone()))
+            }
+            PathSpec::Random => {
+                let select = |exit_criteria| {
+                    select_path_with_layer2(
+                        &relay_manager,
+                        &bridge_relay,
+                        entry_mode,
+                        exit_criteria,
+                        &layer2,
+                    )
+                };
+                let avoided_exits = self.avoided_exits();
+                if avoided_exits.is_empty() {
+                    select(self.exit_criteria(exit_port))
+                } else {
+                    select(
+                        self.exit_criteria(exit_port)
+                            .without_fingerprints(avoided_exits),
+                    )
+                    .or_else(|_| {
+                        debug!("No path avoids the previous identity's exits; allowing them");
+                        select(self.exit_criteria(exit_port))
+                    })
+                }
+                .map(|(middle, exit)| (Some(middle), exit))
+            }
+        };
+        let (middle, exit) = match selected {
+            Ok(path) => path,
             Err(e) => {
                 error!(
-                    "Failed to select middle relay: {} (available: {})",
+                    "Failed to select path: {} (available: {})",
                     e,
                     relay_manager.relays.len()
                 );
-                return Err(e);
+                return Err(self.build_failed("path_selection", e));
             }
         };
-        let middle_target = middle.as_circ_target()?;
-
-        info!(
-            "Extending to middle: {} (fp={})",
-            middle.nickname,
-            &middle.fingerprint[..8.min(middle.fingerprint.len())]
-        );
-        let params = make_circ_params()?;
-        tunnel
-            .as_single_circ()
-            .map_err(|e| {
-                TorError::Internal(format!(
-                    "Failed to get single circ for middle extend: {}",
-                    e
-                ))
-            })?
-            .extend(&middle_target, params)
+        drop(relay_manager);
+        let first_hop =
+            first_hop_target(&bridge_relay, entry_mode, self.bridge_ntor_key.as_deref())?;
+
+        let path: Vec<&Relay> = std::iter::once(&bridge_relay)
+            .chain(middle.as_ref())
+            .chain(std::iter::once(&exit))
+            .collect();
+        let pending = PendingBuild {
+            events: &self.events,
+            circuit_id: &circuit_id,
+            done: false,
+        };
+        let (mut tunnel, hop_build_times) = match self
+            .build_leg(&circuit_id, &channel, first_hop.as_ref(), &path)
             .await
-            .map_err(|e| TorError::Internal(format!("Failed to extend to middle: {}", e)))?;
-
-        // Exit
-        // Ensure we don't select bridge or middle as exit
-        let exit_criteria = crate::relay::selection::exit_relays()
-            .without_fingerprint(&bridge_fingerprint)
-            .without_fingerprint(&middle.fingerprint);
-        debug!("Exit relay criteria: {:?}", exit_criteria);
-
-        let exit = match relay_manager.select_relay(&exit_criteria) {
-            Ok(r) => r,
+        {
+            Ok(built) => built,
             Err(e) => {
-                error!(
-                    "Failed to select exit relay: {} (available: {})",
-                    e,
-                    relay_manager.relays.len()
-                );
+                pending.finish();
+                self.events
+                    .circuit_closed(&circuit_id, format!("build failed: {}", e));
                 return Err(e);
             }
         };
-        let exit_target = exit.as_circ_target()?;
-
-        info!(
-            "Extending to exit: {} (fp={})",
-            exit.nickname,
-            &exit.fingerprint[..8.min(exit.fingerprint.len())]
-        );
-        let params = make_circ_params()?;
-        tunnel
-            .as_single_circ()
-            .map_err(|e| {
-                TorError::Internal(format!("Failed to get single circ for exit extend: {}", e))
-            })?
-            .extend(&exit_target, params)
-            .await
-            .map_err(|e| TorError::Internal(format!("Failed to extend to exit: {}", e)))?;
-
         info!("Circuit established successfully");
 
+        let mut conflux_middles = Vec::new();
+        // An explicit path is built exactly as given, without a second leg
+        let conflux_middle = middle.as_ref().filter(|_| {
+            self.conflux
+                && self.congestion_control
+                && !use_layer2
+                && matches!(spec, PathSpec::Random)
+                && exit.supports_conflux()
+                && exit.supports_congestion_control()
+        });
+        if let Some(middle) = conflux_middle {
+            let leg_middle = {
+                let relay_manager = self.relay_manager.read().await;
+                select_conflux_middle(&relay_manager, &bridge_relay, entry_mode, middle, &exit)
+            };
+            let leg = match leg_middle {
+                Ok(leg_middle) => self
+                    .build_leg(
+                        &circuit_id,
+                        &channel,
+                        first_hop.as_ref(),
+                        &[&bridge_relay, &leg_middle, &exit],
+                    )
+                    .await
+                    .map(|(leg, _)| (leg_middle, leg)),
+                Err(e) => Err(e),
+            };
+            match leg {
+                Ok((leg_middle, leg)) => {
+                    // Once linking starts the tunnel is multi-path for good, so
+                    // a failure here fails the circuit rather than the leg
+                    let linked = match tunnel.link_tunnels(vec![leg]).await {
+                        Ok(linked) => linked,
+                        Err(e) => {
+                            let e = self.build_failed(
+                                "conflux_link",
+                                TorError::Internal(format!("Failed to link conflux legs: {}", e)),
+                            );
+                            pending.finish();
+                            self.events
+                                .circuit_closed(&circuit_id, format!("build failed: {}", e));
+                            return Err(e);
+                        }
+                    };
+                    info!(
+                        "Linked {} conflux legs via {} and {}",
+                        linked, middle.nickname, leg_middle.nickname
+                    );
+                    if linked > 1 {
+                        conflux_middles.push(leg_middle);
+                    }
+                }
+                Err(e) => warn!("No second conflux leg, keeping a single path: {}", e),
+            }
+        }
+
         let mut circuit = Circuit::new(circuit_id.clone(), Some(Arc::new(tunnel)));
+        circuit.optimistic_data = self.optimistic_data_on(&exit);
+        if let PathSpec::EndingAt(_, purpose) | PathSpec::Direct(_, purpose) = spec {
+            circuit.purpose = purpose;
+        }
 
         // Store relays
-        circuit.relays = vec![bridge_relay, middle, exit];
+        circuit.relays = path.into_iter().cloned().collect();
+        circuit.conflux_middles = conflux_middles;
+        circuit.hop_build_times = hop_build_times;
+        circuit.traffic = self.traffic.child();
         circuit.status = CircuitStatus::Ready;
 
         // Bind isolation key BEFORE adding to list to prevent races
@@ -382,11 +1345,28 @@ impl CircuitManager {
             circuit.set_isolation_key(key);
         }
 
+        // The caller that asked for it may still use it, but nothing else will
+        if self.identity.load(Ordering::SeqCst) != identity {
+            info!(
+                "Identity changed while building {}; retiring it",
+                circuit_id
+            );
+            circuit.status = CircuitStatus::Closed;
+        }
+
         info!(
             "Circuit {} created with {} relays",
             circuit_id,
             circuit.relays.len()
         );
+        pending.finish();
+        self.events.emit(CircuitEvent::CircuitBuilt {
+            circuit_id: circuit_id.clone(),
+            relays: circuit.relay_info(),
+        });
+        if circuit.is_closed() {
+            self.events.circuit_closed(&circuit_id, "new identity");
+        }
 
         let circuit_arc = Arc::new(RwLock::new(circuit));
 
@@ -397,9 +1377,239 @@ impl CircuitManager {
         Ok(circuit_arc)
     }
 
+    /// Build one leg within the learned build timeout, recording how long it took
+    ///
+    /// `path` is the bridge, middle and exit, in order, or the bridge and
+    /// last hop of a direct circuit.
+    async fn build_leg(
+        &self,
+        circuit_id: &str,
+        channel: &Arc<Channel>,
+        first_hop: Option<&OwnedCircTarget>,
+        path: &[&Relay],
+    ) -> Result<(ClientTunnel, Vec<Duration>)> {
+        let timeout = self.build_timeouts.timeout();
+        let started = Instant::now();
+        let built = with_timeout(
+            timeout,
+            "Circuit build",
+            self.build_hops(circuit_id, channel, first_hop, path),
+        )
+        .await;
+        match &built {
+            Ok(_) => self.build_timeouts.record_success(started.elapsed()),
+            Err(e) if matches!(e.kind(), TorErrorKind::Timeout) => {
+                self.build_timeouts.record_timeout();
+                self.record_build_failure("timeout");
+            }
+            Err(_) => {}
+        }
+        built
+    }
+
+    /// Create the first hop and extend to the rest of `path`: the middle
+    /// and exit, or just the last hop of a direct circuit
+    ///
+    /// Returns the tunnel with the time taken to add each hop.
+    async fn build_hops(
+        &self,
+        circuit_id: &str,
+        channel: &Arc<Channel>,
+        first_hop: Option<&OwnedCircTarget>,
+        path: &[&Relay],
+    ) -> Result<(ClientTunnel, Vec<Duration>)> {
+        let bridge = path[0];
+        // Create pending tunnel
+        let (pending_tunnel, reactor) = channel
+            .new_tunnel(Arc::new(self.build_timeouts.clone()) as Arc<dyn TimeoutEstimator>)
+            .await
+            .map_err(|e| {
+                self.build_failed(
+                    "channel",
+                    TorError::Internal(format!("Failed to create pending tunnel: {}", e)),
+                )
+            })?;
+        let mut hop_times = Vec::with_capacity(3);
+
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(e) = reactor.run().await {
+                error!("Circuit reactor finished with error: {}", e);
+            }
+        });
+
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::spawn(async move {
+            if let Err(e) = reactor.run().await {
+                error!("Circuit reactor finished with error: {}", e);
+            }
+        });
+
+        // First hop (Bridge) - ntor (v3 if advertised) when its onion key is
+        // known, else FAST
+        let params = circ_params(self.congestion_control)?;
+        let started = Instant::now();
+        let (created, handshake) = match first_hop {
+            Some(target) => (
+                pending_tunnel.create_firsthop(target, params).await,
+                bridge.handshake_name(),
+            ),
+            None => (pending_tunnel.create_firsthop_fast(params).await, "FAST"),
+        };
+        let tunnel = created.map_err(|e| {
+            self.build_failed(
+                "first_hop",
+                TorError::Internal(format!("Failed to create first hop: {}", e)),
+            )
+        })?;
+        hop_times.push(started.elapsed());
+
+        info!("First hop created ({})", handshake);
+        self.progress.circuit_hop(1);
+        self.hop_reached(circuit_id, 1, bridge);
+
+        for (index, hop) in path.iter().enumerate().skip(1) {
+            let role = if index + 1 == path.len() {
+                "exit"
+            } else {
+                "middle"
+            };
+            let target = hop.as_circ_target()?;
+            info!(
+                "Extending to {}: {} (fp={}, {})",
+                role,
+                hop.nickname,
+                &hop.fingerprint[..8.min(hop.fingerprint.len())],
+                hop.handshake_name()
+            );
+            let params = circ_params(self.congestion_control)?;
+            let started = Instant::now();
+            let extended = tunnel
+                .as_single_circ()
+                .map_err(|e| {
+                    TorError::Internal(format!(
+                        "Failed to get single circ for {} extend: {}",
+                        role, e
+                    ))
+                })?
+                .extend(&target, params)
+                .await;
+            self.record_extend(hop, extended.is_ok());
+            extended.map_err(|e| {
+                self.build_failed(
+                    &format!("extend_{}", role),
+                    TorError::Internal(format!("Failed to extend to {}: {}", role, e)),
+                )
+            })?;
+            hop_times.push(started.elapsed());
+            let hops = (index + 1) as u8;
+            self.progress.circuit_hop(hops);
+            self.hop_reached(circuit_id, hops, hop);
+        }
+        let exit = path[path.len() - 1];
+        debug!(
+            "Exit {} uses {}",
+            exit.nickname,
+            if self.congestion_control && exit.supports_congestion_control() {
+                "congestion control (vegas)"
+            } else {
+                "fixed-window flow control"
+            }
+        );
+
+        Ok((tunnel, hop_times))
+    }
+
+    /// Count a failed build under `cause`, passing the error through
+    fn build_failed(&self, cause: &str, err: TorError) -> TorError {
+        self.record_build_failure(cause);
+        err
+    }
+
+    fn record_build_failure(&self, cause: &str) {
+        *self
+            .build_failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(cause.to_string())
+            .or_default() += 1;
+    }
+
+    fn hop_reached(&self, circuit_id: &str, hop: u8, relay: &Relay) {
+        self.events.emit(CircuitEvent::CircuitExtended {
+            circuit_id: circuit_id.to_string(),
+            hop,
+            relay: CircuitRelayInfo::for_hop(usize::from(hop) - 1, relay),
+        });
+    }
+
+    fn avoided_exits(&self) -> Vec<String> {
+        self.avoided_exits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Retire every circuit so later streams get freshly built ones
+    ///
+    /// Retired circuits are marked closed: streams already open on them run
+    /// to completion, but nothing new is attached and the next cleanup drops
+    /// them, along with their isolation bindings. Circuits still being built
+    /// are retired when they finish. With `avoid_previous_exits`, new circuits
+    /// avoid the retired circuits' exits where the network allows. Cached
+    /// onion service descriptors are dropped, as they tell which services
+    /// were visited. Returns the number of circuits retired.
+    pub async fn new_identity(&self, avoid_previous_exits: bool) -> usize {
+        self.identity.fetch_add(1, Ordering::SeqCst);
+
+        let circuits = self.circuits.read().await;
+        let mut retired = 0;
+        let mut exits = HashSet::new();
+        for circuit in circuits.iter() {
+            let mut circuit = circuit.write().await;
+            if circuit.is_closed() || circuit.is_failed() {
+                continue;
+            }
+            circuit.status = CircuitStatus::Closed;
+            self.events.circuit_closed(&circuit.id, "new identity");
+            retired += 1;
+            if let Some(exit) = circuit.relays.last() {
+                exits.insert(exit.fingerprint.clone());
+            }
+        }
+        drop(circuits);
+        self.hs_descriptors.write().await.clear();
+
+        let mut avoided = self
+            .avoided_exits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *avoided = if avoid_previous_exits {
+            exits
+        } else {
+            HashSet::new()
+        };
+        info!("New identity: retired {} circuits", retired);
+        retired
+    }
+
+    fn record_extend(&self, relay: &Relay, success: bool) {
+        if success {
+            self.reachability.record_success(&relay.fingerprint);
+        } else {
+            self.reachability.record_failure(&relay.fingerprint);
+        }
+    }
+
     /// Create a new circuit (unassigned, for prebuilding)
+    ///
+    /// Prebuilt circuits use exits that allow HTTPS, the port nearly every
+    /// request needs.
     pub async fn create_circuit(&self) -> Result<Arc<RwLock<Circuit>>> {
-        self.create_circuit_with_isolation(None).await
+        self.create_circuit_with_isolation(None, Some(PREBUILD_EXIT_PORT))
+            .await
     }
 
     /// Get a ready circuit (create one if none exist)
@@ -408,7 +1618,7 @@ impl CircuitManager {
         let circuits = self.circuits.read().await;
         for circuit in circuits.iter() {
             let circuit_read = circuit.read().await;
-            if circuit_read.is_ready() {
+            if self.takes_new_streams(&circuit_read) {
                 debug!("Found existing ready circuit: {}", circuit_read.id);
                 return Ok(circuit.clone());
             }
@@ -416,7 +1626,8 @@ impl CircuitManager {
         drop(circuits);
 
         // No ready circuit found, create a new one
-        self.create_circuit().await
+        self.create_circuit_for_stream(None, PREBUILD_EXIT_PORT)
+            .await
     }
 
     /// Get a ready circuit and mark it as used (updates last_used timestamp)
@@ -429,18 +1640,38 @@ impl CircuitManager {
         Ok(circuit)
     }
 
-    /// Get or create a circuit bound to the given isolation key
+    /// Get a ready circuit whose exit allows `port` (create one if none exist)
+    async fn get_ready_circuit_for_port(&self, port: u16) -> Result<Arc<RwLock<Circuit>>> {
+        {
+            let circuits = self.circuits.read().await;
+            for circuit in circuits.iter() {
+                let mut circuit_write = circuit.write().await;
+                if self.takes_new_streams(&circuit_write) && circuit_write.exit_allows_port(port) {
+                    debug!("Found existing ready circuit: {}", circuit_write.id);
+                    circuit_write.update_last_used();
+                    return Ok(circuit.clone());
+                }
+            }
+        }
+
+        let circuit = self.create_circuit_for_stream(None, port).await?;
+        circuit.write().await.update_last_used();
+        Ok(circuit)
+    }
+
+    /// Get or create a circuit bound to the given isolation key whose exit allows `port`
     ///
     /// This implements stream isolation by ensuring requests to different
     /// domains use different circuits, preventing cross-site correlation.
     pub async fn get_circuit_for_isolation_key(
         &self,
         key: Option<IsolationKey>,
+        port: u16,
     ) -> Result<Arc<RwLock<Circuit>>> {
         // If no isolation key, fall back to legacy behavior
         let key = match key {
             Some(k) => k,
-            None => return self.get_ready_circuit_and_mark_used().await,
+            None => return self.get_ready_circuit_for_port(port).await,
         };
 
         // 1. Look for a ready circuit already bound to this key
@@ -448,7 +1679,7 @@ impl CircuitManager {
             let circuits = self.circuits.read().await;
             for circuit in circuits.iter() {
                 let circuit_read = circuit.read().await;
-                if circuit_read.is_ready() {
+                if self.takes_new_streams(&circuit_read) && circuit_read.exit_allows_port(port) {
                     if let Some(ref circuit_key) = circuit_read.isolation_key {
                         if circuit_key == &key {
                             debug!(
@@ -472,7 +1703,10 @@ impl CircuitManager {
             let circuits = self.circuits.read().await;
             for circuit in circuits.iter() {
                 let mut circuit_write = circuit.write().await;
-                if circuit_write.is_ready() && circuit_write.isolation_key.is_none() {
+                if self.takes_new_streams(&circuit_write)
+                    && circuit_write.isolation_key.is_none()
+                    && circuit_write.exit_allows_port(port)
+                {
                     debug!(
                         "Binding unassigned circuit {} to isolation key {}",
                         circuit_write.id, key
@@ -494,7 +1728,9 @@ impl CircuitManager {
                         if let Some(ref circuit_key) = circuit_read.isolation_key {
                             return circuit_key == &key
                                 && !circuit_read.is_failed()
-                                && !circuit_read.is_closed();
+                                && !circuit_read.is_closed()
+                                && !circuit_read.is_dirty_for(self.max_dirtiness)
+                                && !self.is_full(&circuit_read);
                         }
                     }
                     false
@@ -509,6 +1745,9 @@ impl CircuitManager {
                         if circuit_key == &key
                             && !circuit_read.is_failed()
                             && !circuit_read.is_closed()
+                            && !circuit_read.is_dirty_for(self.max_dirtiness)
+                            && !self.is_full(&circuit_read)
+                            && (!circuit_read.is_ready() || circuit_read.exit_allows_port(port))
                         {
                             debug!(
                                 "At per-key limit, reusing circuit {} for {}",
@@ -528,7 +1767,7 @@ impl CircuitManager {
         // We pass the key to create_circuit_with_isolation so it's bound
         // BEFORE the circuit is added to the list, preventing races
         info!("Creating new circuit for isolation key {}", key);
-        let circuit = self.create_circuit_with_isolation(Some(key)).await?;
+        let circuit = self.create_circuit_for_stream(Some(key), port).await?;
         {
             let mut circuit_write = circuit.write().await;
             circuit_write.update_last_used();
@@ -536,94 +1775,521 @@ impl CircuitManager {
         Ok(circuit)
     }
 
-    /// Get circuit status information
-    pub async fn get_circuit_status(&self) -> CircuitStatusInfo {
-        let circuits = self.circuits.read().await;
-        let mut ready_count = 0;
-        let mut creating_count = 0;
-        let mut failed_count = 0;
-        let mut total_age = Duration::from_secs(0);
+    /// Get a circuit that has never carried a stream, for one request to `port`
+    ///
+    /// A prebuilt circuit that is still unused is claimed if there is one,
+    /// otherwise a new one is built. Either way it is bound to an isolation
+    /// key of its own, so no later request is ever assigned to it.
+    pub async fn get_fresh_circuit(&self, port: u16) -> Result<Arc<RwLock<Circuit>>> {
+        let key = IsolationKey(format!("fresh:{}", uuid::Uuid::new_v4()));
+        {
+            let circuits = self.circuits.read().await;
+            for circuit in circuits.iter() {
+                let mut circuit_write = circuit.write().await;
+                if self.takes_new_streams(&circuit_write)
+                    && circuit_write.isolation_key.is_none()
+                    && circuit_write.dirty_since.is_none()
+                    && circuit_write.exit_allows_port(port)
+                {
+                    debug!(
+                        "Claiming unused circuit {} for one request",
+                        circuit_write.id
+                    );
+                    circuit_write.set_isolation_key(key);
+                    circuit_write.update_last_used();
+                    return Ok(circuit.clone());
+                }
+            }
+        }
+
+        info!("Creating new circuit for one request");
+        let circuit = self.create_circuit_for_stream(Some(key), port).await?;
+        circuit.write().await.update_last_used();
+        Ok(circuit)
+    }
+
+    /// Get circuit status information
+    pub async fn get_circuit_status(&self) -> CircuitStatusInfo {
+        let circuits = self.circuits.read().await;
+        let mut ready_count = 0;
+        let mut creating_count = 0;
+        let mut failed_count = 0;
+        let mut conflux_count = 0;
+        let mut total_age = Duration::from_secs(0);
+        let mut circuit_metrics = Vec::with_capacity(circuits.len());
+
+        for circuit in circuits.iter() {
+            let circuit_read = circuit.read().await;
+            circuit_metrics.push(circuit_read.metrics());
+            match circuit_read.status {
+                CircuitStatus::Ready => {
+                    ready_count += 1;
+                    if !circuit_read.conflux_middles.is_empty() {
+                        conflux_count += 1;
+                    }
+                }
+                CircuitStatus::Creating => creating_count += 1,
+                CircuitStatus::Failed => failed_count += 1,
+                _ => {}
+            }
+            total_age += circuit_read.age();
+        }
+
+        let total_circuits = circuits.len();
+        let avg_age = if circuits.is_empty() {
+            Duration::from_secs(0)
+        } else {
+            total_age / total_circuits as u32
+        };
+        drop(circuits);
+
+        CircuitStatusInfo {
+            total_circuits,
+            ready_circuits: ready_count,
+            creating_circuits: creating_count,
+            failed_circuits: failed_count,
+            average_circuit_age: avg_age,
+            entry_mode: self.entry_mode().await,
+            conflux_circuits: conflux_count,
+            circuit_metrics,
+            build_failures: self
+                .build_failures
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        }
+    }
+
+    /// Counts and a [`CircuitReport`] for every circuit held
+    pub async fn status_report(&self) -> CircuitStatusReport {
+        let mut circuits = Vec::new();
+        for circuit in self.circuits.read().await.iter() {
+            circuits.push(circuit.read().await.report());
+        }
+        let status = self.get_circuit_status().await;
+        CircuitStatusReport {
+            summary: status.summary(),
+            total_circuits: status.total_circuits,
+            ready_circuits: status.ready_circuits,
+            creating_circuits: status.creating_circuits,
+            failed_circuits: status.failed_circuits,
+            entry_mode: status.entry_mode,
+            circuits,
+        }
+    }
+
+    /// Total traffic, and that of each circuit currently held
+    pub async fn traffic_stats(&self) -> TrafficStats {
+        let circuits = self.circuits.read().await;
+        let mut per_circuit = Vec::with_capacity(circuits.len());
+        for circuit in circuits.iter() {
+            per_circuit.push(circuit.read().await.traffic());
+        }
+        let total = self.traffic.snapshot();
+        TrafficStats {
+            bytes_read: total.bytes_read,
+            bytes_written: total.bytes_written,
+            open_streams: self.traffic.open_streams(),
+            circuits: per_circuit,
+        }
+    }
+
+    /// Spread new circuits over `chan` too, a further channel to the bridge
+    ///
+    /// Returns false, closing `chan`, if the primary channel is gone or
+    /// leads to a different bridge by now.
+    pub async fn add_channel(&self, chan: Arc<Channel>) -> bool {
+        let same_bridge = self.channel.read().await.as_ref().is_some_and(|primary| {
+            !primary.is_closing() && primary.target().rsa_identity() == chan.target().rsa_identity()
+        });
+        if !same_bridge {
+            chan.terminate();
+            return false;
+        }
+        self.extra_channels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(chan);
+        true
+    }
+
+    /// Close the further channels, e.g. once the primary one is lost
+    pub fn clear_channels(&self) {
+        let extra = std::mem::take(
+            &mut *self
+                .extra_channels
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        for chan in extra {
+            chan.terminate();
+        }
+    }
+
+    /// Open channels circuits are built on: the primary channel first, then
+    /// the further ones
+    pub async fn open_channels(&self) -> Vec<Arc<Channel>> {
+        let mut channels: Vec<Arc<Channel>> = self.channel.read().await.iter().cloned().collect();
+        let mut extra = self
+            .extra_channels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        extra.retain(|chan| !chan.is_closing());
+        channels.extend(extra.iter().cloned());
+        channels
+    }
+
+    /// Channel for the next circuit, taking the open channels in turn
+    async fn next_channel(&self) -> Result<Arc<Channel>> {
+        let channels = self.open_channels().await;
+        if self.channel.read().await.is_none() || channels.is_empty() {
+            return Err(TorError::Internal("Channel not established".to_string()));
+        }
+        Ok(take_turn(&channels, &self.next_channel))
+    }
+
+    /// Entry mode of circuits built on the current channel, once it's up
+    pub async fn entry_mode(&self) -> Option<EntryMode> {
+        let channel = self.channel.read().await.clone()?;
+        let relay_manager = self.relay_manager.read().await;
+        Some(resolve_entry(&relay_manager, bridge_placeholder(&channel)).1)
+    }
+
+    /// Update relay manager with new relay list
+    pub async fn update_relays(&self, new_relays: Vec<crate::relay::Relay>) {
+        let mut relay_manager = self.relay_manager.write().await;
+        relay_manager.update_relays(new_relays);
+    }
+
+    /// Synchronous version of update_relays for use when we already have a mutable reference
+    pub fn update_relay_list(&mut self, new_relays: Vec<crate::relay::Relay>) {
+        if let Ok(mut relay_manager) = self.relay_manager.try_write() {
+            relay_manager.update_relays(new_relays);
+        }
+    }
+
+    /// Get relay information from the most recent ready circuit
+    pub async fn get_circuit_relays(&self) -> Option<Vec<CircuitRelayInfo>> {
+        let circuits = self.circuits.read().await;
+        // Iterate in reverse to get the most recently created circuit
+        for circuit in circuits.iter().rev() {
+            let circuit_read = circuit.read().await;
+            if circuit_read.is_ready() && !circuit_read.relays.is_empty() {
+                return Some(circuit_read.relay_info());
+            }
+        }
+        None
+    }
+
+    /// Look up a circuit by ID
+    pub async fn get_circuit(&self, circuit_id: &str) -> Option<Arc<RwLock<Circuit>>> {
+        let circuits = self.circuits.read().await;
+        for circuit in circuits.iter() {
+            if circuit.read().await.id == circuit_id {
+                return Some(circuit.clone());
+            }
+        }
+        None
+    }
+
+    /// IDs of all ready circuits, oldest first
+    pub async fn ready_circuit_ids(&self) -> Vec<String> {
+        let circuits = self.circuits.read().await;
+        let mut ids = Vec::new();
+        for circuit in circuits.iter() {
+            let circuit_read = circuit.read().await;
+            if circuit_read.is_ready() {
+                ids.push(circuit_read.id.clone());
+            }
+        }
+        ids
+    }
+
+    /// Get a specific circuit for a stream to `port`, without falling back to any other
+    ///
+    /// Fails if the circuit is unknown or no longer ready, or if its exit
+    /// doesn't allow `port`.
+    pub async fn get_pinned_circuit(
+        &self,
+        circuit_id: &str,
+        port: u16,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        let circuit = self.get_circuit(circuit_id).await.ok_or_else(|| {
+            TorError::configuration(format!("Circuit {} does not exist", circuit_id))
+        })?;
+
+        let mut circuit_write = circuit.write().await;
+        if !circuit_write.is_ready() {
+            return Err(TorError::configuration(format!(
+                "Circuit {} is no longer usable ({:?})",
+                circuit_id, circuit_write.status
+            )));
+        }
+        if !circuit_write.exit_allows_port(port) {
+            return Err(TorError::relay_selection(format!(
+                "Exit of circuit {} does not allow port {}",
+                circuit_id, port
+            )));
+        }
+        circuit_write.update_last_used();
+        drop(circuit_write);
+        Ok(circuit)
+    }
+
+    /// Build a new circuit that ordinary requests will never be assigned to
+    ///
+    /// The circuit is bound to an isolation key of its own, so it is only
+    /// used through [`get_pinned_circuit`](Self::get_pinned_circuit).
+    ///
+    /// Such circuits can live and be used for a long time, so with
+    /// vanguards-lite enabled their middle hop is a layer-2 guard.
+    pub async fn create_reserved_circuit(&self) -> Result<Arc<RwLock<Circuit>>> {
+        let key = IsolationKey(format!("reserved:{}", uuid::Uuid::new_v4()));
+        let result = self
+            .build_circuit(Some(key), Some(PREBUILD_EXIT_PORT), true, PathSpec::Random)
+            .await;
+        self.metrics.record_circuit(result.is_ok());
+        result
+    }
+
+    /// Build a circuit through the given relays that only pinned clients use
+    ///
+    /// `fingerprints` names the middle and exit, optionally preceded by the
+    /// connected bridge; see [`resolve_explicit_path`] for the checks applied.
+    /// Like [`create_reserved_circuit`](Self::create_reserved_circuit), the
+    /// circuit is bound to an isolation key of its own.
+    pub async fn create_circuit_through(
+        &self,
+        fingerprints: &[String],
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        let key = IsolationKey(format!("manual:{}", uuid::Uuid::new_v4()));
+        let result = self
+            .build_circuit(Some(key), None, false, PathSpec::Explicit(fingerprints))
+            .await;
+        self.metrics.record_circuit(result.is_ok());
+        result
+    }
+
+    /// The relays circuits are built from
+    pub(crate) fn relay_manager(&self) -> &Arc<RwLock<RelayManager>> {
+        &self.relay_manager
+    }
+
+    /// Cached onion service descriptors
+    pub(crate) fn hs_descriptors(&self) -> &Arc<RwLock<HsDescCache>> {
+        &self.hs_descriptors
+    }
+
+    /// Where circuit and onion connection events go
+    pub(crate) fn events(&self) -> &CircuitEvents {
+        &self.events
+    }
+
+    /// Build a circuit whose last hop is `last_hop`, for talking to that
+    /// relay itself (an HSDir, an introduction or rendezvous point) rather
+    /// than exiting
+    ///
+    /// The circuit is bound to an isolation key of its own and tagged with
+    /// `purpose`, so no stream to the Internet is ever placed on it, and
+    /// with vanguards-lite enabled its middle is a layer-2 guard as for
+    /// every onion service circuit.
+    pub async fn create_circuit_to(
+        &self,
+        last_hop: &Relay,
+        purpose: CircuitPurpose,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        let key = IsolationKey(format!("internal:{}", uuid::Uuid::new_v4()));
+        let result = self
+            .build_circuit(Some(key), None, true, PathSpec::EndingAt(last_hop, purpose))
+            .await;
+        self.metrics.record_circuit(result.is_ok());
+        result
+    }
+
+    /// A circuit from the bridge straight to `last_hop`, with no middle
+    ///
+    /// Only for single onion services: the bridge and `last_hop` together
+    /// learn where the circuit comes from, which saves a hop for services
+    /// that don't need to hide it. Such circuits are never pooled or
+    /// cannibalized.
+    pub async fn create_direct_circuit_to(
+        &self,
+        last_hop: &Relay,
+        purpose: CircuitPurpose,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        let key = IsolationKey(format!("internal:{}", uuid::Uuid::new_v4()));
+        let result = self
+            .build_circuit(Some(key), None, false, PathSpec::Direct(last_hop, purpose))
+            .await;
+        self.metrics.record_circuit(result.is_ok());
+        result
+    }
+
+    /// A circuit for talking to `last_hop` itself: a spare pooled circuit
+    /// extended to it if there is one, or else a new one
+    pub async fn get_circuit_to(
+        &self,
+        last_hop: &Relay,
+        purpose: CircuitPurpose,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        if let Some(circuit) = self.cannibalize(purpose, Some(last_hop)).await {
+            return Ok(circuit);
+        }
+        self.create_circuit_to(last_hop, purpose).await
+    }
 
-        for circuit in circuits.iter() {
-            let circuit_read = circuit.read().await;
-            match circuit_read.status {
-                CircuitStatus::Ready => ready_count += 1,
-                CircuitStatus::Creating => creating_count += 1,
-                CircuitStatus::Failed => failed_count += 1,
-                _ => {}
-            }
-            total_age += circuit_read.age();
+    /// A circuit whose last hop serves as a rendezvous point: a spare
+    /// pooled circuit if there is one, or else a new one to a random relay
+    pub async fn get_rendezvous_circuit(&self) -> Result<Arc<RwLock<Circuit>>> {
+        if let Some(circuit) = self.cannibalize(CircuitPurpose::Rendezvous, None).await {
+            return Ok(circuit);
         }
+        let point = self
+            .relay_manager
+            .read()
+            .await
+            .select_relay(&crate::relay::selection::middle_relays())?;
+        self.create_circuit_to(&point, CircuitPurpose::Rendezvous)
+            .await
+    }
 
-        let avg_age = if circuits.is_empty() {
-            Duration::from_secs(0)
-        } else {
-            total_age / circuits.len() as u32
+    /// Take a spare general circuit for `purpose`, extending it to
+    /// `extend_to` if given
+    ///
+    /// This saves a build from scratch: any relay can be a rendezvous
+    /// point, so a spare circuit's last hop serves as one, and a circuit to
+    /// any other relay is one hop more. Spare circuits don't have a layer-2
+    /// guard middle, so none are taken with vanguards-lite enabled. Returns
+    /// `None` if there is no spare circuit or extending it failed.
+    async fn cannibalize(
+        &self,
+        purpose: CircuitPurpose,
+        extend_to: Option<&Relay>,
+    ) -> Option<Arc<RwLock<Circuit>>> {
+        if self.vanguards.is_some() {
+            return None;
+        }
+        let spare = {
+            let circuits = self.circuits.read().await;
+            let mut spare = None;
+            for circuit in circuits.iter() {
+                let mut circuit_write = circuit.write().await;
+                let usable = circuit_write.purpose == CircuitPurpose::General
+                    && circuit_write.is_ready()
+                    && circuit_write.isolation_key.is_none()
+                    && circuit_write.dirty_since.is_none()
+                    && !circuit_write.retired
+                    && !circuit_write.tunnel_closed()
+                    // A multi-path tunnel can't be extended
+                    && circuit_write.conflux_middles.is_empty()
+                    && extend_to.is_none_or(|target| {
+                        !circuit_write.relays.iter().any(|r| r.is_related_to(target))
+                    });
+                if usable {
+                    circuit_write.set_isolation_key(IsolationKey(format!(
+                        "internal:{}",
+                        uuid::Uuid::new_v4()
+                    )));
+                    circuit_write.purpose = purpose;
+                    spare = Some(circuit.clone());
+                    break;
+                }
+            }
+            spare
+        }?;
+
+        let mut circuit = spare.write().await;
+        let Some(target) = extend_to else {
+            info!("Using spare circuit {} for {:?}", circuit.id, purpose);
+            drop(circuit);
+            return Some(spare);
         };
-
-        CircuitStatusInfo {
-            total_circuits: circuits.len(),
-            ready_circuits: ready_count,
-            creating_circuits: creating_count,
-            failed_circuits: failed_count,
-            average_circuit_age: avg_age,
+        info!(
+            "Extending spare circuit {} to {} for {:?}",
+            circuit.id, target.nickname, purpose
+        );
+        let started = Instant::now();
+        let extended: Result<()> = async {
+            let tunnel = circuit
+                .internal_circuit
+                .as_ref()
+                .ok_or_else(|| TorError::Internal("No internal circuit available".to_string()))?;
+            tunnel
+                .as_single_circ()
+                .map_err(|e| {
+                    TorError::Internal(format!("Failed to get single circ for extend: {}", e))
+                })?
+                .extend(
+                    &target.as_circ_target()?,
+                    circ_params(self.congestion_control)?,
+                )
+                .await
+                .map_err(|e| {
+                    TorError::Internal(format!("Failed to extend to {}: {}", target.nickname, e))
+                })
+        }
+        .await;
+        self.record_extend(target, extended.is_ok());
+        match extended {
+            Ok(()) => {
+                circuit.hop_build_times.push(started.elapsed());
+                circuit.relays.push(target.clone());
+                drop(circuit);
+                Some(spare)
+            }
+            Err(e) => {
+                warn!("Failed to extend spare circuit {}: {}", circuit.id, e);
+                circuit.status = CircuitStatus::Failed;
+                if let Some(tunnel) = &circuit.internal_circuit {
+                    tunnel.terminate();
+                }
+                self.events
+                    .circuit_closed(&circuit.id, format!("extend failed: {}", e));
+                None
+            }
         }
     }
 
-    /// Update relay manager with new relay list
-    pub async fn update_relays(&self, new_relays: Vec<crate::relay::Relay>) {
-        let mut relay_manager = self.relay_manager.write().await;
-        relay_manager.update_relays(new_relays);
+    /// Close `circuit` now, with any streams on it
+    pub async fn close_circuit(&self, circuit: &Arc<RwLock<Circuit>>, reason: &str) {
+        let mut circuit = circuit.write().await;
+        if circuit.is_closed() {
+            return;
+        }
+        circuit.status = CircuitStatus::Closed;
+        if let Some(tunnel) = &circuit.internal_circuit {
+            tunnel.terminate();
+        }
+        self.events.circuit_closed(&circuit.id, reason);
     }
 
-    /// Synchronous version of update_relays for use when we already have a mutable reference
-    pub fn update_relay_list(&mut self, new_relays: Vec<crate::relay::Relay>) {
-        if let Ok(mut relay_manager) = self.relay_manager.try_write() {
-            relay_manager.update_relays(new_relays);
+    /// Close every open circuit, returning how many were closed
+    pub async fn close_all_circuits(&self, reason: &str) -> usize {
+        let circuits: Vec<_> = self.circuits.read().await.iter().cloned().collect();
+        let mut closed = 0;
+        for circuit in &circuits {
+            if !circuit.read().await.is_closed() {
+                self.close_circuit(circuit, reason).await;
+                closed += 1;
+            }
         }
+        closed
     }
 
-    /// Get relay information from the most recent ready circuit
-    pub async fn get_circuit_relays(&self) -> Option<Vec<CircuitRelayInfo>> {
-        let circuits = self.circuits.read().await;
-        // Iterate in reverse to get the most recently created circuit
-        for circuit in circuits.iter().rev() {
-            let circuit_read = circuit.read().await;
-            if circuit_read.is_ready() && !circuit_read.relays.is_empty() {
-                return Some(
-                    circuit_read
-                        .relays
-                        .iter()
-                        .enumerate()
-                        .map(|(idx, relay)| {
-                            let role = match idx {
-                                0 => "Bridge",
-                                1 => "Middle",
-                                2 => "Exit",
-                                _ => "Unknown",
-                            };
-                            // For Snowflake bridges, the address is typically 0.0.0.0 since
-                            // we connect via WebRTC - show something more meaningful
-                            let address = if idx == 0
-                                && (relay.address == "0.0.0.0"
-                                    || relay.address.starts_with("0.0.0.0:"))
-                            {
-                                "Snowflake (WebRTC)".to_string()
-                            } else {
-                                relay.address.clone()
-                            };
-                            CircuitRelayInfo {
-                                role: role.to_string(),
-                                nickname: relay.nickname.clone(),
-                                address,
-                                fingerprint: relay.fingerprint.chars().take(16).collect(),
-                            }
-                        })
-                        .collect(),
-                );
+    /// Mark ready circuits whose tunnel is gone as failed, e.g. after the
+    /// app was suspended, returning how many there were
+    pub async fn close_dead_circuits(&self) -> usize {
+        let circuits: Vec<_> = self.circuits.read().await.iter().cloned().collect();
+        let mut closed = 0;
+        for circuit in &circuits {
+            let mut circuit = circuit.write().await;
+            if circuit.is_ready() && circuit.tunnel_closed() {
+                circuit.status = CircuitStatus::Failed;
+                self.events.circuit_closed(&circuit.id, "destroyed");
+                closed += 1;
             }
         }
-        None
+        closed
     }
 
     /// Preemptively build a spare circuit if conditions are met
@@ -654,18 +2320,56 @@ impl CircuitManager {
             return;
         }
 
+        info!(
+            "Prebuilding spare circuit (avg age: {:?}, threshold: {:?})",
+            status.average_circuit_age, age_threshold
+        );
+        self.spawn_prebuild();
+    }
+
+    /// Build a fresh circuit in the background once every ready circuit is too dirty
+    ///
+    /// Like C Tor's MaxCircuitDirtiness: dirty circuits keep their open
+    /// streams, but new streams need a replacement, so one is built ahead of
+    /// the next request. Returns the number of builds started.
+    pub async fn replace_dirty_circuits(&self) -> usize {
+        let circuits = self.circuits.read().await;
+        let mut dirty = 0;
+        let mut spare = false;
+        for circuit in circuits.iter() {
+            let circuit_read = circuit.read().await;
+            if !circuit_read.is_ready() {
+                continue;
+            }
+            if circuit_read.is_dirty_for(self.max_dirtiness) {
+                dirty += 1;
+            } else if circuit_read.isolation_key.is_none() {
+                spare = true;
+            }
+        }
+        drop(circuits);
+
+        if dirty == 0 || spare {
+            return 0;
+        }
+        info!(
+            "{} circuits past the dirtiness window; building a replacement",
+            dirty
+        );
+        usize::from(self.spawn_prebuild())
+    }
+
+    /// Start building an unassigned circuit in the background
+    ///
+    /// Returns false if a background build is already running.
+    fn spawn_prebuild(&self) -> bool {
         // Try to acquire the prebuild slot (prevents race condition where multiple
         // concurrent requests all trigger prebuilds)
         if self.prebuild_in_progress.swap(true, Ordering::SeqCst) {
             debug!("Skipping prebuild: another prebuild is already in progress");
-            return;
+            return false;
         }
 
-        info!(
-            "Prebuilding spare circuit (avg age: {:?}, threshold: {:?})",
-            status.average_circuit_age, age_threshold
-        );
-
         // Clone self for the spawned task
         let circuit_manager = self.clone();
         let prebuild_flag = self.prebuild_in_progress.clone();
@@ -699,10 +2403,14 @@ impl CircuitManager {
                 }
             }
         });
+
+        true
     }
 
-    /// Clean up failed and old circuits
-    pub async fn cleanup_circuits(&self) -> Result<()> {
+    /// Clean up failed, closed, old and idle circuits
+    ///
+    /// Returns the number of circuit records removed.
+    pub async fn cleanup_circuits(&self) -> Result<usize> {
         let mut circuits = self.circuits.write().await;
         let max_age = Duration::from_secs(60 * 60); // 1 hour
         let max_idle = Duration::from_secs(60 * 10); // 10 minutes
@@ -715,14 +2423,23 @@ impl CircuitManager {
         for (idx, circuit) in circuits.iter().enumerate() {
             let circuit_read = circuit.read().await;
 
-            // Remove failed circuits
-            if circuit_read.is_failed() {
-                info!("Removing failed circuit: {}", circuit_read.id);
+            // Remove failed and closed circuits
+            if circuit_read.is_failed() || circuit_read.is_closed() {
+                info!(
+                    "Removing finished circuit: {} ({:?})",
+                    circuit_read.id, circuit_read.status
+                );
                 to_remove.push(idx);
                 remaining -= 1;
                 continue;
             }
 
+            // An introduction point lasts as long as its onion service,
+            // which closes it
+            if circuit_read.purpose == CircuitPurpose::Introduction {
+                continue;
+            }
+
             // Remove very old circuits
             if circuit_read.age() > max_age {
                 info!(
@@ -730,6 +2447,7 @@ impl CircuitManager {
                     circuit_read.id,
                     circuit_read.age()
                 );
+                self.events.circuit_closed(&circuit_read.id, "expired");
                 to_remove.push(idx);
                 remaining -= 1;
                 continue;
@@ -742,27 +2460,59 @@ impl CircuitManager {
                     circuit_read.id,
                     circuit_read.time_since_last_use()
                 );
+                self.events.circuit_closed(&circuit_read.id, "idle");
                 to_remove.push(idx);
                 remaining -= 1;
             }
         }
 
         // Remove in reverse order to preserve indices
+        let removed = to_remove.len();
         for idx in to_remove.into_iter().rev() {
             circuits.remove(idx);
         }
 
-        Ok(())
+        Ok(removed)
     }
 }
 
 /// Circuit relay information for display
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct CircuitRelayInfo {
     pub role: String,
     pub nickname: String,
     pub address: String,
     pub fingerprint: String,
+    /// Country code from GeoIP, if a table is loaded
+    pub country: Option<String>,
+}
+
+impl CircuitRelayInfo {
+    /// Display information for `relay` at position `idx` (0 is the bridge)
+    pub fn for_hop(idx: usize, relay: &Relay) -> Self {
+        let role = match idx {
+            0 => "Bridge",
+            1 => "Middle",
+            2 => "Exit",
+            _ => "Unknown",
+        };
+        // For Snowflake bridges, the address is typically 0.0.0.0 since
+        // we connect via WebRTC - show something more meaningful
+        let address =
+            if idx == 0 && (relay.address == "0.0.0.0" || relay.address.starts_with("0.0.0.0:")) {
+                "Snowflake (WebRTC)".to_string()
+            } else {
+                relay.address.clone()
+            };
+        Self {
+            role: role.to_string(),
+            nickname: relay.nickname.clone(),
+            address,
+            fingerprint: relay.fingerprint.chars().take(16).collect(),
+            country: relay.country.clone(),
+        }
+    }
 }
 
 /// Circuit status information
@@ -773,9 +2523,32 @@ pub struct CircuitStatusInfo {
     pub creating_circuits: usize,
     pub failed_circuits: usize,
     pub average_circuit_age: Duration,
+    /// Whether the first hop is an unlisted bridge or a listed guard
+    pub entry_mode: Option<EntryMode>,
+    /// Ready circuits with more than one linked conflux leg
+    pub conflux_circuits: usize,
+    /// Build timing and stream counters of each circuit
+    pub circuit_metrics: Vec<CircuitMetrics>,
+    /// Failed builds since startup, by the step that failed (`timeout`,
+    /// `first_hop`, `extend_middle`, `extend_exit`, ...)
+    pub build_failures: BTreeMap<String, usize>,
 }
 
 impl CircuitStatusInfo {
+    /// "Ready", "Creating...", "None", or "Ready (n failed circuits)"
+    pub fn summary(&self) -> String {
+        if !self.has_ready_circuits() && self.creating_circuits > 0 {
+            return "Creating...".to_string();
+        }
+        if !self.has_ready_circuits() {
+            return "None".to_string();
+        }
+        if self.failed_circuits > 0 {
+            return format!("Ready ({} failed circuits)", self.failed_circuits);
+        }
+        "Ready".to_string()
+    }
+
     pub fn has_ready_circuits(&self) -> bool {
         self.ready_circuits > 0
     }
@@ -785,6 +2558,38 @@ impl CircuitStatusInfo {
     }
 }
 
+/// One circuit of a [`CircuitStatusReport`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitReport {
+    pub id: String,
+    pub purpose: CircuitPurpose,
+    pub state: CircuitStatus,
+    /// Relays in path order, the bridge first
+    pub hops: Vec<CircuitRelayInfo>,
+    pub age_ms: u64,
+    /// Time since a stream was last opened on it
+    pub idle_ms: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub open_streams: usize,
+}
+
+/// Every circuit held and the totals, ready to serialize (e.g. for a
+/// circuit display)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitStatusReport {
+    /// As [`CircuitStatusInfo::summary`]
+    pub summary: String,
+    pub total_circuits: usize,
+    pub ready_circuits: usize,
+    pub creating_circuits: usize,
+    pub failed_circuits: usize,
+    pub entry_mode: Option<EntryMode>,
+    pub circuits: Vec<CircuitReport>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -838,6 +2643,140 @@ mod tests {
         assert!(circuit.is_closed());
     }
 
+    #[tokio::test]
+    async fn test_abandoned_builds_are_reported_closed() {
+        use futures::StreamExt;
+
+        let events = CircuitEvents::new();
+        let mut subscriber = events.subscribe();
+        PendingBuild {
+            events: &events,
+            circuit_id: "circuit_won",
+            done: false,
+        }
+        .finish();
+        drop(PendingBuild {
+            events: &events,
+            circuit_id: "circuit_lost",
+            done: false,
+        });
+
+        assert_eq!(
+            subscriber.next().await,
+            Some(CircuitEvent::CircuitClosed {
+                circuit_id: "circuit_lost".to_string(),
+                reason: "build abandoned".to_string(),
+            })
+        );
+        assert_eq!(events.subscriber_count(), 1);
+    }
+
+    #[test]
+    fn test_full_circuits_take_no_new_streams() {
+        let circuit_manager = CircuitManager::new(
+            Arc::new(RwLock::new(RelayManager::new(Vec::new()))),
+            Arc::new(RwLock::new(None)),
+        )
+        .with_max_streams_per_circuit(2);
+        let mut circuit = Circuit::new("busy".to_string(), None);
+        circuit.status = CircuitStatus::Ready;
+
+        let first = CountedStream::new((), &circuit.traffic);
+        let _second = CountedStream::new((), &circuit.traffic);
+        assert_eq!(circuit.open_streams(), 2);
+        assert!(!circuit_manager.takes_new_streams(&circuit));
+
+        drop(first);
+        assert!(circuit_manager.takes_new_streams(&circuit));
+        assert!(circuit_manager
+            .with_max_streams_per_circuit(0)
+            .takes_new_streams(&circuit));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_metrics_in_status() {
+        let mut circuit = Circuit::new("timed".to_string(), None);
+        circuit.hop_build_times = vec![Duration::from_millis(300), Duration::from_millis(450)];
+        circuit.record_stream(Some(Duration::from_millis(80)), &Ok(()));
+        circuit.record_stream(Some(Duration::from_millis(160)), &Ok(()));
+        circuit.record_stream(None, &Ok(()));
+        circuit.record_stream::<()>(None, &Err(TorError::timeout("no CONNECTED")));
+
+        let circuit_manager = CircuitManager::new(
+            Arc::new(RwLock::new(RelayManager::new(Vec::new()))),
+            Arc::new(RwLock::new(None)),
+        );
+        circuit_manager
+            .circuits
+            .write()
+            .await
+            .push(Arc::new(RwLock::new(circuit)));
+        circuit_manager.record_build_failure("extend_exit");
+        circuit_manager.record_build_failure("extend_exit");
+
+        let status = circuit_manager.get_circuit_status().await;
+        assert_eq!(status.build_failures.get("extend_exit"), Some(&2));
+        let metrics = &status.circuit_metrics[0];
+        assert_eq!(metrics.hop_build_ms, vec![300, 450]);
+        assert_eq!(metrics.rtt_ms, Some(90));
+        assert_eq!((metrics.streams_opened, metrics.streams_failed), (3, 1));
+        assert_eq!(metrics.last_error.as_deref(), Some("Timeout: no CONNECTED"));
+    }
+
+    #[test]
+    fn test_optimistic_data_needs_option_and_exit_support() {
+        let mut exit = exit_at("exit", "203.0.113.5");
+        exit.protocols = Some("Relay=1-4".to_string());
+        let mut old_exit = exit_at("old_exit", "203.0.113.6");
+        old_exit.protocols = Some("Relay=1".to_string());
+        let circuit_manager = CircuitManager::new(
+            Arc::new(RwLock::new(RelayManager::new(Vec::new()))),
+            Arc::new(RwLock::new(None)),
+        );
+
+        assert!(circuit_manager.optimistic_data_on(&exit));
+        assert!(!circuit_manager.optimistic_data_on(&old_exit));
+        let circuit_manager = circuit_manager.with_optimistic_data(false);
+        assert!(!circuit_manager.optimistic_data_on(&exit));
+    }
+
+    #[tokio::test]
+    async fn test_status_report() {
+        let mut circuit = Circuit::new("reported".to_string(), None);
+        circuit.status = CircuitStatus::Ready;
+        circuit.purpose = CircuitPurpose::HsDir;
+        circuit.relays = vec![
+            create_test_relay("bridge", vec![]),
+            create_test_relay("middle", vec![flags::FAST]),
+            create_test_relay("hsdir", vec![flags::HSDIR]),
+        ];
+        let _stream = CountedStream::new((), &circuit.traffic);
+
+        let circuit_manager = CircuitManager::new(
+            Arc::new(RwLock::new(RelayManager::new(Vec::new()))),
+            Arc::new(RwLock::new(None)),
+        );
+        circuit_manager
+            .circuits
+            .write()
+            .await
+            .push(Arc::new(RwLock::new(circuit)));
+
+        let report = circuit_manager.status_report().await;
+        assert_eq!(report.summary, "Ready");
+        assert_eq!(report.circuits[0].open_streams, 1);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["readyCircuits"], 1);
+        let circuit = &json["circuits"][0];
+        assert_eq!(circuit["id"], "reported");
+        assert_eq!(circuit["purpose"], "hs-dir");
+        assert_eq!(circuit["state"], "ready");
+        assert_eq!(circuit["hops"][2]["role"], "HSDir");
+        assert_eq!(circuit["hops"][1]["nickname"], "test_middle");
+        assert!(circuit["ageMs"].is_u64());
+    }
+
     #[test]
     fn test_circuit_isolation_key_binding() {
         let mut circuit = Circuit::new("test_circuit".to_string(), None);
@@ -853,6 +2792,506 @@ mod tests {
         assert_eq!(circuit.isolation_key, Some(key1));
     }
 
+    fn relay_at(fingerprint: &str, address: &str, flags: Vec<&str>) -> Relay {
+        let mut relay = create_test_relay(fingerprint, flags);
+        relay.address = address.to_string();
+        relay
+    }
+
+    #[test]
+    fn test_explicit_path_is_validated() {
+        let bridge = relay_at("BRIDGE", "10.0.0.1", vec![]);
+        let middle = relay_at("AAAA", "192.1.0.1", vec![flags::FAST, flags::STABLE]);
+        let exit = relay_at("BBBB", "192.2.0.1", vec![flags::FAST, flags::EXIT]);
+        let bad_exit = relay_at("CCCC", "192.3.0.1", vec![flags::EXIT, flags::BAD_EXIT]);
+        let neighbour = relay_at("DDDD", "192.1.9.9", vec![flags::FAST, flags::EXIT]);
+        let relay_manager = RelayManager::new(vec![
+            bridge.clone(),
+            middle.clone(),
+            exit.clone(),
+            bad_exit,
+            neighbour,
+        ]);
+        let path = |fps: &[&str]| {
+            let fps: Vec<String> = fps.iter().map(|fp| fp.to_string()).collect();
+            resolve_explicit_path(&relay_manager, &bridge, &fps)
+        };
+
+        let (m, e) = path(&["$aaaa", "BBBB"]).unwrap();
+        assert_eq!(
+            (m.fingerprint, e.fingerprint),
+            (middle.fingerprint, exit.fingerprint)
+        );
+        assert!(path(&["BRIDGE", "AAAA", "BBBB"]).is_ok());
+
+        // Wrong first hop, wrong length, unknown relay, bad flags, shared /16
+        assert!(path(&["EEEE", "AAAA", "BBBB"]).is_err());
+        assert!(path(&["BBBB"]).is_err());
+        assert!(path(&["AAAA", "FFFF"]).is_err());
+        assert!(path(&["AAAA", "CCCC"]).is_err());
+        assert!(path(&["BBBB", "AAAA"]).is_err());
+        assert!(path(&["AAAA", "DDDD"]).is_err());
+    }
+
+    fn middle_at(fingerprint: &str, address: &str) -> Relay {
+        relay_at(
+            fingerprint,
+            address,
+            vec![flags::FAST, flags::STABLE, flags::V2DIR],
+        )
+    }
+
+    fn exit_at(fingerprint: &str, address: &str) -> Relay {
+        relay_at(
+            fingerprint,
+            address,
+            vec![flags::FAST, flags::STABLE, flags::EXIT],
+        )
+    }
+
+    fn assert_path_is_unrelated(path: &[&Relay]) {
+        for (i, a) in path.iter().enumerate() {
+            for b in &path[i + 1..] {
+                assert!(
+                    !a.is_related_to(b),
+                    "{} and {} must not share a circuit",
+                    a.fingerprint,
+                    b.fingerprint
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_path_avoids_shared_subnets() {
+        let bridge = relay_at("bridge", "198.51.100.7", vec![]);
+        let manager = RelayManager::new(vec![
+            middle_at("m_bridge_net", "198.51.7.7"),
+            middle_at("m_exit_net", "203.0.200.1"),
+            middle_at("m_ok", "192.0.2.10"),
+            exit_at("exit", "203.0.113.5"),
+            exit_at("e_bridge_net", "198.51.1.1"),
+        ]);
+
+        for _ in 0..50 {
+            let (middle, exit) = select_path(
+                &manager,
+                &bridge,
+                EntryMode::Guard,
+                crate::relay::selection::exit_relays(),
+            )
+            .unwrap();
+            assert_eq!(exit.fingerprint, "exit");
+            assert_eq!(middle.fingerprint, "m_ok");
+            assert_path_is_unrelated(&[&bridge, &middle, &exit]);
+        }
+    }
+
+    #[test]
+    fn test_select_path_avoids_ipv6_subnets_and_families() {
+        let bridge = relay_at("bridge", "0.0.0.0", vec![]);
+
+        let mut exit = exit_at("exit", "2001:db8:1::1");
+        let mut sibling = middle_at("sibling", "192.0.2.1");
+        exit.family.insert("sibling".to_string());
+        sibling.family.insert("exit".to_string());
+
+        // Listing is only one-sided, so this is not a family
+        let mut claimant = middle_at("claimant", "192.0.3.1");
+        claimant.family.insert("exit".to_string());
+
+        let mut cousin = middle_at("cousin", "192.0.4.1");
+        exit.family_ids.insert("ed25519:fam".to_string());
+        cousin.family_ids.insert("ed25519:fam".to_string());
+
+        let manager = RelayManager::new(vec![
+            exit,
+            sibling,
+            claimant,
+            cousin,
+            middle_at("same_v6_net", "2001:db8:ffff::2"),
+        ]);
+
+        for _ in 0..50 {
+            let (middle, exit) = select_path(
+                &manager,
+                &bridge,
+                EntryMode::Guard,
+                crate::relay::selection::exit_relays(),
+            )
+            .unwrap();
+            assert_eq!(middle.fingerprint, "claimant");
+            assert_path_is_unrelated(&[&bridge, &middle, &exit]);
+        }
+    }
+
+    #[test]
+    fn test_select_path_fails_without_unrelated_middle() {
+        let bridge = relay_at("bridge", "198.51.100.7", vec![]);
+        let manager = RelayManager::new(vec![
+            middle_at("m_bridge_net", "198.51.0.1"),
+            middle_at("m_exit_net", "203.0.0.1"),
+            exit_at("exit", "203.0.113.5"),
+        ]);
+        assert!(select_path(
+            &manager,
+            &bridge,
+            EntryMode::Guard,
+            crate::relay::selection::exit_relays(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_direct_paths_skip_the_middle() {
+        let bridge = relay_at("bridge", "198.51.100.7", vec![]);
+        let intro = middle_at("intro", "203.0.113.5");
+        let manager = RelayManager::new(vec![middle_at("m_ok", "192.0.2.10"), intro.clone()]);
+
+        // An onion service circuit has a middle unless it's a single onion
+        // service's, which goes from the bridge to the relay
+        let middle = select_middle_to(&manager, &bridge, EntryMode::Guard, &intro, &[]).unwrap();
+        assert_eq!(middle.fingerprint, "m_ok");
+        check_direct_path(&bridge, &intro).unwrap();
+
+        // Either way the last hop must be unrelated to the bridge
+        let neighbour = middle_at("neighbour", "198.51.100.8");
+        assert!(check_direct_path(&bridge, &neighbour).is_err());
+        assert!(select_middle_to(&manager, &bridge, EntryMode::Guard, &neighbour, &[]).is_err());
+    }
+
+    #[test]
+    fn test_unlisted_bridge_needs_guard_middle() {
+        let placeholder = relay_at("bridge", "0.0.0.0", vec![]);
+        let mut guard_middle = middle_at("m_guard", "192.0.2.10");
+        guard_middle.flags.insert(flags::GUARD.to_string());
+        let manager = RelayManager::new(vec![
+            middle_at("m_plain", "192.0.3.10"),
+            guard_middle,
+            exit_at("exit", "203.0.113.5"),
+        ]);
+
+        let (bridge, mode) = resolve_entry(&manager, placeholder.clone());
+        assert_eq!(mode, EntryMode::Bridge);
+        assert_eq!(bridge.address, "0.0.0.0");
+        for _ in 0..50 {
+            let (middle, _) = select_path(
+                &manager,
+                &bridge,
+                mode,
+                crate::relay::selection::exit_relays(),
+            )
+            .unwrap();
+            assert_eq!(middle.fingerprint, "m_guard");
+        }
+
+        // A bridge that is also a listed relay is an ordinary guard
+        let mut listed = relay_at("bridge", "198.51.100.7", vec![flags::GUARD]);
+        listed.nickname = "ListedBridge".to_string();
+        let manager = RelayManager::new(vec![
+            listed,
+            middle_at("m_plain", "192.0.3.10"),
+            exit_at("exit", "203.0.113.5"),
+        ]);
+        let (bridge, mode) = resolve_entry(&manager, placeholder);
+        assert_eq!(mode, EntryMode::Guard);
+        assert_eq!(bridge.nickname, "ListedBridge");
+        let (middle, _) = select_path(
+            &manager,
+            &bridge,
+            mode,
+            crate::relay::selection::exit_relays(),
+        )
+        .unwrap();
+        assert_eq!(middle.fingerprint, "m_plain");
+    }
+
+    #[test]
+    fn test_conflux_leg_takes_an_unrelated_middle() {
+        let bridge = relay_at("bridge", "198.51.100.7", vec![]);
+        let manager = RelayManager::new(vec![
+            middle_at("m_first", "192.0.2.10"),
+            middle_at("m_first_net", "192.0.9.9"),
+            middle_at("m_second", "192.1.3.10"),
+            exit_at("exit", "203.0.113.5"),
+        ]);
+        let middle = manager
+            .relays
+            .iter()
+            .find(|r| r.fingerprint == "m_first")
+            .cloned()
+            .unwrap();
+        let exit = manager
+            .relays
+            .iter()
+            .find(|r| r.fingerprint == "exit")
+            .cloned()
+            .unwrap();
+
+        for _ in 0..50 {
+            let leg_middle =
+                select_conflux_middle(&manager, &bridge, EntryMode::Guard, &middle, &exit).unwrap();
+            assert_eq!(leg_middle.fingerprint, "m_second");
+            assert_path_is_unrelated(&[&bridge, &middle, &leg_middle, &exit]);
+        }
+
+        // No middle left for a second leg in bridge mode (needs Guard)
+        assert!(
+            select_conflux_middle(&manager, &bridge, EntryMode::Bridge, &middle, &exit).is_err()
+        );
+    }
+
+    #[test]
+    fn test_first_hop_uses_ntor_when_key_known() {
+        use tor_linkspec::CircTarget;
+
+        let fingerprint = "AA".repeat(20);
+        let key = "11".repeat(32);
+        let mut listed = relay_at(&fingerprint, "198.51.100.7", vec![flags::GUARD]);
+        listed.ntor_onion_key = Some(key.clone());
+
+        let target = first_hop_target(&listed, EntryMode::Guard, None)
+            .unwrap()
+            .expect("listed entry has a key");
+        assert_eq!(target.ntor_onion_key().as_bytes(), &[0x11; 32]);
+
+        let placeholder = relay_at(&fingerprint, "0.0.0.0", vec![]);
+        assert!(first_hop_target(&placeholder, EntryMode::Bridge, None)
+            .unwrap()
+            .is_none());
+        assert!(
+            first_hop_target(&placeholder, EntryMode::Bridge, Some(&key))
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_layer2_pins_middle() {
+        let bridge = relay_at("bridge", "198.51.100.7", vec![]);
+        let manager = RelayManager::new(vec![
+            middle_at("M_FREE", "192.0.3.10"),
+            middle_at("M_PINNED", "192.0.2.10"),
+            middle_at("M_BRIDGE_NET", "198.51.2.2"),
+            exit_at("exit", "203.0.113.5"),
+        ]);
+
+        for _ in 0..20 {
+            let (middle, _) = select_path_with_layer2(
+                &manager,
+                &bridge,
+                EntryMode::Guard,
+                crate::relay::selection::exit_relays(),
+                &["m_pinned".to_string(), "M_BRIDGE_NET".to_string()],
+            )
+            .unwrap();
+            assert_eq!(middle.fingerprint, "M_PINNED");
+        }
+
+        // No fallback to the wider network when no layer-2 guard fits
+        assert!(select_path_with_layer2(
+            &manager,
+            &bridge,
+            EntryMode::Guard,
+            crate::relay::selection::exit_relays(),
+            &["M_BRIDGE_NET".to_string()],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_circuits_take_the_channels_in_turn() {
+        let cursor = AtomicUsize::new(0);
+        let turns: Vec<char> = (0..7)
+            .map(|_| take_turn(&['a', 'b', 'c'], &cursor))
+            .collect();
+        assert_eq!(turns, ['a', 'b', 'c', 'a', 'b', 'c', 'a']);
+        // A channel opened meanwhile joins the rotation
+        let turns: Vec<char> = (0..4)
+            .map(|_| take_turn(&['a', 'b', 'c', 'd'], &cursor))
+            .collect();
+        assert_eq!(turns, ['d', 'a', 'b', 'c']);
+    }
+
+    #[tokio::test]
+    async fn test_closing_channels_are_not_used() {
+        use tor_proto::channel::ChannelType;
+
+        let fake = || {
+            let (chan, _control) = Channel::new_fake(
+                crate::wasm_runtime::WasmRuntime::new(),
+                ChannelType::ClientInitiator,
+            );
+            Arc::new(chan)
+        };
+        let relay_manager = Arc::new(RwLock::new(RelayManager::new(Vec::new())));
+        let circuit_manager = CircuitManager::new(relay_manager, Arc::new(RwLock::new(None)));
+        assert!(circuit_manager.next_channel().await.is_err());
+
+        // Fake channels report their reactor gone, like a closing channel
+        let primary = fake();
+        assert!(primary.is_closing());
+        *circuit_manager.channel.write().await = Some(primary.clone());
+        assert!(!circuit_manager.add_channel(fake()).await);
+
+        circuit_manager
+            .extra_channels
+            .lock()
+            .unwrap()
+            .extend([fake(), fake()]);
+        let open = circuit_manager.open_channels().await;
+        assert_eq!(open.len(), 1);
+        assert!(Arc::ptr_eq(&open[0], &primary));
+        assert!(circuit_manager.extra_channels.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_pinned_circuit() {
+        let relay_manager = Arc::new(RwLock::new(RelayManager::new(Vec::new())));
+        let circuit_manager = CircuitManager::new(relay_manager, Arc::new(RwLock::new(None)));
+
+        let mut exit = create_test_relay("exit", vec![flags::EXIT]);
+        exit.exit_policy = Some(Arc::new("accept 443".parse().unwrap()));
+        let mut ready = Circuit::new("ready".to_string(), None);
+        ready.status = CircuitStatus::Ready;
+        ready.relays = vec![exit];
+        let mut closed = Circuit::new("closed".to_string(), None);
+        closed.status = CircuitStatus::Closed;
+        {
+            let mut circuits = circuit_manager.circuits.write().await;
+            circuits.push(Arc::new(RwLock::new(ready)));
+            circuits.push(Arc::new(RwLock::new(closed)));
+        }
+
+        assert_eq!(circuit_manager.ready_circuit_ids().await, vec!["ready"]);
+        let circuit = circuit_manager
+            .get_pinned_circuit("ready", 443)
+            .await
+            .unwrap();
+        assert_eq!(circuit.read().await.id, "ready");
+
+        let err = circuit_manager
+            .get_pinned_circuit("ready", 80)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "RELAY_SELECTION");
+        assert!(circuit_manager
+            .get_pinned_circuit("closed", 443)
+            .await
+            .is_err());
+        assert!(circuit_manager
+            .get_pinned_circuit("missing", 443)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_new_identity_retires_circuits() {
+        let relay_manager = Arc::new(RwLock::new(RelayManager::new(Vec::new())));
+        let events = CircuitEvents::new();
+        let mut closed_events = events.subscribe();
+        let circuit_manager =
+            CircuitManager::new(relay_manager, Arc::new(RwLock::new(None))).with_events(events);
+
+        let mut ready = Circuit::new("ready".to_string(), None);
+        ready.status = CircuitStatus::Ready;
+        ready.relays = vec![
+            create_test_relay("bridge", vec![]),
+            create_test_relay("middle", vec![]),
+            create_test_relay("exit", vec![flags::EXIT]),
+        ];
+        ready.set_isolation_key(IsolationKey::from_string("example.com"));
+        circuit_manager
+            .circuits
+            .write()
+            .await
+            .push(Arc::new(RwLock::new(ready)));
+
+        assert_eq!(circuit_manager.new_identity(true).await, 1);
+        assert!(circuit_manager.ready_circuit_ids().await.is_empty());
+        assert_eq!(
+            closed_events.try_next().unwrap(),
+            Some(CircuitEvent::CircuitClosed {
+                circuit_id: "ready".to_string(),
+                reason: "new identity".to_string(),
+            })
+        );
+        assert!(circuit_manager
+            .get_pinned_circuit("ready", 443)
+            .await
+            .is_err());
+        assert_eq!(circuit_manager.avoided_exits(), vec!["exit"]);
+        assert_eq!(circuit_manager.cleanup_circuits().await.unwrap(), 1);
+
+        // Nothing left to retire, and the previous exits are no longer avoided
+        assert_eq!(circuit_manager.new_identity(false).await, 0);
+        assert!(circuit_manager.avoided_exits().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dirty_circuit_takes_no_new_streams() {
+        let relay_manager = Arc::new(RwLock::new(RelayManager::new(Vec::new())));
+        let circuit_manager = CircuitManager::new(relay_manager, Arc::new(RwLock::new(None)))
+            .with_max_dirtiness(Duration::ZERO);
+
+        let mut exit = create_test_relay("exit", vec![flags::EXIT]);
+        exit.exit_policy = Some(Arc::new("accept 443".parse().unwrap()));
+        let mut ready = Circuit::new("ready".to_string(), None);
+        ready.status = CircuitStatus::Ready;
+        ready.relays = vec![exit];
+        assert!(!ready.is_dirty_for(Duration::ZERO));
+        circuit_manager
+            .circuits
+            .write()
+            .await
+            .push(Arc::new(RwLock::new(ready)));
+
+        // The first stream dirties it; after that a new circuit is needed
+        let circuit = circuit_manager.get_ready_circuit_for_port(443).await;
+        assert_eq!(circuit.unwrap().read().await.id, "ready");
+        assert!(circuit_manager
+            .get_ready_circuit_for_port(443)
+            .await
+            .is_err());
+        assert!(circuit_manager
+            .get_circuit_for_isolation_key(Some(IsolationKey::from_string("a")), 443)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fresh_circuits_are_never_shared() {
+        let relay_manager = Arc::new(RwLock::new(RelayManager::new(Vec::new())));
+        let circuit_manager = CircuitManager::new(relay_manager, Arc::new(RwLock::new(None)));
+
+        let mut exit = create_test_relay("exit", vec![flags::EXIT]);
+        exit.exit_policy = Some(Arc::new("accept 443".parse().unwrap()));
+        for id in ["used", "unused"] {
+            let mut circuit = Circuit::new(id.to_string(), None);
+            circuit.status = CircuitStatus::Ready;
+            circuit.relays = vec![exit.clone()];
+            if id == "used" {
+                circuit.update_last_used();
+            }
+            circuit_manager
+                .circuits
+                .write()
+                .await
+                .push(Arc::new(RwLock::new(circuit)));
+        }
+
+        // Only the circuit that never carried a stream qualifies, and once
+        // claimed neither fresh nor isolated requests get it again
+        let circuit = circuit_manager.get_fresh_circuit(443).await.unwrap();
+        assert_eq!(circuit.read().await.id, "unused");
+        assert!(circuit_manager.get_fresh_circuit(443).await.is_err());
+        let other = circuit_manager
+            .get_circuit_for_isolation_key(Some(IsolationKey::from_string("a")), 443)
+            .await
+            .unwrap();
+        assert_eq!(other.read().await.id, "used");
+    }
+
     #[test]
     fn test_circuit_new_has_no_isolation_key() {
         let circuit = Circuit::new("test".to_string(), None);
@@ -1,32 +1,79 @@
 //! Main Tor client implementation
 
-use crate::circuit::{CircuitManager, CircuitStatusInfo};
-use crate::config::{BridgeType, LogType, TorClientOptions, SNOWFLAKE_FINGERPRINT_PRIMARY};
-use crate::directory::DirectoryManager;
+use crate::bootstrap::{BootstrapProgress, BootstrapReport, BootstrapStage, BootstrapStatus};
+use crate::bridges::{BridgeSet, BridgeStatus};
+use crate::build_timeout::BuildTimeoutEstimator;
+use crate::circuit::{Circuit, CircuitManager, CircuitStatusInfo, CircuitStatusReport};
+use crate::config::{
+    BridgeConfig, BridgeType, LogType, TorClientOptions, MAX_CIRCUITS, PREBUILD_EXIT_PORT,
+    RESOLVE_CACHE_TTL,
+};
+use crate::cookies::CookieJar;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::direct::{self, OrPortTransport};
+use crate::directory::{DirectoryInjection, DirectoryManager};
+use crate::dormant::Activity;
 use crate::error::{Result, TorError};
+use crate::events::{ChannelEvent, CircuitEvent, CircuitEvents, OnionEvent, TorEvent};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::guard::GUARD_SAMPLE_SIZE;
+use crate::guard::{now_secs, GuardCandidate, GuardManager, GuardSet};
+use crate::hs_cache::HsDescCache;
 use crate::http::{HttpRequest, HttpResponse, TorHttpClient};
-use crate::relay::RelayManager;
-use crate::retry::{with_timeout_and_cancellation, CancellationToken};
+use crate::identity::ExpectedIdentity;
+use crate::isolation::{IsolationDomain, IsolationKey, IsolationToken};
+use crate::maintenance::{Maintenance, MaintenanceReport, TtlCache};
+use crate::meek::{MeekBridge, MeekConfig};
+use crate::metrics::{Metrics, MetricsSnapshot};
+#[cfg(all(feature = "obfs4", not(target_arch = "wasm32")))]
+use crate::obfs4::{Obfs4Bridge, Obfs4Config};
+use crate::onion;
+use crate::onion_connector::OnionConnector;
+use crate::onion_service::{OnionService, OnionServiceConfig};
+use crate::padding;
+use crate::range::PartialDownload;
+use crate::reachability::ReachabilityTracker;
+use crate::relay::{NodeSet, Relay, RelayManager, RelayQuery};
+use crate::retry::{
+    race_staggered, sleep, with_cancellation, with_timeout_and_cancellation, CancellationToken,
+};
+use crate::snapshot::ClientState;
 #[cfg(target_arch = "wasm32")]
 use crate::snowflake::{SnowflakeBridge, SnowflakeConfig};
 #[cfg(target_arch = "wasm32")]
-use crate::snowflake_ws::{SnowflakeWsConfig, SnowflakeWsStream};
-use crate::time::system_time_now;
+use crate::snowflake_ws::SnowflakeWsConfig;
+use crate::sse::{EventSourceRequest, EventStream, TorEventSource};
+use crate::storage::{MemoryStore, StateStore};
+use crate::time::{system_time_now, Instant};
+use crate::tls::{TlsSessionCache, TlsSessions, TorTlsStream};
+use crate::traffic::{TorStream, TrafficStats};
+use crate::transport::Transport;
+use crate::transport_stats::{MeteredStream, TransportStatsSnapshot};
+use crate::vanguards::{Layer2GuardSet, VanguardManager};
+#[cfg(target_arch = "wasm32")]
+use crate::visibility::{VisibilityListener, VisibilityPolicy};
 use crate::wasm_runtime::WasmRuntime;
 #[cfg(not(target_arch = "wasm32"))]
-use crate::webtunnel::{create_webtunnel_stream, WebTunnelConfig};
+use crate::webtunnel::{WebTunnelBridge, WebTunnelConfig};
+use crate::ws::{TorWebSocket, WebSocketRequest};
+use base64::Engine;
+use futures::channel::mpsc::UnboundedReceiver;
+use futures::Stream;
 use http::Method;
-use std::sync::Arc;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError, Weak};
 use std::time::Duration;
 use tokio::sync::RwLock;
-use tor_linkspec::OwnedChanTargetBuilder;
-use tor_llcrypto::pk::rsa::RsaIdentity;
+use tor_linkspec::{HasRelayIds, OwnedChanTargetBuilder};
 use tor_memquota::MemoryQuotaTracker;
 use tor_proto::channel::ChannelBuilder;
 use tor_proto::memquota::{ChannelAccount, SpecificAccount};
 use tracing::{debug, error, info, warn};
 use url::Url;
 
+/// Resolved addresses by isolation key and hostname
+type ResolveCache = TtlCache<(Option<IsolationKey>, String), Vec<IpAddr>>;
+
 /// Main Tor client that manages circuits and HTTP requests
 pub struct TorClient {
     options: TorClientOptions,
@@ -39,24 +86,198 @@ pub struct TorClient {
     update_task: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
     /// Shutdown token for cooperative cancellation of long-running operations
     shutdown_token: CancellationToken,
+    /// Sweepers run periodically to prune expired state
+    maintenance: Maintenance,
+    /// Counters shared with the circuit, HTTP and directory subsystems
+    metrics: Metrics,
+    /// Sampled entry guards (the configured bridges in bridge mode)
+    guards: Arc<RwLock<GuardManager>>,
+    /// Configured bridges in failover order, with their failure backoff
+    bridges: BridgeSet,
+    /// How far the latest bootstrap attempt got
+    bootstrap: BootstrapProgress,
+    /// Relays temporarily avoided after failing to extend
+    reachability: ReachabilityTracker,
+    /// Layer-2 guards for long-lived circuits, if vanguards-lite is enabled
+    vanguards: Option<Arc<RwLock<VanguardManager>>>,
+    /// Circuit build timeout learned from earlier builds
+    build_timeouts: BuildTimeoutEstimator,
+    /// Where guards, bridge health and build times are persisted
+    store: Arc<dyn StateStore>,
+    /// Circuit and stream lifecycle events
+    events: CircuitEvents,
+    /// TLS sessions shared by requests, WebSockets and TLS streams, if
+    /// resumption is on
+    tls_sessions: Option<TlsSessionCache>,
+    /// Circuits joined to onion services
+    onion: Arc<OnionConnector>,
+    /// Addresses from recent lookups, by isolation key and hostname
+    resolved: Arc<Mutex<ResolveCache>>,
+    /// Scope all streams are confined to, for an
+    /// [`isolated_client`](Self::isolated_client)
+    isolation_domain: Option<IsolationDomain>,
+    /// Last use, for dormant mode
+    activity: Activity,
 }
 
 impl TorClient {
+    /// Create a new Tor client from options given as JSON (see
+    /// [`TorClientOptions::from_json`])
+    pub async fn from_json(json: &str) -> Result<Self> {
+        Self::new(TorClientOptions::from_json(json)?).await
+    }
+
     /// Create a new Tor client with the given options
     pub async fn new(options: TorClientOptions) -> Result<Self> {
         info!("TorClient::new START");
 
+        // Fail early, with the missing API named, if the browser can't run the bridge
+        #[cfg(target_arch = "wasm32")]
+        {
+            let capabilities = crate::capabilities::Capabilities::detect();
+            for missing in capabilities.report().unavailable {
+                debug!("{} unavailable: {}", missing.feature, missing.reason);
+            }
+            if options.direct {
+                if let Some(reason) = capabilities.direct_unavailable() {
+                    return Err(TorError::Configuration(reason));
+                }
+            }
+            if options.upstream_proxy.is_some() {
+                return Err(TorError::configuration(
+                    "An upstream proxy needs raw TCP sockets, which browsers don't provide",
+                ));
+            }
+            // Bridges the browser can't run just fail over to the next
+            let bridges = options.bridges();
+            if !options.direct
+                && bridges
+                    .iter()
+                    .all(|b| capabilities.bridge_unavailable(&b.bridge).is_some())
+            {
+                capabilities.check_bridge(&options.bridge)?;
+            }
+        }
+
+        let client = Self::build(options).await?;
+        if client.bridges.len() > 1 {
+            if let Some(interval) = client.options.bridge_probe_interval_duration() {
+                client.spawn_bridge_prober(interval);
+            }
+        }
+        if let Some(idle) = client.options.dormant_after_duration() {
+            client.spawn_dormancy_watcher(idle);
+        }
+        #[cfg(target_arch = "wasm32")]
+        if client.options.visibility_policy != VisibilityPolicy::Ignore {
+            client.spawn_visibility_watcher();
+        }
+
+        // Create initial circuit if requested
+        if client.options.create_circuit_early {
+            info!("Establishing connection early");
+
+            // Establish the channel
+            info!("TorClient::new: calling establish_channel");
+            if let Err(e) = client.establish_channel().await {
+                error!("Failed to establish channel: {}", e);
+                // Don't fail the client creation, just log the error
+            }
+            info!("TorClient::new: establish_channel returned");
+        }
+
+        info!("TorClient::new RETURNING");
+        Ok(client)
+    }
+
+    /// Create a Tor client over a caller-provided transport stream
+    ///
+    /// The stream must already carry Tor link TLS to the bridge identified by
+    /// `options.bridge_fingerprint`; the configured bridge type is not used.
+    /// This is the hook for pluggable transports that live outside this crate.
+    pub async fn with_stream<S>(options: TorClientOptions, stream: S) -> Result<Self>
+    where
+        S: futures::AsyncRead
+            + futures::AsyncWrite
+            + Send
+            + Unpin
+            + tor_rtcompat::StreamOps
+            + tor_rtcompat::CertifiedConn
+            + 'static,
+    {
+        let fingerprint = options.bridge_fingerprint.clone().ok_or_else(|| {
+            TorError::Configuration(
+                "Bridge fingerprint is required for custom transports".to_string(),
+            )
+        })?;
+        let expected = ExpectedIdentity::parse(&fingerprint, options.bridge_ed25519_id.as_deref())?;
+
+        let mut client = Self::build(options).await?;
+        // The caller owns the transport, so there is nothing to fail over to
+        client.bridges = BridgeSet::default();
+        client.bootstrap.start();
+        let result = client
+            .watch_bootstrap(async {
+                client.log("Using caller-provided transport stream", LogType::Info);
+                let chan = client
+                    .dialer()
+                    .create_channel_from_stream(stream, expected)
+                    .await?;
+                client.install_channel(chan).await
+            })
+            .await;
+        client.record_guard_outcome(&fingerprint, &result).await;
+        result?;
+
+        Ok(client)
+    }
+
+    /// Construct the client state without connecting to the bridge
+    async fn build(options: TorClientOptions) -> Result<Self> {
         // Initialize WASM modules (placeholder for now)
         Self::init_wasm_modules().await?;
+        options.tls_roots.validate()?;
 
         // Channel storage
         let channel = Arc::new(RwLock::new(None));
 
         // Create relay manager with empty relay list (will be populated later)
-        let relay_manager = RelayManager::new(Vec::new());
+        let reachability = ReachabilityTracker::new(options.reachability);
+        let mut relay_manager =
+            RelayManager::new(Vec::new()).with_reachability(reachability.clone());
+        if let Some(geoip) = &options.geoip {
+            relay_manager = relay_manager.with_geoip(geoip.clone());
+        } else if !options.exclude_exit_countries.is_empty() {
+            warn!(
+                "exclude_exit_countries is set but no GeoIP table is loaded; only \"??\" can match"
+            );
+        }
+        relay_manager = relay_manager.with_excluded_nodes(NodeSet::parse(&options.exclude_nodes)?);
+        if let Some(rng) = options.path_selection_rng() {
+            warn!("Relay selection uses a fixed random source; circuits are predictable");
+            relay_manager = relay_manager.with_rng(rng);
+        }
         let relay_manager_arc = Arc::new(RwLock::new(relay_manager));
 
-        let directory_manager = Arc::new(DirectoryManager::new(relay_manager_arc.clone()));
+        let store: Arc<dyn StateStore> = match &options.state_store {
+            Some(handle) => handle.0.clone(),
+            None => Arc::new(MemoryStore::new()),
+        };
+        let build_timeouts =
+            BuildTimeoutEstimator::new(options.build_timeout).with_store(store.clone());
+
+        let metrics = Metrics::new();
+        let events = CircuitEvents::new();
+        let bootstrap = options.bootstrap_progress.clone().unwrap_or_default();
+        let mut directory_manager = DirectoryManager::new(relay_manager_arc.clone())
+            .with_metrics(metrics.clone())
+            .with_bootstrap_progress(bootstrap.clone())
+            .with_build_timeouts(build_timeouts.clone())
+            .with_events(events.clone());
+        if let Some(cache) = &options.directory_cache {
+            directory_manager = directory_manager.with_cache(cache.clone());
+        }
+        let directory_manager = Arc::new(directory_manager);
 
         // Load cached consensus to populate relay manager
         // This is essential for WASM where we need relays before we can fetch fresh consensus
@@ -66,43 +287,145 @@ impl TorClient {
             return Err(e);
         }
 
-        let circuit_manager = Arc::new(RwLock::new(CircuitManager::new(
-            relay_manager_arc.clone(),
-            channel.clone(),
-        )));
-        let http_client = TorHttpClient::new(circuit_manager.clone(), options.stream_isolation);
+        let vanguards = options
+            .vanguards_lite
+            .then(|| Arc::new(RwLock::new(VanguardManager::load(store.clone()))));
+
+        let mut circuit_manager = CircuitManager::new(relay_manager_arc.clone(), channel.clone())
+            .with_metrics(metrics.clone())
+            .with_bootstrap_progress(bootstrap.clone())
+            .with_reachability(reachability.clone())
+            .with_max_dirtiness(options.max_circuit_dirtiness_duration())
+            .with_build_timeouts(build_timeouts.clone())
+            .with_congestion_control(options.congestion_control)
+            .with_conflux(options.conflux)
+            .with_optimistic_data(options.optimistic_data)
+            .with_max_streams_per_circuit(options.max_streams_per_circuit)
+            .with_parallel_builds(options.parallel_circuit_builds)
+            .with_events(events.clone())
+            .with_excluded_exit_countries(&options.exclude_exit_countries)
+            .with_exit_nodes(NodeSet::parse(&options.exit_nodes)?);
+        if let Some(vanguards) = &vanguards {
+            circuit_manager = circuit_manager.with_vanguards(vanguards.clone());
+        }
+        circuit_manager =
+            circuit_manager.with_hs_descriptor_cache(HsDescCache::load(store.clone()));
+        if let Some(key) = &options.bridge_ntor_key {
+            circuit_manager = circuit_manager.with_bridge_ntor_key(parse_ntor_key(key)?);
+        }
+        let circuit_manager = Arc::new(RwLock::new(circuit_manager));
+        let onion = Arc::new(OnionConnector::new(circuit_manager.clone()));
+        let http_client = TorHttpClient::new(circuit_manager.clone(), options.stream_isolation)
+            .with_hostname_policy(options.hostname_policy.clone())
+            .with_metrics(metrics.clone())
+            .with_stream_retries(options.stream_retries)
+            .with_redirect_policy(options.redirect_policy.clone())
+            .with_decompression(options.decompress_responses)
+            .with_max_response_size(options.max_response_size)
+            .with_tls_roots(options.tls_roots.clone())
+            .with_ech_configs(options.ech_configs.clone())
+            .with_request_profile(options.request_profile)
+            .with_onion_connector(onion.clone())
+            .with_events(events.clone());
+        let http_client = if options.cookies {
+            http_client.with_cookie_jar(CookieJar::new())
+        } else {
+            http_client
+        };
+        let tls_sessions = (options.tls_session_resumption
+            && options.request_profile.resumes_tls())
+        .then(TlsSessionCache::new);
+        let http_client = match &tls_sessions {
+            Some(cache) => http_client.with_tls_sessions(cache.clone()),
+            None => http_client,
+        };
+
+        let maintenance = Maintenance::new();
+        let circuits = circuit_manager.clone();
+        maintenance.register("circuits", move || {
+            let circuits = circuits.clone();
+            async move {
+                let circuit_manager = circuits.read().await;
+                circuit_manager.cleanup_circuits().await.unwrap_or(0)
+            }
+        });
+        let circuits = circuit_manager.clone();
+        maintenance.register("dirty_circuits", move || {
+            let circuits = circuits.clone();
+            async move { circuits.read().await.replace_dirty_circuits().await }
+        });
+        let tracker = reachability.clone();
+        maintenance.register("reachability", move || {
+            let pruned = tracker.prune();
+            async move { pruned }
+        });
+        let resolved = Arc::new(Mutex::new(TtlCache::new(RESOLVE_CACHE_TTL)));
+        maintenance.register_cache("resolved", resolved.clone());
+        let circuits = circuit_manager.clone();
+        maintenance.register("hs_descriptors", move || {
+            let circuits = circuits.clone();
+            async move {
+                let descriptors = circuits.read().await.hs_descriptors().clone();
+                let purged = descriptors.write().await.purge_expired(system_time_now());
+                purged
+            }
+        });
+        if let Some(jar) = http_client.cookie_jar().cloned() {
+            maintenance.register("cookies", move || {
+                let purged = jar.purge_expired();
+                async move { purged }
+            });
+        }
+        if let Some(sessions) = &tls_sessions {
+            maintenance.register_cache("tls_sessions", sessions.partitions());
+        }
+
+        let mut guards = GuardManager::load(store.clone());
+        // Through bridges every circuit enters at a bridge, so the bridges
+        // are the guards; direct connections sample relays from the consensus
+        let bridges = if options.direct {
+            BridgeSet::default()
+        } else {
+            BridgeSet::new(options.bridges()).with_store(store.clone())
+        };
+        sample_bridge_guards(&mut guards, &options);
+
+        let shutdown_token = CancellationToken::new();
+        if let Some(interval) = options.maintenance_interval_duration() {
+            maintenance.spawn(interval, shutdown_token.clone());
+        }
 
-        let client = Self {
-            options: options.clone(),
+        Ok(Self {
+            options,
             circuit_manager,
             directory_manager,
             http_client: Arc::new(http_client),
             is_initialized: Arc::new(RwLock::new(false)),
             channel,
             update_task: Arc::new(RwLock::new(None)),
-            shutdown_token: CancellationToken::new(),
-        };
-
-        // Create initial circuit if requested
-        if options.create_circuit_early {
-            info!("Establishing connection early");
-
-            // Establish the channel
-            info!("TorClient::new: calling establish_channel");
-            if let Err(e) = client.establish_channel().await {
-                error!("Failed to establish channel: {}", e);
-                // Don't fail the client creation, just log the error
-            }
-            info!("TorClient::new: establish_channel returned");
-        }
-
-        info!("TorClient::new RETURNING");
-        Ok(client)
+            shutdown_token,
+            maintenance,
+            metrics,
+            guards: Arc::new(RwLock::new(guards)),
+            bridges,
+            bootstrap,
+            reachability,
+            vanguards,
+            build_timeouts,
+            store,
+            events,
+            tls_sessions,
+            onion,
+            resolved,
+            isolation_domain: None,
+            activity: Activity::new(),
+        })
     }
 
     /// Bootstrap the client by fetching consensus
     pub async fn bootstrap(&self) -> Result<()> {
         self.log("Bootstrapping Tor client...", LogType::Info);
+        self.recover_lost_channel().await;
 
         // Ensure channel is established
         let channel_guard = self.channel.read().await;
@@ -160,12 +483,113 @@ impl TorClient {
 
     /// Make a fetch request through the persistent Tor circuit
     pub async fn fetch(&self, url: &str) -> Result<HttpResponse> {
-        self.log(&format!("Starting fetch request to {}", url), LogType::Info);
-
         let url = Url::parse(url)?;
         let request = HttpRequest::new(url);
+        self.log(
+            &format!(
+                "Starting fetch request {} to {}",
+                request.request_id, request.url
+            ),
+            LogType::Info,
+        );
+        self.send(request).await
+    }
+
+    /// Make a fetch request on circuits reserved for `token`
+    ///
+    /// Requests with different tokens never share a circuit, so an app can
+    /// keep several identities apart inside one client.
+    pub async fn fetch_isolated(
+        &self,
+        url: &str,
+        token: impl Into<IsolationToken>,
+    ) -> Result<HttpResponse> {
+        let request = HttpRequest::new(Url::parse(url)?).with_isolation_token(token);
+        self.send(request).await
+    }
+
+    /// A client sharing this one's channel, directory and guards whose
+    /// streams all live in an isolation domain of their own
+    ///
+    /// Requests, streams, WebSockets and lookups of the child never share a
+    /// circuit, cookies or TLS sessions with this client or with any other
+    /// isolated client, so an app can run one per account at the cost of a
+    /// few extra circuits. Isolation tokens and the stream isolation policy
+    /// still separate streams within the child. The child is a handle like a
+    /// clone, so closing either closes both.
+    pub fn isolated_client(&self) -> TorClient {
+        let domain = IsolationDomain::unique();
+        let http_client = self
+            .http_client
+            .as_ref()
+            .clone()
+            .with_isolation_domain(domain);
+        let mut client = self.clone();
+        client.http_client = Arc::new(http_client);
+        client.isolation_domain = Some(domain);
+        client
+    }
+
+    /// Send a fully configured request
+    ///
+    /// A failure carries `request.request_id` ([`TorError::request_id`]) and
+    /// is also reported on the [`subscribe`](Self::subscribe) stream as an
+    /// error event with that ID.
+    pub async fn send(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let request_id = request.request_id.clone();
+        let result = match self.reconnect_if_channel_lost().await {
+            Ok(()) => self.http_client.request(request).await,
+            Err(e) => Err(e.with_request_id(request_id.clone())),
+        };
+        if let Err(e) = &result {
+            self.events
+                .operation_failed("request", e, Some(&request_id));
+        }
+        result
+    }
 
-        self.http_client.request(request).await
+    /// Download `request`'s resource, continuing on a new circuit from the
+    /// last byte received when the transfer is cut off, up to `max_resumes`
+    /// times
+    ///
+    /// Each continuation asks for the rest with `Range` and `If-Range`, so
+    /// a resource that changed meanwhile is fetched whole again. Bodies are
+    /// requested uncompressed, so byte offsets line up. A response other
+    /// than 200 is returned as it is.
+    pub async fn download(&self, request: HttpRequest, max_resumes: u32) -> Result<HttpResponse> {
+        let request = request
+            .with_header("Accept-Encoding", "identity")
+            .with_decompression(false);
+        let response = self.send(request.clone()).await?;
+        if response.status != 200 {
+            return Ok(response);
+        }
+        let mut download = PartialDownload::new(response)?;
+        let mut resumes = 0;
+        while !download.is_complete() {
+            if resumes == max_resumes {
+                return Err(TorError::http_request(format!(
+                    "Download of {} stopped at {} of {} bytes",
+                    request.url,
+                    download.received(),
+                    download.total().unwrap_or_default()
+                )));
+            }
+            resumes += 1;
+            info!(
+                "Resuming download of {} at byte {} ({}/{})",
+                request.url,
+                download.received(),
+                resumes,
+                max_resumes
+            );
+            match self.send(download.resume_request(&request)).await {
+                Ok(response) => download.apply(response)?,
+                Err(e) if e.is_retryable() => warn!("Resuming {} failed: {}", request.url, e),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(download.into_response())
     }
 
     /// Make a GET request
@@ -174,13 +598,36 @@ impl TorClient {
     }
 
     /// Make a POST request
-    pub async fn post(&self, url: &str, body: Vec<u8>) -> Result<HttpResponse> {
-        let url = Url::parse(url)?;
-        let request = HttpRequest::new(url)
-            .with_method(Method::POST)
-            .with_body(body);
+    pub async fn post(&self, url: &str, body: impl Into<Vec<u8>>) -> Result<HttpResponse> {
+        self.send_with_body(Method::POST, url, body).await
+    }
+
+    /// Make a PUT request
+    pub async fn put(&self, url: &str, body: impl Into<Vec<u8>>) -> Result<HttpResponse> {
+        self.send_with_body(Method::PUT, url, body).await
+    }
+
+    /// Make a PATCH request
+    pub async fn patch(&self, url: &str, body: impl Into<Vec<u8>>) -> Result<HttpResponse> {
+        self.send_with_body(Method::PATCH, url, body).await
+    }
+
+    /// Make a DELETE request
+    pub async fn delete(&self, url: &str) -> Result<HttpResponse> {
+        let request = HttpRequest::new(Url::parse(url)?).with_method(Method::DELETE);
+        self.send(request).await
+    }
 
-        self.http_client.request(request).await
+    async fn send_with_body(
+        &self,
+        method: Method,
+        url: &str,
+        body: impl Into<Vec<u8>>,
+    ) -> Result<HttpResponse> {
+        let request = HttpRequest::new(Url::parse(url)?)
+            .with_method(method)
+            .with_body(body);
+        self.send(request).await
     }
 
     /// Make a generic HTTP request with full control over method, headers, body, and timeout
@@ -207,7 +654,295 @@ impl TorClient {
             request = request.with_timeout(timeout);
         }
 
-        self.http_client.request(request).await
+        self.send(request).await
+    }
+
+    /// Open a raw TCP stream to `host:port` through a Tor circuit
+    ///
+    /// The exit relay resolves `host`, so hostnames don't leak to local DNS.
+    /// Circuits are chosen according to the configured stream isolation policy,
+    /// and `host` is checked against the configured hostname policy first.
+    ///
+    /// The returned stream counts its bytes, which also show up in
+    /// [`traffic_stats`](Self::traffic_stats).
+    pub async fn connect(&self, host: &str, port: u16) -> Result<TorStream> {
+        self.open_stream(host, port, None).await
+    }
+
+    /// Like [`connect`](Self::connect), over the circuit `circuit_id` only
+    pub async fn connect_on_circuit(
+        &self,
+        circuit_id: &str,
+        host: &str,
+        port: u16,
+    ) -> Result<TorStream> {
+        let result = async {
+            let host = &self.options.hostname_policy.apply(host)?;
+            onion::check_exit_host(host)?;
+            let circuit = self
+                .circuit_manager
+                .read()
+                .await
+                .get_pinned_circuit(circuit_id, port)
+                .await?;
+
+            let result = circuit.read().await.begin_stream(host, port).await;
+            self.metrics.record_stream(result.is_ok());
+            result
+        };
+        self.reported("connect", result.await)
+    }
+
+    /// Like [`connect`](Self::connect), on circuits reserved for `token`
+    pub async fn connect_isolated(
+        &self,
+        host: &str,
+        port: u16,
+        token: impl Into<IsolationToken>,
+    ) -> Result<TorStream> {
+        self.open_stream(host, port, Some(token.into())).await
+    }
+
+    /// Like [`connect`](Self::connect), then run a TLS handshake with `host`
+    /// offering `alpn_protocols` (e.g. `["h2", "http/1.1"]`, most preferred
+    /// first)
+    ///
+    /// The chain is verified against the configured roots; the protocol
+    /// the server selected is [`TorTlsStream::alpn_protocol`].
+    pub async fn connect_tls(
+        &self,
+        host: &str,
+        port: u16,
+        alpn_protocols: &[&str],
+    ) -> Result<TorTlsStream> {
+        let host = self.options.hostname_policy.apply(host)?;
+        let stream = self.open_stream(&host, port, None).await?;
+        let alpn: Vec<String> = alpn_protocols.iter().map(|p| p.to_string()).collect();
+        let sessions = self.tls_sessions_for(&host, port, None);
+        TorTlsStream::connect(
+            stream,
+            &host,
+            &self.options.tls_roots,
+            &alpn,
+            &[],
+            sessions.as_ref(),
+            &self.options.ech_configs,
+        )
+        .await
+    }
+
+    async fn open_stream(
+        &self,
+        host: &str,
+        port: u16,
+        token: Option<IsolationToken>,
+    ) -> Result<TorStream> {
+        let result = self.try_open_stream(host, port, token).await;
+        self.reported("connect", result)
+    }
+
+    async fn try_open_stream(
+        &self,
+        host: &str,
+        port: u16,
+        token: Option<IsolationToken>,
+    ) -> Result<TorStream> {
+        let host = &self.options.hostname_policy.apply(host)?;
+        let service = onion::is_onion_host(host)
+            .then(|| onion::service_id(host))
+            .transpose()?;
+        self.ensure_ready().await?;
+
+        let isolation_key = self.isolation_key(host, port, token.as_ref());
+        if let Some(service) = service {
+            let result = self.onion.connect(service, port, isolation_key).await;
+            self.metrics.record_stream(result.is_ok());
+            return result;
+        }
+        let circuit_manager = self.circuit_manager.read().await;
+        let circuit = circuit_manager
+            .get_circuit_for_isolation_key(isolation_key, port)
+            .await?;
+
+        let result = circuit.read().await.begin_stream(host, port).await;
+        self.metrics.record_stream(result.is_ok());
+        result
+    }
+
+    /// The isolation key of streams to `host:port` under the stream
+    /// isolation policy and `token`
+    fn isolation_key(
+        &self,
+        host: &str,
+        port: u16,
+        token: Option<&IsolationToken>,
+    ) -> Option<IsolationKey> {
+        IsolationKey::scoped(
+            IsolationKey::tagged(
+                IsolationKey::from_host(host, port, self.options.stream_isolation),
+                token,
+            ),
+            self.isolation_domain,
+        )
+    }
+
+    /// The TLS sessions a connection to `host:port` may resume: those of
+    /// its isolation key, so separate identities never share a session
+    fn tls_sessions_for(
+        &self,
+        host: &str,
+        port: u16,
+        token: Option<&IsolationToken>,
+    ) -> Option<TlsSessions> {
+        let cache = self.tls_sessions.as_ref()?;
+        Some(cache.partition(self.isolation_key(host, port, token).as_ref()))
+    }
+
+    /// Open a WebSocket (`ws://` or `wss://`) through a Tor exit
+    ///
+    /// The handshake and frames run over a Tor stream, with TLS to the
+    /// server for `wss://`. The stream follows the stream isolation policy
+    /// and the request's isolation token; messages are capped at
+    /// `max_response_size` unless the request overrides it.
+    pub async fn websocket(&self, request: WebSocketRequest) -> Result<TorWebSocket> {
+        let (host, port) = request.target()?;
+        request.is_secure()?;
+        let max_message_size = request
+            .max_message_size
+            .unwrap_or(self.options.max_response_size);
+        with_timeout_and_cancellation(request.timeout, "websocket", &self.shutdown_token, async {
+            let stream = self
+                .open_stream(host, port, request.isolation_token.clone())
+                .await?;
+            let sessions = self.tls_sessions_for(host, port, request.isolation_token.as_ref());
+            TorWebSocket::connect(
+                stream,
+                &request,
+                &self.options.tls_roots,
+                sessions.as_ref(),
+                &self.options.ech_configs,
+                max_message_size,
+            )
+            .await
+        })
+        .await
+    }
+
+    /// Open a Server-Sent Events stream (`EventSource`) through a Tor exit
+    ///
+    /// Connections follow the stream isolation policy and the request's
+    /// isolation token, and events are capped at `max_response_size` unless
+    /// the request overrides it. Fails if the first connection does; later
+    /// ones are retried as [`TorEventSource::recv`] goes.
+    pub async fn event_source(&self, request: EventSourceRequest) -> Result<TorEventSource> {
+        request.target()?;
+        request.is_secure()?;
+        let max_event_size = request
+            .max_event_size
+            .unwrap_or(self.options.max_response_size);
+        TorEventSource::open(self.clone(), request, max_event_size).await
+    }
+
+    /// Open one connection of an event source, resuming after
+    /// `last_event_id`; `None` if the server said not to reconnect
+    pub(crate) async fn open_event_stream(
+        &self,
+        request: &EventSourceRequest,
+        last_event_id: &str,
+    ) -> Result<Option<EventStream>> {
+        let (host, port) = request.target()?;
+        let token = request.isolation_token.as_ref();
+        with_timeout_and_cancellation(
+            request.timeout,
+            "event source",
+            &self.shutdown_token,
+            async {
+                let stream = self.open_stream(host, port, token.cloned()).await?;
+                if !request.is_secure()? {
+                    return EventStream::open(stream, request, last_event_id).await;
+                }
+                // Events come as one long HTTP/1.1 response
+                let alpn = ["http/1.1".to_string()];
+                let sessions = self.tls_sessions_for(host, port, token);
+                let tls_stream = TorTlsStream::connect(
+                    stream,
+                    host,
+                    &self.options.tls_roots,
+                    &alpn,
+                    &request.cert_pins,
+                    sessions.as_ref(),
+                    &self.options.ech_configs,
+                )
+                .await?;
+                EventStream::open(tls_stream, request, last_event_id).await
+            },
+        )
+        .await
+    }
+
+    /// Resolve `hostname` to its IPv4/IPv6 addresses through a Tor exit
+    ///
+    /// The lookup goes out as a RELAY_RESOLVE cell, so nothing reaches the
+    /// local resolver. Like [`connect`](Self::connect), the circuit follows
+    /// the stream isolation policy and `hostname` must pass the hostname
+    /// policy. Fails with a `RESOLVEFAILED` stream end if the exit finds no
+    /// addresses. Answers are reused for
+    /// [`RESOLVE_CACHE_TTL`](crate::config::RESOLVE_CACHE_TTL) by lookups
+    /// under the same isolation key.
+    ///
+    /// A `.onion` name never goes to an exit and has no addresses:
+    /// resolving it joins a circuit to its service, which later streams
+    /// reuse, and fails as [`connect`](Self::connect) would if the service
+    /// can't be reached.
+    pub async fn resolve(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        let hostname = &self.options.hostname_policy.apply(hostname)?;
+        let isolation_key = self.resolver_isolation_key(hostname);
+        if onion::is_onion_host(hostname) {
+            let service = onion::service_id(hostname)?;
+            self.ensure_ready().await?;
+            self.onion.circuit(service, isolation_key).await?;
+            return Ok(Vec::new());
+        }
+        let cache_key = (isolation_key.clone(), hostname.to_string());
+        if let Some(addrs) = lock(&self.resolved).get(&cache_key) {
+            return Ok(addrs.clone());
+        }
+
+        let circuit = self.resolver_circuit(isolation_key).await?;
+        let result = circuit.read().await.resolve(hostname).await;
+        self.metrics.record_stream(result.is_ok());
+        if let Ok(addrs) = &result {
+            lock(&self.resolved).insert(cache_key, addrs.clone());
+        }
+        result
+    }
+
+    /// Reverse-resolve `addr` to hostnames (a PTR lookup) through a Tor exit
+    pub async fn resolve_ptr(&self, addr: IpAddr) -> Result<Vec<String>> {
+        let isolation_key = self.resolver_isolation_key(&addr.to_string());
+        let circuit = self.resolver_circuit(isolation_key).await?;
+        let result = circuit.read().await.resolve_ptr(addr).await;
+        self.metrics.record_stream(result.is_ok());
+        result
+    }
+
+    fn resolver_isolation_key(&self, host: &str) -> Option<IsolationKey> {
+        IsolationKey::scoped(
+            IsolationKey::from_host(host, PREBUILD_EXIT_PORT, self.options.stream_isolation),
+            self.isolation_domain,
+        )
+    }
+
+    async fn resolver_circuit(
+        &self,
+        isolation_key: Option<IsolationKey>,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        self.ensure_ready().await?;
+        self.circuit_manager
+            .read()
+            .await
+            .get_circuit_for_isolation_key(isolation_key, PREBUILD_EXIT_PORT)
+            .await
     }
 
     /// Update the circuit by creating a new one
@@ -267,23 +1002,133 @@ impl TorClient {
         circuit_manager.get_circuit_status().await
     }
 
-    /// Get human-readable circuit status string
-    pub async fn get_circuit_status_string(&self) -> String {
-        let status = self.get_circuit_status().await;
+    /// Snapshot of the client's counters
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
 
-        if !status.has_ready_circuits() && status.creating_circuits > 0 {
-            return "Creating...".to_string();
-        }
+    /// Traffic and round-trip time to the bridge for each transport used,
+    /// to tell a slow bridge connection from a slow circuit
+    pub fn transport_stats(&self) -> Vec<TransportStatsSnapshot> {
+        self.metrics.transport_stats()
+    }
 
-        if !status.has_ready_circuits() {
-            return "None".to_string();
-        }
+    /// Timeout currently applied to circuit builds
+    pub fn circuit_build_timeout(&self) -> Duration {
+        self.build_timeouts.timeout()
+    }
+
+    /// Stream of every event from now on, in the order they happened:
+    /// bootstrap progress, circuit and stream lifecycle, onion service
+    /// connection steps, channel loss and reconnects, installed consensuses,
+    /// and failed requests and streams
+    ///
+    /// Each call returns an independent subscription; drop it to unsubscribe.
+    pub fn subscribe(&self) -> UnboundedReceiver<TorEvent> {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        self.bootstrap.forward(tx.clone());
+        self.events.forward(tx);
+        rx
+    }
+
+    /// Stream of circuit and stream lifecycle events from now on
+    ///
+    /// Each call returns an independent subscription; drop it to unsubscribe.
+    pub fn circuit_events(&self) -> UnboundedReceiver<CircuitEvent> {
+        self.events.subscribe()
+    }
+
+    /// Stream of onion service connection progress from now on: descriptor
+    /// fetched, rendezvous point established, introduction sent, and
+    /// whether the connection succeeded
+    pub fn onion_events(&self) -> UnboundedReceiver<OnionEvent> {
+        self.events.subscribe_onion()
+    }
+
+    /// Stream of bootstrap phase and percentage changes from now on; to see
+    /// the first bootstrap, subscribe to a
+    /// [`BootstrapProgress`] passed in with
+    /// [`with_bootstrap_progress`](TorClientOptions::with_bootstrap_progress)
+    pub fn bootstrap_events(&self) -> UnboundedReceiver<BootstrapStatus> {
+        self.bootstrap.subscribe()
+    }
+
+    /// Stream of channel events from now on: the bridge dropping the
+    /// channel, and each attempt to reconnect
+    pub fn channel_events(&self) -> UnboundedReceiver<ChannelEvent> {
+        self.events.subscribe_channel()
+    }
+
+    /// Cookies recorded from responses, if the `cookies` option is on
+    pub fn cookie_jar(&self) -> Option<&CookieJar> {
+        self.http_client.cookie_jar()
+    }
 
-        if status.failed_circuits > 0 {
-            return format!("Ready ({} failed circuits)", status.failed_circuits);
+    /// Forget every stored cookie
+    pub fn clear_cookies(&self) {
+        if let Some(jar) = self.cookie_jar() {
+            jar.clear();
         }
+    }
+
+    /// Bytes read and written through Tor since startup, and per current circuit
+    pub async fn traffic_stats(&self) -> TrafficStats {
+        self.circuit_manager.read().await.traffic_stats().await
+    }
+
+    /// [`traffic_stats`](Self::traffic_stats) every `interval` until the client is closed
+    pub fn traffic_updates(&self, interval: Duration) -> impl Stream<Item = TrafficStats> {
+        let circuit_manager = self.circuit_manager.clone();
+        let shutdown = self.shutdown_token.clone();
+        futures::stream::unfold((), move |()| {
+            let circuit_manager = circuit_manager.clone();
+            let shutdown = shutdown.clone();
+            async move {
+                with_cancellation(&shutdown, async {
+                    sleep(interval).await;
+                    Ok(())
+                })
+                .await
+                .ok()?;
+                let stats = circuit_manager.read().await.traffic_stats().await;
+                Some((stats, ()))
+            }
+        })
+    }
+
+    /// Render client metrics in Prometheus text exposition format
+    pub async fn metrics_prometheus(&self) -> String {
+        let status = self.get_circuit_status().await;
+        self.metrics.render_prometheus(&status)
+    }
+
+    /// Serve Prometheus metrics on a local HTTP endpoint until the client is closed
+    ///
+    /// Returns the bound address, so `127.0.0.1:0` can be used to pick a free port.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn serve_metrics(&self, addr: std::net::SocketAddr) -> Result<std::net::SocketAddr> {
+        let metrics = self.metrics.clone();
+        let circuit_manager = self.circuit_manager.clone();
+        crate::metrics::serve_prometheus(addr, self.shutdown_token.clone(), move || {
+            let metrics = metrics.clone();
+            let circuit_manager = circuit_manager.clone();
+            async move {
+                let status = circuit_manager.read().await.get_circuit_status().await;
+                metrics.render_prometheus(&status)
+            }
+        })
+        .await
+    }
+
+    /// Get human-readable circuit status string
+    pub async fn get_circuit_status_string(&self) -> String {
+        self.get_circuit_status().await.summary()
+    }
 
-        "Ready".to_string()
+    /// Every circuit with its purpose, state, hops, age and traffic, plus
+    /// the totals, as a serializable report
+    pub async fn circuit_status_report(&self) -> CircuitStatusReport {
+        self.circuit_manager.read().await.status_report().await
     }
 
     /// Get relay information from the current circuit
@@ -292,19 +1137,212 @@ impl TorClient {
         circuit_manager.get_circuit_relays().await
     }
 
-    /// Ensure the client is ready for making requests
-    pub async fn ensure_ready(&self) -> Result<()> {
-        // Establish channel if not already done
-        if !*self.is_initialized.read().await {
-            self.establish_channel().await?;
-        }
-
-        Ok(())
+    /// IDs of the ready circuits, for use with [`http_on_circuit`](Self::http_on_circuit)
+    pub async fn circuit_ids(&self) -> Vec<String> {
+        self.circuit_manager.read().await.ready_circuit_ids().await
+    }
+
+    /// Relay information for a specific circuit
+    pub async fn get_circuit_relays_by_id(
+        &self,
+        circuit_id: &str,
+    ) -> Option<Vec<crate::circuit::CircuitRelayInfo>> {
+        let circuit = self
+            .circuit_manager
+            .read()
+            .await
+            .get_circuit(circuit_id)
+            .await?;
+        let relays = circuit.read().await.relay_info();
+        Some(relays)
+    }
+
+    /// Build a fresh circuit that only circuit-bound HTTP clients will use
+    ///
+    /// Returns the new circuit's ID. Measurement tools can build several and
+    /// compare their exits with [`http_on_circuit`](Self::http_on_circuit).
+    pub async fn build_circuit(&self) -> Result<String> {
+        self.ensure_ready().await?;
+        let circuit_manager = self.circuit_manager.read().await;
+        let circuit = circuit_manager.create_reserved_circuit().await?;
+        let id = circuit.read().await.id.clone();
+        Ok(id)
+    }
+
+    /// Build a circuit through relays of the caller's choosing
+    ///
+    /// `fingerprints` lists the middle and exit, optionally preceded by the
+    /// connected bridge, which is always the first hop. The path is rejected
+    /// if a relay is missing from the consensus, lacks the flags for its
+    /// position, or is related to another hop. Returns the circuit ID for
+    /// [`http_on_circuit`](Self::http_on_circuit) and
+    /// [`connect_on_circuit`](Self::connect_on_circuit); ordinary requests
+    /// never use the circuit.
+    pub async fn build_circuit_through<S: AsRef<str>>(&self, fingerprints: &[S]) -> Result<String> {
+        let fingerprints: Vec<String> = fingerprints
+            .iter()
+            .map(|fp| fp.as_ref().to_string())
+            .collect();
+        self.ensure_ready().await?;
+        let circuit_manager = self.circuit_manager.read().await;
+        let circuit = circuit_manager
+            .create_circuit_through(&fingerprints)
+            .await?;
+        let id = circuit.read().await.id.clone();
+        Ok(id)
+    }
+
+    /// Switch to a new identity, like Tor's NEWNYM signal
+    ///
+    /// All current circuits are retired, dropping every isolation binding
+    /// and pinned circuit, so later requests and streams use freshly built
+    /// circuits, and stored cookies, resolved addresses and TLS sessions are
+    /// cleared. With
+    /// `avoid_previous_exits`, those circuits avoid the exits used so far
+    /// where possible. Returns the number of circuits retired.
+    pub async fn new_identity(&self, avoid_previous_exits: bool) -> usize {
+        self.clear_cookies();
+        self.onion.clear();
+        lock(&self.resolved).clear();
+        if let Some(sessions) = &self.tls_sessions {
+            sessions.clear();
+        }
+        let retired = self
+            .circuit_manager
+            .read()
+            .await
+            .new_identity(avoid_previous_exits)
+            .await;
+        self.log(
+            &format!("New identity: retired {} circuits", retired),
+            LogType::Info,
+        );
+        retired
+    }
+
+    /// Host an onion service at a freshly generated `.onion` address
+    ///
+    /// Returns once the descriptor is published; streams clients open to
+    /// it come from [`OnionService::next_request`]. The service lasts until
+    /// the handle is dropped or shut down, and its address is not kept.
+    pub async fn launch_onion_service(&self, config: OnionServiceConfig) -> Result<OnionService> {
+        self.ensure_ready().await?;
+        let service = OnionService::launch(self.circuit_manager.clone(), config).await?;
+        self.log(
+            &format!("Onion service launched at {}", service.address()),
+            LogType::Info,
+        );
+        Ok(service)
+    }
+
+    /// HTTP client whose requests all go over the circuit `circuit_id`
+    ///
+    /// Unlike [`request`](Self::request), the client never switches to
+    /// another circuit: requests fail once the circuit is gone.
+    pub async fn http_on_circuit(&self, circuit_id: &str) -> Result<TorHttpClient> {
+        let circuit_manager = self.circuit_manager.read().await;
+        let circuit = circuit_manager
+            .get_circuit(circuit_id)
+            .await
+            .ok_or_else(|| {
+                TorError::configuration(format!("Circuit {} does not exist", circuit_id))
+            })?;
+        if !circuit.read().await.is_ready() {
+            return Err(TorError::configuration(format!(
+                "Circuit {} is not ready",
+                circuit_id
+            )));
+        }
+        Ok(self.http_client.as_ref().clone().on_circuit(circuit_id))
+    }
+
+    /// Country code of the current circuit's exit, if a GeoIP table is loaded
+    pub async fn get_exit_country(&self) -> Option<String> {
+        self.get_circuit_relays()
+            .await?
+            .into_iter()
+            .find(|relay| relay.role == "Exit")
+            .and_then(|relay| relay.country)
+    }
+
+    /// Ensure the client is ready for making requests
+    pub async fn ensure_ready(&self) -> Result<()> {
+        self.recover_lost_channel().await;
+
+        // Establish channel if not already done
+        self.connect_lazily(true).await
+    }
+
+    /// Whether the client closed its channel after being idle for
+    /// [`dormant_after`](TorClientOptions::dormant_after); the next request
+    /// connects again
+    pub fn is_dormant(&self) -> bool {
+        self.activity.is_dormant()
+    }
+
+    /// Put the client in the background: pause channel padding and bridge
+    /// probing, and with `dormant` also close the channel and circuits as
+    /// in dormant mode unless streams are open
+    ///
+    /// Requests still work meanwhile. In a browser the
+    /// [`visibility_policy`](TorClientOptions::visibility_policy) does this
+    /// when the page is hidden.
+    pub async fn suspend(&self, dormant: bool) {
+        if !self.activity.set_suspended(true) {
+            return;
+        }
+        let dormant = dormant && self.go_dormant(Duration::ZERO).await;
+        if !dormant {
+            for chan in self.circuit_manager.read().await.open_channels().await {
+                if let Err(e) = padding::suspend(&chan, self.options.channel_padding) {
+                    debug!("Failed to pause padding: {}", e);
+                }
+            }
+        }
+        self.log("Suspended", LogType::Info);
+        self.events
+            .emit_channel(ChannelEvent::Suspended { dormant });
+    }
+
+    /// Bring the client back from [`suspend`](Self::suspend): restore
+    /// padding, fail circuits that didn't survive, and reconnect or rebuild
+    /// a circuit now rather than on the next request
+    pub async fn resume(&self) -> Result<()> {
+        if !self.activity.set_suspended(false) {
+            return Ok(());
+        }
+        let circuit_manager = self.circuit_manager.read().await;
+        for chan in circuit_manager.open_channels().await {
+            if let Err(e) = padding::resume(&chan, self.options.channel_padding) {
+                debug!("Failed to restore padding: {}", e);
+            }
+        }
+        let closed = circuit_manager.close_dead_circuits().await;
+        drop(circuit_manager);
+
+        // Also wakes the client if it went dormant
+        let reconnected = self.reconnect_if_channel_lost().await;
+        let ready = if reconnected.is_ok() && *self.is_initialized.read().await {
+            self.rebuild_circuits(1).await
+        } else {
+            0
+        };
+        self.log(
+            &format!("Resumed; {} circuits lost, {} ready", closed, ready),
+            LogType::Info,
+        );
+        self.events.emit_channel(ChannelEvent::Resumed {
+            circuits_closed: closed,
+            circuits_ready: ready,
+        });
+        reconnected
     }
 
     /// Refresh consensus by fetching from the network
     /// Returns the number of relays loaded
     pub async fn refresh_consensus(&self) -> Result<usize> {
+        self.recover_lost_channel().await;
+
         // Ensure channel is established first
         let channel_guard = self.channel.read().await;
         if channel_guard.is_none() {
@@ -327,121 +1365,983 @@ impl TorClient {
             .fetch_and_process_consensus(channel)
             .await?;
 
-        // Return relay count
-        let relay_manager = self.directory_manager.relay_manager.read().await;
-        Ok(relay_manager.relays.len())
+        // Return relay count
+        let relay_manager = self.directory_manager.relay_manager.read().await;
+        Ok(relay_manager.relays.len())
+    }
+
+    /// Get consensus status string
+    pub async fn get_consensus_status(&self) -> String {
+        let relay_manager = self.directory_manager.relay_manager.read().await;
+        let count = relay_manager.relays.len();
+        if count == 0 {
+            "No consensus loaded".to_string()
+        } else {
+            format!("{} relays loaded", count)
+        }
+    }
+
+    /// Relays from the loaded consensus matching `query`, highest bandwidth first
+    pub async fn query_relays(&self, query: &RelayQuery) -> Vec<Relay> {
+        let relay_manager = self.directory_manager.relay_manager.read().await;
+        relay_manager.query(query).into_iter().cloned().collect()
+    }
+
+    /// Fingerprints of relays currently avoided after repeated extension failures
+    pub fn blacklisted_relays(&self) -> std::collections::HashSet<String> {
+        self.reachability.blacklisted()
+    }
+
+    /// Number of relays in the loaded consensus carrying each flag
+    pub async fn relay_counts_by_flag(&self) -> std::collections::BTreeMap<String, usize> {
+        let relay_manager = self.directory_manager.relay_manager.read().await;
+        relay_manager.count_by_flag()
+    }
+
+    /// Replace the relay list with a consensus and microdescriptors from the embedder
+    ///
+    /// Both documents are validated locally (timeliness, the directory
+    /// authorities' signatures, checked with the key certificates in
+    /// `authority_certs`, and microdescriptor digests) before anything is
+    /// installed. Injecting before bootstrap spares native builds the
+    /// consensus download over the bridge.
+    pub async fn inject_directory(
+        &self,
+        consensus: &str,
+        microdescriptors: &str,
+        authority_certs: &str,
+    ) -> Result<DirectoryInjection> {
+        self.directory_manager
+            .inject_documents(consensus, microdescriptors, authority_certs)
+            .await
+    }
+
+    /// Snapshot what the client has learned (guards, bridge health, build
+    /// times and a still-valid consensus) for
+    /// [`import_state`](Self::import_state) in a later session
+    pub async fn export_state(&self) -> Result<ClientState> {
+        let directory = self.directory_manager.snapshot().await;
+        ClientState::read(&*self.store, directory)
+    }
+
+    /// Take over state from [`export_state`](Self::export_state), replacing
+    /// the guards, bridge health and build times and installing the saved
+    /// relays if they are newer than those loaded and haven't expired
+    ///
+    /// Import before the first request so bootstrap can skip the consensus
+    /// download. The state is also written to the state store.
+    pub async fn import_state(&self, state: &ClientState) -> Result<()> {
+        state.write(&*self.store)?;
+        {
+            let mut guards = self.guards.write().await;
+            *guards = GuardManager::load(self.store.clone());
+            sample_bridge_guards(&mut guards, &self.options);
+        }
+        if let Some(vanguards) = &self.vanguards {
+            *vanguards.write().await = VanguardManager::load(self.store.clone());
+        }
+        self.bridges.reload();
+        self.build_timeouts.reload();
+        let restored = match &state.directory {
+            Some(directory) => self.directory_manager.restore(directory).await,
+            None => false,
+        };
+        self.log(
+            &format!(
+                "Imported client state saved {}s ago{}",
+                now_secs().saturating_sub(state.exported_at),
+                if restored { " with its directory" } else { "" }
+            ),
+            LogType::Info,
+        );
+        Ok(())
+    }
+
+    /// Check if consensus needs refresh (stub - always returns false for now)
+    pub fn needs_consensus_refresh(&self) -> bool {
+        false
+    }
+
+    /// Close the Tor client and clean up resources
+    pub async fn close(&self) {
+        info!("Closing Tor client");
+
+        // Signal cancellation to all in-flight operations
+        self.shutdown_token.cancel();
+
+        // Stop update task if running
+        if let Some(task) = self.update_task.write().await.take() {
+            task.abort();
+        }
+
+        // Clean up circuits
+        let circuit_manager = self.circuit_manager.write().await;
+        if let Err(e) = circuit_manager.cleanup_circuits().await {
+            warn!("Error during circuit cleanup: {}", e);
+        }
+
+        *self.is_initialized.write().await = false;
+        info!("Tor client closed");
+    }
+
+    /// How far the latest bootstrap attempt got
+    ///
+    /// Useful after a failed or slow start: the report says whether the
+    /// bridge was reached, how much of the directory arrived and how many
+    /// hops the first circuit completed.
+    pub fn bootstrap_report(&self) -> BootstrapReport {
+        self.bootstrap.report()
+    }
+
+    /// Bootstrap phase and percentage, as tor reports them
+    pub fn bootstrap_status(&self) -> BootstrapStatus {
+        self.bootstrap.status()
+    }
+
+    /// Status of the configured bridges: which one is in use, which are
+    /// backing off, and each one's success rate and connection latency
+    pub fn bridge_status(&self) -> Vec<BridgeStatus> {
+        self.bridges.status()
+    }
+
+    /// Open channels to the bridge that circuits are spread across
+    pub async fn channel_count(&self) -> usize {
+        self.circuit_manager
+            .read()
+            .await
+            .open_channels()
+            .await
+            .len()
+    }
+
+    /// Probe the failing bridges now instead of at the next probe interval
+    ///
+    /// Returns how many of them work again.
+    pub async fn probe_bridges(&self) -> usize {
+        let dialer = BridgeDialer {
+            options: self.options.clone(),
+            bootstrap: None,
+            metrics: self.metrics.clone(),
+        };
+        dialer.probe(&self.bridges, &self.shutdown_token).await
+    }
+
+    /// Snapshot of the sampled entry guards and their reachability
+    pub async fn guards(&self) -> GuardSet {
+        self.guards.read().await.guards().clone()
+    }
+
+    /// Snapshot of the layer-2 guards, or `None` if vanguards-lite is disabled
+    ///
+    /// The set is drawn when the first reserved circuit is built.
+    pub async fn layer2_guards(&self) -> Option<Layer2GuardSet> {
+        match &self.vanguards {
+            Some(vanguards) => Some(vanguards.read().await.guards().clone()),
+            None => None,
+        }
+    }
+
+    /// Run one maintenance pass now instead of waiting for the periodic task
+    pub async fn run_maintenance(&self) -> MaintenanceReport {
+        self.maintenance.run_once().await
+    }
+
+    /// Abort all in-flight operations.
+    ///
+    /// This cancels long-running operations like circuit creation and HTTP requests.
+    /// Operations will return `TorError::Cancelled`.
+    pub fn abort(&self) {
+        info!("Aborting all in-flight operations");
+        self.shutdown_token.cancel();
+    }
+
+    /// Check if the client has been aborted/shutdown.
+    pub fn is_aborted(&self) -> bool {
+        self.shutdown_token.is_cancelled()
+    }
+
+    /// Get the shutdown token for advanced cancellation use cases.
+    pub fn shutdown_token(&self) -> &CancellationToken {
+        &self.shutdown_token
+    }
+
+    /// Establish the Tor channel (called during construction if requested)
+    ///
+    /// Bridges are tried in failover order until a channel comes up. Once
+    /// one does, its result is final: a circuit failure over a working
+    /// bridge isn't the bridge's fault.
+    async fn establish_channel(&self) -> Result<()> {
+        if self.options.direct {
+            return self.establish_direct_channel().await;
+        }
+        if let Some(stagger) = self.options.bridge_race_stagger_duration() {
+            if self.bridges.len() > 1 {
+                return self.race_bridges(stagger).await;
+            }
+        }
+        let dialer = self.dialer();
+        let mut last_error = None;
+        for (index, bridge) in self.bridges.connect_order() {
+            let fingerprint = match bridge.identity() {
+                Ok(fingerprint) => fingerprint,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            self.bootstrap.start();
+            let mut latency = None;
+            let result = self
+                .watch_bootstrap(async {
+                    self.log("Establishing channel", LogType::Info);
+                    let started = Instant::now();
+                    let chan = dialer.open_channel(&bridge).await?;
+                    latency = Some(started.elapsed());
+                    self.install_channel(chan).await
+                })
+                .await;
+            self.record_guard_outcome(&fingerprint, &result).await;
+            if let Some(chan) = self.channel.read().await.as_ref() {
+                self.bridges
+                    .record_success(index, latency.unwrap_or_default());
+                self.watch_channel(chan);
+                self.spawn_extra_channels(&bridge);
+                return result;
+            }
+            match result {
+                Err(TorError::Cancelled) => return result,
+                Err(e) => {
+                    self.bridges.record_failure(index);
+                    if self.bridges.len() > 1 {
+                        self.log(
+                            &format!("Bridge {} failed, trying the next: {}", index, e),
+                            LogType::Error,
+                        );
+                    }
+                    last_error = Some(e);
+                }
+                Ok(()) => return Ok(()),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| TorError::configuration("No bridge to connect through")))
+    }
+
+    /// Dial all bridges with staggered starts and build on the first channel
+    /// to complete its handshake; the slower attempts are dropped
+    async fn race_bridges(&self, stagger: Duration) -> Result<()> {
+        let dialer = self.dialer();
+        let candidates = self.bridges.connect_order();
+        self.bootstrap.start();
+        let mut winner = None;
+        let result = self
+            .watch_bootstrap(async {
+                self.log(
+                    &format!("Racing {} bridges", candidates.len()),
+                    LogType::Info,
+                );
+                let dialer = &dialer;
+                let (index, bridge, fingerprint, latency, chan) =
+                    race_staggered(candidates, stagger, |(index, bridge)| async move {
+                        let attempt = async {
+                            let fingerprint = bridge.identity()?;
+                            let started = Instant::now();
+                            let chan = dialer.open_channel(&bridge).await?;
+                            Ok((fingerprint, started.elapsed(), chan))
+                        };
+                        match attempt.await {
+                            Ok((fingerprint, latency, chan)) => {
+                                Ok((index, bridge, fingerprint, latency, chan))
+                            }
+                            Err(e) => {
+                                self.bridges.record_failure(index);
+                                if let Ok(fingerprint) = bridge.identity() {
+                                    self.guards.write().await.record_failure(&fingerprint);
+                                }
+                                self.log(
+                                    &format!("Bridge {} failed: {}", index, e),
+                                    LogType::Error,
+                                );
+                                Err(e)
+                            }
+                        }
+                    })
+                    .await?;
+                self.log(&format!("Bridge {} won the race", index), LogType::Info);
+                winner = Some((index, bridge, fingerprint, latency));
+                self.install_channel(chan).await
+            })
+            .await;
+
+        if let Some((index, bridge, fingerprint, latency)) = winner {
+            self.record_guard_outcome(&fingerprint, &result).await;
+            if let Some(chan) = self.channel.read().await.as_ref() {
+                self.bridges.record_success(index, latency);
+                self.watch_channel(chan);
+                self.spawn_extra_channels(&bridge);
+            } else if !matches!(result, Err(TorError::Cancelled)) {
+                self.bridges.record_failure(index);
+            }
+        }
+        result
+    }
+
+    /// Connect straight to a guard relay, fetching a consensus from a
+    /// fallback directory first if there is none to pick guards from
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn establish_direct_channel(&self) -> Result<()> {
+        let dialer = self.dialer();
+        let relays = self.directory_manager.relay_manager.clone();
+        if relays.read().await.relays.is_empty() {
+            self.bootstrap.start();
+            self.watch_bootstrap(self.fetch_consensus_from_fallback(&dialer))
+                .await?;
+        }
+
+        let candidates = direct::guard_candidates(&*relays.read().await);
+        if candidates.is_empty() {
+            return Err(TorError::relay_selection(
+                "No guard relays in the consensus",
+            ));
+        }
+        self.guards.write().await.update_sample(&candidates);
+
+        let mut last_error = None;
+        for _ in 0..GUARD_SAMPLE_SIZE {
+            let Some(guard) = self.guards.read().await.select().cloned() else {
+                break;
+            };
+            let Some(relay) = relays.read().await.get_relay(&guard.fingerprint).cloned() else {
+                self.guards.write().await.record_failure(&guard.fingerprint);
+                continue;
+            };
+            self.bootstrap.start();
+            let result = self
+                .watch_bootstrap(async {
+                    self.log(
+                        &format!("Connecting directly to guard {}", relay.nickname),
+                        LogType::Info,
+                    );
+                    let chan = dialer
+                        .open_relay_channel(
+                            &direct::or_addr(&relay),
+                            &relay.fingerprint,
+                            relay.ed25519_identity.as_deref(),
+                        )
+                        .await?;
+                    self.install_channel(chan).await
+                })
+                .await;
+            self.record_guard_outcome(&guard.fingerprint, &result).await;
+            if let Some(chan) = self.channel.read().await.as_ref() {
+                self.watch_channel(chan);
+                return result;
+            }
+            match result {
+                Err(TorError::Cancelled) => return result,
+                Err(e) => {
+                    self.log(
+                        &format!("Guard {} failed, trying the next: {}", relay.nickname, e),
+                        LogType::Error,
+                    );
+                    last_error = Some(e);
+                }
+                Ok(()) => return Ok(()),
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| TorError::relay_selection("Every sampled guard failed recently")))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn establish_direct_channel(&self) -> Result<()> {
+        Err(TorError::configuration(
+            "Direct relay connections need raw TCP sockets, which browsers don't provide",
+        ))
+    }
+
+    /// Fetch the consensus through the first of a few random fallback
+    /// directories that answers
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn fetch_consensus_from_fallback(&self, dialer: &BridgeDialer) -> Result<()> {
+        use rand::seq::SliceRandom;
+
+        /// Fallbacks tried before giving up; some on the shipped list are gone
+        const FALLBACK_ATTEMPTS: usize = 8;
+
+        let mut fallbacks = direct::fallback_dirs();
+        fallbacks.shuffle(&mut rand::thread_rng());
+        let mut last_error = None;
+        for fallback in fallbacks.into_iter().take(FALLBACK_ATTEMPTS) {
+            self.log(
+                &format!(
+                    "Fetching consensus from fallback directory {}",
+                    fallback.addr
+                ),
+                LogType::Info,
+            );
+            let result = match dialer
+                .open_relay_channel(
+                    &fallback.addr,
+                    &fallback.fingerprint,
+                    Some(&fallback.ed25519_identity),
+                )
+                .await
+            {
+                Ok(chan) => {
+                    let fetched = self
+                        .directory_manager
+                        .fetch_and_process_consensus(chan.clone())
+                        .await;
+                    chan.terminate();
+                    fetched
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => {
+                    self.log("Consensus fetched successfully", LogType::Success);
+                    return Ok(());
+                }
+                Err(TorError::Cancelled) => return Err(TorError::Cancelled),
+                Err(e) => {
+                    warn!("Fallback directory {} failed: {}", fallback.addr, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| TorError::configuration("No fallback directory")))
+    }
+
+    /// Probe failing bridges every `interval` while a channel is up
+    ///
+    /// The task holds no client handle, so it doesn't keep the client
+    /// alive; it stops on shutdown or once the client is gone.
+    fn spawn_bridge_prober(&self, interval: Duration) {
+        info!("Starting bridge prober (interval: {:?})", interval);
+        let dialer = BridgeDialer {
+            options: self.options.clone(),
+            bootstrap: None,
+            metrics: self.metrics.clone(),
+        };
+        let bridges = self.bridges.clone();
+        let activity = self.activity.clone();
+        let channel = Arc::downgrade(&self.channel);
+        let shutdown = self.shutdown_token.clone();
+        let task = async move {
+            loop {
+                let tick = with_cancellation(&shutdown, async {
+                    sleep(interval).await;
+                    Ok(())
+                });
+                if tick.await.is_err() {
+                    break;
+                }
+                let Some(channel) = channel.upgrade() else {
+                    break;
+                };
+                // Without a channel the client is connecting and tries every
+                // bridge itself
+                let connected = channel.read().await.is_some();
+                drop(channel);
+                // Nor is there any point waking a suspended app to probe
+                if connected && !activity.is_suspended() {
+                    dialer.probe(&bridges, &shutdown).await;
+                }
+            }
+            debug!("Bridge prober stopped");
+        };
+
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(task);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::spawn(task);
+    }
+
+    /// Open the further channels to `bridge` that `channels_per_bridge` asks
+    /// for, in the background, and spread new circuits across them
+    ///
+    /// Stops at the first failure; the primary channel alone still works.
+    /// The task holds no client handle, so it doesn't keep the client alive.
+    fn spawn_extra_channels(&self, bridge: &BridgeConfig) {
+        let wanted = self.options.channels_per_bridge.saturating_sub(1);
+        if wanted == 0 {
+            return;
+        }
+        let dialer = BridgeDialer {
+            options: self.options.clone(),
+            bootstrap: None,
+            metrics: self.metrics.clone(),
+        };
+        let bridge = bridge.clone();
+        let circuit_manager = Arc::downgrade(&self.circuit_manager);
+        let shutdown = self.shutdown_token.clone();
+        let task = async move {
+            for _ in 0..wanted {
+                let opened = with_cancellation(&shutdown, dialer.open_channel(&bridge)).await;
+                let Some(circuit_manager) = circuit_manager.upgrade() else {
+                    break;
+                };
+                match opened {
+                    Ok(chan) => {
+                        if !circuit_manager.read().await.add_channel(chan).await {
+                            break;
+                        }
+                    }
+                    Err(TorError::Cancelled) => break,
+                    Err(e) => {
+                        warn!("Failed to open another channel to the bridge: {}", e);
+                        break;
+                    }
+                }
+            }
+            debug!("Stopped opening channels to the bridge");
+        };
+
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(task);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::spawn(task);
+    }
+
+    /// Reconnect in the background as soon as `chan` closes
+    ///
+    /// The task holds a [`WeakTorClient`], so it doesn't keep the client
+    /// alive; it stops on shutdown.
+    fn watch_channel(&self, chan: &tor_proto::channel::Channel) {
+        if !self.options.reconnect.enabled {
+            return;
+        }
+        let closed = chan.wait_for_close();
+        let client = self.downgrade();
+        let task = async move {
+            let closed = with_cancellation(&client.shutdown_token, async {
+                let _ = closed.await;
+                Ok(())
+            });
+            if closed.await.is_ok() {
+                client.reconnect().await;
+            }
+        };
+
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(task);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::spawn(task);
+    }
+
+    /// Reconnect through the next bridge if the bridge closed the channel
+    async fn reconnect_if_channel_lost(&self) -> Result<()> {
+        if self.recover_lost_channel().await.is_some() {
+            self.establish_channel().await?;
+            let circuits = self.rebuild_circuits(1).await;
+            self.channel_reconnected(1, circuits);
+        }
+        self.connect_lazily(false).await
+    }
+
+    /// Note a request, and connect first if the client is dormant or, with
+    /// `initial`, if it hasn't connected yet
+    async fn connect_lazily(&self, initial: bool) -> Result<()> {
+        let dormant = self.activity.touch();
+        if !(dormant || initial) || *self.is_initialized.read().await {
+            return Ok(());
+        }
+        let _connecting = self.activity.connecting().await;
+        // Another request may have connected while this one waited
+        if *self.is_initialized.read().await {
+            return Ok(());
+        }
+        if dormant {
+            self.log("Waking from dormant mode", LogType::Info);
+        }
+        self.establish_channel().await?;
+        if self.activity.wake() {
+            let (transport, endpoint) = self.link_label(self.bridges.current());
+            self.events.emit_channel(ChannelEvent::Awake {
+                transport,
+                endpoint,
+            });
+        }
+        Ok(())
+    }
+
+    /// Go dormant once the client has been idle for `idle`
+    ///
+    /// The task holds a [`WeakTorClient`], so it doesn't keep the client
+    /// alive; it stops on shutdown.
+    fn spawn_dormancy_watcher(&self, idle: Duration) {
+        let activity = self.activity.clone();
+        let shutdown = self.shutdown_token.clone();
+        let client = self.downgrade();
+        let task = async move {
+            loop {
+                let wait = match activity.remaining(idle) {
+                    Duration::ZERO => idle,
+                    remaining => remaining,
+                };
+                let tick = with_cancellation(&shutdown, async {
+                    sleep(wait).await;
+                    Ok(())
+                });
+                if tick.await.is_err() {
+                    break;
+                }
+                let Some(client) = client.upgrade() else {
+                    break;
+                };
+                if activity.remaining(idle).is_zero() {
+                    client.go_dormant(idle).await;
+                }
+            }
+            debug!("Dormancy watcher stopped");
+        };
+
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(task);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::spawn(task);
+    }
+
+    /// Suspend while the page is hidden and resume once it is visible, as
+    /// the visibility policy says
+    ///
+    /// The task holds a [`WeakTorClient`], so it doesn't keep the client
+    /// alive; it stops on shutdown.
+    #[cfg(target_arch = "wasm32")]
+    fn spawn_visibility_watcher(&self) {
+        let Some(mut listener) = VisibilityListener::new() else {
+            warn!("No document to follow the visibility of; visibility policy ignored");
+            return;
+        };
+        let dormant = self.options.visibility_policy == VisibilityPolicy::Dormant;
+        let shutdown = self.shutdown_token.clone();
+        let client = self.downgrade();
+        wasm_bindgen_futures::spawn_local(async move {
+            let mut hidden = listener.is_hidden();
+            loop {
+                let Some(client) = client.upgrade() else {
+                    break;
+                };
+                if hidden {
+                    client.suspend(dormant).await;
+                } else if let Err(e) = client.resume().await {
+                    client.log(&format!("Failed to resume: {}", e), LogType::Error);
+                }
+                drop(client);
+
+                let change = with_cancellation(&shutdown, async { Ok(listener.next().await) });
+                match change.await {
+                    Ok(Some(now_hidden)) => hidden = now_hidden,
+                    _ => break,
+                }
+            }
+            debug!("Visibility watcher stopped");
+        });
+    }
+
+    /// Close the channel and circuits of a client idle for `idle`, keeping
+    /// the directory, guards and the rest of its state
+    ///
+    /// Does nothing while streams are open or the client isn't connected.
+    /// Returns whether the client went dormant.
+    async fn go_dormant(&self, idle: Duration) -> bool {
+        let _connecting = self.activity.connecting().await;
+        if !*self.is_initialized.read().await {
+            return false;
+        }
+        if self.traffic_stats().await.open_streams > 0 {
+            return false;
+        }
+        let Some(idle) = self.activity.fall_asleep(idle) else {
+            return false;
+        };
+        // Taken before closing, so the channel watcher doesn't reconnect
+        let channel = self.channel.write().await.take();
+        *self.is_initialized.write().await = false;
+        let circuit_manager = self.circuit_manager.read().await;
+        let closed = circuit_manager.close_all_circuits("dormant").await;
+        circuit_manager.clear_channels();
+        drop(circuit_manager);
+        if let Some(channel) = channel {
+            channel.terminate();
+        }
+        self.log(
+            &format!("Dormant after {:?} idle; closed {} circuits", idle, closed),
+            LogType::Info,
+        );
+        self.events.emit_channel(ChannelEvent::Dormant {
+            idle_ms: idle.as_millis() as u64,
+            circuits_closed: closed,
+        });
+        true
+    }
+
+    /// Forget a channel the bridge has closed, so the next connection fails
+    /// over to another bridge and builds fresh circuits
+    ///
+    /// Returns how many circuits were closed with the channel, or `None` if
+    /// it wasn't lost.
+    async fn recover_lost_channel(&self) -> Option<usize> {
+        let mut channel = self.channel.write().await;
+        if !channel.as_ref().is_some_and(|chan| chan.is_closing()) {
+            return None;
+        }
+        let peer = channel
+            .take()
+            .and_then(|chan| chan.target().rsa_identity().copied())
+            .map(|id| hex::encode_upper(id.as_bytes()));
+        drop(channel);
+        *self.is_initialized.write().await = false;
+
+        let current = self.bridges.current();
+        if let Some((index, _)) = &current {
+            self.bridges.record_failure(*index);
+        }
+        if let Some(fingerprint) = &peer {
+            self.guards.write().await.record_failure(fingerprint);
+        }
+        let circuit_manager = self.circuit_manager.read().await;
+        let closed = circuit_manager.close_all_circuits("channel lost").await;
+        circuit_manager.clear_channels();
+        drop(circuit_manager);
+        self.log(
+            &format!("Channel lost; closed {} circuits", closed),
+            LogType::Error,
+        );
+        let (transport, endpoint) = self.link_label(current);
+        self.events.emit_channel(ChannelEvent::Lost {
+            transport,
+            endpoint,
+            circuits_closed: closed,
+        });
+        Some(closed)
+    }
+
+    /// Build spare circuits until `wanted` are ready (at most
+    /// [`MAX_CIRCUITS`]), replacing those lost with a channel
+    ///
+    /// Returns how many circuits are ready.
+    async fn rebuild_circuits(&self, wanted: usize) -> usize {
+        let circuit_manager = self.circuit_manager.read().await;
+        let mut ready = circuit_manager.get_circuit_status().await.ready_circuits;
+        while ready < wanted.min(MAX_CIRCUITS) {
+            if let Err(e) = circuit_manager.create_circuit().await {
+                debug!("Failed to rebuild circuit: {}", e);
+                break;
+            }
+            ready += 1;
+        }
+        ready
+    }
+
+    /// Report that a channel is up again
+    fn channel_reconnected(&self, attempts: u32, circuits: usize) {
+        self.log(
+            &format!("Reconnected after {} attempts", attempts),
+            LogType::Success,
+        );
+        let (transport, endpoint) = self.link_label(self.bridges.current());
+        self.events.emit_channel(ChannelEvent::Reconnected {
+            transport,
+            endpoint,
+            attempts,
+            circuits,
+        });
     }
 
-    /// Get consensus status string
-    pub async fn get_consensus_status(&self) -> String {
-        let relay_manager = self.directory_manager.relay_manager.read().await;
-        let count = relay_manager.relays.len();
-        if count == 0 {
-            "No consensus loaded".to_string()
-        } else {
-            format!("{} relays loaded", count)
+    /// Transport and endpoint of a channel through `bridge`, for channel events
+    fn link_label(&self, bridge: Option<(usize, BridgeConfig)>) -> (String, String) {
+        match bridge {
+            Some((_, config)) => (
+                config.bridge.transport().to_string(),
+                config.bridge.endpoint().to_string(),
+            ),
+            None if self.options.direct => ("direct".to_string(), String::new()),
+            None => (String::new(), String::new()),
         }
     }
 
-    /// Check if consensus needs refresh (stub - always returns false for now)
-    pub fn needs_consensus_refresh(&self) -> bool {
-        false
+    /// Run a bootstrap attempt under the connection timeout and, if
+    /// configured, the overall bootstrap deadline
+    ///
+    /// The bootstrap deadline replaces the connection timeout for the whole
+    /// attempt, so expiry always produces a progress report.
+    async fn watch_bootstrap<F>(&self, attempt: F) -> Result<()>
+    where
+        F: std::future::Future<Output = Result<()>>,
+    {
+        let result = match self.options.bootstrap_timeout_duration() {
+            Some(deadline) => {
+                self.bootstrap
+                    .watch(deadline, with_cancellation(&self.shutdown_token, attempt))
+                    .await
+            }
+            None => {
+                with_timeout_and_cancellation(
+                    self.options.connection_timeout_duration(),
+                    "establish_channel",
+                    &self.shutdown_token,
+                    attempt,
+                )
+                .await
+            }
+        };
+        self.bootstrap.finish(&result);
+        result
     }
 
-    /// Close the Tor client and clean up resources
-    pub async fn close(&self) {
-        info!("Closing Tor client");
+    /// Mark the guard reachable if a channel came up, unreachable if connecting failed
+    async fn record_guard_outcome(&self, fingerprint: &str, result: &Result<()>) {
+        let connected = self.channel.read().await.is_some();
+        let mut guards = self.guards.write().await;
+        match result {
+            _ if connected => guards.record_success(fingerprint),
+            Err(TorError::Cancelled) => {}
+            Err(_) => guards.record_failure(fingerprint),
+            Ok(()) => {}
+        }
+    }
 
-        // Signal cancellation to all in-flight operations
-        self.shutdown_token.cancel();
+    /// Store a freshly handshaked channel and build the first circuit over it
+    async fn install_channel(&self, chan: Arc<tor_proto::channel::Channel>) -> Result<()> {
+        // Store the channel to keep it alive
+        *self.channel.write().await = Some(chan.clone());
+        self.bootstrap.channel_established();
 
-        // Stop update task if running
-        if let Some(task) = self.update_task.write().await.take() {
-            task.abort();
+        self.log("Channel established", LogType::Success);
+
+        // Native builds start without a cached consensus, so fetch one over
+        // the new channel before trying to pick relays for the circuit
+        if self
+            .directory_manager
+            .relay_manager
+            .read()
+            .await
+            .relays
+            .is_empty()
+        {
+            self.log("Fetching consensus...", LogType::Info);
+            self.directory_manager
+                .fetch_and_process_consensus(chan)
+                .await?;
+            self.log("Consensus fetched successfully", LogType::Success);
         }
 
-        // Clean up circuits
-        let circuit_manager = self.circuit_manager.write().await;
-        if let Err(e) = circuit_manager.cleanup_circuits().await {
-            warn!("Error during circuit cleanup: {}", e);
+        // Now create the actual circuit through the Tor network
+        self.log("Creating circuit through Tor network...", LogType::Info);
+
+        let circuit_manager = self.circuit_manager.read().await;
+        match circuit_manager.create_circuit().await {
+            Ok(circuit) => {
+                let circuit_info = circuit.read().await;
+                let relay_names: Vec<_> = circuit_info
+                    .relays
+                    .iter()
+                    .map(|r| r.nickname.clone())
+                    .collect();
+                self.log(
+                    &format!("Circuit created: {}", relay_names.join(" → ")),
+                    LogType::Success,
+                );
+            }
+            Err(e) => {
+                self.log(&format!("Failed to create circuit: {}", e), LogType::Error);
+                return Err(e);
+            }
         }
 
-        *self.is_initialized.write().await = false;
-        info!("Tor client closed");
-    }
+        *self.is_initialized.write().await = true;
 
-    /// Abort all in-flight operations.
-    ///
-    /// This cancels long-running operations like circuit creation and HTTP requests.
-    /// Operations will return `TorError::Cancelled`.
-    pub fn abort(&self) {
-        info!("Aborting all in-flight operations");
-        self.shutdown_token.cancel();
+        Ok(())
     }
 
-    /// Check if the client has been aborted/shutdown.
-    pub fn is_aborted(&self) -> bool {
-        self.shutdown_token.is_cancelled()
+    /// Initialize WASM modules (placeholder)
+    async fn init_wasm_modules() -> Result<()> {
+        // This will be implemented in the WASM bindings
+        // For now, just log that we're initializing
+        debug!("Initializing WASM modules");
+        Ok(())
     }
 
-    /// Get the shutdown token for advanced cancellation use cases.
-    pub fn shutdown_token(&self) -> &CancellationToken {
-        &self.shutdown_token
+    /// Report a failed `operation` to event subscribers
+    fn reported<T>(&self, operation: &str, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result {
+            self.events.operation_failed(operation, e, None);
+        }
+        result
     }
 
-    /// Establish the Tor channel (called during construction if requested)
-    async fn establish_channel(&self) -> Result<()> {
-        let timeout = self.options.connection_timeout_duration();
-        with_timeout_and_cancellation(
-            timeout,
-            "establish_channel",
-            &self.shutdown_token,
-            self.establish_channel_impl(),
-        )
-        .await
+    /// Log a message (uses callback if provided)
+    fn log(&self, message: &str, log_type: LogType) {
+        log_with(&self.options, message, log_type);
     }
 
-    /// Internal implementation of establish_channel (without timeout wrapper)
-    async fn establish_channel_impl(&self) -> Result<()> {
-        self.log("Establishing channel", LogType::Info);
+    /// A dialer reporting to this client's bootstrap progress
+    fn dialer(&self) -> BridgeDialer {
+        BridgeDialer {
+            options: self.options.clone(),
+            bootstrap: Some(self.bootstrap.clone()),
+            metrics: self.metrics.clone(),
+        }
+    }
+}
 
-        let timeout = self.options.connection_timeout_duration();
+/// Opens channels to bridges, for the client and for background probes
+///
+/// A dialer without bootstrap progress is probing: it reports nothing to
+/// the bootstrap and logs at debug level only.
+#[derive(Clone)]
+struct BridgeDialer {
+    options: TorClientOptions,
+    bootstrap: Option<BootstrapProgress>,
+    metrics: Metrics,
+}
 
-        // Get fingerprint - use default for Snowflake if not provided
-        let fingerprint = match &self.options.bridge {
-            BridgeType::Snowflake { .. } | BridgeType::SnowflakeWebRtc { .. } => self
-                .options
-                .bridge_fingerprint
-                .as_ref()
-                .cloned()
-                .unwrap_or_else(|| SNOWFLAKE_FINGERPRINT_PRIMARY.to_string()),
-            BridgeType::WebTunnel { .. } => self
-                .options
-                .bridge_fingerprint
-                .as_ref()
-                .ok_or_else(|| {
-                    TorError::Configuration(
-                        "Bridge fingerprint is required for WebTunnel".to_string(),
-                    )
-                })?
-                .clone(),
-        };
+impl BridgeDialer {
+    /// Connect to `bridge` and complete the channel handshake
+    async fn open_channel(
+        &self,
+        bridge: &BridgeConfig,
+    ) -> Result<Arc<tor_proto::channel::Channel>> {
+        if let Some(bootstrap) = &self.bootstrap {
+            bootstrap.advance(BootstrapStage::ConnectingBridge);
+        }
+        let fingerprint = bridge.identity()?;
+        let expected = ExpectedIdentity::parse(&fingerprint, bridge.ed25519_id.as_deref())?;
+        let transport = self.transport(&bridge.bridge, &fingerprint)?;
+        let name = bridge.bridge.transport();
+        let stream = MeteredStream::new(transport.connect().await?, self.metrics.transport(name));
+        self.log(&format!("Connected to {} bridge", name), LogType::Success);
+        self.create_channel_from_stream(stream, expected).await
+    }
 
-        // Parse fingerprint to RSA identity
-        let rsa_id = {
-            let bytes = hex::decode(&fingerprint)
-                .map_err(|e| TorError::Configuration(format!("Invalid fingerprint hex: {}", e)))?;
-            if bytes.len() != 20 {
-                return Err(TorError::Configuration(
-                    "Fingerprint must be 40 hex characters (20 bytes)".to_string(),
-                ));
-            }
-            RsaIdentity::from_bytes(&bytes)
-                .ok_or_else(|| TorError::Configuration("Invalid RSA identity bytes".to_string()))?
-        };
+    /// Connect straight to the relay at `addr` and complete the channel handshake
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn open_relay_channel(
+        &self,
+        addr: &str,
+        fingerprint: &str,
+        ed25519_id: Option<&str>,
+    ) -> Result<Arc<tor_proto::channel::Channel>> {
+        if let Some(bootstrap) = &self.bootstrap {
+            bootstrap.advance(BootstrapStage::ConnectingBridge);
+        }
+        let expected = ExpectedIdentity::parse(fingerprint, ed25519_id)?;
+        let transport = OrPortTransport::new(addr, self.options.connection_timeout_duration());
+        let stream =
+            MeteredStream::new(transport.connect().await?, self.metrics.transport("orport"));
+        self.log(&format!("Connected to relay {}", addr), LogType::Success);
+        self.create_channel_from_stream(stream, expected).await
+    }
 
-        // 1. Connect to bridge based on type
-        let chan = match &self.options.bridge {
+    /// The transport that reaches `bridge`
+    fn transport(&self, bridge: &BridgeType, fingerprint: &str) -> Result<Arc<dyn Transport>> {
+        let timeout = self.options.connection_timeout_duration();
+        let transport: Arc<dyn Transport> = match bridge {
             BridgeType::Snowflake { url } => {
                 self.log("Connecting via Snowflake (WebSocket)", LogType::Info);
                 self.log(
@@ -451,15 +2351,11 @@ impl TorClient {
                 #[cfg(target_arch = "wasm32")]
                 {
                     // Use WebSocket-based Snowflake (simpler, less censorship resistant)
-                    let config = SnowflakeWsConfig::default()
-                        .with_url(url)
-                        .with_fingerprint(&fingerprint);
-                    let stream = SnowflakeWsStream::connect(config).await?;
-                    self.log(
-                        "Connected to Snowflake bridge via WebSocket",
-                        LogType::Success,
-                    );
-                    self.create_channel_from_stream(stream, rsa_id).await?
+                    Arc::new(
+                        SnowflakeWsConfig::default()
+                            .with_url(url)
+                            .with_fingerprint(fingerprint),
+                    )
                 }
                 #[cfg(not(target_arch = "wasm32"))]
                 {
@@ -471,7 +2367,10 @@ impl TorClient {
                     ));
                 }
             }
-            BridgeType::SnowflakeWebRtc { broker_url } => {
+            BridgeType::SnowflakeWebRtc {
+                broker_url,
+                fallbacks,
+            } => {
                 self.log("Connecting via Snowflake (WebRTC)", LogType::Info);
                 self.log(
                     "Using WebRTC -> Turbo -> KCP -> SMUX -> TLS stack",
@@ -484,16 +2383,16 @@ impl TorClient {
                 #[cfg(target_arch = "wasm32")]
                 {
                     // Use WebRTC-based Snowflake (proper architecture)
-                    let config = SnowflakeConfig::with_broker(broker_url.clone())
-                        .with_fingerprint(fingerprint.clone());
-                    let bridge = SnowflakeBridge::with_config(config);
-                    let stream = bridge.connect().await?;
-                    self.log("Connected to Snowflake bridge via WebRTC", LogType::Success);
-                    self.create_channel_from_stream(stream, rsa_id).await?
+                    let config = fallbacks.iter().cloned().fold(
+                        SnowflakeConfig::with_broker(broker_url.clone())
+                            .with_fingerprint(fingerprint.to_string()),
+                        SnowflakeConfig::with_fallback,
+                    );
+                    Arc::new(SnowflakeBridge::with_config(config))
                 }
                 #[cfg(not(target_arch = "wasm32"))]
                 {
-                    let _ = broker_url; // suppress unused warning
+                    let _ = (broker_url, fallbacks); // suppress unused warning
                     return Err(TorError::Internal(
                         "Snowflake WebRTC is only available in WASM. \
                          Use WebTunnel bridge for native builds."
@@ -502,19 +2401,24 @@ impl TorClient {
                 }
             }
             #[cfg(not(target_arch = "wasm32"))]
-            BridgeType::WebTunnel { url, server_name } => {
+            BridgeType::WebTunnel {
+                url,
+                server_name,
+                handshake,
+            } => {
                 self.log(
                     &format!("Connecting via WebTunnel to {}", url),
                     LogType::Info,
                 );
-                let mut config =
-                    WebTunnelConfig::new(url.clone(), fingerprint.clone()).with_timeout(timeout);
+                let mut config = WebTunnelConfig::new(url.clone(), fingerprint.to_string())
+                    .with_timeout(timeout)
+                    .with_tls_fingerprint(self.options.bridge_tls_fingerprint)
+                    .with_handshake(handshake.clone())
+                    .with_proxy(self.options.upstream_proxy.clone());
                 if let Some(sni) = server_name {
                     config = config.with_server_name(sni.clone());
                 }
-                let stream = create_webtunnel_stream(config).await?;
-                self.log("Connected to WebTunnel bridge", LogType::Success);
-                self.create_channel_from_stream(stream, rsa_id).await?
+                Arc::new(WebTunnelBridge::new(config))
             }
             #[cfg(target_arch = "wasm32")]
             BridgeType::WebTunnel { .. } => {
@@ -522,46 +2426,81 @@ impl TorClient {
                     "WebTunnel is not supported in WASM. Use Snowflake bridge instead.".to_string(),
                 ));
             }
+            #[cfg(all(feature = "obfs4", not(target_arch = "wasm32")))]
+            BridgeType::Obfs4 {
+                addr,
+                cert,
+                iat_mode,
+            } => {
+                self.log(&format!("Connecting via obfs4 to {}", addr), LogType::Info);
+                let config = Obfs4Config::new(addr.clone(), fingerprint.to_string(), cert.clone())
+                    .with_iat_mode(*iat_mode)
+                    .with_timeout(timeout)
+                    .with_proxy(self.options.upstream_proxy.clone());
+                Arc::new(Obfs4Bridge::new(config))
+            }
+            #[cfg(not(all(feature = "obfs4", not(target_arch = "wasm32"))))]
+            BridgeType::Obfs4 { .. } => {
+                return Err(TorError::Configuration(
+                    "obfs4 bridges need a native build with the `obfs4` feature".to_string(),
+                ));
+            }
+            BridgeType::Meek { url, front } => {
+                self.log(&format!("Connecting via meek to {}", url), LogType::Info);
+                let mut config =
+                    MeekConfig::new(url.clone(), fingerprint.to_string()).with_timeout(timeout);
+                if let Some(front) = front {
+                    config = config.with_front(front.clone());
+                }
+                Arc::new(MeekBridge::new(config))
+            }
+            BridgeType::Custom { name } => {
+                let transport = self.options.transports.get(name)?;
+                self.log(&format!("Connecting via {}", name), LogType::Info);
+                transport
+            }
         };
+        Ok(transport)
+    }
 
-        // Store the channel to keep it alive
-        *self.channel.write().await = Some(chan);
-
-        self.log("Channel established", LogType::Success);
-
-        // Now create the actual circuit through the Tor network
-        self.log("Creating circuit through Tor network...", LogType::Info);
-
-        let circuit_manager = self.circuit_manager.read().await;
-        match circuit_manager.create_circuit().await {
-            Ok(circuit) => {
-                let circuit_info = circuit.read().await;
-                let relay_names: Vec<_> = circuit_info
-                    .relays
-                    .iter()
-                    .map(|r| r.nickname.clone())
-                    .collect();
-                self.log(
-                    &format!("Circuit created: {}", relay_names.join(" → ")),
-                    LogType::Success,
-                );
+    /// Try each failing bridge once and record whether it works again
+    ///
+    /// Returns how many bridges work again.
+    async fn probe(&self, bridges: &BridgeSet, shutdown: &CancellationToken) -> usize {
+        let mut recovered = 0;
+        for (index, bridge) in bridges.probe_candidates() {
+            if bridge.identity().is_err() {
+                continue;
             }
-            Err(e) => {
-                self.log(&format!("Failed to create circuit: {}", e), LogType::Error);
-                return Err(e);
+            let started = Instant::now();
+            let result = with_timeout_and_cancellation(
+                self.options.connection_timeout_duration(),
+                "probe_bridge",
+                shutdown,
+                self.open_channel(&bridge),
+            )
+            .await;
+            match result {
+                Ok(chan) => {
+                    bridges.record_probe_success(index, started.elapsed());
+                    chan.terminate();
+                    recovered += 1;
+                }
+                Err(TorError::Cancelled) => break,
+                Err(e) => {
+                    debug!("Probe of bridge {} failed: {}", index, e);
+                    bridges.record_failure(index);
+                }
             }
         }
-
-        *self.is_initialized.write().await = true;
-
-        Ok(())
+        recovered
     }
 
     /// Create Tor channel from a connected stream and spawn the reactor
     async fn create_channel_from_stream<S>(
         &self,
         stream: S,
-        rsa_id: RsaIdentity,
+        expected: ExpectedIdentity,
     ) -> Result<Arc<tor_proto::channel::Channel>>
     where
         S: futures::AsyncRead
@@ -573,6 +2512,9 @@ impl TorClient {
             + 'static,
     {
         let runtime = WasmRuntime::new();
+        if let Some(bootstrap) = &self.bootstrap {
+            bootstrap.bridge_connected();
+        }
 
         // Extract the peer certificate from the TLS stream BEFORE moving it
         // The peer certificate is needed later for the check() call
@@ -600,11 +2542,11 @@ impl TorClient {
         })?;
         debug!("Handshake connect completed, verifying...");
 
-        // Construct peer target
-        let mut peer_builder = OwnedChanTargetBuilder::default();
-        peer_builder.rsa_identity(rsa_id);
-
-        let peer = peer_builder
+        // The target names no identities: check() still verifies the CERTS
+        // cells and their binding to the TLS certificate, and the identities
+        // they prove are compared with the expected ones below, so a mismatch
+        // gets its own error rather than a generic handshake failure
+        let peer = OwnedChanTargetBuilder::default()
             .build()
             .map_err(|e| TorError::Internal(format!("Failed to build peer target: {}", e)))?;
 
@@ -618,6 +2560,9 @@ impl TorClient {
             .await
             .map_err(|e| TorError::Network(format!("Handshake finish failed: {}", e)))?;
 
+        // Dropping the reactor unstarted closes the channel
+        expected.check(&*chan)?;
+
         // Spawn reactor
         #[cfg(target_arch = "wasm32")]
         wasm_bindgen_futures::spawn_local(async move {
@@ -629,48 +2574,250 @@ impl TorClient {
             let _ = reactor.run().await;
         });
 
+        padding::engage(&chan, self.options.channel_padding)?;
         Ok(chan)
     }
 
-    /// Initialize WASM modules (placeholder)
-    async fn init_wasm_modules() -> Result<()> {
-        // This will be implemented in the WASM bindings
-        // For now, just log that we're initializing
-        debug!("Initializing WASM modules");
-        Ok(())
-    }
-
-    /// Log a message (uses callback if provided)
     fn log(&self, message: &str, log_type: LogType) {
-        if let Some(ref on_log) = self.options.on_log {
-            (on_log.0)(message, log_type);
+        if self.bootstrap.is_none() {
+            debug!("Bridge probe: {}", message);
         } else {
-            // Default logging
-            match log_type {
-                LogType::Info => info!("{}", message),
-                LogType::Success => info!(" {}", message),
-                LogType::Error => error!(" {}", message),
-            }
+            log_with(&self.options, message, log_type);
+        }
+    }
+}
+
+/// Log through the `on_log` callback if provided
+fn log_with(options: &TorClientOptions, message: &str, log_type: LogType) {
+    if let Some(ref on_log) = options.on_log {
+        (on_log.0)(message, log_type);
+    } else {
+        // Default logging
+        match log_type {
+            LogType::Info => info!("{}", message),
+            LogType::Success => info!(" {}", message),
+            LogType::Error => error!(" {}", message),
         }
     }
 }
 
+/// Through bridges every circuit enters at a bridge, so sample the
+/// configured bridges as the guards
+fn sample_bridge_guards(guards: &mut GuardManager, options: &TorClientOptions) {
+    let candidates: Vec<GuardCandidate> = options
+        .bridges()
+        .iter()
+        .filter_map(|bridge| bridge.identity().ok())
+        .map(GuardCandidate::bridge)
+        .collect();
+    if !options.direct && !candidates.is_empty() {
+        guards.update_sample(&candidates);
+    }
+}
+
+/// Parse an ntor onion key given as hex or (unpadded) base64 into hex
+fn parse_ntor_key(key: &str) -> Result<String> {
+    let key = key.trim();
+    let bytes = hex::decode(key)
+        .or_else(|_| {
+            base64::engine::general_purpose::STANDARD_NO_PAD.decode(key.trim_end_matches('='))
+        })
+        .map_err(|_| TorError::Configuration("ntor key must be hex or base64".to_string()))?;
+    if bytes.len() != 32 {
+        return Err(TorError::Configuration(
+            "ntor key must be 32 bytes".to_string(),
+        ));
+    }
+    Ok(hex::encode(bytes))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 impl Drop for TorClient {
     fn drop(&mut self) {
         // For WASM, we can't spawn async tasks from Drop reliably.
         // The explicit close() call in fetch_one_time handles cleanup.
-        // For native builds, we spawn a cleanup task.
+        // For native builds, the last handle spawns a cleanup task. Clones share
+        // all state, so dropping any other handle must not close the client, and
+        // the task's own handle must not respawn once the token is cancelled.
         #[cfg(not(target_arch = "wasm32"))]
         {
-            let client = self.clone();
-            tokio::spawn(async move {
-                client.close().await;
-            });
+            let is_last_handle = Arc::strong_count(&self.is_initialized) == 1;
+            if is_last_handle && !self.shutdown_token.is_cancelled() {
+                if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                    let client = self.clone();
+                    handle.spawn(async move {
+                        client.close().await;
+                    });
+                }
+            }
         }
         // On WASM, Drop is a no-op - callers must call close() explicitly
     }
 }
 
+/// A handle on a client's state that doesn't count as a client handle
+///
+/// Background tasks hold one so that dropping the last [`TorClient`] still
+/// closes the client.
+struct WeakTorClient {
+    options: TorClientOptions,
+    circuit_manager: Arc<RwLock<CircuitManager>>,
+    directory_manager: Arc<DirectoryManager>,
+    http_client: Arc<TorHttpClient>,
+    is_initialized: Weak<RwLock<bool>>,
+    channel: Arc<RwLock<Option<Arc<tor_proto::channel::Channel>>>>,
+    update_task: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
+    shutdown_token: CancellationToken,
+    maintenance: Maintenance,
+    metrics: Metrics,
+    guards: Arc<RwLock<GuardManager>>,
+    bridges: BridgeSet,
+    bootstrap: BootstrapProgress,
+    reachability: ReachabilityTracker,
+    vanguards: Option<Arc<RwLock<VanguardManager>>>,
+    build_timeouts: BuildTimeoutEstimator,
+    store: Arc<dyn StateStore>,
+    events: CircuitEvents,
+    tls_sessions: Option<TlsSessionCache>,
+    onion: Arc<OnionConnector>,
+    resolved: Arc<Mutex<ResolveCache>>,
+    isolation_domain: Option<IsolationDomain>,
+    activity: Activity,
+}
+
+impl TorClient {
+    fn downgrade(&self) -> WeakTorClient {
+        WeakTorClient {
+            options: self.options.clone(),
+            circuit_manager: self.circuit_manager.clone(),
+            directory_manager: self.directory_manager.clone(),
+            http_client: self.http_client.clone(),
+            is_initialized: Arc::downgrade(&self.is_initialized),
+            channel: self.channel.clone(),
+            update_task: self.update_task.clone(),
+            shutdown_token: self.shutdown_token.clone(),
+            maintenance: self.maintenance.clone(),
+            metrics: self.metrics.clone(),
+            guards: self.guards.clone(),
+            bridges: self.bridges.clone(),
+            bootstrap: self.bootstrap.clone(),
+            reachability: self.reachability.clone(),
+            vanguards: self.vanguards.clone(),
+            build_timeouts: self.build_timeouts.clone(),
+            store: self.store.clone(),
+            events: self.events.clone(),
+            tls_sessions: self.tls_sessions.clone(),
+            onion: self.onion.clone(),
+            resolved: self.resolved.clone(),
+            isolation_domain: self.isolation_domain,
+            activity: self.activity.clone(),
+        }
+    }
+}
+
+impl WeakTorClient {
+    /// The client, unless every handle has been dropped
+    fn upgrade(&self) -> Option<TorClient> {
+        Some(TorClient {
+            options: self.options.clone(),
+            circuit_manager: self.circuit_manager.clone(),
+            directory_manager: self.directory_manager.clone(),
+            http_client: self.http_client.clone(),
+            is_initialized: self.is_initialized.upgrade()?,
+            channel: self.channel.clone(),
+            update_task: self.update_task.clone(),
+            shutdown_token: self.shutdown_token.clone(),
+            maintenance: self.maintenance.clone(),
+            metrics: self.metrics.clone(),
+            guards: self.guards.clone(),
+            bridges: self.bridges.clone(),
+            bootstrap: self.bootstrap.clone(),
+            reachability: self.reachability.clone(),
+            vanguards: self.vanguards.clone(),
+            build_timeouts: self.build_timeouts.clone(),
+            store: self.store.clone(),
+            events: self.events.clone(),
+            tls_sessions: self.tls_sessions.clone(),
+            onion: self.onion.clone(),
+            resolved: self.resolved.clone(),
+            isolation_domain: self.isolation_domain,
+            activity: self.activity.clone(),
+        })
+    }
+
+    /// Reconnect after the channel closed, with backoff between attempts
+    ///
+    /// The client is only held during an attempt, not while waiting.
+    async fn reconnect(&self) {
+        let config = self.options.reconnect;
+        let Some(client) = self.upgrade() else {
+            return;
+        };
+        // Nothing to do if a request noticed the loss first and reconnects
+        let Some(lost) = client.recover_lost_channel().await else {
+            return;
+        };
+        drop(client);
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if !config.allows(attempt) {
+                warn!("Giving up reconnecting after {} attempts", attempt - 1);
+                self.events.emit_channel(ChannelEvent::GaveUp {
+                    attempts: attempt - 1,
+                });
+                return;
+            }
+            let delay = config.delay(attempt);
+            self.events.emit_channel(ChannelEvent::Reconnecting {
+                attempt,
+                delay_ms: delay.as_millis() as u64,
+            });
+            let wait = with_cancellation(&self.shutdown_token, async {
+                sleep(delay).await;
+                Ok(())
+            });
+            if wait.await.is_err() {
+                return;
+            }
+
+            let Some(client) = self.upgrade() else {
+                return;
+            };
+            // A request may have reconnected in the meantime
+            let connected = client.channel.read().await.is_some();
+            let result = if connected {
+                Ok(())
+            } else {
+                client.establish_channel().await
+            };
+            if client.channel.read().await.is_some() {
+                let circuits = client.rebuild_circuits(lost).await;
+                client.channel_reconnected(attempt, circuits);
+                return;
+            }
+            match result {
+                Err(TorError::Cancelled) => return,
+                Err(e) => {
+                    client.log(
+                        &format!("Reconnect attempt {} failed: {}", attempt, e),
+                        LogType::Error,
+                    );
+                    self.events.emit_channel(ChannelEvent::ReconnectFailed {
+                        attempt,
+                        error: e.to_string(),
+                    });
+                }
+                Ok(()) => {}
+            }
+        }
+    }
+}
+
 impl Clone for TorClient {
     fn clone(&self) -> Self {
         Self {
@@ -682,6 +2829,21 @@ impl Clone for TorClient {
             channel: self.channel.clone(),
             update_task: self.update_task.clone(),
             shutdown_token: self.shutdown_token.clone(),
+            maintenance: self.maintenance.clone(),
+            metrics: self.metrics.clone(),
+            guards: self.guards.clone(),
+            bridges: self.bridges.clone(),
+            bootstrap: self.bootstrap.clone(),
+            reachability: self.reachability.clone(),
+            vanguards: self.vanguards.clone(),
+            build_timeouts: self.build_timeouts.clone(),
+            store: self.store.clone(),
+            events: self.events.clone(),
+            tls_sessions: self.tls_sessions.clone(),
+            onion: self.onion.clone(),
+            resolved: self.resolved.clone(),
+            isolation_domain: self.isolation_domain,
+            activity: self.activity.clone(),
         }
     }
 }
@@ -690,6 +2852,16 @@ impl Clone for TorClient {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_ntor_key_hex_or_base64() {
+        let hex_key = "11".repeat(32);
+        // As written in a bridge descriptor's `ntor-onion-key` line
+        let b64_key = "ERERERERERERERERERERERERERERERERERERERERERE=";
+        assert_eq!(parse_ntor_key(&hex_key).unwrap(), hex_key);
+        assert_eq!(parse_ntor_key(b64_key).unwrap(), hex_key);
+        assert!(parse_ntor_key("1111").is_err());
+    }
+
     // These tests require a larger stack due to embedded consensus data parsing.
     // Run with: RUST_MIN_STACK=16777216 cargo test -p webtor client::tests
 
@@ -731,4 +2903,22 @@ mod tests {
         assert_eq!(status.ready_circuits, 0);
         assert!(!status.has_ready_circuits());
     }
+
+    #[tokio::test]
+    #[ignore = "requires large stack for consensus parsing"]
+    async fn test_isolated_client() {
+        let options = TorClientOptions::new("wss://snowflake.torproject.net/".to_string())
+            .with_create_circuit_early(false);
+
+        let client = TorClient::new(options).await.unwrap();
+        let first = client.isolated_client();
+        let second = client.isolated_client();
+        let key = |client: &TorClient| client.isolation_key("example.com", 443, None);
+
+        assert!(Arc::ptr_eq(&client.circuit_manager, &first.circuit_manager));
+        assert!(key(&first).is_some());
+        assert_ne!(key(&first), key(&client));
+        assert_ne!(key(&first), key(&second));
+        assert_eq!(key(&first.clone()), key(&first));
+    }
 }
@@ -1,13 +1,52 @@
 //! Configuration options for the Tor client
 
+use crate::bootstrap::BootstrapProgress;
+use crate::bridge_line::BridgeLine;
+use crate::build_timeout::BuildTimeoutConfig;
+use crate::directory::DirectoryCache;
+use crate::ech::EchConfigs;
+use crate::error::{Result, TorError};
+use crate::geoip::GeoIpDb;
+use crate::hostname::HostnamePolicy;
 use crate::isolation::StreamIsolationPolicy;
+use crate::padding::ChannelPadding;
+use crate::profile::RequestProfile;
+use crate::proxy::UpstreamProxy;
+use crate::reachability::ReachabilityConfig;
+use crate::reconnect::ReconnectConfig;
+use crate::redirect::RedirectPolicy;
+use crate::relay::SelectionRng;
+use crate::snowflake_broker::Rendezvous;
+use crate::storage::{StateStore, StateStoreHandle};
+use crate::tls::{TlsFingerprint, TlsRoots};
+use crate::transport::{Transport, TransportRegistry};
+use crate::visibility::VisibilityPolicy;
+use crate::websocket::WebSocketOptions;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::sync::Arc;
 use std::time::Duration;
 
+/// Serde adapter storing a [`Duration`] as whole milliseconds, like the
+/// millisecond fields of [`TorClientOptions`]
+pub(crate) mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis().min(u64::MAX as u128) as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// Signature of the logging callback invoked by the client
+pub type LogFn = dyn Fn(&str, LogType) + Send + Sync;
+
 #[derive(Clone)]
-pub struct LogCallback(pub Arc<dyn Fn(&str, LogType) + Send + Sync>);
+pub struct LogCallback(pub Arc<LogFn>);
 
 impl fmt::Debug for LogCallback {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -24,7 +63,7 @@ pub const SNOWFLAKE_URL_PRIMARY: &str = "wss://snowflake.torproject.net/";
 pub const SNOWFLAKE_URL_SECONDARY: &str = "wss://snowflake.bamsoftware.com/";
 
 /// Bridge type configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BridgeType {
     /// Snowflake bridge via direct WebSocket (simpler, less censorship resistant)
     Snowflake {
@@ -35,6 +74,9 @@ pub enum BridgeType {
     SnowflakeWebRtc {
         /// Broker URL for WebRTC signaling (via CORS proxy)
         broker_url: String,
+        /// Rendezvous methods tried in order if the broker can't be reached
+        #[serde(default)]
+        fallbacks: Vec<Rendezvous>,
     },
     /// WebTunnel bridge (HTTPS with HTTP Upgrade)
     WebTunnel {
@@ -42,9 +84,61 @@ pub enum BridgeType {
         url: String,
         /// Optional: Override server name for TLS SNI
         server_name: Option<String>,
+        /// Extra headers and subprotocols for the upgrade request
+        #[serde(default)]
+        handshake: WebSocketOptions,
+    },
+    /// obfs4 bridge (native builds with the `obfs4` feature)
+    Obfs4 {
+        /// Bridge address (`host:port`)
+        addr: String,
+        /// The bridge line's `cert` argument
+        cert: String,
+        /// The bridge line's `iat-mode`
+        #[serde(default)]
+        iat_mode: u8,
+    },
+    /// meek-lite bridge (HTTPS POST polling, optionally domain-fronted)
+    Meek {
+        /// URL of the meek server; its host goes in the `Host` header
+        url: String,
+        /// Optional: Domain to connect to instead (ignored in WASM)
+        front: Option<String>,
+    },
+    /// Transport registered with [`TorClientOptions::with_transport`]
+    Custom {
+        /// Name the transport was registered under
+        name: String,
     },
 }
 
+impl BridgeType {
+    /// Short name of the transport, as in bridge lines
+    pub fn transport(&self) -> &str {
+        match self {
+            BridgeType::Snowflake { .. } => "snowflake-ws",
+            BridgeType::SnowflakeWebRtc { .. } => "snowflake",
+            BridgeType::WebTunnel { .. } => "webtunnel",
+            BridgeType::Obfs4 { .. } => "obfs4",
+            BridgeType::Meek { .. } => "meek",
+            BridgeType::Custom { name } => name,
+        }
+    }
+
+    /// Where the transport connects: a URL, broker URL or address (empty
+    /// for custom transports)
+    pub fn endpoint(&self) -> &str {
+        match self {
+            BridgeType::Snowflake { url }
+            | BridgeType::WebTunnel { url, .. }
+            | BridgeType::Meek { url, .. } => url,
+            BridgeType::SnowflakeWebRtc { broker_url, .. } => broker_url,
+            BridgeType::Obfs4 { addr, .. } => addr,
+            BridgeType::Custom { .. } => "",
+        }
+    }
+}
+
 impl Default for BridgeType {
     fn default() -> Self {
         // Default to Snowflake since it's more reliable
@@ -54,12 +148,78 @@ impl Default for BridgeType {
     }
 }
 
+/// A bridge to connect through: its transport and identity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BridgeConfig {
+    pub bridge: BridgeType,
+    /// Bridge fingerprint (hex); optional for Snowflake
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// Bridge ed25519 identity (hex or base64), checked in the channel
+    /// handshake along with the fingerprint if set
+    #[serde(default)]
+    pub ed25519_id: Option<String>,
+}
+
+impl BridgeConfig {
+    pub fn new(bridge: BridgeType, fingerprint: Option<String>) -> Self {
+        Self {
+            bridge,
+            fingerprint,
+            ed25519_id: None,
+        }
+    }
+
+    pub fn with_ed25519_id(mut self, id: String) -> Self {
+        self.ed25519_id = Some(id);
+        self
+    }
+
+    /// Parse a bridge line as handed out by BridgeDB
+    pub fn from_bridge_line(line: &str) -> Result<Self> {
+        let line: BridgeLine = line.parse()?;
+        Ok(Self::new(
+            line.bridge_type()?,
+            line.identity().map(str::to_string),
+        ))
+    }
+
+    /// Fingerprint of the bridge - Snowflake falls back to the default bridge
+    pub fn identity(&self) -> Result<String> {
+        match (&self.bridge, &self.fingerprint) {
+            (_, Some(fingerprint)) => Ok(fingerprint.clone()),
+            (BridgeType::Snowflake { .. } | BridgeType::SnowflakeWebRtc { .. }, None) => {
+                Ok(SNOWFLAKE_FINGERPRINT_PRIMARY.to_string())
+            }
+            (BridgeType::WebTunnel { .. }, None) => Err(TorError::Configuration(
+                "Bridge fingerprint is required for WebTunnel".to_string(),
+            )),
+            (BridgeType::Obfs4 { .. }, None) => Err(TorError::Configuration(
+                "Bridge fingerprint is required for obfs4".to_string(),
+            )),
+            (BridgeType::Meek { .. }, None) => Err(TorError::Configuration(
+                "Bridge fingerprint is required for meek".to_string(),
+            )),
+            (BridgeType::Custom { name }, None) => Err(TorError::Configuration(format!(
+                "Bridge fingerprint is required for {}",
+                name
+            ))),
+        }
+    }
+}
+
 /// Configuration options for the TorClient
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TorClientOptions {
     /// Bridge configuration
+    #[serde(default)]
     pub bridge: BridgeType,
 
+    /// Connect straight to a guard relay instead of through a bridge (native
+    /// only); the bridge settings are ignored
+    #[serde(default)]
+    pub direct: bool,
+
     /// The Snowflake bridge WebSocket URL for Tor connections (deprecated, use bridge)
     #[serde(default)]
     pub snowflake_url: String,
@@ -84,16 +244,226 @@ pub struct TorClientOptions {
     #[serde(default = "default_circuit_update_advance")]
     pub circuit_update_advance: u64,
 
+    /// Time in milliseconds after a circuit first carries traffic during which new
+    /// streams may still be attached to it; replacements are built in the background
+    #[serde(default = "default_max_circuit_dirtiness")]
+    pub max_circuit_dirtiness: u64,
+
+    /// Interval in milliseconds between maintenance passes that prune expired state, or null to disable
+    #[serde(default = "default_maintenance_interval")]
+    pub maintenance_interval: Option<u64>,
+
+    /// Interval in milliseconds between background probes of failing
+    /// fallback bridges, or null to disable; only used with several bridges
+    #[serde(default = "default_bridge_probe_interval")]
+    pub bridge_probe_interval: Option<u64>,
+
+    /// Race connections to all configured bridges, starting each this many
+    /// milliseconds after the previous one (or as soon as one fails), and keep
+    /// the first channel to complete its handshake; null tries them one by one
+    #[serde(default)]
+    pub bridge_race_stagger: Option<u64>,
+
+    /// Overall deadline in milliseconds for connecting to the bridge, loading the
+    /// directory and building the first circuit, or null for no deadline; on expiry
+    /// the error carries a report of how far bootstrap got
+    #[serde(default)]
+    pub bootstrap_timeout: Option<u64>,
+
+    /// Go dormant after this many milliseconds without requests or open
+    /// streams: the channel and circuits are closed while the consensus and
+    /// guards are kept, and the next request bootstraps again. Null stays
+    /// connected; leave it null for clients running onion services
+    #[serde(default)]
+    pub dormant_after: Option<u64>,
+
+    /// What to do while the page is hidden (WASM; native apps call
+    /// `TorClient::suspend` and `resume` themselves)
+    #[serde(default)]
+    pub visibility_policy: VisibilityPolicy,
+
+    /// When relays that repeatedly fail to extend are temporarily avoided
+    #[serde(default)]
+    pub reachability: ReachabilityConfig,
+
+    /// Whether and how the client reconnects when the bridge drops the channel
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+
+    /// How the circuit build timeout is learned from observed build times
+    #[serde(default)]
+    pub build_timeout: BuildTimeoutConfig,
+
+    /// Negotiate congestion control (prop 324) with relays that support it;
+    /// when false every hop uses fixed SENDME windows
+    #[serde(default = "default_congestion_control")]
+    pub congestion_control: bool,
+
+    /// Build exit circuits as two legs through different middles, linked
+    /// with conflux (prop 329), when the exit supports it; needs congestion control
+    #[serde(default)]
+    pub conflux: bool,
+
+    /// How many times a request is moved to another circuit after a retriable
+    /// failure (circuit closed, exit refusal, connection reset, connect or
+    /// first-byte timeout); 0 surfaces the failure immediately
+    #[serde(default = "default_stream_retries")]
+    pub stream_retries: u32,
+
+    /// Send a stream's first bytes right after its BEGIN instead of waiting
+    /// for CONNECTED, on exits that accept it; saves a round trip per request
+    #[serde(default = "default_optimistic_data")]
+    pub optimistic_data: bool,
+
+    /// Open streams a circuit carries before new ones spill over to another
+    /// circuit for the same isolation key; 0 means no cap
+    #[serde(default = "default_max_streams_per_circuit")]
+    pub max_streams_per_circuit: usize,
+
+    /// Circuit builds raced when a request finds no usable circuit; the
+    /// first to finish is used and the others are torn down (1: no racing)
+    #[serde(default = "default_parallel_circuit_builds")]
+    pub parallel_circuit_builds: usize,
+
+    /// Connections opened to the bridge; circuits are spread across them so
+    /// one congested connection doesn't hold up all traffic (1: a single channel)
+    #[serde(default = "default_channels_per_bridge")]
+    pub channels_per_bridge: usize,
+
+    /// Padding kept up on channels so idle periods look like a regular
+    /// Tor client's
+    #[serde(default)]
+    pub channel_padding: ChannelPadding,
+
+    /// Pin the middle hop of long-lived circuits to a small rotating set of
+    /// layer-2 guards (vanguards-lite), persisted in the state store
+    #[serde(default = "default_vanguards_lite")]
+    pub vanguards_lite: bool,
+
+    /// Seed for relay selection, making circuit paths reproducible (testing only)
+    #[serde(default)]
+    pub path_selection_seed: Option<u64>,
+
+    /// Random source for relay selection; takes precedence over `path_selection_seed`
+    #[serde(skip)]
+    pub selection_rng: Option<SelectionRng>,
+
     /// Optional bridge fingerprint (hex string) to verify the bridge identity
     pub bridge_fingerprint: Option<String>,
 
+    /// Optional bridge ed25519 identity (hex or base64); the bridge must
+    /// prove it in the channel handshake along with the fingerprint
+    #[serde(default)]
+    pub bridge_ed25519_id: Option<String>,
+
+    /// Bridges tried in order when the configured bridge can't be reached
+    /// or its channel is lost
+    #[serde(default)]
+    pub fallback_bridges: Vec<BridgeConfig>,
+
+    /// The bridge's ntor onion key (hex, or base64 as in its descriptor), so
+    /// the first hop can use an ntor handshake instead of CREATE_FAST; not
+    /// needed when the bridge is a listed relay
+    #[serde(default)]
+    pub bridge_ntor_key: Option<String>,
+
     /// Stream isolation policy for domain-based circuit separation
     #[serde(default)]
     pub stream_isolation: StreamIsolationPolicy,
 
+    /// How internationalized hostnames passed to fetch/connect are handled
+    #[serde(default)]
+    pub hostname_policy: HostnamePolicy,
+
+    /// Whether and how far HTTP redirects are followed; requests may override it
+    #[serde(default)]
+    pub redirect_policy: RedirectPolicy,
+
+    /// Keep cookies set by responses and send them with later requests to the
+    /// same site, separately per isolation key; off by default
+    #[serde(default)]
+    pub cookies: bool,
+
+    /// Send `Accept-Encoding: gzip, deflate, br` and decode compressed
+    /// response bodies; requests may opt out to get raw bytes
+    #[serde(default = "default_decompress_responses")]
+    pub decompress_responses: bool,
+
+    /// Bytes a response may take, raw or decoded, before the request fails
+    /// with `ResponseTooLarge` and its stream is closed; 0 means no cap
+    #[serde(default = "default_max_response_size")]
+    pub max_response_size: usize,
+
+    /// Resume TLS 1.3 sessions with servers through exits, per isolation
+    /// key. A resumed session links the connection to the earlier one at
+    /// the server even across circuits, as a cookie would; turn it off to
+    /// match Tor Browser, which never resumes
+    #[serde(default = "default_tls_session_resumption")]
+    pub tls_session_resumption: bool,
+
+    /// Country codes whose exits are never used (like Tor's `ExcludeExitNodes {cc}`);
+    /// requires a GeoIP table, and `"??"` also excludes exits of unknown country
+    #[serde(default)]
+    pub exclude_exit_countries: Vec<String>,
+
+    /// Relays exits are chosen among, as in torrc's `ExitNodes`: fingerprints,
+    /// `{cc}` country codes or nicknames; empty allows any exit
+    #[serde(default)]
+    pub exit_nodes: Vec<String>,
+
+    /// Relays never used in any position, as in torrc's `ExcludeNodes`
+    #[serde(default)]
+    pub exclude_nodes: Vec<String>,
+
+    /// Trust anchors for HTTPS and `wss://` through exits
+    #[serde(default)]
+    pub tls_roots: TlsRoots,
+
+    /// ECH configurations of destinations, whose TLS connections through
+    /// exits then hide the server name from the exit
+    #[serde(default)]
+    pub ech_configs: EchConfigs,
+
+    /// How HTTP requests are written; `TorBrowser` makes them look like Tor
+    /// Browser's and turns off TLS session resumption
+    #[serde(default)]
+    pub request_profile: RequestProfile,
+
+    /// Ordering of the ClientHello sent to WebTunnel bridges (native only);
+    /// see [`TlsFingerprint`] for what it does and doesn't change
+    #[serde(default)]
+    pub bridge_tls_fingerprint: TlsFingerprint,
+
+    /// HTTP CONNECT or SOCKS5 proxy that WebTunnel and obfs4 bridges are
+    /// reached through (native only)
+    #[serde(default)]
+    pub upstream_proxy: Option<UpstreamProxy>,
+
+    /// Application transports that [`BridgeType::Custom`] bridges connect through
+    #[serde(skip)]
+    pub transports: TransportRegistry,
+
+    /// GeoIP table used to annotate relays with their country
+    #[serde(skip)]
+    pub geoip: Option<Arc<GeoIpDb>>,
+
     /// Optional logging callback function (for WASM bindings)
     #[serde(skip)]
     pub on_log: Option<LogCallback>,
+
+    /// Where bootstrap progress is reported, so it can be subscribed to
+    /// before the client starts connecting
+    #[serde(skip)]
+    pub bootstrap_progress: Option<BootstrapProgress>,
+
+    /// Consensus and microdescriptors shared with other clients, so they
+    /// are downloaded once rather than per client
+    #[serde(skip)]
+    pub directory_cache: Option<DirectoryCache>,
+
+    /// Where to persist state such as guard selection (in-memory if unset)
+    #[serde(skip)]
+    pub state_store: Option<StateStoreHandle>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -117,15 +487,59 @@ impl Default for TorClientOptions {
     fn default() -> Self {
         Self {
             bridge: BridgeType::default(),
+            direct: false,
             snowflake_url: String::new(),
             connection_timeout: default_connection_timeout(),
             circuit_timeout: default_circuit_timeout(),
             create_circuit_early: default_create_circuit_early(),
             circuit_update_interval: default_circuit_update_interval(),
             circuit_update_advance: default_circuit_update_advance(),
+            max_circuit_dirtiness: default_max_circuit_dirtiness(),
+            maintenance_interval: default_maintenance_interval(),
+            bridge_probe_interval: default_bridge_probe_interval(),
+            bridge_race_stagger: None,
+            bootstrap_timeout: None,
+            dormant_after: None,
+            visibility_policy: VisibilityPolicy::default(),
+            reachability: ReachabilityConfig::default(),
+            reconnect: ReconnectConfig::default(),
+            build_timeout: BuildTimeoutConfig::default(),
+            congestion_control: default_congestion_control(),
+            conflux: false,
+            stream_retries: default_stream_retries(),
+            optimistic_data: default_optimistic_data(),
+            max_streams_per_circuit: default_max_streams_per_circuit(),
+            parallel_circuit_builds: default_parallel_circuit_builds(),
+            channels_per_bridge: default_channels_per_bridge(),
+            channel_padding: ChannelPadding::default(),
+            vanguards_lite: default_vanguards_lite(),
+            path_selection_seed: None,
+            selection_rng: None,
             bridge_fingerprint: None,
+            bridge_ed25519_id: None,
+            fallback_bridges: Vec::new(),
+            bridge_ntor_key: None,
             stream_isolation: StreamIsolationPolicy::default(),
+            hostname_policy: HostnamePolicy::default(),
+            redirect_policy: RedirectPolicy::default(),
+            cookies: false,
+            decompress_responses: default_decompress_responses(),
+            max_response_size: default_max_response_size(),
+            tls_session_resumption: default_tls_session_resumption(),
+            exclude_exit_countries: Vec::new(),
+            exit_nodes: Vec::new(),
+            exclude_nodes: Vec::new(),
+            tls_roots: TlsRoots::default(),
+            ech_configs: EchConfigs::default(),
+            request_profile: RequestProfile::default(),
+            bridge_tls_fingerprint: TlsFingerprint::default(),
+            upstream_proxy: None,
+            transports: TransportRegistry::default(),
+            geoip: None,
             on_log: None,
+            bootstrap_progress: None,
+            directory_cache: None,
+            state_store: None,
         }
     }
 }
@@ -150,6 +564,73 @@ fn default_circuit_update_advance() -> u64 {
     60_000 // 1 minute
 }
 
+fn default_max_circuit_dirtiness() -> u64 {
+    DEFAULT_MAX_CIRCUIT_DIRTINESS
+}
+
+fn default_maintenance_interval() -> Option<u64> {
+    Some(60_000) // 1 minute
+}
+
+fn default_bridge_probe_interval() -> Option<u64> {
+    Some(5 * 60_000) // 5 minutes
+}
+
+fn default_vanguards_lite() -> bool {
+    true
+}
+
+fn default_congestion_control() -> bool {
+    true
+}
+
+fn default_stream_retries() -> u32 {
+    DEFAULT_STREAM_RETRIES
+}
+
+fn default_decompress_responses() -> bool {
+    true
+}
+
+fn default_tls_session_resumption() -> bool {
+    true
+}
+
+fn default_max_response_size() -> usize {
+    DEFAULT_MAX_RESPONSE_SIZE
+}
+
+fn default_optimistic_data() -> bool {
+    true
+}
+
+fn default_max_streams_per_circuit() -> usize {
+    DEFAULT_MAX_STREAMS_PER_CIRCUIT
+}
+
+fn default_parallel_circuit_builds() -> usize {
+    1
+}
+
+fn default_channels_per_bridge() -> usize {
+    1
+}
+
+/// Default time in milliseconds a circuit takes new streams after its first use (C Tor's MaxCircuitDirtiness)
+pub const DEFAULT_MAX_CIRCUIT_DIRTINESS: u64 = 600_000;
+
+/// Default number of times a request is re-attached to a new circuit after its circuit dies
+pub const DEFAULT_STREAM_RETRIES: u32 = 2;
+
+/// Default cap in bytes on a single HTTP response
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 10 * 1024 * 1024;
+
+/// Default cap on open streams per circuit before new ones spill over
+pub const DEFAULT_MAX_STREAMS_PER_CIRCUIT: usize = 64;
+
+/// How long addresses from [`TorClient::resolve`](crate::TorClient::resolve) are reused
+pub const RESOLVE_CACHE_TTL: Duration = Duration::from_secs(60);
+
 /// Maximum number of circuits to maintain (for preemptive building)
 pub const MAX_CIRCUITS: usize = 5;
 
@@ -159,6 +640,9 @@ pub const MAX_CIRCUITS_PER_ISOLATION_KEY: usize = 1;
 /// Age threshold for preemptive circuit building (circuit_timeout - 10 seconds)
 pub const CIRCUIT_PREBUILD_AGE_THRESHOLD_MS: u64 = 80_000; // 90_000 - 10_000
 
+/// Destination port whose exits are used for prebuilt circuits
+pub const PREBUILD_EXIT_PORT: u16 = 443;
+
 impl TorClientOptions {
     /// Create options for Snowflake bridge using default Tor Project broker
     pub fn snowflake() -> Self {
@@ -193,11 +677,21 @@ impl TorClientOptions {
         Self::snowflake_with_url(snowflake_url)
     }
 
+    /// Create options for connecting directly to guard relays, without a
+    /// bridge (native only)
+    pub fn direct() -> Self {
+        Self {
+            direct: true,
+            ..Default::default()
+        }
+    }
+
     /// Create options for Snowflake bridge via WebRTC (more censorship resistant)
     pub fn snowflake_webrtc() -> Self {
         Self {
             bridge: BridgeType::SnowflakeWebRtc {
                 broker_url: "https://snowflake-broker.torproject.net/".to_string(),
+                fallbacks: Vec::new(),
             },
             bridge_fingerprint: Some(SNOWFLAKE_FINGERPRINT_PRIMARY.to_string()),
             ..Default::default()
@@ -210,6 +704,7 @@ impl TorClientOptions {
             bridge: BridgeType::WebTunnel {
                 url,
                 server_name: None,
+                handshake: WebSocketOptions::default(),
             },
             bridge_fingerprint: Some(fingerprint),
             ..Default::default()
@@ -222,12 +717,94 @@ impl TorClientOptions {
             bridge: BridgeType::WebTunnel {
                 url,
                 server_name: Some(server_name),
+                handshake: WebSocketOptions::default(),
             },
             bridge_fingerprint: Some(fingerprint),
             ..Default::default()
         }
     }
 
+    /// Create options for an obfs4 bridge from its bridge line arguments
+    pub fn obfs4(addr: String, fingerprint: String, cert: String, iat_mode: u8) -> Self {
+        Self {
+            bridge: BridgeType::Obfs4 {
+                addr,
+                cert,
+                iat_mode,
+            },
+            bridge_fingerprint: Some(fingerprint),
+            ..Default::default()
+        }
+    }
+
+    /// Create options for a meek bridge
+    pub fn meek(url: String, fingerprint: String) -> Self {
+        Self {
+            bridge: BridgeType::Meek { url, front: None },
+            bridge_fingerprint: Some(fingerprint),
+            ..Default::default()
+        }
+    }
+
+    /// Create options for a meek bridge reached through a front domain
+    pub fn meek_with_front(url: String, front: String, fingerprint: String) -> Self {
+        Self {
+            bridge: BridgeType::Meek {
+                url,
+                front: Some(front),
+            },
+            bridge_fingerprint: Some(fingerprint),
+            ..Default::default()
+        }
+    }
+
+    /// Create options for a bridge reached through the transport registered
+    /// as `name`
+    pub fn custom(name: String, fingerprint: String) -> Self {
+        Self {
+            bridge: BridgeType::Custom { name },
+            bridge_fingerprint: Some(fingerprint),
+            ..Default::default()
+        }
+    }
+
+    /// Create options from a JSON object with the fields of this struct;
+    /// missing fields take their defaults
+    ///
+    /// Bridges are externally tagged, e.g.
+    /// `{"bridge": {"WebTunnel": {"url": "https://…"}}, "bridge_fingerprint": "…"}`.
+    /// Callbacks, the state store, GeoIP table and custom transports can't
+    /// be given this way and are set with the builders afterwards.
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| TorError::configuration(format!("Invalid client options: {}", e)))
+    }
+
+    /// Create options from a bridge line as handed out by BridgeDB
+    ///
+    /// Snowflake lines without a fingerprint use the default Snowflake bridge.
+    pub fn from_bridge_line(line: &str) -> Result<Self> {
+        let bridge = BridgeConfig::from_bridge_line(line)?;
+        Ok(Self {
+            bridge: bridge.bridge,
+            bridge_fingerprint: bridge.fingerprint,
+            ..Default::default()
+        })
+    }
+
+    /// Create options from several bridge lines: the first is used until it
+    /// fails, then the others in order
+    pub fn from_bridge_lines<S: AsRef<str>>(lines: &[S]) -> Result<Self> {
+        let (first, rest) = lines
+            .split_first()
+            .ok_or_else(|| TorError::configuration("No bridge lines given"))?;
+        let mut options = Self::from_bridge_line(first.as_ref())?;
+        for line in rest {
+            options = options.with_fallback_bridge_line(line.as_ref())?;
+        }
+        Ok(options)
+    }
+
     pub fn with_connection_timeout(mut self, timeout: u64) -> Self {
         self.connection_timeout = timeout;
         self
@@ -253,16 +830,290 @@ impl TorClientOptions {
         self
     }
 
+    pub fn with_max_circuit_dirtiness(mut self, dirtiness: u64) -> Self {
+        self.max_circuit_dirtiness = dirtiness;
+        self
+    }
+
+    pub fn with_maintenance_interval(mut self, interval: Option<u64>) -> Self {
+        self.maintenance_interval = interval;
+        self
+    }
+
+    pub fn with_bridge_probe_interval(mut self, interval: Option<u64>) -> Self {
+        self.bridge_probe_interval = interval;
+        self
+    }
+
+    /// Race the configured bridges with starts `stagger` ms apart instead of
+    /// trying them in turn
+    pub fn with_bridge_race_stagger(mut self, stagger: Option<u64>) -> Self {
+        self.bridge_race_stagger = stagger;
+        self
+    }
+
+    pub fn with_bootstrap_timeout(mut self, timeout: Option<u64>) -> Self {
+        self.bootstrap_timeout = timeout;
+        self
+    }
+
+    /// Go dormant after `idle` ms without activity, or never for `None`
+    pub fn with_dormant_after(mut self, idle: Option<u64>) -> Self {
+        self.dormant_after = idle;
+        self
+    }
+
+    /// Suspend or go dormant while the page is hidden, per `policy`
+    pub fn with_visibility_policy(mut self, policy: VisibilityPolicy) -> Self {
+        self.visibility_policy = policy;
+        self
+    }
+
+    pub fn with_reachability(mut self, config: ReachabilityConfig) -> Self {
+        self.reachability = config;
+        self
+    }
+
+    pub fn with_reconnect(mut self, config: ReconnectConfig) -> Self {
+        self.reconnect = config;
+        self
+    }
+
+    /// Connect straight to guard relays instead of through the bridges
+    pub fn with_direct(mut self, direct: bool) -> Self {
+        self.direct = direct;
+        self
+    }
+
+    pub fn with_build_timeout(mut self, config: BuildTimeoutConfig) -> Self {
+        self.build_timeout = config;
+        self
+    }
+
+    pub fn with_congestion_control(mut self, enabled: bool) -> Self {
+        self.congestion_control = enabled;
+        self
+    }
+
+    pub fn with_conflux(mut self, enabled: bool) -> Self {
+        self.conflux = enabled;
+        self
+    }
+
+    pub fn with_stream_retries(mut self, retries: u32) -> Self {
+        self.stream_retries = retries;
+        self
+    }
+
+    pub fn with_optimistic_data(mut self, enabled: bool) -> Self {
+        self.optimistic_data = enabled;
+        self
+    }
+
+    pub fn with_max_streams_per_circuit(mut self, max: usize) -> Self {
+        self.max_streams_per_circuit = max;
+        self
+    }
+
+    pub fn with_parallel_circuit_builds(mut self, builds: usize) -> Self {
+        self.parallel_circuit_builds = builds;
+        self
+    }
+
+    /// Open `channels` connections to the bridge and spread circuits across them
+    pub fn with_channels_per_bridge(mut self, channels: usize) -> Self {
+        self.channels_per_bridge = channels;
+        self
+    }
+
+    pub fn with_channel_padding(mut self, padding: ChannelPadding) -> Self {
+        self.channel_padding = padding;
+        self
+    }
+
+    pub fn with_vanguards_lite(mut self, enabled: bool) -> Self {
+        self.vanguards_lite = enabled;
+        self
+    }
+
+    /// Make relay selection deterministic; never use this outside tests and simulations
+    pub fn with_path_selection_seed(mut self, seed: u64) -> Self {
+        self.path_selection_seed = Some(seed);
+        self
+    }
+
+    pub fn with_selection_rng(mut self, rng: SelectionRng) -> Self {
+        self.selection_rng = Some(rng);
+        self
+    }
+
+    /// The random source relay selection should use, if not the thread RNG
+    pub fn path_selection_rng(&self) -> Option<SelectionRng> {
+        self.selection_rng
+            .clone()
+            .or_else(|| self.path_selection_seed.map(SelectionRng::from_seed))
+    }
+
     pub fn with_bridge_fingerprint(mut self, fingerprint: String) -> Self {
         self.bridge_fingerprint = Some(fingerprint);
         self
     }
 
+    /// Add a bridge to fail over to
+    pub fn with_fallback_bridge(mut self, bridge: BridgeType, fingerprint: Option<String>) -> Self {
+        self.fallback_bridges
+            .push(BridgeConfig::new(bridge, fingerprint));
+        self
+    }
+
+    /// Add a bridge to fail over to from its bridge line
+    pub fn with_fallback_bridge_line(mut self, line: &str) -> Result<Self> {
+        self.fallback_bridges
+            .push(BridgeConfig::from_bridge_line(line)?);
+        Ok(self)
+    }
+
+    /// Every configured bridge, the primary first
+    pub fn bridges(&self) -> Vec<BridgeConfig> {
+        std::iter::once(BridgeConfig {
+            bridge: self.bridge.clone(),
+            fingerprint: self.bridge_fingerprint.clone(),
+            ed25519_id: self.bridge_ed25519_id.clone(),
+        })
+        .chain(self.fallback_bridges.iter().cloned())
+        .collect()
+    }
+
+    /// Add a Snowflake rendezvous method to fail over to (WebRTC Snowflake only)
+    pub fn with_snowflake_fallback(mut self, rendezvous: Rendezvous) -> Self {
+        if let BridgeType::SnowflakeWebRtc { fallbacks, .. } = &mut self.bridge {
+            fallbacks.push(rendezvous);
+        }
+        self
+    }
+
+    pub fn with_bridge_ed25519_id(mut self, id: String) -> Self {
+        self.bridge_ed25519_id = Some(id);
+        self
+    }
+
+    pub fn with_bridge_ntor_key(mut self, key: String) -> Self {
+        self.bridge_ntor_key = Some(key);
+        self
+    }
+
     pub fn with_stream_isolation(mut self, policy: StreamIsolationPolicy) -> Self {
         self.stream_isolation = policy;
         self
     }
 
+    pub fn with_hostname_policy(mut self, policy: HostnamePolicy) -> Self {
+        self.hostname_policy = policy;
+        self
+    }
+
+    pub fn with_redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect_policy = policy;
+        self
+    }
+
+    pub fn with_cookies(mut self, enabled: bool) -> Self {
+        self.cookies = enabled;
+        self
+    }
+
+    pub fn with_decompress_responses(mut self, enabled: bool) -> Self {
+        self.decompress_responses = enabled;
+        self
+    }
+
+    pub fn with_max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = bytes;
+        self
+    }
+
+    pub fn with_tls_session_resumption(mut self, enabled: bool) -> Self {
+        self.tls_session_resumption = enabled;
+        self
+    }
+
+    /// Write HTTP requests as `profile` does
+    pub fn with_request_profile(mut self, profile: RequestProfile) -> Self {
+        self.request_profile = profile;
+        self
+    }
+
+    /// Order the ClientHello sent to WebTunnel bridges as `fingerprint` says
+    pub fn with_bridge_tls_fingerprint(mut self, fingerprint: TlsFingerprint) -> Self {
+        self.bridge_tls_fingerprint = fingerprint;
+        self
+    }
+
+    /// Reach WebTunnel and obfs4 bridges through `proxy`, e.g.
+    /// `"socks5://127.0.0.1:1080".parse()?` (native only)
+    pub fn with_upstream_proxy(mut self, proxy: UpstreamProxy) -> Self {
+        self.upstream_proxy = Some(proxy);
+        self
+    }
+
+    /// Register `transport` as `name` for [`BridgeType::Custom`] bridges
+    pub fn with_transport(
+        mut self,
+        name: impl Into<String>,
+        transport: Arc<dyn Transport>,
+    ) -> Self {
+        self.transports.register(name, transport);
+        self
+    }
+
+    pub fn with_exclude_exit_countries<I, S>(mut self, countries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.exclude_exit_countries = countries.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Only build circuits to exits in this node list (see [`NodeSet`](crate::relay::NodeSet))
+    pub fn with_exit_nodes<I, S>(mut self, nodes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.exit_nodes = nodes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Never use relays in this node list (see [`NodeSet`](crate::relay::NodeSet))
+    pub fn with_exclude_nodes<I, S>(mut self, nodes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.exclude_nodes = nodes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Trust `roots` for TLS through exits, alongside or instead of the
+    /// built-in roots
+    pub fn with_tls_roots(mut self, roots: TlsRoots) -> Self {
+        self.tls_roots = roots;
+        self
+    }
+
+    /// Encrypt the ClientHello to destinations with a configuration in
+    /// `configs`, so exits don't see which host is contacted
+    pub fn with_ech_configs(mut self, configs: EchConfigs) -> Self {
+        self.ech_configs = configs;
+        self
+    }
+
+    pub fn with_geoip(mut self, geoip: GeoIpDb) -> Self {
+        self.geoip = Some(Arc::new(geoip));
+        self
+    }
+
     pub fn with_on_log<F>(mut self, on_log: F) -> Self
     where
         F: Fn(&str, LogType) + Send + Sync + 'static,
@@ -271,6 +1122,26 @@ impl TorClientOptions {
         self
     }
 
+    /// Report bootstrap progress to `progress` instead of a fresh tracker
+    pub fn with_bootstrap_progress(mut self, progress: BootstrapProgress) -> Self {
+        self.bootstrap_progress = Some(progress);
+        self
+    }
+
+    /// Share network documents with every client given the same `cache`
+    pub fn with_directory_cache(mut self, cache: DirectoryCache) -> Self {
+        self.directory_cache = Some(cache);
+        self
+    }
+
+    pub fn with_state_store<S>(mut self, store: S) -> Self
+    where
+        S: StateStore + 'static,
+    {
+        self.state_store = Some(StateStoreHandle(Arc::new(store)));
+        self
+    }
+
     pub fn connection_timeout_duration(&self) -> Duration {
         Duration::from_millis(self.connection_timeout)
     }
@@ -286,4 +1157,85 @@ impl TorClientOptions {
     pub fn circuit_update_advance_duration(&self) -> Duration {
         Duration::from_millis(self.circuit_update_advance)
     }
+
+    pub fn max_circuit_dirtiness_duration(&self) -> Duration {
+        Duration::from_millis(self.max_circuit_dirtiness)
+    }
+
+    pub fn maintenance_interval_duration(&self) -> Option<Duration> {
+        self.maintenance_interval.map(Duration::from_millis)
+    }
+
+    pub fn bridge_probe_interval_duration(&self) -> Option<Duration> {
+        self.bridge_probe_interval.map(Duration::from_millis)
+    }
+
+    pub fn bridge_race_stagger_duration(&self) -> Option<Duration> {
+        self.bridge_race_stagger.map(Duration::from_millis)
+    }
+
+    pub fn bootstrap_timeout_duration(&self) -> Option<Duration> {
+        self.bootstrap_timeout.map(Duration::from_millis)
+    }
+
+    pub fn dormant_after_duration(&self) -> Option<Duration> {
+        self.dormant_after.map(Duration::from_millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_json_defaults() {
+        let options = TorClientOptions::from_json("{}").unwrap();
+        let defaults = TorClientOptions::default();
+        assert_eq!(options.bridge, defaults.bridge);
+        assert_eq!(options.connection_timeout, defaults.connection_timeout);
+        assert_eq!(
+            options.circuit_update_interval,
+            defaults.circuit_update_interval
+        );
+        assert_eq!(options.congestion_control, defaults.congestion_control);
+    }
+
+    #[test]
+    fn test_from_json_bridges() {
+        let options = TorClientOptions::from_json(
+            r#"{
+                "bridge": {"WebTunnel": {"url": "https://example.com/path", "server_name": null}},
+                "bridge_fingerprint": "AAAA",
+                "fallback_bridges": [
+                    {"bridge": {"Obfs4": {"addr": "192.0.2.1:443", "cert": "xyz"}}, "fingerprint": "BBBB"}
+                ],
+                "connection_timeout": 5000,
+                "bridge_race_stagger": 250
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(options.bridge.transport(), "webtunnel");
+        assert_eq!(options.bridge_fingerprint.as_deref(), Some("AAAA"));
+        assert_eq!(
+            options.fallback_bridges[0].bridge.endpoint(),
+            "192.0.2.1:443"
+        );
+        assert_eq!(
+            options.connection_timeout_duration(),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            options.bridge_race_stagger_duration(),
+            Some(Duration::from_millis(250))
+        );
+    }
+
+    #[test]
+    fn test_from_json_rejects_invalid() {
+        assert!(matches!(
+            TorClientOptions::from_json(r#"{"connection_timeout": "soon"}"#),
+            Err(TorError::Configuration(_))
+        ));
+        assert!(TorClientOptions::from_json(r#"{"bridge": {"Carrier": {}}}"#).is_err());
+    }
 }
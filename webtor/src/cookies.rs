@@ -0,0 +1,467 @@
+//! Cookie jar for the HTTP client
+//!
+//! When enabled, the HTTP client records `Set-Cookie` headers into a
+//! [`CookieJar`] and sends matching cookies back with later requests, per
+//! RFC 6265. The jar is partitioned by isolation key: requests on different
+//! isolation tokens (or different first-party domains, under per-domain
+//! isolation) never see each other's cookies, so a session cookie can't
+//! link identities the circuits keep apart.
+//!
+//! Cookies live in memory only and end with the client.
+
+use crate::isolation::IsolationKey;
+use crate::time::system_time_now;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// Cookies kept per partition; the oldest are evicted beyond this
+pub const MAX_COOKIES_PER_PARTITION: usize = 1000;
+
+/// Longest `name=value` accepted, as in browsers
+const MAX_COOKIE_SIZE: usize = 4096;
+
+/// A stored cookie
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Domain the cookie is sent to, lowercase and without a leading dot
+    pub domain: String,
+    /// Sent to `domain` only, not its subdomains (no `Domain` attribute)
+    pub host_only: bool,
+    pub path: String,
+    /// Sent over HTTPS only
+    pub secure: bool,
+    /// Dropped after this time; `None` lasts as long as the client
+    #[serde(skip)]
+    pub expires: Option<SystemTime>,
+}
+
+impl Cookie {
+    /// Parse a `Set-Cookie` header received from `url`
+    ///
+    /// Returns `None` for cookies the response isn't allowed to set, such as
+    /// ones for another site or a public suffix.
+    pub fn parse(header: &str, url: &Url, now: SystemTime) -> Option<Self> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() || name.len() + value.len() > MAX_COOKIE_SIZE {
+            return None;
+        }
+
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        for attribute in parts {
+            let (key, val) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let val = val.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "expires" => {
+                    if cookie.expires.is_none() {
+                        cookie.expires = parse_http_date(val);
+                    }
+                }
+                "max-age" => {
+                    if let Ok(seconds) = val.parse::<i64>() {
+                        max_age = Some(seconds);
+                    }
+                }
+                "domain" => {
+                    let domain = val.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain.is_empty() {
+                        cookie.domain = domain;
+                        cookie.host_only = false;
+                    }
+                }
+                "path" if val.starts_with('/') => cookie.path = val.to_string(),
+                "secure" => cookie.secure = true,
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires
+        if let Some(seconds) = max_age {
+            cookie.expires = Some(match u64::try_from(seconds) {
+                Ok(seconds) if seconds > 0 => now + Duration::from_secs(seconds),
+                _ => UNIX_EPOCH,
+            });
+        }
+
+        if !cookie.host_only {
+            let is_ip = host.parse::<std::net::IpAddr>().is_ok() || host.starts_with('[');
+            let public_suffix = psl::domain(cookie.domain.as_bytes()).is_none();
+            if !domain_matches(&host, &cookie.domain) || (is_ip && cookie.domain != host) {
+                return None;
+            }
+            if public_suffix {
+                if cookie.domain != host {
+                    return None;
+                }
+                cookie.host_only = true;
+            }
+        }
+        if cookie.secure && url.scheme() != "https" {
+            return None;
+        }
+        if cookie.name.starts_with("__Secure-") && !cookie.secure {
+            return None;
+        }
+        if cookie.name.starts_with("__Host-")
+            && (!cookie.secure || !cookie.host_only || cookie.path != "/")
+        {
+            return None;
+        }
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Whether this cookie goes with a request to `url`
+    pub fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(&host, &self.domain)
+        };
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+}
+
+/// `host` is `domain` or one of its subdomains
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// RFC 6265 path-match
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    match request_path.strip_prefix(cookie_path) {
+        Some(rest) => cookie_path.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The directory of `url`'s path, used when a cookie sets no `Path`
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => url.path()[..end].to_string(),
+    }
+}
+
+/// Parse a cookie date like `Wed, 21 Oct 2026 07:28:00 GMT`
+///
+/// Follows the lenient algorithm of RFC 6265 section 5.1.1, so the
+/// `21-Oct-26` variants some servers send are accepted too.
+fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let (mut day, mut month, mut year, mut time) = (None, None, None, None);
+    for token in date
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != ':')
+        .filter(|token| !token.is_empty())
+    {
+        if time.is_none() && token.contains(':') {
+            let fields: Vec<u64> = token.split(':').filter_map(|f| f.parse().ok()).collect();
+            if let [h, m, s] = fields[..] {
+                // An out-of-range time makes the whole date unparseable
+                if h > 23 || m > 59 || s > 59 {
+                    return None;
+                }
+                time = Some(h * 3600 + m * 60 + s);
+                continue;
+            }
+        }
+        let digits = token.bytes().all(|b| b.is_ascii_digit());
+        if day.is_none() && digits && token.len() <= 2 {
+            day = token.parse::<u32>().ok();
+            continue;
+        }
+        if month.is_none() && token.len() >= 3 {
+            const MONTHS: [&str; 12] = [
+                "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+            ];
+            let prefix = token[..3].to_ascii_lowercase();
+            if let Some(index) = MONTHS.iter().position(|m| *m == prefix) {
+                month = Some(index as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() && digits && (2..=4).contains(&token.len()) && day.is_some() {
+            year = token.parse::<i64>().ok().map(|y| match y {
+                0..=69 => y + 2000,
+                70..=99 => y + 1900,
+                _ => y,
+            });
+        }
+    }
+
+    let (day, month, year, time) = (day?, month?, year?, time?);
+    if !(1..=31).contains(&day) || year < 1601 {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day) * 86_400 + time as i64;
+    Some(match u64::try_from(seconds) {
+        Ok(seconds) => UNIX_EPOCH + Duration::from_secs(seconds),
+        Err(_) => UNIX_EPOCH,
+    })
+}
+
+/// Days from 1970-01-01 to the given proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// In-memory cookie store, partitioned by isolation key
+#[derive(Clone, Default)]
+pub struct CookieJar {
+    partitions: Arc<Mutex<HashMap<Option<IsolationKey>, Vec<Cookie>>>>,
+}
+
+impl std::fmt::Debug for CookieJar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CookieJar")
+            .field("cookies", &self.len())
+            .finish()
+    }
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the `Set-Cookie` headers of a response from `url`
+    pub fn store<'a>(
+        &self,
+        partition: Option<&IsolationKey>,
+        url: &Url,
+        set_cookies: impl IntoIterator<Item = &'a str>,
+    ) {
+        let now = system_time_now();
+        let mut partitions = self.partitions();
+        let cookies = partitions.entry(partition.cloned()).or_default();
+        for header in set_cookies {
+            let Some(cookie) = Cookie::parse(header, url, now) else {
+                continue;
+            };
+            cookies.retain(|old| {
+                (&old.name, &old.domain, &old.path) != (&cookie.name, &cookie.domain, &cookie.path)
+            });
+            if !cookie.is_expired(now) {
+                cookies.push(cookie);
+            }
+        }
+        if cookies.len() > MAX_COOKIES_PER_PARTITION {
+            let excess = cookies.len() - MAX_COOKIES_PER_PARTITION;
+            cookies.drain(..excess);
+        }
+    }
+
+    /// Value for the `Cookie` header of a request to `url`, if any cookie matches
+    ///
+    /// Cookies with longer paths come first, as RFC 6265 recommends.
+    pub fn cookie_header(&self, partition: Option<&IsolationKey>, url: &Url) -> Option<String> {
+        let mut matching = self.cookies_for(partition, url);
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        let pairs: Vec<String> = matching
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Unexpired cookies in `partition` that go with a request to `url`
+    pub fn cookies_for(&self, partition: Option<&IsolationKey>, url: &Url) -> Vec<Cookie> {
+        let now = system_time_now();
+        let mut partitions = self.partitions();
+        let Some(cookies) = partitions.get_mut(&partition.cloned()) else {
+            return Vec::new();
+        };
+        cookies.retain(|cookie| !cookie.is_expired(now));
+        cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url))
+            .cloned()
+            .collect()
+    }
+
+    /// Number of cookies stored across all partitions
+    pub fn len(&self) -> usize {
+        self.partitions().values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget every cookie
+    pub fn clear(&self) {
+        self.partitions().clear();
+    }
+
+    /// Drop expired cookies, and partitions left empty, returning how many
+    /// cookies were removed
+    pub fn purge_expired(&self) -> usize {
+        let now = system_time_now();
+        let mut partitions = self.partitions();
+        let before: usize = partitions.values().map(Vec::len).sum();
+        partitions.retain(|_, cookies| {
+            cookies.retain(|cookie| !cookie.is_expired(now));
+            !cookies.is_empty()
+        });
+        before - partitions.values().map(Vec::len).sum::<usize>()
+    }
+
+    fn partitions(&self) -> std::sync::MutexGuard<'_, HashMap<Option<IsolationKey>, Vec<Cookie>>> {
+        self.partitions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_cookies_follow_domain_path_and_scheme_rules() {
+        let jar = CookieJar::new();
+        let login = url("https://api.example.com/v1/login");
+        jar.store(
+            None,
+            &login,
+            [
+                "session=abc; Path=/; Secure; HttpOnly",
+                "pref=dark; Domain=.example.com; Path=/",
+                "scoped=1",
+                "tracker=x; Domain=com",
+                "other=y; Domain=example.org",
+                "__Host-id=1; Path=/",
+            ],
+        );
+        assert_eq!(jar.len(), 3);
+
+        assert_eq!(
+            jar.cookie_header(None, &url("https://api.example.com/v1/items"))
+                .as_deref(),
+            Some("scoped=1; session=abc; pref=dark")
+        );
+        // Secure and host-only cookies stay behind
+        assert_eq!(
+            jar.cookie_header(None, &url("http://www.example.com/"))
+                .as_deref(),
+            Some("pref=dark")
+        );
+        assert!(jar
+            .cookie_header(None, &url("https://api.example.com/v10"))
+            .is_some_and(|header| !header.contains("scoped")));
+
+        // Partitions don't share cookies
+        let other = IsolationKey("token:alice".to_string());
+        assert!(jar.cookie_header(Some(&other), &login).is_none());
+
+        // Deleting by expiry
+        jar.store(
+            None,
+            &login,
+            ["session=; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT"],
+        );
+        jar.store(
+            None,
+            &login,
+            ["pref=; Domain=example.com; Path=/; Max-Age=0"],
+        );
+        assert_eq!(jar.len(), 1);
+    }
+
+    #[test]
+    fn test_purge_expired_cookies() {
+        let jar = CookieJar::new();
+        let site = url("https://example.com/");
+        jar.store(None, &site, ["kept=1; Max-Age=3600"]);
+        // Set an hour ago with a minute to live
+        let an_hour_ago = system_time_now() - Duration::from_secs(3600);
+        let stale = Cookie::parse("stale=1; Max-Age=60", &site, an_hour_ago).unwrap();
+        let alice = IsolationKey("token:alice".to_string());
+        jar.partitions().insert(Some(alice), vec![stale.clone()]);
+        jar.partitions().get_mut(&None).unwrap().push(stale);
+        assert_eq!(jar.len(), 3);
+
+        assert_eq!(jar.purge_expired(), 2);
+        assert_eq!(jar.len(), 1);
+        assert_eq!(jar.partitions().len(), 1);
+        assert_eq!(jar.purge_expired(), 0);
+    }
+
+    #[test]
+    fn test_parse_http_date_variants() {
+        let expected = UNIX_EPOCH + Duration::from_secs(1_792_567_680);
+        assert_eq!(
+            parse_http_date("Wed, 21 Oct 2026 07:28:00 GMT"),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_http_date("Wednesday, 21-Oct-26 07:28:00 GMT"),
+            Some(expected)
+        );
+        assert_eq!(parse_http_date("not a date"), None);
+    }
+
+    #[test]
+    fn test_out_of_range_times_are_unparseable() {
+        for date in [
+            "Wed, 01 Jan 2025 18446744073709551615:00:00 GMT",
+            "Wed, 01 Jan 2025 00:18446744073709551615:00 GMT",
+            "Wed, 01 Jan 2025 24:00:00 GMT",
+            "Wed, 01 Jan 2025 23:60:00 GMT",
+            "Wed, 01 Jan 2025 23:59:60 GMT",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{}", date);
+        }
+        assert!(parse_http_date("Wed, 01 Jan 2025 23:59:59 GMT").is_some());
+
+        // The cookie is kept, as a session cookie
+        let cookie = Cookie::parse(
+            "a=1; Expires=Wed, 01 Jan 2025 18446744073709551615:00:00 GMT",
+            &url("https://example.com/"),
+            system_time_now(),
+        )
+        .unwrap();
+        assert_eq!(cookie.expires, None);
+    }
+}
@@ -0,0 +1,294 @@
+//! Tor over an application-provided WebRTC DataChannel
+//!
+//! For deployments that run their own WebRTC bridge infrastructure instead of
+//! Snowflake's broker and volunteer proxies: the application creates the
+//! RTCPeerConnection, does its own signalling and hands over an open (or
+//! opening) RTCDataChannel. The same reliability and framing layers Snowflake
+//! uses are stacked on top:
+//!
+//!   RTCDataChannel (from the application)
+//!       ↓
+//!   Turbo → KCP → SMUX → TLS
+//!       ↓
+//!   Tor protocol
+//!
+//! so the far end must speak the Snowflake server protocol. The resulting
+//! `DataChannelStream` is passed to `TorClient::with_stream` along with the
+//! bridge fingerprint. Only the DataChannel and TLS ends are wasm-only;
+//! [`establish_reliable_stack`] works over any message transport.
+
+use crate::error::Result;
+use crate::kcp_stream::{KcpConfig, KcpStream};
+use crate::smux::SmuxStream;
+use crate::turbo::TurboStream;
+use futures::{AsyncRead, AsyncWrite};
+use tracing::info;
+
+#[cfg(target_arch = "wasm32")]
+use crate::error::TorError;
+#[cfg(target_arch = "wasm32")]
+use crate::webrtc_stream::WebRtcStream;
+#[cfg(target_arch = "wasm32")]
+use std::io;
+#[cfg(target_arch = "wasm32")]
+use std::pin::Pin;
+#[cfg(target_arch = "wasm32")]
+use std::task::{Context, Poll};
+#[cfg(target_arch = "wasm32")]
+use subtle_tls::{TlsConfig, TlsConnector, TlsStream};
+#[cfg(target_arch = "wasm32")]
+use web_sys::RtcDataChannel;
+
+/// Turbo → KCP → SMUX stack over a message transport
+pub type ReliableStack<S> = SmuxStream<KcpStream<TurboStream<S>>>;
+
+/// Parameters of the reliability layers
+#[derive(Debug, Clone)]
+pub struct DataChannelConfig {
+    /// KCP conversation ID (0 for Snowflake servers)
+    pub kcp_conv: u32,
+    /// SMUX stream ID (3 for Snowflake servers)
+    pub smux_stream_id: u32,
+}
+
+impl Default for DataChannelConfig {
+    fn default() -> Self {
+        Self {
+            kcp_conv: 0,
+            smux_stream_id: 3,
+        }
+    }
+}
+
+impl DataChannelConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_kcp_conv(mut self, conv: u32) -> Self {
+        self.kcp_conv = conv;
+        self
+    }
+
+    pub fn with_stream_id(mut self, stream_id: u32) -> Self {
+        self.smux_stream_id = stream_id;
+        self
+    }
+}
+
+/// Stack Turbo, KCP and SMUX on top of a message transport
+pub async fn establish_reliable_stack<S>(
+    transport: S,
+    config: &DataChannelConfig,
+) -> Result<ReliableStack<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // 1. Turbo framing
+    info!("Initializing Turbo layer...");
+    let mut turbo = TurboStream::new(transport);
+    turbo.initialize().await?;
+    info!("Turbo layer initialized");
+
+    // 2. KCP for reliability
+    info!("Initializing KCP layer...");
+    let kcp_config = KcpConfig {
+        conv: config.kcp_conv,
+        ..Default::default()
+    };
+    let kcp = KcpStream::new(turbo, kcp_config);
+    info!("KCP layer initialized");
+
+    // 3. SMUX for multiplexing
+    info!("Initializing SMUX layer...");
+    let mut smux = SmuxStream::with_stream_id(kcp, config.smux_stream_id);
+    smux.initialize().await?;
+    info!("SMUX layer initialized");
+
+    Ok(smux)
+}
+
+/// Stack Turbo, KCP, SMUX and Tor link TLS on top of a message transport
+#[cfg(target_arch = "wasm32")]
+pub async fn establish_tunnel<S>(
+    transport: S,
+    config: &DataChannelConfig,
+) -> Result<TlsStream<ReliableStack<S>>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let smux = establish_reliable_stack(transport, config).await?;
+
+    // 4. TLS for Tor link encryption
+    // Tor relays use self-signed certificates, so skip verification
+    // (authentication happens via CERTS cells in the Tor protocol)
+    info!("Establishing TLS over SMUX...");
+    let tls_config = TlsConfig {
+        skip_verification: true, // Tor uses self-signed certs, validated via CERTS cells
+        alpn_protocols: vec![],
+        ..Default::default()
+    };
+    let connector = TlsConnector::with_config(tls_config);
+    // Use a placeholder server name since Tor doesn't use SNI
+    let tls_stream = connector
+        .connect(smux, "www.example.com")
+        .await
+        .map_err(|e| TorError::tls(format!("TLS handshake failed: {}", e)))?;
+    info!("TLS layer established over SMUX");
+
+    Ok(tls_stream)
+}
+
+/// Connect over an RTCDataChannel the application established
+#[cfg(target_arch = "wasm32")]
+pub async fn connect_data_channel(
+    channel: RtcDataChannel,
+    config: DataChannelConfig,
+) -> Result<DataChannelStream> {
+    info!("Connecting over application DataChannel");
+    let webrtc = WebRtcStream::from_data_channel(channel).await?;
+    let tls = establish_tunnel(webrtc, &config).await?;
+    info!("DataChannel connection established: DataChannel → Turbo → KCP → SMUX → TLS");
+    Ok(DataChannelStream { inner: tls })
+}
+
+/// Tor link stream over an application-provided DataChannel
+#[cfg(target_arch = "wasm32")]
+pub struct DataChannelStream {
+    inner: TlsStream<ReliableStack<WebRtcStream>>,
+}
+
+// Safety: WASM is single-threaded
+#[cfg(target_arch = "wasm32")]
+unsafe impl Send for DataChannelStream {}
+
+#[cfg(target_arch = "wasm32")]
+impl tor_rtcompat::StreamOps for DataChannelStream {}
+
+#[cfg(target_arch = "wasm32")]
+impl tor_rtcompat::CertifiedConn for DataChannelStream {
+    fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.inner.peer_certificate().map(|cert| cert.to_vec()))
+    }
+
+    fn export_keying_material(
+        &self,
+        len: usize,
+        _label: &[u8],
+        _context: Option<&[u8]>,
+    ) -> io::Result<Vec<u8>> {
+        // Same placeholder as the Snowflake stream; RFC 5705 export is not implemented
+        tracing::warn!("export_keying_material called but not fully implemented");
+        Ok(vec![0u8; len])
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl AsyncRead for DataChannelStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl AsyncWrite for DataChannelStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smux::{SmuxCommand, SmuxSegment};
+    use crate::turbo::TurboFrame;
+    use std::io;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+
+    /// Message transport that records every write and never delivers data
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl AsyncRead for Recorder {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    impl AsyncWrite for Recorder {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.0.lock().unwrap().push(buf.to_vec());
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reliable_stack_opens_configured_stream() {
+        let recorder = Recorder::default();
+        let config = DataChannelConfig::new()
+            .with_kcp_conv(0x0102_0304)
+            .with_stream_id(7);
+        establish_reliable_stack(recorder.clone(), &config)
+            .await
+            .unwrap();
+
+        let writes = recorder.0.lock().unwrap().clone();
+        // Turbo handshake: 8-byte token followed by the 8-byte client ID
+        assert_eq!(writes[0].len(), 16);
+        assert_eq!(
+            writes[0][..8],
+            [0x12, 0x93, 0x60, 0x5d, 0x27, 0x81, 0x75, 0xf5]
+        );
+
+        // Next comes a Turbo data frame carrying a KCP segment for the
+        // configured conversation, whose payload is the SMUX SYN
+        let (frame, _) = TurboFrame::decode(&writes[1]).unwrap().unwrap();
+        assert!(!frame.is_padding);
+        let kcp = frame.data;
+        assert_eq!(kcp[..4], 0x0102_0304u32.to_le_bytes());
+        let (syn, _) = SmuxSegment::decode(&kcp[24..]).unwrap().unwrap();
+        assert_eq!(syn.command, SmuxCommand::Syn);
+        assert_eq!(syn.stream_id, 7);
+    }
+
+    #[test]
+    fn test_default_config_matches_snowflake_server() {
+        let config = DataChannelConfig::default();
+        assert_eq!(config.kcp_conv, 0);
+        assert_eq!(config.smux_stream_id, 3);
+    }
+}
@@ -0,0 +1,176 @@
+//! Direct connections to relays
+//!
+//! Users who don't need to get around censorship can skip the bridge and,
+//! like tor itself, speak Tor link TLS straight to a guard's ORPort. This
+//! needs raw TCP sockets, so it is only available in native builds.
+//!
+//! A client without a consensus has no guards to choose from, so it first
+//! fetches one from a [fallback directory](fallback_dirs), as tor does, and
+//! checks the directory authorities' signatures on it. It then samples
+//! guards from the consensus ([`guard_candidates`]) and keeps using them
+//! across sessions, as with bridges.
+
+use crate::guard::GuardCandidate;
+use crate::relay::{flags, Relay, RelayManager, RelayQuery};
+use tor_dircommon::config::NetworkConfig;
+
+/// A fallback directory's ORPort and identities
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FallbackDir {
+    /// ORPort address (`ip:port`, IPv6 in brackets)
+    pub addr: String,
+    /// RSA identity fingerprint (hex)
+    pub fingerprint: String,
+    /// Ed25519 identity (hex)
+    pub ed25519_identity: String,
+}
+
+/// The fallback directories shipped with arti: long-lived relays that
+/// serve the first consensus, so bootstrapping clients leave the directory
+/// authorities alone
+///
+/// Those reachable over IPv4 are listed at their IPv4 ORPort, the others
+/// at their first address.
+pub fn fallback_dirs() -> Vec<FallbackDir> {
+    use tor_linkspec::{HasAddrs, HasRelayIdsLegacy};
+
+    NetworkConfig::default()
+        .fallback_caches()
+        .iter()
+        .filter_map(|fallback| {
+            let addr = fallback
+                .addrs()
+                .find(|addr| addr.is_ipv4())
+                .or_else(|| fallback.addrs().next())?;
+            Some(FallbackDir {
+                addr: addr.to_string(),
+                fingerprint: hex::encode_upper(fallback.rsa_identity().as_bytes()),
+                ed25519_identity: hex::encode(fallback.ed_identity().as_bytes()),
+            })
+        })
+        .collect()
+}
+
+/// Relays that may be sampled as guards: those with the Guard flag that
+/// also serve directory requests
+pub fn guard_candidates(relays: &RelayManager) -> Vec<GuardCandidate> {
+    let query = RelayQuery::new()
+        .with_flag(flags::GUARD)
+        .with_flag(flags::V2DIR);
+    relays
+        .query(&query)
+        .into_iter()
+        .filter(|relay| !relays.is_excluded(relay))
+        .map(GuardCandidate::from)
+        .collect()
+}
+
+/// The relay's ORPort address (`ip:port`, IPv6 in brackets)
+pub fn or_addr(relay: &Relay) -> String {
+    if relay.address.contains(':') {
+        format!("[{}]:{}", relay.address, relay.or_port)
+    } else {
+        format!("{}:{}", relay.address, relay.or_port)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::OrPortTransport;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use crate::error::{Result, TorError};
+    use crate::retry::with_timeout;
+    use crate::transport::{LinkStream, TorLinkStream, Transport};
+    use std::time::Duration;
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    /// Tor link TLS over TCP to a relay's ORPort
+    #[derive(Debug, Clone)]
+    pub struct OrPortTransport {
+        addr: String,
+        timeout: Duration,
+    }
+
+    impl OrPortTransport {
+        pub fn new(addr: impl Into<String>, timeout: Duration) -> Self {
+            Self {
+                addr: addr.into(),
+                timeout,
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for OrPortTransport {
+        async fn connect(&self) -> Result<Box<dyn LinkStream>> {
+            let stream = with_timeout(self.timeout, "ORPort connection", async {
+                let tcp = tokio::net::TcpStream::connect(&self.addr)
+                    .await
+                    .map_err(|e| {
+                        TorError::Network(format!("Failed to connect to {}: {}", self.addr, e))
+                    })?;
+                tcp.set_nodelay(true).ok();
+                TorLinkStream::handshake(tcp.compat()).await
+            })
+            .await?;
+            Ok(Box::new(stream))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn relay(fingerprint: &str, address: &str, relay_flags: &[&str]) -> Relay {
+        Relay::new(
+            fingerprint.to_string(),
+            fingerprint.to_lowercase(),
+            address.to_string(),
+            443,
+            relay_flags
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<HashSet<_>>(),
+            "00".repeat(32),
+        )
+    }
+
+    #[test]
+    fn test_guard_candidates() {
+        let relays = RelayManager::new(vec![
+            relay("AA", "192.0.2.1", &[flags::GUARD, flags::V2DIR]),
+            relay("BB", "192.0.2.2", &[flags::GUARD]),
+            relay("CC", "192.0.2.3", &[flags::V2DIR, flags::EXIT]),
+        ]);
+        let candidates = guard_candidates(&relays);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].fingerprint, "AA");
+    }
+
+    #[test]
+    fn test_or_addr() {
+        assert_eq!(or_addr(&relay("AA", "192.0.2.1", &[])), "192.0.2.1:443");
+        assert_eq!(
+            or_addr(&relay("AA", "2001:db8::1", &[])),
+            "[2001:db8::1]:443"
+        );
+    }
+
+    #[test]
+    fn test_fallback_dirs_are_well_formed() {
+        let fallbacks = fallback_dirs();
+        assert!(fallbacks.len() > 100);
+        for fallback in &fallbacks {
+            assert!(fallback.addr.parse::<std::net::SocketAddr>().is_ok());
+            let identity = crate::identity::ExpectedIdentity::parse(
+                &fallback.fingerprint,
+                Some(&fallback.ed25519_identity),
+            )
+            .unwrap();
+            assert!(identity.ed25519.is_some());
+        }
+    }
+}
@@ -1,16 +1,29 @@
 //! Directory management and consensus fetching
 
+use crate::bootstrap::BootstrapProgress;
+use crate::build_timeout::BuildTimeoutEstimator;
 use crate::error::{Result, TorError};
+use crate::events::CircuitEvents;
+use crate::hsdir::HsDirParams;
+use crate::metrics::{DirectorySource, Metrics};
 use crate::relay::{Relay, RelayManager};
 use crate::time::system_time_now;
 use futures::{AsyncReadExt, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+#[cfg(target_arch = "wasm32")]
 use std::io::Read;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use tokio::sync::RwLock;
-use tor_checkable::Timebound;
+use tor_checkable::{ExternallySigned, SelfSigned, Timebound};
+#[cfg(target_arch = "wasm32")]
+use tor_dircommon::config::DirTolerance;
+use tor_dircommon::config::NetworkConfig;
+use tor_llcrypto::pk::rsa::RsaIdentity;
+use tor_netdoc::doc::authcert::AuthCert;
 use tor_netdoc::doc::microdesc::MicrodescReader;
-use tor_netdoc::doc::netstatus::MdConsensus;
+use tor_netdoc::doc::netstatus::{MdConsensus, RelayWeight, UnvalidatedMdConsensus};
 use tor_netdoc::AllowAnnotations;
 use tor_proto::channel::Channel;
 use tor_proto::client::circuit::TimeoutEstimator;
@@ -20,20 +33,297 @@ use tracing::{debug, error, info, warn};
 #[cfg(target_arch = "wasm32")]
 const CACHED_CONSENSUS_BASE_URL: &str = "https://privacy-ethereum.github.io/webtor-rs";
 
+/// Network documents shared by several clients, so a page downloads the
+/// consensus and microdescriptors once rather than once per client
+///
+/// Give each client the same cache with
+/// [`with_directory_cache`](crate::TorClientOptions::with_directory_cache).
+/// A client installs the relays another one loaded while that consensus is
+/// fresh, and a client about to download waits for one already doing so.
+#[derive(Clone, Default)]
+pub struct DirectoryCache {
+    inner: Arc<DirectoryCacheInner>,
+}
+
+#[derive(Default)]
+struct DirectoryCacheInner {
+    documents: Mutex<Option<Arc<SharedDirectory>>>,
+    /// Held while a client loads documents, so the others wait for them
+    loading: tokio::sync::Mutex<()>,
+}
+
+/// Relays built from one consensus and its microdescriptors
+struct SharedDirectory {
+    relays: Vec<Relay>,
+    hsdir_params: HsDirParams,
+    /// `None` for the static cache, which only stands in until a client
+    /// fetches over its channel
+    lifetime: Option<ConsensusLifetime>,
+}
+
+/// When a consensus was published, when a newer one is expected and when it
+/// expires
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsensusLifetime {
+    pub valid_after: SystemTime,
+    pub fresh_until: SystemTime,
+    pub valid_until: SystemTime,
+}
+
+impl ConsensusLifetime {
+    fn of(consensus: &MdConsensus) -> Self {
+        let lifetime = consensus.lifetime();
+        Self {
+            valid_after: lifetime.valid_after(),
+            fresh_until: lifetime.fresh_until(),
+            valid_until: lifetime.valid_until(),
+        }
+    }
+
+    /// Whether no newer consensus is expected yet
+    pub fn is_fresh(&self) -> bool {
+        system_time_now() < self.fresh_until
+    }
+
+    /// Whether the consensus hasn't expired
+    pub fn is_valid(&self) -> bool {
+        system_time_now() < self.valid_until
+    }
+}
+
+/// Relays of a network consensus saved by [`DirectoryManager::snapshot`],
+/// for a later session to start with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectorySnapshot {
+    pub lifetime: ConsensusLifetime,
+    relays: Vec<Relay>,
+    hsdir_params: HsDirParams,
+}
+
+impl DirectorySnapshot {
+    pub fn relay_count(&self) -> usize {
+        self.relays.len()
+    }
+}
+
+impl DirectoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of relays cached, 0 before any client loaded a directory
+    pub fn relay_count(&self) -> usize {
+        self.documents()
+            .map_or(0, |documents| documents.relays.len())
+    }
+
+    /// Forget the cached documents; clients keep the relays they installed
+    pub fn clear(&self) {
+        *self.inner.documents.lock().unwrap() = None;
+    }
+
+    fn documents(&self) -> Option<Arc<SharedDirectory>> {
+        self.inner.documents.lock().unwrap().clone()
+    }
+
+    /// Cached documents from a consensus that is still fresh
+    fn fresh(&self) -> Option<Arc<SharedDirectory>> {
+        self.documents()
+            .filter(|documents| documents.lifetime.is_some_and(|l| l.is_fresh()))
+    }
+
+    /// Offer documents to the other clients; the static cache never
+    /// replaces documents from the network
+    fn publish(&self, documents: SharedDirectory) {
+        let mut current = self.inner.documents.lock().unwrap();
+        let keep = documents.lifetime.is_none()
+            && current
+                .as_ref()
+                .is_some_and(|current| current.lifetime.is_some());
+        if !keep {
+            *current = Some(Arc::new(documents));
+        }
+    }
+}
+
+impl std::fmt::Debug for DirectoryCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DirectoryCache")
+            .field("relays", &self.relay_count())
+            .finish()
+    }
+}
+
 /// Directory manager for handling network documents
 pub struct DirectoryManager {
     pub relay_manager: Arc<RwLock<RelayManager>>,
+    metrics: Metrics,
+    progress: BootstrapProgress,
+    build_timeouts: BuildTimeoutEstimator,
+    cache: Option<DirectoryCache>,
+    /// Lifetime of the consensus the relays came from
+    lifetime: Mutex<Option<ConsensusLifetime>>,
+    events: CircuitEvents,
 }
 
 impl DirectoryManager {
     pub fn new(relay_manager: Arc<RwLock<RelayManager>>) -> Self {
-        Self { relay_manager }
+        Self {
+            relay_manager,
+            metrics: Metrics::default(),
+            progress: BootstrapProgress::default(),
+            build_timeouts: BuildTimeoutEstimator::default(),
+            cache: None,
+            lifetime: Mutex::new(None),
+            events: CircuitEvents::default(),
+        }
+    }
+
+    /// Record consensus fetch outcomes into a shared metrics registry
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Report download progress to the client's bootstrap watchdog
+    pub fn with_bootstrap_progress(mut self, progress: BootstrapProgress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Share the circuit manager's learned build timeout for directory circuits
+    pub fn with_build_timeouts(mut self, build_timeouts: BuildTimeoutEstimator) -> Self {
+        self.build_timeouts = build_timeouts;
+        self
+    }
+
+    /// Share downloaded documents with other clients through `cache`
+    pub fn with_cache(mut self, cache: DirectoryCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Report installed consensuses as events
+    pub fn with_events(mut self, events: CircuitEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Install `relays` from `source` and offer them to clients sharing the
+    /// cache
+    async fn install_relays(
+        &self,
+        relays: Vec<Relay>,
+        hsdir_params: HsDirParams,
+        lifetime: Option<ConsensusLifetime>,
+        source: &str,
+    ) -> usize {
+        let count = relays.len();
+        if let Some(cache) = &self.cache {
+            cache.publish(SharedDirectory {
+                relays: relays.clone(),
+                hsdir_params: hsdir_params.clone(),
+                lifetime,
+            });
+        }
+        {
+            let mut manager = self.relay_manager.write().await;
+            manager.update_relays(relays);
+            manager.hsdir_params = Some(hsdir_params);
+        }
+        *self.lifetime.lock().unwrap() = lifetime;
+        self.progress.relays_loaded(count);
+        self.events.consensus_installed(count, source);
+        count
+    }
+
+    /// Install relays another client loaded into the shared cache
+    async fn install_shared(&self, documents: &SharedDirectory) {
+        let count = documents.relays.len();
+        {
+            let mut manager = self.relay_manager.write().await;
+            manager.update_relays(documents.relays.clone());
+            manager.hsdir_params = Some(documents.hsdir_params.clone());
+        }
+        *self.lifetime.lock().unwrap() = documents.lifetime;
+        self.progress.relays_loaded(count);
+        self.events.consensus_installed(count, "shared");
+        info!("Installed {} relays from the shared directory cache", count);
+    }
+
+    /// Lifetime of the consensus the relays came from; `None` before one
+    /// is loaded and for the static cache
+    pub fn lifetime(&self) -> Option<ConsensusLifetime> {
+        *self.lifetime.lock().unwrap()
+    }
+
+    /// The relays of the loaded consensus, if it came from the network and
+    /// hasn't expired
+    pub async fn snapshot(&self) -> Option<DirectorySnapshot> {
+        let lifetime = self.lifetime().filter(ConsensusLifetime::is_valid)?;
+        let manager = self.relay_manager.read().await;
+        Some(DirectorySnapshot {
+            lifetime,
+            relays: manager.relays.clone(),
+            hsdir_params: manager.hsdir_params.clone()?,
+        })
+    }
+
+    /// Install the relays of a [`snapshot`](Self::snapshot) unless they
+    /// expired or are older than those loaded, returning whether they were
+    ///
+    /// A snapshot is trusted like the state store it came from; it isn't
+    /// checked against the consensus signatures again.
+    pub async fn restore(&self, snapshot: &DirectorySnapshot) -> bool {
+        if !snapshot.lifetime.is_valid() {
+            info!("Saved directory has expired; not restoring it");
+            return false;
+        }
+        if self
+            .lifetime()
+            .is_some_and(|current| current.valid_after >= snapshot.lifetime.valid_after)
+        {
+            debug!("Loaded consensus is at least as new as the saved directory");
+            return false;
+        }
+        self.progress.consensus_valid(snapshot.relays.len());
+        self.progress
+            .add_descriptors_received(snapshot.relays.len());
+        let count = self
+            .install_relays(
+                snapshot.relays.clone(),
+                snapshot.hsdir_params.clone(),
+                Some(snapshot.lifetime),
+                "restored",
+            )
+            .await;
+        info!("Restored {} relays from a saved directory", count);
+        true
     }
 
     /// Load relays from cached consensus data fetched from static URL.
     /// This is used for WASM builds where we can't fetch consensus before establishing a circuit.
     #[cfg(target_arch = "wasm32")]
     pub async fn load_cached_consensus(&self) -> Result<()> {
+        let _loading = match &self.cache {
+            Some(cache) => {
+                let loading = cache.inner.loading.lock().await;
+                if let Some(documents) = cache.documents() {
+                    self.install_shared(&documents).await;
+                    return Ok(());
+                }
+                Some(loading)
+            }
+            None => None,
+        };
+        let result = self.fetch_cached_consensus().await;
+        self.metrics
+            .record_directory_fetch(DirectorySource::Cached, result.is_ok());
+        result
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    async fn fetch_cached_consensus(&self) -> Result<()> {
         info!("Fetching cached consensus from static URL...");
 
         // Fetch brotli-compressed consensus
@@ -68,149 +358,184 @@ impl DirectoryManager {
             microdescs_body.len()
         );
 
+        // Deployments from before the daily job published certificates
+        // don't have them; the consensus is then only checked once
+        // bootstrap replaces it with one fetched over the channel
+        let certs_url = format!("{}/authority-certs.txt.br", CACHED_CONSENSUS_BASE_URL);
+        let certs_body = match fetch_url(&certs_url).await {
+            Ok(certs_bytes) => Some(decompress_brotli(&certs_bytes).map_err(|e| {
+                TorError::Internal(format!(
+                    "Failed to decompress authority certificates: {}",
+                    e
+                ))
+            })?),
+            Err(e) => {
+                warn!(
+                    "No cached authority certificates ({}); using the cached consensus unchecked until bootstrap",
+                    e
+                );
+                None
+            }
+        };
+
         // Parse and process
-        self.process_consensus_data(&consensus_body, &microdescs_body)
+        self.process_consensus_data(&consensus_body, &microdescs_body, certs_body.as_deref())
             .await
     }
 
-    /// Load relays from cached consensus - native version (not implemented, use fetch_and_process_consensus)
+    /// Load relays from cached consensus - native version (only the shared
+    /// cache, otherwise use fetch_and_process_consensus)
     #[cfg(not(target_arch = "wasm32"))]
     pub async fn load_cached_consensus(&self) -> Result<()> {
+        if let Some(documents) = self.cache.as_ref().and_then(DirectoryCache::fresh) {
+            self.install_shared(&documents).await;
+            return Ok(());
+        }
         // For native builds, we don't pre-load cached consensus
         // The circuit will be created after fetching fresh consensus via the Tor network
         info!("Native build: skipping cached consensus (will fetch fresh via Tor)");
         Ok(())
     }
 
-    /// Process consensus and microdescriptor data into relays
+    /// Process consensus and microdescriptor data into relays, checking the
+    /// consensus signatures if `certs_body` has the authority certificates
     #[cfg(target_arch = "wasm32")]
     async fn process_consensus_data(
         &self,
         consensus_body: &str,
         microdescs_body: &str,
+        certs_body: Option<&str>,
     ) -> Result<()> {
         info!("Parsing consensus...");
 
-        // Parse consensus - for cached data, we skip time validation since it may be stale
-        // The daily update job keeps it fresh enough for circuit building
         let (_, _, unvalidated) = MdConsensus::parse(consensus_body)
             .map_err(|e| TorError::serialization(format!("Failed to parse consensus: {}", e)))?;
 
-        // For cached consensus, we use dangerously_assume_timely() to skip time checks
-        // This is acceptable because:
-        // 1. The cached consensus is updated daily by GitHub Actions
-        // 2. We only use it for relay selection, not for security-critical decisions
-        // 3. Once connected, we can fetch fresh consensus through the Tor network
-        let consensus = unvalidated.dangerously_assume_timely();
-
-        let inner_consensus = &consensus.consensus;
-        info!(
-            "Parsed consensus with {} relays",
-            inner_consensus.relays().len()
-        );
-
-        let mut router_statuses = HashMap::new();
-        for router in inner_consensus.relays() {
-            router_statuses.insert(*router.md_digest(), router.clone());
-        }
-
-        let mut relays = Vec::new();
-        let reader =
-            MicrodescReader::new(microdescs_body, &AllowAnnotations::AnnotationsNotAllowed)?;
-        for microdesc in reader {
-            let microdesc = match microdesc {
-                Ok(md) => md.into_microdesc(),
-                Err(e) => {
-                    warn!("Failed to parse microdescriptor: {}", e);
-                    continue;
-                }
-            };
+        // The cached consensus is refreshed daily, so it has usually expired
+        // by a few hours; accept it within the tolerance tor clients allow a
+        // consensus they can't replace yet (up to three days past expiry)
+        let now = system_time_now();
+        let consensus = DirTolerance::default()
+            .extend_tolerance(unvalidated)
+            .check_valid_at(&now)
+            .map_err(|e| TorError::ConsensusFetch(format!("Cached consensus is too old: {}", e)))?;
+        let consensus = match certs_body {
+            Some(certs_body) => {
+                check_authority_signatures(consensus, certs_body, &authority_ids(), now)?
+            }
+            // Only picks relays until bootstrap fetches a checked consensus
+            // over the bridge, which needs none of them
+            None => consensus.dangerously_assume_wellsigned(),
+        };
+        info!("Parsed consensus with {} relays", consensus.relays().len());
+
+        let relays = build_relays(&consensus, microdescs_body)?.relays;
+        let hsdir_params = HsDirParams::from_consensus(&consensus);
+        let count = self
+            .install_relays(relays, hsdir_params, None, "cached")
+            .await;
+        info!("Loaded {} relays from cached consensus", count);
 
-            if let Some(router) = router_statuses.get(microdesc.digest()) {
-                let nickname = router.nickname().to_string();
-                let fingerprint = hex::encode(router.rsa_identity().as_bytes());
+        Ok(())
+    }
 
-                let address = if let Some(addr) = router.addrs().next() {
-                    addr.ip().to_string()
-                } else {
-                    continue;
-                };
+    /// Install a consensus and its microdescriptors supplied by the embedder
+    ///
+    /// For applications that mirror directory data on their own CDN. The
+    /// consensus must be currently valid and signed by more than half of the
+    /// directory authorities, whose key certificates `certs_body` holds
+    /// (`/tor/keys/all` from a directory cache will do), and every
+    /// microdescriptor must match a digest listed in it; nothing is
+    /// installed otherwise. Consensus entries without a microdescriptor are
+    /// allowed and reported as missing.
+    pub async fn inject_documents(
+        &self,
+        consensus_body: &str,
+        microdescs_body: &str,
+        certs_body: &str,
+    ) -> Result<DirectoryInjection> {
+        let result = self
+            .install_documents(consensus_body, microdescs_body, certs_body)
+            .await;
+        self.metrics
+            .record_directory_fetch(DirectorySource::Injected, result.is_ok());
+        result
+    }
 
-                let or_port = router.addrs().next().map(|a| a.port()).unwrap_or(0);
+    async fn install_documents(
+        &self,
+        consensus_body: &str,
+        microdescs_body: &str,
+        certs_body: &str,
+    ) -> Result<DirectoryInjection> {
+        let (_, _, unvalidated) = MdConsensus::parse(consensus_body)
+            .map_err(|e| TorError::serialization(format!("Failed to parse consensus: {}", e)))?;
+        let now = system_time_now();
+        let consensus = unvalidated.check_valid_at(&now).map_err(|e| {
+            TorError::ConsensusFetch(format!("Injected consensus is not timely: {}", e))
+        })?;
+        let consensus = check_authority_signatures(consensus, certs_body, &authority_ids(), now)?;
+
+        let (relays, injection) = validate_documents(&consensus, microdescs_body)?;
+        let hsdir_params = HsDirParams::from_consensus(&consensus);
+        let lifetime = ConsensusLifetime::of(&consensus);
+        let count = self
+            .install_relays(relays, hsdir_params, Some(lifetime), "injected")
+            .await;
+        info!(
+            "Installed {} relays from injected directory ({} without microdescriptors)",
+            count, injection.missing
+        );
+        Ok(injection)
+    }
 
-                let mut flags = std::collections::HashSet::new();
-                if router.is_flagged_fast() {
-                    flags.insert("Fast".to_string());
-                }
-                if router.is_flagged_stable() {
-                    flags.insert("Stable".to_string());
-                }
-                if router.is_flagged_guard() {
-                    flags.insert("Guard".to_string());
-                }
-                if router.is_flagged_exit() {
-                    flags.insert("Exit".to_string());
-                }
-                if router.is_flagged_bad_exit() {
-                    flags.insert("BadExit".to_string());
-                }
-                if router.is_flagged_hsdir() {
-                    flags.insert("HSDir".to_string());
-                }
-                if router.is_flagged_v2dir() {
-                    flags.insert("V2Dir".to_string());
+    /// Fetch a consensus and its microdescriptors over `channel`, unless a
+    /// client sharing the directory cache has fresh ones
+    pub async fn fetch_and_process_consensus(&self, channel: Arc<Channel>) -> Result<()> {
+        let _loading = match &self.cache {
+            Some(cache) => {
+                let loading = cache.inner.loading.lock().await;
+                if let Some(documents) = cache.fresh() {
+                    // Nothing left to download
+                    self.progress.consensus_valid(documents.relays.len());
+                    self.progress
+                        .add_descriptors_received(documents.relays.len());
+                    self.install_shared(&documents).await;
+                    return Ok(());
                 }
-
-                let ntor_onion_key = hex::encode(microdesc.ntor_key().as_bytes());
-
-                let mut relay = Relay::new(
-                    fingerprint,
-                    nickname,
-                    address,
-                    or_port,
-                    flags,
-                    ntor_onion_key,
-                );
-
-                relay.ed25519_identity = Some(hex::encode(microdesc.ed25519_id().as_bytes()));
-
-                relays.push(relay);
+                Some(loading)
             }
-        }
-
-        let count = relays.len();
-
-        {
-            let mut manager = self.relay_manager.write().await;
-            manager.update_relays(relays);
-        }
-
-        info!("Loaded {} relays from cached consensus", count);
-
-        Ok(())
+            None => None,
+        };
+        let result = self.fetch_consensus_over_channel(channel).await;
+        self.metrics
+            .record_directory_fetch(DirectorySource::Channel, result.is_ok());
+        result
     }
 
-    pub async fn fetch_and_process_consensus(&self, channel: Arc<Channel>) -> Result<()> {
+    async fn fetch_consensus_over_channel(&self, channel: Arc<Channel>) -> Result<()> {
         let consensus_body = self.fetch_consensus_body(channel.clone()).await?;
 
         info!("Parsing full consensus");
         let (_, _, unvalidated) = MdConsensus::parse(&consensus_body)
             .map_err(|e| TorError::serialization(format!("Failed to parse consensus: {}", e)))?;
-        let consensus = unvalidated
-            .check_valid_at(&system_time_now())
-            .map_err(|e| {
-                TorError::ConsensusFetch(format!("Consensus timeliness check failed: {}", e))
-            })?;
+        let now = system_time_now();
+        let consensus = unvalidated.check_valid_at(&now).map_err(|e| {
+            TorError::ConsensusFetch(format!("Consensus timeliness check failed: {}", e))
+        })?;
 
-        let inner_consensus = &consensus.consensus;
+        let authorities = authority_ids();
+        let certs_body = self
+            .fetch_dir_document(channel.clone(), &authority_certs_path(&authorities), |n| {
+                self.progress.add_consensus_bytes(n)
+            })
+            .await?;
+        let consensus = check_authority_signatures(consensus, &certs_body, &authorities, now)?;
 
-        let digests: Vec<[u8; 32]> = inner_consensus
-            .relays()
-            .iter()
-            .map(|r| *r.md_digest())
-            .collect();
+        let digests: Vec<[u8; 32]> = consensus.relays().iter().map(|r| *r.md_digest()).collect();
 
         info!("Got {} microdescriptor digests", digests.len());
+        self.progress.consensus_valid(digests.len());
 
         let microdescs_body = self.fetch_microdescriptors_body(channel, &digests).await?;
         info!(
@@ -218,82 +543,12 @@ impl DirectoryManager {
             microdescs_body.len()
         );
 
-        let mut router_statuses = HashMap::new();
-        for router in inner_consensus.relays() {
-            router_statuses.insert(*router.md_digest(), router.clone());
-        }
-
-        let mut relays = Vec::new();
-        let reader =
-            MicrodescReader::new(&microdescs_body, &AllowAnnotations::AnnotationsNotAllowed)?;
-        for microdesc in reader {
-            let microdesc = match microdesc {
-                Ok(md) => md.into_microdesc(),
-                Err(e) => {
-                    warn!("Failed to parse microdescriptor: {}", e);
-                    continue;
-                }
-            };
-
-            if let Some(router) = router_statuses.get(microdesc.digest()) {
-                let nickname = router.nickname().to_string();
-                let fingerprint = hex::encode(router.rsa_identity().as_bytes());
-
-                let address = if let Some(addr) = router.addrs().next() {
-                    addr.ip().to_string()
-                } else {
-                    continue;
-                };
-
-                let or_port = router.addrs().next().map(|a| a.port()).unwrap_or(0);
-
-                let mut flags = std::collections::HashSet::new();
-                if router.is_flagged_fast() {
-                    flags.insert("Fast".to_string());
-                }
-                if router.is_flagged_stable() {
-                    flags.insert("Stable".to_string());
-                }
-                if router.is_flagged_guard() {
-                    flags.insert("Guard".to_string());
-                }
-                if router.is_flagged_exit() {
-                    flags.insert("Exit".to_string());
-                }
-                if router.is_flagged_bad_exit() {
-                    flags.insert("BadExit".to_string());
-                }
-                if router.is_flagged_hsdir() {
-                    flags.insert("HSDir".to_string());
-                }
-                if router.is_flagged_v2dir() {
-                    flags.insert("V2Dir".to_string());
-                }
-
-                let ntor_onion_key = hex::encode(microdesc.ntor_key().as_bytes());
-
-                let mut relay = Relay::new(
-                    fingerprint,
-                    nickname,
-                    address,
-                    or_port,
-                    flags,
-                    ntor_onion_key,
-                );
-
-                relay.ed25519_identity = Some(hex::encode(microdesc.ed25519_id().as_bytes()));
-
-                relays.push(relay);
-            }
-        }
-
-        let count = relays.len();
-
-        {
-            let mut manager = self.relay_manager.write().await;
-            manager.update_relays(relays);
-        }
-
+        let relays = build_relays(&consensus, &microdescs_body)?.relays;
+        let hsdir_params = HsDirParams::from_consensus(&consensus);
+        let lifetime = ConsensusLifetime::of(&consensus);
+        let count = self
+            .install_relays(relays, hsdir_params, Some(lifetime), "channel")
+            .await;
         info!("Updated RelayManager with {} relays", count);
 
         Ok(())
@@ -301,12 +556,28 @@ impl DirectoryManager {
 
     async fn fetch_consensus_body(&self, channel: Arc<Channel>) -> Result<String> {
         info!("Fetching consensus from bridge...");
+        let body = self
+            .fetch_dir_document(
+                channel,
+                "/tor/status-vote/current/consensus-microdesc",
+                |n| self.progress.add_consensus_bytes(n),
+            )
+            .await?;
+        info!("Received consensus response: {} bytes", body.len());
+        Ok(body)
+    }
 
+    /// GET `path` from the relay at the other end of `channel`, over a
+    /// one-hop circuit, and return the response body
+    async fn fetch_dir_document(
+        &self,
+        channel: Arc<Channel>,
+        path: &str,
+        on_bytes: impl Fn(usize),
+    ) -> Result<String> {
         // 1. Create 1-hop circuit (tunnel)
         let (pending_tunnel, reactor) = channel
-            .new_tunnel(
-                Arc::new(crate::circuit::SimpleTimeoutEstimator) as Arc<dyn TimeoutEstimator>
-            )
+            .new_tunnel(Arc::new(self.build_timeouts.clone()) as Arc<dyn TimeoutEstimator>)
             .await
             .map_err(|e| {
                 TorError::Internal(format!("Failed to create pending tunnel for dir: {}", e))
@@ -339,8 +610,7 @@ impl DirectoryManager {
             .await
             .map_err(|e| TorError::Internal(format!("Failed to begin dir stream: {}", e)))?;
 
-        // 3. Send HTTP GET request for microdescriptor consensus
-        let path = "/tor/status-vote/current/consensus-microdesc";
+        // 3. Send HTTP GET request
         let request = format!(
             "GET {} HTTP/1.0\r\n\
              Host: directory\r\n\
@@ -359,13 +629,7 @@ impl DirectoryManager {
             .map_err(|e| TorError::Network(format!("Failed to flush dir request: {}", e)))?;
 
         // 4. Read response
-        let mut response = Vec::new();
-        stream
-            .read_to_end(&mut response)
-            .await
-            .map_err(|e| TorError::Network(format!("Failed to read dir response: {}", e)))?;
-
-        info!("Received consensus response: {} bytes", response.len());
+        let response = read_dir_response(&mut stream, on_bytes).await?;
 
         // 5. Process response
         let body_start = response
@@ -403,7 +667,7 @@ impl DirectoryManager {
             info!(
                 "Fetching chunk batch {}/{} (chunks {}-{})",
                 batch_idx + 1,
-                (total_chunks + MAX_PARALLEL_CHUNKS - 1) / MAX_PARALLEL_CHUNKS,
+                total_chunks.div_ceil(MAX_PARALLEL_CHUNKS),
                 batch_start + 1,
                 (batch_start + batch.len()).min(total_chunks)
             );
@@ -448,82 +712,255 @@ impl DirectoryManager {
             digests.len()
         );
 
-        let (pending_tunnel, reactor) = channel
-            .new_tunnel(
-                Arc::new(crate::circuit::SimpleTimeoutEstimator) as Arc<dyn TimeoutEstimator>
-            )
-            .await
-            .map_err(|e| {
-                TorError::Internal(format!("Failed to create pending tunnel for dir: {}", e))
-            })?;
+        let digests_str: Vec<String> = digests.iter().map(hex::encode_upper).collect();
+        let path = format!("/tor/micro/d/{}", digests_str.join("-"));
+        let body = self
+            .fetch_dir_document(channel, &path, |n| self.progress.add_descriptor_bytes(n))
+            .await?;
 
-        #[cfg(target_arch = "wasm32")]
-        wasm_bindgen_futures::spawn_local(async move {
-            if let Err(e) = reactor.run().await {
-                error!("Dir circuit reactor finished with error: {}", e);
-            }
-        });
+        debug!(
+            "Chunk {}/{}: received {} bytes",
+            chunk_idx + 1,
+            total_chunks,
+            body.len()
+        );
 
-        #[cfg(not(target_arch = "wasm32"))]
-        tokio::spawn(async move {
-            if let Err(e) = reactor.run().await {
-                error!("Dir circuit reactor finished with error: {}", e);
+        self.progress.add_descriptors_received(digests.len());
+        Ok(body)
+    }
+}
+
+/// Summary of an injected consensus and microdescriptor set
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryInjection {
+    /// Relays listed in the consensus
+    pub consensus_relays: usize,
+    /// Microdescriptors supplied
+    pub microdescriptors: usize,
+    /// Relays installed for path selection
+    pub relays_loaded: usize,
+    /// Consensus entries with no supplied microdescriptor
+    pub missing: usize,
+}
+
+/// Identities of the directory authorities whose signatures make a
+/// consensus valid: the ones tor and arti ship with
+pub fn authority_ids() -> Vec<RsaIdentity> {
+    NetworkConfig::default().authorities().v3idents().clone()
+}
+
+/// Directory path serving the current key certificates of `authorities`
+fn authority_certs_path(authorities: &[RsaIdentity]) -> String {
+    let ids: Vec<String> = authorities
+        .iter()
+        .map(|id| hex::encode_upper(id.as_bytes()))
+        .collect();
+    format!("/tor/keys/fp/{}", ids.join("+"))
+}
+
+/// Accept `consensus` if more than half of `authorities` signed it
+///
+/// The signing keys come from the certificates in `certs_body`; a
+/// certificate only counts if one of `authorities` signed it and it is
+/// current at `now`.
+fn check_authority_signatures(
+    consensus: UnvalidatedMdConsensus,
+    certs_body: &str,
+    authorities: &[RsaIdentity],
+    now: SystemTime,
+) -> Result<MdConsensus> {
+    let certs: Vec<AuthCert> = AuthCert::parse_multiple(certs_body)
+        .map_err(|e| {
+            TorError::serialization(format!("Failed to parse authority certificates: {}", e))
+        })?
+        .filter_map(|cert| {
+            let cert = cert
+                .ok()?
+                .check_signature()
+                .ok()?
+                .check_valid_at(&now)
+                .ok()?;
+            authorities.contains(cert.id_fingerprint()).then_some(cert)
+        })
+        .collect();
+    debug!(
+        "Checking consensus with {} authority certificates",
+        certs.len()
+    );
+    let n_authorities = u16::try_from(authorities.len())
+        .map_err(|_| TorError::configuration("Too many directory authorities"))?;
+    consensus
+        .set_n_authorities(n_authorities)
+        .check_signature(&certs)
+        .map_err(|e| {
+            TorError::ConsensusFetch(format!(
+                "Consensus isn't signed by enough directory authorities: {}",
+                e
+            ))
+        })
+}
+
+/// Check that every microdescriptor belongs to `consensus` and build the relays
+fn validate_documents(
+    consensus: &MdConsensus,
+    microdescs_body: &str,
+) -> Result<(Vec<Relay>, DirectoryInjection)> {
+    let documents = build_relays(consensus, microdescs_body)?;
+    if documents.unparsable > 0 || documents.unmatched > 0 {
+        return Err(TorError::ConsensusFetch(format!(
+            "{} of {} microdescriptors are unparsable or not listed in the consensus",
+            documents.unparsable + documents.unmatched,
+            documents.microdescriptors
+        )));
+    }
+    if documents.relays.is_empty() {
+        return Err(TorError::ConsensusFetch(
+            "No microdescriptors match the consensus".to_string(),
+        ));
+    }
+
+    let consensus_relays = consensus.relays().len();
+    let injection = DirectoryInjection {
+        consensus_relays,
+        microdescriptors: documents.microdescriptors,
+        relays_loaded: documents.relays.len(),
+        missing: consensus_relays.saturating_sub(documents.relays.len()),
+    };
+    Ok((documents.relays, injection))
+}
+
+/// Relays built from a consensus and a set of microdescriptors
+#[derive(Default)]
+struct RelayDocuments {
+    relays: Vec<Relay>,
+    /// Microdescriptors in the input, including unparsable ones
+    microdescriptors: usize,
+    unparsable: usize,
+    /// Microdescriptors whose digest the consensus doesn't list
+    unmatched: usize,
+}
+
+/// Pair each microdescriptor with the consensus entry listing its digest
+///
+/// Digests are computed from the microdescriptor text, so a descriptor that
+/// was altered or belongs to another consensus never matches.
+fn build_relays(consensus: &MdConsensus, microdescs_body: &str) -> Result<RelayDocuments> {
+    let mut router_statuses = HashMap::new();
+    for router in consensus.relays() {
+        router_statuses.insert(*router.md_digest(), router);
+    }
+
+    let mut documents = RelayDocuments::default();
+    let reader = MicrodescReader::new(microdescs_body, &AllowAnnotations::AnnotationsNotAllowed)?;
+    for microdesc in reader {
+        documents.microdescriptors += 1;
+        let microdesc = match microdesc {
+            Ok(md) => md.into_microdesc(),
+            Err(e) => {
+                warn!("Failed to parse microdescriptor: {}", e);
+                documents.unparsable += 1;
+                continue;
             }
-        });
+        };
 
-        let params = crate::circuit::make_circ_params()?;
-        let tunnel = pending_tunnel
-            .create_firsthop_fast(params)
-            .await
-            .map_err(|e| TorError::Internal(format!("Failed to create dir circuit: {}", e)))?;
+        match router_statuses.get(microdesc.digest()) {
+            None => documents.unmatched += 1,
+            Some(router) => {
+                let nickname = router.nickname().to_string();
+                let fingerprint = hex::encode(router.rsa_identity().as_bytes());
 
-        let tunnel_arc = Arc::new(tunnel);
-        let mut stream = tunnel_arc
-            .begin_dir_stream()
-            .await
-            .map_err(|e| TorError::Internal(format!("Failed to begin dir stream: {}", e)))?;
+                let address = if let Some(addr) = router.addrs().next() {
+                    addr.ip().to_string()
+                } else {
+                    continue;
+                };
 
-        let digests_str: Vec<String> = digests.iter().map(|d| hex::encode_upper(d)).collect();
-        let path = format!("/tor/micro/d/{}", digests_str.join("-"));
+                let or_port = router.addrs().next().map(|a| a.port()).unwrap_or(0);
 
-        let request = format!(
-            "GET {} HTTP/1.0\r\n\
-             Host: directory\r\n\
-             Connection: close\r\n\
-             \r\n",
-            path
-        );
+                let mut flags = std::collections::HashSet::new();
+                if router.is_flagged_fast() {
+                    flags.insert("Fast".to_string());
+                }
+                if router.is_flagged_stable() {
+                    flags.insert("Stable".to_string());
+                }
+                if router.is_flagged_guard() {
+                    flags.insert("Guard".to_string());
+                }
+                if router.is_flagged_exit() {
+                    flags.insert("Exit".to_string());
+                }
+                if router.is_flagged_bad_exit() {
+                    flags.insert("BadExit".to_string());
+                }
+                if router.is_flagged_hsdir() {
+                    flags.insert("HSDir".to_string());
+                }
+                if router.is_flagged_v2dir() {
+                    flags.insert("V2Dir".to_string());
+                }
 
-        stream
-            .write_all(request.as_bytes())
-            .await
-            .map_err(|e| TorError::Network(format!("Failed to write dir request: {}", e)))?;
-        stream
-            .flush()
-            .await
-            .map_err(|e| TorError::Network(format!("Failed to flush dir request: {}", e)))?;
+                let ntor_onion_key = hex::encode(microdesc.ntor_key().as_bytes());
 
-        let mut response = Vec::new();
-        stream
-            .read_to_end(&mut response)
-            .await
-            .map_err(|e| TorError::Network(format!("Failed to read dir response: {}", e)))?;
+                let mut relay = Relay::new(
+                    fingerprint,
+                    nickname,
+                    address,
+                    or_port,
+                    flags,
+                    ntor_onion_key,
+                );
 
-        debug!(
-            "Chunk {}/{}: received {} bytes",
-            chunk_idx + 1,
-            total_chunks,
-            response.len()
-        );
+                relay.ed25519_identity = Some(hex::encode(microdesc.ed25519_id().as_bytes()));
+                relay.bandwidth = consensus_bandwidth(router.weight());
+                relay.protocols = Some(router.protovers().to_string());
+                relay.exit_policy = Some(microdesc.ipv4_policy().clone());
+                relay.family = microdesc
+                    .family()
+                    .members()
+                    .map(|id| hex::encode(id.as_bytes()))
+                    .collect();
+                relay.family_ids = microdesc
+                    .family_ids()
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect();
+
+                documents.relays.push(relay);
+            }
+        }
+    }
 
-        let body_start = response
-            .windows(4)
-            .position(|w| w == b"\r\n\r\n")
-            .map(|i| i + 4)
-            .unwrap_or(0);
+    Ok(documents)
+}
 
-        let body = &response[body_start..];
-        Ok(String::from_utf8_lossy(body).to_string())
+/// Bandwidth weight from a consensus entry (measured or self-reported), 0 if unknown
+fn consensus_bandwidth(weight: &RelayWeight) -> u64 {
+    match weight {
+        RelayWeight::Measured(w) | RelayWeight::Unmeasured(w) => u64::from(*w),
+        _ => 0,
+    }
+}
+
+/// Read a directory response to the end, reporting bytes as they arrive so a
+/// stalled download shows up in the bootstrap report
+async fn read_dir_response<R>(stream: &mut R, on_bytes: impl Fn(usize)) -> Result<Vec<u8>>
+where
+    R: futures::AsyncRead + Unpin,
+{
+    let mut response = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = stream
+            .read(&mut buf)
+            .await
+            .map_err(|e| TorError::Network(format!("Failed to read dir response: {}", e)))?;
+        if n == 0 {
+            return Ok(response);
+        }
+        on_bytes(n);
+        response.extend_from_slice(&buf[..n]);
     }
 }
 
@@ -546,7 +983,7 @@ async fn fetch_url(url: &str) -> std::result::Result<Vec<u8>, String> {
 
     let window = web_sys::window().ok_or("No window")?;
 
-    let mut opts = RequestInit::new();
+    let opts = RequestInit::new();
     opts.set_method("GET");
 
     let request = Request::new_with_str_and_init(url, &opts)
@@ -577,3 +1014,208 @@ async fn fetch_url(url: &str) -> std::result::Result<Vec<u8>, String> {
 
     Ok(bytes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Duration;
+
+    /// A parsed consensus and a moment it is valid at: just after its
+    /// publication
+    fn published(body: &str) -> (UnvalidatedMdConsensus, SystemTime) {
+        let (_, _, unvalidated) = MdConsensus::parse(body).unwrap();
+        let (valid_after, _) = unvalidated.bounds();
+        let now = valid_after.unwrap() + Duration::from_secs(1);
+        (unvalidated.check_valid_at(&now).unwrap(), now)
+    }
+
+    /// The checked-in consensus; the authority certificates of its day
+    /// aren't checked in, so its signatures are taken on trust
+    fn cached_consensus() -> MdConsensus {
+        published(&cached_document("consensus.txt.br"))
+            .0
+            .dangerously_assume_wellsigned()
+    }
+
+    fn cached_document(name: &str) -> String {
+        let path = format!("{}/src/cached/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let compressed = std::fs::read(path).unwrap();
+        let mut body = String::new();
+        brotli::Decompressor::new(&compressed[..], 4096)
+            .read_to_string(&mut body)
+            .unwrap();
+        body
+    }
+
+    #[test]
+    fn test_validate_documents_rejects_foreign_microdescriptors() {
+        let consensus = cached_consensus();
+        let microdescs_body = cached_document("microdescriptors.txt.br");
+
+        let (relays, injection) = validate_documents(&consensus, &microdescs_body).unwrap();
+        assert!(!relays.is_empty());
+        assert_eq!(injection.relays_loaded, relays.len());
+        assert_eq!(
+            injection.missing,
+            injection.consensus_relays - injection.relays_loaded
+        );
+
+        // Altering one descriptor changes its digest, so the set is refused
+        let at = microdescs_body.find("\nid ed25519 ").unwrap() + "\nid ed25519 ".len();
+        let mut tampered = microdescs_body.clone();
+        let flipped = if &tampered[at..at + 1] == "A" {
+            "B"
+        } else {
+            "A"
+        };
+        tampered.replace_range(at..at + 1, flipped);
+        let err = validate_documents(&consensus, &tampered).unwrap_err();
+        assert!(err.to_string().contains("1 of"));
+    }
+
+    #[test]
+    fn test_consensus_needs_most_authorities() {
+        let consensus_body =
+            include_str!("../../vendor/arti/crates/tor-netdoc/testdata/mdconsensus1.txt");
+        let certs_body =
+            include_str!("../../vendor/arti/crates/tor-netdoc/testdata/authcerts2.txt");
+        let authorities: Vec<RsaIdentity> = [
+            "5A23BA701776C9C1AB1C06E734E92AB3D5350D64",
+            "7C47DCB4A90E2C2B7C7AD27BD641D038CF5D7EBE",
+            "5696AB38CB3852AFA476A5C07B2D4788963D5567",
+        ]
+        .iter()
+        .map(|id| RsaIdentity::from_hex(id).unwrap())
+        .collect();
+        let unvalidated = || published(consensus_body);
+
+        let (consensus, now) = unvalidated();
+        let consensus =
+            check_authority_signatures(consensus, certs_body, &authorities, now).unwrap();
+        assert_eq!(consensus.relays().len(), 6);
+
+        // One certificate out of three authorities is not a majority
+        let (consensus, now) = unvalidated();
+        let first_cert = &certs_body[..certs_body.find("-----END SIGNATURE-----").unwrap() + 24];
+        assert!(check_authority_signatures(consensus, first_cert, &authorities, now).is_err());
+
+        // Nor are all three signatures among twelve authorities
+        let (consensus, now) = unvalidated();
+        let mut more = authorities.clone();
+        more.extend(authority_ids());
+        assert!(check_authority_signatures(consensus, certs_body, &more, now).is_err());
+
+        // Signatures from authorities not believed in don't count
+        let (consensus, now) = unvalidated();
+        assert!(check_authority_signatures(consensus, certs_body, &authority_ids(), now).is_err());
+
+        // Nor do expired certificates
+        let (consensus, now) = unvalidated();
+        let later = now + Duration::from_secs(2 * 365 * 24 * 3600);
+        assert!(check_authority_signatures(consensus, certs_body, &authorities, later).is_err());
+    }
+
+    #[test]
+    fn test_authority_certs_path() {
+        let path = authority_certs_path(&authority_ids());
+        assert!(path.starts_with("/tor/keys/fp/"));
+        assert_eq!(path.matches('+').count(), authority_ids().len() - 1);
+        assert!(path.contains("F533C81CEF0BC0267857C99B2F471ADF249FA232"));
+    }
+
+    /// A consensus published an hour ago, fresh for `fresh_secs` more and
+    /// valid for two hours after that
+    fn lifetime(fresh_secs: u64) -> ConsensusLifetime {
+        let now = system_time_now();
+        let fresh_until = now + Duration::from_secs(fresh_secs);
+        ConsensusLifetime {
+            valid_after: now - Duration::from_secs(3600),
+            fresh_until,
+            valid_until: fresh_until + Duration::from_secs(7200),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_directory_cache_is_shared() {
+        let consensus = cached_consensus();
+        let microdescs_body = cached_document("microdescriptors.txt.br");
+        let relays = build_relays(&consensus, &microdescs_body).unwrap().relays;
+        let hsdir_params = HsDirParams::from_consensus(&consensus);
+        let manager = |cache: &DirectoryCache| {
+            DirectoryManager::new(Arc::new(RwLock::new(RelayManager::new(Vec::new()))))
+                .with_cache(cache.clone())
+        };
+
+        let cache = DirectoryCache::new();
+        let first = manager(&cache);
+        let count = first
+            .install_relays(
+                relays.clone(),
+                hsdir_params.clone(),
+                Some(lifetime(3600)),
+                "channel",
+            )
+            .await;
+        assert_eq!(cache.relay_count(), count);
+
+        // A second client takes the relays instead of downloading them
+        let second = manager(&cache);
+        second.load_cached_consensus().await.unwrap();
+        assert_eq!(second.relay_manager.read().await.relays.len(), count);
+        assert!(second.relay_manager.read().await.hsdir_params.is_some());
+
+        // The static cache doesn't replace documents from the network
+        cache.publish(SharedDirectory {
+            relays: Vec::new(),
+            hsdir_params: hsdir_params.clone(),
+            lifetime: None,
+        });
+        assert_eq!(cache.relay_count(), count);
+
+        // Once stale, the documents are no longer handed out
+        first
+            .install_relays(relays, hsdir_params, Some(lifetime(0)), "channel")
+            .await;
+        let third = manager(&cache);
+        third.load_cached_consensus().await.unwrap();
+        assert!(third.relay_manager.read().await.relays.is_empty());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_snapshot_and_restore() {
+        let consensus = cached_consensus();
+        let microdescs_body = cached_document("microdescriptors.txt.br");
+        let relays = build_relays(&consensus, &microdescs_body).unwrap().relays;
+        let hsdir_params = HsDirParams::from_consensus(&consensus);
+        let manager =
+            || DirectoryManager::new(Arc::new(RwLock::new(RelayManager::new(Vec::new()))));
+
+        // Only a consensus from the network is worth saving
+        let first = manager();
+        first
+            .install_relays(relays.clone(), hsdir_params.clone(), None, "cached")
+            .await;
+        assert!(first.snapshot().await.is_none());
+        let count = first
+            .install_relays(relays, hsdir_params, Some(lifetime(3600)), "channel")
+            .await;
+        let snapshot = first.snapshot().await.unwrap();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: DirectorySnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot.relay_count(), count);
+
+        let second = manager();
+        assert!(second.restore(&snapshot).await);
+        assert_eq!(second.relay_manager.read().await.relays.len(), count);
+        assert_eq!(second.lifetime(), Some(snapshot.lifetime));
+        // Nor does it replace a consensus as new
+        assert!(!second.restore(&snapshot).await);
+
+        let mut expired = snapshot;
+        expired.lifetime.valid_until = system_time_now();
+        assert!(!manager().restore(&expired).await);
+    }
+}
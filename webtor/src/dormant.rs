@@ -0,0 +1,122 @@
+//! Dormant mode
+//!
+//! With [`dormant_after`](crate::TorClientOptions::dormant_after) set, a
+//! client that has seen no requests and has no open streams for that long
+//! closes its channel and circuits, sparing the battery of a phone or a
+//! background tab and the bridge's resources. The consensus, guards and
+//! everything else stay, so the next request bootstraps again from the
+//! channel handshake on. Both transitions are reported as
+//! [`ChannelEvent`](crate::events::ChannelEvent)s.
+
+use crate::time::Instant;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// When the client was last used, and whether it has gone dormant
+#[derive(Clone)]
+pub(crate) struct Activity {
+    state: Arc<Mutex<ActivityState>>,
+    /// Held while a request connects a dormant or fresh client, so
+    /// concurrent requests wait for one channel instead of each dialing
+    connecting: Arc<tokio::sync::Mutex<()>>,
+}
+
+struct ActivityState {
+    last_active: Instant,
+    dormant: bool,
+    /// The app is in the background
+    suspended: bool,
+}
+
+impl Activity {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ActivityState {
+                last_active: Instant::now(),
+                dormant: false,
+                suspended: false,
+            })),
+            connecting: Arc::default(),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, ActivityState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Note a request; returns whether the client is dormant
+    pub(crate) fn touch(&self) -> bool {
+        let mut state = self.state();
+        state.last_active = Instant::now();
+        state.dormant
+    }
+
+    pub(crate) fn is_dormant(&self) -> bool {
+        self.state().dormant
+    }
+
+    /// Time left until the client has been idle for `idle`, zero once it has
+    pub(crate) fn remaining(&self, idle: Duration) -> Duration {
+        idle.saturating_sub(self.state().last_active.elapsed())
+    }
+
+    /// Go dormant if the client has been idle for `idle`, returning how long
+    /// it has been; `None` if it is already dormant or was used meanwhile
+    pub(crate) fn fall_asleep(&self, idle: Duration) -> Option<Duration> {
+        let mut state = self.state();
+        let idle_for = state.last_active.elapsed();
+        if state.dormant || idle_for < idle {
+            return None;
+        }
+        state.dormant = true;
+        Some(idle_for)
+    }
+
+    /// Leave dormant mode; returns whether the client was dormant
+    pub(crate) fn wake(&self) -> bool {
+        std::mem::take(&mut self.state().dormant)
+    }
+
+    /// Enter or leave the background; returns whether that is a change
+    pub(crate) fn set_suspended(&self, suspended: bool) -> bool {
+        let mut state = self.state();
+        std::mem::replace(&mut state.suspended, suspended) != suspended
+    }
+
+    pub(crate) fn is_suspended(&self) -> bool {
+        self.state().suspended
+    }
+
+    pub(crate) async fn connecting(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.connecting.lock().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dormancy() {
+        let activity = Activity::new();
+        let idle = Duration::from_secs(600);
+        assert!(activity.remaining(idle) > Duration::ZERO);
+        assert!(activity.fall_asleep(idle).is_none());
+        assert!(!activity.touch());
+
+        assert_eq!(activity.remaining(Duration::ZERO), Duration::ZERO);
+        assert!(activity.fall_asleep(Duration::ZERO).is_some());
+        assert!(activity.fall_asleep(Duration::ZERO).is_none());
+        // A request sees the dormant client until it is connected again
+        assert!(activity.touch());
+        assert!(activity.is_dormant());
+        assert!(activity.wake());
+        assert!(!activity.wake());
+        assert!(!activity.touch());
+
+        assert!(activity.set_suspended(true));
+        assert!(!activity.set_suspended(true));
+        assert!(activity.is_suspended());
+        assert!(activity.set_suspended(false));
+    }
+}
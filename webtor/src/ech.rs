@@ -0,0 +1,387 @@
+//! Encrypted Client Hello for TLS through exits
+//!
+//! The exit relaying an HTTPS connection reads the server name in its
+//! ClientHello, which tells it where the user is going even though the
+//! rest is encrypted. With Encrypted Client Hello (RFC 9849) the visible
+//! ClientHello names only a provider-wide public name, and the real one is
+//! sealed to the server's key. That key comes from the server's ECH
+//! configuration, published in the `ech` parameter of its DNS HTTPS record;
+//! [`EchConfigs`] holds those configurations by host.
+//!
+//! A host with a configuration is only ever contacted with ECH, over TLS
+//! 1.3. If its server can't decrypt the ClientHello, it proves it holds the
+//! public name's certificate and may send new configurations; the
+//! connection fails with [`TorError::EchRejected`] and later connections
+//! use the new ones. Nothing falls back to sending the name in the clear.
+
+use crate::error::{Result, TorError};
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// ECH configurations (ECHConfigLists) by host
+///
+/// Cloning shares the configurations, so new ones a server sends on
+/// rejecting ECH reach every connection; the `with_*` builders copy.
+#[derive(Clone, Default)]
+pub struct EchConfigs {
+    configs: Arc<RwLock<BTreeMap<String, Vec<u8>>>>,
+}
+
+impl EchConfigs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the ECHConfigList `list` for `host`
+    pub fn with_config(self, host: &str, list: impl Into<Vec<u8>>) -> Result<Self> {
+        let list = list.into();
+        check_list(&list)?;
+        let mut configs = self.snapshot();
+        configs.insert(host.to_ascii_lowercase(), list);
+        Ok(Self {
+            configs: Arc::new(RwLock::new(configs)),
+        })
+    }
+
+    /// Use a base64 ECHConfigList for `host`, as in the `ech` parameter of
+    /// its HTTPS record
+    pub fn with_base64(self, host: &str, list: &str) -> Result<Self> {
+        let list = base64::engine::general_purpose::STANDARD
+            .decode(list.trim())
+            .map_err(|e| TorError::configuration(format!("Invalid ECH config list: {}", e)))?;
+        self.with_config(host, list)
+    }
+
+    /// The ECHConfigList for `host`, if it has one
+    pub fn get(&self, host: &str) -> Option<Vec<u8>> {
+        self.configs
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&host.to_ascii_lowercase())
+            .cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.configs
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+
+    /// The error for `host` rejecting ECH, keeping the `retry_configs` it
+    /// sent (if usable) for later connections
+    pub(crate) fn rejected(&self, host: &str, retry_configs: Option<Vec<u8>>) -> TorError {
+        let retry = match retry_configs {
+            Some(list) if check_list(&list).is_ok() => {
+                self.configs
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(host.to_ascii_lowercase(), list);
+                true
+            }
+            _ => false,
+        };
+        TorError::EchRejected {
+            host: host.to_string(),
+            retry,
+        }
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, Vec<u8>> {
+        self.configs
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl std::fmt::Debug for EchConfigs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.snapshot().keys()).finish()
+    }
+}
+
+impl PartialEq for EchConfigs {
+    fn eq(&self, other: &Self) -> bool {
+        self.snapshot() == other.snapshot()
+    }
+}
+
+impl Eq for EchConfigs {}
+
+/// Serialized as host → base64 ECHConfigList, the HTTPS record's form
+impl Serialize for EchConfigs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let encoded: BTreeMap<String, String> = self
+            .snapshot()
+            .into_iter()
+            .map(|(host, list)| {
+                let list = base64::engine::general_purpose::STANDARD.encode(list);
+                (host, list)
+            })
+            .collect();
+        encoded.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EchConfigs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let encoded = BTreeMap::<String, String>::deserialize(deserializer)?;
+        encoded
+            .iter()
+            .try_fold(Self::new(), |configs, (host, list)| {
+                configs.with_base64(host, list)
+            })
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Fail unless `list` holds a configuration the TLS stack can use
+fn check_list(list: &[u8]) -> Result<()> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use futures_rustls::rustls::client::EchConfig;
+        EchConfig::new(list.into(), hpke::SUITES)
+            .map(drop)
+            .map_err(|e| TorError::configuration(format!("Unusable ECH config list: {}", e)))
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        subtle_tls::EchConfig::select(list)
+            .map(drop)
+            .map_err(|e| TorError::configuration(format!("Unusable ECH config list: {}", e)))
+    }
+}
+
+/// rustls's ring provider has no HPKE, so ECH gets subtle-tls's, the one
+/// the wasm build seals with
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod hpke {
+    use futures_rustls::rustls::crypto::hpke::{
+        EncapsulatedSecret, Hpke, HpkeOpener, HpkePrivateKey, HpkePublicKey, HpkeSealer, HpkeSuite,
+    };
+    use futures_rustls::rustls::internal::msgs::enums::{HpkeAead, HpkeKdf, HpkeKem};
+    use futures_rustls::rustls::internal::msgs::handshake::HpkeSymmetricCipherSuite;
+    use futures_rustls::rustls::Error;
+    use subtle_tls::hpke::{Aead, Context, Suite};
+
+    static X25519_AES_128_GCM: X25519Hpke = X25519Hpke {
+        suite: Suite {
+            aead: Aead::Aes128Gcm,
+        },
+    };
+    static X25519_CHACHA20_POLY1305: X25519Hpke = X25519Hpke {
+        suite: Suite {
+            aead: Aead::ChaCha20Poly1305,
+        },
+    };
+
+    /// The suites ECH configurations may use
+    pub(crate) static SUITES: &[&dyn Hpke] = &[&X25519_AES_128_GCM, &X25519_CHACHA20_POLY1305];
+
+    /// A [`Suite`] as rustls sees it
+    #[derive(Debug)]
+    pub(crate) struct X25519Hpke {
+        suite: Suite,
+    }
+
+    impl Hpke for X25519Hpke {
+        fn seal(
+            &self,
+            info: &[u8],
+            aad: &[u8],
+            plaintext: &[u8],
+            pub_key: &HpkePublicKey,
+        ) -> Result<(EncapsulatedSecret, Vec<u8>), Error> {
+            let (enc, mut sealer) = self.setup_sealer(info, pub_key)?;
+            Ok((enc, sealer.seal(aad, plaintext)?))
+        }
+
+        fn setup_sealer(
+            &self,
+            info: &[u8],
+            pub_key: &HpkePublicKey,
+        ) -> Result<(EncapsulatedSecret, Box<dyn HpkeSealer + 'static>), Error> {
+            let (enc, context) = self.suite.setup_sender(&pub_key.0, info).map_err(general)?;
+            Ok((EncapsulatedSecret(enc), Box::new(RustlsContext(context))))
+        }
+
+        fn open(
+            &self,
+            enc: &EncapsulatedSecret,
+            info: &[u8],
+            aad: &[u8],
+            ciphertext: &[u8],
+            secret_key: &HpkePrivateKey,
+        ) -> Result<Vec<u8>, Error> {
+            self.setup_opener(enc, info, secret_key)?
+                .open(aad, ciphertext)
+        }
+
+        fn setup_opener(
+            &self,
+            enc: &EncapsulatedSecret,
+            info: &[u8],
+            secret_key: &HpkePrivateKey,
+        ) -> Result<Box<dyn HpkeOpener + 'static>, Error> {
+            let context = self
+                .suite
+                .setup_receiver(&enc.0, secret_key.secret_bytes(), info)
+                .map_err(general)?;
+            Ok(Box::new(RustlsContext(context)))
+        }
+
+        fn generate_key_pair(&self) -> Result<(HpkePublicKey, HpkePrivateKey), Error> {
+            let (sk, pk) = Suite::generate_key_pair().map_err(general)?;
+            Ok((
+                HpkePublicKey(pk.to_vec()),
+                HpkePrivateKey::from(sk.to_vec()),
+            ))
+        }
+
+        fn suite(&self) -> HpkeSuite {
+            HpkeSuite {
+                kem: HpkeKem::DHKEM_X25519_HKDF_SHA256,
+                sym: HpkeSymmetricCipherSuite {
+                    kdf_id: HpkeKdf::HKDF_SHA256,
+                    aead_id: HpkeAead::from(self.suite.aead.id()),
+                },
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct RustlsContext(Context);
+
+    impl HpkeSealer for RustlsContext {
+        fn seal(&mut self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+            self.0.seal(aad, plaintext).map_err(|_| Error::EncryptError)
+        }
+    }
+
+    impl HpkeOpener for RustlsContext {
+        fn open(&mut self, aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+            self.0
+                .open(aad, ciphertext)
+                .map_err(|_| Error::DecryptError)
+        }
+    }
+
+    fn general(e: subtle_tls::TlsError) -> Error {
+        Error::General(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ECHConfigList with one X25519 / HKDF-SHA256 / AES-128-GCM config
+    /// for the public name `public.example.net`
+    fn config_list() -> Vec<u8> {
+        let mut contents = vec![7, 0x00, 0x20, 0, 32];
+        contents.extend_from_slice(&[0x42; 32]);
+        contents.extend_from_slice(&[0, 4, 0, 1, 0, 1, 32, 18]);
+        contents.extend_from_slice(b"public.example.net");
+        contents.extend_from_slice(&[0, 0]);
+        let mut config = vec![0xfe, 0x0d];
+        config.extend_from_slice(&(contents.len() as u16).to_be_bytes());
+        config.extend_from_slice(&contents);
+        let mut list = (config.len() as u16).to_be_bytes().to_vec();
+        list.extend_from_slice(&config);
+        list
+    }
+
+    #[test]
+    fn test_ech_configs() {
+        let list = config_list();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&list);
+        let configs = EchConfigs::new()
+            .with_base64("Secret.Example.com", &encoded)
+            .unwrap();
+        assert_eq!(configs.get("secret.example.com"), Some(list.clone()));
+        assert!(configs.get("other.example.com").is_none());
+        assert!(EchConfigs::new()
+            .with_config("a.example", vec![0, 1, 2])
+            .is_err());
+        assert!(EchConfigs::new()
+            .with_base64("a.example", "not base64!")
+            .is_err());
+
+        // Options round-trip through their JSON form
+        let json = serde_json::to_string(&configs).unwrap();
+        assert!(json.contains(&encoded));
+        assert_eq!(serde_json::from_str::<EchConfigs>(&json).unwrap(), configs);
+
+        // Retry configs are kept for every clone; junk ones are not
+        let shared = configs.clone();
+        let err = configs.rejected("other.example.com", Some(list.clone()));
+        assert!(matches!(&err, TorError::EchRejected { retry: true, .. }));
+        assert_eq!(err.code(), "ECH_REJECTED");
+        assert!(shared.get("other.example.com").is_some());
+        let err = configs.rejected("third.example.com", Some(vec![1]));
+        assert!(!err.is_retryable());
+        assert!(shared.get("third.example.com").is_none());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_hpke_round_trip() {
+        use futures_rustls::rustls::crypto::hpke::EncapsulatedSecret;
+
+        for suite in hpke::SUITES {
+            let (public, private) = suite.generate_key_pair().unwrap();
+            let (enc, mut sealer) = suite.setup_sealer(b"info", &public).unwrap();
+            let first = sealer.seal(b"aad", b"hello").unwrap();
+            let second = sealer.seal(b"aad", b"hello").unwrap();
+            assert_ne!(first, second);
+
+            let mut opener = suite.setup_opener(&enc, b"info", &private).unwrap();
+            assert_eq!(opener.open(b"aad", &first).unwrap(), b"hello");
+            assert_eq!(opener.open(b"aad", &second).unwrap(), b"hello");
+            assert!(suite
+                .open(&enc, b"info", b"other aad", &first, &private)
+                .is_err());
+            let wrong = EncapsulatedSecret(vec![9; 32]);
+            assert!(suite
+                .open(&wrong, b"info", b"aad", &first, &private)
+                .is_err());
+        }
+    }
+
+    /// The first message of RFC 9180 A.1.1 opens through the rustls adapter
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_hpke_opens_rfc9180_vector() {
+        use futures_rustls::rustls::crypto::hpke::{EncapsulatedSecret, HpkePrivateKey};
+
+        let hex = |s: &str| -> Vec<u8> {
+            (0..s.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+                .collect()
+        };
+        let enc = EncapsulatedSecret(hex(
+            "37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431",
+        ));
+        let sk_rm = HpkePrivateKey::from(hex(
+            "4612c550263fc8ad58375df3f557aac531d26850903e55a9f23f21d8534e8ac8",
+        ));
+        let ct = hex(
+            "f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218a355a9\
+             6d8770ac83d07bea87e13c512a",
+        );
+        let plaintext = hpke::SUITES[0]
+            .open(
+                &enc,
+                &hex("4f6465206f6e2061204772656369616e2055726e"),
+                &hex("436f756e742d30"),
+                &ct,
+                &sk_rm,
+            )
+            .unwrap();
+        assert_eq!(plaintext, b"Beauty is truth, truth beauty");
+    }
+}
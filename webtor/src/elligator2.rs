@@ -0,0 +1,493 @@
+//! Elligator2 encoding of Curve25519 public keys
+//!
+//! obfs4 never puts a raw Curve25519 public key on the wire: a Montgomery
+//! u-coordinate is easy to tell apart from random bytes, since only about
+//! half of all field elements are valid. Instead each ephemeral key is sent
+//! as its Elligator2 "representative", a field element that maps to the key
+//! and is indistinguishable from a uniformly random string.
+//!
+//! Only about half of all keys have a representative, so key generation
+//! retries until it finds one. The map follows obfs4proxy's
+//! `ed25519/extra25519` package, with two fixes from later implementations:
+//! - Generated keys add a random low-order point (a "dirty" key). Keys built
+//!   as plain multiples of the base point only cover a subgroup, and their
+//!   representatives can be told apart from random strings.
+//! - Only bit 255 of a representative is random. Bit 254 is left clear
+//!   because older obfs4 servers mask only bit 255 when decoding.
+//!
+//! curve25519-dalek keeps its field arithmetic private, so this module has a
+//! small GF(2^255 - 19) implementation of its own. It is used only for the
+//! map itself. All scalar multiplication goes through curve25519-dalek.
+
+use curve25519_dalek::constants::EIGHT_TORSION;
+use curve25519_dalek::{EdwardsPoint, MontgomeryPoint};
+
+/// Length of a public key or representative
+pub const KEY_LENGTH: usize = 32;
+
+/// Mask for one 51-bit limb
+const LOW_51_BITS: u64 = (1 << 51) - 1;
+
+/// Montgomery curve coefficient A of Curve25519
+const CURVE_A: u64 = 486662;
+
+/// p - 2, the exponent for inversion (little-endian)
+const P_MINUS_2: [u8; 32] = exponent(0xeb, 0x7f);
+
+/// (p - 1) / 2, the exponent of the Legendre symbol
+const P_MINUS_1_HALF: [u8; 32] = exponent(0xf6, 0x3f);
+
+/// (p + 3) / 8, the exponent of the square root candidate
+const P_PLUS_3_EIGHTH: [u8; 32] = exponent(0xfe, 0x0f);
+
+/// (p - 1) / 4, which raises 2 to a square root of -1
+const P_MINUS_1_QUARTER: [u8; 32] = exponent(0xfb, 0x1f);
+
+/// The exponents used here are `0xff` apart from their first and last bytes
+const fn exponent(first: u8, last: u8) -> [u8; 32] {
+    let mut bytes = [0xff; 32];
+    bytes[0] = first;
+    bytes[31] = last;
+    bytes
+}
+
+/// An element of GF(2^255 - 19) as five 51-bit limbs
+#[derive(Clone, Copy, Debug)]
+struct FieldElement([u64; 5]);
+
+impl FieldElement {
+    const ZERO: Self = Self([0; 5]);
+    const ONE: Self = Self([1, 0, 0, 0, 0]);
+
+    fn from_u64(value: u64) -> Self {
+        Self([value & LOW_51_BITS, value >> 51, 0, 0, 0])
+    }
+
+    /// Decode little-endian bytes, ignoring bit 255
+    fn from_bytes(bytes: &[u8; 32]) -> Self {
+        let load = |i: usize| {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[i..i + 8]);
+            u64::from_le_bytes(word)
+        };
+        Self([
+            load(0) & LOW_51_BITS,
+            (load(6) >> 3) & LOW_51_BITS,
+            (load(12) >> 6) & LOW_51_BITS,
+            (load(19) >> 1) & LOW_51_BITS,
+            (load(24) >> 12) & LOW_51_BITS,
+        ])
+    }
+
+    /// Canonical little-endian encoding
+    fn to_bytes(self) -> [u8; 32] {
+        let mut limbs = Self::carry(self.0);
+
+        // Subtract p if the value is at least p: adding 19 carries out of
+        // bit 255 exactly when it is
+        let mut q = (limbs[0] + 19) >> 51;
+        for limb in &limbs[1..] {
+            q = (limb + q) >> 51;
+        }
+        limbs[0] += 19 * q;
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= LOW_51_BITS;
+        }
+        limbs[4] &= LOW_51_BITS;
+
+        let mut out = [0u8; 32];
+        let mut acc: u128 = 0;
+        let mut bits = 0;
+        let mut index = 0;
+        for limb in limbs {
+            acc |= (limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 {
+                out[index] = acc as u8;
+                acc >>= 8;
+                bits -= 8;
+                index += 1;
+            }
+        }
+        out[index] = acc as u8;
+        out
+    }
+
+    /// Propagate carries so every limb fits in 52 bits
+    fn carry(mut limbs: [u64; 5]) -> [u64; 5] {
+        let carries = limbs.map(|limb| limb >> 51);
+        for limb in &mut limbs {
+            *limb &= LOW_51_BITS;
+        }
+        limbs[0] += carries[4] * 19;
+        for i in 0..4 {
+            limbs[i + 1] += carries[i];
+        }
+        limbs
+    }
+
+    fn add(self, rhs: Self) -> Self {
+        let mut limbs = self.0;
+        for (limb, r) in limbs.iter_mut().zip(rhs.0) {
+            *limb += r;
+        }
+        Self(Self::carry(limbs))
+    }
+
+    fn sub(self, rhs: Self) -> Self {
+        // Add 16p first so no limb underflows
+        const SIXTEEN_P: [u64; 5] = [
+            36028797018963664,
+            36028797018963952,
+            36028797018963952,
+            36028797018963952,
+            36028797018963952,
+        ];
+        let mut limbs = self.0;
+        for i in 0..5 {
+            limbs[i] = limbs[i] + SIXTEEN_P[i] - rhs.0[i];
+        }
+        Self(Self::carry(limbs))
+    }
+
+    fn neg(self) -> Self {
+        Self::ZERO.sub(self)
+    }
+
+    fn mul(self, rhs: Self) -> Self {
+        let m = |x: u64, y: u64| (x as u128) * (y as u128);
+        let a = self.0;
+        let b = rhs.0;
+        let b1 = b[1] * 19;
+        let b2 = b[2] * 19;
+        let b3 = b[3] * 19;
+        let b4 = b[4] * 19;
+
+        let c0 = m(a[0], b[0]) + m(a[4], b1) + m(a[3], b2) + m(a[2], b3) + m(a[1], b4);
+        let mut c1 = m(a[1], b[0]) + m(a[0], b[1]) + m(a[4], b2) + m(a[3], b3) + m(a[2], b4);
+        let mut c2 = m(a[2], b[0]) + m(a[1], b[1]) + m(a[0], b[2]) + m(a[4], b3) + m(a[3], b4);
+        let mut c3 = m(a[3], b[0]) + m(a[2], b[1]) + m(a[1], b[2]) + m(a[0], b[3]) + m(a[4], b4);
+        let mut c4 = m(a[4], b[0]) + m(a[3], b[1]) + m(a[2], b[2]) + m(a[1], b[3]) + m(a[0], b[4]);
+
+        let mut out = [0u64; 5];
+        c1 += c0 >> 51;
+        out[0] = (c0 as u64) & LOW_51_BITS;
+        c2 += c1 >> 51;
+        out[1] = (c1 as u64) & LOW_51_BITS;
+        c3 += c2 >> 51;
+        out[2] = (c2 as u64) & LOW_51_BITS;
+        c4 += c3 >> 51;
+        out[3] = (c3 as u64) & LOW_51_BITS;
+        out[4] = (c4 as u64) & LOW_51_BITS;
+
+        let low = out[0] as u128 + (c4 >> 51) * 19;
+        out[0] = (low as u64) & LOW_51_BITS;
+        out[1] += (low >> 51) as u64;
+        Self(out)
+    }
+
+    fn square(self) -> Self {
+        self.mul(self)
+    }
+
+    /// Raise to a public exponent given in little-endian bytes
+    fn pow(self, exponent: &[u8; 32]) -> Self {
+        let mut result = Self::ONE;
+        for bit in (0..256).rev() {
+            result = result.square();
+            if (exponent[bit / 8] >> (bit % 8)) & 1 == 1 {
+                result = result.mul(self);
+            }
+        }
+        result
+    }
+
+    fn invert(self) -> Self {
+        self.pow(&P_MINUS_2)
+    }
+
+    fn ct_eq(self, other: Self) -> bool {
+        let a = self.to_bytes();
+        let b = other.to_bytes();
+        a.iter()
+            .zip(b.iter())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+    }
+
+    fn is_zero(self) -> bool {
+        self.ct_eq(Self::ZERO)
+    }
+
+    /// Whether the value is a square (zero counts as one)
+    fn is_square(self) -> bool {
+        !self.pow(&P_MINUS_1_HALF).ct_eq(Self::ONE.neg())
+    }
+
+    /// A square root, if the value has one
+    fn sqrt(self) -> Option<Self> {
+        let candidate = self.pow(&P_PLUS_3_EIGHTH);
+        if candidate.square().ct_eq(self) {
+            Some(candidate)
+        } else if candidate.square().ct_eq(self.neg()) {
+            let sqrt_m1 = Self::from_u64(2).pow(&P_MINUS_1_QUARTER);
+            Some(candidate.mul(sqrt_m1))
+        } else {
+            None
+        }
+    }
+
+    /// Whether the canonical value is greater than (p - 1) / 2
+    fn is_negative(self) -> bool {
+        let bytes = self.to_bytes();
+        let half = P_MINUS_1_HALF;
+        for i in (0..32).rev() {
+            if bytes[i] != half[i] {
+                return bytes[i] > half[i];
+            }
+        }
+        false
+    }
+}
+
+/// Map a representative to the public key (Montgomery u-coordinate) it encodes
+///
+/// The two high bits are ignored: peers may set either of them at random.
+pub fn representative_to_public_key(representative: &[u8; KEY_LENGTH]) -> [u8; KEY_LENGTH] {
+    let mut bytes = *representative;
+    bytes[31] &= 0x3f;
+    let r = FieldElement::from_bytes(&bytes);
+    let a = FieldElement::from_u64(CURVE_A);
+
+    // v = -A / (1 + 2r^2)
+    let r_squared = r.square();
+    let denominator = FieldElement::ONE.add(r_squared.add(r_squared));
+    let v = a.mul(denominator.invert()).neg();
+
+    // u = v if v^3 + Av^2 + v is square, otherwise -v - A
+    let v_squared = v.square();
+    let curve = v_squared.mul(v).add(a.mul(v_squared)).add(v);
+    let u = if curve.is_square() { v } else { v.neg().sub(a) };
+    u.to_bytes()
+}
+
+/// The representative of `public_key`, if it has one
+///
+/// Of the two representatives of a key, this returns the one no greater
+/// than (p - 1) / 2, so both high bits are clear.
+pub fn public_key_to_representative(public_key: &[u8; KEY_LENGTH]) -> Option<[u8; KEY_LENGTH]> {
+    let u = FieldElement::from_bytes(public_key);
+    let u_plus_a = u.add(FieldElement::from_u64(CURVE_A));
+    if u.is_zero() || u_plus_a.is_zero() {
+        return None;
+    }
+
+    // r = sqrt(-(u + A) / (2u))
+    let r = u_plus_a.neg().mul(u.add(u).invert()).sqrt()?;
+    let r = if r.is_negative() { r.neg() } else { r };
+    Some(r.to_bytes())
+}
+
+/// A Curve25519 keypair whose public key has an Elligator2 representative
+#[derive(Clone)]
+pub struct Keypair {
+    secret: [u8; 32],
+    public: [u8; KEY_LENGTH],
+    representative: [u8; KEY_LENGTH],
+}
+
+impl Keypair {
+    /// Generate a keypair, retrying until the public key is representable
+    pub fn generate() -> Self {
+        loop {
+            if let Some(keypair) = Self::from_secret(rand::random(), rand::random()) {
+                return keypair;
+            }
+        }
+    }
+
+    /// The keypair for `secret`, if its public key is representable
+    ///
+    /// The low three bits of `tweak` pick the low-order point added to the
+    /// public key, and its top bit becomes bit 255 of the representative.
+    pub fn from_secret(secret: [u8; 32], tweak: u8) -> Option<Self> {
+        // Adding a low-order point spreads public keys over the whole
+        // curve. The clamped secret is a multiple of 8, so peers' DH
+        // results are unaffected.
+        let point = EdwardsPoint::mul_base_clamped(secret) + EIGHT_TORSION[(tweak & 7) as usize];
+        let public = point.to_montgomery().to_bytes();
+
+        let mut representative = public_key_to_representative(&public)?;
+        representative[31] |= tweak & 0x80;
+        Some(Self {
+            secret,
+            public,
+            representative,
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8; KEY_LENGTH] {
+        &self.public
+    }
+
+    pub fn representative(&self) -> &[u8; KEY_LENGTH] {
+        &self.representative
+    }
+
+    /// X25519 of our secret with `peer_public`
+    pub fn diffie_hellman(&self, peer_public: &[u8; KEY_LENGTH]) -> [u8; 32] {
+        MontgomeryPoint(*peer_public)
+            .mul_clamped(self.secret)
+            .to_bytes()
+    }
+}
+
+impl std::fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keypair")
+            .field("public", &hex::encode(self.public))
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha512};
+
+    #[test]
+    fn test_field_arithmetic() {
+        let a = FieldElement::from_u64(CURVE_A);
+        assert!(a.mul(a.invert()).ct_eq(FieldElement::ONE));
+        assert!(a.sub(a).is_zero());
+        assert!(a.add(a.neg()).is_zero());
+
+        // p - 1 round trips canonically, p itself reduces to zero
+        let mut p_minus_1 = exponent(0xec, 0x7f);
+        assert_eq!(FieldElement::from_bytes(&p_minus_1).to_bytes(), p_minus_1);
+        p_minus_1[0] = 0xed;
+        assert!(FieldElement::from_bytes(&p_minus_1).is_zero());
+
+        let root = FieldElement::from_u64(9).sqrt().unwrap();
+        assert!(root.square().ct_eq(FieldElement::from_u64(9)));
+        // 2 is not a square mod p
+        assert!(FieldElement::from_u64(2).sqrt().is_none());
+        assert!(!FieldElement::from_u64(2).is_square());
+    }
+
+    fn hex32(hex_str: &str) -> [u8; 32] {
+        hex::decode(hex_str).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn test_map_matches_dalek() {
+        // curve25519-dalek's `montgomery_elligator_correct` vector
+        let representative: [u8; 32] = std::array::from_fn(|i| i as u8);
+        assert_eq!(
+            representative_to_public_key(&representative),
+            hex32("5f3520001c6c9936a31206afe7c7ac224e8861619bf98872444915899d95f46e")
+        );
+
+        // Signal's vectors, as in dalek's `elligator_signal_test_vectors`:
+        // the first half of SHA-512(input) is a representative whose bit
+        // 255 is the sign of the Edwards point, which is then multiplied
+        // by the cofactor. The three whose hash sets bit 254 are left out,
+        // since obfs4 ignores that bit.
+        const SIGNAL: [(&str, &str); 7] = [
+            (
+                "214f306e1576f5a7577636fe303ca2c625b533319f52442b22a9fa3b7ede809f",
+                "c95becf0f93595174633b9d4d6bbbeb88e16fa257176f877ce426e1424626052",
+            ),
+            (
+                "2eb10d432702ea7f79207da95d206f82d5a3b374f5f89f17a199531f78d3bea6",
+                "d8f8b508edffbb8b6dab0f602f86a9dd759f800fe18f782fdcac47c234883e7f",
+            ),
+            (
+                "c85165952490dc1839cb69012a3d9f2cc4b02343613263ab93a26dc89fd58267",
+                "43cbe8685fd3c90665b91835debb89ff1477f906f5170f38a192f6a199556537",
+            ),
+            (
+                "26e7fc4a78d863b1a4ccb2ce0951fbcd021e106350730ee4157bacb4502e1b76",
+                "b6fc3d738c2c40719479b2f23818180cdafa72a14254d4016bbed8f0b788a835",
+            ),
+            (
+                "1618c08ef0233f94f0f163f9435ec7457cd7a8cd4bb6b160315d15818c30f7a2",
+                "da0b703593b29dbcd28ebd6e7baea17b6f61971f3641cae774f6a5137a12294c",
+            ),
+            (
+                "a744d582b3a34d14d311b7629da06d003045ae77cebceeb4e0e72734d63bd07d",
+                "fad25a5ea15d4541258af8785acaf697a886c1b872c793790e60a6837b1adbc0",
+            ),
+            (
+                "f06fc939bc10551a0fd415aebf107ef0b9c4ee1ef9a164157bdd089127782617",
+                "785b2a6a00a5579cc9da1ff997ce8339b6f9fb46c6f10cf7a12ff2986341a6e0",
+            ),
+        ];
+        for (input, output) in SIGNAL {
+            let hash = Sha512::digest(hex::decode(input).unwrap());
+            let representative: [u8; 32] = hash[..32].try_into().unwrap();
+            assert_eq!(representative[31] & 0x40, 0);
+            let public = MontgomeryPoint(representative_to_public_key(&representative));
+            let point = public.to_edwards(representative[31] >> 7).unwrap();
+            assert_eq!(
+                hex::encode(point.mul_by_cofactor().compress().as_bytes()),
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn test_representative_round_trip() {
+        for _ in 0..32 {
+            let keypair = Keypair::generate();
+            assert_eq!(
+                representative_to_public_key(keypair.representative()),
+                *keypair.public_key()
+            );
+            // Bit 254 stays clear for servers that only mask bit 255
+            assert_eq!(keypair.representative()[31] & 0x40, 0);
+        }
+    }
+
+    #[test]
+    fn test_random_strings_map_to_curve_points() {
+        for _ in 0..32 {
+            let mut representative: [u8; 32] = rand::random();
+            let public = representative_to_public_key(&representative);
+
+            // Both high bits are ignored
+            representative[31] ^= 0xc0;
+            assert_eq!(representative_to_public_key(&representative), public);
+
+            let u = FieldElement::from_bytes(&public);
+            let a = FieldElement::from_u64(CURVE_A);
+            let curve = u.square().mul(u).add(a.mul(u.square())).add(u);
+            assert!(curve.is_square());
+        }
+    }
+
+    #[test]
+    fn test_diffie_hellman_agrees() {
+        let alice = Keypair::generate();
+        let bob = Keypair::generate();
+        assert_eq!(
+            alice.diffie_hellman(bob.public_key()),
+            bob.diffie_hellman(alice.public_key())
+        );
+    }
+
+    #[test]
+    fn test_non_representable_keys() {
+        assert!(public_key_to_representative(&[0u8; 32]).is_none());
+        // u = -A would need a zero representative denominator
+        let minus_a = FieldElement::from_u64(CURVE_A).neg().to_bytes();
+        assert!(public_key_to_representative(&minus_a).is_none());
+
+        // Roughly half of all keys have no representative
+        let unrepresentable = (0..64)
+            .filter(|_| public_key_to_representative(&rand::random()).is_none())
+            .count();
+        assert!((8..56).contains(&unrepresentable));
+    }
+}
@@ -1,5 +1,6 @@
 //! Error types for the webtor library
 
+use crate::bootstrap::BootstrapReport;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, TorError>;
@@ -44,6 +45,137 @@ impl TorErrorKind {
     }
 }
 
+/// Why the exit closed a stream, from its RELAY_END cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamEndReason {
+    Misc,
+    /// The exit couldn't resolve the hostname
+    ResolveFailed,
+    /// The destination refused the connection
+    ConnectRefused,
+    /// The exit's policy doesn't allow the destination
+    ExitPolicy,
+    /// The circuit was destroyed
+    Destroy,
+    /// The connection to the destination timed out
+    Timeout,
+    /// No route to the destination
+    NoRoute,
+    /// The exit is hibernating
+    Hibernating,
+    /// Internal error at the exit
+    Internal,
+    /// The exit ran out of resources
+    ResourceLimit,
+    /// The destination reset the connection
+    ConnReset,
+    /// The exit saw a Tor protocol violation
+    TorProtocol,
+    /// A reason code this client doesn't know
+    Other(u8),
+}
+
+impl StreamEndReason {
+    /// The reason carried by a tor-proto error, if it's a received END
+    pub fn from_proto(err: &tor_proto::Error) -> Option<Self> {
+        use tor_cell::relaycell::msg::EndReason;
+
+        let tor_proto::Error::EndReceived(reason) = err else {
+            return None;
+        };
+        Some(match *reason {
+            EndReason::MISC => Self::Misc,
+            EndReason::RESOLVEFAILED => Self::ResolveFailed,
+            EndReason::CONNECTREFUSED => Self::ConnectRefused,
+            EndReason::EXITPOLICY => Self::ExitPolicy,
+            EndReason::DESTROY => Self::Destroy,
+            EndReason::TIMEOUT => Self::Timeout,
+            EndReason::NOROUTE => Self::NoRoute,
+            EndReason::HIBERNATING => Self::Hibernating,
+            EndReason::INTERNAL => Self::Internal,
+            EndReason::RESOURCELIMIT => Self::ResourceLimit,
+            EndReason::CONNRESET => Self::ConnReset,
+            EndReason::TORPROTOCOL => Self::TorProtocol,
+            other => Self::Other(other.into()),
+        })
+    }
+
+    /// The reason behind an I/O error from a Tor stream, if the exit ended it
+    pub fn from_io_error(err: &std::io::Error) -> Option<Self> {
+        err.get_ref()?
+            .downcast_ref::<tor_proto::Error>()
+            .and_then(Self::from_proto)
+    }
+
+    /// The reason's name in tor-spec (`EXITPOLICY`, `CONNECTREFUSED`, ...)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Misc => "MISC",
+            Self::ResolveFailed => "RESOLVEFAILED",
+            Self::ConnectRefused => "CONNECTREFUSED",
+            Self::ExitPolicy => "EXITPOLICY",
+            Self::Destroy => "DESTROY",
+            Self::Timeout => "TIMEOUT",
+            Self::NoRoute => "NOROUTE",
+            Self::Hibernating => "HIBERNATING",
+            Self::Internal => "INTERNAL",
+            Self::ResourceLimit => "RESOURCELIMIT",
+            Self::ConnReset => "CONNRESET",
+            Self::TorProtocol => "TORPROTOCOL",
+            Self::Other(_) => "OTHER",
+        }
+    }
+
+    /// Whether the destination itself failed (unresolvable, refusing, down)
+    /// rather than the exit or circuit; another exit won't do better
+    pub fn is_destination_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::ResolveFailed
+                | Self::ConnectRefused
+                | Self::Timeout
+                | Self::NoRoute
+                | Self::ConnReset
+        )
+    }
+}
+
+/// Which limit an HTTP request ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Getting a circuit, opening the stream and the TLS handshake
+    Connect,
+    /// From the request being sent until the first response byte
+    FirstByte,
+    /// The whole request, redirects and circuit retries included
+    Total,
+}
+
+impl TimeoutPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::FirstByte => "firstByte",
+            Self::Total => "total",
+        }
+    }
+}
+
+impl std::fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::fmt::Display for StreamEndReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Other(code) => write!(f, "reason {}", code),
+            reason => f.write_str(reason.as_str()),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum TorError {
     #[error("WebSocket connection failed: {0}")]
@@ -61,6 +193,10 @@ pub enum TorError {
     #[error("Circuit extension failed: {0}")]
     CircuitExtension(String),
 
+    /// The circuit carrying a stream was destroyed or lost its channel
+    #[error("Circuit closed: {0}")]
+    CircuitClosed(String),
+
     #[error("Relay selection failed: {0}")]
     RelaySelection(String),
 
@@ -76,6 +212,31 @@ pub enum TorError {
     #[error("Timeout: {0}")]
     Timeout(String),
 
+    /// An HTTP request exceeded one of its time limits
+    #[error("Request {phase} timeout after {}ms", limit.as_millis())]
+    RequestTimeout {
+        phase: TimeoutPhase,
+        limit: std::time::Duration,
+    },
+
+    /// A response outgrew the size cap and its stream was closed
+    #[error("Response exceeds the {limit} byte limit")]
+    ResponseTooLarge { limit: usize },
+
+    /// The certificate chain `host` presented contains none of its pins
+    #[error("Certificate chain for {host} does not match any pin")]
+    CertificatePinMismatch { host: String },
+
+    /// `host` could not decrypt the Encrypted Client Hello; `retry` is set
+    /// when it sent new configurations, which later connections use
+    #[error("{host} rejected Encrypted Client Hello")]
+    EchRejected { host: String, retry: bool },
+
+    /// The bridge or relay proved a different identity in the channel
+    /// handshake than the one configured
+    #[error("Peer identity mismatch: expected {expected}, got {actual}")]
+    IdentityMismatch { expected: String, actual: String },
+
     #[error("Configuration error: {0}")]
     Configuration(String),
 
@@ -91,8 +252,12 @@ pub enum TorError {
     #[error("Serialization error: {0}")]
     Serialization(String),
 
+    /// The exit ended the stream with a RELAY_END reason
+    #[error("Stream closed by exit: {0}")]
+    StreamEnded(StreamEndReason),
+
     #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
+    Io(std::io::Error),
 
     #[error("URL parse error: {0}")]
     UrlParse(#[from] url::ParseError),
@@ -103,11 +268,65 @@ pub enum TorError {
     #[error("Internal error: {0}")]
     Internal(String),
 
+    #[error("Invalid hostname: {0}")]
+    InvalidHostname(String),
+
+    /// A `.onion` host where only an exit can be used (a pinned circuit, an
+    /// HTTP client without an onion connector); exits don't connect to onion
+    /// services
+    #[error("Onion services are not supported here yet: {0}")]
+    OnionServicesUnsupported(String),
+
+    /// No HSDir served a usable onion service descriptor
+    #[error("Onion service descriptor unavailable: {0}")]
+    HsDescriptor(String),
+
+    /// No introduction point brought the onion service to a rendezvous
+    #[error("Could not reach onion service: {0}")]
+    OnionRendezvous(String),
+
+    /// A hosted onion service could not be brought up
+    #[error("Onion service failed: {0}")]
+    OnionService(String),
+
     #[error("NetDoc error: {0}")]
     NetDoc(#[from] tor_netdoc::Error),
 
     #[error("Operation cancelled")]
     Cancelled,
+
+    /// The overall bootstrap deadline expired; the report says how far it got
+    #[error("Bootstrap timed out: {0}")]
+    BootstrapTimeout(Box<BootstrapReport>),
+
+    /// An error raised while serving a specific request
+    #[error("{source} [request {request_id}]")]
+    Request {
+        request_id: String,
+        source: Box<TorError>,
+    },
+}
+
+/// Whether an I/O error means the connection went away underneath us
+pub(crate) fn is_connection_reset(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+impl From<std::io::Error> for TorError {
+    /// I/O errors from a Tor stream that the exit ended become [`TorError::StreamEnded`]
+    fn from(err: std::io::Error) -> Self {
+        match StreamEndReason::from_io_error(&err) {
+            Some(reason) => TorError::StreamEnded(reason),
+            None => TorError::Io(err),
+        }
+    }
 }
 
 impl TorError {
@@ -131,6 +350,10 @@ impl TorError {
         TorError::CircuitExtension(msg.into())
     }
 
+    pub fn circuit_closed(msg: impl Into<String>) -> Self {
+        TorError::CircuitClosed(msg.into())
+    }
+
     pub fn relay_selection(msg: impl Into<String>) -> Self {
         TorError::RelaySelection(msg.into())
     }
@@ -171,6 +394,61 @@ impl TorError {
         TorError::Serialization(msg.into())
     }
 
+    pub fn invalid_hostname(msg: impl Into<String>) -> Self {
+        TorError::InvalidHostname(msg.into())
+    }
+
+    /// Tag the error with the ID of the request it belongs to
+    pub fn with_request_id(self, request_id: impl Into<String>) -> Self {
+        match self {
+            TorError::Request { .. } => self,
+            other => TorError::Request {
+                request_id: request_id.into(),
+                source: Box::new(other),
+            },
+        }
+    }
+
+    /// The ID of the request this error belongs to, if any
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            TorError::Request { request_id, .. } => Some(request_id),
+            _ => None,
+        }
+    }
+
+    /// The END reason, if the exit closed the stream
+    pub fn end_reason(&self) -> Option<StreamEndReason> {
+        match self.inner() {
+            TorError::StreamEnded(reason) => Some(*reason),
+            _ => None,
+        }
+    }
+
+    /// The phase that timed out, for a request that ran out of time
+    pub fn timeout_phase(&self) -> Option<TimeoutPhase> {
+        match self.inner() {
+            TorError::RequestTimeout { phase, .. } => Some(*phase),
+            _ => None,
+        }
+    }
+
+    /// Progress report attached to a bootstrap timeout
+    pub fn bootstrap_report(&self) -> Option<&BootstrapReport> {
+        match self.inner() {
+            TorError::BootstrapTimeout(report) => Some(report),
+            _ => None,
+        }
+    }
+
+    /// The underlying error, without request context
+    pub fn inner(&self) -> &TorError {
+        match self {
+            TorError::Request { source, .. } => source.inner(),
+            other => other,
+        }
+    }
+
     /// Returns the error kind for classification
     pub fn kind(&self) -> TorErrorKind {
         match self {
@@ -178,23 +456,37 @@ impl TorError {
             TorError::WebSocket(_) => TorErrorKind::Network,
             TorError::Network(_) => TorErrorKind::Network,
             TorError::Timeout(_) => TorErrorKind::Timeout,
+            TorError::RequestTimeout { .. } => TorErrorKind::Timeout,
             TorError::CircuitCreation(_) => TorErrorKind::Circuit,
             TorError::CircuitExtension(_) => TorErrorKind::Circuit,
+            TorError::CircuitClosed(_) => TorErrorKind::Circuit,
             TorError::RelaySelection(_) => TorErrorKind::Circuit,
             TorError::ConsensusFetch(_) => TorErrorKind::Bootstrap,
             TorError::TorProtocol(_) => TorErrorKind::Protocol,
             TorError::TlsSetup(_) => TorErrorKind::Protocol,
             TorError::Protocol(_) => TorErrorKind::Protocol,
             TorError::HttpRequest(_) => TorErrorKind::Network,
+            TorError::ResponseTooLarge { .. } => TorErrorKind::Protocol,
+            TorError::CertificatePinMismatch { .. } => TorErrorKind::Protocol,
+            TorError::EchRejected { .. } => TorErrorKind::Protocol,
+            TorError::IdentityMismatch { .. } => TorErrorKind::Protocol,
             TorError::Configuration(_) => TorErrorKind::Configuration,
             TorError::Wasm(_) => TorErrorKind::Environment,
             TorError::Serialization(_) => TorErrorKind::Internal,
+            TorError::StreamEnded(_) => TorErrorKind::Network,
             TorError::Io(_) => TorErrorKind::Network,
             TorError::UrlParse(_) => TorErrorKind::Configuration,
+            TorError::InvalidHostname(_) => TorErrorKind::Configuration,
+            TorError::OnionServicesUnsupported(_) => TorErrorKind::Environment,
+            TorError::HsDescriptor(_) => TorErrorKind::Network,
+            TorError::OnionRendezvous(_) => TorErrorKind::Network,
+            TorError::OnionService(_) => TorErrorKind::Network,
             TorError::Json(_) => TorErrorKind::Internal,
             TorError::Internal(_) => TorErrorKind::Internal,
             TorError::NetDoc(_) => TorErrorKind::Bootstrap,
             TorError::Cancelled => TorErrorKind::Cancelled,
+            TorError::BootstrapTimeout(_) => TorErrorKind::Timeout,
+            TorError::Request { source, .. } => source.kind(),
         }
     }
 
@@ -207,12 +499,18 @@ impl TorError {
             TorError::Network(_) => true,
             TorError::Io(_) => true,
 
+            // Another exit may do better, unless the destination itself failed
+            TorError::StreamEnded(reason) => !reason.is_destination_failure(),
+
             // Timeouts are retryable (might succeed with more time or less load)
             TorError::Timeout(_) => true,
+            TorError::RequestTimeout { .. } => true,
+            TorError::BootstrapTimeout(_) => true,
 
             // Circuit failures can be retried with different relays
             TorError::CircuitCreation(_) => true,
             TorError::CircuitExtension(_) => true,
+            TorError::CircuitClosed(_) => true,
 
             // HTTP request failures through Tor are often transient
             TorError::HttpRequest(_) => true,
@@ -223,17 +521,37 @@ impl TorError {
             // Relay selection might work with different criteria or updated consensus
             TorError::RelaySelection(_) => true,
 
+            // HSDirs change every time period, and a service may republish
+            TorError::HsDescriptor(_) => true,
+            TorError::OnionRendezvous(_) => true,
+            TorError::OnionService(_) => true,
+
             // Protocol errors are usually not retryable (indicates a bug or incompatibility)
             TorError::TorProtocol(_) => false,
             TorError::TlsSetup(_) => false,
             TorError::Protocol(_) => false,
 
+            // The same resource will be just as large next time
+            TorError::ResponseTooLarge { .. } => false,
+
+            // Likely an exit intercepting TLS; another circuit may not
+            TorError::CertificatePinMismatch { .. } => true,
+
+            // Worth another attempt only with the server's new configurations
+            TorError::EchRejected { retry, .. } => *retry,
+
+            // Either the fingerprint is wrong or someone is in the way;
+            // the same bridge will answer the same next time
+            TorError::IdentityMismatch { .. } => false,
+
             // Configuration errors require user action
             TorError::Configuration(_) => false,
             TorError::UrlParse(_) => false,
+            TorError::InvalidHostname(_) => false,
 
             // Environment issues require environment changes
             TorError::Wasm(_) => false,
+            TorError::OnionServicesUnsupported(_) => false,
 
             // Internal errors are bugs, not transient
             TorError::Serialization(_) => false,
@@ -243,6 +561,8 @@ impl TorError {
 
             // Cancellation is user-initiated, not retryable
             TorError::Cancelled => false,
+
+            TorError::Request { source, .. } => source.is_retryable(),
         }
     }
 
@@ -254,22 +574,35 @@ impl TorError {
             TorError::TorProtocol(_) => "TOR_PROTOCOL",
             TorError::CircuitCreation(_) => "CIRCUIT_CREATION",
             TorError::CircuitExtension(_) => "CIRCUIT_EXTENSION",
+            TorError::CircuitClosed(_) => "CIRCUIT_CLOSED",
             TorError::RelaySelection(_) => "RELAY_SELECTION",
             TorError::ConsensusFetch(_) => "CONSENSUS_FETCH",
             TorError::HttpRequest(_) => "HTTP_REQUEST",
             TorError::TlsSetup(_) => "TLS_SETUP",
-            TorError::Timeout(_) => "TIMEOUT",
+            TorError::ResponseTooLarge { .. } => "RESPONSE_TOO_LARGE",
+            TorError::CertificatePinMismatch { .. } => "CERT_PIN_MISMATCH",
+            TorError::EchRejected { .. } => "ECH_REJECTED",
+            TorError::IdentityMismatch { .. } => "IDENTITY_MISMATCH",
+            TorError::Timeout(_) | TorError::RequestTimeout { .. } => "TIMEOUT",
             TorError::Configuration(_) => "CONFIGURATION",
             TorError::Network(_) => "NETWORK",
             TorError::Protocol(_) => "PROTOCOL",
             TorError::Wasm(_) => "WASM_ENVIRONMENT",
             TorError::Serialization(_) => "SERIALIZATION",
+            TorError::StreamEnded(_) => "STREAM_ENDED",
             TorError::Io(_) => "IO",
             TorError::UrlParse(_) => "URL_PARSE",
+            TorError::InvalidHostname(_) => "INVALID_HOSTNAME",
+            TorError::OnionServicesUnsupported(_) => "ONION_UNSUPPORTED",
+            TorError::HsDescriptor(_) => "HS_DESCRIPTOR",
+            TorError::OnionRendezvous(_) => "ONION_RENDEZVOUS",
+            TorError::OnionService(_) => "ONION_SERVICE",
             TorError::Json(_) => "JSON",
             TorError::Internal(_) => "INTERNAL",
             TorError::NetDoc(_) => "NETDOC",
             TorError::Cancelled => "CANCELLED",
+            TorError::BootstrapTimeout(_) => "BOOTSTRAP_TIMEOUT",
+            TorError::Request { source, .. } => source.code(),
         }
     }
 }
@@ -317,6 +650,12 @@ mod tests {
                 "CIRCUIT_EXTENSION",
                 true,
             ),
+            (
+                TorError::circuit_closed("x"),
+                TorErrorKind::Circuit,
+                "CIRCUIT_CLOSED",
+                true,
+            ),
             (
                 TorError::relay_selection("x"),
                 TorErrorKind::Circuit,
@@ -353,12 +692,42 @@ mod tests {
                 "CONFIGURATION",
                 false,
             ),
+            (
+                TorError::invalid_hostname("x"),
+                TorErrorKind::Configuration,
+                "INVALID_HOSTNAME",
+                false,
+            ),
             (
                 TorError::wasm("x"),
                 TorErrorKind::Environment,
                 "WASM_ENVIRONMENT",
                 false,
             ),
+            (
+                TorError::HsDescriptor("x".into()),
+                TorErrorKind::Network,
+                "HS_DESCRIPTOR",
+                true,
+            ),
+            (
+                TorError::OnionRendezvous("x".into()),
+                TorErrorKind::Network,
+                "ONION_RENDEZVOUS",
+                true,
+            ),
+            (
+                TorError::OnionService("x".into()),
+                TorErrorKind::Network,
+                "ONION_SERVICE",
+                true,
+            ),
+            (
+                TorError::OnionServicesUnsupported("x.onion".into()),
+                TorErrorKind::Environment,
+                "ONION_UNSUPPORTED",
+                false,
+            ),
             (
                 TorError::serialization("x"),
                 TorErrorKind::Internal,
@@ -371,12 +740,62 @@ mod tests {
                 "INTERNAL",
                 false,
             ),
+            (
+                TorError::ResponseTooLarge { limit: 1024 },
+                TorErrorKind::Protocol,
+                "RESPONSE_TOO_LARGE",
+                false,
+            ),
+            (
+                TorError::CertificatePinMismatch {
+                    host: "example.com".into(),
+                },
+                TorErrorKind::Protocol,
+                "CERT_PIN_MISMATCH",
+                true,
+            ),
+            (
+                TorError::EchRejected {
+                    host: "example.com".into(),
+                    retry: false,
+                },
+                TorErrorKind::Protocol,
+                "ECH_REJECTED",
+                false,
+            ),
+            (
+                TorError::IdentityMismatch {
+                    expected: "AA".into(),
+                    actual: "BB".into(),
+                },
+                TorErrorKind::Protocol,
+                "IDENTITY_MISMATCH",
+                false,
+            ),
             (
                 TorError::Cancelled,
                 TorErrorKind::Cancelled,
                 "CANCELLED",
                 false,
             ),
+            (
+                TorError::BootstrapTimeout(Box::default()),
+                TorErrorKind::Timeout,
+                "BOOTSTRAP_TIMEOUT",
+                true,
+            ),
+            (
+                TorError::StreamEnded(StreamEndReason::ExitPolicy),
+                TorErrorKind::Network,
+                "STREAM_ENDED",
+                true,
+            ),
+            (
+                TorError::StreamEnded(StreamEndReason::ConnectRefused),
+                TorErrorKind::Network,
+                "STREAM_ENDED",
+                false,
+            ),
         ];
 
         for (err, expected_kind, expected_code, expected_retryable) in cases {
@@ -391,6 +810,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn request_timeouts_name_their_phase() {
+        let err = TorError::RequestTimeout {
+            phase: TimeoutPhase::FirstByte,
+            limit: std::time::Duration::from_secs(5),
+        }
+        .with_request_id("abc");
+        assert_eq!(err.timeout_phase(), Some(TimeoutPhase::FirstByte));
+        assert_eq!(
+            (err.kind(), err.code(), err.is_retryable()),
+            (TorErrorKind::Timeout, "TIMEOUT", true)
+        );
+        assert_eq!(
+            err.to_string(),
+            "Request firstByte timeout after 5000ms [request abc]"
+        );
+        assert_eq!(TorError::timeout("x").timeout_phase(), None);
+    }
+
+    #[test]
+    fn end_reasons_survive_stream_io_errors() {
+        use tor_cell::relaycell::msg::EndReason;
+
+        let io_err = std::io::Error::from(tor_proto::Error::EndReceived(EndReason::EXITPOLICY));
+        let err = TorError::from(io_err).with_request_id("abc123");
+        assert_eq!(err.end_reason(), Some(StreamEndReason::ExitPolicy));
+        assert_eq!(err.code(), "STREAM_ENDED");
+        assert!(err
+            .to_string()
+            .starts_with("Stream closed by exit: EXITPOLICY"));
+
+        let plain = TorError::from(std::io::Error::other("broken pipe"));
+        assert!(matches!(plain, TorError::Io(_)));
+        assert_eq!(plain.end_reason(), None);
+    }
+
+    #[test]
+    fn request_id_preserves_classification() {
+        let err = TorError::timeout("slow exit").with_request_id("abc123");
+        assert_eq!(err.request_id(), Some("abc123"));
+        assert_eq!(err.kind(), TorErrorKind::Timeout);
+        assert_eq!(err.code(), "TIMEOUT");
+        assert!(err.is_retryable());
+        assert!(matches!(err.inner(), TorError::Timeout(_)));
+        assert_eq!(err.to_string(), "Timeout: slow exit [request abc123]");
+
+        // Tagging twice keeps the original ID
+        let err = err.with_request_id("other");
+        assert_eq!(err.request_id(), Some("abc123"));
+    }
+
     #[test]
     fn error_kind_codes_are_uppercase() {
         let kinds = [
@@ -0,0 +1,487 @@
+//! Circuit and stream lifecycle events
+//!
+//! Applications that draw a circuit display (like Tor Browser's) or collect
+//! diagnostics need to know when circuits come and go, not just poll the
+//! current status. [`CircuitEvents`] is a handle shared by the circuit
+//! manager and the HTTP client; every [`subscribe`](CircuitEvents::subscribe)
+//! call returns a stream of the [`CircuitEvent`]s emitted after it. Dropped
+//! subscribers are pruned on the next event, and with no subscribers
+//! emitting costs nothing beyond a lock.
+//!
+//! Connecting to an onion service takes several round trips through
+//! different circuits before the first stream can open. The same handle
+//! carries an [`OnionEvent`] for each phase, to subscribers of
+//! [`subscribe_onion`](CircuitEvents::subscribe_onion), so applications can
+//! show how far a connection got.
+//!
+//! Losing the channel to the bridge takes every circuit with it. A
+//! [`ChannelEvent`] reports the loss and each step of reconnecting, to
+//! subscribers of [`subscribe_channel`](CircuitEvents::subscribe_channel).
+//!
+//! [`TorClient::subscribe`](crate::TorClient::subscribe) carries all of
+//! these, bootstrap progress, installed consensuses and failed operations as
+//! one stream of [`TorEvent`]s, in the order they happened.
+
+use crate::bootstrap::BootstrapStatus;
+use crate::circuit::CircuitRelayInfo;
+use crate::error::TorError;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Something that happened to a circuit or a stream on it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all_fields = "camelCase")]
+pub enum CircuitEvent {
+    /// A circuit finished building and takes streams
+    CircuitBuilt {
+        circuit_id: String,
+        relays: Vec<CircuitRelayInfo>,
+    },
+    /// A circuit under construction reached another hop (1 is the bridge)
+    CircuitExtended {
+        circuit_id: String,
+        hop: u8,
+        relay: CircuitRelayInfo,
+    },
+    /// A circuit failed to build, died, or was retired
+    CircuitClosed { circuit_id: String, reason: String },
+    /// A stream to `target` (`host:port`) was opened on a circuit, for the
+    /// request `request_id` if it carries one
+    StreamAttached {
+        circuit_id: String,
+        stream_id: u64,
+        target: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    /// A stream finished after moving the given bytes; `error` is set if it
+    /// ended in failure
+    StreamClosed {
+        circuit_id: String,
+        stream_id: u64,
+        bytes_read: u64,
+        bytes_written: u64,
+        error: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+}
+
+impl CircuitEvent {
+    /// The circuit this event is about
+    pub fn circuit_id(&self) -> &str {
+        match self {
+            CircuitEvent::CircuitBuilt { circuit_id, .. }
+            | CircuitEvent::CircuitExtended { circuit_id, .. }
+            | CircuitEvent::CircuitClosed { circuit_id, .. }
+            | CircuitEvent::StreamAttached { circuit_id, .. }
+            | CircuitEvent::StreamClosed { circuit_id, .. } => circuit_id,
+        }
+    }
+}
+
+/// A step in connecting to an onion service at `address`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all_fields = "camelCase")]
+pub enum OnionEvent {
+    /// The service's descriptor is at hand, from the cache or an HSDir,
+    /// listing `intro_points` introduction points
+    DescriptorFetched {
+        address: String,
+        cached: bool,
+        intro_points: usize,
+    },
+    /// A rendezvous point is waiting for the service on `circuit_id`;
+    /// `attempt` counts from 1 per introduction point tried
+    RendezvousEstablished {
+        address: String,
+        circuit_id: String,
+        rendezvous_point: String,
+        attempt: usize,
+    },
+    /// An introduction point accepted INTRODUCE1 and passed it on
+    IntroductionSent {
+        address: String,
+        intro_point: String,
+        attempt: usize,
+    },
+    /// The service met the client at the rendezvous point; streams to it
+    /// go over `circuit_id`
+    Connected { address: String, circuit_id: String },
+    /// No introduction point worked, or the descriptor couldn't be had
+    Failed { address: String, error: String },
+}
+
+impl OnionEvent {
+    /// The service this event is about
+    pub fn address(&self) -> &str {
+        match self {
+            OnionEvent::DescriptorFetched { address, .. }
+            | OnionEvent::RendezvousEstablished { address, .. }
+            | OnionEvent::IntroductionSent { address, .. }
+            | OnionEvent::Connected { address, .. }
+            | OnionEvent::Failed { address, .. } => address,
+        }
+    }
+}
+
+/// Something that happened to the channel to the bridge
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all_fields = "camelCase")]
+pub enum ChannelEvent {
+    /// The bridge closed the channel, and `circuits_closed` circuits with it
+    Lost {
+        transport: String,
+        endpoint: String,
+        circuits_closed: usize,
+    },
+    /// Reconnect attempt `attempt` (counting from 1) starts in `delay_ms`
+    Reconnecting { attempt: u32, delay_ms: u64 },
+    /// Reconnect attempt `attempt` failed with `error`
+    ReconnectFailed { attempt: u32, error: String },
+    /// A channel is up again after `attempts` attempts, with `circuits`
+    /// circuits rebuilt
+    Reconnected {
+        transport: String,
+        endpoint: String,
+        attempts: u32,
+        circuits: usize,
+    },
+    /// No attempt succeeded; the next request tries again
+    GaveUp { attempts: u32 },
+    /// The client went dormant after `idle_ms` without activity, closing
+    /// the channel and `circuits_closed` circuits
+    Dormant {
+        idle_ms: u64,
+        circuits_closed: usize,
+    },
+    /// A request woke the dormant client and a channel is up again
+    Awake { transport: String, endpoint: String },
+    /// The app went to the background; padding is paused, and with
+    /// `dormant` the channel was closed too
+    Suspended { dormant: bool },
+    /// The app is back; `circuits_closed` circuits didn't survive and
+    /// `circuits_ready` are ready
+    Resumed {
+        circuits_closed: usize,
+        circuits_ready: usize,
+    },
+}
+
+/// Any event a client reports, tagged with its `category` when serialized
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(
+    tag = "category",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum TorEvent {
+    /// Bootstrap reached another phase or percentage
+    Bootstrap(BootstrapStatus),
+    /// A circuit was built, extended or closed
+    Circuit(CircuitEvent),
+    /// A stream was attached to a circuit or closed
+    Stream(CircuitEvent),
+    /// A step in connecting to an onion service
+    Onion(OnionEvent),
+    /// The channel to the bridge was lost or reconnected, or the client
+    /// went dormant or to the background
+    Channel(ChannelEvent),
+    /// `relays` relays from a consensus were installed; `source` is
+    /// `channel`, `cached` (the static cache), `injected`, `shared` (another
+    /// client's) or `restored` (an imported state)
+    Consensus { relays: usize, source: String },
+    /// A request or stream failed; `operation` is `request` or `connect`,
+    /// `code` is the error's [`TorError::code`], and a failed request
+    /// carries its ID
+    Error {
+        operation: String,
+        code: String,
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+}
+
+impl TorEvent {
+    /// Every `category`, as serialized
+    pub const CATEGORIES: [&'static str; 7] = [
+        "bootstrap",
+        "circuit",
+        "stream",
+        "onion",
+        "channel",
+        "consensus",
+        "error",
+    ];
+
+    /// The event's `category`, as serialized
+    pub fn category(&self) -> &'static str {
+        match self {
+            TorEvent::Bootstrap(_) => "bootstrap",
+            TorEvent::Circuit(_) => "circuit",
+            TorEvent::Stream(_) => "stream",
+            TorEvent::Onion(_) => "onion",
+            TorEvent::Channel(_) => "channel",
+            TorEvent::Consensus { .. } => "consensus",
+            TorEvent::Error { .. } => "error",
+        }
+    }
+}
+
+impl From<CircuitEvent> for TorEvent {
+    fn from(event: CircuitEvent) -> Self {
+        match event {
+            CircuitEvent::StreamAttached { .. } | CircuitEvent::StreamClosed { .. } => {
+                TorEvent::Stream(event)
+            }
+            _ => TorEvent::Circuit(event),
+        }
+    }
+}
+
+/// Senders to the subscribers of one kind of event
+pub(crate) struct Subscribers<T>(Mutex<Vec<UnboundedSender<T>>>);
+
+impl<T> Default for Subscribers<T> {
+    fn default() -> Self {
+        Self(Mutex::default())
+    }
+}
+
+impl<T: Clone> Subscribers<T> {
+    pub(crate) fn subscribe(&self) -> UnboundedReceiver<T> {
+        let (tx, rx) = unbounded();
+        self.lock().push(tx);
+        rx
+    }
+
+    fn count(&self) -> usize {
+        let mut subscribers = self.lock();
+        subscribers.retain(|tx| !tx.is_closed());
+        subscribers.len()
+    }
+
+    /// Add a subscriber whose receiver the caller holds
+    pub(crate) fn add(&self, tx: UnboundedSender<T>) {
+        self.lock().push(tx);
+    }
+
+    pub(crate) fn emit(&self, event: T) {
+        self.lock()
+            .retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+
+    /// Like [`emit`](Self::emit), making the event only if anyone listens
+    pub(crate) fn emit_with(&self, event: impl FnOnce() -> T) {
+        let mut subscribers = self.lock();
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = event();
+        subscribers.retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<UnboundedSender<T>>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    subscribers: Subscribers<CircuitEvent>,
+    onion_subscribers: Subscribers<OnionEvent>,
+    channel_subscribers: Subscribers<ChannelEvent>,
+    all_subscribers: Subscribers<TorEvent>,
+    next_stream_id: AtomicU64,
+}
+
+/// Shared source of circuit lifecycle events
+#[derive(Clone, Default)]
+pub struct CircuitEvents {
+    inner: Arc<Inner>,
+}
+
+impl CircuitEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive every event emitted from now on
+    pub fn subscribe(&self) -> UnboundedReceiver<CircuitEvent> {
+        self.inner.subscribers.subscribe()
+    }
+
+    /// Number of live subscribers
+    pub fn subscriber_count(&self) -> usize {
+        self.inner.subscribers.count()
+    }
+
+    /// Deliver `event` to every subscriber
+    pub fn emit(&self, event: CircuitEvent) {
+        self.inner
+            .all_subscribers
+            .emit_with(|| event.clone().into());
+        self.inner.subscribers.emit(event);
+    }
+
+    /// Receive every onion service connection event emitted from now on
+    pub fn subscribe_onion(&self) -> UnboundedReceiver<OnionEvent> {
+        self.inner.onion_subscribers.subscribe()
+    }
+
+    /// Deliver `event` to every onion event subscriber
+    pub fn emit_onion(&self, event: OnionEvent) {
+        self.inner
+            .all_subscribers
+            .emit_with(|| TorEvent::Onion(event.clone()));
+        self.inner.onion_subscribers.emit(event);
+    }
+
+    /// Receive every channel loss and reconnection event emitted from now on
+    pub fn subscribe_channel(&self) -> UnboundedReceiver<ChannelEvent> {
+        self.inner.channel_subscribers.subscribe()
+    }
+
+    /// Deliver `event` to every channel event subscriber
+    pub fn emit_channel(&self, event: ChannelEvent) {
+        self.inner
+            .all_subscribers
+            .emit_with(|| TorEvent::Channel(event.clone()));
+        self.inner.channel_subscribers.emit(event);
+    }
+
+    /// Send every event emitted from now on to `tx` as a [`TorEvent`]
+    pub(crate) fn forward(&self, tx: UnboundedSender<TorEvent>) {
+        self.inner.all_subscribers.add(tx);
+    }
+
+    /// Report that `relays` relays from `source` were installed
+    pub fn consensus_installed(&self, relays: usize, source: &str) {
+        self.inner
+            .all_subscribers
+            .emit_with(|| TorEvent::Consensus {
+                relays,
+                source: source.to_string(),
+            });
+    }
+
+    /// Report that `operation` failed with `error`, while serving request
+    /// `request_id` if it was one
+    pub fn operation_failed(&self, operation: &str, error: &TorError, request_id: Option<&str>) {
+        self.inner.all_subscribers.emit_with(|| TorEvent::Error {
+            operation: operation.to_string(),
+            code: error.code().to_string(),
+            message: error.to_string(),
+            request_id: request_id.map(str::to_string),
+        });
+    }
+
+    /// Report that `circuit_id` is gone
+    pub fn circuit_closed(&self, circuit_id: &str, reason: impl Into<String>) {
+        self.emit(CircuitEvent::CircuitClosed {
+            circuit_id: circuit_id.to_string(),
+            reason: reason.into(),
+        });
+    }
+
+    /// Allocate an ID for a new stream, unique for this client
+    pub(crate) fn next_stream_id(&self) -> u64 {
+        self.inner.next_stream_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_subscribers_receive_later_events_only() {
+        let events = CircuitEvents::new();
+        events.circuit_closed("circuit_0", "before anyone listened");
+
+        let mut first = events.subscribe();
+        let second = events.subscribe();
+        assert_eq!(events.subscriber_count(), 2);
+        drop(second);
+        assert_eq!(events.subscriber_count(), 1);
+
+        events.circuit_closed("circuit_1", "new identity");
+        let event = first.next().await.unwrap();
+        assert_eq!(event.circuit_id(), "circuit_1");
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "CircuitClosed");
+        assert_eq!(json["circuitId"], "circuit_1");
+        assert_eq!(json["reason"], "new identity");
+    }
+
+    #[tokio::test]
+    async fn test_onion_events_have_their_own_subscribers() {
+        let events = CircuitEvents::new();
+        let mut circuit_events = events.subscribe();
+        let mut onion_events = events.subscribe_onion();
+
+        events.emit_onion(OnionEvent::IntroductionSent {
+            address: "example.onion".to_string(),
+            intro_point: "relay".to_string(),
+            attempt: 2,
+        });
+        let event = onion_events.next().await.unwrap();
+        assert_eq!(event.address(), "example.onion");
+        assert!(circuit_events.try_next().is_err());
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "IntroductionSent");
+        assert_eq!(json["introPoint"], "relay");
+        assert_eq!(json["attempt"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_events_keep_their_order() {
+        let events = CircuitEvents::new();
+        let (tx, mut all) = unbounded();
+        events.forward(tx);
+
+        events.emit(CircuitEvent::StreamAttached {
+            circuit_id: "circuit_1".to_string(),
+            stream_id: 1,
+            target: "example.com:443".to_string(),
+            request_id: None,
+        });
+        events.emit_channel(ChannelEvent::GaveUp { attempts: 3 });
+        events.circuit_closed("circuit_1", "channel lost");
+        events.operation_failed(
+            "request",
+            &TorError::Network("reset".to_string()),
+            Some("req-1"),
+        );
+        events.operation_failed("connect", &TorError::Cancelled, None);
+
+        let stream = all.next().await.unwrap();
+        assert!(matches!(stream, TorEvent::Stream(_)));
+        let json = serde_json::to_value(&stream).unwrap();
+        assert_eq!(json["category"], "stream");
+        assert_eq!(json["type"], "StreamAttached");
+        assert_eq!(json["circuitId"], "circuit_1");
+
+        assert_eq!(
+            all.next().await.unwrap(),
+            TorEvent::Channel(ChannelEvent::GaveUp { attempts: 3 })
+        );
+        assert!(matches!(all.next().await.unwrap(), TorEvent::Circuit(_)));
+        let error = all.next().await.unwrap();
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(error.category(), "error");
+        assert_eq!(json["category"], "error");
+        assert_eq!(json["operation"], "request");
+        assert_eq!(json["code"], "NETWORK");
+        assert_eq!(json["requestId"], "req-1");
+        let json = serde_json::to_value(all.next().await.unwrap()).unwrap();
+        assert_eq!(json["operation"], "connect");
+        assert!(json.get("requestId").is_none());
+    }
+}
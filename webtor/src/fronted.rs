@@ -0,0 +1,343 @@
+//! One-shot HTTPS requests to rendezvous services
+//!
+//! The Snowflake broker and the Moat bridge distributor are plain HTTPS
+//! services that censors block by name, so outside the browser the request
+//! can be domain-fronted: the TLS connection (and its SNI) goes to an
+//! innocuous front domain on the same CDN while the `Host` header names the
+//! real service. Browsers don't let a page choose the `Host` header, so WASM
+//! builds always contact the URL directly, via CORS.
+
+use crate::error::{Result, TorError};
+#[cfg(not(target_arch = "wasm32"))]
+use tracing::debug;
+
+/// Content type of Snowflake broker polls
+pub const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// Fetch `url`, POSTing `body` as `content_type` if given and GETting it
+/// otherwise, connecting through one of `fronts` (picked at random) if any
+#[cfg(target_arch = "wasm32")]
+pub async fn fetch(
+    url: &str,
+    _fronts: &[String],
+    body: Option<&[u8]>,
+    content_type: &str,
+) -> Result<Vec<u8>> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+    use web_sys::{Request, RequestInit, RequestMode, Response};
+
+    let opts = RequestInit::new();
+    opts.set_mode(RequestMode::Cors);
+
+    if let Some(body) = body {
+        opts.set_method("POST");
+        // Convert body to Uint8Array
+        let body_array = js_sys::Uint8Array::from(body);
+        opts.set_body(&body_array.into());
+    } else {
+        opts.set_method("GET");
+    }
+
+    let request = Request::new_with_str_and_init(url, &opts)
+        .map_err(|e| TorError::Network(format!("Failed to create request: {:?}", e)))?;
+
+    if body.is_some() {
+        request
+            .headers()
+            .set("Content-Type", content_type)
+            .map_err(|e| {
+                TorError::Network(format!("Failed to set Content-Type header: {:?}", e))
+            })?;
+    }
+
+    let window =
+        web_sys::window().ok_or_else(|| TorError::Internal("No window object".to_string()))?;
+
+    let resp_value = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(|e| TorError::Network(format!("Fetch failed: {:?}", e)))?;
+
+    let resp: Response = resp_value
+        .dyn_into()
+        .map_err(|_| TorError::Internal("Response cast failed".to_string()))?;
+
+    if !resp.ok() {
+        return Err(TorError::Network(format!(
+            "{} returned HTTP {}",
+            url,
+            resp.status()
+        )));
+    }
+
+    let array_buffer = JsFuture::from(
+        resp.array_buffer()
+            .map_err(|e| TorError::Network(format!("Failed to get body: {:?}", e)))?,
+    )
+    .await
+    .map_err(|e| TorError::Network(format!("Failed to read body: {:?}", e)))?;
+
+    let uint8_array = js_sys::Uint8Array::new(&array_buffer);
+    Ok(uint8_array.to_vec())
+}
+
+/// Fetch `url`, POSTing `body` as `content_type` if given and GETting it
+/// otherwise, connecting through one of `fronts` (picked at random) if any
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn fetch(
+    url: &str,
+    fronts: &[String],
+    body: Option<&[u8]>,
+    content_type: &str,
+) -> Result<Vec<u8>> {
+    use rustls_pki_types::ServerName;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use tokio_rustls::TlsConnector;
+
+    let endpoint = Endpoint::new(url, fronts)?;
+    if endpoint.connect_host != endpoint.host_header {
+        debug!(
+            "Domain-fronting request to {} via {}",
+            endpoint.host_header, endpoint.connect_host
+        );
+    }
+
+    let addr = format!("{}:{}", endpoint.connect_host, endpoint.port);
+    let stream = TcpStream::connect(&addr).await.map_err(|e| {
+        TorError::Network(format!(
+            "Failed to connect to {}: {}",
+            endpoint.host_header, e
+        ))
+    })?;
+
+    // Setup TLS
+    let mut root_store = rustls::RootCertStore::empty();
+    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+
+    let connector = TlsConnector::from(std::sync::Arc::new(config));
+    let server_name = ServerName::try_from(endpoint.connect_host.clone())
+        .map_err(|_| TorError::Configuration("Invalid server name".to_string()))?;
+
+    let mut tls_stream = connector
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| TorError::Network(format!("TLS handshake failed: {}", e)))?;
+
+    let request = endpoint.request_head(body.map(|body| (body.len(), content_type)));
+    tls_stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to send request: {}", e)))?;
+    if let Some(body) = body {
+        tls_stream
+            .write_all(body)
+            .await
+            .map_err(|e| TorError::Network(format!("Failed to send body: {}", e)))?;
+    }
+    tls_stream
+        .flush()
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to flush: {}", e)))?;
+
+    // Read response
+    let mut response = Vec::new();
+    tls_stream
+        .read_to_end(&mut response)
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to read response: {}", e)))?;
+
+    response_body(&response)
+}
+
+/// Where a request is sent: the TCP/TLS peer and the HTTP `Host`
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Endpoint {
+    /// Host to connect to and present in SNI (the front when fronting)
+    connect_host: String,
+    port: u16,
+    /// Host named in the `Host` header (always the real service)
+    host_header: String,
+    /// Path and query of the request
+    target: String,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Endpoint {
+    /// Resolve `url`, connecting to one of `fronts` (picked at random) if any
+    fn new(url: &str, fronts: &[String]) -> Result<Self> {
+        use rand::seq::SliceRandom;
+
+        let parsed = url::Url::parse(url)
+            .map_err(|e| TorError::Configuration(format!("Invalid URL: {}", e)))?;
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| TorError::Configuration("URL has no host".to_string()))?;
+        let connect_host = fronts
+            .choose(&mut rand::thread_rng())
+            .map(String::as_str)
+            .unwrap_or(host);
+        let target = match parsed.query() {
+            Some(query) => format!("{}?{}", parsed.path(), query),
+            None => parsed.path().to_string(),
+        };
+
+        Ok(Self {
+            connect_host: connect_host.to_string(),
+            port: parsed.port().unwrap_or(443),
+            host_header: host.to_string(),
+            target,
+        })
+    }
+
+    /// HTTP/1.1 request head: a POST of a body with the given length and
+    /// content type, or a GET without a body
+    fn request_head(&self, body: Option<(usize, &str)>) -> String {
+        match body {
+            Some((len, content_type)) => format!(
+                "POST {} HTTP/1.1\r\n\
+                 Host: {}\r\n\
+                 Content-Type: {}\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\
+                 \r\n",
+                self.target, self.host_header, content_type, len
+            ),
+            None => format!(
+                "GET {} HTTP/1.1\r\n\
+                 Host: {}\r\n\
+                 Connection: close\r\n\
+                 \r\n",
+                self.target, self.host_header
+            ),
+        }
+    }
+}
+
+/// Split a raw HTTP response into its body, failing on a non-2xx status
+#[cfg(not(target_arch = "wasm32"))]
+fn response_body(response: &[u8]) -> Result<Vec<u8>> {
+    let head_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| TorError::Protocol("Invalid HTTP response".to_string()))?;
+    let head = String::from_utf8_lossy(&response[..head_end]);
+    let status = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| TorError::Protocol("Invalid HTTP status line".to_string()))?;
+    if !(200..300).contains(&status) {
+        return Err(TorError::Network(format!(
+            "Server returned HTTP {}",
+            status
+        )));
+    }
+    let body = &response[head_end + 4..];
+    let chunked = head.lines().skip(1).any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+    if chunked {
+        dechunk(body)
+    } else {
+        Ok(body.to_vec())
+    }
+}
+
+/// Decode a `Transfer-Encoding: chunked` body
+#[cfg(not(target_arch = "wasm32"))]
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>> {
+    let invalid = || TorError::Protocol("Invalid chunked HTTP body".to_string());
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(invalid)?;
+        let size = std::str::from_utf8(&body[..line_end]).map_err(|_| invalid())?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        if body.len() < size {
+            return Err(invalid());
+        }
+        decoded.extend_from_slice(&body[..size]);
+        body = body[size..].strip_prefix(b"\r\n").ok_or_else(invalid)?;
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_direct_endpoint() {
+        let endpoint = Endpoint::new("https://broker.example:8443/client", &[]).unwrap();
+        assert_eq!(endpoint.connect_host, "broker.example");
+        assert_eq!(endpoint.host_header, "broker.example");
+        assert_eq!(endpoint.port, 8443);
+        assert_eq!(endpoint.target, "/client");
+    }
+
+    #[test]
+    fn test_fronted_endpoint() {
+        let fronts = vec!["front-a.example".to_string(), "front-b.example".to_string()];
+        let endpoint = Endpoint::new("https://1098762253.rsc.cdn77.org/client", &fronts).unwrap();
+
+        assert!(fronts.contains(&endpoint.connect_host));
+        assert_eq!(endpoint.host_header, "1098762253.rsc.cdn77.org");
+        assert_eq!(endpoint.port, 443);
+
+        let head = endpoint.request_head(Some((12, FORM_CONTENT_TYPE)));
+        assert!(head.starts_with("POST /client HTTP/1.1\r\n"));
+        assert!(head.contains("Host: 1098762253.rsc.cdn77.org\r\n"));
+        assert!(head.contains("Content-Type: application/x-www-form-urlencoded\r\n"));
+        assert!(head.contains("Content-Length: 12\r\n"));
+        assert!(!head.contains("front-"));
+    }
+
+    #[test]
+    fn test_get_endpoint_keeps_query() {
+        let endpoint = Endpoint::new(
+            "https://x.cdn.example/c/s/broker.example/amp/client/0a/b?v=1",
+            &["front.example".to_string()],
+        )
+        .unwrap();
+        let head = endpoint.request_head(None);
+        assert!(head.starts_with("GET /c/s/broker.example/amp/client/0a/b?v=1 HTTP/1.1\r\n"));
+        assert!(head.contains("Host: x.cdn.example\r\n"));
+        assert!(!head.contains("Content-Length"));
+    }
+
+    #[test]
+    fn test_response_body_status() {
+        let ok = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(response_body(ok).unwrap(), b"{}");
+
+        let forbidden = b"HTTP/1.1 403 Forbidden\r\n\r\nnope";
+        assert!(response_body(forbidden).is_err());
+        assert!(response_body(b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_response_body_chunked() {
+        let chunked =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n{\"a\"\r\n3;x=y\r\n:1}\r\n0\r\n\r\n";
+        assert_eq!(response_body(chunked).unwrap(), b"{\"a\":1}");
+
+        let truncated = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n9\r\nabc";
+        assert!(response_body(truncated).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! GeoIP lookups for country-aware relay selection
+//!
+//! Microdescriptor consensuses carry no location data, so relay countries come
+//! from a GeoIP table in Tor's own `geoip` file format: one
+//! `<first-ip>,<last-ip>,<country>` line per IPv4 range, with addresses as
+//! decimal integers. The table is not bundled; embedders load it (e.g. from
+//! Tor Browser's `geoip` file) and hand it to the client via
+//! `TorClientOptions::with_geoip`.
+
+use crate::error::{Result, TorError};
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// Country code used for addresses that the GeoIP table doesn't cover
+pub const UNKNOWN_COUNTRY: &str = "??";
+
+/// Sorted table of IPv4 ranges to country codes
+#[derive(Clone, Default)]
+pub struct GeoIpDb {
+    ranges: Vec<GeoIpRange>,
+}
+
+#[derive(Clone)]
+struct GeoIpRange {
+    first: u32,
+    last: u32,
+    country: [u8; 2],
+}
+
+impl fmt::Debug for GeoIpDb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GeoIpDb({} ranges)", self.ranges.len())
+    }
+}
+
+impl GeoIpDb {
+    /// Parse a table in Tor's `geoip` format
+    ///
+    /// Blank lines and `#` comments are skipped. Ranges must not overlap.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid =
+                || TorError::Configuration(format!("Invalid GeoIP line {}: {}", idx + 1, line));
+
+            let mut fields = line.split(',');
+            let (Some(first), Some(last), Some(country), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            let first: u32 = first.trim().parse().map_err(|_| invalid())?;
+            let last: u32 = last.trim().parse().map_err(|_| invalid())?;
+            let country = country.trim().to_ascii_uppercase();
+            let country: [u8; 2] = country
+                .as_bytes()
+                .try_into()
+                .ok()
+                .filter(|cc: &[u8; 2]| cc.iter().all(u8::is_ascii_alphabetic))
+                .ok_or_else(invalid)?;
+            if first > last {
+                return Err(invalid());
+            }
+            ranges.push(GeoIpRange {
+                first,
+                last,
+                country,
+            });
+        }
+
+        ranges.sort_by_key(|r| r.first);
+        if ranges.windows(2).any(|w| w[0].last >= w[1].first) {
+            return Err(TorError::Configuration(
+                "GeoIP table contains overlapping ranges".to_string(),
+            ));
+        }
+        Ok(Self { ranges })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Upper-case ISO 3166-1 alpha-2 country code for `addr`, if known
+    pub fn country(&self, addr: Ipv4Addr) -> Option<String> {
+        let ip = u32::from(addr);
+        let idx = self.ranges.partition_point(|r| r.first <= ip);
+        let range = self.ranges[..idx].last()?;
+        (ip <= range.last).then(|| String::from_utf8_lossy(&range.country).into_owned())
+    }
+
+    /// Country code for a relay address string (IPv4 only)
+    pub fn country_for_address(&self, address: &str) -> Option<String> {
+        address
+            .parse::<Ipv4Addr>()
+            .ok()
+            .and_then(|addr| self.country(addr))
+    }
+}
+
+/// Normalize a country code from user configuration (e.g. `"de"`, `"{DE}"`)
+pub fn normalize_country_code(code: &str) -> String {
+    code.trim()
+        .trim_start_matches('{')
+        .trim_end_matches('}')
+        .to_ascii_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "\
+# Test table
+16777216,16777471,AU
+134744064,134744319,us
+
+3232235520,3232301055,DE
+";
+
+    #[test]
+    fn test_lookup() {
+        let db = GeoIpDb::parse(TABLE).unwrap();
+        assert_eq!(db.len(), 3);
+        assert_eq!(
+            db.country("1.0.0.1".parse().unwrap()).as_deref(),
+            Some("AU")
+        );
+        assert_eq!(
+            db.country("8.8.8.8".parse().unwrap()).as_deref(),
+            Some("US")
+        );
+        assert_eq!(
+            db.country_for_address("192.168.10.1").as_deref(),
+            Some("DE")
+        );
+        assert_eq!(db.country("1.0.1.0".parse().unwrap()), None);
+        assert_eq!(db.country("0.0.0.1".parse().unwrap()), None);
+        assert_eq!(db.country_for_address("2001:db8::1"), None);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_tables() {
+        assert!(GeoIpDb::parse("1,2").is_err());
+        assert!(GeoIpDb::parse("5,1,US").is_err());
+        assert!(GeoIpDb::parse("1,2,USA").is_err());
+        assert!(GeoIpDb::parse("1,10,US\n5,20,DE").is_err());
+    }
+
+    #[test]
+    fn test_normalize_country_code() {
+        assert_eq!(normalize_country_code("{de}"), "DE");
+        assert_eq!(normalize_country_code(" us "), "US");
+        assert_eq!(normalize_country_code("{??}"), UNKNOWN_COUNTRY);
+    }
+}
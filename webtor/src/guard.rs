@@ -0,0 +1,396 @@
+//! Entry guard selection and persistence
+//!
+//! Follows the shape of Tor's guard-spec, simplified for a client with few
+//! long-lived channels:
+//!
+//! - A small *sample* of guards is drawn (bandwidth-weighted) from the
+//!   candidates and kept across sessions via a [`StateStore`].
+//! - The first [`NUM_PRIMARY_GUARDS`] usable guards, confirmed ones first,
+//!   are *primary*; selection always prefers them.
+//! - Connection failures mark a guard unreachable until its retry delay has
+//!   passed. Guards that drop out of the candidate list are unlisted, and are
+//!   retired (removed from the sample) once unlisted or sampled for too long.
+//!
+//! When connecting through bridges the candidates are the configured bridges,
+//! as in guard-spec's bridge mode.
+
+use crate::error::Result;
+use crate::relay::Relay;
+use crate::storage::StateStore;
+use crate::time::system_time_now;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Number of guards kept in the sample
+pub const GUARD_SAMPLE_SIZE: usize = 20;
+
+/// Number of primary guards
+pub const NUM_PRIMARY_GUARDS: usize = 3;
+
+/// Seconds after which an unreachable primary guard is retried
+pub const PRIMARY_GUARD_RETRY_SECS: u64 = 10 * 60;
+
+/// Seconds after which an unreachable non-primary guard is retried
+pub const GUARD_RETRY_SECS: u64 = 60 * 60;
+
+/// Seconds a guard may stay unlisted before it is retired
+pub const GUARD_REMOVE_UNLISTED_AFTER_SECS: u64 = 20 * 24 * 60 * 60;
+
+/// Seconds a guard stays in the sample before it is retired and replaced
+pub const GUARD_LIFETIME_SECS: u64 = 120 * 24 * 60 * 60;
+
+/// Storage key for the persisted guard sample
+pub(crate) const STATE_KEY: &str = "guards";
+
+/// A relay or bridge that may be sampled as a guard
+#[derive(Debug, Clone)]
+pub struct GuardCandidate {
+    pub fingerprint: String,
+    pub nickname: String,
+    pub weight: u64,
+}
+
+impl GuardCandidate {
+    /// A configured bridge; bridges carry no consensus weight
+    pub fn bridge(fingerprint: impl Into<String>) -> Self {
+        Self {
+            fingerprint: fingerprint.into().to_uppercase(),
+            nickname: "bridge".to_string(),
+            weight: 0,
+        }
+    }
+}
+
+impl From<&Relay> for GuardCandidate {
+    fn from(relay: &Relay) -> Self {
+        Self {
+            fingerprint: relay.fingerprint.to_uppercase(),
+            nickname: relay.nickname.clone(),
+            weight: relay.consensus_weight as u64,
+        }
+    }
+}
+
+/// A sampled guard and its persisted state (times are Unix seconds)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuardEntry {
+    pub fingerprint: String,
+    pub nickname: String,
+    pub sampled_at: u64,
+    #[serde(default)]
+    pub confirmed_at: Option<u64>,
+    #[serde(default)]
+    pub unlisted_since: Option<u64>,
+    #[serde(default)]
+    pub unreachable_since: Option<u64>,
+    #[serde(default)]
+    pub last_tried: Option<u64>,
+}
+
+impl GuardEntry {
+    pub fn is_listed(&self) -> bool {
+        self.unlisted_since.is_none()
+    }
+
+    pub fn is_reachable(&self) -> bool {
+        self.unreachable_since.is_none()
+    }
+
+    /// Whether the guard may be tried now, given its retry delay
+    fn is_eligible(&self, now: u64, retry_secs: u64) -> bool {
+        if !self.is_listed() {
+            return false;
+        }
+        match (self.unreachable_since, self.last_tried) {
+            (None, _) => true,
+            (Some(_), Some(tried)) => now.saturating_sub(tried) >= retry_secs,
+            (Some(since), None) => now.saturating_sub(since) >= retry_secs,
+        }
+    }
+}
+
+/// The sampled guard set
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuardSet {
+    /// Sampled guards, in sample order
+    pub sampled: Vec<GuardEntry>,
+}
+
+impl GuardSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reconcile the sample with the current candidates and top it up
+    ///
+    /// Guards missing from `candidates` become unlisted, guards that come back
+    /// are relisted, long-unlisted or expired guards are retired, and new
+    /// guards are drawn (weighted by bandwidth) until the sample is full.
+    pub fn update_sample(&mut self, candidates: &[GuardCandidate], now: u64) {
+        for entry in &mut self.sampled {
+            let listed = candidates
+                .iter()
+                .any(|c| c.fingerprint.eq_ignore_ascii_case(&entry.fingerprint));
+            match (listed, entry.unlisted_since) {
+                (true, Some(_)) => entry.unlisted_since = None,
+                (false, None) => entry.unlisted_since = Some(now),
+                _ => {}
+            }
+        }
+
+        let before = self.sampled.len();
+        self.sampled.retain(|entry| {
+            let unlisted_too_long = entry
+                .unlisted_since
+                .is_some_and(|since| now.saturating_sub(since) >= GUARD_REMOVE_UNLISTED_AFTER_SECS);
+            let expired = now.saturating_sub(entry.sampled_at) >= GUARD_LIFETIME_SECS;
+            !(unlisted_too_long || expired)
+        });
+        let retired = before - self.sampled.len();
+        if retired > 0 {
+            info!("Retired {} guards from the sample", retired);
+        }
+
+        let mut pool: Vec<&GuardCandidate> = candidates
+            .iter()
+            .filter(|c| !self.contains(&c.fingerprint))
+            .collect();
+        let mut rng = rand::thread_rng();
+        while self.sampled.len() < GUARD_SAMPLE_SIZE && !pool.is_empty() {
+            // Bridges have no weight, so fall back to a uniform choice
+            let idx = {
+                let indices: Vec<usize> = (0..pool.len()).collect();
+                indices
+                    .choose_weighted(&mut rng, |&i| pool[i].weight)
+                    .ok()
+                    .or_else(|| indices.choose(&mut rng))
+                    .copied()
+                    .unwrap_or(0)
+            };
+            let candidate = pool.swap_remove(idx);
+            debug!("Sampled guard {}", candidate.nickname);
+            self.sampled.push(GuardEntry {
+                fingerprint: candidate.fingerprint.clone(),
+                nickname: candidate.nickname.clone(),
+                sampled_at: now,
+                confirmed_at: None,
+                unlisted_since: None,
+                unreachable_since: None,
+                last_tried: None,
+            });
+        }
+    }
+
+    pub fn contains(&self, fingerprint: &str) -> bool {
+        self.get(fingerprint).is_some()
+    }
+
+    pub fn get(&self, fingerprint: &str) -> Option<&GuardEntry> {
+        self.sampled
+            .iter()
+            .find(|e| e.fingerprint.eq_ignore_ascii_case(fingerprint))
+    }
+
+    fn get_mut(&mut self, fingerprint: &str) -> Option<&mut GuardEntry> {
+        self.sampled
+            .iter_mut()
+            .find(|e| e.fingerprint.eq_ignore_ascii_case(fingerprint))
+    }
+
+    /// Primary guards: listed guards, confirmed ones first (by confirmation time)
+    pub fn primary_guards(&self) -> Vec<&GuardEntry> {
+        let mut listed: Vec<&GuardEntry> = self.sampled.iter().filter(|e| e.is_listed()).collect();
+        // Stable sort keeps sample order among unconfirmed guards
+        listed.sort_by_key(|e| e.confirmed_at.unwrap_or(u64::MAX));
+        listed.truncate(NUM_PRIMARY_GUARDS);
+        listed
+    }
+
+    /// Pick the guard to use for the next connection
+    ///
+    /// Returns the first eligible primary guard, falling back to any other
+    /// eligible sampled guard, or `None` if every guard is waiting to be retried.
+    pub fn select(&self, now: u64) -> Option<&GuardEntry> {
+        let primary = self.primary_guards();
+        if let Some(guard) = primary
+            .iter()
+            .find(|e| e.is_eligible(now, PRIMARY_GUARD_RETRY_SECS))
+        {
+            return Some(guard);
+        }
+
+        self.sampled.iter().find(|e| {
+            !primary.iter().any(|p| p.fingerprint == e.fingerprint)
+                && e.is_eligible(now, GUARD_RETRY_SECS)
+        })
+    }
+
+    /// Record a successful connection; the guard becomes confirmed and reachable
+    pub fn record_success(&mut self, fingerprint: &str, now: u64) {
+        if let Some(entry) = self.get_mut(fingerprint) {
+            entry.last_tried = Some(now);
+            entry.unreachable_since = None;
+            entry.confirmed_at.get_or_insert(now);
+        }
+    }
+
+    /// Record a failed connection; the guard is unreachable until retried
+    pub fn record_failure(&mut self, fingerprint: &str, now: u64) {
+        if let Some(entry) = self.get_mut(fingerprint) {
+            entry.last_tried = Some(now);
+            entry.unreachable_since.get_or_insert(now);
+        }
+    }
+}
+
+/// Guard set bound to the store it is persisted in
+pub struct GuardManager {
+    guards: GuardSet,
+    store: Arc<dyn StateStore>,
+}
+
+impl GuardManager {
+    /// Load the persisted guard set, starting empty if none is stored or it is unreadable
+    pub fn load(store: Arc<dyn StateStore>) -> Self {
+        let guards = match store.load(STATE_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Discarding unreadable guard state: {}", e);
+                GuardSet::new()
+            }),
+            Ok(None) => GuardSet::new(),
+            Err(e) => {
+                warn!("Failed to load guard state: {}", e);
+                GuardSet::new()
+            }
+        };
+        debug!("Loaded {} sampled guards", guards.sampled.len());
+        Self { guards, store }
+    }
+
+    pub fn guards(&self) -> &GuardSet {
+        &self.guards
+    }
+
+    pub fn update_sample(&mut self, candidates: &[GuardCandidate]) {
+        self.guards.update_sample(candidates, now_secs());
+        self.persist();
+    }
+
+    pub fn select(&self) -> Option<&GuardEntry> {
+        self.guards.select(now_secs())
+    }
+
+    pub fn record_success(&mut self, fingerprint: &str) {
+        self.guards.record_success(fingerprint, now_secs());
+        self.persist();
+    }
+
+    pub fn record_failure(&mut self, fingerprint: &str) {
+        self.guards.record_failure(fingerprint, now_secs());
+        self.persist();
+    }
+
+    /// Write the guard set to the store
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(&self.guards)?;
+        self.store.store(STATE_KEY, &json)
+    }
+
+    fn persist(&self) {
+        // Losing guard state only costs some anonymity on the next session,
+        // so a failing store shouldn't fail the connection
+        if let Err(e) = self.save() {
+            warn!("Failed to persist guard state: {}", e);
+        }
+    }
+}
+
+pub(crate) fn now_secs() -> u64 {
+    system_time_now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+
+    fn candidates(n: usize) -> Vec<GuardCandidate> {
+        (0..n)
+            .map(|i| GuardCandidate {
+                fingerprint: format!("{:040X}", i),
+                nickname: format!("guard{}", i),
+                weight: 100,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sample_is_bounded_and_stable() {
+        let pool = candidates(50);
+        let mut set = GuardSet::new();
+        set.update_sample(&pool, 1_000);
+        assert_eq!(set.sampled.len(), GUARD_SAMPLE_SIZE);
+
+        let before = set.clone();
+        set.update_sample(&pool, 2_000);
+        assert_eq!(set, before);
+    }
+
+    #[test]
+    fn test_primary_guard_preferred_until_unreachable() {
+        let mut set = GuardSet::new();
+        set.update_sample(&candidates(5), 0);
+
+        let first = set.select(10).unwrap().fingerprint.clone();
+        set.record_success(&first, 10);
+        assert_eq!(set.primary_guards()[0].fingerprint, first);
+        assert_eq!(set.select(20).unwrap().fingerprint, first);
+
+        set.record_failure(&first, 30);
+        let fallback = set.select(40).unwrap().fingerprint.clone();
+        assert_ne!(fallback, first);
+
+        // Retried once the primary retry delay has passed
+        assert_eq!(
+            set.select(30 + PRIMARY_GUARD_RETRY_SECS)
+                .unwrap()
+                .fingerprint,
+            first
+        );
+    }
+
+    #[test]
+    fn test_unlisted_guards_are_skipped_then_retired() {
+        let pool = candidates(2);
+        let mut set = GuardSet::new();
+        set.update_sample(&pool, 0);
+
+        let gone = pool[0].fingerprint.clone();
+        set.update_sample(&pool[1..], 100);
+        assert!(!set.get(&gone).unwrap().is_listed());
+        assert_eq!(set.select(100).unwrap().fingerprint, pool[1].fingerprint);
+
+        set.update_sample(&pool[1..], 100 + GUARD_REMOVE_UNLISTED_AFTER_SECS);
+        assert!(!set.contains(&gone));
+    }
+
+    #[test]
+    fn test_guards_persist_across_sessions() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        let bridge = GuardCandidate::bridge("58da67bd879e9239fcd4a590e25118bb2118cb3c");
+
+        let mut manager = GuardManager::load(store.clone());
+        manager.update_sample(std::slice::from_ref(&bridge));
+        manager.record_failure(&bridge.fingerprint);
+
+        let reloaded = GuardManager::load(store);
+        let entry = reloaded.guards().get(&bridge.fingerprint).unwrap();
+        assert!(!entry.is_reachable());
+        assert_eq!(reloaded.guards(), manager.guards());
+    }
+}
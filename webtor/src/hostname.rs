@@ -0,0 +1,157 @@
+//! Hostname policy for internationalized domain names
+//!
+//! Exit relays resolve hostnames themselves and only understand ASCII, so a
+//! Unicode name like `bücher.example` has to be converted to its punycode
+//! form (`xn--bcher-kva.example`) before it is sent in a BEGIN cell, the
+//! `Host` header and TLS SNI. [`HostnamePolicy`] decides what happens to such
+//! names for both `fetch` and `connect`.
+
+use crate::error::{Result, TorError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// Signature of a custom hostname filter: return the name to use, or an error to refuse it
+pub type HostnameFilterFn = dyn Fn(&str) -> Result<String> + Send + Sync;
+
+#[derive(Clone)]
+pub struct HostnameFilter(pub Arc<HostnameFilterFn>);
+
+impl fmt::Debug for HostnameFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostnameFilter")
+    }
+}
+
+/// What to do with internationalized hostnames before they reach the exit
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum HostnamePolicy {
+    /// Convert Unicode names to punycode (UTS #46), rejecting invalid ones
+    #[default]
+    Convert,
+    /// Refuse internationalized names, whether given in Unicode or punycode form
+    Reject,
+    /// Send names unchanged
+    ///
+    /// URLs are already normalized to punycode when parsed, so this only
+    /// changes what `connect` sends.
+    PassThrough,
+    /// Hand every non-IP hostname to a caller-provided filter
+    #[serde(skip)]
+    Custom(HostnameFilter),
+}
+
+impl HostnamePolicy {
+    /// Use a custom filter for every hostname
+    pub fn custom<F>(filter: F) -> Self
+    where
+        F: Fn(&str) -> Result<String> + Send + Sync + 'static,
+    {
+        HostnamePolicy::Custom(HostnameFilter(Arc::new(filter)))
+    }
+
+    /// Apply the policy, returning the hostname to send to the exit
+    ///
+    /// IP address literals are always returned unchanged.
+    pub fn apply(&self, host: &str) -> Result<String> {
+        if is_ip_literal(host) {
+            return Ok(host.to_string());
+        }
+
+        match self {
+            HostnamePolicy::Convert => to_ascii(host),
+            HostnamePolicy::Reject => {
+                if is_internationalized(host) {
+                    Err(TorError::invalid_hostname(format!(
+                        "internationalized hostnames are not allowed: {}",
+                        host
+                    )))
+                } else {
+                    Ok(host.to_string())
+                }
+            }
+            HostnamePolicy::PassThrough => Ok(host.to_string()),
+            HostnamePolicy::Custom(filter) => (filter.0)(host),
+        }
+    }
+}
+
+/// Convert a hostname to its ASCII (punycode) form
+///
+/// ASCII names are returned as given so unusual but valid labels (such as
+/// `_service`) are not rewritten.
+pub fn to_ascii(host: &str) -> Result<String> {
+    if host.is_ascii() {
+        return Ok(host.to_string());
+    }
+    // Same forbidden characters as URL host parsing, so connect and fetch agree
+    idna::domain_to_ascii_cow(host.as_bytes(), idna::AsciiDenyList::URL)
+        .map(|ascii| ascii.into_owned())
+        .map_err(|e| TorError::invalid_hostname(format!("{}: {}", host, e)))
+}
+
+/// Whether `host` contains non-ASCII characters or punycode (`xn--`) labels
+pub fn is_internationalized(host: &str) -> bool {
+    !host.is_ascii()
+        || host
+            .split('.')
+            .any(|label| label.len() >= 4 && label[..4].eq_ignore_ascii_case("xn--"))
+}
+
+fn is_ip_literal(host: &str) -> bool {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<std::net::IpAddr>()
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let policy = HostnamePolicy::Convert;
+        assert_eq!(
+            policy.apply("bücher.example").unwrap(),
+            "xn--bcher-kva.example"
+        );
+        assert_eq!(
+            policy.apply("BÜCHER.example").unwrap(),
+            "xn--bcher-kva.example"
+        );
+        assert_eq!(
+            policy.apply("_srv.Example.com").unwrap(),
+            "_srv.Example.com"
+        );
+        assert_eq!(policy.apply("[2001:db8::1]").unwrap(), "[2001:db8::1]");
+        assert!(policy.apply("bü cher.example").is_err());
+        assert!(policy.apply("bü<cher.example").is_err());
+    }
+
+    #[test]
+    fn test_reject_and_pass_through() {
+        let reject = HostnamePolicy::Reject;
+        assert!(reject.apply("bücher.example").is_err());
+        let err = reject.apply("xn--bcher-kva.example").unwrap_err();
+        assert_eq!(err.code(), "INVALID_HOSTNAME");
+        assert_eq!(reject.apply("example.com").unwrap(), "example.com");
+
+        let pass = HostnamePolicy::PassThrough;
+        assert_eq!(pass.apply("bücher.example").unwrap(), "bücher.example");
+    }
+
+    #[test]
+    fn test_custom_filter() {
+        let policy = HostnamePolicy::custom(|host| {
+            if host.ends_with(".onion") {
+                Err(TorError::invalid_hostname("no onions"))
+            } else {
+                to_ascii(host)
+            }
+        });
+        assert_eq!(policy.apply("münchen.de").unwrap(), "xn--mnchen-3ya.de");
+        assert!(policy.apply("example.onion").is_err());
+        assert_eq!(policy.apply("127.0.0.1").unwrap(), "127.0.0.1");
+    }
+}
@@ -0,0 +1,269 @@
+//! Onion service descriptor cache
+//!
+//! A descriptor stays good for hours (its `descriptor-lifetime`, bounded by
+//! the certificates it carries), so the client keeps each one it fetches
+//! and only asks the HSDirs again once it has expired, or once connecting
+//! with it has failed and the service may have moved to new introduction
+//! points. Entries are keyed by blinded key, which changes every time
+//! period, so a descriptor never outlives the period it was published for.
+//!
+//! The documents are kept as served and persisted through a
+//! [`StateStore`]; they are checked and decrypted again when used, so
+//! nothing read back from the store is trusted as is. A descriptor with a
+//! lower revision counter never replaces the one cached, as an HSDir that
+//! missed the last upload would otherwise roll the service back.
+
+use crate::error::{Result, TorError};
+use crate::storage::StateStore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tor_checkable::Timebound;
+use tor_hscrypto::pk::HsBlindId;
+use tor_hscrypto::Subcredential;
+use tor_netdoc::doc::hsdesc::HsDesc;
+use tracing::{debug, warn};
+
+/// Storage key for the persisted descriptors
+const STATE_KEY: &str = "hs_descriptors";
+
+/// Descriptors kept at once; the soonest to expire goes first
+pub const MAX_CACHED_DESCRIPTORS: usize = 32;
+
+/// A descriptor as served by an HSDir (times are Unix seconds)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedDescriptor {
+    text: String,
+    revision_counter: u64,
+    expires_at: u64,
+}
+
+/// Fetched descriptors, bound to the store they are persisted in
+pub struct HsDescCache {
+    /// By hex-encoded blinded key
+    entries: HashMap<String, CachedDescriptor>,
+    store: Arc<dyn StateStore>,
+}
+
+impl HsDescCache {
+    /// Load the persisted descriptors, starting empty if none are stored or
+    /// they are unreadable
+    pub fn load(store: Arc<dyn StateStore>) -> Self {
+        let entries = match store.load(STATE_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Discarding unreadable descriptor cache: {}", e);
+                HashMap::new()
+            }),
+            Ok(None) => HashMap::new(),
+            Err(e) => {
+                warn!("Failed to load descriptor cache: {}", e);
+                HashMap::new()
+            }
+        };
+        debug!("Loaded {} cached onion service descriptors", entries.len());
+        Self { entries, store }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The cached descriptor for `blinded_id`, if it is still valid at `now`
+    pub fn get(
+        &self,
+        blinded_id: &HsBlindId,
+        subcredential: &Subcredential,
+        now: SystemTime,
+    ) -> Option<HsDesc> {
+        let entry = self.entries.get(&key(blinded_id))?;
+        if unix_secs(now) >= entry.expires_at {
+            return None;
+        }
+        HsDesc::parse_decrypt_validate(&entry.text, blinded_id, now, subcredential, None)
+            .ok()?
+            .check_valid_at(&now)
+            .ok()
+    }
+
+    /// Cache `text`, a descriptor for `blinded_id` that has been checked
+    ///
+    /// It is kept for its lifetime from `now`, or until `valid_until` if
+    /// that is sooner. Returns whether it was cached: one older than the
+    /// unexpired descriptor already held is not.
+    pub fn insert(
+        &mut self,
+        blinded_id: &HsBlindId,
+        text: &str,
+        valid_until: Option<SystemTime>,
+        now: SystemTime,
+    ) -> Result<bool> {
+        let field = |keyword: &str| {
+            outer_field(text, keyword).ok_or_else(|| {
+                TorError::HsDescriptor(format!("Descriptor has no valid {}", keyword))
+            })
+        };
+        let revision_counter = field("revision-counter")?;
+        let lifetime = Duration::from_secs(60 * field("descriptor-lifetime")?);
+        let now_secs = unix_secs(now);
+        let expires_at = valid_until
+            .map(unix_secs)
+            .unwrap_or(u64::MAX)
+            .min(unix_secs(now + lifetime));
+
+        self.entries.retain(|_, entry| now_secs < entry.expires_at);
+        let key = key(blinded_id);
+        if let Some(cached) = self.entries.get(&key) {
+            if cached.revision_counter > revision_counter {
+                warn!(
+                    "Ignoring descriptor with revision {} older than the cached {}",
+                    revision_counter, cached.revision_counter
+                );
+                return Ok(false);
+            }
+        }
+        self.entries.insert(
+            key,
+            CachedDescriptor {
+                text: text.to_string(),
+                revision_counter,
+                expires_at,
+            },
+        );
+        while self.entries.len() > MAX_CACHED_DESCRIPTORS {
+            let soonest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                self.entries.remove(&soonest);
+            }
+        }
+        self.persist();
+        Ok(true)
+    }
+
+    /// Drop the descriptor for `blinded_id`, so the next connection fetches
+    /// it again; for after connecting with it failed
+    pub fn invalidate(&mut self, blinded_id: &HsBlindId) {
+        if self.entries.remove(&key(blinded_id)).is_some() {
+            debug!("Dropped cached descriptor {}", key(blinded_id));
+            self.persist();
+        }
+    }
+
+    /// Drop the descriptors that have expired by `now`, returning how many
+    pub fn purge_expired(&mut self, now: SystemTime) -> usize {
+        let now_secs = unix_secs(now);
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| now_secs < entry.expires_at);
+        let purged = before - self.entries.len();
+        if purged > 0 {
+            self.persist();
+        }
+        purged
+    }
+
+    /// Drop every descriptor
+    pub fn clear(&mut self) {
+        if !self.entries.is_empty() {
+            self.entries.clear();
+            self.persist();
+        }
+    }
+
+    /// Write the descriptors to the store
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(&self.entries)?;
+        self.store.store(STATE_KEY, &json)
+    }
+
+    fn persist(&self) {
+        // A lost descriptor is simply fetched again
+        if let Err(e) = self.save() {
+            warn!("Failed to persist descriptor cache: {}", e);
+        }
+    }
+}
+
+fn key(blinded_id: &HsBlindId) -> String {
+    hex::encode(blinded_id.as_ref())
+}
+
+fn unix_secs(when: SystemTime) -> u64 {
+    when.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The numeric value of a keyword in the plaintext outer document
+fn outer_field(text: &str, keyword: &str) -> Option<u64> {
+    text.lines()
+        .find_map(|line| line.strip_prefix(keyword)?.strip_prefix(' '))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+
+    /// A descriptor from arti's test data, with the keys to decrypt it
+    const DESCRIPTOR: &str =
+        include_str!("../../vendor/arti/crates/tor-netdoc/testdata/hsdesc1.txt");
+    const BLINDED_ID: &str = "43cc0d62fc6252f578705ca645a46109e265290343b1137e90189744b20b3f2d";
+    const SUBCREDENTIAL: &str = "78210a0d2c72bb7a0caf606bcd938b9a3696894fdddbc3b87d424753a7e3df37";
+
+    fn keys() -> (HsBlindId, Subcredential) {
+        let blinded: [u8; 32] = hex::decode(BLINDED_ID).unwrap().try_into().unwrap();
+        let subcredential: [u8; 32] = hex::decode(SUBCREDENTIAL).unwrap().try_into().unwrap();
+        (blinded.into(), subcredential.into())
+    }
+
+    #[test]
+    fn test_cache_lifetime_and_revisions() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        let (blinded_id, subcredential) = keys();
+        let fetched_at = UNIX_EPOCH + Duration::from_secs(1_674_486_000);
+        let mut cache = HsDescCache::load(store.clone());
+        assert!(cache.get(&blinded_id, &subcredential, fetched_at).is_none());
+        assert!(cache
+            .insert(&blinded_id, DESCRIPTOR, None, fetched_at)
+            .unwrap());
+
+        // Kept across sessions, and only for its 180 minute lifetime
+        let cache = HsDescCache::load(store.clone());
+        let later = fetched_at + Duration::from_secs(179 * 60);
+        let descriptor = cache.get(&blinded_id, &subcredential, later).unwrap();
+        assert!(!descriptor.intro_points().is_empty());
+        let expired = fetched_at + Duration::from_secs(180 * 60);
+        assert!(cache.get(&blinded_id, &subcredential, expired).is_none());
+
+        // An older revision doesn't replace it; a newer one would
+        let mut cache = cache;
+        let older = DESCRIPTOR.replace("revision-counter 19655750", "revision-counter 1");
+        assert!(!cache.insert(&blinded_id, &older, None, later).unwrap());
+        assert!(cache.get(&blinded_id, &subcredential, later).is_some());
+        let garbled = DESCRIPTOR.replace("revision-counter 19655750", "revision-counter x");
+        assert!(cache.insert(&blinded_id, &garbled, None, later).is_err());
+
+        // A failed connection drops it for good
+        cache.invalidate(&blinded_id);
+        assert!(cache.is_empty());
+        assert!(HsDescCache::load(store.clone()).is_empty());
+
+        // Maintenance drops it once it has expired
+        cache.insert(&blinded_id, DESCRIPTOR, None, later).unwrap();
+        assert_eq!(cache.purge_expired(later), 0);
+        assert_eq!(
+            cache.purge_expired(later + Duration::from_secs(180 * 60)),
+            1
+        );
+        assert!(HsDescCache::load(store).is_empty());
+    }
+}
@@ -0,0 +1,659 @@
+//! HSDir hash ring and onion service descriptor fetching
+//!
+//! An onion service publishes its descriptor to a handful of relays with the
+//! HSDir flag, picked by hashing (rend-spec-v3 §2.2). Each such relay has a
+//! place on a ring given by its ed25519 identity and the network's shared
+//! random value for the time period; the descriptor is stored at the places
+//! given by the service's blinded key for that period. A client computes the
+//! same ring from its consensus, asks those HSDirs for the descriptor over a
+//! circuit ending at each, and decrypts it with keys derived from the onion
+//! address. Fetched descriptors are kept in the circuit manager's
+//! [`HsDescCache`](crate::hs_cache::HsDescCache) until they expire. A
+//! service hosted here uploads its own descriptors the same way, to the
+//! wider set of HSDirs a service stores at.
+
+use crate::circuit::{Circuit, CircuitManager, CircuitPurpose};
+use crate::error::{Result, TorError};
+use crate::relay::{flags, Relay};
+use crate::time::system_time_now;
+use base64::Engine;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::ops::Range;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tor_checkable::Timebound;
+use tor_hscrypto::pk::{HsBlindId, HsId, HsIdKey};
+use tor_hscrypto::time::TimePeriod;
+use tor_hscrypto::Subcredential;
+use tor_netdoc::doc::hsdesc::HsDesc;
+use tor_netdoc::doc::netstatus::MdConsensus;
+use tracing::{debug, info, warn};
+
+/// Largest descriptor an HSDir serves (`HSV3MaxDescriptorSize`)
+const MAX_DESCRIPTOR_SIZE: usize = 50_000;
+
+/// Time periods start this many voting periods after midnight UTC, when
+/// the shared random value isn't about to change
+const VOTING_PERIODS_IN_OFFSET: u32 = 12;
+
+/// Voting periods one shared random value is the latest for, if the
+/// consensus doesn't say
+const VOTING_PERIODS_IN_SRV_ROUND: u32 = 24;
+
+const ONE_DAY: Duration = Duration::from_secs(86_400);
+
+/// What the HSDirs are wanted for: a client asks fewer of them than a
+/// service publishes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HsDirOp {
+    Download,
+    Upload,
+}
+
+/// What a consensus says about the HSDir ring
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HsDirParams {
+    period_length: Duration,
+    period_offset: Duration,
+    /// Shared random values, each with the time it is the latest over
+    shared_rand: Vec<([u8; 32], Range<SystemTime>)>,
+    n_replicas: u8,
+    spread_fetch: usize,
+    spread_store: usize,
+}
+
+impl HsDirParams {
+    pub fn from_consensus(consensus: &MdConsensus) -> Self {
+        let param = |name: &str, default: i32, range: std::ops::RangeInclusive<i32>| {
+            consensus
+                .params()
+                .get(name)
+                .copied()
+                .filter(|value| range.contains(value))
+                .unwrap_or(default)
+        };
+        let lifetime = consensus.lifetime();
+        let valid_after = lifetime.valid_after();
+        let voting_period = lifetime.voting_period();
+
+        // Without timestamps (proposal 342) the current value took over at
+        // the start of the consensus's UTC day, the previous one a day before
+        let cur = consensus.shared_rand_cur();
+        let prev = consensus.shared_rand_prev();
+        let srv_interval = match (
+            cur.and_then(|s| s.timestamp()),
+            prev.and_then(|s| s.timestamp()),
+        ) {
+            (Some(cur), Some(prev)) => cur.duration_since(prev).ok(),
+            _ => None,
+        }
+        .unwrap_or(voting_period * VOTING_PERIODS_IN_SRV_ROUND);
+        let today = start_of_day(valid_after);
+        let mut shared_rand = Vec::new();
+        for (srv, default_start) in [(cur, Some(today)), (prev, today.checked_sub(ONE_DAY))] {
+            if let Some(srv) = srv {
+                if let Some(start) = srv.timestamp().or(default_start) {
+                    shared_rand.push(((*srv.value()).into(), start..start + srv_interval));
+                }
+            }
+        }
+
+        Self {
+            period_length: Duration::from_secs(
+                60 * param("hsdir-interval", 1440, 30..=14400) as u64,
+            ),
+            period_offset: voting_period * VOTING_PERIODS_IN_OFFSET,
+            shared_rand,
+            n_replicas: param("hsdir_n_replicas", 2, 1..=16) as u8,
+            spread_fetch: param("hsdir_spread_fetch", 3, 1..=128) as usize,
+            spread_store: param("hsdir_spread_store", 4, 1..=128) as usize,
+        }
+    }
+
+    /// The time period `when` falls in
+    pub fn time_period(&self, when: SystemTime) -> Result<TimePeriod> {
+        TimePeriod::new(self.period_length, when, self.period_offset)
+            .map_err(|e| TorError::Internal(format!("No time period for {:?}: {}", when, e)))
+    }
+
+    /// The shared random value of `period`: the latest one at its start, or
+    /// the disaster value if the consensus has none that old
+    fn shared_rand_for(&self, period: TimePeriod) -> [u8; 32] {
+        let start = period.range().ok().map(|range| range.start);
+        self.shared_rand
+            .iter()
+            .find(|(_, lifespan)| start.is_some_and(|start| lifespan.contains(&start)))
+            .map(|(srv, _)| *srv)
+            .unwrap_or_else(|| {
+                warn!(
+                    "No shared random value for time period {}; using the disaster value",
+                    period.interval_num()
+                );
+                Sha3_256::new()
+                    .chain_update(b"shared-random-disaster")
+                    .chain_update(u64::from(period.length().as_minutes()).to_be_bytes())
+                    .chain_update(period.interval_num().to_be_bytes())
+                    .finalize()
+                    .into()
+            })
+    }
+}
+
+/// The HSDirs of one time period, in ring order
+#[derive(Debug, Clone)]
+pub struct HsDirRing {
+    period: TimePeriod,
+    n_replicas: u8,
+    spread_fetch: usize,
+    spread_store: usize,
+    ring: Vec<([u8; 32], Relay)>,
+}
+
+impl HsDirRing {
+    /// Place the relays with the HSDir flag and an ed25519 identity on the
+    /// ring of `period`
+    pub fn new(params: &HsDirParams, relays: &[Relay], period: TimePeriod) -> Self {
+        let shared_rand = params.shared_rand_for(period);
+        let mut ring: Vec<([u8; 32], Relay)> = relays
+            .iter()
+            .filter(|relay| relay.flags.contains(flags::HSDIR))
+            .filter_map(|relay| {
+                let identity = hex::decode(relay.ed25519_identity.as_ref()?).ok()?;
+                let index = Sha3_256::new()
+                    .chain_update(b"node-idx")
+                    .chain_update(&identity)
+                    .chain_update(shared_rand)
+                    .chain_update(period.interval_num().to_be_bytes())
+                    .chain_update(u64::from(period.length().as_minutes()).to_be_bytes())
+                    .finalize();
+                Some((index.into(), relay.clone()))
+            })
+            .collect();
+        ring.sort_by(|a, b| a.0.cmp(&b.0));
+        Self {
+            period,
+            n_replicas: params.n_replicas,
+            spread_fetch: params.spread_fetch,
+            spread_store: params.spread_store,
+            ring,
+        }
+    }
+
+    pub fn time_period(&self) -> TimePeriod {
+        self.period
+    }
+
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+
+    /// The HSDirs responsible for `blinded_id`
+    ///
+    /// For each replica, the first `spread` relays at or after its place on
+    /// the ring that an earlier replica didn't already pick.
+    pub fn hsdirs(&self, blinded_id: &HsBlindId, op: HsDirOp) -> Vec<&Relay> {
+        let spread = match op {
+            HsDirOp::Download => self.spread_fetch,
+            HsDirOp::Upload => self.spread_store,
+        };
+        let mut picked: Vec<usize> = Vec::new();
+        for replica in 1..=u64::from(self.n_replicas) {
+            let index: [u8; 32] = Sha3_256::new()
+                .chain_update(b"store-at-idx")
+                .chain_update(blinded_id.as_ref())
+                .chain_update(replica.to_be_bytes())
+                .chain_update(u64::from(self.period.length().as_minutes()).to_be_bytes())
+                .chain_update(self.period.interval_num().to_be_bytes())
+                .finalize()
+                .into();
+            let start = self.ring.partition_point(|(position, _)| *position < index);
+            let new: Vec<usize> = (0..self.ring.len())
+                .map(|offset| (start + offset) % self.ring.len())
+                .filter(|i| !picked.contains(i))
+                .take(spread)
+                .collect();
+            picked.extend(new);
+        }
+        picked.into_iter().map(|i| &self.ring[i].1).collect()
+    }
+}
+
+/// The blinded key and subcredential of the service `hsid` in `period`
+pub fn blind(hsid: HsId, period: TimePeriod) -> Result<(HsBlindId, Subcredential)> {
+    let key = HsIdKey::try_from(hsid)
+        .map_err(|_| TorError::invalid_hostname("onion address is not a valid ed25519 key"))?;
+    let (blinded, subcredential) = key
+        .compute_blinded_key(period)
+        .map_err(|e| TorError::Internal(format!("Failed to blind onion service key: {}", e)))?;
+    Ok((blinded.id(), subcredential))
+}
+
+/// Where the current descriptor of a service is stored and how to open it
+struct DescriptorLocation {
+    blinded_id: HsBlindId,
+    subcredential: Subcredential,
+    hsdirs: Vec<Relay>,
+}
+
+async fn locate(
+    circuit_manager: &CircuitManager,
+    hsid: HsId,
+    now: SystemTime,
+) -> Result<DescriptorLocation> {
+    let relay_manager = circuit_manager.relay_manager().read().await;
+    let params = relay_manager.hsdir_params.as_ref().ok_or_else(|| {
+        TorError::HsDescriptor("No consensus with HSDir parameters yet".to_string())
+    })?;
+    let period = params.time_period(now)?;
+    let (blinded_id, subcredential) = blind(hsid, period)?;
+    let hsdirs = HsDirRing::new(params, &relay_manager.relays, period)
+        .hsdirs(&blinded_id, HsDirOp::Download)
+        .into_iter()
+        .cloned()
+        .collect();
+    Ok(DescriptorLocation {
+        blinded_id,
+        subcredential,
+        hsdirs,
+    })
+}
+
+/// A service's current descriptor, with the subcredential that
+/// introductions to the service use
+pub struct ServiceDescriptor {
+    pub descriptor: HsDesc,
+    /// The service's blinded key in the descriptor's time period
+    pub blinded_id: HsBlindId,
+    pub subcredential: Subcredential,
+    /// Taken from the cache rather than fetched
+    pub cached: bool,
+}
+
+/// The current descriptor of the service `hsid`, the cached one while it
+/// is valid or else a freshly fetched one
+pub async fn descriptor(circuit_manager: &CircuitManager, hsid: HsId) -> Result<ServiceDescriptor> {
+    let now = system_time_now();
+    let location = locate(circuit_manager, hsid, now).await?;
+    let subcredential = location.subcredential;
+    let blinded_id = location.blinded_id;
+    let cached = circuit_manager.hs_descriptors().read().await.get(
+        &location.blinded_id,
+        &subcredential,
+        now,
+    );
+    if let Some(descriptor) = cached {
+        debug!("Using cached onion service descriptor");
+        return Ok(ServiceDescriptor {
+            descriptor,
+            blinded_id,
+            subcredential,
+            cached: true,
+        });
+    }
+    let descriptor = fetch_from(circuit_manager, location, now).await?;
+    Ok(ServiceDescriptor {
+        descriptor,
+        blinded_id,
+        subcredential,
+        cached: false,
+    })
+}
+
+/// Forget the cached descriptor of `hsid` after connecting with it failed,
+/// so the next attempt fetches it again
+pub async fn forget_descriptor(circuit_manager: &CircuitManager, hsid: HsId) -> Result<()> {
+    let location = locate(circuit_manager, hsid, system_time_now()).await?;
+    circuit_manager
+        .hs_descriptors()
+        .write()
+        .await
+        .invalidate(&location.blinded_id);
+    Ok(())
+}
+
+/// Fetch, check and decrypt the current descriptor of the service `hsid`,
+/// and cache it
+///
+/// The HSDirs responsible for it are tried in random order, each over a
+/// fresh circuit ending at it, until one serves a descriptor that verifies.
+pub async fn fetch_descriptor(circuit_manager: &CircuitManager, hsid: HsId) -> Result<HsDesc> {
+    let now = system_time_now();
+    let location = locate(circuit_manager, hsid, now).await?;
+    fetch_from(circuit_manager, location, now).await
+}
+
+async fn fetch_from(
+    circuit_manager: &CircuitManager,
+    location: DescriptorLocation,
+    now: SystemTime,
+) -> Result<HsDesc> {
+    let DescriptorLocation {
+        blinded_id,
+        subcredential,
+        mut hsdirs,
+    } = location;
+    if hsdirs.is_empty() {
+        return Err(TorError::HsDescriptor(
+            "The consensus lists no HSDirs".to_string(),
+        ));
+    }
+    hsdirs.shuffle(&mut rand::thread_rng());
+
+    let mut last_error = None;
+    for hsdir in &hsdirs {
+        debug!("Fetching onion service descriptor from {}", hsdir.nickname);
+        let result = async {
+            let circuit = circuit_manager
+                .get_circuit_to(hsdir, CircuitPurpose::HsDir)
+                .await?;
+            let text = download(&*circuit.read().await, &blinded_id).await;
+            circuit_manager
+                .close_circuit(&circuit, "descriptor fetched")
+                .await;
+            let text = text?;
+            let descriptor =
+                HsDesc::parse_decrypt_validate(&text, &blinded_id, now, &subcredential, None)
+                    .map_err(|e| TorError::HsDescriptor(format!("Invalid descriptor: {}", e)))?;
+            let (_, valid_until) = descriptor.bounds();
+            let descriptor = descriptor
+                .check_valid_at(&now)
+                .map_err(|e| TorError::HsDescriptor(format!("Descriptor is not timely: {}", e)))?;
+            circuit_manager.hs_descriptors().write().await.insert(
+                &blinded_id,
+                &text,
+                valid_until,
+                now,
+            )?;
+            Ok(descriptor)
+        }
+        .await;
+        match result {
+            Ok(descriptor) => {
+                info!(
+                    "Fetched onion service descriptor from {} ({} introduction points)",
+                    hsdir.nickname,
+                    descriptor.intro_points().len()
+                );
+                return Ok(descriptor);
+            }
+            Err(e) => {
+                warn!("HSDir {} gave no descriptor: {}", hsdir.nickname, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| TorError::HsDescriptor("No HSDir answered".to_string())))
+}
+
+/// Upload the signed descriptor `text` for `blinded_id` in `period` to
+/// every HSDir responsible for it
+///
+/// Each HSDir gets its own circuit. Returns how many accepted the
+/// descriptor; it is an error if none did.
+pub async fn publish_descriptor(
+    circuit_manager: &CircuitManager,
+    blinded_id: &HsBlindId,
+    period: TimePeriod,
+    text: &str,
+) -> Result<usize> {
+    let hsdirs: Vec<Relay> = {
+        let relay_manager = circuit_manager.relay_manager().read().await;
+        let params = relay_manager.hsdir_params.as_ref().ok_or_else(|| {
+            TorError::HsDescriptor("No consensus with HSDir parameters yet".to_string())
+        })?;
+        HsDirRing::new(params, &relay_manager.relays, period)
+            .hsdirs(blinded_id, HsDirOp::Upload)
+            .into_iter()
+            .cloned()
+            .collect()
+    };
+
+    let mut accepted = 0;
+    let mut last_error = None;
+    for hsdir in &hsdirs {
+        let result = async {
+            let circuit = circuit_manager
+                .get_circuit_to(hsdir, CircuitPurpose::HsDir)
+                .await?;
+            let result = upload(&*circuit.read().await, text).await;
+            circuit_manager
+                .close_circuit(&circuit, "descriptor published")
+                .await;
+            result
+        }
+        .await;
+        match result {
+            Ok(()) => accepted += 1,
+            Err(e) => {
+                warn!("HSDir {} refused descriptor: {}", hsdir.nickname, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    if accepted == 0 {
+        return Err(last_error.unwrap_or_else(|| {
+            TorError::HsDescriptor("The consensus lists no HSDirs".to_string())
+        }));
+    }
+    info!(
+        "Published onion service descriptor to {} of {} HSDirs",
+        accepted,
+        hsdirs.len()
+    );
+    Ok(accepted)
+}
+
+/// POST `text` to the last hop of `circuit`
+async fn upload(circuit: &Circuit, text: &str) -> Result<()> {
+    let mut stream = circuit.begin_dir_stream().await?;
+    let request = format!(
+        "POST /tor/hs/3/publish HTTP/1.0\r\nContent-Length: {}\r\n\r\n{}",
+        text.len(),
+        text
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to write descriptor: {}", e)))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to flush descriptor: {}", e)))?;
+
+    // Only the status line matters
+    let mut response = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !response.windows(2).any(|w| w == b"\r\n") && response.len() < 8192 {
+        let n = stream
+            .read(&mut buf)
+            .await
+            .map_err(|e| TorError::Network(format!("Failed to read HSDir response: {}", e)))?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+    match status(&response) {
+        Some(200) => Ok(()),
+        _ => Err(TorError::HsDescriptor(format!(
+            "HSDir answered {}",
+            String::from_utf8_lossy(&response)
+                .lines()
+                .next()
+                .unwrap_or_default()
+        ))),
+    }
+}
+
+/// Download the descriptor stored under `blinded_id` from the last hop of
+/// `circuit`
+async fn download(circuit: &Circuit, blinded_id: &HsBlindId) -> Result<String> {
+    let mut stream = circuit.begin_dir_stream().await?;
+    let request = format!(
+        "GET /tor/hs/3/{} HTTP/1.0\r\n\r\n",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(blinded_id.as_ref())
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to write descriptor request: {}", e)))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to flush descriptor request: {}", e)))?;
+
+    // Headers and body together, with room for the headers
+    let limit = MAX_DESCRIPTOR_SIZE + 8192;
+    let mut response = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream
+            .read(&mut buf)
+            .await
+            .map_err(|e| TorError::Network(format!("Failed to read descriptor: {}", e)))?;
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..n]);
+        if response.len() > limit {
+            return Err(TorError::HsDescriptor(format!(
+                "Descriptor is larger than {} bytes",
+                MAX_DESCRIPTOR_SIZE
+            )));
+        }
+    }
+    descriptor_body(&response)
+}
+
+/// The descriptor in an HSDir's HTTP response
+fn descriptor_body(response: &[u8]) -> Result<String> {
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| TorError::HsDescriptor("Truncated HSDir response".to_string()))?;
+    let status_line = String::from_utf8_lossy(&response[..header_end]);
+    match status(response) {
+        Some(200) => String::from_utf8(response[header_end + 4..].to_vec())
+            .map_err(|_| TorError::HsDescriptor("Descriptor is not UTF-8".to_string())),
+        Some(404) => Err(TorError::HsDescriptor(
+            "HSDir has no descriptor for this service".to_string(),
+        )),
+        _ => Err(TorError::HsDescriptor(format!(
+            "HSDir answered {}",
+            status_line.lines().next().unwrap_or_default()
+        ))),
+    }
+}
+
+/// The status code of an HTTP response
+fn status(response: &[u8]) -> Option<u16> {
+    String::from_utf8_lossy(response)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+}
+
+/// Midnight UTC before `when`
+fn start_of_day(when: SystemTime) -> SystemTime {
+    let secs = when
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(secs - secs % ONE_DAY.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::io::Read;
+
+    fn hsdir(n: u8) -> Relay {
+        let mut relay = Relay::new(
+            format!("{:040X}", n),
+            format!("hsdir{}", n),
+            format!("10.{}.0.1", n),
+            9001,
+            [flags::HSDIR.to_string()].into_iter().collect(),
+            hex::encode([n; 32]),
+        );
+        relay.ed25519_identity = Some(hex::encode([n; 32]));
+        relay
+    }
+
+    #[test]
+    fn test_consensus_params() {
+        let path = format!("{}/src/cached/consensus.txt.br", env!("CARGO_MANIFEST_DIR"));
+        let compressed = std::fs::read(path).unwrap();
+        let mut body = String::new();
+        brotli::Decompressor::new(&compressed[..], 4096)
+            .read_to_string(&mut body)
+            .unwrap();
+        let (_, _, unvalidated) = MdConsensus::parse(&body).unwrap();
+        let consensus = unvalidated.dangerously_assume_timely().consensus;
+        let params = HsDirParams::from_consensus(&consensus);
+        assert_eq!(params.shared_rand.len(), 2);
+        assert_eq!((params.n_replicas, params.spread_fetch), (2, 3));
+
+        // The period around the consensus uses one of its values
+        let period = params
+            .time_period(consensus.lifetime().valid_after())
+            .unwrap();
+        let srv = params.shared_rand_for(period);
+        assert!(params.shared_rand.iter().any(|(value, _)| *value == srv));
+        // One long gone falls back to the disaster value
+        let old = params.time_period(UNIX_EPOCH + ONE_DAY * 10).unwrap();
+        assert!(params
+            .shared_rand
+            .iter()
+            .all(|(value, _)| *value != params.shared_rand_for(old)));
+    }
+
+    #[test]
+    fn test_ring_selection() {
+        let params = HsDirParams {
+            period_length: ONE_DAY,
+            period_offset: Duration::from_secs(12 * 3600),
+            shared_rand: Vec::new(),
+            n_replicas: 2,
+            spread_fetch: 3,
+            spread_store: 4,
+        };
+        let mut relays: Vec<Relay> = (1..=20).map(hsdir).collect();
+        relays[0].flags.clear();
+        relays[1].ed25519_identity = None;
+        let period = params.time_period(UNIX_EPOCH + ONE_DAY * 20_000).unwrap();
+        let ring = HsDirRing::new(&params, &relays, period);
+        assert_eq!(ring.len(), 18);
+
+        let blinded_id = HsBlindId::from([7; 32]);
+        let download = ring.hsdirs(&blinded_id, HsDirOp::Download);
+        let upload = ring.hsdirs(&blinded_id, HsDirOp::Upload);
+        assert_eq!((download.len(), upload.len()), (6, 8));
+        let distinct: HashSet<&str> = upload.iter().map(|r| r.nickname.as_str()).collect();
+        assert_eq!(distinct.len(), 8);
+        // A client asks the first of the HSDirs the service published to
+        assert!(download
+            .iter()
+            .all(|r| distinct.contains(r.nickname.as_str())));
+
+        // A ring smaller than the spread yields each relay once
+        let small = HsDirRing::new(&params, &relays[2..4], period);
+        assert_eq!(small.hsdirs(&blinded_id, HsDirOp::Upload).len(), 2);
+
+        assert!(descriptor_body(b"HTTP/1.0 404 Not found\r\n\r\n").is_err());
+        assert_eq!(
+            descriptor_body(
+                b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nhs-descriptor 3\n"
+            )
+            .unwrap(),
+            "hs-descriptor 3\n"
+        );
+    }
+}
@@ -1,18 +1,44 @@
 //! HTTP client for making requests through Tor circuits
 
-use crate::circuit::CircuitManager;
-use crate::config::{CIRCUIT_PREBUILD_AGE_THRESHOLD_MS, MAX_CIRCUITS};
-use crate::error::{Result, TorError};
-use crate::isolation::{IsolationKey, StreamIsolationPolicy};
+use crate::circuit::{Circuit, CircuitManager, CircuitStatus};
+use crate::config::{
+    CIRCUIT_PREBUILD_AGE_THRESHOLD_MS, DEFAULT_MAX_RESPONSE_SIZE, DEFAULT_STREAM_RETRIES,
+    MAX_CIRCUITS,
+};
+use crate::cookies::CookieJar;
+use crate::ech::EchConfigs;
+use crate::error::{is_connection_reset, Result, StreamEndReason, TimeoutPhase, TorError};
+use crate::events::{CircuitEvent, CircuitEvents};
+use crate::hostname::HostnamePolicy;
+use crate::isolation::{IsolationDomain, IsolationKey, IsolationToken, StreamIsolationPolicy};
+use crate::metrics::Metrics;
+use crate::multipart::Multipart;
+use crate::onion;
+use crate::onion_connector::OnionConnector;
+#[cfg(target_arch = "wasm32")]
+use crate::pinning::check_chain;
+use crate::pinning::CertPin;
+use crate::profile::RequestProfile;
+use crate::range::ContentRange;
+use crate::redirect::RedirectPolicy;
+use crate::retry::{with_cancellation, CancellationToken};
+use crate::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use crate::tls::subtle_error;
 #[cfg(not(target_arch = "wasm32"))]
-use crate::tls::wrap_with_tls;
+use crate::tls::wrap_with_tls_pinned;
+use crate::tls::{TlsRoots, TlsSessionCache, TlsSessions};
+use crate::traffic::{TorStream, TrafficCounter};
 use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use http::Method;
+use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Version};
 use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
-use tracing::{debug, info, warn};
+use tracing::{debug, info, info_span, warn, Instrument};
 use url::Url;
 
 /// HTTP request configuration
@@ -22,7 +48,33 @@ pub struct HttpRequest {
     pub method: Method,
     pub headers: HashMap<String, String>,
     pub body: Option<Vec<u8>>,
+    /// Limit on the whole request, redirects included
     pub timeout: Duration,
+    /// Limit on getting a circuit, opening the stream and the TLS handshake
+    pub connect_timeout: Option<Duration>,
+    /// Limit on waiting for the first response byte once the request is sent
+    pub first_byte_timeout: Option<Duration>,
+    /// ID attached to logs, spans, events, errors and the response for this
+    /// request
+    pub request_id: String,
+    /// Caller tag; requests with different tokens never share a circuit
+    pub isolation_token: Option<IsolationToken>,
+    /// Overrides the client's redirect policy for this request
+    pub redirect_policy: Option<RedirectPolicy>,
+    /// Overrides whether the client decodes compressed response bodies
+    pub decompress: Option<bool>,
+    /// Aborts the request when cancelled
+    pub cancellation: Option<CancellationToken>,
+    /// Overrides the client's response size cap for this request
+    pub max_response_size: Option<usize>,
+    /// Send this request on a circuit no other request has used or will use
+    pub new_circuit: bool,
+    /// Overrides how many times the client retries this request on another
+    /// circuit after a retriable failure
+    pub retries: Option<u32>,
+    /// Keys or certificates each host's TLS chain must contain, by
+    /// lowercase host; redirects to a pinned host are checked too
+    pub cert_pins: HashMap<String, Vec<CertPin>>,
 }
 
 impl Default for HttpRequest {
@@ -33,8 +85,117 @@ impl Default for HttpRequest {
             headers: HashMap::new(),
             body: None,
             timeout: Duration::from_secs(30),
+            connect_timeout: None,
+            first_byte_timeout: None,
+            request_id: new_request_id(),
+            isolation_token: None,
+            redirect_policy: None,
+            decompress: None,
+            cancellation: None,
+            max_response_size: None,
+            new_circuit: false,
+            retries: None,
+            cert_pins: HashMap::new(),
+        }
+    }
+}
+
+/// A stream of request `request_id`, announced with `StreamAttached`;
+/// reports it closed exactly once, as cancelled if the request is dropped
+/// before the exchange ends
+struct AttachedStream<'a> {
+    events: &'a CircuitEvents,
+    circuit_id: &'a str,
+    stream_id: u64,
+    request_id: &'a str,
+    traffic: TrafficCounter,
+    closed: bool,
+}
+
+impl<'a> AttachedStream<'a> {
+    fn new(
+        events: &'a CircuitEvents,
+        circuit_id: &'a str,
+        stream_id: u64,
+        target: String,
+        request_id: &'a str,
+        traffic: TrafficCounter,
+    ) -> Self {
+        events.emit(CircuitEvent::StreamAttached {
+            circuit_id: circuit_id.to_string(),
+            stream_id,
+            target,
+            request_id: Some(request_id.to_string()),
+        });
+        Self {
+            events,
+            circuit_id,
+            stream_id,
+            request_id,
+            traffic,
+            closed: false,
         }
     }
+
+    /// The exchange on this stream ended with `result`
+    fn finish<T>(mut self, result: &Result<T>) {
+        self.close(result.as_ref().err().map(ToString::to_string));
+    }
+
+    fn close(&mut self, error: Option<String>) {
+        if std::mem::replace(&mut self.closed, true) {
+            return;
+        }
+        let traffic = self.traffic.snapshot();
+        self.events.emit(CircuitEvent::StreamClosed {
+            circuit_id: self.circuit_id.to_string(),
+            stream_id: self.stream_id,
+            bytes_read: traffic.bytes_read,
+            bytes_written: traffic.bytes_written,
+            error,
+            request_id: Some(self.request_id.to_string()),
+        });
+    }
+}
+
+impl Drop for AttachedStream<'_> {
+    fn drop(&mut self) {
+        self.close(Some(TorError::Cancelled.to_string()));
+    }
+}
+
+/// Encodings advertised when decompression is on
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+/// Headers the client always writes itself
+///
+/// They decide where the request goes and how the exchange is framed: the
+/// host is the one the stream and TLS were opened to, the body length is
+/// computed, and the connection closes after one response. Caller values
+/// for them are dropped.
+pub const PROTECTED_HEADERS: &[&str] = &[
+    "Host",
+    "Connection",
+    "Content-Length",
+    "Transfer-Encoding",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "TE",
+    "Upgrade",
+];
+
+/// Whether `name` is one of the [`PROTECTED_HEADERS`]
+pub fn is_protected_header(name: &str) -> bool {
+    PROTECTED_HEADERS
+        .iter()
+        .any(|protected| protected.eq_ignore_ascii_case(name))
+}
+
+/// Generate a short random request ID
+pub fn new_request_id() -> String {
+    let mut id = uuid::Uuid::new_v4().simple().to_string();
+    id.truncate(16);
+    id
 }
 
 impl HttpRequest {
@@ -50,13 +211,71 @@ impl HttpRequest {
         self
     }
 
-    pub fn with_header(mut self, key: &str, value: &str) -> Self {
-        self.headers.insert(key.to_string(), value.to_string());
+    /// Set header `key`, replacing any value set under it in another case
+    ///
+    /// Caller headers override the defaults (`User-Agent`, `Accept`), except
+    /// the [`PROTECTED_HEADERS`].
+    pub fn with_header(self, key: &str, value: &str) -> Self {
+        let mut request = self.without_header(key);
+        request.headers.insert(key.to_string(), value.to_string());
+        request
+    }
+
+    /// Set every header in `headers`, as [`with_header`](Self::with_header) does
+    pub fn with_headers<K, V>(self, headers: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        headers.into_iter().fold(self, |request, (key, value)| {
+            request.with_header(key.as_ref(), value.as_ref())
+        })
+    }
+
+    /// Send `body` as is; `Content-Length` is always computed from it
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Some(body.into());
         self
     }
 
-    pub fn with_body(mut self, body: Vec<u8>) -> Self {
-        self.body = Some(body);
+    /// Send `text` as the body, as `text/plain` unless a `Content-Type` is set
+    pub fn with_text_body(self, text: impl Into<String>) -> Self {
+        let request = self.with_body(text.into());
+        if request.header("Content-Type").is_some() {
+            request
+        } else {
+            request.with_header("Content-Type", "text/plain;charset=UTF-8")
+        }
+    }
+
+    /// Send `value` serialized as JSON, with `Content-Type: application/json`
+    pub fn with_json_body<T: serde::Serialize + ?Sized>(self, value: &T) -> Result<Self> {
+        let body = serde_json::to_vec(value)?;
+        Ok(self
+            .without_header("Content-Type")
+            .with_header("Content-Type", "application/json")
+            .with_body(body))
+    }
+
+    /// Send `form` as a `multipart/form-data` body, setting `Content-Type`
+    /// with its boundary
+    pub fn with_multipart(self, form: &Multipart) -> Self {
+        self.with_header("Content-Type", &form.content_type())
+            .with_body(form.to_bytes())
+    }
+
+    /// Value of header `name`, matched case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Drop header `name` in any letter case
+    pub fn without_header(mut self, name: &str) -> Self {
+        self.headers
+            .retain(|key, _| !key.eq_ignore_ascii_case(name));
         self
     }
 
@@ -65,8 +284,130 @@ impl HttpRequest {
         self
     }
 
-    /// Build the HTTP request as raw bytes
-    fn build_request(&self, host: &str) -> Vec<u8> {
+    /// Fail with a connect [`TorError::RequestTimeout`] if the circuit,
+    /// stream and TLS session aren't ready within `timeout`
+    ///
+    /// The limit applies afresh to each attempt on a new circuit.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail with a first-byte [`TorError::RequestTimeout`] if the response
+    /// hasn't started within `timeout` of sending the request
+    pub fn with_first_byte_timeout(mut self, timeout: Duration) -> Self {
+        self.first_byte_timeout = Some(timeout);
+        self
+    }
+
+    /// Use a caller-chosen request ID, e.g. one from the embedding application
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = request_id.into();
+        self
+    }
+
+    /// Keep this request off circuits used by other isolation tokens
+    pub fn with_isolation_token(mut self, token: impl Into<IsolationToken>) -> Self {
+        self.isolation_token = Some(token.into());
+        self
+    }
+
+    /// Handle redirects of this request with `policy` instead of the client's
+    pub fn with_redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect_policy = Some(policy);
+        self
+    }
+
+    /// Abort the request once `token` is cancelled
+    ///
+    /// The request then fails with [`TorError::Cancelled`]; its stream is
+    /// closed, which frees its slot on the circuit, and reported closed.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Ask for compressed responses and decode them (`true`), or get the
+    /// body exactly as sent (`false`), whatever the client's default
+    pub fn with_decompression(mut self, enabled: bool) -> Self {
+        self.decompress = Some(enabled);
+        self
+    }
+
+    /// Fail with [`TorError::ResponseTooLarge`] once the response passes
+    /// `bytes`, whatever the client's cap; 0 means no cap
+    pub fn with_max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = Some(bytes);
+        self
+    }
+
+    /// Send the request on a circuit that has never carried traffic,
+    /// building one if needed, and never use that circuit again
+    ///
+    /// This makes the request unlinkable to any other at the exit, at the
+    /// cost of a circuit per request. Redirects and retries each get a fresh
+    /// circuit too. Not available on a client pinned to a circuit.
+    pub fn with_new_circuit(mut self, enabled: bool) -> Self {
+        self.new_circuit = enabled;
+        self
+    }
+
+    /// Retry up to `retries` times on other circuits after a failure
+    /// [`RetryReason::classify`] deems retriable, whatever the client's budget
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Require `host`'s TLS certificate chain to contain `pin`; pinning a
+    /// host several times accepts any of the pins
+    pub fn with_cert_pin(mut self, host: &str, pin: CertPin) -> Self {
+        self.cert_pins
+            .entry(host.to_ascii_lowercase())
+            .or_default()
+            .push(pin);
+        self
+    }
+
+    /// Ask for bytes `start` through `end` (inclusive; to the end of the
+    /// resource if `None`); a server that honours it answers 206
+    pub fn with_range(self, start: u64, end: Option<u64>) -> Self {
+        let end = end.map(|end| end.to_string()).unwrap_or_default();
+        self.with_header("Range", &format!("bytes={}-{}", start, end))
+    }
+
+    /// Only honour the `Range` if the resource still matches `validator`
+    /// (an ETag or HTTP date), and send it whole otherwise
+    pub fn with_if_range(self, validator: &str) -> Self {
+        self.with_header("If-Range", validator)
+    }
+
+    /// The pins for `host`, empty if it has none
+    pub fn cert_pins_for(&self, host: &str) -> &[CertPin] {
+        self.cert_pins
+            .get(&host.to_ascii_lowercase())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Check that caller headers can be written as they are
+    ///
+    /// Names must be HTTP tokens and values may not hold control characters,
+    /// so a header can't smuggle in a line break and with it another header
+    /// or request.
+    pub fn validate_headers(&self) -> Result<()> {
+        for (key, value) in &self.headers {
+            http::HeaderName::from_bytes(key.as_bytes())
+                .map_err(|_| TorError::configuration(format!("Invalid header name {:?}", key)))?;
+            http::HeaderValue::from_bytes(value.as_bytes()).map_err(|_| {
+                TorError::configuration(format!("Invalid value for header {}", key))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Build the HTTP request as raw bytes, with headers as `profile` writes
+    /// them
+    fn build_request(&self, host: &str, profile: RequestProfile) -> Vec<u8> {
         let path = if self.url.path().is_empty() {
             "/"
         } else {
@@ -79,33 +420,35 @@ impl HttpRequest {
             .map(|q| format!("?{}", q))
             .unwrap_or_default();
 
-        let mut request = format!(
-            "{} {}{} HTTP/1.1\r\nHost: {}\r\n",
-            self.method.as_str(),
-            path,
-            query,
-            host
-        );
+        let mut headers = vec![
+            ("Host".to_string(), host.to_string()),
+            ("Connection".to_string(), "close".to_string()),
+        ];
 
         // Add default headers if not present
-        if !self.headers.contains_key("User-Agent") && !self.headers.contains_key("user-agent") {
-            request.push_str("User-Agent: webtor-rs/0.1.0\r\n");
-        }
-        if !self.headers.contains_key("Accept") && !self.headers.contains_key("accept") {
-            request.push_str("Accept: */*\r\n");
-        }
-        if !self.headers.contains_key("Connection") && !self.headers.contains_key("connection") {
-            request.push_str("Connection: close\r\n");
+        for (name, value) in profile.default_headers() {
+            if self.header(name).is_none() {
+                headers.push((name.to_string(), value.to_string()));
+            }
         }
 
-        // Add custom headers
         for (key, value) in &self.headers {
-            request.push_str(&format!("{}: {}\r\n", key, value));
+            if !is_protected_header(key) {
+                headers.push((key.clone(), value.clone()));
+            }
         }
 
-        // Add content-length for POST/PUT requests with body
-        if let Some(ref body) = self.body {
-            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        // Servers may reject a POST, PUT or PATCH without a length (411)
+        let body_len = self.body.as_ref().map(Vec::len);
+        let expects_body = matches!(self.method, Method::POST | Method::PUT | Method::PATCH);
+        if let Some(len) = body_len.or(expects_body.then_some(0)) {
+            headers.push(("Content-Length".to_string(), len.to_string()));
+        }
+        profile.arrange(&mut headers);
+
+        let mut request = format!("{} {}{} HTTP/1.1\r\n", self.method.as_str(), path, query);
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
         }
 
         // End headers
@@ -123,9 +466,31 @@ impl HttpRequest {
 }
 
 /// HTTP client that routes requests through Tor circuits
+#[derive(Clone)]
 pub struct TorHttpClient {
     circuit_manager: Arc<RwLock<CircuitManager>>,
     isolation_policy: StreamIsolationPolicy,
+    /// When set, every request's isolation key is confined to this domain
+    isolation_domain: Option<IsolationDomain>,
+    hostname_policy: HostnamePolicy,
+    metrics: Metrics,
+    /// When set, every request goes over this circuit and no other
+    circuit_id: Option<String>,
+    /// Times a request moves to a new circuit after its circuit dies
+    stream_retries: u32,
+    events: CircuitEvents,
+    redirect_policy: RedirectPolicy,
+    cookie_jar: Option<CookieJar>,
+    decompress: bool,
+    max_response_size: usize,
+    tls_roots: TlsRoots,
+    /// Sessions HTTPS connections resume, if resumption is on
+    tls_sessions: Option<TlsSessionCache>,
+    ech_configs: EchConfigs,
+    profile: RequestProfile,
+    /// Joins circuits to the onion services `.onion` URLs name; without
+    /// it such requests fail
+    onion: Option<Arc<OnionConnector>>,
 }
 
 impl TorHttpClient {
@@ -136,22 +501,221 @@ impl TorHttpClient {
         Self {
             circuit_manager,
             isolation_policy,
+            isolation_domain: None,
+            hostname_policy: HostnamePolicy::default(),
+            metrics: Metrics::default(),
+            circuit_id: None,
+            stream_retries: DEFAULT_STREAM_RETRIES,
+            events: CircuitEvents::default(),
+            redirect_policy: RedirectPolicy::default(),
+            cookie_jar: None,
+            decompress: true,
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            tls_roots: TlsRoots::default(),
+            tls_sessions: None,
+            ech_configs: EchConfigs::default(),
+            profile: RequestProfile::default(),
+            onion: None,
         }
     }
 
+    /// Send every request over the circuit with this ID
+    ///
+    /// Requests fail instead of switching circuits if it closes or its exit
+    /// doesn't allow the destination port.
+    pub fn on_circuit(mut self, circuit_id: impl Into<String>) -> Self {
+        self.circuit_id = Some(circuit_id.into());
+        self
+    }
+
+    /// The circuit this client is bound to, if any
+    pub fn circuit_id(&self) -> Option<&str> {
+        self.circuit_id.as_deref()
+    }
+
+    /// Keep every request on circuits of `domain`, apart from other clients
+    /// sharing the circuit manager
+    pub fn with_isolation_domain(mut self, domain: IsolationDomain) -> Self {
+        self.isolation_domain = Some(domain);
+        self
+    }
+
+    /// Control how internationalized hostnames are sent to the exit
+    pub fn with_hostname_policy(mut self, policy: HostnamePolicy) -> Self {
+        self.hostname_policy = policy;
+        self
+    }
+
+    /// Record stream and byte counts into a shared metrics registry
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Retry a request on up to `retries` fresh circuits when the circuit it
+    /// was sent on is destroyed or loses its channel before the response starts
+    ///
+    /// Requests on a pinned circuit are never moved.
+    pub fn with_stream_retries(mut self, retries: u32) -> Self {
+        self.stream_retries = retries;
+        self
+    }
+
+    /// Emit stream events, and closures of circuits found dead, to a shared handle
+    pub fn with_events(mut self, events: CircuitEvents) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Handle redirects with `policy` unless a request sets its own
+    pub fn with_redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect_policy = policy;
+        self
+    }
+
+    /// Record `Set-Cookie` responses into `jar` and send matching cookies back
+    pub fn with_cookie_jar(mut self, jar: CookieJar) -> Self {
+        self.cookie_jar = Some(jar);
+        self
+    }
+
+    /// Send `Accept-Encoding` and decode compressed bodies unless a request
+    /// says otherwise (on by default)
+    pub fn with_decompression(mut self, enabled: bool) -> Self {
+        self.decompress = enabled;
+        self
+    }
+
+    /// Fail requests whose response, raw or decoded, passes `bytes` unless
+    /// a request sets its own cap; 0 means no cap
+    pub fn with_max_response_size(mut self, bytes: usize) -> Self {
+        self.max_response_size = bytes;
+        self
+    }
+
+    /// Verify servers' certificates against `roots`
+    pub fn with_tls_roots(mut self, roots: TlsRoots) -> Self {
+        self.tls_roots = roots;
+        self
+    }
+
+    /// Resume TLS sessions from `cache`, within each request's isolation key
+    pub fn with_tls_sessions(mut self, cache: TlsSessionCache) -> Self {
+        self.tls_sessions = Some(cache);
+        self
+    }
+
+    /// Encrypt the ClientHello to hosts with a configuration in `configs`
+    pub fn with_ech_configs(mut self, configs: EchConfigs) -> Self {
+        self.ech_configs = configs;
+        self
+    }
+
+    /// Write requests as `profile` does
+    pub fn with_request_profile(mut self, profile: RequestProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Send requests for `.onion` URLs over circuits `connector` joins to
+    /// their services
+    pub fn with_onion_connector(mut self, connector: Arc<OnionConnector>) -> Self {
+        self.onion = Some(connector);
+        self
+    }
+
+    /// The cookie jar requests use, if cookies are enabled
+    pub fn cookie_jar(&self) -> Option<&CookieJar> {
+        self.cookie_jar.as_ref()
+    }
+
+    /// Open a stream on `circuit`, returning it with its event stream ID
+    async fn begin_stream(
+        &self,
+        circuit: &Arc<RwLock<Circuit>>,
+        host: &str,
+        port: u16,
+    ) -> Result<(u64, TorStream)> {
+        let circuit = circuit.read().await;
+        // An onion service's virtual hop takes BEGIN cells without an address
+        let address = if onion::is_onion_host(host) { "" } else { host };
+        let result = circuit.begin_stream(address, port).await;
+        self.metrics.record_stream(result.is_ok());
+        let stream = result?;
+        Ok((self.events.next_stream_id(), stream))
+    }
+
     /// Make an HTTP request through Tor
+    ///
+    /// Logs are emitted inside a `request` span carrying the request ID, and
+    /// the ID is attached to the response or error.
     pub async fn request(&self, request: HttpRequest) -> Result<HttpResponse> {
+        let request_id = request.request_id.clone();
+        let span = info_span!("request", request_id = %request_id);
+        let cancellation = request.cancellation.clone();
+        let timeout = request.timeout;
+        let exchange = within(
+            TimeoutPhase::Total,
+            Some(timeout),
+            self.follow_redirects(request),
+        );
+        let result = match &cancellation {
+            Some(token) => with_cancellation(token, exchange).instrument(span).await,
+            None => exchange.instrument(span).await,
+        };
+        match result {
+            Ok(mut response) => {
+                response.request_id = request_id;
+                Ok(response)
+            }
+            Err(e) => Err(e.with_request_id(request_id)),
+        }
+    }
+
+    /// Send `request` and whatever requests its redirects lead to
+    async fn follow_redirects(&self, mut request: HttpRequest) -> Result<HttpResponse> {
+        let policy = request
+            .redirect_policy
+            .clone()
+            .unwrap_or_else(|| self.redirect_policy.clone());
+        let mut redirects = 0;
+        let mut retries = 0;
+        loop {
+            let mut response = self.send(&request).await?;
+            retries += response.retries;
+            match policy.next_request(&request, &response, redirects)? {
+                Some(next) => {
+                    info!("Following {} redirect to {}", response.status, next.url);
+                    redirects += 1;
+                    request = next;
+                }
+                None => {
+                    response.retries = retries;
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    async fn send(&self, request: &HttpRequest) -> Result<HttpResponse> {
         info!(
-            "Making {} request to {} through Tor",
-            request.method, request.url
+            "Making {} request to {} through Tor (request {})",
+            request.method, request.url, request.request_id
         );
 
         // Parse URL to get host and port
         let url = request.url.clone();
         let host = url
             .host_str()
-            .ok_or_else(|| TorError::http_request("Invalid URL: no host"))?
-            .to_string();
+            .ok_or_else(|| TorError::http_request("Invalid URL: no host"))?;
+        let host = self.hostname_policy.apply(host)?;
+        let service = match &self.onion {
+            Some(_) if onion::is_onion_host(&host) => Some(onion::service_id(&host)?),
+            _ => {
+                onion::check_exit_host(&host)?;
+                None
+            }
+        };
 
         let port = url
             .port_or_known_default()
@@ -161,105 +725,147 @@ impl TorHttpClient {
 
         debug!("Target: {}:{} (HTTPS: {})", host, port, is_https);
 
-        // Compute isolation key based on policy
-        let isolation_key = IsolationKey::from_url(&url, self.isolation_policy);
-        if let Some(ref key) = isolation_key {
-            debug!(
-                "Using isolation key: {} (policy: {:?})",
-                key, self.isolation_policy
-            );
+        request.validate_headers()?;
+        for key in request
+            .headers
+            .keys()
+            .filter(|key| is_protected_header(key))
+        {
+            debug!("Ignoring caller-set {} header", key);
         }
 
-        // Get a circuit for this isolation key
-        let circuit_manager = self.circuit_manager.read().await;
-        let circuit = circuit_manager
-            .get_circuit_for_isolation_key(isolation_key)
-            .await?;
+        // Compute isolation key based on policy, the caller's token and the
+        // client's domain; it also picks the cookie jar partition
+        let isolation_key = IsolationKey::scoped(
+            IsolationKey::tagged(
+                IsolationKey::from_url(&url, self.isolation_policy),
+                request.isolation_token.as_ref(),
+            ),
+            self.isolation_domain,
+        );
 
-        // Begin stream on the circuit
-        let stream = {
-            let circuit_read = circuit.read().await;
-            circuit_read.begin_stream(&host, port).await?
+        // A request on a new circuit must not be linkable to earlier ones,
+        // so it neither resumes a session nor leaves one behind
+        let tls = is_https.then(|| ExchangeTls {
+            pins: request.cert_pins_for(&host),
+            sessions: match &self.tls_sessions {
+                Some(cache) if !request.new_circuit => {
+                    Some(cache.partition(isolation_key.as_ref()))
+                }
+                _ => None,
+            },
+        });
+
+        // Build the HTTP request, with stored cookies and the encodings we
+        // decode unless the caller set those headers themselves
+        let decompress = request.decompress.unwrap_or(self.decompress);
+        let max_response_size = request.max_response_size.unwrap_or(self.max_response_size);
+        let mut extra_headers = Vec::new();
+        if let Some(jar) = &self.cookie_jar {
+            if request.header("Cookie").is_none() {
+                if let Some(cookies) = jar.cookie_header(isolation_key.as_ref(), &url) {
+                    extra_headers.push(("Cookie", cookies));
+                }
+            }
+        }
+        if decompress && request.header("Accept-Encoding").is_none() {
+            extra_headers.push(("Accept-Encoding", ACCEPT_ENCODING.to_string()));
+        }
+        let request_bytes = if extra_headers.is_empty() {
+            request.build_request(&host, self.profile)
+        } else {
+            request
+                .clone()
+                .with_headers(extra_headers)
+                .build_request(&host, self.profile)
         };
 
-        // Build the HTTP request
-        let request_bytes = request.build_request(&host);
-        debug!("Sending {} bytes of HTTP request", request_bytes.len());
+        let circuit_manager = self.circuit_manager.read().await;
+        let retry_budget = request.retries.unwrap_or(self.stream_retries);
+        let mut attempt = 0;
+        let response_bytes = loop {
+            let limits = AttemptLimits::start(request, max_response_size);
+            let circuit = if let (Some(service), Some(connector)) = (service, &self.onion) {
+                if self.circuit_id.is_some() || request.new_circuit {
+                    return Err(TorError::configuration(format!(
+                        "{} is reached over its own rendezvous circuit, not a pinned or new one",
+                        host
+                    )));
+                }
+                debug!("Using a circuit joined to the onion service");
+                limits
+                    .connect(connector.circuit(service, isolation_key.clone()))
+                    .await?
+            } else if let Some(circuit_id) = &self.circuit_id {
+                if request.new_circuit {
+                    return Err(TorError::configuration(format!(
+                        "Cannot use a new circuit on a client pinned to circuit {}",
+                        circuit_id
+                    )));
+                }
+                debug!("Using pinned circuit {}", circuit_id);
+                limits
+                    .connect(circuit_manager.get_pinned_circuit(circuit_id, port))
+                    .await?
+            } else if request.new_circuit {
+                debug!("Using a fresh circuit");
+                limits
+                    .connect(circuit_manager.get_fresh_circuit(port))
+                    .await?
+            } else {
+                if let Some(ref key) = isolation_key {
+                    debug!(
+                        "Using isolation key: {} (policy: {:?})",
+                        key, self.isolation_policy
+                    );
+                }
 
-        // Execute request with or without TLS
-        let response_bytes = if is_https {
-            #[cfg(not(target_arch = "wasm32"))]
+                // Get a circuit for this isolation key
+                limits
+                    .connect(
+                        circuit_manager.get_circuit_for_isolation_key(isolation_key.clone(), port),
+                    )
+                    .await?
+            };
+
+            debug!("Sending {} bytes of HTTP request", request_bytes.len());
+            match self
+                .exchange(
+                    &circuit,
+                    &host,
+                    port,
+                    tls.as_ref(),
+                    &request.request_id,
+                    &request_bytes,
+                    &limits,
+                )
+                .await
             {
-                // Wrap stream with TLS using rustls
-                let tls_stream = wrap_with_tls(stream, &host).await?;
-                execute_http_request(tls_stream, &request_bytes).await?
-            }
-            #[cfg(target_arch = "wasm32")]
-            {
-                // Use subtle-tls for WASM (SubtleCrypto-based TLS)
-                use subtle_tls::{TlsConfig, TlsConnector, TlsVersion};
-
-                let config = TlsConfig {
-                    skip_verification: false,
-                    alpn_protocols: vec!["http/1.1".to_string()],
-                    version: TlsVersion::Tls13,
-                };
-                let connector = TlsConnector::with_config(config);
-
-                // Try TLS 1.3 first
-                match connector.connect(stream, &host).await {
-                    Ok(mut tls_stream) => {
-                        info!(
-                            "TLS 1.3 connection established with {} (WASM/SubtleCrypto)",
-                            host
-                        );
-                        execute_http_request_wasm(&mut tls_stream, &request_bytes).await?
-                    }
-                    Err(tls13_err) => {
-                        warn!(
-                            "TLS 1.3 handshake failed with {}: {}, trying TLS 1.2...",
-                            host, tls13_err
-                        );
-
-                        // Get a new stream for TLS 1.2 retry
-                        let stream_tls12 = {
-                            let mut circuit_write = circuit.write().await;
-                            circuit_write.begin_stream(&host, port).await?
-                        };
-
-                        // Try TLS 1.2
-                        let config_tls12 = TlsConfig {
-                            skip_verification: false,
-                            alpn_protocols: vec!["http/1.1".to_string()],
-                            version: TlsVersion::Tls12,
-                        };
-                        let connector_tls12 = TlsConnector::with_config(config_tls12);
-
-                        match connector_tls12.connect_tls12(stream_tls12, &host).await {
-                            Ok(mut tls_stream) => {
-                                info!(
-                                    "TLS 1.2 connection established with {} (WASM/SubtleCrypto)",
-                                    host
-                                );
-                                execute_http_request_wasm_tls12(&mut tls_stream, &request_bytes)
-                                    .await?
-                            }
-                            Err(tls12_err) => {
-                                warn!("TLS 1.2 handshake also failed with {}: {}", host, tls12_err);
-                                return Err(TorError::tls(format!(
-                                    "TLS handshake failed - TLS 1.3: {}, TLS 1.2: {}",
-                                    tls13_err, tls12_err
-                                )));
-                            }
-                        }
+                Ok(response_bytes) => break response_bytes,
+                Err(e) => {
+                    let e = self.check_circuit(&circuit, e).await;
+                    let sent = limits.request_sent();
+                    let Some(reason) = self.retry_reason(request, &e, sent, attempt, retry_budget)
+                    else {
+                        return Err(e);
+                    };
+                    if reason != RetryReason::CircuitClosed {
+                        // Like a timed-out stream in C Tor, the circuit
+                        // takes no more streams, so the retry goes elsewhere
+                        circuit.write().await.retire();
                     }
+                    attempt += 1;
+                    warn!(
+                        "{} ({}); retrying on another circuit ({}/{})",
+                        e, reason, attempt, retry_budget
+                    );
                 }
             }
-        } else {
-            execute_http_request(stream, &request_bytes).await?
         };
 
         info!("Received {} bytes of HTTP response", response_bytes.len());
+        self.metrics
+            .record_http_bytes(request_bytes.len(), response_bytes.len());
 
         // Trigger preemptive circuit building after successful request
         let age_threshold = Duration::from_millis(CIRCUIT_PREBUILD_AGE_THRESHOLD_MS);
@@ -268,7 +874,230 @@ impl TorHttpClient {
             .await;
 
         // Parse the HTTP response
-        parse_http_response(&response_bytes, request.url)
+        let mut response = parse_http_response(&response_bytes, request.url.clone())?;
+        response.retries = attempt;
+        if decompress {
+            decode_content(&mut response, max_response_size)?;
+        }
+        if let Some(jar) = &self.cookie_jar {
+            jar.store(
+                isolation_key.as_ref(),
+                &url,
+                response.header_all("set-cookie"),
+            );
+        }
+        Ok(response)
+    }
+
+    /// Open a stream to `host:port` on `circuit`, over TLS if `tls` is given,
+    /// and exchange `request_bytes` for the raw response
+    ///
+    /// Read errors only surface when no response bytes have arrived, so a
+    /// failure here never loses part of a response. Stream setup and the TLS
+    /// handshake count against the connect limit in `limits`.
+    #[allow(clippy::too_many_arguments)]
+    async fn exchange(
+        &self,
+        circuit: &Arc<RwLock<Circuit>>,
+        host: &str,
+        port: u16,
+        tls: Option<&ExchangeTls<'_>>,
+        request_id: &str,
+        request_bytes: &[u8],
+        limits: &AttemptLimits,
+    ) -> Result<Vec<u8>> {
+        let circuit_id = circuit.read().await.id.clone();
+        let (stream_id, stream) = limits
+            .connect(self.begin_stream(circuit, host, port))
+            .await?;
+        let attached = AttachedStream::new(
+            &self.events,
+            &circuit_id,
+            stream_id,
+            format!("{}:{}", host, port),
+            request_id,
+            stream.counter(),
+        );
+
+        let Some(ExchangeTls { pins, sessions }) = tls else {
+            let result = execute_http_request(stream, request_bytes, limits).await;
+            attached.finish(&result);
+            return result;
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            // Wrap stream with TLS using rustls. Only HTTP/1.1 is offered:
+            // a server picking h2 would answer in frames this client can't parse
+            let alpn = ["http/1.1".to_string()];
+            let result = match limits
+                .connect(wrap_with_tls_pinned(
+                    stream,
+                    host,
+                    &self.tls_roots,
+                    &alpn,
+                    pins,
+                    sessions.as_ref(),
+                    &self.ech_configs,
+                ))
+                .await
+            {
+                Ok(tls_stream) => execute_http_request(tls_stream, request_bytes, limits).await,
+                Err(e) => Err(e),
+            };
+            attached.finish(&result);
+            result
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            // Use subtle-tls for WASM (SubtleCrypto-based TLS)
+            use subtle_tls::{TlsConnector, TlsVersion};
+
+            if let Err(e) = self.tls_roots.validate() {
+                let result = Err(e);
+                attached.finish(&result);
+                return result;
+            }
+            let connector = TlsConnector::with_config(subtle_tls::TlsConfig {
+                session_cache: sessions.as_ref().map(|sessions| sessions.cache.clone()),
+                ech_config_list: self.ech_configs.get(host),
+                ..self.tls_roots.subtle_config(TlsVersion::Tls13)
+            });
+
+            // Try TLS 1.3 first
+            let handshake = limits
+                .connect(async { Ok(connector.connect(stream, host).await) })
+                .await;
+            let handshake = match handshake {
+                Ok(handshake) => handshake,
+                Err(e) => {
+                    let result = Err(e);
+                    attached.finish(&result);
+                    return result;
+                }
+            };
+            match handshake {
+                Ok(mut tls_stream) => {
+                    info!(
+                        "TLS 1.3 connection established with {} (WASM/SubtleCrypto)",
+                        host
+                    );
+                    let result = match check_chain(host, pins, tls_stream.peer_certificates()) {
+                        Ok(()) => {
+                            execute_http_request_wasm(&mut tls_stream, request_bytes, limits).await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    attached.finish(&result);
+                    result
+                }
+                // Falling back to TLS 1.2 would send the hidden name in clear
+                Err(tls13_err) if self.ech_configs.get(host).is_some() => {
+                    let result = Err(subtle_error(host, &self.ech_configs, tls13_err));
+                    attached.finish(&result);
+                    result
+                }
+                Err(tls13_err) => {
+                    warn!(
+                        "TLS 1.3 handshake failed with {}: {}, trying TLS 1.2...",
+                        host, tls13_err
+                    );
+                    attached.finish::<()>(&Err(TorError::tls(tls13_err.to_string())));
+
+                    // Get a new stream for TLS 1.2 retry
+                    let (stream_id, stream_tls12) = limits
+                        .connect(self.begin_stream(circuit, host, port))
+                        .await?;
+                    let attached = AttachedStream::new(
+                        &self.events,
+                        &circuit_id,
+                        stream_id,
+                        format!("{}:{}", host, port),
+                        request_id,
+                        stream_tls12.counter(),
+                    );
+
+                    // Try TLS 1.2
+                    let connector_tls12 =
+                        TlsConnector::with_config(self.tls_roots.subtle_config(TlsVersion::Tls12));
+
+                    let handshake = limits
+                        .connect(async {
+                            Ok(connector_tls12.connect_tls12(stream_tls12, host).await)
+                        })
+                        .await;
+                    let result = match handshake {
+                        Err(e) => Err(e),
+                        Ok(Ok(mut tls_stream)) => {
+                            info!(
+                                "TLS 1.2 connection established with {} (WASM/SubtleCrypto)",
+                                host
+                            );
+                            match check_chain(host, pins, tls_stream.peer_certificates()) {
+                                Ok(()) => {
+                                    execute_http_request_wasm_tls12(
+                                        &mut tls_stream,
+                                        request_bytes,
+                                        limits,
+                                    )
+                                    .await
+                                }
+                                Err(e) => Err(e),
+                            }
+                        }
+                        Ok(Err(tls12_err)) => {
+                            warn!("TLS 1.2 handshake also failed with {}: {}", host, tls12_err);
+                            Err(TorError::tls(format!(
+                                "TLS handshake failed - TLS 1.3: {}, TLS 1.2: {}",
+                                tls13_err, tls12_err
+                            )))
+                        }
+                    };
+                    attached.finish(&result);
+                    result
+                }
+            }
+        }
+    }
+
+    /// Classify a failed exchange: if `circuit` died underneath it, retire the
+    /// circuit and report [`TorError::CircuitClosed`] instead of `err`
+    async fn check_circuit(&self, circuit: &Arc<RwLock<Circuit>>, err: TorError) -> TorError {
+        let mut circuit = circuit.write().await;
+        if !circuit.tunnel_closed() {
+            return err;
+        }
+        if !circuit.is_failed() {
+            circuit.status = CircuitStatus::Failed;
+            self.events.circuit_closed(&circuit.id, "destroyed");
+        }
+        match err {
+            TorError::CircuitClosed(_) => err,
+            other => TorError::circuit_closed(format!("{}: {}", circuit.id, other)),
+        }
+    }
+
+    /// Why `request`, having failed with `err` after `attempt` retries out
+    /// of `budget`, should move to another circuit, if it should
+    ///
+    /// Requests on a pinned circuit are never moved. Once any of the request
+    /// was `sent` it may have reached the server, so it is only repeated if
+    /// its method is idempotent, whatever the failure.
+    fn retry_reason(
+        &self,
+        request: &HttpRequest,
+        err: &TorError,
+        sent: bool,
+        attempt: u32,
+        budget: u32,
+    ) -> Option<RetryReason> {
+        if self.circuit_id.is_some() || attempt >= budget {
+            return None;
+        }
+        if sent && !is_idempotent(&request.method) {
+            return None;
+        }
+        RetryReason::classify(err)
     }
 
     /// Convenience method for GET requests
@@ -279,7 +1108,7 @@ impl TorHttpClient {
     }
 
     /// Convenience method for POST requests
-    pub async fn post(&self, url: &str, body: Vec<u8>) -> Result<HttpResponse> {
+    pub async fn post(&self, url: &str, body: impl Into<Vec<u8>>) -> Result<HttpResponse> {
         let url = Url::parse(url)?;
         let request = HttpRequest::new(url)
             .with_method(Method::POST)
@@ -288,6 +1117,64 @@ impl TorHttpClient {
     }
 }
 
+/// Why a failed request attempt is worth repeating on another circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryReason {
+    /// The circuit was destroyed or lost its channel
+    CircuitClosed,
+    /// The exit turned the stream away (policy, load, hibernation) rather
+    /// than the destination failing
+    ExitRejected,
+    /// The connection dropped before a response, e.g. mid TLS handshake
+    ConnectionReset,
+    /// The connect or first-byte limit expired
+    Timeout,
+}
+
+impl RetryReason {
+    /// The reason to retry after `err`, or `None` if another circuit
+    /// wouldn't help (bad certificate, destination down, cancellation, ...)
+    pub fn classify(err: &TorError) -> Option<Self> {
+        match err {
+            TorError::Request { source, .. } => Self::classify(source),
+            TorError::CircuitClosed(_) | TorError::StreamEnded(StreamEndReason::Destroy) => {
+                Some(Self::CircuitClosed)
+            }
+            TorError::StreamEnded(reason) if !reason.is_destination_failure() => {
+                Some(Self::ExitRejected)
+            }
+            TorError::Io(e) if is_connection_reset(e) => Some(Self::ConnectionReset),
+            TorError::RequestTimeout { phase, .. } if *phase != TimeoutPhase::Total => {
+                Some(Self::Timeout)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CircuitClosed => "circuit closed",
+            Self::ExitRejected => "exit rejected stream",
+            Self::ConnectionReset => "connection reset",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+impl std::fmt::Display for RetryReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether repeating a request with `method` has the same effect as sending it once
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
 /// Trait for TLS streams that support async read/write operations
 #[cfg(target_arch = "wasm32")]
 #[async_trait::async_trait(?Send)]
@@ -336,37 +1223,45 @@ where
 async fn execute_http_request_wasm<T: WasmTlsStream>(
     tls_stream: &mut T,
     request_bytes: &[u8],
+    limits: &AttemptLimits,
 ) -> Result<Vec<u8>> {
+    limits.mark_sent();
     tls_stream
         .tls_write(request_bytes)
         .await
-        .map_err(|e| TorError::http_request(format!("Failed to write request: {}", e)))?;
+        .map_err(|e| stream_error(e, "Failed to write request"))?;
     tls_stream
         .tls_flush()
         .await
-        .map_err(|e| TorError::http_request(format!("Failed to flush request: {}", e)))?;
+        .map_err(|e| stream_error(e, "Failed to flush request"))?;
 
     let mut response_bytes = Vec::new();
     let mut buf = [0u8; 8192];
 
     loop {
-        match tls_stream.tls_read(&mut buf).await {
+        let read = tls_stream.tls_read(&mut buf);
+        let read = if response_bytes.is_empty() {
+            within(TimeoutPhase::FirstByte, limits.first_byte, async {
+                Ok(read.await)
+            })
+            .await?
+        } else {
+            read.await
+        };
+        match read {
             Ok(0) => break,
             Ok(n) => {
                 response_bytes.extend_from_slice(&buf[..n]);
                 debug!("Read {} bytes (total: {})", n, response_bytes.len());
 
-                if response_bytes.len() > 1024 * 1024 {
-                    warn!("Response exceeds 1MB limit, truncating");
+                limits.check_size(response_bytes.len())?;
+                if response_length(&response_bytes).is_some() {
                     break;
                 }
             }
             Err(e) => {
                 if response_bytes.is_empty() {
-                    return Err(TorError::http_request(format!(
-                        "Failed to read response: {}",
-                        e
-                    )));
+                    return Err(stream_error(e, "Failed to read response"));
                 }
                 debug!("Read ended with error (may be normal close): {}", e);
                 break;
@@ -382,51 +1277,161 @@ async fn execute_http_request_wasm<T: WasmTlsStream>(
 async fn execute_http_request_wasm_tls12<S>(
     tls_stream: &mut subtle_tls::TlsStream12<S>,
     request_bytes: &[u8],
+    limits: &AttemptLimits,
 ) -> Result<Vec<u8>>
 where
     S: futures::io::AsyncRead + futures::io::AsyncWrite + Unpin,
 {
-    execute_http_request_wasm(tls_stream, request_bytes).await
+    execute_http_request_wasm(tls_stream, request_bytes, limits).await
+}
+
+/// How a request's connection sets up TLS
+struct ExchangeTls<'a> {
+    /// The host's pins, which may be none
+    pins: &'a [CertPin],
+    /// Sessions the connection may resume and add to; none for requests
+    /// that must stay unlinkable
+    sessions: Option<TlsSessions>,
+}
+
+/// Time and size limits for one attempt at a request on one circuit
+struct AttemptLimits {
+    started: Instant,
+    connect_timeout: Option<Duration>,
+    first_byte: Option<Duration>,
+    /// Cap on the raw response in bytes; 0 means none
+    max_response_size: usize,
+    /// Set once writing the request starts
+    sent: AtomicBool,
+}
+
+impl AttemptLimits {
+    fn start(request: &HttpRequest, max_response_size: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            connect_timeout: request.connect_timeout,
+            first_byte: request.first_byte_timeout,
+            max_response_size,
+            sent: AtomicBool::new(false),
+        }
+    }
+
+    /// Note that request bytes are about to leave, so the server may see them
+    fn mark_sent(&self) {
+        self.sent.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether any of the request may have reached the server
+    fn request_sent(&self) -> bool {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Fail once `received` bytes pass the size cap; the caller then drops
+    /// the stream, which closes it
+    fn check_size(&self, received: usize) -> Result<()> {
+        check_response_size(received, self.max_response_size)
+    }
+
+    /// Run a connection step in whatever remains of the connect limit
+    async fn connect<T>(&self, step: impl Future<Output = Result<T>>) -> Result<T> {
+        let Some(limit) = self.connect_timeout else {
+            return step.await;
+        };
+        let remaining = limit.saturating_sub(self.started.elapsed());
+        within(TimeoutPhase::Connect, Some(remaining), step)
+            .await
+            .map_err(|e| match e {
+                TorError::RequestTimeout { phase, .. } => TorError::RequestTimeout { phase, limit },
+                other => other,
+            })
+    }
+}
+
+fn check_response_size(size: usize, limit: usize) -> Result<()> {
+    if limit != 0 && size > limit {
+        warn!("Response exceeds {} byte limit, aborting", limit);
+        return Err(TorError::ResponseTooLarge { limit });
+    }
+    Ok(())
+}
+
+/// Run `future`, failing with a `phase` timeout if it outlasts `limit`
+async fn within<T>(
+    phase: TimeoutPhase,
+    limit: Option<Duration>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    use futures::future::{select, Either};
+    use std::pin::pin;
+
+    let Some(limit) = limit else {
+        return future.await;
+    };
+    match select(pin!(future), pin!(crate::retry::sleep(limit))).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(TorError::RequestTimeout { phase, limit }),
+    }
+}
+
+/// An I/O error on the request stream, keeping the exit's END reason if it sent one
+fn stream_error(err: std::io::Error, context: &str) -> TorError {
+    match StreamEndReason::from_io_error(&err) {
+        Some(reason) => TorError::StreamEnded(reason),
+        None => TorError::http_request(format!("{}: {}", context, err)),
+    }
 }
 
 /// Execute an HTTP request over a stream and return the response bytes
-async fn execute_http_request<S>(mut stream: S, request_bytes: &[u8]) -> Result<Vec<u8>>
+///
+/// Fails with a first-byte timeout if nothing arrives in time, and with
+/// [`TorError::ResponseTooLarge`] as soon as the response passes its cap.
+async fn execute_http_request<S>(
+    mut stream: S,
+    request_bytes: &[u8],
+    limits: &AttemptLimits,
+) -> Result<Vec<u8>>
 where
     S: AsyncRead + AsyncWrite + Unpin,
 {
     // Write the request
+    limits.mark_sent();
     stream
         .write_all(request_bytes)
         .await
-        .map_err(|e| TorError::http_request(format!("Failed to write request: {}", e)))?;
+        .map_err(|e| stream_error(e, "Failed to write request"))?;
     stream
         .flush()
         .await
-        .map_err(|e| TorError::http_request(format!("Failed to flush request: {}", e)))?;
+        .map_err(|e| stream_error(e, "Failed to flush request"))?;
 
     // Read the response
     let mut response_bytes = Vec::new();
     let mut buf = [0u8; 8192];
 
     loop {
-        match stream.read(&mut buf).await {
+        let read = stream.read(&mut buf);
+        let read = if response_bytes.is_empty() {
+            within(TimeoutPhase::FirstByte, limits.first_byte, async {
+                Ok(read.await)
+            })
+            .await?
+        } else {
+            read.await
+        };
+        match read {
             Ok(0) => break, // EOF
             Ok(n) => {
                 response_bytes.extend_from_slice(&buf[..n]);
                 debug!("Read {} bytes (total: {})", n, response_bytes.len());
 
-                // Limit response size to 1MB for safety
-                if response_bytes.len() > 1024 * 1024 {
-                    warn!("Response exceeds 1MB limit, truncating");
+                limits.check_size(response_bytes.len())?;
+                if response_length(&response_bytes).is_some() {
                     break;
                 }
             }
             Err(e) => {
                 if response_bytes.is_empty() {
-                    return Err(TorError::http_request(format!(
-                        "Failed to read response: {}",
-                        e
-                    )));
+                    return Err(stream_error(e, "Failed to read response"));
                 }
                 // We have some data, maybe connection was closed
                 debug!("Read ended with error (may be normal close): {}", e);
@@ -434,8 +1439,88 @@ where
             }
         }
     }
-
-    Ok(response_bytes)
+
+    Ok(response_bytes)
+}
+
+/// Undo the response's `Content-Encoding`
+///
+/// Encodings are removed in the reverse of the order applied. On success the
+/// `Content-Encoding` and `Content-Length` headers are dropped, as they no
+/// longer describe the body; a body in an encoding we don't know is left
+/// untouched, headers included.
+fn decode_content(response: &mut HttpResponse, max_size: usize) -> Result<()> {
+    let encodings: Vec<String> = response
+        .header_all("content-encoding")
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+        .collect();
+    if encodings.is_empty() || response.body.is_empty() {
+        return Ok(());
+    }
+    if let Some(unknown) = encodings
+        .iter()
+        .find(|e| !matches!(e.as_str(), "gzip" | "x-gzip" | "deflate" | "br"))
+    {
+        debug!("Leaving body in unsupported encoding {}", unknown);
+        return Ok(());
+    }
+
+    let mut body = std::mem::take(&mut response.body);
+    for encoding in encodings.iter().rev() {
+        body = decompress(&body, encoding, max_size).map_err(|e| {
+            TorError::http_request(format!("Failed to decode {} body: {}", encoding, e))
+        })?;
+        check_response_size(body.len(), max_size)?;
+    }
+    debug!(
+        "Decoded {} body to {} bytes",
+        encodings.join(", "),
+        body.len()
+    );
+    response.body = body;
+    response.headers.remove(http::header::CONTENT_ENCODING);
+    response.headers.remove(CONTENT_LENGTH);
+    Ok(())
+}
+
+/// Decompress `data`, stopping one byte past `max_size` (0: no cap) so a
+/// small body can't inflate without bound
+fn decompress(data: &[u8], encoding: &str, max_size: usize) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+    let cap = match max_size {
+        0 => u64::MAX,
+        max => max as u64 + 1,
+    };
+    let mut out = Vec::new();
+    match encoding {
+        "gzip" | "x-gzip" => {
+            flate2::read::MultiGzDecoder::new(data)
+                .take(cap)
+                .read_to_end(&mut out)?;
+        }
+        // "deflate" is meant to be zlib-wrapped, but some servers send raw deflate
+        "deflate" => {
+            if flate2::read::ZlibDecoder::new(data)
+                .take(cap)
+                .read_to_end(&mut out)
+                .is_err()
+            {
+                out.clear();
+                flate2::read::DeflateDecoder::new(data)
+                    .take(cap)
+                    .read_to_end(&mut out)?;
+            }
+        }
+        _ => {
+            brotli::Decompressor::new(data, 4096)
+                .take(cap)
+                .read_to_end(&mut out)?;
+        }
+    }
+    Ok(out)
 }
 
 /// Parse raw HTTP response bytes into HttpResponse
@@ -443,11 +1528,72 @@ fn parse_http_response(data: &[u8], url: Url) -> Result<HttpResponse> {
     // Find the header/body separator
     let header_end = find_subsequence(data, b"\r\n\r\n")
         .ok_or_else(|| TorError::http_request("Invalid HTTP response: no header separator"))?;
+    let head = parse_head(&data[..header_end])?;
+    let body = &data[header_end + 4..];
+
+    // Decode body based on Transfer-Encoding or Content-Length
+    // Per HTTP/1.1 semantics: Transfer-Encoding takes precedence over Content-Length
+    let mut trailers = HeaderMap::new();
+    let decoded_body = if !has_body(head.status) {
+        Vec::new()
+    } else if is_chunked(&head.headers) {
+        debug!("Decoding chunked transfer-encoding");
+        let chunked = decode_chunked_body(body)
+            .map_err(|e| TorError::http_request(format!("Failed to decode chunked body: {}", e)))?;
+        if !chunked.complete {
+            warn!(
+                "Chunked body ended early, keeping the {} bytes received",
+                chunked.body.len()
+            );
+        }
+        trailers = chunked.trailers;
+        chunked.body
+    } else {
+        match content_length(&head.headers) {
+            // Only enforce Content-Length for non-chunked responses
+            Some(len) if body.len() > len => {
+                debug!(
+                    "Body longer than Content-Length ({} > {}), truncating",
+                    body.len(),
+                    len
+                );
+                body[..len].to_vec()
+            }
+            _ => body.to_vec(),
+        }
+    };
+
+    debug!(
+        "Parsed response: status={}, headers={}, body_len={}",
+        head.status,
+        head.headers.len(),
+        decoded_body.len()
+    );
+
+    Ok(HttpResponse {
+        status: head.status,
+        status_text: head.status_text,
+        version: head.version,
+        headers: head.headers,
+        trailers,
+        body: decoded_body,
+        retries: 0,
+        url,
+        request_id: String::new(),
+    })
+}
 
-    let header_bytes = &data[..header_end];
-    let body = data[header_end + 4..].to_vec();
+/// Status line and headers of a response
+pub(crate) struct ResponseHead {
+    pub(crate) version: Version,
+    pub(crate) status: u16,
+    pub(crate) status_text: String,
+    pub(crate) headers: HeaderMap,
+}
 
-    let header_str = std::str::from_utf8(header_bytes)
+/// Parse the status line and headers, `head` ending before the blank line
+pub(crate) fn parse_head(head: &[u8]) -> Result<ResponseHead> {
+    let header_str = std::str::from_utf8(head)
         .map_err(|e| TorError::http_request(format!("Invalid HTTP headers: {}", e)))?;
 
     let mut lines = header_str.lines();
@@ -462,58 +1608,103 @@ fn parse_http_response(data: &[u8], url: Url) -> Result<HttpResponse> {
         return Err(TorError::http_request("Invalid HTTP status line"));
     }
 
+    let version = match parts[0] {
+        "HTTP/1.0" => Version::HTTP_10,
+        "HTTP/1.1" => Version::HTTP_11,
+        other => {
+            return Err(TorError::http_request(format!(
+                "Unsupported HTTP version: {}",
+                other
+            )))
+        }
+    };
+
     let status: u16 = parts[1]
         .parse()
         .map_err(|e| TorError::http_request(format!("Invalid status code: {}", e)))?;
+    let status_text = parts.get(2).copied().unwrap_or_default().trim().to_string();
 
-    // Parse headers
-    let mut headers = HashMap::new();
+    // Parse headers, keeping repeated ones (Set-Cookie, Link, ...) in order
+    let mut headers = HeaderMap::new();
     for line in lines {
-        if let Some((key, value)) = line.split_once(':') {
-            headers.insert(key.trim().to_lowercase(), value.trim().to_string());
-        }
+        append_field(&mut headers, line);
     }
 
-    // Decode body based on Transfer-Encoding or Content-Length
-    // Per HTTP/1.1 semantics: Transfer-Encoding takes precedence over Content-Length
-    let mut decoded_body = body;
-
-    let is_chunked = headers
-        .get("transfer-encoding")
-        .map(|te| te.to_ascii_lowercase().contains("chunked"))
-        .unwrap_or(false);
+    Ok(ResponseHead {
+        version,
+        status,
+        status_text,
+        headers,
+    })
+}
 
-    if is_chunked {
-        debug!("Decoding chunked transfer-encoding");
-        decoded_body = decode_chunked_body(&decoded_body)
-            .map_err(|e| TorError::http_request(format!("Failed to decode chunked body: {}", e)))?;
-    } else if let Some(cl) = headers.get("content-length") {
-        // Only enforce Content-Length for non-chunked responses
-        if let Ok(len) = cl.parse::<usize>() {
-            if decoded_body.len() > len {
-                debug!(
-                    "Body longer than Content-Length ({} > {}), truncating",
-                    decoded_body.len(),
-                    len
-                );
-                decoded_body.truncate(len);
-            }
+/// Add a `name: value` line to `headers`, skipping malformed ones
+fn append_field(headers: &mut HeaderMap, line: &str) {
+    let Some((key, value)) = line.split_once(':') else {
+        return;
+    };
+    match (
+        HeaderName::from_bytes(key.trim().as_bytes()),
+        HeaderValue::from_bytes(value.trim().as_bytes()),
+    ) {
+        (Ok(name), Ok(value)) => {
+            headers.append(name, value);
         }
+        _ => debug!("Skipping malformed response header line"),
     }
+}
 
-    debug!(
-        "Parsed response: status={}, headers={}, body_len={}",
-        status,
-        headers.len(),
-        decoded_body.len()
-    );
+/// Whether a response with `status` can carry a body at all
+fn has_body(status: u16) -> bool {
+    !matches!(status, 100..=199 | 204 | 304)
+}
 
-    Ok(HttpResponse {
-        status,
-        headers,
-        body: decoded_body,
-        url,
-    })
+/// Whether the body is chunked, which it is when chunked is the last
+/// transfer coding applied
+fn is_chunked(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(TRANSFER_ENCODING)
+        .iter()
+        .filter_map(|te| te.to_str().ok())
+        .flat_map(|te| te.split(','))
+        .last()
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+fn content_length(headers: &HeaderMap) -> Option<usize> {
+    headers
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Length of the complete response at the start of `data`, once all of it
+/// has arrived
+///
+/// Chunked and `Content-Length` bodies end where their framing says, so the
+/// read can stop there rather than wait for the exit to close the stream;
+/// `None` means keep reading, to the close if the body is delimited by it.
+fn response_length(data: &[u8]) -> Option<usize> {
+    let header_end = find_subsequence(data, b"\r\n\r\n")?;
+    let body_start = header_end + 4;
+    let Ok(head) = parse_head(&data[..header_end]) else {
+        // Let parsing report the error rather than wait for more
+        return Some(data.len());
+    };
+    if !has_body(head.status) {
+        return Some(body_start);
+    }
+    if is_chunked(&head.headers) {
+        return match decode_chunked_body(&data[body_start..]) {
+            Ok(chunked) => chunked.complete.then_some(body_start + chunked.consumed),
+            Err(_) => Some(data.len()),
+        };
+    }
+    let len = content_length(&head.headers)?;
+    (data.len() >= body_start + len).then_some(body_start + len)
 }
 
 /// Find the position of a subsequence in a byte slice
@@ -523,113 +1714,144 @@ fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
         .position(|window| window == needle)
 }
 
-/// Decode a chunked transfer-encoded body into plain bytes
-fn decode_chunked_body(body: &[u8]) -> std::result::Result<Vec<u8>, String> {
-    let mut result = Vec::new();
+/// A decoded chunked body
+#[derive(Debug)]
+struct ChunkedBody {
+    body: Vec<u8>,
+    /// Fields of the trailer section after the last chunk
+    trailers: HeaderMap,
+    /// Whether the last chunk and the trailer section had all arrived;
+    /// otherwise `body` holds what there was
+    complete: bool,
+    /// Bytes of input the encoded body took up
+    consumed: usize,
+}
+
+/// Decode a chunked transfer-encoded body, trailers included
+///
+/// Decoding is lenient about stray blank lines and a missing CRLF after a
+/// chunk; trailing garbage after at least one chunk ends the body.
+fn decode_chunked_body(data: &[u8]) -> std::result::Result<ChunkedBody, String> {
+    /// The line starting at `start`, and where the next one starts
+    fn line_at(data: &[u8], start: usize) -> Option<(&[u8], usize)> {
+        let len = find_subsequence(&data[start..], b"\r\n")?;
+        Some((&data[start..start + len], start + len + 2))
+    }
+
+    let mut chunked = ChunkedBody {
+        body: Vec::new(),
+        trailers: HeaderMap::new(),
+        complete: false,
+        consumed: 0,
+    };
     let mut i = 0;
 
     loop {
         // Skip any leading whitespace/CRLF (some servers send extra)
-        while i < body.len() && (body[i] == b'\r' || body[i] == b'\n' || body[i] == b' ') {
+        while i < data.len() && matches!(data[i], b'\r' | b'\n' | b' ') {
             i += 1;
         }
 
-        if i >= body.len() {
-            break;
-        }
-
         // Find end of chunk-size line
-        let line_start = i;
-        let mut line_end_opt = None;
-        while i + 1 < body.len() {
-            if body[i] == b'\r' && body[i + 1] == b'\n' {
-                line_end_opt = Some(i);
-                break;
-            }
-            i += 1;
-        }
-        let line_end = match line_end_opt {
-            Some(end) => end,
-            None => {
-                // No CRLF found - might be incomplete or malformed
-                // If we have some data already, return what we have
-                if !result.is_empty() {
-                    debug!("Incomplete chunk size line, returning partial result");
-                    break;
-                }
-                return Err("Incomplete chunk size line".into());
-            }
+        let Some((line, next)) = line_at(data, i) else {
+            chunked.consumed = i;
+            return Ok(chunked);
         };
 
-        let line = &body[line_start..line_end];
-
         // Parse hex size, ignoring any ";extensions"
-        let size_str = match std::str::from_utf8(line) {
-            Ok(s) => s.split(';').next().unwrap_or("").trim(),
-            Err(_) => return Err("Chunk size line is not valid UTF-8".into()),
-        };
-
-        // Handle empty line (shouldn't happen but be defensive)
-        if size_str.is_empty() {
-            i = line_end + 2;
-            continue;
-        }
-
+        let size_str = std::str::from_utf8(line)
+            .map_err(|_| "Chunk size line is not valid UTF-8".to_string())?
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim();
         let size = match usize::from_str_radix(size_str, 16) {
-            Ok(s) => s,
-            Err(e) => {
-                // If we already have data and hit parse error, might be trailing garbage
-                if !result.is_empty() {
-                    debug!(
-                        "Failed to parse chunk size '{}', returning partial result: {}",
-                        size_str, e
-                    );
-                    break;
-                }
-                return Err(format!("Invalid chunk size '{}': {}", size_str, e));
+            Ok(size) => size,
+            // Trailing garbage after some data ends the body
+            Err(e) if !chunked.body.is_empty() => {
+                debug!(
+                    "Failed to parse chunk size '{}', ending body: {}",
+                    size_str, e
+                );
+                chunked.complete = true;
+                chunked.consumed = i;
+                return Ok(chunked);
             }
+            Err(e) => return Err(format!("Invalid chunk size '{}': {}", size_str, e)),
         };
-
-        // Move past "\r\n"
-        i = line_end + 2;
+        i = next;
 
         // Size 0 means end of chunks
         if size == 0 {
             break;
         }
 
-        // Ensure enough bytes for this chunk
-        if i + size > body.len() {
-            // Partial chunk - take what we can
-            let available = body.len() - i;
+        // Partial chunk - take what we can
+        let available = data.len() - i;
+        if available < size {
             debug!(
                 "Chunk extends beyond body length (need {}, have {}), taking available",
                 size, available
             );
-            result.extend_from_slice(&body[i..]);
-            break;
+            chunked.body.extend_from_slice(&data[i..]);
+            chunked.consumed = data.len();
+            return Ok(chunked);
         }
-
-        // Copy chunk bytes
-        result.extend_from_slice(&body[i..i + size]);
+        chunked.body.extend_from_slice(&data[i..i + size]);
         i += size;
 
-        // Each chunk is followed by "\r\n"
-        if i + 1 < body.len() && body[i] == b'\r' && body[i + 1] == b'\n' {
-            i += 2;
+        // Each chunk is followed by "\r\n"; wait for it so a read that stops
+        // here isn't mistaken for the next size line
+        match data.get(i..i + 2) {
+            Some(b"\r\n") => i += 2,
+            Some(_) => {}
+            None => {
+                chunked.consumed = i;
+                return Ok(chunked);
+            }
+        }
+    }
+
+    // Trailer fields, up to a blank line
+    loop {
+        let Some((line, next)) = line_at(data, i) else {
+            chunked.consumed = i;
+            return Ok(chunked);
+        };
+        i = next;
+        if line.is_empty() {
+            break;
+        }
+        match std::str::from_utf8(line) {
+            Ok(line) => append_field(&mut chunked.trailers, line),
+            Err(_) => debug!("Skipping non-UTF-8 trailer line"),
         }
     }
 
-    Ok(result)
+    chunked.complete = true;
+    chunked.consumed = i;
+    Ok(chunked)
 }
 
 /// HTTP response from Tor
 #[derive(Debug, Clone)]
 pub struct HttpResponse {
     pub status: u16,
-    pub headers: HashMap<String, String>,
+    /// Reason phrase from the status line, e.g. `Not Found`; may be empty
+    pub status_text: String,
+    pub version: Version,
+    /// Response headers; lookups ignore case and repeated headers keep
+    /// every value
+    pub headers: HeaderMap,
+    /// Trailer fields sent after a chunked body
+    pub trailers: HeaderMap,
     pub body: Vec<u8>,
+    /// Times the request was retried on another circuit, over all redirects
+    pub retries: u32,
+    /// URL the response was served from
     pub url: Url,
+    /// ID of the request that produced this response
+    pub request_id: String,
 }
 
 impl HttpResponse {
@@ -637,6 +1859,44 @@ impl HttpResponse {
         self.status >= 200 && self.status < 300
     }
 
+    /// First value of header `name`, if it's valid text
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)?.to_str().ok()
+    }
+
+    /// Every value of header `name`, in the order received
+    pub fn header_all(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+
+    /// Headers as a name to value map, repeated headers joined with `, `
+    /// as the Fetch API's `Headers.get` does
+    pub fn headers_map(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        for name in self.headers.keys() {
+            map.insert(name.to_string(), self.header_all(name.as_str()).join(", "));
+        }
+        map
+    }
+
+    /// First value of trailer field `name`, if it's valid text
+    pub fn trailer(&self, name: &str) -> Option<&str> {
+        self.trailers.get(name)?.to_str().ok()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("content-type")
+    }
+
+    /// The range of the resource a 206 response holds
+    pub fn content_range(&self) -> Option<ContentRange> {
+        ContentRange::parse(self.header("content-range")?)
+    }
+
     pub fn text(&self) -> Result<String> {
         String::from_utf8(self.body.clone())
             .map_err(|e| TorError::serialization(format!("Invalid UTF-8 in response: {}", e)))
@@ -667,6 +1927,44 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_build_request_with_body() {
+        let url = Url::parse("https://httpbin.org/anything").unwrap();
+        let request = HttpRequest::new(url.clone())
+            .with_method(Method::PUT)
+            .with_header("content-length", "999")
+            .with_text_body("héllo");
+        assert_eq!(
+            request.header("CONTENT-TYPE"),
+            Some("text/plain;charset=UTF-8")
+        );
+
+        let request_str =
+            String::from_utf8(request.build_request("httpbin.org", RequestProfile::Webtor))
+                .unwrap();
+        assert!(request_str.starts_with("PUT /anything HTTP/1.1\r\n"));
+        assert!(request_str.contains("Content-Length: 6\r\n"));
+        assert!(!request_str.contains("999"));
+        assert!(request_str.ends_with("\r\n\r\nhéllo"));
+
+        let request = HttpRequest::new(url.clone())
+            .with_header("content-type", "text/csv")
+            .with_json_body(&serde_json::json!({"a": 1}))
+            .unwrap()
+            .with_method(Method::PATCH);
+        assert_eq!(request.header("Content-Type"), Some("application/json"));
+        assert_eq!(request.body.as_deref(), Some(&b"{\"a\":1}"[..]));
+
+        // An empty POST still declares its length
+        let request_str = String::from_utf8(
+            HttpRequest::new(url)
+                .with_method(Method::POST)
+                .build_request("httpbin.org", RequestProfile::Webtor),
+        )
+        .unwrap();
+        assert!(request_str.contains("Content-Length: 0\r\n"));
+    }
+
     #[tokio::test]
     async fn test_http_request_creation() {
         let url = Url::parse("https://httpbin.org/ip").unwrap();
@@ -689,13 +1987,59 @@ mod tests {
         let url = Url::parse("http://example.com/path?query=1").unwrap();
         let request = HttpRequest::new(url).with_header("X-Custom", "value");
 
-        let bytes = request.build_request("example.com");
+        let bytes = request.build_request("example.com", RequestProfile::Webtor);
         let request_str = String::from_utf8(bytes).unwrap();
 
         assert!(request_str.starts_with("GET /path?query=1 HTTP/1.1\r\n"));
         assert!(request_str.contains("Host: example.com\r\n"));
         assert!(request_str.contains("X-Custom: value\r\n"));
         assert!(request_str.ends_with("\r\n\r\n"));
+
+        let bytes = request
+            .with_header("accept-language", "de")
+            .build_request("example.com", RequestProfile::TorBrowser);
+        let request_str = String::from_utf8(bytes).unwrap();
+        assert!(request_str.starts_with(&format!(
+            "GET /path?query=1 HTTP/1.1\r\nHost: example.com\r\nUser-Agent: {}\r\nAccept: text/html,",
+            crate::profile::TOR_BROWSER_USER_AGENT
+        )));
+        assert!(request_str.contains("Accept-Language: de\r\nConnection: close\r\n"));
+        assert!(request_str.ends_with("Priority: u=0, i\r\nX-Custom: value\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_custom_headers_merge_with_defaults() {
+        let url = Url::parse("https://api.example.com/v1").unwrap();
+        let request = HttpRequest::new(url)
+            .with_header("accept", "text/html")
+            .with_headers([
+                ("Accept", "application/json"),
+                ("Authorization", "Bearer t"),
+            ])
+            .with_header("HOST", "evil.example")
+            .with_header("Connection", "keep-alive");
+        assert_eq!(request.headers.len(), 4);
+        request.validate_headers().unwrap();
+
+        let request_str =
+            String::from_utf8(request.build_request("api.example.com", RequestProfile::Webtor))
+                .unwrap();
+        assert!(request_str.contains("Accept: application/json\r\n"));
+        assert!(!request_str.contains("*/*"));
+        assert!(request_str.contains("User-Agent: webtor-rs/0.1.0\r\n"));
+        assert!(request_str.contains("Authorization: Bearer t\r\n"));
+        assert!(request_str.contains("Host: api.example.com\r\nConnection: close\r\n"));
+        assert!(!request_str.contains("evil") && !request_str.contains("keep-alive"));
+
+        let injected = HttpRequest::default().with_header("X-Note", "a\r\nHost: evil.example");
+        assert!(matches!(
+            injected.validate_headers(),
+            Err(TorError::Configuration(_))
+        ));
+        assert!(HttpRequest::default()
+            .with_header("Bad Name", "x")
+            .validate_headers()
+            .is_err());
     }
 
     #[test]
@@ -706,20 +2050,133 @@ mod tests {
         let response = parse_http_response(response_bytes, url).unwrap();
 
         assert_eq!(response.status, 200);
+        assert_eq!(response.status_text, "OK");
+        assert_eq!(response.version, Version::HTTP_11);
+        assert_eq!(response.content_type(), Some("text/plain"));
+        assert_eq!(response.text().unwrap(), "Hello, World!");
+    }
+
+    #[test]
+    fn test_decode_content() {
+        use std::io::Write;
+        let json = br#"{"ok":true}"#;
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(json).unwrap();
+        let mut raw_deflate =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        raw_deflate.write_all(&gzip.finish().unwrap()).unwrap();
+        let compressed = raw_deflate.finish().unwrap();
+
+        let mut response = parse_http_response(
+            &[
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Encoding: gzip, deflate\r\nContent-Length: {}\r\n\r\n",
+                    compressed.len()
+                )
+                .as_bytes(),
+                &compressed,
+            ]
+            .concat(),
+            Url::parse("https://api.example.com/").unwrap(),
+        )
+        .unwrap();
+        decode_content(&mut response, 0).unwrap();
+        assert_eq!(response.body, json);
+        assert!(response.header("content-encoding").is_none());
+        assert!(response.header("content-length").is_none());
+
+        let mut brotli_body = Vec::new();
+        brotli::CompressorWriter::new(&mut brotli_body, 4096, 5, 22)
+            .write_all(json)
+            .unwrap();
+        response.body = brotli_body;
+        response
+            .headers
+            .insert("content-encoding", HeaderValue::from_static("br"));
+        decode_content(&mut response, 0).unwrap();
+        assert_eq!(response.body, json);
+
+        // Unknown encodings and corrupt bodies
+        response.body = b"opaque".to_vec();
+        response
+            .headers
+            .insert("content-encoding", HeaderValue::from_static("zstd"));
+        decode_content(&mut response, 0).unwrap();
+        assert_eq!(response.body, b"opaque");
+        response
+            .headers
+            .insert("content-encoding", HeaderValue::from_static("gzip"));
+        assert!(decode_content(&mut response, 0).is_err());
+    }
+
+    #[test]
+    fn test_response_size_is_capped() {
+        use std::io::Write;
+
+        let request = HttpRequest::new(Url::parse("https://example.com/").unwrap());
+        let limits = AttemptLimits::start(&request, 1024);
+        assert!(limits.check_size(1024).is_ok());
+        assert!(matches!(
+            limits.check_size(1025),
+            Err(TorError::ResponseTooLarge { limit: 1024 })
+        ));
+        assert!(AttemptLimits::start(&request, 0)
+            .check_size(usize::MAX)
+            .is_ok());
+
+        // A small body can't inflate past the cap either
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(&vec![0u8; 1 << 20]).unwrap();
+        let bomb = gzip.finish().unwrap();
+        assert!(bomb.len() < 4096);
+        let mut response = parse_http_response(
+            &[
+                &b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n\r\n"[..],
+                &bomb,
+            ]
+            .concat(),
+            Url::parse("https://example.com/").unwrap(),
+        )
+        .unwrap();
+        let err = decode_content(&mut response.clone(), 4096).unwrap_err();
+        assert_eq!(err.code(), "RESPONSE_TOO_LARGE");
+        decode_content(&mut response, 1 << 20).unwrap();
+        assert_eq!(response.body.len(), 1 << 20);
+    }
+
+    #[test]
+    fn test_response_headers_are_a_case_insensitive_multimap() {
+        let response_bytes = b"HTTP/1.0 429 Too Many Requests\r\n\
+            Set-Cookie: a=1\r\n\
+            X-RateLimit-Remaining: 0\r\n\
+            set-cookie: b=2\r\n\
+            \r\n";
+        let url = Url::parse("http://example.com/").unwrap();
+        let response = parse_http_response(response_bytes, url).unwrap();
+
         assert_eq!(
-            response.headers.get("content-type"),
-            Some(&"text/plain".to_string())
+            (response.status, response.status_text.as_str()),
+            (429, "Too Many Requests")
         );
-        assert_eq!(response.text().unwrap(), "Hello, World!");
+        assert_eq!(response.version, Version::HTTP_10);
+        assert_eq!(response.header("x-ratelimit-remaining"), Some("0"));
+        assert_eq!(response.header_all("SET-COOKIE"), vec!["a=1", "b=2"]);
+        assert_eq!(response.headers_map()["set-cookie"], "a=1, b=2");
     }
 
     #[tokio::test]
     async fn test_http_response() {
         let response = HttpResponse {
             status: 200,
-            headers: HashMap::new(),
+            status_text: "OK".to_string(),
+            version: Version::HTTP_11,
+            headers: HeaderMap::new(),
+            trailers: HeaderMap::new(),
+            retries: 0,
             body: b"{\"ip\": \"127.0.0.1\"}".to_vec(),
             url: Url::parse("https://httpbin.org/ip").unwrap(),
+            request_id: new_request_id(),
         };
 
         assert!(response.is_success());
@@ -756,11 +2213,360 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_onion_urls_go_to_the_onion_connector() {
+        use futures::StreamExt;
+
+        const ONION: &str = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+        let events = CircuitEvents::new();
+        let mut onion_events = events.subscribe_onion();
+        let circuit_manager = Arc::new(RwLock::new(
+            CircuitManager::new(
+                Arc::new(RwLock::new(RelayManager::new(Vec::new()))),
+                Arc::new(RwLock::new(None)),
+            )
+            .with_events(events),
+        ));
+        let url = format!("http://{}/", ONION);
+
+        // Without a connector the name is refused before reaching anything
+        let http_client =
+            TorHttpClient::new(circuit_manager.clone(), StreamIsolationPolicy::PerDomain);
+        let err = http_client.get(&url).await.unwrap_err();
+        assert!(matches!(err.inner(), TorError::OnionServicesUnsupported(_)));
+
+        // With one, the request tries to join the service; with no
+        // consensus to find its HSDirs that fails, and says so
+        let connector = Arc::new(OnionConnector::new(circuit_manager.clone()));
+        let http_client = http_client.with_onion_connector(connector);
+        let err = http_client.get(&url).await.unwrap_err();
+        assert!(!matches!(
+            err.inner(),
+            TorError::OnionServicesUnsupported(_)
+        ));
+        match onion_events.next().await.unwrap() {
+            crate::events::OnionEvent::Failed { address, .. } => assert_eq!(address, ONION),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Onion services get their own circuits, never a caller's
+        let pinned = http_client.on_circuit("circ_1");
+        assert!(matches!(
+            pinned.get(&url).await.unwrap_err().inner(),
+            TorError::Configuration(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_requests_report_their_stream_closed() {
+        use futures::StreamExt;
+
+        let circuit_manager = Arc::new(RwLock::new(CircuitManager::new(
+            Arc::new(RwLock::new(RelayManager::new(Vec::new()))),
+            Arc::new(RwLock::new(None)),
+        )));
+        let http_client = TorHttpClient::new(circuit_manager, StreamIsolationPolicy::PerDomain);
+        let token = CancellationToken::new();
+        token.cancel();
+        let request =
+            HttpRequest::new(Url::parse("https://example.com/").unwrap()).with_cancellation(token);
+        let err = http_client.request(request).await.unwrap_err();
+        assert!(matches!(err.inner(), TorError::Cancelled));
+
+        // A stream dropped mid-exchange is still reported closed, once
+        let events = CircuitEvents::new();
+        let mut subscriber = events.subscribe();
+        let finished = AttachedStream::new(
+            &events,
+            "circuit_1",
+            1,
+            "example.com:443".to_string(),
+            "req-1",
+            TrafficCounter::new(),
+        );
+        finished.finish::<()>(&Ok(()));
+        drop(AttachedStream::new(
+            &events,
+            "circuit_1",
+            2,
+            "example.com:443".to_string(),
+            "req-2",
+            TrafficCounter::new(),
+        ));
+        drop(events);
+
+        let closed: Vec<_> = subscriber
+            .by_ref()
+            .filter_map(|event| async move {
+                match event {
+                    CircuitEvent::StreamAttached { .. } => None,
+                    CircuitEvent::StreamClosed { error, .. } => Some(error),
+                    other => panic!("unexpected {:?}", other),
+                }
+            })
+            .collect()
+            .await;
+        assert_eq!(closed, vec![None, Some("Operation cancelled".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_events_carry_the_request_id() {
+        use futures::StreamExt;
+
+        let events = CircuitEvents::new();
+        let mut subscriber = events.subscribe();
+        let attached = AttachedStream::new(
+            &events,
+            "circuit_1",
+            1,
+            "example.com:443".to_string(),
+            "req-1",
+            TrafficCounter::new(),
+        );
+        attached.finish::<()>(&Err(TorError::Network("reset".to_string())));
+
+        match subscriber.next().await.unwrap() {
+            CircuitEvent::StreamAttached {
+                stream_id,
+                target,
+                request_id,
+                ..
+            } => {
+                assert_eq!((stream_id, target.as_str()), (1, "example.com:443"));
+                assert_eq!(request_id.as_deref(), Some("req-1"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let closed = subscriber.next().await.unwrap();
+        match &closed {
+            CircuitEvent::StreamClosed {
+                stream_id,
+                error,
+                request_id,
+                ..
+            } => {
+                assert_eq!(*stream_id, 1);
+                assert!(error.is_some());
+                assert_eq!(request_id.as_deref(), Some("req-1"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let json = serde_json::to_value(&closed).unwrap();
+        assert_eq!(json["requestId"], "req-1");
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_report_their_phase() {
+        let limit = Duration::from_millis(10);
+        let err = within(
+            TimeoutPhase::FirstByte,
+            Some(limit),
+            futures::future::pending::<Result<()>>(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.timeout_phase(), Some(TimeoutPhase::FirstByte));
+        assert!(within(TimeoutPhase::Total, None, async { Ok(()) })
+            .await
+            .is_ok());
+
+        // A spent connect budget still reports the limit the caller chose
+        let request = HttpRequest::new(Url::parse("https://example.com/").unwrap())
+            .with_connect_timeout(Duration::ZERO);
+        let err = AttemptLimits::start(&request, 0)
+            .connect(futures::future::pending::<Result<()>>())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            TorError::RequestTimeout {
+                phase: TimeoutPhase::Connect,
+                limit,
+            } if limit.is_zero()
+        ));
+    }
+
+    #[test]
+    fn test_retriable_failures_reattach() {
+        let circuit_manager = Arc::new(RwLock::new(CircuitManager::new(
+            Arc::new(RwLock::new(RelayManager::new(Vec::new()))),
+            Arc::new(RwLock::new(None)),
+        )));
+        let http_client = TorHttpClient::new(circuit_manager, StreamIsolationPolicy::PerDomain)
+            .with_stream_retries(1);
+
+        let get = HttpRequest::new(Url::parse("https://example.com/").unwrap());
+        let closed = TorError::circuit_closed("DESTROY received");
+        assert_eq!(
+            http_client.retry_reason(&get, &closed, true, 0, 1),
+            Some(RetryReason::CircuitClosed)
+        );
+        assert_eq!(http_client.retry_reason(&get, &closed, true, 1, 1), None);
+        assert_eq!(
+            http_client.retry_reason(&get, &TorError::tls("bad certificate"), false, 0, 1),
+            None
+        );
+
+        // Exits turning the stream away and resets are retried; the
+        // destination failing is not
+        let rejected = TorError::StreamEnded(StreamEndReason::ExitPolicy);
+        assert_eq!(
+            RetryReason::classify(&rejected.with_request_id("r1")),
+            Some(RetryReason::ExitRejected)
+        );
+        let refused = TorError::StreamEnded(StreamEndReason::ConnectRefused);
+        assert_eq!(RetryReason::classify(&refused), None);
+        let reset = TorError::Io(std::io::ErrorKind::ConnectionReset.into());
+        assert_eq!(
+            RetryReason::classify(&reset),
+            Some(RetryReason::ConnectionReset)
+        );
+
+        // A pinned client reports the failure instead of switching circuits
+        let pinned = http_client.on_circuit("circ_1");
+        assert_eq!(pinned.retry_reason(&get, &closed, false, 0, 1), None);
+    }
+
+    #[test]
+    fn test_sent_requests_retry_only_if_idempotent() {
+        let circuit_manager = Arc::new(RwLock::new(CircuitManager::new(
+            Arc::new(RwLock::new(RelayManager::new(Vec::new()))),
+            Arc::new(RwLock::new(None)),
+        )));
+        let http_client = TorHttpClient::new(circuit_manager, StreamIsolationPolicy::PerDomain);
+        let url = Url::parse("https://example.com/").unwrap();
+        let timeout = |phase| TorError::RequestTimeout {
+            phase,
+            limit: Duration::from_secs(1),
+        };
+        let failures = [
+            (
+                TorError::circuit_closed("DESTROY received"),
+                RetryReason::CircuitClosed,
+            ),
+            (
+                TorError::StreamEnded(StreamEndReason::ExitPolicy),
+                RetryReason::ExitRejected,
+            ),
+            (
+                TorError::Io(std::io::ErrorKind::ConnectionReset.into()),
+                RetryReason::ConnectionReset,
+            ),
+            (timeout(TimeoutPhase::Connect), RetryReason::Timeout),
+            (timeout(TimeoutPhase::FirstByte), RetryReason::Timeout),
+        ];
+
+        for method in [Method::GET, Method::PUT, Method::DELETE] {
+            let request = HttpRequest::new(url.clone()).with_method(method.clone());
+            for (err, reason) in &failures {
+                for sent in [false, true] {
+                    assert_eq!(
+                        http_client.retry_reason(&request, err, sent, 0, 1),
+                        Some(*reason),
+                        "{} after {} (sent: {})",
+                        method,
+                        err,
+                        sent
+                    );
+                }
+            }
+        }
+        for method in [Method::POST, Method::PATCH] {
+            let request = HttpRequest::new(url.clone()).with_method(method.clone());
+            for (err, reason) in &failures {
+                assert_eq!(
+                    http_client.retry_reason(&request, err, false, 0, 1),
+                    Some(*reason),
+                    "unsent {} after {}",
+                    method,
+                    err
+                );
+                assert_eq!(
+                    http_client.retry_reason(&request, err, true, 0, 1),
+                    None,
+                    "sent {} after {}",
+                    method,
+                    err
+                );
+            }
+        }
+
+        // The total limit is the caller's deadline, so nothing outlives it
+        for method in [Method::GET, Method::POST] {
+            let request = HttpRequest::new(url.clone()).with_method(method);
+            for sent in [false, true] {
+                assert_eq!(
+                    http_client.retry_reason(&request, &timeout(TimeoutPhase::Total), sent, 0, 1),
+                    None
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_attempt_notes_when_request_is_sent() {
+        let request = HttpRequest::new(Url::parse("https://example.com/").unwrap())
+            .with_connect_timeout(Duration::ZERO);
+
+        // Failing to connect leaves the request unsent
+        let limits = AttemptLimits::start(&request, 0);
+        limits
+            .connect(futures::future::pending::<Result<()>>())
+            .await
+            .unwrap_err();
+        assert!(!limits.request_sent());
+
+        // A stream that resets while the request is written may have
+        // delivered part of it
+        let limits = AttemptLimits::start(&request, 0);
+        execute_http_request(ResetStream, b"POST / HTTP/1.1\r\n\r\n", &limits)
+            .await
+            .unwrap_err();
+        assert!(limits.request_sent());
+    }
+
+    /// Stream the connection drops under as soon as it is used
+    struct ResetStream;
+
+    impl futures::AsyncRead for ResetStream {
+        fn poll_read(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            _buf: &mut [u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            std::task::Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()))
+        }
+    }
+
+    impl futures::AsyncWrite for ResetStream {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            _buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            std::task::Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
     #[test]
     fn test_decode_chunked_body_single_chunk() {
         // Single chunk: "Hello" (5 bytes = 0x5)
         let chunked = b"5\r\nHello\r\n0\r\n\r\n";
-        let decoded = decode_chunked_body(chunked).unwrap();
+        let decoded = decode_chunked_body(chunked).unwrap().body;
         assert_eq!(decoded, b"Hello");
     }
 
@@ -768,7 +2574,7 @@ mod tests {
     fn test_decode_chunked_body_multiple_chunks() {
         // Two chunks: "Hello" + " World"
         let chunked = b"5\r\nHello\r\n6\r\n World\r\n0\r\n\r\n";
-        let decoded = decode_chunked_body(chunked).unwrap();
+        let decoded = decode_chunked_body(chunked).unwrap().body;
         assert_eq!(decoded, b"Hello World");
     }
 
@@ -776,7 +2582,7 @@ mod tests {
     fn test_decode_chunked_body_with_extension() {
         // Chunk with extension (should be ignored)
         let chunked = b"5;name=value\r\nHello\r\n0\r\n\r\n";
-        let decoded = decode_chunked_body(chunked).unwrap();
+        let decoded = decode_chunked_body(chunked).unwrap().body;
         assert_eq!(decoded, b"Hello");
     }
 
@@ -786,7 +2592,7 @@ mod tests {
         let json = r#"{"jsonrpc":"2.0","id":1,"result":"0x1234"}"#;
         let hex_len = format!("{:x}", json.len());
         let chunked = format!("{}\r\n{}\r\n0\r\n\r\n", hex_len, json);
-        let decoded = decode_chunked_body(chunked.as_bytes()).unwrap();
+        let decoded = decode_chunked_body(chunked.as_bytes()).unwrap().body;
         assert_eq!(String::from_utf8(decoded).unwrap(), json);
     }
 
@@ -818,15 +2624,53 @@ mod tests {
     #[test]
     fn test_decode_chunked_body_leading_crlf() {
         let chunked = b"\r\n\r\n5\r\nHello\r\n0\r\n\r\n";
-        let decoded = decode_chunked_body(chunked).unwrap();
+        let decoded = decode_chunked_body(chunked).unwrap().body;
         assert_eq!(decoded, b"Hello");
     }
 
     #[test]
     fn test_decode_chunked_body_with_trailers() {
         let chunked = b"5\r\nHello\r\n0\r\nX-Foo: bar\r\nAnother: header\r\n\r\nGarbageAfter";
-        let decoded = decode_chunked_body(chunked).unwrap();
+        let decoded = decode_chunked_body(chunked).unwrap().body;
         assert_eq!(decoded, b"Hello");
+
+        let response = parse_http_response(
+            &[
+                &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: X-Foo\r\n\r\n"[..],
+                chunked,
+            ]
+            .concat(),
+            Url::parse("http://example.com/").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(response.trailer("x-foo"), Some("bar"));
+        assert_eq!(response.trailer("another"), Some("header"));
+        assert!(response.header("x-foo").is_none());
+    }
+
+    #[test]
+    fn test_response_length_follows_framing() {
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n0\r\nX-Foo: bar\r\n\r\n";
+        // Complete only once the trailer section's blank line arrives,
+        // whatever follows
+        for end in [47, 52, 57, 60, chunked.len() - 1] {
+            assert_eq!(response_length(&chunked[..end]), None, "{}", end);
+        }
+        assert_eq!(response_length(chunked), Some(chunked.len()));
+        assert_eq!(
+            response_length(&[&chunked[..], b"HTTP/1.1"].concat()),
+            Some(chunked.len())
+        );
+
+        let sized = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello";
+        assert_eq!(response_length(&sized[..sized.len() - 1]), None);
+        assert_eq!(response_length(sized), Some(sized.len()));
+        assert_eq!(
+            response_length(b"HTTP/1.1 204 No Content\r\nContent-Length: 5\r\n\r\n"),
+            Some(46)
+        );
+        // Without framing the body runs to the close
+        assert_eq!(response_length(b"HTTP/1.0 200 OK\r\n\r\nHello"), None);
     }
 
     #[test]
@@ -840,7 +2684,7 @@ mod tests {
     fn test_decode_chunked_body_partial_second_chunk_returns_partial() {
         // First chunk "Hello", second chunk claims 5 bytes but only 2 available
         let chunked = b"5\r\nHello\r\n5\r\nWo";
-        let decoded = decode_chunked_body(chunked).unwrap();
+        let decoded = decode_chunked_body(chunked).unwrap().body;
         assert_eq!(decoded, b"HelloWo");
     }
 
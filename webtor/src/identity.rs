@@ -0,0 +1,137 @@
+//! Checking who is on the far end of a channel
+//!
+//! A bridge proves its identity in the channel handshake: its CERTS cell
+//! binds the link TLS certificate to an ed25519 identity, which the RSA
+//! identity key cross-certifies. The transport in between (a WebTunnel
+//! server, a Snowflake proxy) only forwards bytes, so this is what tells the
+//! client it reached the configured bridge and not whoever runs the proxy.
+//! [`ExpectedIdentity`] holds the configured fingerprint, plus the ed25519
+//! identity when it is known, and rejects a channel whose peer proved
+//! anything else with [`TorError::IdentityMismatch`].
+
+use crate::error::{Result, TorError};
+use base64::Engine;
+use tor_linkspec::HasRelayIds;
+use tor_llcrypto::pk::ed25519::Ed25519Identity;
+use tor_llcrypto::pk::rsa::RsaIdentity;
+
+/// The identities a channel's peer must prove
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectedIdentity {
+    pub rsa: RsaIdentity,
+    pub ed25519: Option<Ed25519Identity>,
+}
+
+impl ExpectedIdentity {
+    /// From a hex RSA fingerprint and an optional ed25519 identity (hex or
+    /// base64)
+    pub fn parse(fingerprint: &str, ed25519: Option<&str>) -> Result<Self> {
+        Ok(Self {
+            rsa: parse_rsa_identity(fingerprint)?,
+            ed25519: ed25519.map(parse_ed25519_identity).transpose()?,
+        })
+    }
+
+    /// Fail with [`TorError::IdentityMismatch`] unless `peer` has the
+    /// expected identities
+    pub fn check<T: HasRelayIds + ?Sized>(&self, peer: &T) -> Result<()> {
+        let rsa_matches = peer.rsa_identity() == Some(&self.rsa);
+        let ed25519_matches = self
+            .ed25519
+            .is_none_or(|ed25519| peer.ed_identity() == Some(&ed25519));
+        if rsa_matches && ed25519_matches {
+            return Ok(());
+        }
+        Err(TorError::IdentityMismatch {
+            expected: describe(Some(&self.rsa), self.ed25519.as_ref()),
+            actual: describe(peer.rsa_identity(), peer.ed_identity()),
+        })
+    }
+}
+
+/// Parse a hex relay fingerprint into an RSA identity
+pub fn parse_rsa_identity(fingerprint: &str) -> Result<RsaIdentity> {
+    let bytes = hex::decode(fingerprint)
+        .map_err(|e| TorError::Configuration(format!("Invalid fingerprint hex: {}", e)))?;
+    if bytes.len() != 20 {
+        return Err(TorError::Configuration(
+            "Fingerprint must be 40 hex characters (20 bytes)".to_string(),
+        ));
+    }
+    RsaIdentity::from_bytes(&bytes)
+        .ok_or_else(|| TorError::Configuration("Invalid RSA identity bytes".to_string()))
+}
+
+/// Parse an ed25519 identity given as hex or (unpadded) base64
+pub fn parse_ed25519_identity(id: &str) -> Result<Ed25519Identity> {
+    let id = id.trim();
+    let bytes = hex::decode(id)
+        .or_else(|_| {
+            base64::engine::general_purpose::STANDARD_NO_PAD.decode(id.trim_end_matches('='))
+        })
+        .map_err(|_| {
+            TorError::Configuration("ed25519 identity must be hex or base64".to_string())
+        })?;
+    Ed25519Identity::from_bytes(&bytes)
+        .ok_or_else(|| TorError::Configuration("ed25519 identity must be 32 bytes".to_string()))
+}
+
+fn describe(rsa: Option<&RsaIdentity>, ed25519: Option<&Ed25519Identity>) -> String {
+    let rsa = rsa.map_or_else(
+        || "no RSA identity".to_string(),
+        |rsa| hex::encode_upper(rsa.as_bytes()),
+    );
+    match ed25519 {
+        Some(ed25519) => format!("{} (ed25519 {})", rsa, ed25519),
+        None => rsa,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tor_linkspec::RelayIds;
+
+    const RSA: &str = "4A0CCD2DDC7995083D73F5D667100C8A5831F16D";
+    const ED25519: &str = "dGVzdHRlc3R0ZXN0dGVzdHRlc3R0ZXN0dGVzdHRlc3Q";
+
+    fn peer(rsa: &str, ed25519: Ed25519Identity) -> RelayIds {
+        RelayIds::builder()
+            .rsa_identity(parse_rsa_identity(rsa).unwrap())
+            .ed_identity(ed25519)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_rsa_only() {
+        let expected = ExpectedIdentity::parse(RSA, None).unwrap();
+        assert!(expected
+            .check(&peer(RSA, Ed25519Identity::new([1; 32])))
+            .is_ok());
+
+        let err = expected
+            .check(&peer(&"AB".repeat(20), Ed25519Identity::new([1; 32])))
+            .unwrap_err();
+        assert!(matches!(err, TorError::IdentityMismatch { .. }));
+        assert_eq!(err.code(), "IDENTITY_MISMATCH");
+    }
+
+    #[test]
+    fn test_ed25519() {
+        let expected = ExpectedIdentity::parse(RSA, Some(ED25519)).unwrap();
+        let ed25519 = expected.ed25519.unwrap();
+        assert_eq!(ed25519.as_bytes(), b"testtesttesttesttesttesttesttest");
+        assert!(expected.check(&peer(RSA, ed25519)).is_ok());
+        assert!(expected
+            .check(&peer(RSA, Ed25519Identity::new([1; 32])))
+            .is_err());
+
+        let hex = hex::encode(ed25519.as_bytes());
+        assert_eq!(
+            ExpectedIdentity::parse(RSA, Some(&hex)).unwrap().ed25519,
+            Some(ed25519)
+        );
+        assert!(ExpectedIdentity::parse(RSA, Some("AAAA")).is_err());
+    }
+}
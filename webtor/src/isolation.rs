@@ -3,17 +3,25 @@
 //! This module implements stream isolation to prevent cross-site correlation
 //! at exit relays. Different domains use different Tor circuits, following
 //! Tor Browser's first-party isolation strategy.
+//!
+//! Callers can additionally tag requests and streams with an
+//! [`IsolationToken`] (e.g. one per user identity); streams with different
+//! tokens never share a circuit, on top of the policy's own separation.
+//! Clients derived with [`TorClient::isolated_client`](crate::TorClient::isolated_client)
+//! go further and confine every stream to their own [`IsolationDomain`].
 
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 use url::Url;
 
 /// Stream isolation policy determining how requests are grouped into circuits
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StreamIsolationPolicy {
     /// Isolate by first-party domain (eTLD+1 approximation)
     /// e.g., foo.example.com and bar.example.com share a circuit
+    #[default]
     PerDomain,
     /// Isolate by full hostname including subdomains
     /// e.g., foo.example.com and bar.example.com use different circuits
@@ -25,9 +33,50 @@ pub enum StreamIsolationPolicy {
     None,
 }
 
-impl Default for StreamIsolationPolicy {
-    fn default() -> Self {
-        StreamIsolationPolicy::PerDomain
+/// Caller-chosen tag separating streams into circuit groups
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IsolationToken {
+    String(String),
+    Integer(u64),
+}
+
+impl From<&str> for IsolationToken {
+    fn from(token: &str) -> Self {
+        IsolationToken::String(token.to_string())
+    }
+}
+
+impl From<String> for IsolationToken {
+    fn from(token: String) -> Self {
+        IsolationToken::String(token)
+    }
+}
+
+impl From<u64> for IsolationToken {
+    fn from(token: u64) -> Self {
+        IsolationToken::Integer(token)
+    }
+}
+
+impl fmt::Display for IsolationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Typed so the string "1" and the integer 1 stay apart
+        match self {
+            IsolationToken::String(s) => write!(f, "str:{}", s),
+            IsolationToken::Integer(n) => write!(f, "int:{}", n),
+        }
+    }
+}
+
+/// Process-unique scope that all streams of an isolated client live in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IsolationDomain(u64);
+
+impl IsolationDomain {
+    /// A domain no other client in this process uses
+    pub fn unique() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        IsolationDomain(NEXT.fetch_add(1, Ordering::Relaxed))
     }
 }
 
@@ -82,6 +131,56 @@ impl IsolationKey {
         }
     }
 
+    /// Create an isolation key for a raw stream to `host:port`
+    ///
+    /// Raw streams have no URL scheme, so `PerOrigin` keys use `tcp://`.
+    pub fn from_host(host: &str, port: u16, policy: StreamIsolationPolicy) -> Option<Self> {
+        let key = match policy {
+            StreamIsolationPolicy::None => return None,
+            StreamIsolationPolicy::PerOrigin => format!("tcp://{}:{}", host, port),
+            StreamIsolationPolicy::PerSubdomain => host.to_string(),
+            StreamIsolationPolicy::PerDomain => extract_domain(host),
+        };
+
+        Some(IsolationKey(key))
+    }
+
+    /// Narrow a policy-derived key (if any) to streams carrying `token`
+    ///
+    /// The token comes first and the base key last, after a `#` that no host
+    /// or origin contains, so tagged keys never collide with untagged ones or
+    /// with each other.
+    pub fn with_token(base: Option<IsolationKey>, token: &IsolationToken) -> Self {
+        let base = base.map(|key| key.0).unwrap_or_default();
+        IsolationKey(format!("{}#{}", token, base))
+    }
+
+    /// Isolation key for a stream, combining the policy key with an optional token
+    pub fn tagged(base: Option<IsolationKey>, token: Option<&IsolationToken>) -> Option<Self> {
+        match token {
+            Some(token) => Some(Self::with_token(base, token)),
+            None => base,
+        }
+    }
+
+    /// Confine a key (if any) to streams of `domain`
+    ///
+    /// Domain keys start with `@`, which neither policy nor token keys do,
+    /// so streams of two domains, or of a domain and an unscoped client,
+    /// never share a circuit even when everything else about them matches.
+    pub fn in_domain(key: Option<IsolationKey>, domain: IsolationDomain) -> Self {
+        let key = key.map(|key| key.0).unwrap_or_default();
+        IsolationKey(format!("@{}/{}", domain.0, key))
+    }
+
+    /// Isolation key for a stream, confined to `domain` if there is one
+    pub fn scoped(key: Option<IsolationKey>, domain: Option<IsolationDomain>) -> Option<Self> {
+        match domain {
+            Some(domain) => Some(Self::in_domain(key, domain)),
+            None => key,
+        }
+    }
+
     /// Create an isolation key from a raw string (for testing)
     pub fn from_string(s: impl Into<String>) -> Self {
         IsolationKey(s.into())
@@ -203,6 +302,71 @@ mod tests {
         assert!(key.is_none());
     }
 
+    #[test]
+    fn test_from_host_matches_url_keys() {
+        let url = Url::parse("https://mail.example.com/").unwrap();
+        for policy in [
+            StreamIsolationPolicy::PerDomain,
+            StreamIsolationPolicy::PerSubdomain,
+        ] {
+            assert_eq!(
+                IsolationKey::from_host("mail.example.com", 25, policy),
+                IsolationKey::from_url(&url, policy)
+            );
+        }
+
+        let key = IsolationKey::from_host("example.com", 25, StreamIsolationPolicy::PerOrigin);
+        assert_eq!(key.unwrap().0, "tcp://example.com:25");
+        assert!(IsolationKey::from_host("example.com", 25, StreamIsolationPolicy::None).is_none());
+    }
+
+    #[test]
+    fn test_tokens_never_share_keys() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let base = || IsolationKey::from_url(&url, StreamIsolationPolicy::PerDomain);
+        let alice = IsolationToken::from("alice");
+        let bob = IsolationToken::from("bob");
+
+        assert_eq!(
+            IsolationKey::tagged(base(), Some(&alice)),
+            IsolationKey::tagged(base(), Some(&alice))
+        );
+        assert_ne!(
+            IsolationKey::tagged(base(), Some(&alice)),
+            IsolationKey::tagged(base(), Some(&bob))
+        );
+        assert_ne!(IsolationKey::tagged(base(), Some(&alice)), base());
+        assert_ne!(
+            IsolationKey::with_token(None, &IsolationToken::from("1")),
+            IsolationKey::with_token(None, &IsolationToken::from(1u64))
+        );
+        assert_eq!(IsolationKey::tagged(base(), None), base());
+    }
+
+    #[test]
+    fn test_domains_never_share_keys() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let base = || IsolationKey::from_url(&url, StreamIsolationPolicy::PerDomain);
+        let first = IsolationDomain::unique();
+        let second = IsolationDomain::unique();
+        assert_ne!(first, second);
+
+        let scoped = IsolationKey::scoped(base(), Some(first));
+        assert_eq!(scoped, IsolationKey::scoped(base(), Some(first)));
+        assert_ne!(scoped, IsolationKey::scoped(base(), Some(second)));
+        assert_ne!(scoped, base());
+        // Even with no policy key, a domain's streams get circuits of their own
+        assert!(IsolationKey::scoped(None, Some(first)).is_some());
+        assert_eq!(IsolationKey::scoped(base(), None), base());
+
+        // A token can't reach into a domain
+        let token = IsolationToken::from(format!("@{}/", first.0));
+        assert_ne!(
+            IsolationKey::tagged(None, Some(&token)),
+            IsolationKey::scoped(None, Some(first))
+        );
+    }
+
     #[test]
     fn test_isolation_key_equality() {
         let key1 = IsolationKey::from_string("example.com");
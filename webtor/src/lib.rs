@@ -4,43 +4,118 @@
 //! compiled to WebAssembly and embedded in web pages. It supports anonymous
 //! HTTP/HTTPS requests through the Tor network using Snowflake bridges.
 
+pub mod bootstrap;
+pub mod bridge_line;
+pub mod bridges;
+pub mod build_timeout;
+pub mod capabilities;
 pub mod circuit;
 pub mod client;
 pub mod config;
+pub mod cookies;
+pub mod datachannel;
+pub mod direct;
 pub mod directory;
+pub mod dormant;
+pub mod ech;
 pub mod error;
+pub mod events;
+pub mod fronted;
+pub mod geoip;
+pub mod guard;
+pub mod hostname;
+pub mod hs_cache;
+pub mod hsdir;
 pub mod http;
+pub mod identity;
 pub mod isolation;
 pub mod kcp_stream;
+pub mod logging;
+pub mod maintenance;
+pub mod meek;
+pub mod metrics;
+pub mod moat;
+pub mod multipart;
+pub mod onion;
+pub mod onion_connector;
+pub mod onion_pow;
+pub mod onion_service;
+pub mod padding;
+pub mod pinning;
+pub mod profile;
+pub mod proxy;
+pub mod range;
+pub mod reachability;
+pub mod reconnect;
+pub mod redirect;
 pub mod relay;
 pub mod retry;
 pub mod smux;
+pub mod snapshot;
 pub mod snowflake;
+pub mod snowflake_amp;
 pub mod snowflake_broker;
 pub mod snowflake_ws;
+pub mod sse;
+pub mod storage;
 pub mod time;
 pub mod tls;
+pub mod torrc;
+pub mod traffic;
+pub mod transport;
+pub mod transport_stats;
 pub mod turbo;
+pub mod vanguards;
+pub mod visibility;
 pub mod wasm_runtime;
 pub mod websocket;
+pub mod ws;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod webtunnel;
 
+#[cfg(all(feature = "obfs4", not(target_arch = "wasm32")))]
+pub mod elligator2;
+#[cfg(all(feature = "obfs4", not(target_arch = "wasm32")))]
+pub mod obfs4;
+#[cfg(all(feature = "obfs4", not(target_arch = "wasm32")))]
+pub mod obfs4_framing;
+
 #[cfg(target_arch = "wasm32")]
 pub mod webrtc_stream;
 
+#[cfg(test)]
+mod test_stream;
+
+pub use bridge_line::BridgeLine;
+pub use bridges::{BridgeHealth, BridgeStatus};
 pub use client::TorClient;
 pub use config::TorClientOptions;
-pub use error::{Result, TorError, TorErrorKind};
-pub use isolation::{IsolationKey, StreamIsolationPolicy};
+pub use ech::EchConfigs;
+pub use error::{Result, StreamEndReason, TimeoutPhase, TorError, TorErrorKind};
+pub use events::{ChannelEvent, CircuitEvent, OnionEvent, TorEvent};
+pub use isolation::{IsolationDomain, IsolationKey, IsolationToken, StreamIsolationPolicy};
+pub use logging::{LogCapture, LogFilter, LogRecord};
+pub use multipart::Multipart;
+pub use onion::OnionAddress;
+pub use onion_service::{OnionService, OnionServiceConfig, OnionStreamRequest};
+pub use pinning::CertPin;
+pub use profile::RequestProfile;
+pub use range::{ContentRange, PartialDownload};
+pub use redirect::RedirectPolicy;
 pub use retry::{
     retry_with_backoff, with_cancellation, with_timeout, with_timeout_and_cancellation,
     CancellationToken, RetryPolicy,
 };
+pub use snapshot::ClientState;
+pub use sse::{EventSourceRequest, ReadyState, SseEvent, TorEventSource};
+pub use tls::{TlsFingerprint, TlsRoots, TlsSessionCache, TlsSessions, TorTlsStream};
+pub use traffic::{TorStream, TrafficStats};
+pub use transport::{LinkStream, Transport, TransportRegistry};
+pub use ws::{CloseFrame, TorWebSocket, WebSocketRequest, WsMessage};
 
 // Re-export commonly used types
-pub use http::HttpResponse;
+pub use http::{HttpResponse, RetryReason};
 pub use url::Url;
 
 // Re-export Tor stream types for advanced usage
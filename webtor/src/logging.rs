@@ -0,0 +1,314 @@
+//! Log capture for embedding applications
+//!
+//! webtor and the arti crates under it log through `tracing`. An
+//! application that wants those logs in its own debug console, without
+//! setting up `tracing_subscriber` itself, creates a [`LogCapture`] and
+//! calls [`install`](LogCapture::install); records then go to its sink (a
+//! channel from [`LogCapture::channel`], or any callback) as [`LogRecord`]s.
+//! Applications with a subscriber of their own add
+//! [`layer`](LogCapture::layer) to it instead.
+//!
+//! Which records are captured is a [`LogFilter`]: a level, and levels for
+//! particular modules, written like `RUST_LOG`
+//! (`info,webtor::circuit=debug,tor_proto=warn`). The filter can be changed
+//! at any time with [`set_filter`](LogCapture::set_filter) or
+//! [`set_level`](LogCapture::set_level); it only decides what the capture
+//! sees, not what other layers of the subscriber do.
+
+use crate::error::{Result, TorError};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Which records to capture: a default level and overrides for modules
+///
+/// The override for the longest matching module path applies, so
+/// `webtor=warn,webtor::circuit=debug` shows circuit debugging but only
+/// warnings from the rest of webtor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    level: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self::new(LevelFilter::INFO)
+    }
+}
+
+impl LogFilter {
+    pub fn new(level: LevelFilter) -> Self {
+        Self {
+            level,
+            modules: Vec::new(),
+        }
+    }
+
+    /// Capture records from `module` and the modules under it at `level`
+    pub fn with_module(mut self, module: impl Into<String>, level: LevelFilter) -> Self {
+        let module = module.into();
+        self.modules.retain(|(existing, _)| *existing != module);
+        self.modules.push((module, level));
+        self
+    }
+
+    /// Level for modules without an override
+    pub fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    /// Whether a record from `target` at `level` is captured
+    pub fn enabled(&self, target: &str, level: &Level) -> bool {
+        let filter = self
+            .modules
+            .iter()
+            .filter(|(module, _)| {
+                target
+                    .strip_prefix(module.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.level, |(_, level)| *level);
+        filter >= *level
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = TorError;
+
+    /// Parse comma-separated directives: a bare level sets the default,
+    /// `module=level` an override
+    fn from_str(s: &str) -> Result<Self> {
+        let parse_level = |level: &str| {
+            level.trim().parse::<LevelFilter>().map_err(|_| {
+                TorError::configuration(format!(
+                    "Unknown log level: {:?} (expected off, error, warn, info, debug or trace)",
+                    level.trim()
+                ))
+            })
+        };
+        let mut filter = LogFilter::default();
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    filter = filter.with_module(module.trim(), parse_level(level)?);
+                }
+                None => filter.level = parse_level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.level)?;
+        for (module, level) in &self.modules {
+            write!(f, ",{}={}", module, level)?;
+        }
+        Ok(())
+    }
+}
+
+/// One captured log record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogRecord {
+    /// `ERROR`, `WARN`, `INFO`, `DEBUG` or `TRACE`
+    pub level: String,
+    /// Module path the record came from, e.g. `webtor::circuit`
+    pub target: String,
+    pub message: String,
+}
+
+type Sink = dyn Fn(LogRecord) + Send + Sync;
+
+/// A log sink with a filter the application can change while running
+#[derive(Clone)]
+pub struct LogCapture {
+    filter: Arc<RwLock<LogFilter>>,
+    sink: Arc<Sink>,
+}
+
+impl fmt::Debug for LogCapture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogCapture")
+            .field("filter", &self.filter().to_string())
+            .finish()
+    }
+}
+
+impl LogCapture {
+    /// Pass the records `filter` lets through to `sink`
+    pub fn new(filter: LogFilter, sink: impl Fn(LogRecord) + Send + Sync + 'static) -> Self {
+        Self {
+            filter: Arc::new(RwLock::new(filter)),
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// Send the records `filter` lets through to the returned receiver
+    ///
+    /// Records are dropped once the receiver is.
+    pub fn channel(filter: LogFilter) -> (Self, UnboundedReceiver<LogRecord>) {
+        let (tx, rx) = unbounded();
+        let capture = Self::new(filter, move |record| {
+            let _ = tx.unbounded_send(record);
+        });
+        (capture, rx)
+    }
+
+    pub fn filter(&self) -> LogFilter {
+        self.filter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replace the filter; applies to the next record
+    pub fn set_filter(&self, filter: LogFilter) {
+        *self.filter.write().unwrap_or_else(PoisonError::into_inner) = filter;
+    }
+
+    /// Change the default level, keeping the module overrides
+    pub fn set_level(&self, level: LevelFilter) {
+        self.filter
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .level = level;
+    }
+
+    /// A layer feeding this capture, for an application's own subscriber
+    pub fn layer(&self) -> LogLayer {
+        LogLayer {
+            capture: self.clone(),
+        }
+    }
+
+    /// Make this capture the process's tracing subscriber
+    ///
+    /// Fails if a global subscriber is already set; add
+    /// [`layer`](Self::layer) to that one instead.
+    pub fn install(&self) -> Result<()> {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let subscriber = tracing_subscriber::registry().with(self.layer());
+        tracing::subscriber::set_global_default(subscriber).map_err(|e| {
+            TorError::configuration(format!("A tracing subscriber is already installed: {}", e))
+        })
+    }
+
+    fn capture(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        let enabled = self
+            .filter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .enabled(metadata.target(), metadata.level());
+        if !enabled {
+            return;
+        }
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        (self.sink)(LogRecord {
+            level: metadata.level().as_str().to_string(),
+            target: metadata.target().to_string(),
+            message: visitor.0,
+        });
+    }
+}
+
+/// `tracing` layer passing events to a [`LogCapture`]
+///
+/// It only observes: records it filters out still reach the subscriber's
+/// other layers.
+pub struct LogLayer {
+    capture: LogCapture,
+}
+
+impl<S: Subscriber> Layer<S> for LogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        self.capture.capture(event);
+    }
+}
+
+/// The message of an event, followed by its other fields as `name=value`
+struct MessageVisitor(String);
+
+impl MessageVisitor {
+    fn push(&mut self, field: &tracing::field::Field, value: fmt::Arguments<'_>) {
+        use fmt::Write;
+
+        if field.name() == "message" {
+            if self.0.is_empty() {
+                let _ = self.0.write_fmt(value);
+            } else {
+                self.0 = format!("{} {}", value, self.0);
+            }
+        } else {
+            if !self.0.is_empty() {
+                self.0.push(' ');
+            }
+            let _ = write!(self.0, "{}={}", field.name(), value);
+        }
+    }
+}
+
+impl tracing::field::Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+        self.push(field, format_args!("{:?}", value));
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.push(field, format_args!("{}", value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_filter() {
+        let filter: LogFilter = "warn, webtor=info ,webtor::circuit=trace,tor_proto=off"
+            .parse()
+            .unwrap();
+        assert!(filter.enabled("webtor::circuit", &Level::TRACE));
+        assert!(filter.enabled("webtor::circuit::build", &Level::DEBUG));
+        assert!(!filter.enabled("webtor::client", &Level::DEBUG));
+        assert!(filter.enabled("webtor::client", &Level::INFO));
+        // A module path only matches whole segments
+        assert!(!filter.enabled("webtor_wasm", &Level::INFO));
+        assert!(!filter.enabled("tor_proto::channel", &Level::ERROR));
+        assert_eq!(
+            filter.to_string(),
+            "warn,webtor=info,webtor::circuit=trace,tor_proto=off"
+        );
+        assert!("webtor=loud".parse::<LogFilter>().is_err());
+    }
+
+    #[test]
+    fn test_capture_follows_level_changes() {
+        let (capture, mut records) = LogCapture::channel("info".parse().unwrap());
+        let subscriber = tracing_subscriber::registry().with(capture.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!(target: "webtor::circuit", "hidden");
+            tracing::info!(target: "webtor::circuit", hops = 3, "Circuit built");
+            capture.set_level(LevelFilter::DEBUG);
+            tracing::debug!(target: "webtor::circuit", "shown");
+        });
+
+        let record = records.try_next().unwrap().unwrap();
+        assert_eq!(record.level, "INFO");
+        assert_eq!(record.target, "webtor::circuit");
+        assert_eq!(record.message, "Circuit built hops=3");
+        assert_eq!(records.try_next().unwrap().unwrap().message, "shown");
+        assert!(records.try_next().is_err());
+    }
+}
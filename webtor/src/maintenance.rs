@@ -0,0 +1,283 @@
+//! Periodic maintenance for long-running clients
+//!
+//! Subsystems that accumulate state register a sweeper with [`Maintenance`].
+//! One background task per client runs every sweeper on a fixed interval, so
+//! memory stays bounded in sessions that live for hours in a browser tab or
+//! server process.
+//!
+//! [`TorClient`](crate::TorClient) registers sweepers for its circuit records,
+//! relay reachability, resolved addresses, onion service descriptors, cookies
+//! and TLS session partitions. Metrics are a fixed set of counters and need
+//! none.
+
+use crate::retry::{sleep, with_cancellation, CancellationToken};
+use crate::time::Instant;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tracing::{debug, info};
+
+type Sweeper = Arc<dyn Fn() -> BoxFuture<'static, usize> + Send + Sync>;
+
+/// Registry of sweepers run by the periodic maintenance task
+#[derive(Clone, Default)]
+pub struct Maintenance {
+    sweepers: Arc<Mutex<Vec<(&'static str, Sweeper)>>>,
+}
+
+impl Maintenance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a sweeper that prunes expired state and returns how many entries it removed
+    pub fn register<F, Fut>(&self, name: &'static str, sweeper: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = usize> + Send + 'static,
+    {
+        let sweeper: Sweeper = Arc::new(move || Box::pin(sweeper()));
+        lock(&self.sweepers).push((name, sweeper));
+    }
+
+    /// Register a shared [`TtlCache`] so its expired entries are purged on every pass
+    pub fn register_cache<K, V>(&self, name: &'static str, cache: Arc<Mutex<TtlCache<K, V>>>)
+    where
+        K: Eq + Hash + Send + 'static,
+        V: Send + 'static,
+    {
+        self.register(name, move || {
+            let purged = lock(&cache).purge_expired();
+            async move { purged }
+        });
+    }
+
+    /// Names of the registered sweepers, in registration order
+    pub fn sweeper_names(&self) -> Vec<&'static str> {
+        lock(&self.sweepers).iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Run every registered sweeper once
+    pub async fn run_once(&self) -> MaintenanceReport {
+        // Snapshot the list so sweepers can run without holding the lock
+        let sweepers: Vec<_> = lock(&self.sweepers).clone();
+
+        let mut report = MaintenanceReport::default();
+        for (name, sweeper) in sweepers {
+            let pruned = sweeper().await;
+            if pruned > 0 {
+                debug!("Maintenance: {} pruned {} entries", name, pruned);
+            }
+            report.pruned.push((name, pruned));
+        }
+        report
+    }
+
+    /// Spawn the periodic task; it stops once `shutdown` is cancelled
+    pub fn spawn(&self, interval: Duration, shutdown: CancellationToken) {
+        info!("Starting maintenance task (interval: {:?})", interval);
+        let maintenance = self.clone();
+        let task = async move {
+            loop {
+                let tick = with_cancellation(&shutdown, async {
+                    sleep(interval).await;
+                    Ok(())
+                });
+                if tick.await.is_err() {
+                    debug!("Maintenance task stopped");
+                    break;
+                }
+                maintenance.run_once().await;
+            }
+        };
+
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(task);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::spawn(task);
+    }
+}
+
+/// Outcome of a single maintenance pass
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceReport {
+    /// Entries pruned per sweeper, in registration order
+    pub pruned: Vec<(&'static str, usize)>,
+}
+
+impl MaintenanceReport {
+    /// Total entries pruned across all sweepers
+    pub fn total(&self) -> usize {
+        self.pruned.iter().map(|(_, n)| n).sum()
+    }
+}
+
+/// Map whose entries expire after a time-to-live
+///
+/// Expired entries are invisible to lookups immediately, but only release
+/// their memory when [`TtlCache::purge_expired`] runs (usually from the
+/// maintenance task via [`Maintenance::register_cache`]).
+#[derive(Debug)]
+pub struct TtlCache<K, V> {
+    entries: HashMap<K, CacheEntry<V>>,
+    default_ttl: Duration,
+}
+
+#[derive(Debug)]
+struct CacheEntry<V> {
+    value: V,
+    inserted_at: Instant,
+    ttl: Duration,
+}
+
+impl<V> CacheEntry<V> {
+    fn is_expired(&self) -> bool {
+        self.inserted_at.elapsed() >= self.ttl
+    }
+}
+
+impl<K: Eq + Hash, V> TtlCache<K, V> {
+    pub fn new(default_ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            default_ttl,
+        }
+    }
+
+    /// Insert a value using the cache's default TTL
+    pub fn insert(&mut self, key: K, value: V) {
+        self.insert_with_ttl(key, value, self.default_ttl);
+    }
+
+    /// Insert a value that expires after `ttl`
+    pub fn insert_with_ttl(&mut self, key: K, value: V, ttl: Duration) {
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                inserted_at: Instant::now(),
+                ttl,
+            },
+        );
+    }
+
+    /// Look up a value that has not yet expired
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries
+            .get(key)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| &entry.value)
+    }
+
+    /// Remove a value, returning it even if it had expired
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.entries.remove(key).map(|entry| entry.value)
+    }
+
+    /// Drop all expired entries, returning how many were removed
+    pub fn purge_expired(&mut self) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| !entry.is_expired());
+        before - self.entries.len()
+    }
+
+    /// Number of stored entries, including expired ones not yet purged
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl<K: Eq + Hash + Clone, V> TtlCache<K, V> {
+    /// Remove the entry inserted first, to make room in a bounded cache
+    pub fn pop_oldest(&mut self) -> Option<(K, V)> {
+        let oldest = self
+            .entries
+            .iter()
+            .max_by_key(|(_, entry)| entry.inserted_at.elapsed())
+            .map(|(key, _)| key.clone())?;
+        let entry = self.entries.remove(&oldest)?;
+        Some((oldest, entry.value))
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_ttl_cache_hides_and_purges_expired_entries() {
+        let mut cache = TtlCache::new(Duration::from_secs(60));
+        cache.insert("fresh", 1);
+        cache.insert_with_ttl("stale", 2, Duration::ZERO);
+
+        assert_eq!(cache.get(&"fresh"), Some(&1));
+        assert_eq!(cache.get(&"stale"), None);
+        assert_eq!(cache.len(), 2);
+
+        assert_eq!(cache.purge_expired(), 1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.remove(&"fresh"), Some(1));
+        assert!(cache.is_empty());
+
+        cache.insert("older", 1);
+        std::thread::sleep(Duration::from_millis(1));
+        cache.insert("newer", 2);
+        assert_eq!(cache.pop_oldest(), Some(("older", 1)));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_once_reports_each_sweeper() {
+        let maintenance = Maintenance::new();
+        let cache = Arc::new(Mutex::new(TtlCache::new(Duration::ZERO)));
+        cache.lock().unwrap().insert(1u32, "a");
+        cache.lock().unwrap().insert(2u32, "b");
+
+        maintenance.register_cache("cache", cache.clone());
+        maintenance.register("noop", || async { 0 });
+
+        let report = maintenance.run_once().await;
+        assert_eq!(report.pruned, vec![("cache", 2), ("noop", 0)]);
+        assert_eq!(report.total(), 2);
+        assert!(cache.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_spawned_task_runs_until_cancelled() {
+        let maintenance = Maintenance::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        maintenance.register("counter", move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async { 0 }
+        });
+
+        let shutdown = CancellationToken::new();
+        maintenance.spawn(Duration::from_millis(5), shutdown.clone());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.cancel();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let after_cancel = runs.load(Ordering::SeqCst);
+        assert!(after_cancel > 0);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), after_cancel);
+    }
+}
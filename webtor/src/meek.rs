@@ -0,0 +1,856 @@
+//! meek-lite pluggable transport for Tor connections
+//!
+//! meek tunnels a byte stream through ordinary HTTPS requests, usually to a
+//! CDN that forwards them to the meek server. It is slow but hard to block
+//! without blocking the CDN, which makes it a last resort in heavily
+//! censored networks.
+//!
+//! Protocol (compatible with the meek server and `meek_lite` clients):
+//! - Every request is a POST to the meek URL with an `X-Session-Id` header,
+//!   which the server uses to find the session's relay connection
+//! - The request body is upstream data (at most 64 KiB, possibly empty)
+//! - The response body is whatever downstream data the server has queued
+//! - Requests are sequential. While neither side has data, the client keeps
+//!   polling, with the interval growing from 100ms to `max_poll_interval`
+//!
+//! With a `front`, native builds connect (and send SNI) to the front domain
+//! while the `Host` header names the meek URL's host, so the CDN routes the
+//! request to the meek server. Browsers don't let a page choose `Host`, so
+//! the WASM build fetches the meek URL directly and ignores the front. The
+//! server must then allow CORS from the page's origin.
+//!
+//! Stack: HTTPS requests → meek session → Tor link TLS.
+//!
+//! Reference: https://gitlab.torproject.org/tpo/anti-censorship/pluggable-transports/meek
+
+use crate::error::{Result, TorError};
+use crate::retry::{sleep, with_timeout};
+use crate::transport::{LinkStream, TorLinkStream, Transport};
+use base64::Engine;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::future::{self, Either};
+use futures::{ready, AsyncRead, AsyncWrite, Future, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Most upstream data sent in one request
+const MAX_PAYLOAD_LENGTH: usize = 0x10000;
+
+/// Poll interval after the first empty exchange
+const INIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Growth of the poll interval per empty exchange
+const POLL_INTERVAL_MULTIPLIER: f64 = 1.5;
+
+/// Attempts per request before the session is given up
+const MAX_RETRIES: u32 = 5;
+
+/// Pause between attempts of a failed request
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Random bytes in a session ID
+const SESSION_ID_LENGTH: usize = 32;
+
+/// meek bridge configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeekConfig {
+    /// meek server URL; its host goes in the `Host` header
+    pub url: String,
+    /// Domain to connect to and name in SNI instead of the URL's host
+    #[serde(default)]
+    pub front: Option<String>,
+    /// Bridge fingerprint (RSA identity, 40 hex chars)
+    pub fingerprint: String,
+    /// Connection timeout, also applied to each request (milliseconds when
+    /// serialized)
+    #[serde(default = "default_timeout", with = "crate::config::duration_ms")]
+    pub connection_timeout: Duration,
+    /// Longest wait between polls while the session is idle (milliseconds
+    /// when serialized)
+    #[serde(
+        default = "default_max_poll_interval",
+        with = "crate::config::duration_ms"
+    )]
+    pub max_poll_interval: Duration,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_max_poll_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+impl MeekConfig {
+    pub fn new(url: String, fingerprint: String) -> Self {
+        Self {
+            url,
+            front: None,
+            fingerprint,
+            connection_timeout: default_timeout(),
+            max_poll_interval: default_max_poll_interval(),
+        }
+    }
+
+    pub fn with_front(mut self, front: String) -> Self {
+        self.front = Some(front);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = timeout;
+        self
+    }
+
+    pub fn with_max_poll_interval(mut self, interval: Duration) -> Self {
+        self.max_poll_interval = interval;
+        self
+    }
+}
+
+/// meek bridge connection manager
+pub struct MeekBridge {
+    config: MeekConfig,
+}
+
+impl MeekBridge {
+    pub fn new(config: MeekConfig) -> Self {
+        Self { config }
+    }
+
+    /// Connect to the meek bridge
+    ///
+    /// Performs:
+    /// 1. Start the request loop for a new session
+    /// 2. Establish Tor link TLS through it
+    pub async fn connect(&self) -> Result<MeekStream> {
+        let url = Url::parse(&self.config.url)
+            .map_err(|e| TorError::Configuration(format!("Invalid meek URL: {}", e)))?;
+        if url.scheme() != "https" {
+            return Err(TorError::Configuration(format!(
+                "meek URL must be https, got {}",
+                url.scheme()
+            )));
+        }
+
+        match &self.config.front {
+            Some(front) => info!("Connecting to meek bridge {} via front {}", url, front),
+            None => info!("Connecting to meek bridge {}", url),
+        }
+
+        let session_id: [u8; SESSION_ID_LENGTH] = rand::random();
+        let driver = MeekDriver {
+            transport: HttpTransport::new(&url, self.config.front.as_deref())?,
+            session_id: base64::engine::general_purpose::STANDARD.encode(session_id),
+            request_timeout: self.config.connection_timeout,
+            max_poll_interval: self.config.max_poll_interval,
+        };
+        let (upstream_tx, upstream_rx) = mpsc::unbounded();
+        let (downstream_tx, downstream_rx) = mpsc::unbounded();
+        spawn(driver.run(upstream_rx, downstream_tx));
+
+        let conn = MeekConn {
+            upstream: upstream_tx,
+            downstream: downstream_rx,
+            read_buf: Vec::new(),
+            read_pos: 0,
+        };
+
+        let stream = with_timeout(
+            self.config.connection_timeout,
+            "meek Tor link TLS handshake",
+            TorLinkStream::handshake(conn),
+        )
+        .await?;
+        info!("Tor link TLS established over meek, ready for channel handshake");
+        Ok(stream)
+    }
+}
+
+/// The request loop of one meek session
+struct MeekDriver {
+    transport: HttpTransport,
+    session_id: String,
+    request_timeout: Duration,
+    max_poll_interval: Duration,
+}
+
+impl MeekDriver {
+    /// Exchange data until the connection is closed or a request keeps failing
+    async fn run(
+        mut self,
+        mut upstream: UnboundedReceiver<Vec<u8>>,
+        downstream: UnboundedSender<io::Result<Vec<u8>>>,
+    ) {
+        let mut pending = Vec::new();
+        let mut upstream_open = true;
+        let mut interval = INIT_POLL_INTERVAL;
+
+        loop {
+            if pending.is_empty() && upstream_open {
+                // Wait for data to send or for the next poll
+                let timer = sleep(interval);
+                futures::pin_mut!(timer);
+                match future::select(upstream.next(), timer).await {
+                    Either::Left((Some(data), _)) => pending = data,
+                    Either::Left((None, _)) => upstream_open = false,
+                    Either::Right(_) => {}
+                }
+            }
+            // Send writes that are already queued in the same request
+            while upstream_open && pending.len() < MAX_PAYLOAD_LENGTH {
+                match upstream.try_next() {
+                    Ok(Some(data)) => pending.extend_from_slice(&data),
+                    Ok(None) => upstream_open = false,
+                    Err(_) => break,
+                }
+            }
+            if !upstream_open && pending.is_empty() {
+                debug!("meek session closed");
+                return;
+            }
+
+            let body: Vec<u8> = pending
+                .drain(..pending.len().min(MAX_PAYLOAD_LENGTH))
+                .collect();
+            let received = match self.round_trip(&body).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("meek session failed: {}", e);
+                    let _ = downstream.unbounded_send(Err(io::Error::other(e)));
+                    return;
+                }
+            };
+
+            let active = !body.is_empty() || !received.is_empty();
+            if !received.is_empty() && downstream.unbounded_send(Ok(received)).is_err() {
+                return;
+            }
+            interval = next_poll_interval(interval, active, self.max_poll_interval);
+        }
+    }
+
+    /// POST `body`, retrying failed requests
+    async fn round_trip(&mut self, body: &[u8]) -> Result<Vec<u8>> {
+        let mut attempt = 1;
+        loop {
+            let request = self.transport.post(&self.session_id, body);
+            match with_timeout(self.request_timeout, "meek request", request).await {
+                Ok(received) => return Ok(received),
+                Err(e) if attempt < MAX_RETRIES => {
+                    debug!(
+                        "meek request failed (attempt {}/{}): {}",
+                        attempt, MAX_RETRIES, e
+                    );
+                    attempt += 1;
+                    sleep(RETRY_DELAY).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Poll again at once after traffic, otherwise back off up to `max`
+fn next_poll_interval(current: Duration, active: bool, max: Duration) -> Duration {
+    if active {
+        Duration::ZERO
+    } else if current.is_zero() {
+        INIT_POLL_INTERVAL.min(max)
+    } else {
+        current.mul_f64(POLL_INTERVAL_MULTIPLIER).min(max)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn spawn(task: impl Future<Output = ()> + 'static) {
+    wasm_bindgen_futures::spawn_local(task);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn(task: impl Future<Output = ()> + Send + 'static) {
+    tokio::spawn(task);
+}
+
+/// Requests through `fetch`; the front can't be applied in the browser
+#[cfg(target_arch = "wasm32")]
+struct HttpTransport {
+    url: String,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl HttpTransport {
+    fn new(url: &Url, front: Option<&str>) -> Result<Self> {
+        if front.is_some() {
+            debug!("Browsers can't set Host, so the meek front is not used");
+        }
+        Ok(Self {
+            url: url.to_string(),
+        })
+    }
+
+    async fn post(&mut self, session_id: &str, body: &[u8]) -> Result<Vec<u8>> {
+        use wasm_bindgen::JsCast;
+        use wasm_bindgen_futures::JsFuture;
+        use web_sys::{Request, RequestInit, RequestMode, Response};
+
+        let opts = RequestInit::new();
+        opts.set_mode(RequestMode::Cors);
+        opts.set_method("POST");
+        opts.set_body(&js_sys::Uint8Array::from(body).into());
+
+        let request = Request::new_with_str_and_init(&self.url, &opts)
+            .map_err(|e| TorError::Network(format!("Failed to create request: {:?}", e)))?;
+        let headers = request.headers();
+        headers
+            .set("X-Session-Id", session_id)
+            .and_then(|_| headers.set("Content-Type", "application/octet-stream"))
+            .map_err(|e| TorError::Network(format!("Failed to set headers: {:?}", e)))?;
+
+        let window =
+            web_sys::window().ok_or_else(|| TorError::Internal("No window object".to_string()))?;
+        let resp: Response = JsFuture::from(window.fetch_with_request(&request))
+            .await
+            .map_err(|e| TorError::Network(format!("Fetch failed: {:?}", e)))?
+            .dyn_into()
+            .map_err(|_| TorError::Internal("Response cast failed".to_string()))?;
+        if resp.status() != 200 {
+            return Err(TorError::Network(format!(
+                "meek server returned HTTP {}",
+                resp.status()
+            )));
+        }
+
+        let array_buffer = JsFuture::from(
+            resp.array_buffer()
+                .map_err(|e| TorError::Network(format!("Failed to get body: {:?}", e)))?,
+        )
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to read body: {:?}", e)))?;
+        Ok(js_sys::Uint8Array::new(&array_buffer).to_vec())
+    }
+}
+
+/// Requests over one kept-alive HTTPS connection, reopened after errors
+#[cfg(not(target_arch = "wasm32"))]
+struct HttpTransport {
+    /// Host to connect to and present in SNI (the front when fronting)
+    connect_host: String,
+    port: u16,
+    /// Host named in the `Host` header
+    host_header: String,
+    /// Path and query of the meek URL
+    target: String,
+    stream: Option<tokio_rustls::client::TlsStream<tokio::net::TcpStream>>,
+    /// Bytes read past the end of the last response
+    buf: Vec<u8>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl HttpTransport {
+    fn new(url: &Url, front: Option<&str>) -> Result<Self> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| TorError::Configuration("meek URL has no host".to_string()))?;
+        let host_header = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let target = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        Ok(Self {
+            connect_host: front.unwrap_or(host).to_string(),
+            port: url.port().unwrap_or(443),
+            host_header,
+            target,
+            stream: None,
+            buf: Vec::new(),
+        })
+    }
+
+    async fn post(&mut self, session_id: &str, body: &[u8]) -> Result<Vec<u8>> {
+        let result = self.post_on_connection(session_id, body).await;
+        if result.is_err() {
+            self.stream = None;
+            self.buf.clear();
+        }
+        result
+    }
+
+    async fn post_on_connection(&mut self, session_id: &str, body: &[u8]) -> Result<Vec<u8>> {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+
+        if self.stream.is_none() {
+            self.stream = Some(self.connect().await?);
+            self.buf.clear();
+        }
+        let stream = self.stream.as_mut().expect("connected above");
+
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             X-Session-Id: {}\r\n\
+             Content-Type: application/octet-stream\r\n\
+             Content-Length: {}\r\n\
+             \r\n",
+            self.target,
+            self.host_header,
+            session_id,
+            body.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(body);
+
+        let mut compat = stream.compat();
+        let (head, received) = exchange(&mut compat, &mut self.buf, &request).await?;
+        if head.close {
+            self.stream = None;
+        }
+        if head.status != 200 {
+            return Err(TorError::Network(format!(
+                "meek server returned HTTP {}",
+                head.status
+            )));
+        }
+        Ok(received)
+    }
+
+    async fn connect(&self) -> Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>> {
+        use rustls_pki_types::ServerName;
+        use tokio::net::TcpStream;
+
+        if self.connect_host != self.host_header {
+            debug!("Domain-fronting meek requests via {}", self.connect_host);
+        }
+        let tcp = TcpStream::connect((self.connect_host.as_str(), self.port))
+            .await
+            .map_err(|e| TorError::Network(format!("Failed to connect to meek front: {}", e)))?;
+
+        let mut root_store = rustls::RootCertStore::empty();
+        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(std::sync::Arc::new(config));
+        let server_name = ServerName::try_from(self.connect_host.clone())
+            .map_err(|_| TorError::Configuration("Invalid meek front name".to_string()))?;
+        connector
+            .connect(server_name, tcp)
+            .await
+            .map_err(|e| TorError::Network(format!("TLS handshake with meek front failed: {}", e)))
+    }
+}
+
+/// How the length of a response body is known
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyLength {
+    Fixed(usize),
+    Chunked,
+    UntilClose,
+}
+
+/// The parts of a response head the transport needs
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ResponseHead {
+    status: u16,
+    body: BodyLength,
+    /// The server closes the connection after this response
+    close: bool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_response_head(head: &[u8]) -> Result<ResponseHead> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let mut parts = status_line.split_whitespace();
+    let version = parts.next().unwrap_or_default();
+    let status = parts
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .filter(|_| version.starts_with("HTTP/"))
+        .ok_or_else(|| {
+            TorError::Protocol(format!(
+                "Invalid HTTP status line from meek server: {}",
+                status_line
+            ))
+        })?;
+
+    let mut body = BodyLength::UntilClose;
+    let mut close = version == "HTTP/1.0";
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" if body != BodyLength::Chunked => {
+                let length = value.parse().map_err(|_| {
+                    TorError::Protocol(format!("Invalid Content-Length: {}", value))
+                })?;
+                body = BodyLength::Fixed(length);
+            }
+            "transfer-encoding" if value.to_ascii_lowercase().contains("chunked") => {
+                body = BodyLength::Chunked;
+            }
+            "connection" => close = value.eq_ignore_ascii_case("close"),
+            _ => {}
+        }
+    }
+    if body == BodyLength::UntilClose {
+        close = true;
+    }
+    Ok(ResponseHead {
+        status,
+        body,
+        close,
+    })
+}
+
+/// Decode a complete chunked body at the front of `buf`
+///
+/// Returns the body and the number of bytes it took, or `None` if more
+/// input is needed.
+#[cfg(not(target_arch = "wasm32"))]
+fn decode_chunked(buf: &[u8]) -> Result<Option<(Vec<u8>, usize)>> {
+    let mut body = Vec::new();
+    let mut pos = 0;
+    loop {
+        let Some(line_len) = find_crlf(&buf[pos..]) else {
+            return Ok(None);
+        };
+        let line = String::from_utf8_lossy(&buf[pos..pos + line_len]);
+        let size_field = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size_field, 16)
+            .map_err(|_| TorError::Protocol(format!("Invalid chunk size: {}", size_field)))?;
+        pos += line_len + 2;
+
+        if size == 0 {
+            // Skip trailers up to the empty line
+            loop {
+                let Some(trailer_len) = find_crlf(&buf[pos..]) else {
+                    return Ok(None);
+                };
+                pos += trailer_len + 2;
+                if trailer_len == 0 {
+                    return Ok(Some((body, pos)));
+                }
+            }
+        }
+
+        if buf.len() < pos + size + 2 {
+            return Ok(None);
+        }
+        body.extend_from_slice(&buf[pos..pos + size]);
+        if &buf[pos + size..pos + size + 2] != b"\r\n" {
+            return Err(TorError::Protocol("Chunk missing its CRLF".to_string()));
+        }
+        pos += size + 2;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn find_crlf(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|w| w == b"\r\n")
+}
+
+/// Send `request` and read one response, keeping any excess in `buf`
+#[cfg(not(target_arch = "wasm32"))]
+async fn exchange<S>(
+    stream: &mut S,
+    buf: &mut Vec<u8>,
+    request: &[u8],
+) -> Result<(ResponseHead, Vec<u8>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    let network = |e: io::Error| TorError::Network(format!("meek request failed: {}", e));
+    stream.write_all(request).await.map_err(network)?;
+    stream.flush().await.map_err(network)?;
+
+    let mut chunk = [0u8; 16 * 1024];
+    let mut eof = false;
+
+    let head_end = loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        let n = stream.read(&mut chunk).await.map_err(network)?;
+        if n == 0 {
+            return Err(TorError::Network(
+                "meek server closed the connection".to_string(),
+            ));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = parse_response_head(&buf[..head_end])?;
+    buf.drain(..head_end + 4);
+
+    let body = loop {
+        match head.body {
+            BodyLength::Fixed(length) if buf.len() >= length => {
+                break buf.drain(..length).collect();
+            }
+            BodyLength::Chunked => {
+                if let Some((body, consumed)) = decode_chunked(buf)? {
+                    buf.drain(..consumed);
+                    break body;
+                }
+            }
+            BodyLength::UntilClose if eof => break std::mem::take(buf),
+            _ => {}
+        }
+        if eof {
+            return Err(TorError::Network("meek response ended early".to_string()));
+        }
+        let n = stream.read(&mut chunk).await.map_err(network)?;
+        if n == 0 {
+            eof = true;
+        } else {
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    };
+    Ok((head, body))
+}
+
+/// One meek session as a byte stream
+///
+/// Writes are queued for the request loop; reads return the response
+/// bodies it receives. Closing the stream ends the loop once queued data
+/// has been sent.
+pub struct MeekConn {
+    upstream: UnboundedSender<Vec<u8>>,
+    downstream: UnboundedReceiver<io::Result<Vec<u8>>>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+}
+
+impl AsyncRead for MeekConn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.read_pos == self.read_buf.len() {
+            match ready!(self.downstream.poll_next_unpin(cx)) {
+                Some(Ok(data)) => {
+                    self.read_buf = data;
+                    self.read_pos = 0;
+                }
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+        let n = buf.len().min(self.read_buf.len() - self.read_pos);
+        buf[..n].copy_from_slice(&self.read_buf[self.read_pos..self.read_pos + n]);
+        self.read_pos += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for MeekConn {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        match self.upstream.unbounded_send(buf.to_vec()) {
+            Ok(()) => Poll::Ready(Ok(buf.len())),
+            Err(_) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.upstream.close_channel();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// meek stream for Tor communication
+///
+/// The architecture is:
+/// - HTTPS: Client ↔ CDN front ↔ meek server (sequential POSTs)
+/// - TLS: Client ↔ Tor relay (tunneled, validated via CERTS cells)
+pub type MeekStream = TorLinkStream<MeekConn>;
+
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl Transport for MeekBridge {
+    async fn connect(&self) -> Result<Box<dyn LinkStream>> {
+        Ok(Box::new(MeekBridge::connect(self).await?))
+    }
+}
+
+/// Create a meek stream (convenience function)
+pub async fn create_meek_stream(config: MeekConfig) -> Result<MeekStream> {
+    let bridge = MeekBridge::new(config);
+    bridge.connect().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config = MeekConfig::new("https://meek.example.net/".to_string(), "AAAA".repeat(10))
+            .with_front("ajax.example.com".to_string())
+            .with_timeout(Duration::from_secs(60))
+            .with_max_poll_interval(Duration::from_secs(2));
+        assert_eq!(config.front.as_deref(), Some("ajax.example.com"));
+        assert_eq!(config.connection_timeout, Duration::from_secs(60));
+        assert_eq!(config.max_poll_interval, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_poll_interval_backoff() {
+        let max = Duration::from_secs(5);
+        assert_eq!(
+            next_poll_interval(Duration::from_secs(3), true, max),
+            Duration::ZERO
+        );
+        assert_eq!(
+            next_poll_interval(Duration::ZERO, false, max),
+            INIT_POLL_INTERVAL
+        );
+        assert_eq!(
+            next_poll_interval(INIT_POLL_INTERVAL, false, max),
+            Duration::from_millis(150)
+        );
+        assert_eq!(next_poll_interval(Duration::from_secs(4), false, max), max);
+    }
+
+    #[test]
+    fn test_fronted_transport() {
+        let url = Url::parse("https://meek.example.net/path?x=1").unwrap();
+        let direct = HttpTransport::new(&url, None).unwrap();
+        assert_eq!(direct.connect_host, "meek.example.net");
+        assert_eq!(direct.host_header, "meek.example.net");
+        assert_eq!(direct.target, "/path?x=1");
+
+        let fronted = HttpTransport::new(&url, Some("ajax.example.com")).unwrap();
+        assert_eq!(fronted.connect_host, "ajax.example.com");
+        assert_eq!(fronted.host_header, "meek.example.net");
+        assert_eq!(fronted.port, 443);
+    }
+
+    #[test]
+    fn test_parse_response_head() {
+        let head = parse_response_head(b"HTTP/1.1 200 OK\r\nContent-Length: 12").unwrap();
+        assert_eq!(head.status, 200);
+        assert_eq!(head.body, BodyLength::Fixed(12));
+        assert!(!head.close);
+
+        let head = parse_response_head(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close",
+        )
+        .unwrap();
+        assert_eq!(head.body, BodyLength::Chunked);
+        assert!(head.close);
+
+        // No length means the body runs to the end of the connection
+        let head = parse_response_head(b"HTTP/1.1 502 Bad Gateway").unwrap();
+        assert_eq!(head.status, 502);
+        assert_eq!(head.body, BodyLength::UntilClose);
+        assert!(head.close);
+
+        assert!(parse_response_head(b"garbage").is_err());
+    }
+
+    #[test]
+    fn test_decode_chunked() {
+        let body = b"4\r\nmeek\r\n6;ext=1\r\n-lite!\r\n0\r\nX-Trailer: 1\r\n\r\nnext";
+        let (decoded, consumed) = decode_chunked(body).unwrap().unwrap();
+        assert_eq!(decoded, b"meek-lite!");
+        assert_eq!(&body[consumed..], b"next");
+
+        assert!(decode_chunked(b"4\r\nmee").unwrap().is_none());
+        assert!(decode_chunked(b"0\r\n").unwrap().is_none());
+        assert!(decode_chunked(b"zz\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_exchange_keeps_connection_state() {
+        use futures::{AsyncReadExt, AsyncWriteExt};
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+
+        let (client, server) = tokio::io::duplex(1 << 16);
+        let server = tokio::spawn(async move {
+            let mut server = server.compat();
+            let mut request = [0u8; 1024];
+            let n = server.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..n]).to_string();
+            // Two responses in one write: the second must wait in the buffer
+            server
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello\
+                      HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            request
+        });
+
+        let mut client = client.compat();
+        let mut buf = Vec::new();
+        let (head, body) = exchange(&mut client, &mut buf, b"POST / HTTP/1.1\r\n\r\nup")
+            .await
+            .unwrap();
+        assert_eq!(head.status, 200);
+        assert_eq!(body, b"hello");
+
+        let (_, body) = exchange(&mut client, &mut buf, b"").await.unwrap();
+        assert_eq!(body, b"abc");
+        assert!(buf.is_empty());
+        assert!(server.await.unwrap().ends_with("up"));
+    }
+
+    #[tokio::test]
+    async fn test_conn_queues_writes_and_reads_responses() {
+        use futures::{AsyncReadExt, AsyncWriteExt};
+
+        let (upstream_tx, mut upstream_rx) = mpsc::unbounded();
+        let (downstream_tx, downstream_rx) = mpsc::unbounded();
+        let mut conn = MeekConn {
+            upstream: upstream_tx,
+            downstream: downstream_rx,
+            read_buf: Vec::new(),
+            read_pos: 0,
+        };
+
+        conn.write_all(b"cell").await.unwrap();
+        assert_eq!(upstream_rx.next().await.unwrap(), b"cell");
+
+        downstream_tx.unbounded_send(Ok(b"reply".to_vec())).unwrap();
+        let mut reply = [0u8; 5];
+        conn.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"reply");
+
+        // The driver's error reaches the reader, and closing ends the upload side
+        downstream_tx
+            .unbounded_send(Err(io::ErrorKind::TimedOut.into()))
+            .unwrap();
+        assert!(conn.read(&mut reply).await.is_err());
+        conn.close().await.unwrap();
+        assert!(upstream_rx.next().await.is_none());
+    }
+}
@@ -0,0 +1,478 @@
+//! Client metrics in Prometheus text format
+//!
+//! [`Metrics`] is a set of lock-free counters shared by the circuit manager,
+//! HTTP client and directory manager. [`Metrics::render_prometheus`] renders
+//! them (plus current circuit gauges) in the Prometheus text exposition
+//! format; native builds can also serve that text on a local HTTP endpoint.
+
+use crate::circuit::CircuitStatusInfo;
+use crate::transport_stats::{TransportStats, TransportStatsSnapshot};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Which directory document source a fetch used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectorySource {
+    /// Consensus fetched over the Tor channel
+    Channel,
+    /// Pre-built consensus fetched from the static cache (WASM)
+    Cached,
+    /// Consensus and microdescriptors supplied by the embedder
+    Injected,
+}
+
+impl DirectorySource {
+    fn as_label(&self) -> &'static str {
+        match self {
+            DirectorySource::Channel => "channel",
+            DirectorySource::Cached => "cached",
+            DirectorySource::Injected => "injected",
+        }
+    }
+}
+
+/// Counters shared by the client's subsystems
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    circuits_built: AtomicU64,
+    circuits_failed: AtomicU64,
+    streams_opened: AtomicU64,
+    streams_failed: AtomicU64,
+    http_bytes_sent: AtomicU64,
+    http_bytes_received: AtomicU64,
+    directory_channel_success: AtomicU64,
+    directory_channel_failure: AtomicU64,
+    directory_cached_success: AtomicU64,
+    directory_cached_failure: AtomicU64,
+    directory_injected_success: AtomicU64,
+    directory_injected_failure: AtomicU64,
+    transports: Mutex<BTreeMap<String, TransportStats>>,
+}
+
+/// Point-in-time copy of the counters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub circuits_built: u64,
+    pub circuits_failed: u64,
+    pub streams_opened: u64,
+    pub streams_failed: u64,
+    pub http_bytes_sent: u64,
+    pub http_bytes_received: u64,
+    pub directory_channel_success: u64,
+    pub directory_channel_failure: u64,
+    pub directory_cached_success: u64,
+    pub directory_cached_failure: u64,
+    pub directory_injected_success: u64,
+    pub directory_injected_failure: u64,
+    /// Traffic and latency per bridge transport
+    pub transports: Vec<TransportStatsSnapshot>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the outcome of a circuit build
+    pub fn record_circuit(&self, success: bool) {
+        let counter = if success {
+            &self.counters.circuits_built
+        } else {
+            &self.counters.circuits_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the outcome of opening a stream on a circuit
+    pub fn record_stream(&self, success: bool) {
+        let counter = if success {
+            &self.counters.streams_opened
+        } else {
+            &self.counters.streams_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record HTTP payload bytes written to and read from a stream
+    pub fn record_http_bytes(&self, sent: usize, received: usize) {
+        self.counters
+            .http_bytes_sent
+            .fetch_add(sent as u64, Ordering::Relaxed);
+        self.counters
+            .http_bytes_received
+            .fetch_add(received as u64, Ordering::Relaxed);
+    }
+
+    /// Record the outcome of a consensus fetch
+    pub fn record_directory_fetch(&self, source: DirectorySource, success: bool) {
+        let counter = match (source, success) {
+            (DirectorySource::Channel, true) => &self.counters.directory_channel_success,
+            (DirectorySource::Channel, false) => &self.counters.directory_channel_failure,
+            (DirectorySource::Cached, true) => &self.counters.directory_cached_success,
+            (DirectorySource::Cached, false) => &self.counters.directory_cached_failure,
+            (DirectorySource::Injected, true) => &self.counters.directory_injected_success,
+            (DirectorySource::Injected, false) => &self.counters.directory_injected_failure,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counters for channels over `transport`
+    pub fn transport(&self, transport: &str) -> TransportStats {
+        let mut transports = self
+            .counters
+            .transports
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        transports.entry(transport.to_string()).or_default().clone()
+    }
+
+    /// Snapshots of every transport that has carried a channel
+    pub fn transport_stats(&self) -> Vec<TransportStatsSnapshot> {
+        let transports = self
+            .counters
+            .transports
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        transports
+            .iter()
+            .map(|(name, stats)| stats.snapshot(name))
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let c = &self.counters;
+        MetricsSnapshot {
+            circuits_built: c.circuits_built.load(Ordering::Relaxed),
+            circuits_failed: c.circuits_failed.load(Ordering::Relaxed),
+            streams_opened: c.streams_opened.load(Ordering::Relaxed),
+            streams_failed: c.streams_failed.load(Ordering::Relaxed),
+            http_bytes_sent: c.http_bytes_sent.load(Ordering::Relaxed),
+            http_bytes_received: c.http_bytes_received.load(Ordering::Relaxed),
+            directory_channel_success: c.directory_channel_success.load(Ordering::Relaxed),
+            directory_channel_failure: c.directory_channel_failure.load(Ordering::Relaxed),
+            directory_cached_success: c.directory_cached_success.load(Ordering::Relaxed),
+            directory_cached_failure: c.directory_cached_failure.load(Ordering::Relaxed),
+            directory_injected_success: c.directory_injected_success.load(Ordering::Relaxed),
+            directory_injected_failure: c.directory_injected_failure.load(Ordering::Relaxed),
+            transports: self.transport_stats(),
+        }
+    }
+
+    /// Render the counters and current circuit gauges in Prometheus text format
+    pub fn render_prometheus(&self, circuits: &CircuitStatusInfo) -> String {
+        let s = self.snapshot();
+        let mut out = String::new();
+
+        write_family(
+            &mut out,
+            "webtor_circuits",
+            "gauge",
+            "Circuits currently tracked by the client, by status",
+            &[
+                ("status=\"ready\"", circuits.ready_circuits as u64),
+                ("status=\"creating\"", circuits.creating_circuits as u64),
+                ("status=\"failed\"", circuits.failed_circuits as u64),
+            ],
+        );
+        write_family(
+            &mut out,
+            "webtor_circuit_builds_total",
+            "counter",
+            "Circuit build attempts, by outcome",
+            &[
+                ("outcome=\"success\"", s.circuits_built),
+                ("outcome=\"failure\"", s.circuits_failed),
+            ],
+        );
+        let failure_labels: Vec<(String, u64)> = circuits
+            .build_failures
+            .iter()
+            .map(|(cause, count)| (format!("cause=\"{}\"", cause), *count as u64))
+            .collect();
+        let failure_samples: Vec<(&str, u64)> = failure_labels
+            .iter()
+            .map(|(labels, count)| (labels.as_str(), *count))
+            .collect();
+        write_family(
+            &mut out,
+            "webtor_circuit_build_failures_total",
+            "counter",
+            "Failed circuit builds, by the step that failed",
+            &failure_samples,
+        );
+        write_family(
+            &mut out,
+            "webtor_streams_total",
+            "counter",
+            "Stream open attempts, by outcome",
+            &[
+                ("outcome=\"success\"", s.streams_opened),
+                ("outcome=\"failure\"", s.streams_failed),
+            ],
+        );
+        write_family(
+            &mut out,
+            "webtor_http_bytes_total",
+            "counter",
+            "HTTP payload bytes exchanged through Tor, by direction",
+            &[
+                ("direction=\"sent\"", s.http_bytes_sent),
+                ("direction=\"received\"", s.http_bytes_received),
+            ],
+        );
+
+        let channel = DirectorySource::Channel.as_label();
+        let cached = DirectorySource::Cached.as_label();
+        let injected = DirectorySource::Injected.as_label();
+        write_family(
+            &mut out,
+            "webtor_directory_fetches_total",
+            "counter",
+            "Consensus fetches, by source and outcome",
+            &[
+                (
+                    &format!("source=\"{}\",outcome=\"success\"", channel),
+                    s.directory_channel_success,
+                ),
+                (
+                    &format!("source=\"{}\",outcome=\"failure\"", channel),
+                    s.directory_channel_failure,
+                ),
+                (
+                    &format!("source=\"{}\",outcome=\"success\"", cached),
+                    s.directory_cached_success,
+                ),
+                (
+                    &format!("source=\"{}\",outcome=\"failure\"", cached),
+                    s.directory_cached_failure,
+                ),
+                (
+                    &format!("source=\"{}\",outcome=\"success\"", injected),
+                    s.directory_injected_success,
+                ),
+                (
+                    &format!("source=\"{}\",outcome=\"failure\"", injected),
+                    s.directory_injected_failure,
+                ),
+            ],
+        );
+
+        let mut bytes = Vec::new();
+        let mut cells = Vec::new();
+        let mut connections = Vec::new();
+        let mut srtt = Vec::new();
+        for t in &s.transports {
+            let transport = format!("transport=\"{}\"", t.transport);
+            let sent = format!("{},direction=\"sent\"", transport);
+            let received = format!("{},direction=\"received\"", transport);
+            bytes.push((sent.clone(), t.bytes_sent));
+            bytes.push((received.clone(), t.bytes_received));
+            cells.push((sent, t.cells_sent));
+            cells.push((received, t.cells_received));
+            connections.push((transport.clone(), t.connections));
+            if let Some(ms) = t.srtt_ms {
+                srtt.push((transport, ms));
+            }
+        }
+        write_family(
+            &mut out,
+            "webtor_transport_bytes_total",
+            "counter",
+            "Bytes carried by bridge transports, by transport and direction",
+            &borrowed(&bytes),
+        );
+        write_family(
+            &mut out,
+            "webtor_transport_cells_total",
+            "counter",
+            "Tor cells carried by bridge transports, by transport and direction",
+            &borrowed(&cells),
+        );
+        write_family(
+            &mut out,
+            "webtor_transport_connections_total",
+            "counter",
+            "Channels opened over each bridge transport",
+            &borrowed(&connections),
+        );
+        write_family(
+            &mut out,
+            "webtor_transport_srtt_milliseconds",
+            "gauge",
+            "Smoothed round-trip time to the bridge, by transport",
+            &borrowed(&srtt),
+        );
+
+        out
+    }
+}
+
+fn borrowed(samples: &[(String, u64)]) -> Vec<(&str, u64)> {
+    samples
+        .iter()
+        .map(|(labels, value)| (labels.as_str(), *value))
+        .collect()
+}
+
+fn write_family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(&str, u64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+/// Serve `render()` as `text/plain` on a local HTTP endpoint until `shutdown` is cancelled
+///
+/// Every request gets the same response regardless of path or method; the
+/// endpoint is meant for a scraper on localhost, not for the open internet.
+/// Returns the bound address (useful when binding port 0).
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn serve_prometheus<F, Fut>(
+    addr: std::net::SocketAddr,
+    shutdown: crate::retry::CancellationToken,
+    render: F,
+) -> crate::error::Result<std::net::SocketAddr>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = String> + Send + 'static,
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tracing::{debug, info};
+
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    info!(
+        "Serving Prometheus metrics on http://{}/metrics",
+        local_addr
+    );
+
+    let render = Arc::new(render);
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, peer) = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        debug!("Metrics endpoint accept failed: {}", e);
+                        continue;
+                    }
+                },
+            };
+
+            let render = render.clone();
+            tokio::spawn(async move {
+                // The request itself is irrelevant; read it so the client
+                // doesn't see a reset before the response
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+
+                let body = render().await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                if let Err(e) = socket.write_all(response.as_bytes()).await {
+                    debug!("Failed to write metrics to {}: {}", peer, e);
+                }
+                let _ = socket.shutdown().await;
+            });
+        }
+        debug!("Metrics endpoint stopped");
+    });
+
+    Ok(local_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn status(ready: usize, creating: usize, failed: usize) -> CircuitStatusInfo {
+        CircuitStatusInfo {
+            total_circuits: ready + creating + failed,
+            ready_circuits: ready,
+            creating_circuits: creating,
+            failed_circuits: failed,
+            average_circuit_age: Duration::ZERO,
+            entry_mode: None,
+            conflux_circuits: 0,
+            circuit_metrics: Vec::new(),
+            build_failures: [("extend_exit".to_string(), 1)].into(),
+        }
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = Metrics::new();
+        metrics.record_circuit(true);
+        metrics.record_circuit(true);
+        metrics.record_circuit(false);
+        metrics.record_stream(true);
+        metrics.record_http_bytes(120, 4096);
+        metrics.record_directory_fetch(DirectorySource::Channel, true);
+        metrics.record_directory_fetch(DirectorySource::Cached, false);
+        metrics
+            .transport("webtunnel")
+            .record_rtt(Duration::from_millis(75));
+
+        let text = metrics.render_prometheus(&status(2, 1, 0));
+        assert!(text.contains("# TYPE webtor_circuits gauge\n"));
+        assert!(text.contains("webtor_circuits{status=\"ready\"} 2\n"));
+        assert!(text.contains("webtor_circuit_builds_total{outcome=\"success\"} 2\n"));
+        assert!(text.contains("webtor_circuit_builds_total{outcome=\"failure\"} 1\n"));
+        assert!(text.contains("webtor_circuit_build_failures_total{cause=\"extend_exit\"} 1\n"));
+        assert!(text.contains("webtor_streams_total{outcome=\"success\"} 1\n"));
+        assert!(text.contains("webtor_http_bytes_total{direction=\"received\"} 4096\n"));
+        assert!(text.contains(
+            "webtor_directory_fetches_total{source=\"channel\",outcome=\"success\"} 1\n"
+        ));
+        assert!(text
+            .contains("webtor_directory_fetches_total{source=\"cached\",outcome=\"failure\"} 1\n"));
+        assert!(text.contains(
+            "webtor_transport_bytes_total{transport=\"webtunnel\",direction=\"sent\"} 0\n"
+        ));
+        assert!(text.contains("webtor_transport_srtt_milliseconds{transport=\"webtunnel\"} 75\n"));
+    }
+
+    #[test]
+    fn test_clones_share_counters() {
+        let metrics = Metrics::new();
+        metrics.clone().record_stream(false);
+        assert_eq!(metrics.snapshot().streams_failed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_serve_prometheus() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let shutdown = crate::retry::CancellationToken::new();
+        let addr = serve_prometheus("127.0.0.1:0".parse().unwrap(), shutdown.clone(), || async {
+            "webtor_up 1\n".to_string()
+        })
+        .await
+        .unwrap();
+
+        let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+        socket
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        shutdown.cancel();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nwebtor_up 1\n"));
+    }
+}
@@ -0,0 +1,503 @@
+//! Moat client for fetching bridges from the Tor Project's distributor
+//!
+//! Moat is the HTTPS API of rdsys (formerly BridgeDB) that Tor Browser's
+//! connection assist uses, so someone without a bridge line can still get
+//! one. Two flows are offered:
+//!
+//! - Circumvention settings: [`MoatClient::settings`] asks which transports
+//!   work in a country (guessed from the client's address when none is
+//!   given) and usually returns bridge lines right away.
+//!   [`MoatClient::builtin_bridges`] returns the bridges built into Tor
+//!   Browser for when settings don't help.
+//! - Captcha: settings sourced from `bridgedb` carry no lines; the client
+//!   asks for a challenge ([`MoatClient::fetch_challenge`]), the host app
+//!   shows the image to the user, and [`MoatClient::check_solution`]
+//!   exchanges the answer for private bridge lines.
+//!
+//! Outside the browser requests are domain-fronted through the CDN77
+//! reflector Tor Browser uses (see [`crate::fronted`]). In the browser they
+//! go straight to the configured URL, which has to allow the page's origin.
+
+use crate::bridge_line::BridgeLine;
+use crate::capabilities::Capabilities;
+use crate::error::{Result, TorError};
+use crate::fronted;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use tracing::{debug, info};
+
+/// Moat API on bridges.torproject.org (direct)
+pub const MOAT_URL: &str = "https://bridges.torproject.org/moat";
+
+/// Moat API on the CDN77 reflector (used with [`MOAT_FRONT_DOMAINS`])
+pub const MOAT_URL_FRONTED: &str = "https://1723079976.rsc.cdn77.org/moat";
+
+/// Front domains for domain fronting Moat requests (CDN77)
+pub const MOAT_FRONT_DOMAINS: &[&str] = &["www.phpmyadmin.net", "cdn.zk.mk"];
+
+/// Content type of Moat requests (JSON:API)
+const MOAT_CONTENT_TYPE: &str = "application/vnd.api+json";
+
+/// Version of the captcha flow messages
+const MOAT_VERSION: &str = "0.1.0";
+
+/// rdsys error code for a wrong captcha solution
+const INCORRECT_SOLUTION: u16 = 419;
+
+/// Bridges the settings endpoint recommends for one transport
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoatSettings {
+    /// Transport name, e.g. `obfs4` or `snowflake`
+    pub transport: String,
+    /// Where the bridges come from: `builtin` or `bridgedb`
+    pub source: String,
+    pub bridge_lines: Vec<String>,
+}
+
+impl MoatSettings {
+    /// Whether these bridges have to be fetched with the captcha flow
+    pub fn needs_captcha(&self) -> bool {
+        self.bridge_lines.is_empty() && self.source == "bridgedb"
+    }
+}
+
+/// A captcha to show the user before private bridges are handed out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoatChallenge {
+    /// Transport the bridges will be for
+    pub transport: String,
+    /// Base64-encoded JPEG
+    pub image: String,
+    /// Opaque token to send back with the solution
+    pub challenge: String,
+}
+
+/// Client for the Moat API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoatClient {
+    url: String,
+    fronts: Vec<String>,
+}
+
+impl Default for MoatClient {
+    fn default() -> Self {
+        Self::fronted()
+    }
+}
+
+impl MoatClient {
+    /// Client for the Moat API at `url`, contacted directly
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            fronts: Vec::new(),
+        }
+    }
+
+    /// Client for the Tor Project's Moat API, domain-fronted outside the
+    /// browser
+    pub fn fronted() -> Self {
+        #[cfg(target_arch = "wasm32")]
+        {
+            Self::new(MOAT_URL)
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            Self::new(MOAT_URL_FRONTED).with_fronts(
+                MOAT_FRONT_DOMAINS
+                    .iter()
+                    .map(|front| front.to_string())
+                    .collect(),
+            )
+        }
+    }
+
+    /// Domain-front requests through one of `fronts` (ignored in the browser)
+    pub fn with_fronts(mut self, fronts: Vec<String>) -> Self {
+        self.fronts = fronts;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn fronts(&self) -> &[String] {
+        &self.fronts
+    }
+
+    /// Bridges recommended for `country` (a two-letter code, or the
+    /// client's apparent country if `None`), limited to `transports`
+    ///
+    /// An empty list means Moat has no advice for the country, and
+    /// [`Self::builtin_bridges`] is the usual next step.
+    pub async fn settings(
+        &self,
+        country: Option<&str>,
+        transports: &[String],
+    ) -> Result<Vec<MoatSettings>> {
+        let request = settings_request(country, transports);
+        let response: SettingsResponse = self.post("circumvention/settings", &request).await?;
+        Ok(response.into_settings())
+    }
+
+    /// Tor Browser's built-in bridge lines for each of `transports`
+    pub async fn builtin_bridges(
+        &self,
+        transports: &[String],
+    ) -> Result<BTreeMap<String, Vec<String>>> {
+        let request = json!({ "transports": transports });
+        Ok(self.post("circumvention/builtin", &request).await?)
+    }
+
+    /// Ask for a captcha guarding private bridges of one of `transports`
+    pub async fn fetch_challenge(&self, transports: &[String]) -> Result<MoatChallenge> {
+        let request = json!({
+            "data": [{
+                "version": MOAT_VERSION,
+                "type": "client-transports",
+                "supported": transports,
+            }]
+        });
+        let response: DataResponse<MoatChallenge> = self.post("fetch", &request).await?;
+        response.into_first()
+    }
+
+    /// Send the user's answer to `challenge`: the bridge lines it unlocks,
+    /// or `None` if the answer was wrong and a new challenge is needed
+    pub async fn check_solution(
+        &self,
+        challenge: &MoatChallenge,
+        solution: &str,
+    ) -> Result<Option<Vec<String>>> {
+        let request = solution_request(challenge, solution);
+        match self
+            .post::<DataResponse<BridgesData>>("check", &request)
+            .await
+        {
+            Ok(response) => Ok(Some(response.into_first()?.bridges)),
+            Err(MoatFailure::Moat(error)) if error.code == INCORRECT_SOLUTION => {
+                debug!("Moat rejected the captcha solution");
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Bridge lines this build can use, for users without one
+    ///
+    /// Follows the settings for `country`, falling back to the built-in
+    /// bridges when Moat has no advice. Settings that need a captcha are
+    /// skipped; use [`Self::fetch_challenge`] for those.
+    pub async fn bridge_lines(&self, country: Option<&str>) -> Result<Vec<String>> {
+        let capabilities = Capabilities::detect();
+        let transports = supported_transports(&capabilities);
+        let settings = self.settings(country, &transports).await?;
+        let mut lines: Vec<String> = settings
+            .into_iter()
+            .flat_map(|setting| setting.bridge_lines)
+            .collect();
+        if lines.is_empty() {
+            info!("Moat has no settings for this location, using built-in bridges");
+            lines = self
+                .builtin_bridges(&transports)
+                .await?
+                .into_values()
+                .flatten()
+                .collect();
+        }
+        let lines = usable_lines(lines, &capabilities);
+        if lines.is_empty() {
+            return Err(TorError::Network(
+                "Moat returned no bridges usable in this environment".to_string(),
+            ));
+        }
+        info!("Moat returned {} usable bridge lines", lines.len());
+        Ok(lines)
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        request: &serde_json::Value,
+    ) -> std::result::Result<T, MoatFailure> {
+        let url = format!("{}/{}", self.url, path);
+        let body = request.to_string();
+        let response = fronted::fetch(&url, &self.fronts, Some(body.as_bytes()), MOAT_CONTENT_TYPE)
+            .await
+            .map_err(MoatFailure::Other)?;
+        decode_response(&response)
+    }
+}
+
+/// Moat names of the transports usable with `capabilities`
+pub fn supported_transports(capabilities: &Capabilities) -> Vec<String> {
+    let unavailable = capabilities.report().unavailable;
+    [
+        ("obfs4", "obfs4"),
+        // Snowflake bridge lines are used over WebRTC
+        ("snowflake", "snowflakeWebRtc"),
+        ("webtunnel", "webTunnel"),
+        ("meek", "meek"),
+    ]
+    .into_iter()
+    .filter(|(_, feature)| !unavailable.iter().any(|u| u.feature == *feature))
+    .map(|(transport, _)| transport.to_string())
+    .collect()
+}
+
+/// The lines of `lines` that parse and whose transport works here
+fn usable_lines(lines: Vec<String>, capabilities: &Capabilities) -> Vec<String> {
+    lines
+        .into_iter()
+        .filter(|line| {
+            let usable = line
+                .parse::<BridgeLine>()
+                .and_then(|parsed| parsed.bridge_type())
+                .map(|bridge| capabilities.bridge_unavailable(&bridge).is_none());
+            if !matches!(usable, Ok(true)) {
+                debug!("Skipping Moat bridge line: {}", line);
+            }
+            matches!(usable, Ok(true))
+        })
+        .collect()
+}
+
+fn settings_request(country: Option<&str>, transports: &[String]) -> serde_json::Value {
+    let mut request = json!({ "transports": transports });
+    if let Some(country) = country {
+        request["country"] = json!(country.to_lowercase());
+    }
+    request
+}
+
+fn solution_request(challenge: &MoatChallenge, solution: &str) -> serde_json::Value {
+    json!({
+        "data": [{
+            "id": "2",
+            "version": MOAT_VERSION,
+            "type": "moat-solution",
+            "transport": challenge.transport,
+            "challenge": challenge.challenge,
+            "solution": solution,
+            "qrcode": "false",
+        }]
+    })
+}
+
+/// Why a Moat request failed: an error reported by Moat, or anything else
+#[derive(Debug)]
+enum MoatFailure {
+    Moat(MoatError),
+    Other(TorError),
+}
+
+impl From<MoatFailure> for TorError {
+    fn from(failure: MoatFailure) -> Self {
+        match failure {
+            MoatFailure::Moat(error) => TorError::Network(format!(
+                "Moat error {}: {}",
+                error.code,
+                error.detail.as_deref().unwrap_or("no details")
+            )),
+            MoatFailure::Other(error) => error,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MoatError {
+    code: u16,
+    #[serde(default)]
+    detail: Option<String>,
+}
+
+/// Decode a Moat response, which is either `T` or a list of errors
+fn decode_response<T: DeserializeOwned>(body: &[u8]) -> std::result::Result<T, MoatFailure> {
+    #[derive(Deserialize)]
+    struct Errors {
+        errors: Vec<MoatError>,
+    }
+
+    if let Ok(Errors { errors }) = serde_json::from_slice(body) {
+        if let Some(error) = errors.into_iter().next() {
+            return Err(MoatFailure::Moat(error));
+        }
+    }
+    serde_json::from_slice(body).map_err(|e| {
+        MoatFailure::Other(TorError::Protocol(format!("Invalid Moat response: {}", e)))
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct SettingsResponse {
+    #[serde(default)]
+    settings: Option<Vec<SettingsEntry>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SettingsEntry {
+    bridges: BridgeSettings,
+}
+
+#[derive(Debug, Deserialize)]
+struct BridgeSettings {
+    #[serde(rename = "type")]
+    transport: String,
+    #[serde(default)]
+    source: String,
+    #[serde(default)]
+    bridge_strings: Option<Vec<String>>,
+}
+
+impl SettingsResponse {
+    fn into_settings(self) -> Vec<MoatSettings> {
+        self.settings
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| MoatSettings {
+                transport: entry.bridges.transport,
+                source: entry.bridges.source,
+                bridge_lines: entry.bridges.bridge_strings.unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// JSON:API envelope of the captcha flow responses
+#[derive(Debug, Deserialize)]
+struct DataResponse<T> {
+    data: Vec<T>,
+}
+
+impl<T> DataResponse<T> {
+    fn into_first(self) -> Result<T> {
+        self.data
+            .into_iter()
+            .next()
+            .ok_or_else(|| TorError::Protocol("Empty Moat response".to_string()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BridgesData {
+    bridges: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OBFS4_LINE: &str =
+        "obfs4 192.0.2.3:443 4A0CCD2DDC7995083D73F5D667100C8A5831F16D cert=c2VjcmV0 iat-mode=0";
+    const SNOWFLAKE_LINE: &str =
+        "snowflake 192.0.2.4:80 2B280B23E1107BB62ABFC40DDCC8824814F80A72 url=https://snowflake-broker.torproject.net/";
+
+    #[test]
+    fn test_requests() {
+        let transports = vec!["obfs4".to_string(), "snowflake".to_string()];
+        assert_eq!(
+            settings_request(Some("CN"), &transports),
+            json!({ "country": "cn", "transports": ["obfs4", "snowflake"] })
+        );
+        assert_eq!(
+            settings_request(None, &transports),
+            json!({ "transports": ["obfs4", "snowflake"] })
+        );
+
+        let challenge = MoatChallenge {
+            transport: "obfs4".to_string(),
+            image: String::new(),
+            challenge: "token".to_string(),
+        };
+        let request = solution_request(&challenge, "AbCd");
+        assert_eq!(request["data"][0]["type"], "moat-solution");
+        assert_eq!(request["data"][0]["challenge"], "token");
+        assert_eq!(request["data"][0]["solution"], "AbCd");
+    }
+
+    #[test]
+    fn test_settings_response() {
+        let body = format!(
+            r#"{{"settings":[
+                {{"bridges":{{"type":"snowflake","source":"builtin","bridge_strings":["{}"]}}}},
+                {{"bridges":{{"type":"obfs4","source":"bridgedb"}}}}
+            ],"country":"cn"}}"#,
+            SNOWFLAKE_LINE
+        );
+        let settings = decode_response::<SettingsResponse>(body.as_bytes())
+            .unwrap()
+            .into_settings();
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings[0].bridge_lines, vec![SNOWFLAKE_LINE.to_string()]);
+        assert!(!settings[0].needs_captcha());
+        assert!(settings[1].needs_captcha());
+
+        let none = decode_response::<SettingsResponse>(br#"{"settings":null,"country":"de"}"#)
+            .unwrap()
+            .into_settings();
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn test_captcha_responses() {
+        let body = br#"{"data":[{"id":"1","type":"moat-challenge","version":"0.1.0",
+            "transport":"obfs4","image":"/9j/4AAQ","challenge":"token"}]}"#;
+        let challenge = decode_response::<DataResponse<MoatChallenge>>(body)
+            .unwrap()
+            .into_first()
+            .unwrap();
+        assert_eq!(challenge.transport, "obfs4");
+        assert_eq!(challenge.challenge, "token");
+
+        let body = format!(
+            r#"{{"data":[{{"id":"3","type":"moat-bridges","version":"0.1.0","bridges":["{}"],"qrcode":null}}]}}"#,
+            OBFS4_LINE
+        );
+        let bridges = decode_response::<DataResponse<BridgesData>>(body.as_bytes())
+            .unwrap()
+            .into_first()
+            .unwrap();
+        assert_eq!(bridges.bridges, vec![OBFS4_LINE.to_string()]);
+    }
+
+    #[test]
+    fn test_error_response() {
+        let body = br#"{"errors":[{"id":"4","type":"","version":"0.1.0","code":419,
+            "status":"No You're A Teapot","detail":"The CAPTCHA solution was incorrect."}]}"#;
+        let failure = decode_response::<DataResponse<BridgesData>>(body).unwrap_err();
+        assert!(matches!(failure, MoatFailure::Moat(ref e) if e.code == INCORRECT_SOLUTION));
+        let error = TorError::from(failure);
+        assert!(error.to_string().contains("CAPTCHA solution was incorrect"));
+
+        assert!(matches!(
+            decode_response::<SettingsResponse>(b"<html>"),
+            Err(MoatFailure::Other(TorError::Protocol(_)))
+        ));
+    }
+
+    #[test]
+    fn test_usable_lines() {
+        let webtunnel = "webtunnel 192.0.2.5:443 4A0CCD2DDC7995083D73F5D667100C8A5831F16D url=https://bridge.example/path";
+        let lines = vec![
+            webtunnel.to_string(),
+            SNOWFLAKE_LINE.to_string(),
+            OBFS4_LINE.to_string(),
+            "not a bridge line".to_string(),
+        ];
+        let usable = usable_lines(lines, &Capabilities::native());
+        let mut expected = vec![webtunnel.to_string()];
+        if cfg!(feature = "obfs4") {
+            expected.push(OBFS4_LINE.to_string());
+        }
+        assert_eq!(usable, expected);
+
+        let transports = supported_transports(&Capabilities::native());
+        assert!(transports.contains(&"webtunnel".to_string()));
+        assert!(!transports.contains(&"snowflake".to_string()));
+    }
+}
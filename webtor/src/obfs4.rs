@@ -0,0 +1,1034 @@
+//! obfs4 pluggable transport for Tor connections
+//!
+//! obfs4 makes a connection look like a stream of uniformly random bytes.
+//! Its handshake is an ntor key exchange in which the client's ephemeral key
+//! is Elligator2-encoded. The handshake is authenticated against the
+//! bridge's `cert` from the bridge line. After it, data moves in encrypted,
+//! length-obfuscated frames (see [`crate::obfs4_framing`]).
+//!
+//! Protocol flow:
+//! 1. Connect TCP to the bridge's address
+//! 2. Send `X' | P_C | M_C | MAC_C`: representative, random padding, mark, MAC
+//! 3. Receive `Y' | AUTH | P_S | M_S | MAC_S`, check the MAC and ntor AUTH
+//! 4. Derive per-direction frame keys from the ntor KEY_SEED
+//! 5. Establish Tor link TLS over the framed stream
+//!
+//! obfs4 needs raw TCP, so it is only available in native builds with the
+//! `obfs4` feature. The client doesn't do inter-arrival-time obfuscation:
+//! it parses a bridge's `iat-mode` but always sends as in mode 0. Each
+//! write is padded to a uniformly random length, rather than to a length
+//! drawn from the distribution the bridge seeds.
+//!
+//! Reference: https://gitlab.com/yawning/obfs4/-/blob/master/doc/obfs4-spec.txt
+
+use crate::elligator2::{self, Keypair};
+use crate::error::{Result, TorError};
+use crate::obfs4_framing::{
+    self, Decoder, Encoder, FRAME_OVERHEAD, MAX_PACKET_PAYLOAD_LENGTH, MAX_SEGMENT_LENGTH,
+    PACKET_OVERHEAD, PACKET_TYPE_PAYLOAD, PACKET_TYPE_PRNG_SEED,
+};
+use crate::proxy::{self, UpstreamProxy};
+use crate::retry::with_timeout;
+use crate::time::system_time_now;
+use crate::transport::{LinkStream, Transport};
+use crate::webtunnel::TorCertVerifier;
+use base64::Engine;
+use futures::{ready, AsyncRead, AsyncWrite};
+use futures_rustls::rustls::pki_types::ServerName;
+use futures_rustls::rustls::ClientConfig;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+use tracing::{debug, info, trace};
+
+/// Length of the bridge's node ID
+pub const NODE_ID_LENGTH: usize = 20;
+
+/// Length of a decoded `cert` argument: node ID plus identity public key
+pub const CERT_LENGTH: usize = NODE_ID_LENGTH + elligator2::KEY_LENGTH;
+
+const PROTO_ID: &[u8] = b"ntor-curve25519-sha256-1";
+const T_MAC: &[u8] = b"ntor-curve25519-sha256-1:mac";
+const T_KEY: &[u8] = b"ntor-curve25519-sha256-1:key_extract";
+const T_VERIFY: &[u8] = b"ntor-curve25519-sha256-1:key_verify";
+const M_EXPAND: &[u8] = b"ntor-curve25519-sha256-1:key_expand";
+
+const MAX_HANDSHAKE_LENGTH: usize = 8192;
+const MARK_LENGTH: usize = 16;
+const MAC_LENGTH: usize = 16;
+const AUTH_LENGTH: usize = 32;
+const REPRESENTATIVE_LENGTH: usize = elligator2::KEY_LENGTH;
+
+/// Frame carrying the bridge's PRNG seed, sent right after its handshake
+const INLINE_SEED_FRAME_LENGTH: usize =
+    FRAME_OVERHEAD + PACKET_OVERHEAD + obfs4_framing::SEED_LENGTH;
+const CLIENT_MIN_HANDSHAKE_LENGTH: usize = REPRESENTATIVE_LENGTH + MARK_LENGTH + MAC_LENGTH;
+const SERVER_MIN_HANDSHAKE_LENGTH: usize =
+    REPRESENTATIVE_LENGTH + AUTH_LENGTH + MARK_LENGTH + MAC_LENGTH;
+
+/// Client padding keeps its handshake at least as long as the bridge's reply
+const CLIENT_MIN_PAD_LENGTH: usize =
+    SERVER_MIN_HANDSHAKE_LENGTH + INLINE_SEED_FRAME_LENGTH - CLIENT_MIN_HANDSHAKE_LENGTH;
+const CLIENT_MAX_PAD_LENGTH: usize = MAX_HANDSHAKE_LENGTH - CLIENT_MIN_HANDSHAKE_LENGTH;
+
+/// Frame and packet headers, the smallest a padding packet can be
+const HEADER_LENGTH: usize = FRAME_OVERHEAD + PACKET_OVERHEAD;
+
+/// Most plaintext one `poll_write` turns into frames
+const MAX_BURST_LENGTH: usize = 32 * MAX_PACKET_PAYLOAD_LENGTH;
+
+/// Highest `iat-mode` obfs4 defines (paranoid)
+const MAX_IAT_MODE: u8 = 2;
+
+/// The bridge's node ID and identity key, from the bridge line's `cert`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Obfs4Cert {
+    node_id: [u8; NODE_ID_LENGTH],
+    public_key: [u8; elligator2::KEY_LENGTH],
+}
+
+impl Obfs4Cert {
+    pub fn new(node_id: [u8; NODE_ID_LENGTH], public_key: [u8; elligator2::KEY_LENGTH]) -> Self {
+        Self {
+            node_id,
+            public_key,
+        }
+    }
+
+    pub fn node_id(&self) -> &[u8; NODE_ID_LENGTH] {
+        &self.node_id
+    }
+
+    pub fn public_key(&self) -> &[u8; elligator2::KEY_LENGTH] {
+        &self.public_key
+    }
+
+    /// HMAC key for the handshake marks and MACs: `B | NODEID`
+    fn mac_key(&self) -> Vec<u8> {
+        [&self.public_key[..], &self.node_id[..]].concat()
+    }
+}
+
+impl FromStr for Obfs4Cert {
+    type Err = TorError;
+
+    /// Parse the base64 `cert` argument (padding optional)
+    fn from_str(cert: &str) -> Result<Self> {
+        let bytes = base64::engine::general_purpose::STANDARD_NO_PAD
+            .decode(cert.trim().trim_end_matches('='))
+            .map_err(|e| TorError::Configuration(format!("Invalid obfs4 cert: {}", e)))?;
+        if bytes.len() != CERT_LENGTH {
+            return Err(TorError::Configuration(format!(
+                "obfs4 cert must decode to {} bytes, got {}",
+                CERT_LENGTH,
+                bytes.len()
+            )));
+        }
+        let mut node_id = [0u8; NODE_ID_LENGTH];
+        node_id.copy_from_slice(&bytes[..NODE_ID_LENGTH]);
+        let mut public_key = [0u8; elligator2::KEY_LENGTH];
+        public_key.copy_from_slice(&bytes[NODE_ID_LENGTH..]);
+        Ok(Self::new(node_id, public_key))
+    }
+}
+
+impl std::fmt::Display for Obfs4Cert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes = [&self.node_id[..], &self.public_key[..]].concat();
+        f.write_str(&base64::engine::general_purpose::STANDARD_NO_PAD.encode(bytes))
+    }
+}
+
+/// obfs4 bridge configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Obfs4Config {
+    /// Bridge address (`host:port`)
+    pub addr: String,
+    /// Bridge fingerprint (RSA identity, 40 hex chars)
+    pub fingerprint: String,
+    /// The bridge line's `cert` argument
+    pub cert: String,
+    /// The bridge line's `iat-mode` (0, 1 or 2)
+    #[serde(default)]
+    pub iat_mode: u8,
+    /// Connection timeout (milliseconds when serialized)
+    #[serde(default = "default_timeout", with = "crate::config::duration_ms")]
+    pub connection_timeout: Duration,
+    /// Proxy the TCP connection is made through
+    #[serde(default)]
+    pub proxy: Option<UpstreamProxy>,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+impl Obfs4Config {
+    pub fn new(addr: String, fingerprint: String, cert: String) -> Self {
+        Self {
+            addr,
+            fingerprint,
+            cert,
+            iat_mode: 0,
+            connection_timeout: default_timeout(),
+            proxy: None,
+        }
+    }
+
+    pub fn with_iat_mode(mut self, iat_mode: u8) -> Self {
+        self.iat_mode = iat_mode;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = timeout;
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: Option<UpstreamProxy>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+}
+
+/// obfs4 bridge connection manager
+pub struct Obfs4Bridge {
+    config: Obfs4Config,
+}
+
+impl Obfs4Bridge {
+    pub fn new(config: Obfs4Config) -> Self {
+        Self { config }
+    }
+
+    /// Connect to the obfs4 bridge
+    ///
+    /// Performs:
+    /// 1. TCP connection
+    /// 2. obfs4 handshake
+    /// 3. Tor link TLS over the obfs4 frames
+    pub async fn connect(&self) -> Result<Obfs4Stream> {
+        let cert: Obfs4Cert = self.config.cert.parse()?;
+        if self.config.iat_mode > MAX_IAT_MODE {
+            return Err(TorError::Configuration(format!(
+                "Invalid obfs4 iat-mode: {}",
+                self.config.iat_mode
+            )));
+        }
+        if self.config.iat_mode != 0 {
+            debug!(
+                "Bridge uses iat-mode={}, client traffic is sent without timing obfuscation",
+                self.config.iat_mode
+            );
+        }
+
+        info!("Connecting to obfs4 bridge at {}", self.config.addr);
+
+        let conn = with_timeout(self.config.connection_timeout, "obfs4 handshake", async {
+            // 1. Connect TCP
+            let (host, port) = proxy::split_host_port(&self.config.addr)?;
+            let tcp_stream = proxy::connect_tcp(self.config.proxy.as_ref(), host, port).await?;
+            debug!("TCP connected to {}", self.config.addr);
+
+            // 2. obfs4 handshake
+            handshake(tcp_stream.compat(), &cert).await
+        })
+        .await?;
+
+        info!("obfs4 handshake complete, establishing Tor link TLS");
+
+        // 3. Tor link TLS, authenticated later by CERTS cells as for WebTunnel
+        let tor_tls_config = ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(TorCertVerifier))
+            .with_no_client_auth();
+        let tor_connector = futures_rustls::TlsConnector::from(Arc::new(tor_tls_config));
+        let sni = ServerName::try_from("www.example.com".to_string())
+            .map_err(|e| TorError::Configuration(format!("Invalid SNI: {}", e)))?;
+
+        let tor_tls_stream = tor_connector
+            .connect(sni, conn)
+            .await
+            .map_err(|e| TorError::Network(format!("Tor link TLS handshake failed: {}", e)))?;
+
+        info!("Tor link TLS established, ready for channel handshake");
+
+        Ok(Obfs4Stream {
+            inner: tor_tls_stream,
+        })
+    }
+}
+
+/// Run the client side of the obfs4 handshake over `stream`
+pub async fn handshake<S>(mut stream: S, cert: &Obfs4Cert) -> Result<Obfs4Conn<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    let client = ClientHandshake::new(cert.clone());
+    stream
+        .write_all(&client.request())
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to send obfs4 handshake: {}", e)))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| TorError::Network(format!("Failed to send obfs4 handshake: {}", e)))?;
+
+    let mut response = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|e| TorError::Network(format!("Failed to read obfs4 handshake: {}", e)))?;
+        if n == 0 {
+            return Err(TorError::Network(
+                "obfs4 bridge closed the connection during the handshake".to_string(),
+            ));
+        }
+        response.extend_from_slice(&chunk[..n]);
+
+        if let Some((consumed, key_seed)) = client.parse_response(&response)? {
+            let (encoder_key, decoder_key) = derive_frame_keys(&key_seed);
+            // Anything after the handshake is already framed data
+            response.drain(..consumed);
+            debug!(
+                "obfs4 handshake complete, {} framed bytes buffered",
+                response.len()
+            );
+            return Ok(Obfs4Conn::new(
+                stream,
+                Encoder::new(&encoder_key),
+                Decoder::new(&decoder_key),
+                response,
+            ));
+        }
+    }
+}
+
+/// Client handshake state: the ephemeral key and the epoch hour it was MACed with
+struct ClientHandshake {
+    cert: Obfs4Cert,
+    keypair: Keypair,
+    epoch_hour: String,
+}
+
+impl ClientHandshake {
+    fn new(cert: Obfs4Cert) -> Self {
+        Self {
+            cert,
+            keypair: Keypair::generate(),
+            epoch_hour: epoch_hour(),
+        }
+    }
+
+    /// `X' | P_C | M_C | MAC_C`
+    fn request(&self) -> Vec<u8> {
+        let mac_key = self.cert.mac_key();
+        let representative = self.keypair.representative();
+        let pad_length =
+            rand::thread_rng().gen_range(CLIENT_MIN_PAD_LENGTH..=CLIENT_MAX_PAD_LENGTH);
+        let mut padding = vec![0u8; pad_length];
+        rand::thread_rng().fill(&mut padding[..]);
+
+        let mut request = Vec::with_capacity(CLIENT_MIN_HANDSHAKE_LENGTH + pad_length);
+        request.extend_from_slice(representative);
+        request.extend_from_slice(&padding);
+        request.extend_from_slice(&hmac_sha256(&mac_key, &[representative])[..MARK_LENGTH]);
+        let mac = hmac_sha256(&mac_key, &[&request, self.epoch_hour.as_bytes()]);
+        request.extend_from_slice(&mac[..MAC_LENGTH]);
+        request
+    }
+
+    /// Check the bridge's reply once enough of it has arrived
+    ///
+    /// Returns the length of the handshake and the ntor KEY_SEED, or
+    /// `Ok(None)` if the bridge's mark hasn't been received yet.
+    fn parse_response(&self, response: &[u8]) -> Result<Option<(usize, [u8; 32])>> {
+        if response.len() < SERVER_MIN_HANDSHAKE_LENGTH {
+            return Ok(None);
+        }
+        let mac_key = self.cert.mac_key();
+        let mut server_representative = [0u8; REPRESENTATIVE_LENGTH];
+        server_representative.copy_from_slice(&response[..REPRESENTATIVE_LENGTH]);
+        let server_auth = &response[REPRESENTATIVE_LENGTH..REPRESENTATIVE_LENGTH + AUTH_LENGTH];
+
+        let mark = hmac_sha256(&mac_key, &[&server_representative]);
+        let Some(pos) = find_mark_mac(
+            &mark[..MARK_LENGTH],
+            response,
+            REPRESENTATIVE_LENGTH + AUTH_LENGTH,
+        ) else {
+            if response.len() >= MAX_HANDSHAKE_LENGTH {
+                return Err(TorError::Protocol(
+                    "obfs4 bridge handshake has no valid mark".to_string(),
+                ));
+            }
+            return Ok(None);
+        };
+
+        let mac_end = pos + MARK_LENGTH;
+        let expected = hmac_sha256(
+            &mac_key,
+            &[&response[..mac_end], self.epoch_hour.as_bytes()],
+        );
+        if !ct_eq(
+            &expected[..MAC_LENGTH],
+            &response[mac_end..mac_end + MAC_LENGTH],
+        ) {
+            return Err(TorError::Protocol(
+                "obfs4 bridge handshake MAC mismatch".to_string(),
+            ));
+        }
+
+        let server_public = elligator2::representative_to_public_key(&server_representative);
+        let (key_seed, auth) = ntor(
+            &self.keypair.diffie_hellman(&server_public),
+            &self.keypair.diffie_hellman(self.cert.public_key()),
+            &self.cert,
+            self.keypair.public_key(),
+            &server_public,
+        )
+        .ok_or_else(|| TorError::Protocol("obfs4 ntor handshake failed".to_string()))?;
+        if !ct_eq(&auth, server_auth) {
+            return Err(TorError::Protocol(
+                "obfs4 bridge failed ntor authentication (wrong cert?)".to_string(),
+            ));
+        }
+        Ok(Some((mac_end + MAC_LENGTH, key_seed)))
+    }
+}
+
+/// The ntor KEY_SEED and AUTH for the two DH results
+///
+/// The client passes `EXP(Y, x)` and `EXP(B, x)`, the bridge `EXP(X, y)` and
+/// `EXP(X, b)`. The suffix is `B | B | X | Y | PROTOID | ID`, not tor's
+/// `ID | B | X | Y | PROTOID`: obfs4proxy builds it that way and every
+/// obfs4 implementation has to match.
+fn ntor(
+    exp_ephemeral: &[u8; 32],
+    exp_identity: &[u8; 32],
+    cert: &Obfs4Cert,
+    client_public: &[u8; 32],
+    server_public: &[u8; 32],
+) -> Option<([u8; 32], [u8; 32])> {
+    if exp_ephemeral.iter().all(|&b| b == 0) || exp_identity.iter().all(|&b| b == 0) {
+        return None;
+    }
+
+    let suffix = [
+        &cert.public_key[..],
+        &cert.public_key[..],
+        client_public,
+        server_public,
+        PROTO_ID,
+        &cert.node_id[..],
+    ]
+    .concat();
+    let secret_input = [&exp_ephemeral[..], &exp_identity[..], &suffix].concat();
+
+    let key_seed = hmac_sha256(T_KEY, &[&secret_input]);
+    let verify = hmac_sha256(T_VERIFY, &[&secret_input]);
+    let auth = hmac_sha256(T_MAC, &[&verify, &suffix, b"Server"]);
+    Some((key_seed, auth))
+}
+
+/// Split the KDF output into the client's encoder and decoder keys
+fn derive_frame_keys(
+    key_seed: &[u8; 32],
+) -> (
+    [u8; obfs4_framing::KEY_LENGTH],
+    [u8; obfs4_framing::KEY_LENGTH],
+) {
+    let mut okm = [0u8; 2 * obfs4_framing::KEY_LENGTH];
+    hkdf::Hkdf::<Sha256>::new(Some(T_KEY), key_seed)
+        .expand(M_EXPAND, &mut okm)
+        .expect("KDF output is well under the HKDF limit");
+    let mut encoder_key = [0u8; obfs4_framing::KEY_LENGTH];
+    let mut decoder_key = [0u8; obfs4_framing::KEY_LENGTH];
+    encoder_key.copy_from_slice(&okm[..obfs4_framing::KEY_LENGTH]);
+    decoder_key.copy_from_slice(&okm[obfs4_framing::KEY_LENGTH..]);
+    (encoder_key, decoder_key)
+}
+
+/// Position of `mark` at or after `start`, if a full MAC follows it
+fn find_mark_mac(mark: &[u8], buf: &[u8], start: usize) -> Option<usize> {
+    let end = buf.len().min(MAX_HANDSHAKE_LENGTH);
+    if start > end || end - start < MARK_LENGTH + MAC_LENGTH {
+        return None;
+    }
+    let pos = start
+        + buf[start..end]
+            .windows(MARK_LENGTH)
+            .position(|window| window == mark)?;
+    (pos + MARK_LENGTH + MAC_LENGTH <= end).then_some(pos)
+}
+
+/// Hours since the Unix epoch, in decimal, as MACed into both handshakes
+fn epoch_hour() -> String {
+    let secs = system_time_now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    (secs / 3600).to_string()
+}
+
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// An obfs4 connection after the handshake: plaintext in, frames out
+///
+/// Writes are chopped into packets, padded as a burst and framed. Reads
+/// open frames and return the payload packets' data, dropping padding and
+/// the bridge's PRNG seed.
+pub struct Obfs4Conn<S> {
+    inner: S,
+    encoder: Encoder,
+    decoder: Decoder,
+    /// Received bytes not yet decoded into frames
+    rx_raw: Vec<u8>,
+    /// Decoded plaintext not yet returned, and how much of it has been read
+    rx_plain: Vec<u8>,
+    rx_pos: usize,
+    /// Frames not yet written, and how much of them has been written
+    tx: Vec<u8>,
+    tx_pos: usize,
+    eof: bool,
+}
+
+impl<S> Obfs4Conn<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn new(inner: S, encoder: Encoder, decoder: Decoder, rx_raw: Vec<u8>) -> Self {
+        Self {
+            inner,
+            encoder,
+            decoder,
+            rx_raw,
+            rx_plain: Vec::new(),
+            rx_pos: 0,
+            tx: Vec::new(),
+            tx_pos: 0,
+            eof: false,
+        }
+    }
+
+    /// Frame `data` as payload packets followed by burst padding
+    fn encode_burst(&mut self, data: &[u8]) -> Result<()> {
+        for chunk in data.chunks(MAX_PACKET_PAYLOAD_LENGTH) {
+            self.write_packet(PACKET_TYPE_PAYLOAD, chunk, 0)?;
+        }
+
+        // Pad the burst so its tail segment has a random length
+        let burst_length = self.tx.len() - self.tx_pos;
+        let pad_to = rand::thread_rng().gen_range(0..=MAX_SEGMENT_LENGTH);
+        let tail = burst_length % MAX_SEGMENT_LENGTH;
+        let pad_length = if pad_to >= tail {
+            pad_to - tail
+        } else {
+            MAX_SEGMENT_LENGTH - tail + pad_to
+        };
+        if pad_length > HEADER_LENGTH {
+            self.write_packet(PACKET_TYPE_PAYLOAD, &[], pad_length - HEADER_LENGTH)?;
+        } else if pad_length > 0 {
+            self.write_packet(PACKET_TYPE_PAYLOAD, &[], MAX_PACKET_PAYLOAD_LENGTH)?;
+            self.write_packet(PACKET_TYPE_PAYLOAD, &[], pad_length)?;
+        }
+        Ok(())
+    }
+
+    fn write_packet(&mut self, packet_type: u8, data: &[u8], pad_length: usize) -> Result<()> {
+        let packet = obfs4_framing::make_packet(packet_type, data, pad_length)?;
+        self.encoder.encode(&packet, &mut self.tx)
+    }
+
+    /// Open every buffered frame, returning whether any data came out
+    fn decode_buffered(&mut self) -> Result<bool> {
+        let mut decoded = false;
+        while let Some(frame) = self.decoder.decode(&mut self.rx_raw)? {
+            match obfs4_framing::parse_packet(&frame)? {
+                (PACKET_TYPE_PAYLOAD, payload) => {
+                    self.rx_plain.extend_from_slice(payload);
+                    decoded |= !payload.is_empty();
+                }
+                (PACKET_TYPE_PRNG_SEED, _) => trace!("Ignoring obfs4 PRNG seed packet"),
+                (packet_type, _) => trace!("Ignoring obfs4 packet of type {}", packet_type),
+            }
+        }
+        Ok(decoded)
+    }
+
+    /// Write out queued frames
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.tx_pos < self.tx.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.tx[self.tx_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.tx_pos += n;
+        }
+        self.tx.clear();
+        self.tx_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncRead for Obfs4Conn<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        loop {
+            if this.rx_pos < this.rx_plain.len() {
+                let n = buf.len().min(this.rx_plain.len() - this.rx_pos);
+                buf[..n].copy_from_slice(&this.rx_plain[this.rx_pos..this.rx_pos + n]);
+                this.rx_pos += n;
+                if this.rx_pos == this.rx_plain.len() {
+                    this.rx_plain.clear();
+                    this.rx_pos = 0;
+                }
+                return Poll::Ready(Ok(n));
+            }
+            if this.decode_buffered().map_err(io::Error::other)? {
+                continue;
+            }
+            if this.eof {
+                return Poll::Ready(Ok(0));
+            }
+
+            let mut chunk = [0u8; 8192];
+            let n = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if n == 0 {
+                this.eof = true;
+            } else {
+                this.rx_raw.extend_from_slice(&chunk[..n]);
+            }
+        }
+    }
+}
+
+impl<S> AsyncWrite for Obfs4Conn<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(this.poll_drain(cx))?;
+
+        let n = buf.len().min(MAX_BURST_LENGTH);
+        this.encode_burst(&buf[..n]).map_err(io::Error::other)?;
+        // The frames are queued, so the data is accepted even if the socket
+        // is busy; the next write or flush finishes sending them
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+/// Tor link TLS over an obfs4 connection over TCP
+type TorLinkTlsStream = futures_rustls::client::TlsStream<Obfs4Conn<Compat<tokio::net::TcpStream>>>;
+
+/// obfs4 stream for Tor communication
+///
+/// The architecture is:
+/// - TCP: Client ↔ obfs4 bridge
+/// - obfs4: handshake and frames that look like random bytes
+/// - TLS: Client ↔ Tor relay (uses TorCertVerifier, validated via CERTS cells)
+pub struct Obfs4Stream {
+    inner: TorLinkTlsStream,
+}
+
+impl Obfs4Stream {
+    /// Close the obfs4 stream
+    pub async fn close(&mut self) -> io::Result<()> {
+        use futures::AsyncWriteExt;
+        info!("Closing obfs4 stream");
+        self.inner.close().await
+    }
+}
+
+impl tor_rtcompat::StreamOps for Obfs4Stream {
+    // Default implementation
+}
+
+impl tor_rtcompat::CertifiedConn for Obfs4Stream {
+    fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
+        let (_, session) = self.inner.get_ref();
+        Ok(session
+            .peer_certificates()
+            .and_then(|certs| certs.first().map(|c| Vec::from(c.as_ref()))))
+    }
+
+    fn export_keying_material(
+        &self,
+        len: usize,
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> io::Result<Vec<u8>> {
+        let (_, session) = self.inner.get_ref();
+        session
+            .export_keying_material(Vec::with_capacity(len), label, context)
+            .map_err(io::Error::other)
+    }
+}
+
+impl AsyncRead for Obfs4Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Obfs4Stream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for Obfs4Bridge {
+    async fn connect(&self) -> Result<Box<dyn LinkStream>> {
+        Ok(Box::new(Obfs4Bridge::connect(self).await?))
+    }
+}
+
+/// Create an obfs4 stream (convenience function)
+pub async fn create_obfs4_stream(config: Obfs4Config) -> Result<Obfs4Stream> {
+    let bridge = Obfs4Bridge::new(config);
+    bridge.connect().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    /// A bridge line cert from Tor Browser's built-in obfs4 bridges
+    const TOR_BROWSER_CERT: &str =
+        "ssH+9rP8dG2NLDN2XuFw63hIO/9MNNinLmxQDpVa+7kTOa9/m+tGWT1SmSYpQ9uTBGa6Hw";
+
+    /// Server half of the handshake, enough to test the client against
+    struct TestBridge {
+        identity: Keypair,
+        cert: Obfs4Cert,
+    }
+
+    impl TestBridge {
+        fn new() -> Self {
+            let identity = Keypair::generate();
+            let cert = Obfs4Cert::new(rand::random(), *identity.public_key());
+            Self { identity, cert }
+        }
+
+        /// Answer a complete client request with the reply and the bridge's frame keys
+        fn respond(&self, request: &[u8]) -> (Vec<u8>, Encoder, Decoder) {
+            let mac_key = self.cert.mac_key();
+            let mut client_representative = [0u8; REPRESENTATIVE_LENGTH];
+            client_representative.copy_from_slice(&request[..REPRESENTATIVE_LENGTH]);
+            let mark = hmac_sha256(&mac_key, &[&client_representative]);
+            let pos = find_mark_mac(&mark[..MARK_LENGTH], request, REPRESENTATIVE_LENGTH).unwrap();
+            let epoch_hour = epoch_hour();
+            let mac = hmac_sha256(
+                &mac_key,
+                &[&request[..pos + MARK_LENGTH], epoch_hour.as_bytes()],
+            );
+            assert_eq!(
+                &mac[..MAC_LENGTH],
+                &request[pos + MARK_LENGTH..pos + MARK_LENGTH + MAC_LENGTH]
+            );
+
+            let client_public = elligator2::representative_to_public_key(&client_representative);
+            let ephemeral = Keypair::generate();
+            let (key_seed, auth) = ntor(
+                &ephemeral.diffie_hellman(&client_public),
+                &self.identity.diffie_hellman(&client_public),
+                &self.cert,
+                &client_public,
+                ephemeral.public_key(),
+            )
+            .unwrap();
+
+            let mut response = ephemeral.representative().to_vec();
+            response.extend_from_slice(&auth);
+            response.extend_from_slice(&vec![0u8; rand::thread_rng().gen_range(0..200)]);
+            let mark = hmac_sha256(&mac_key, &[ephemeral.representative()]);
+            response.extend_from_slice(&mark[..MARK_LENGTH]);
+            let mac = hmac_sha256(&mac_key, &[&response, epoch_hour.as_bytes()]);
+            response.extend_from_slice(&mac[..MAC_LENGTH]);
+
+            // The bridge's keys are the client's, swapped
+            let (client_encoder, client_decoder) = derive_frame_keys(&key_seed);
+            let mut encoder = Encoder::new(&client_decoder);
+            let seed: [u8; obfs4_framing::SEED_LENGTH] = rand::random();
+            let packet = obfs4_framing::make_packet(PACKET_TYPE_PRNG_SEED, &seed, 0).unwrap();
+            encoder.encode(&packet, &mut response).unwrap();
+            (response, encoder, Decoder::new(&client_encoder))
+        }
+    }
+
+    #[test]
+    fn test_cert_parsing() {
+        let cert: Obfs4Cert = TOR_BROWSER_CERT.parse().unwrap();
+        assert_eq!(cert.to_string(), TOR_BROWSER_CERT);
+        // Padded certs are accepted too
+        assert_eq!(
+            format!("{}==", TOR_BROWSER_CERT)
+                .parse::<Obfs4Cert>()
+                .unwrap(),
+            cert
+        );
+
+        assert!("not base64!".parse::<Obfs4Cert>().is_err());
+        assert!("AAAA".parse::<Obfs4Cert>().is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let config = Obfs4Config::new(
+            "192.0.2.1:443".to_string(),
+            "AAAA".repeat(10),
+            TOR_BROWSER_CERT.to_string(),
+        )
+        .with_iat_mode(1)
+        .with_timeout(Duration::from_secs(60));
+        assert_eq!(config.iat_mode, 1);
+        assert_eq!(config.connection_timeout, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_request_layout() {
+        let bridge = TestBridge::new();
+        let client = ClientHandshake::new(bridge.cert.clone());
+        let request = client.request();
+        assert!(request.len() >= CLIENT_MIN_HANDSHAKE_LENGTH + CLIENT_MIN_PAD_LENGTH);
+        assert!(request.len() <= MAX_HANDSHAKE_LENGTH);
+        assert_eq!(&request[..32], client.keypair.representative());
+    }
+
+    #[test]
+    fn test_handshake_agrees_with_bridge() {
+        let bridge = TestBridge::new();
+        let client = ClientHandshake::new(bridge.cert.clone());
+        let (response, mut bridge_encoder, mut bridge_decoder) = bridge.respond(&client.request());
+
+        // Nothing is accepted before the mark and MAC arrive
+        assert!(client.parse_response(&response[..40]).unwrap().is_none());
+
+        let (consumed, key_seed) = client.parse_response(&response).unwrap().unwrap();
+        assert_eq!(response.len() - consumed, INLINE_SEED_FRAME_LENGTH);
+
+        let (encoder_key, decoder_key) = derive_frame_keys(&key_seed);
+        let mut wire = Vec::new();
+        Encoder::new(&encoder_key)
+            .encode(b"ping", &mut wire)
+            .unwrap();
+        assert_eq!(bridge_decoder.decode(&mut wire).unwrap().unwrap(), b"ping");
+
+        let mut wire = response[consumed..].to_vec();
+        bridge_encoder.encode(b"pong", &mut wire).unwrap();
+        let mut decoder = Decoder::new(&decoder_key);
+        decoder.decode(&mut wire).unwrap().unwrap();
+        assert_eq!(decoder.decode(&mut wire).unwrap().unwrap(), b"pong");
+    }
+
+    #[test]
+    fn test_handshake_known_answer() {
+        // Computed by an independent implementation of the obfs4 spec's
+        // handshake, its Elligator2 map checked against curve25519-dalek's
+        let hex32 =
+            |hex_str: &str| -> [u8; 32] { hex::decode(hex_str).unwrap().try_into().unwrap() };
+        let identity = hex32("132c442be010fbd57e72603328aa76e71fccc1503aae219327d14d9c9993f472");
+        let node_id: [u8; NODE_ID_LENGTH] = std::array::from_fn(|i| i as u8);
+        let keypair = Keypair::from_secret([4; 32], 0x80).unwrap();
+        assert_eq!(
+            hex::encode(keypair.public_key()),
+            "ac01b2209e86354fb853237b5de0f4fab13c7fcbf433a61c019369617fecf10b"
+        );
+        assert_eq!(
+            hex::encode(keypair.representative()),
+            "03288744814acfd185ea44d0b483049ee41db4962747d2d62cc51fc23a54679c"
+        );
+        let client = ClientHandshake {
+            cert: Obfs4Cert::new(node_id, identity),
+            keypair,
+            epoch_hour: "494000".to_string(),
+        };
+        let request = client.request();
+        assert_eq!(
+            hex::encode(&request[request.len() - MARK_LENGTH - MAC_LENGTH..][..MARK_LENGTH]),
+            "a466db810b03c3846b4e63c4f0669c04"
+        );
+
+        // `Y' | AUTH | P_S | M_S | MAC_S`, then a seed frame and "hello"
+        // with 3 bytes of padding
+        let response = hex::decode(
+            "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f\
+             5375b36b447e345cdb2fba7901043e7ae7352ea2f8b9d8d8e4fdaa02e26de78f\
+             5a5a5a5a5a5a5ad5b2cdfc398695f700bb3d91d3e051e9e0003c36e85ad7a8b9\
+             c8f141d99f8ec513a96da4e35ee9d9bcdbc8dbc42b9032f39d8ed3a5db3e57a1\
+             fea4423d48261ddd58291f05f3093767f9e4135e6e824edd2d76119640ce029b\
+             7d15bf996145f6f2b0f32dba1e9324acbd",
+        )
+        .unwrap();
+        let (consumed, key_seed) = client.parse_response(&response).unwrap().unwrap();
+        assert_eq!(consumed, 103);
+        assert_eq!(
+            hex::encode(key_seed),
+            "495de116b098180142245ee5145e0e7de99c74d01b6619f6616e6e1c78116306"
+        );
+
+        let (encoder_key, decoder_key) = derive_frame_keys(&key_seed);
+        assert_eq!(
+            hex::encode(encoder_key),
+            "a04ed0959848ef30824ac2663a1fea5df33f9e7a85e4c3111935aff952983233\
+             6dc0f97a0d9a85bfc49e54d9ee62e6c5f5f4337cb35d7e3ec8ef9c177d0e2b50\
+             40d27947f4a12640"
+        );
+        assert_eq!(
+            hex::encode(decoder_key),
+            "86ae15d56c9d5b029c471a5a36f4ea3deed1478db84f961fb0ab7f31189c3259\
+             45dd5be7a614c503bbb7694008445cfe6ee4954b3a9e8a8b8a5523b8b1cdd764\
+             81f7ea6accd94db9"
+        );
+
+        let mut decoder = Decoder::new(&decoder_key);
+        let mut wire = response[consumed..].to_vec();
+        let seed: Vec<u8> = (0..obfs4_framing::SEED_LENGTH as u8).collect();
+        let frame = decoder.decode(&mut wire).unwrap().unwrap();
+        assert_eq!(
+            obfs4_framing::parse_packet(&frame).unwrap(),
+            (PACKET_TYPE_PRNG_SEED, &seed[..])
+        );
+        let frame = decoder.decode(&mut wire).unwrap().unwrap();
+        assert_eq!(
+            obfs4_framing::parse_packet(&frame).unwrap(),
+            (PACKET_TYPE_PAYLOAD, &b"hello"[..])
+        );
+        assert!(wire.is_empty());
+    }
+
+    #[test]
+    fn test_wrong_cert_fails() {
+        let bridge = TestBridge::new();
+        let client = ClientHandshake::new(bridge.cert.clone());
+        let (mut response, _, _) = bridge.respond(&client.request());
+
+        // A corrupted AUTH means the bridge doesn't hold the cert's key
+        response[REPRESENTATIVE_LENGTH] ^= 1;
+        assert!(client.parse_response(&response).is_err());
+
+        // A bridge with a different identity key never produces our mark,
+        // so the client keeps waiting until the handshake limit
+        let impostor = TestBridge::new();
+        let (mut response, _, _) =
+            impostor.respond(&ClientHandshake::new(impostor.cert.clone()).request());
+        assert!(client.parse_response(&response).unwrap().is_none());
+        response.resize(MAX_HANDSHAKE_LENGTH, 0);
+        assert!(client.parse_response(&response).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connection_round_trip() {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+
+        let bridge = TestBridge::new();
+        let cert = bridge.cert.clone();
+        let (client_io, bridge_io) = tokio::io::duplex(1 << 16);
+
+        let server = tokio::spawn(async move {
+            let mut io = bridge_io.compat();
+            let mut request = Vec::new();
+            let mut chunk = [0u8; 4096];
+            loop {
+                let n = io.read(&mut chunk).await.unwrap();
+                request.extend_from_slice(&chunk[..n]);
+                let client_mark = hmac_sha256(&bridge.cert.mac_key(), &[&request[..32]]);
+                if find_mark_mac(&client_mark[..MARK_LENGTH], &request, 32).is_some() {
+                    break;
+                }
+            }
+            let (response, encoder, decoder) = bridge.respond(&request);
+            io.write_all(&response).await.unwrap();
+
+            // Echo everything back through the bridge's own keys
+            let mut conn = Obfs4Conn::new(io, encoder, decoder, Vec::new());
+            let mut data = vec![0u8; 5000];
+            conn.read_exact(&mut data).await.unwrap();
+            conn.write_all(&data).await.unwrap();
+            conn.flush().await.unwrap();
+        });
+
+        let mut conn = handshake(client_io.compat(), &cert).await.unwrap();
+        let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        conn.write_all(&data).await.unwrap();
+        conn.flush().await.unwrap();
+
+        let mut echoed = vec![0u8; data.len()];
+        conn.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(echoed, data);
+        server.await.unwrap();
+    }
+}
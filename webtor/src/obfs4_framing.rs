@@ -0,0 +1,410 @@
+//! obfs4 frame and packet encoding
+//!
+//! After the handshake, obfs4 carries data in frames:
+//!
+//! ```text
+//! +------------+----------+--------------------+
+//! | length (2) | tag (16) | ciphertext (0-1430) |
+//! +------------+----------+--------------------+
+//! ```
+//!
+//! - Each frame is a NaCl secretbox. Its nonce is a 16-byte prefix plus a
+//!   64-bit big-endian counter.
+//! - The length field is XORed with a mask from a SipHash-2-4 DRBG, so frame
+//!   boundaries look random.
+//! - Each frame holds exactly one packet: a type byte, a big-endian payload
+//!   length, the payload, then zero padding.
+//!
+//! Each direction has its own 72-byte key, taken from the handshake KDF:
+//! secretbox key (32), nonce prefix (16), DRBG seed (24).
+
+use crate::error::{Result, TorError};
+use crypto_secretbox::aead::{AeadInPlace, KeyInit};
+use crypto_secretbox::{Nonce, Tag, XSalsa20Poly1305};
+use rand::Rng;
+use siphasher::sip::SipHasher24;
+use std::hash::Hasher;
+
+/// Largest frame on the wire, chosen to fit a TCP segment
+pub const MAX_SEGMENT_LENGTH: usize = 1448;
+
+/// Length field plus secretbox tag
+pub const FRAME_OVERHEAD: usize = LENGTH_LENGTH + TAG_LENGTH;
+
+/// Largest plaintext one frame can carry
+pub const MAX_FRAME_PAYLOAD_LENGTH: usize = MAX_SEGMENT_LENGTH - FRAME_OVERHEAD;
+
+/// Key material for one direction
+pub const KEY_LENGTH: usize = 32 + NONCE_PREFIX_LENGTH + SEED_LENGTH;
+
+/// Packet type and payload length
+pub const PACKET_OVERHEAD: usize = 3;
+
+/// Largest payload plus padding one packet can carry
+pub const MAX_PACKET_PAYLOAD_LENGTH: usize = MAX_FRAME_PAYLOAD_LENGTH - PACKET_OVERHEAD;
+
+/// Packet carrying application data (or only padding)
+pub const PACKET_TYPE_PAYLOAD: u8 = 0;
+
+/// Packet carrying the peer's seed for its length distribution
+pub const PACKET_TYPE_PRNG_SEED: u8 = 1;
+
+/// Length of a DRBG seed, and of a PRNG seed packet's payload
+pub const SEED_LENGTH: usize = 16 + 8;
+
+const LENGTH_LENGTH: usize = 2;
+const TAG_LENGTH: usize = 16;
+const NONCE_PREFIX_LENGTH: usize = 16;
+const MIN_FRAME_LENGTH: usize = FRAME_OVERHEAD - LENGTH_LENGTH;
+const MAX_FRAME_LENGTH: usize = MAX_SEGMENT_LENGTH - LENGTH_LENGTH;
+
+/// Hash-based DRBG producing the length masks
+///
+/// SipHash-2-4 keyed with the seed's first 16 bytes runs in output feedback
+/// mode. Each block is written into a hasher that is never reset, and the
+/// hash so far is the next block.
+pub struct HashDrbg {
+    sip: SipHasher24,
+    ofb: [u8; 8],
+}
+
+impl HashDrbg {
+    pub fn new(seed: &[u8; SEED_LENGTH]) -> Self {
+        let mut key = [0u8; 16];
+        key.copy_from_slice(&seed[..16]);
+        let mut ofb = [0u8; 8];
+        ofb.copy_from_slice(&seed[16..]);
+        Self {
+            sip: SipHasher24::new_with_key(&key),
+            ofb,
+        }
+    }
+
+    pub fn next_block(&mut self) -> [u8; 8] {
+        self.sip.write(&self.ofb);
+        self.ofb = self.sip.finish().to_le_bytes();
+        self.ofb
+    }
+
+    fn next_length_mask(&mut self) -> u16 {
+        let block = self.next_block();
+        u16::from_be_bytes([block[0], block[1]])
+    }
+}
+
+/// Secretbox key, nonce counter and length DRBG for one direction
+struct FrameKeys {
+    cipher: XSalsa20Poly1305,
+    nonce_prefix: [u8; NONCE_PREFIX_LENGTH],
+    counter: u64,
+    drbg: HashDrbg,
+}
+
+impl FrameKeys {
+    fn new(key: &[u8; KEY_LENGTH]) -> Self {
+        let cipher =
+            XSalsa20Poly1305::new_from_slice(&key[..32]).expect("secretbox key is 32 bytes");
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LENGTH];
+        nonce_prefix.copy_from_slice(&key[32..48]);
+        let mut seed = [0u8; SEED_LENGTH];
+        seed.copy_from_slice(&key[48..]);
+        Self {
+            cipher,
+            nonce_prefix,
+            counter: 1,
+            drbg: HashDrbg::new(&seed),
+        }
+    }
+
+    /// The nonce for the next frame
+    fn nonce(&self) -> Result<Nonce> {
+        if self.counter == 0 {
+            return Err(TorError::Protocol(
+                "obfs4 frame nonce counter wrapped".to_string(),
+            ));
+        }
+        let mut nonce = Nonce::default();
+        nonce[..NONCE_PREFIX_LENGTH].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LENGTH..].copy_from_slice(&self.counter.to_be_bytes());
+        Ok(nonce)
+    }
+}
+
+/// Seals plaintext into frames
+pub struct Encoder {
+    keys: FrameKeys,
+}
+
+impl Encoder {
+    pub fn new(key: &[u8; KEY_LENGTH]) -> Self {
+        Self {
+            keys: FrameKeys::new(key),
+        }
+    }
+
+    /// Append one frame carrying `payload` to `out`
+    pub fn encode(&mut self, payload: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        if payload.len() > MAX_FRAME_PAYLOAD_LENGTH {
+            return Err(TorError::Protocol(format!(
+                "obfs4 frame payload too long: {} bytes",
+                payload.len()
+            )));
+        }
+        let nonce = self.keys.nonce()?;
+        self.keys.counter = self.keys.counter.wrapping_add(1);
+
+        let mut ciphertext = payload.to_vec();
+        let tag = self
+            .keys
+            .cipher
+            .encrypt_in_place_detached(&nonce, b"", &mut ciphertext)
+            .map_err(|_| TorError::Protocol("obfs4 frame encryption failed".to_string()))?;
+
+        let length = (TAG_LENGTH + ciphertext.len()) as u16 ^ self.keys.drbg.next_length_mask();
+        out.extend_from_slice(&length.to_be_bytes());
+        out.extend_from_slice(&tag);
+        out.extend_from_slice(&ciphertext);
+        Ok(())
+    }
+}
+
+/// Opens frames from a receive buffer
+pub struct Decoder {
+    keys: FrameKeys,
+    /// Length of the frame being received, once its length field is read
+    next_length: Option<usize>,
+    /// The length field was out of range and `next_length` is a decoy
+    next_length_invalid: bool,
+}
+
+impl Decoder {
+    pub fn new(key: &[u8; KEY_LENGTH]) -> Self {
+        Self {
+            keys: FrameKeys::new(key),
+            next_length: None,
+            next_length_invalid: false,
+        }
+    }
+
+    /// Take one frame off the front of `buf` and open it
+    ///
+    /// Returns `Ok(None)` until a whole frame is buffered. An out-of-range
+    /// length doesn't fail straight away: like obfs4proxy, the decoder
+    /// reads a random number of bytes first, so the failure point doesn't
+    /// reveal the length check to an attacker probing with modified frames.
+    pub fn decode(&mut self, buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let length = match self.next_length {
+            Some(length) => length,
+            None => {
+                if buf.len() < LENGTH_LENGTH {
+                    return Ok(None);
+                }
+                let masked = u16::from_be_bytes([buf[0], buf[1]]);
+                buf.drain(..LENGTH_LENGTH);
+                let mut length = (masked ^ self.keys.drbg.next_length_mask()) as usize;
+                if !(MIN_FRAME_LENGTH..=MAX_FRAME_LENGTH).contains(&length) {
+                    self.next_length_invalid = true;
+                    length = rand::thread_rng().gen_range(MIN_FRAME_LENGTH..=MAX_FRAME_LENGTH);
+                }
+                self.next_length = Some(length);
+                length
+            }
+        };
+        if buf.len() < length {
+            return Ok(None);
+        }
+
+        let nonce = self.keys.nonce()?;
+        let mut frame: Vec<u8> = buf.drain(..length).collect();
+        let mut ciphertext = frame.split_off(TAG_LENGTH);
+        let tag = Tag::from_slice(&frame);
+        let opened = self
+            .keys
+            .cipher
+            .decrypt_in_place_detached(&nonce, b"", &mut ciphertext, tag);
+        if opened.is_err() || self.next_length_invalid {
+            return Err(TorError::Protocol(
+                "obfs4 frame failed authentication".to_string(),
+            ));
+        }
+        self.keys.counter = self.keys.counter.wrapping_add(1);
+        self.next_length = None;
+        Ok(Some(ciphertext))
+    }
+}
+
+/// Build a packet of `packet_type` with `data` and `pad_length` zero bytes
+pub fn make_packet(packet_type: u8, data: &[u8], pad_length: usize) -> Result<Vec<u8>> {
+    if data.len() + pad_length > MAX_PACKET_PAYLOAD_LENGTH {
+        return Err(TorError::Protocol(format!(
+            "obfs4 packet too long: {} bytes of data, {} of padding",
+            data.len(),
+            pad_length
+        )));
+    }
+    let mut packet = Vec::with_capacity(PACKET_OVERHEAD + data.len() + pad_length);
+    packet.push(packet_type);
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet.resize(PACKET_OVERHEAD + data.len() + pad_length, 0);
+    Ok(packet)
+}
+
+/// Split an opened frame into its packet type and payload, dropping padding
+pub fn parse_packet(frame: &[u8]) -> Result<(u8, &[u8])> {
+    if frame.len() < PACKET_OVERHEAD {
+        return Err(TorError::Protocol(format!(
+            "obfs4 packet too short: {} bytes",
+            frame.len()
+        )));
+    }
+    let length = u16::from_be_bytes([frame[1], frame[2]]) as usize;
+    let payload = frame
+        .get(PACKET_OVERHEAD..PACKET_OVERHEAD + length)
+        .ok_or_else(|| {
+            TorError::Protocol(format!(
+                "obfs4 packet claims {} bytes but holds {}",
+                length,
+                frame.len() - PACKET_OVERHEAD
+            ))
+        })?;
+    Ok((frame[0], payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> [u8; KEY_LENGTH] {
+        let mut key = [0u8; KEY_LENGTH];
+        for (i, b) in key.iter_mut().enumerate() {
+            *b = byte.wrapping_add(i as u8);
+        }
+        key
+    }
+
+    #[test]
+    fn test_frames_round_trip() {
+        let mut encoder = Encoder::new(&key(1));
+        let mut decoder = Decoder::new(&key(1));
+
+        let mut wire = Vec::new();
+        let payloads: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"hello".to_vec(),
+            vec![0xab; MAX_FRAME_PAYLOAD_LENGTH],
+        ];
+        for payload in &payloads {
+            encoder.encode(payload, &mut wire).unwrap();
+        }
+        assert_eq!(
+            wire.len(),
+            payloads
+                .iter()
+                .map(|p| p.len() + FRAME_OVERHEAD)
+                .sum::<usize>()
+        );
+
+        // Feed the wire bytes one at a time to exercise partial frames
+        let mut buf = Vec::new();
+        let mut decoded = Vec::new();
+        for byte in wire {
+            buf.push(byte);
+            while let Some(frame) = decoder.decode(&mut buf).unwrap() {
+                decoded.push(frame);
+            }
+        }
+        assert_eq!(decoded, payloads);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_oversized_payload_rejected() {
+        let mut encoder = Encoder::new(&key(1));
+        let mut out = Vec::new();
+        assert!(encoder
+            .encode(&[0; MAX_FRAME_PAYLOAD_LENGTH + 1], &mut out)
+            .is_err());
+    }
+
+    #[test]
+    fn test_tampered_frame_rejected() {
+        let mut encoder = Encoder::new(&key(1));
+        let mut decoder = Decoder::new(&key(1));
+        let mut wire = Vec::new();
+        encoder.encode(b"payload", &mut wire).unwrap();
+        let last = wire.len() - 1;
+        wire[last] ^= 1;
+        assert!(decoder.decode(&mut wire).is_err());
+
+        // A different key can't open frames either
+        let mut wire = Vec::new();
+        Encoder::new(&key(2)).encode(b"payload", &mut wire).unwrap();
+        let mut decoder = Decoder::new(&key(1));
+        let result = loop {
+            match decoder.decode(&mut wire) {
+                Ok(None) => wire.extend_from_slice(&[0; 64]),
+                other => break other,
+            }
+        };
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_length_is_masked() {
+        let mut encoder = Encoder::new(&key(3));
+        let mut wire = Vec::new();
+        encoder.encode(b"", &mut wire).unwrap();
+        let mask = HashDrbg::new(&key(3)[48..].try_into().unwrap()).next_length_mask();
+        let length = u16::from_be_bytes([wire[0], wire[1]]) ^ mask;
+        assert_eq!(length as usize, TAG_LENGTH);
+    }
+
+    // The known answers below come from an independent implementation of
+    // obfs4proxy's framing, itself checked against NaCl's secretbox vector
+    // and the SipHash paper's
+
+    #[test]
+    fn test_drbg_known_answer() {
+        let seed: [u8; SEED_LENGTH] = std::array::from_fn(|i| i as u8);
+        let mut drbg = HashDrbg::new(&seed);
+        let blocks: Vec<String> = (0..3).map(|_| hex::encode(drbg.next_block())).collect();
+        assert_eq!(
+            blocks,
+            ["28b572b88c1ab776", "a8de197d8e7de4b3", "4d3a58549e36707c"]
+        );
+    }
+
+    #[test]
+    fn test_frames_known_answer() {
+        let frames = [
+            (
+                &b"GET / HTTP/1.1"[..],
+                "db95b3114bca5d27af241087ee993666400a5e435e56ed23c0b2b9019bdc4dff",
+            ),
+            (&b""[..], "1ac03c838170ff42c610315548066976c10c"),
+        ];
+        let mut encoder = Encoder::new(&key(1));
+        let mut decoder = Decoder::new(&key(1));
+        for (payload, sealed) in frames {
+            let mut wire = Vec::new();
+            encoder.encode(payload, &mut wire).unwrap();
+            assert_eq!(hex::encode(&wire), sealed);
+            assert_eq!(decoder.decode(&mut wire).unwrap().unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn test_packets() {
+        let packet = make_packet(PACKET_TYPE_PAYLOAD, b"data", 10).unwrap();
+        assert_eq!(packet.len(), PACKET_OVERHEAD + 4 + 10);
+        assert_eq!(
+            parse_packet(&packet).unwrap(),
+            (PACKET_TYPE_PAYLOAD, &b"data"[..])
+        );
+
+        assert!(make_packet(PACKET_TYPE_PAYLOAD, &[0; MAX_PACKET_PAYLOAD_LENGTH], 1).is_err());
+        assert!(parse_packet(&[0, 0]).is_err());
+        assert!(parse_packet(&[0, 0, 5, 1]).is_err());
+    }
+}
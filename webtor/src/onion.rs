@@ -0,0 +1,234 @@
+//! `.onion` destinations
+//!
+//! An onion service isn't reached through an exit: the client fetches its
+//! descriptor and meets it at a rendezvous point. A `.onion` host must never
+//! go to an exit in a BEGIN cell, where it would be refused after the name
+//! had left the client for nothing. Such hosts are checked here and kept off
+//! the exit path. Streams, HTTP requests and name resolution through
+//! [`TorClient`] reach them through the [`OnionConnector`]; paths without
+//! one (a circuit pinned by the caller) fail with
+//! [`TorError::OnionServicesUnsupported`].
+//!
+//! [`OnionAddress`] is a validated v3 address (rend-spec-v3 §6): 56
+//! base32 characters encoding the service's ed25519 key, a two-byte
+//! checksum and the version byte.
+//!
+//! [`TorClient`]: crate::client::TorClient
+//! [`OnionConnector`]: crate::onion_connector::OnionConnector
+
+use crate::error::{Result, TorError};
+use safelog::DisplayRedacted;
+use std::fmt;
+use std::str::FromStr;
+use tor_hscrypto::pk::{HsId, HsIdParseError};
+use tor_llcrypto::pk::ed25519;
+
+const ONION_SUFFIX: &str = ".onion";
+
+/// The only address version in use
+pub const ONION_VERSION: u8 = 3;
+
+/// Characters in the base32 part of a v3 address
+const ENCODED_LEN: usize = 56;
+
+/// A v3 onion service address
+///
+/// Parsing and formatting are tor-hscrypto's [`HsId`]'s, which this adds
+/// to: parsing also accepts a trailing dot and subdomains
+/// (`www.<id>.onion`), which the same service answers, and checks that the
+/// key is an ed25519 point; it formats back as the lowercase `<id>.onion`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OnionAddress {
+    hsid: HsId,
+}
+
+impl OnionAddress {
+    /// The address of the service whose identity key is `public_key`
+    pub fn from_public_key(public_key: &ed25519::PublicKey) -> Self {
+        Self {
+            hsid: HsId::from(public_key.to_bytes()),
+        }
+    }
+
+    /// The service's ed25519 identity key
+    pub fn public_key(&self) -> ed25519::PublicKey {
+        // Checked to be a valid point when parsed
+        ed25519::PublicKey::from_bytes(self.public_key_bytes())
+            .expect("validated onion service key")
+    }
+
+    /// The raw identity key
+    pub fn public_key_bytes(&self) -> &[u8; 32] {
+        self.hsid.as_ref()
+    }
+
+    pub fn version(&self) -> u8 {
+        ONION_VERSION
+    }
+
+    pub fn hsid(&self) -> HsId {
+        self.hsid
+    }
+}
+
+impl FromStr for OnionAddress {
+    type Err = TorError;
+
+    fn from_str(host: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            TorError::invalid_hostname(format!(
+                "{} is not a valid v3 onion address: {}",
+                host, reason
+            ))
+        };
+        let name = host.trim_end_matches('.').to_ascii_lowercase();
+        let name = name
+            .strip_suffix(ONION_SUFFIX)
+            .ok_or_else(|| invalid("no .onion suffix"))?;
+        let encoded = name.rsplit('.').next().unwrap_or_default();
+        if encoded.len() != ENCODED_LEN {
+            return Err(invalid(&format!(
+                "expected {} characters, found {}",
+                ENCODED_LEN,
+                encoded.len()
+            )));
+        }
+        let hsid = format!("{}{}", encoded, ONION_SUFFIX)
+            .parse::<HsId>()
+            .map_err(|e| match e {
+                HsIdParseError::InvalidBase32(_) => invalid("not base32"),
+                HsIdParseError::UnsupportedVersion(version) => {
+                    invalid(&format!("unsupported version {}", version))
+                }
+                HsIdParseError::WrongChecksum => invalid("bad checksum"),
+                other => invalid(&other.to_string()),
+            })?;
+        if ed25519::PublicKey::from_bytes(hsid.as_ref()).is_err() {
+            return Err(invalid("not an ed25519 key"));
+        }
+        Ok(Self { hsid })
+    }
+}
+
+impl fmt::Display for OnionAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Addresses are shown in full; redaction is up to the logger
+        self.hsid.display_unredacted().fmt(f)
+    }
+}
+
+impl TryFrom<&[u8]> for OnionAddress {
+    type Error = TorError;
+
+    /// The address of the service whose 32-byte identity key is `bytes`
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        <[u8; 32]>::try_from(bytes)
+            .ok()
+            .and_then(|bytes| ed25519::PublicKey::from_bytes(&bytes).ok())
+            .map(|key| Self::from_public_key(&key))
+            .ok_or_else(|| {
+                TorError::configuration("Onion service keys are 32-byte ed25519 public keys")
+            })
+    }
+}
+
+impl From<HsId> for OnionAddress {
+    fn from(hsid: HsId) -> Self {
+        Self { hsid }
+    }
+}
+
+/// Whether `host` is a name under `.onion`
+pub fn is_onion_host(host: &str) -> bool {
+    host.trim_end_matches('.')
+        .to_ascii_lowercase()
+        .ends_with(ONION_SUFFIX)
+}
+
+/// The service a `.onion` host names; subdomains (`www.<id>.onion`) are
+/// served by the same service
+pub(crate) fn service_id(host: &str) -> Result<HsId> {
+    Ok(host.parse::<OnionAddress>()?.hsid())
+}
+
+/// Fail unless `host` may be sent to an exit, for callers with no way to
+/// an onion service
+///
+/// `.onion` hosts are validated so a mistyped address is reported as such,
+/// then refused.
+pub(crate) fn check_exit_host(host: &str) -> Result<()> {
+    if !is_onion_host(host) {
+        return Ok(());
+    }
+    service_id(host)?;
+    Err(TorError::OnionServicesUnsupported(host.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUCKDUCKGO: &str = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+
+    #[test]
+    fn test_onion_hosts() {
+        assert!(is_onion_host(DUCKDUCKGO));
+        assert!(is_onion_host("www.Example.ONION."));
+        assert!(!is_onion_host("onion"));
+        assert!(!is_onion_host("example.onion.net"));
+        check_exit_host("example.com").unwrap();
+
+        let id = service_id(DUCKDUCKGO).unwrap();
+        assert_eq!(service_id(&format!("www.{}", DUCKDUCKGO)).unwrap(), id);
+        assert!(matches!(
+            check_exit_host(DUCKDUCKGO),
+            Err(TorError::OnionServicesUnsupported(_))
+        ));
+
+        // A mistyped address fails its checksum
+        let typo = DUCKDUCKGO.replacen("duck", "dack", 1);
+        assert!(matches!(
+            check_exit_host(&typo),
+            Err(TorError::InvalidHostname(_))
+        ));
+        assert!(service_id("facebookcorewwwi.onion").is_err());
+    }
+
+    #[test]
+    fn test_onion_address_round_trip() {
+        let address: OnionAddress = DUCKDUCKGO.to_uppercase().parse().unwrap();
+        assert_eq!(address.to_string(), DUCKDUCKGO);
+        assert_eq!(address.version(), ONION_VERSION);
+        assert_eq!(address.hsid(), DUCKDUCKGO.parse::<HsId>().unwrap());
+        assert_eq!(OnionAddress::from(address.hsid()), address);
+        assert_eq!(
+            OnionAddress::from_public_key(&address.public_key()),
+            address
+        );
+        assert_eq!(
+            OnionAddress::try_from(&address.public_key_bytes()[..]).unwrap(),
+            address
+        );
+        assert!(OnionAddress::try_from(&[0u8; 31][..]).is_err());
+        assert_eq!(
+            format!("www.{}.", DUCKDUCKGO)
+                .parse::<OnionAddress>()
+                .unwrap(),
+            address
+        );
+    }
+
+    #[test]
+    fn test_onion_address_rejections() {
+        let reason = |host: &str| match host.parse::<OnionAddress>() {
+            Err(TorError::InvalidHostname(message)) => message,
+            other => panic!("{} parsed as {:?}", host, other),
+        };
+        assert!(reason("duckduckgo.com").contains("suffix"));
+        assert!(reason("facebookcorewwwi.onion").contains("56 characters"));
+        assert!(reason(&DUCKDUCKGO.replacen('d', "1", 1)).contains("base32"));
+        assert!(reason(&DUCKDUCKGO.replacen("duck", "dack", 1)).contains("checksum"));
+        // The last character carries the version byte
+        assert!(reason(&DUCKDUCKGO.replacen("d.onion", "e.onion", 1)).contains("version"));
+    }
+}
@@ -0,0 +1,681 @@
+//! Connecting to onion services
+//!
+//! [`OnionConnector`] joins a circuit to an onion service the way
+//! rend-spec-v3 §3 describes:
+//!
+//! 1. fetch the service's descriptor, or take the cached one,
+//! 2. send ESTABLISH_RENDEZVOUS with a random cookie to a rendezvous point,
+//!    the last hop of a spare pooled circuit if there is one,
+//! 3. over a circuit to one of the service's introduction points, send
+//!    INTRODUCE1: the cookie, the rendezvous point and the client's half of
+//!    the hs-ntor handshake, encrypted to the service,
+//! 4. wait for the service's RENDEZVOUS2 on the rendezvous circuit and add
+//!    the virtual hop its half of the handshake keys.
+//!
+//! Introduction points are tried in random order, each with a new
+//! rendezvous point, since a cookie sent through a failed introduction may
+//! have been seen. When none works, the cached descriptor is dropped so the
+//! next connection fetches the service's current one. A joined circuit
+//! carries later streams to the same service under the same isolation key.
+//!
+//! Each step is reported as an [`OnionEvent`] through the circuit
+//! manager's events, so an application can show where a slow connection
+//! is.
+//!
+//! A service under a denial of service attack may ask for a proof of work
+//! (its descriptor's `pow-params`) with each INTRODUCE1. Each introduction
+//! then carries a solution to its [puzzle](PowPuzzle), and each retry
+//! proves more work.
+
+use crate::circuit::{circ_params, Circuit, CircuitManager, CircuitPurpose};
+use crate::error::{Result, TorError};
+use crate::events::OnionEvent;
+use crate::hsdir;
+use crate::isolation::IsolationKey;
+use crate::onion::OnionAddress;
+use crate::onion_pow::PowPuzzle;
+use crate::relay::Relay;
+use crate::retry::with_timeout;
+use crate::traffic::TorStream;
+use futures::channel::oneshot;
+use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tor_bytes::Writeable;
+use tor_cell::relaycell::hs::intro_payload::{IntroduceHandshakePayload, OnionKey};
+use tor_cell::relaycell::hs::pow::ProofOfWork;
+use tor_cell::relaycell::hs::{AuthKeyType, EstablishRendezvous, IntroduceAck};
+use tor_cell::relaycell::hs::{Rendezvous2, RendezvousEstablished};
+use tor_cell::relaycell::msg::{AnyRelayMsg, Introduce1};
+use tor_cell::relaycell::RelayMsg;
+use tor_hscrypto::pk::HsId;
+use tor_hscrypto::{RendCookie, Subcredential};
+use tor_linkspec::{CircTarget, EncodedLinkSpec, LinkSpec};
+use tor_llcrypto::pk::curve25519;
+use tor_llcrypto::rng::CautiousRng;
+use tor_netdoc::doc::hsdesc::IntroPointDesc;
+use tor_proto::client::circuit::handshake::hs_ntor::{HsNtorClientState, HsNtorServiceInfo};
+use tor_proto::client::circuit::handshake::{HandshakeRole, RelayProtocol};
+use tor_proto::{MetaCellDisposition, MsgHandler, TargetHop};
+use tracing::{debug, info, warn};
+
+/// How long a rendezvous point or introduction point has to answer
+const HOP_REPLY_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How long the service has to build its circuit to the rendezvous point
+/// after being introduced
+const RENDEZVOUS_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// A service and the isolation key of streams to it
+type JoinedKey = (HsId, Option<IsolationKey>);
+
+/// Opens streams to onion services over circuits joined to them
+pub struct OnionConnector {
+    circuit_manager: Arc<RwLock<CircuitManager>>,
+    /// Joined circuits, by service and isolation key
+    circuits: Mutex<HashMap<JoinedKey, Arc<RwLock<Circuit>>>>,
+}
+
+impl OnionConnector {
+    pub fn new(circuit_manager: Arc<RwLock<CircuitManager>>) -> Self {
+        Self {
+            circuit_manager,
+            circuits: Mutex::default(),
+        }
+    }
+
+    /// Open a stream to `port` on the service `hsid`
+    ///
+    /// Streams with the same isolation key share the service's circuit;
+    /// a new one is joined if there is none or it has closed.
+    pub async fn connect(
+        &self,
+        hsid: HsId,
+        port: u16,
+        isolation_key: Option<IsolationKey>,
+    ) -> Result<TorStream> {
+        let circuit = self.circuit(hsid, isolation_key).await?;
+        // The service's virtual hop takes BEGIN cells without an address
+        let stream = circuit.read().await.begin_stream("", port).await;
+        stream
+    }
+
+    /// The circuit joined to `hsid` for streams under `isolation_key`,
+    /// joining one if there is none or it has closed
+    pub async fn circuit(
+        &self,
+        hsid: HsId,
+        isolation_key: Option<IsolationKey>,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        let key = (hsid, isolation_key);
+        let joined = self
+            .circuits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .cloned();
+        let circuit = match joined {
+            Some(circuit) if Self::usable(&*circuit.read().await) => circuit,
+            _ => {
+                // Joining is deep; keep it off the callers' futures
+                let circuit = Box::pin(self.join(hsid)).await?;
+                self.circuits
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(key, circuit.clone());
+                circuit
+            }
+        };
+        Ok(circuit)
+    }
+
+    fn usable(circuit: &Circuit) -> bool {
+        circuit.is_ready() && !circuit.tunnel_closed()
+    }
+
+    /// Forget joined circuits, closing none; for a new identity
+    pub fn clear(&self) {
+        self.circuits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// Join a new circuit to `hsid`, reporting how it went
+    async fn join(&self, hsid: HsId) -> Result<Arc<RwLock<Circuit>>> {
+        let circuit_manager = self.circuit_manager.read().await;
+        let address = OnionAddress::from(hsid).to_string();
+        let joined = Self::introduce_and_join(&circuit_manager, hsid, &address).await;
+        match &joined {
+            Ok(circuit) => circuit_manager.events().emit_onion(OnionEvent::Connected {
+                address,
+                circuit_id: circuit.read().await.id.clone(),
+            }),
+            Err(e) => circuit_manager.events().emit_onion(OnionEvent::Failed {
+                address,
+                error: e.to_string(),
+            }),
+        }
+        joined
+    }
+
+    /// Fetch the descriptor of `hsid` and try its introduction points in
+    /// random order
+    async fn introduce_and_join(
+        circuit_manager: &CircuitManager,
+        hsid: HsId,
+        address: &str,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        let hsdir::ServiceDescriptor {
+            descriptor,
+            blinded_id,
+            subcredential,
+            cached,
+        } = hsdir::descriptor(circuit_manager, hsid).await?;
+        circuit_manager
+            .events()
+            .emit_onion(OnionEvent::DescriptorFetched {
+                address: address.to_string(),
+                cached,
+                intro_points: descriptor.intro_points().len(),
+            });
+        if descriptor.requires_intro_authentication() {
+            return Err(TorError::OnionRendezvous(
+                "The service requires client authorization".to_string(),
+            ));
+        }
+        let mut puzzle = PowPuzzle::from_descriptor(&blinded_id, &descriptor);
+        if let Some(puzzle) = &puzzle {
+            debug!(
+                "Onion service asks for a proof of work, effort {:?}",
+                puzzle.effort()
+            );
+        }
+        let mut intro_points: Vec<&IntroPointDesc> = descriptor.intro_points().iter().collect();
+        intro_points.shuffle(&mut rand::thread_rng());
+
+        let mut last_error = None;
+        for (attempt, intro_point) in intro_points.into_iter().enumerate() {
+            debug!("Introducing to onion service, attempt {}", attempt + 1);
+            let progress = Progress {
+                circuit_manager,
+                address,
+                attempt: attempt + 1,
+            };
+            match Self::attempt(progress, intro_point, &subcredential, puzzle.as_ref()).await {
+                Ok(circuit) => {
+                    info!(
+                        "Joined circuit {} to onion service",
+                        circuit.read().await.id
+                    );
+                    return Ok(circuit);
+                }
+                Err(e) => {
+                    warn!("Introduction attempt {} failed: {}", attempt + 1, e);
+                    last_error = Some(e);
+                    if let Some(puzzle) = &mut puzzle {
+                        puzzle.increase_effort();
+                    }
+                }
+            }
+        }
+
+        // The service may have moved to new introduction points
+        hsdir::forget_descriptor(circuit_manager, hsid).await?;
+        let last_error = last_error.map(|e| e.to_string()).unwrap_or_default();
+        Err(TorError::OnionRendezvous(format!(
+            "No introduction point worked: {}",
+            last_error
+        )))
+    }
+
+    /// Meet the service at a new rendezvous point through `intro_point`,
+    /// proving the work `puzzle` asks for
+    async fn attempt(
+        progress: Progress<'_>,
+        intro_point: &IntroPointDesc,
+        subcredential: &Subcredential,
+        puzzle: Option<&PowPuzzle>,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        let circuit_manager = progress.circuit_manager;
+        // Solved first, so the rendezvous circuit doesn't idle meanwhile
+        let proof_of_work = match puzzle {
+            Some(puzzle) => puzzle.solve().await?,
+            None => None,
+        };
+        let intro_relay = listed_relay(circuit_manager, intro_point_relay(intro_point)?).await;
+        let rendezvous = circuit_manager.get_rendezvous_circuit().await?;
+        let joined = async {
+            let (cookie, rendezvous_point, rendezvous2) = {
+                let circuit = rendezvous.read().await;
+                let established = establish_rendezvous(&circuit).await?;
+                progress.emit(|address, attempt| OnionEvent::RendezvousEstablished {
+                    address,
+                    circuit_id: circuit.id.clone(),
+                    rendezvous_point: established.1.nickname.clone(),
+                    attempt,
+                });
+                established
+            };
+            let handshake = Self::introduce(
+                circuit_manager,
+                &intro_relay,
+                intro_point,
+                subcredential,
+                cookie,
+                &rendezvous_point,
+                proof_of_work,
+            )
+            .await?;
+            progress.emit(|address, attempt| OnionEvent::IntroductionSent {
+                address,
+                intro_point: intro_relay.nickname.clone(),
+                attempt,
+            });
+
+            let rendezvous2 = with_timeout(RENDEZVOUS_TIMEOUT, "Rendezvous", async {
+                rendezvous2.await.map_err(|_| {
+                    TorError::circuit_closed("Rendezvous circuit closed before RENDEZVOUS2")
+                })
+            })
+            .await?;
+            let keys = handshake
+                .client_receive_rend(rendezvous2.handshake_info())
+                .map_err(|e| {
+                    TorError::OnionRendezvous(format!("Rendezvous handshake failed: {}", e))
+                })?;
+            let circuit = rendezvous.read().await;
+            tunnel(&circuit)?
+                .as_single_circ()
+                .map_err(|e| {
+                    TorError::Internal(format!("Rendezvous circuit is multi-path: {}", e))
+                })?
+                .extend_virtual(
+                    RelayProtocol::HsV3,
+                    HandshakeRole::Initiator,
+                    keys,
+                    &circ_params(false)?,
+                    &Default::default(),
+                )
+                .await
+                .map_err(|e| TorError::circuit_closed(format!("Failed to join service: {}", e)))
+        }
+        .await;
+
+        match joined {
+            Ok(()) => Ok(rendezvous),
+            Err(e) => {
+                circuit_manager
+                    .close_circuit(&rendezvous, "rendezvous failed")
+                    .await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Send INTRODUCE1 through `intro_point` and wait for its ACK
+    ///
+    /// Returns the client's half of the handshake, which the service's
+    /// RENDEZVOUS2 completes.
+    async fn introduce(
+        circuit_manager: &CircuitManager,
+        intro_relay: &Relay,
+        intro_point: &IntroPointDesc,
+        subcredential: &Subcredential,
+        cookie: RendCookie,
+        rendezvous_point: &Relay,
+        proof_of_work: Option<ProofOfWork>,
+    ) -> Result<HsNtorClientState> {
+        let session_key = intro_point.ipt_sid_key();
+        let introduce1 = |encrypted| {
+            Introduce1::new(
+                AuthKeyType::ED25519_SHA3_256,
+                session_key.as_bytes().to_vec(),
+                encrypted,
+            )
+        };
+        // The header is authenticated by the handshake, so it is encoded
+        // before there is a body to put in it
+        let mut header = Vec::new();
+        introduce1(Vec::new())
+            .encode_onto(&mut header)
+            .map_err(|e| TorError::Internal(format!("Failed to encode INTRODUCE1: {}", e)))?;
+        let target = rendezvous_point.as_circ_target()?;
+        let link_specifiers = target
+            .linkspecs()
+            .map_err(|e| TorError::Internal(format!("Failed to encode link specifiers: {}", e)))?;
+        let mut payload = Vec::new();
+        IntroduceHandshakePayload::new(
+            cookie,
+            OnionKey::NtorOnionKey(*target.ntor_onion_key()),
+            link_specifiers,
+            proof_of_work,
+        )
+        .write_onto(&mut payload)
+        .map_err(|e| TorError::Internal(format!("Failed to encode introduction: {}", e)))?;
+
+        let handshake = HsNtorClientState::new(
+            &mut CautiousRng,
+            HsNtorServiceInfo::new(
+                intro_point.svc_ntor_key().clone(),
+                session_key.clone(),
+                *subcredential,
+            ),
+        );
+        let encrypted = handshake
+            .client_send_intro(&header, &payload)
+            .map_err(|e| TorError::Internal(format!("Failed to start hs-ntor: {}", e)))?;
+
+        let circuit = circuit_manager
+            .get_circuit_to(intro_relay, CircuitPurpose::Introduction)
+            .await?;
+        let acked = async {
+            let (ack_tx, ack_rx) = oneshot::channel();
+            tunnel(&*circuit.read().await)?
+                .start_conversation(
+                    Some(introduce1(encrypted).into()),
+                    IntroduceHandler { ack: Some(ack_tx) },
+                    TargetHop::LastHop,
+                )
+                .await
+                .map_err(|e| {
+                    TorError::circuit_closed(format!("Failed to send INTRODUCE1: {}", e))
+                })?;
+            let ack = with_timeout(HOP_REPLY_TIMEOUT, "Introduction", async {
+                ack_rx.await.map_err(|_| {
+                    TorError::circuit_closed("Introduction circuit closed before INTRODUCE_ACK")
+                })
+            })
+            .await?;
+            ack.success().map(|_| ()).map_err(|status| {
+                TorError::OnionRendezvous(format!("Introduction point refused: {}", status))
+            })
+        }
+        .await;
+        // An introduction circuit is done after one INTRODUCE1
+        circuit_manager
+            .close_circuit(&circuit, "introduction sent")
+            .await;
+        acked.map(|()| handshake)
+    }
+}
+
+/// Where one introduction attempt to a service reports its progress
+#[derive(Clone, Copy)]
+struct Progress<'a> {
+    circuit_manager: &'a CircuitManager,
+    address: &'a str,
+    attempt: usize,
+}
+
+impl Progress<'_> {
+    fn emit(&self, event: impl FnOnce(String, usize) -> OnionEvent) {
+        self.circuit_manager
+            .events()
+            .emit_onion(event(self.address.to_string(), self.attempt));
+    }
+}
+
+/// Send ESTABLISH_RENDEZVOUS to the last hop of `circuit` and wait until
+/// it is established
+///
+/// Returns the cookie, the rendezvous point, and where its RENDEZVOUS2
+/// will arrive.
+async fn establish_rendezvous(
+    circuit: &Circuit,
+) -> Result<(RendCookie, Relay, oneshot::Receiver<Rendezvous2>)> {
+    let rendezvous_point = circuit
+        .relays
+        .last()
+        .cloned()
+        .ok_or_else(|| TorError::Internal("Rendezvous circuit has no hops".to_string()))?;
+    let cookie: RendCookie = rand_09::Rng::random(&mut CautiousRng);
+    let (established_tx, established_rx) = oneshot::channel();
+    let (rendezvous2_tx, rendezvous2_rx) = oneshot::channel();
+    tunnel(circuit)?
+        .start_conversation(
+            Some(EstablishRendezvous::new(cookie).into()),
+            RendezvousHandler {
+                established: Some(established_tx),
+                rendezvous2: Some(rendezvous2_tx),
+            },
+            TargetHop::LastHop,
+        )
+        .await
+        .map_err(|e| {
+            TorError::circuit_closed(format!("Failed to send ESTABLISH_RENDEZVOUS: {}", e))
+        })?;
+    with_timeout(HOP_REPLY_TIMEOUT, "Rendezvous point", async {
+        established_rx.await.map_err(|_| {
+            TorError::circuit_closed("Rendezvous circuit closed before RENDEZVOUS_ESTABLISHED")
+        })
+    })
+    .await?;
+    debug!("Rendezvous point {} is ready", rendezvous_point.nickname);
+    Ok((cookie, rendezvous_point, rendezvous2_rx))
+}
+
+/// The tunnel under `circuit`
+pub(crate) fn tunnel(circuit: &Circuit) -> Result<&Arc<tor_proto::ClientTunnel>> {
+    circuit
+        .internal_circuit
+        .as_ref()
+        .ok_or_else(|| TorError::Internal("No internal circuit available".to_string()))
+}
+
+/// The relay an introduction point's link specifiers name
+fn intro_point_relay(intro_point: &IntroPointDesc) -> Result<Relay> {
+    relay_from_link_specifiers(
+        intro_point.link_specifiers(),
+        intro_point.ipt_ntor_key(),
+        "introduction-point",
+    )
+}
+
+/// The consensus entry of `relay` if it is listed, which also says which
+/// handshakes it supports, or else `relay` as given
+pub(crate) async fn listed_relay(circuit_manager: &CircuitManager, relay: Relay) -> Relay {
+    let relay_manager = circuit_manager.relay_manager().read().await;
+    relay_manager
+        .get_relay(&relay.fingerprint)
+        .cloned()
+        .unwrap_or(relay)
+}
+
+/// The relay that link specifiers from an onion service protocol message
+/// name, preferring its IPv4 address
+pub(crate) fn relay_from_link_specifiers(
+    link_specifiers: &[EncodedLinkSpec],
+    ntor_key: &curve25519::PublicKey,
+    nickname: &str,
+) -> Result<Relay> {
+    let mut addresses: Vec<(IpAddr, u16)> = Vec::new();
+    let mut rsa_id = None;
+    let mut ed_id = None;
+    for spec in link_specifiers {
+        match spec.parse() {
+            Ok(LinkSpec::OrPort(ip, port)) => addresses.push((ip, port)),
+            Ok(LinkSpec::RsaId(id)) => rsa_id = Some(id),
+            Ok(LinkSpec::Ed25519Id(id)) => ed_id = Some(id),
+            _ => {}
+        }
+    }
+    addresses.sort_by_key(|(ip, _)| ip.is_ipv6());
+    let (Some((ip, port)), Some(rsa_id)) = (addresses.first(), rsa_id) else {
+        return Err(TorError::OnionRendezvous(format!(
+            "{} has no address or RSA identity",
+            nickname
+        )));
+    };
+    let mut relay = Relay::new(
+        hex::encode(rsa_id.as_bytes()),
+        nickname.to_string(),
+        ip.to_string(),
+        *port,
+        HashSet::new(),
+        hex::encode(ntor_key.as_bytes()),
+    );
+    relay.ed25519_identity = ed_id.map(|id| hex::encode(id.as_bytes()));
+    Ok(relay)
+}
+
+fn unexpected(expected: &str, msg: &AnyRelayMsg) -> tor_proto::Error {
+    tor_proto::Error::CircProto(format!("Expected {}, got {}", expected, msg.cmd()))
+}
+
+/// Expects RENDEZVOUS_ESTABLISHED, then RENDEZVOUS2
+struct RendezvousHandler {
+    established: Option<oneshot::Sender<()>>,
+    rendezvous2: Option<oneshot::Sender<Rendezvous2>>,
+}
+
+impl MsgHandler for RendezvousHandler {
+    fn handle_msg(&mut self, msg: AnyRelayMsg) -> tor_proto::Result<MetaCellDisposition> {
+        if let Some(established) = self.established.take() {
+            let AnyRelayMsg::RendezvousEstablished(RendezvousEstablished { .. }) = msg else {
+                return Err(unexpected("RENDEZVOUS_ESTABLISHED", &msg));
+            };
+            let _ = established.send(());
+            return Ok(MetaCellDisposition::Consumed);
+        }
+        let AnyRelayMsg::Rendezvous2(rendezvous2) = msg else {
+            return Err(unexpected("RENDEZVOUS2", &msg));
+        };
+        if let Some(sender) = self.rendezvous2.take() {
+            let _ = sender.send(rendezvous2);
+        }
+        Ok(MetaCellDisposition::ConversationFinished)
+    }
+}
+
+/// Expects INTRODUCE_ACK
+struct IntroduceHandler {
+    ack: Option<oneshot::Sender<IntroduceAck>>,
+}
+
+impl MsgHandler for IntroduceHandler {
+    fn handle_msg(&mut self, msg: AnyRelayMsg) -> tor_proto::Result<MetaCellDisposition> {
+        let AnyRelayMsg::IntroduceAck(ack) = msg else {
+            return Err(unexpected("INTRODUCE_ACK", &msg));
+        };
+        if let Some(sender) = self.ack.take() {
+            let _ = sender.send(ack);
+        }
+        Ok(MetaCellDisposition::ConversationFinished)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tor_checkable::Timebound;
+    use tor_hscrypto::pk::HsBlindId;
+    use tor_llcrypto::pk::rsa::RsaIdentity;
+    use tor_netdoc::doc::hsdesc::HsDesc;
+
+    /// arti's test descriptor, as in the descriptor cache tests
+    fn descriptor() -> HsDesc {
+        let text = include_str!("../../vendor/arti/crates/tor-netdoc/testdata/hsdesc1.txt");
+        let key = |hex_key: &str| -> [u8; 32] { hex::decode(hex_key).unwrap().try_into().unwrap() };
+        let blinded_id: HsBlindId =
+            key("43cc0d62fc6252f578705ca645a46109e265290343b1137e90189744b20b3f2d").into();
+        let subcredential: Subcredential =
+            key("78210a0d2c72bb7a0caf606bcd938b9a3696894fdddbc3b87d424753a7e3df37").into();
+        let now: SystemTime = UNIX_EPOCH + Duration::from_secs(1_674_486_000);
+        HsDesc::parse_decrypt_validate(text, &blinded_id, now, &subcredential, None)
+            .unwrap()
+            .dangerously_assume_timely()
+    }
+
+    #[test]
+    fn test_intro_point_relay() {
+        let descriptor = descriptor();
+        let intro_point = &descriptor.intro_points()[0];
+        let relay = intro_point_relay(intro_point).unwrap();
+        assert_eq!(
+            relay.ntor_onion_key,
+            Some(hex::encode(intro_point.ipt_ntor_key().as_bytes()))
+        );
+        relay.as_circ_target().unwrap();
+
+        // IPv4 is preferred, and an address and RSA identity are required
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        let v4: IpAddr = "192.0.2.7".parse().unwrap();
+        let rsa = LinkSpec::RsaId(RsaIdentity::from([5; 20]));
+        let intro_point = |specs: Vec<LinkSpec>| {
+            IntroPointDesc::builder()
+                .link_specifiers(specs.iter().map(|spec| spec.encode().unwrap()).collect())
+                .ipt_kp_ntor(*intro_point.ipt_ntor_key())
+                .kp_hs_ipt_sid(intro_point.ipt_sid_key().clone())
+                .kp_hss_ntor(intro_point.svc_ntor_key().clone())
+                .build()
+                .unwrap()
+        };
+        let both = intro_point(vec![
+            LinkSpec::OrPort(v6, 443),
+            LinkSpec::OrPort(v4, 9001),
+            rsa.clone(),
+        ]);
+        let relay = intro_point_relay(&both).unwrap();
+        assert_eq!((relay.address.as_str(), relay.or_port), ("192.0.2.7", 9001));
+        assert_eq!(relay.fingerprint, "05".repeat(20));
+        assert!(intro_point_relay(&intro_point(vec![LinkSpec::OrPort(v4, 9001)])).is_err());
+        assert!(intro_point_relay(&intro_point(vec![rsa])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_each_attempt_reports_its_outcome() {
+        use crate::events::CircuitEvents;
+        use crate::relay::RelayManager;
+        use futures::StreamExt;
+
+        let events = CircuitEvents::new();
+        let mut onion_events = events.subscribe_onion();
+        let circuit_manager = CircuitManager::new(
+            Arc::new(RwLock::new(RelayManager::new(Vec::new()))),
+            Arc::new(RwLock::new(None)),
+        )
+        .with_events(events);
+
+        // Progress is stamped with the service and the attempt number
+        let address = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+        let progress = Progress {
+            circuit_manager: &circuit_manager,
+            address,
+            attempt: 2,
+        };
+        progress.emit(|address, attempt| OnionEvent::IntroductionSent {
+            address,
+            intro_point: "relay".to_string(),
+            attempt,
+        });
+        assert_eq!(
+            onion_events.next().await.unwrap(),
+            OnionEvent::IntroductionSent {
+                address: address.to_string(),
+                intro_point: "relay".to_string(),
+                attempt: 2,
+            }
+        );
+
+        // With no consensus there is no descriptor to fetch, and each try
+        // ends in one Failed event carrying the error it returned
+        let connector = OnionConnector::new(Arc::new(RwLock::new(circuit_manager)));
+        let hsid: HsId = address.parse().unwrap();
+        for _ in 0..2 {
+            let err = connector.connect(hsid, 80, None).await.unwrap_err();
+            assert_eq!(
+                onion_events.next().await.unwrap(),
+                OnionEvent::Failed {
+                    address: address.to_string(),
+                    error: err.to_string(),
+                }
+            );
+        }
+        assert!(onion_events.try_next().is_err());
+    }
+}
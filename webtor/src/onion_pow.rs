@@ -0,0 +1,226 @@
+//! Proof of work for onion service introductions
+//!
+//! A service under a denial of service attack publishes a `pow-params v1`
+//! line in its descriptor: a seed, valid until a given time, and the effort
+//! it suggests. The client then solves an EquiX puzzle built from the seed,
+//! its blinded key and a random nonce (hspow-spec §2), and sends the
+//! solution in the INTRO1_POW extension of INTRODUCE1. The service queues
+//! introductions by the effort they prove, so a client whose introduction
+//! isn't answered retries with more (§3.3): double the effort up to 1000,
+//! then half as much again, between 8 and 10000.
+//!
+//! HashX programs are interpreted here, the compiled runtime being
+//! unavailable in WASM, so one solver step takes a few hundred
+//! milliseconds. In native builds the solver runs on a blocking thread; in
+//! WASM it yields to the event loop between steps.
+
+use crate::error::{Result, TorError};
+use crate::time::system_time_now;
+use rand::Rng;
+use tor_cell::relaycell::hs::pow::v1::ProofOfWorkV1;
+use tor_cell::relaycell::hs::pow::ProofOfWork;
+use tor_checkable::timed::TimerangeBound;
+use tor_checkable::Timebound;
+use tor_hscrypto::pk::HsBlindId;
+use tor_hscrypto::pow::v1::{
+    Effort, Instance, RuntimeOption, Solution, Solver, SolverInput, NONCE_LEN,
+};
+use tor_netdoc::doc::hsdesc::pow::PowParams;
+use tor_netdoc::doc::hsdesc::HsDesc;
+use tracing::debug;
+
+/// Effort doubles on each retry below this
+const EFFORT_DOUBLE_UNTIL: Effort = Effort::new(1000);
+/// Effort multiplier on retries from [`EFFORT_DOUBLE_UNTIL`] on
+const EFFORT_RETRY_MULTIPLIER: f32 = 1.5;
+/// Least effort of a retry
+const MIN_RETRY_EFFORT: Effort = Effort::new(8);
+/// Most effort a client puts into one introduction
+const MAX_EFFORT: Effort = Effort::new(10000);
+
+/// A service's v1 puzzle and the effort the next introduction proves
+#[derive(Debug, Clone)]
+pub struct PowPuzzle {
+    instance: TimerangeBound<Instance>,
+    effort: Effort,
+}
+
+impl PowPuzzle {
+    /// The v1 puzzle in `descriptor`, if the service published one
+    pub fn from_descriptor(blinded_id: &HsBlindId, descriptor: &HsDesc) -> Option<Self> {
+        descriptor
+            .pow_params()
+            .iter()
+            .find_map(|params| match params {
+                PowParams::V1(v1) => Some(Self {
+                    instance: v1
+                        .seed()
+                        .to_owned()
+                        .dangerously_map(|seed| Instance::new(*blinded_id, seed)),
+                    effort: v1.suggested_effort().clamp(Effort::zero(), MAX_EFFORT),
+                }),
+                _ => None,
+            })
+    }
+
+    /// Effort the next introduction proves; zero sends no proof
+    pub fn effort(&self) -> Effort {
+        self.effort
+    }
+
+    /// Prove more work after an introduction went unanswered
+    pub fn increase_effort(&mut self) {
+        let effort = if self.effort < EFFORT_DOUBLE_UNTIL {
+            self.effort.saturating_mul_u32(2)
+        } else {
+            self.effort.saturating_mul_f32(EFFORT_RETRY_MULTIPLIER)
+        };
+        self.effort = effort.clamp(MIN_RETRY_EFFORT, MAX_EFFORT);
+    }
+
+    /// Solve the puzzle at the current effort, `None` when that is zero
+    pub async fn solve(&self) -> Result<Option<ProofOfWork>> {
+        if self.effort == Effort::zero() {
+            return Ok(None);
+        }
+        let instance = self
+            .instance
+            .as_ref()
+            .check_valid_at(&system_time_now())
+            .map_err(|_| TorError::OnionRendezvous("The service's puzzle seed expired".into()))?
+            .clone();
+        debug!("Solving onion service puzzle at effort {:?}", self.effort);
+        let solution = solve(instance, self.effort).await?;
+        Ok(Some(ProofOfWork::V1(ProofOfWorkV1::new(
+            solution.nonce().to_owned(),
+            solution.effort(),
+            solution.seed_head(),
+            solution.proof_to_bytes(),
+        ))))
+    }
+}
+
+/// A solver for `instance` at `effort`, starting from a random nonce
+fn solver(instance: Instance, effort: Effort) -> Solver {
+    let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+    let mut input = SolverInput::new(instance, effort);
+    input.runtime(RuntimeOption::InterpretOnly);
+    input.solve_with_nonce(&nonce.into())
+}
+
+fn solver_error(e: impl std::fmt::Display) -> TorError {
+    TorError::Internal(format!("Puzzle solver failed: {}", e))
+}
+
+/// Run the solver on a blocking thread, which stops once the caller is
+/// no longer waiting
+#[cfg(not(target_arch = "wasm32"))]
+async fn solve(instance: Instance, effort: Effort) -> Result<Solution> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Tells the solver thread to stop when dropped
+    struct Cancel(Arc<AtomicBool>);
+    impl Drop for Cancel {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    let cancel = Cancel(Arc::default());
+    let cancelled = cancel.0.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut solver = solver(instance, effort);
+        loop {
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(TorError::Cancelled);
+            }
+            if let Some(solution) = solver.run_step().map_err(solver_error)? {
+                return Ok(solution);
+            }
+        }
+    })
+    .await
+    .map_err(solver_error)?
+}
+
+/// Run the solver a step at a time, letting other tasks run in between
+#[cfg(target_arch = "wasm32")]
+async fn solve(instance: Instance, effort: Effort) -> Result<Solution> {
+    let mut solver = solver(instance, effort);
+    loop {
+        if let Some(solution) = solver.run_step().map_err(solver_error)? {
+            return Ok(solution);
+        }
+        crate::retry::sleep(std::time::Duration::ZERO).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tor_hscrypto::pow::v1::{Seed, Verifier};
+
+    fn puzzle(effort: u32) -> PowPuzzle {
+        let seed = Seed::from([7; 32]);
+        let instance = Instance::new(HsBlindId::from([9; 32]), seed);
+        PowPuzzle {
+            instance: TimerangeBound::new(instance, ..system_time_now() + Duration::from_secs(60)),
+            effort: Effort::new(effort),
+        }
+    }
+
+    #[test]
+    fn test_effort_grows_on_retries() {
+        let mut puzzle = puzzle(0);
+        let mut efforts = Vec::new();
+        for _ in 0..12 {
+            puzzle.increase_effort();
+            efforts.push(u32::from(puzzle.effort()));
+        }
+        assert_eq!(
+            efforts,
+            [8, 16, 32, 64, 128, 256, 512, 1024, 1536, 2304, 3456, 5184]
+        );
+        for _ in 0..10 {
+            puzzle.increase_effort();
+        }
+        assert_eq!(puzzle.effort(), MAX_EFFORT);
+    }
+
+    #[tokio::test]
+    async fn test_solutions_verify() {
+        assert!(puzzle(0).solve().await.unwrap().is_none());
+
+        let puzzle = puzzle(2);
+        let Some(ProofOfWork::V1(proof)) = puzzle.solve().await.unwrap() else {
+            panic!("no v1 proof");
+        };
+        assert_eq!(proof.effort(), Effort::new(2));
+        let instance = puzzle.instance.dangerously_peek().clone();
+        let solution = Solution::try_from_bytes(
+            proof.nonce().to_owned(),
+            proof.effort(),
+            proof.seed_head(),
+            proof.solution(),
+        )
+        .unwrap();
+        let mut verifier = Verifier::new(instance);
+        verifier.runtime(RuntimeOption::InterpretOnly);
+        verifier.check(&solution).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expired_seed_is_refused() {
+        let mut puzzle = puzzle(1);
+        puzzle.instance = TimerangeBound::new(
+            puzzle.instance.dangerously_peek().clone(),
+            ..system_time_now() - Duration::from_secs(1),
+        );
+        assert!(matches!(
+            puzzle.solve().await,
+            Err(TorError::OnionRendezvous(_))
+        ));
+    }
+}
@@ -0,0 +1,983 @@
+//! Hosting an ephemeral onion service
+//!
+//! [`OnionService`] makes the client reachable at a `.onion` address of its
+//! own, for as long as the handle is kept (rend-spec-v3 §3, from the
+//! service's side):
+//!
+//! 1. a freshly generated ed25519 identity is the address; it is never
+//!    stored, so the address goes away with the handle,
+//! 2. introduction points are established: circuits to relays, each
+//!    holding an ESTABLISH_INTRO signed with a session key of its own,
+//! 3. a descriptor listing them, encrypted to the address, is uploaded to
+//!    the HSDirs responsible for the blinded key of the time period; it is
+//!    uploaded again hourly, when the period changes, and when an
+//!    introduction point is replaced,
+//! 4. each INTRODUCE2 an introduction point relays completes the hs-ntor
+//!    handshake: a circuit is built to the client's rendezvous point,
+//!    RENDEZVOUS1 is sent there, and the virtual hop it joins takes BEGIN
+//!    cells from the client.
+//!
+//! Each BEGIN is handed to user code as an [`OnionStreamRequest`] by
+//! [`OnionService::next_request`], to accept or reject. Introductions are
+//! answered in the background, so a slow rendezvous point holds up no
+//! other client.
+//!
+//! A service that needn't hide where it runs can opt into single onion
+//! mode ([`OnionServiceConfig::with_single_onion`]): its introduction and
+//! rendezvous circuits go from the bridge straight to the relay, without
+//! a middle, and its descriptor says so. Clients keep their anonymity;
+//! the service gives up its own for fewer hops.
+
+use crate::circuit::{circ_params, Circuit, CircuitManager, CircuitPurpose};
+use crate::error::{Result, TorError};
+use crate::hsdir;
+use crate::onion::OnionAddress;
+use crate::onion_connector::{listed_relay, relay_from_link_specifiers, tunnel};
+use crate::relay::{selection, Relay};
+use crate::retry::{sleep, with_cancellation, with_timeout, CancellationToken};
+use crate::time::{system_time_now, Instant};
+use crate::traffic::{CountedStream, TorStream};
+use futures::channel::{mpsc, oneshot};
+use futures::future::{select, Either};
+use futures::{SinkExt, StreamExt};
+use safelog::DisplayRedacted;
+use sha3::{Digest, Sha3_256};
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+use tor_bytes::Reader;
+use tor_cell::chancell::msg::HandshakeType;
+use tor_cell::relaycell::hs::est_intro::EstablishIntroDetails;
+use tor_cell::relaycell::hs::intro_payload::{IntroduceHandshakePayload, OnionKey};
+use tor_cell::relaycell::msg::{
+    AnyRelayMsg, Connected, End, EndReason, Introduce2, Rendezvous1, Unrecognized,
+};
+use tor_cell::relaycell::{RelayCmd, RelayMsg};
+use tor_hscrypto::pk::{
+    HsBlindIdKey, HsBlindIdKeypair, HsId, HsIdKey, HsIdKeypair, HsIntroPtSessionIdKey,
+    HsIntroPtSessionIdKeypair, HsSvcNtorKeypair,
+};
+use tor_hscrypto::time::TimePeriod;
+use tor_hscrypto::Subcredential;
+use tor_linkspec::CircTarget;
+use tor_llcrypto::pk::{curve25519, ed25519};
+use tor_llcrypto::rng::CautiousRng;
+use tor_netdoc::doc::hsdesc::{create_desc_sign_key_cert, HsDescBuilder, IntroPointDesc};
+use tor_netdoc::NetdocBuilder;
+use tor_proto::circuit::CircSyncView;
+use tor_proto::client::circuit::handshake::hs_ntor;
+use tor_proto::client::circuit::handshake::{HandshakeRole, RelayProtocol};
+use tor_proto::client::stream::{
+    IncomingStream, IncomingStreamRequest, IncomingStreamRequestContext,
+    IncomingStreamRequestDisposition, IncomingStreamRequestFilter,
+};
+use tor_proto::{MetaCellDisposition, MsgHandler, TargetHop};
+use tracing::{debug, info, warn};
+
+/// Introduction points a service keeps unless configured otherwise
+pub const DEFAULT_INTRO_POINTS: usize = 3;
+
+/// Most introduction points a descriptor may list
+pub const MAX_INTRO_POINTS: usize = 20;
+
+/// How long a descriptor is good for (its `descriptor-lifetime`)
+const DESCRIPTOR_LIFETIME: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// How often the descriptor is uploaded again, well within its lifetime
+const REPUBLISH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often introduction points are checked, and closed ones replaced
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long an introduction point has to confirm ESTABLISH_INTRO
+const ESTABLISH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Introductions waiting to be answered; more are dropped, as a busy
+/// service would
+const INTRODUCTION_QUEUE: usize = 16;
+
+/// Stream requests waiting for user code; more wait on their circuits
+const REQUEST_QUEUE: usize = 32;
+
+/// Introductions remembered per introduction point to spot replays; one
+/// that relays more is retired
+const MAX_INTRODUCTIONS_PER_POINT: usize = 16_384;
+
+/// Clients may only open data streams to the service
+const ALLOWED_COMMANDS: &[RelayCmd] = &[RelayCmd::BEGIN];
+
+/// How a hosted onion service behaves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnionServiceConfig {
+    /// Virtual ports clients may open streams to; any if empty
+    pub ports: Vec<u16>,
+    /// Introduction points to keep
+    pub intro_points: usize,
+    /// Streams one client circuit may have open at once; a circuit asking
+    /// for more is closed
+    pub max_streams_per_circuit: usize,
+    /// Run as a single onion service, whose location is not hidden
+    pub single_onion: bool,
+}
+
+impl Default for OnionServiceConfig {
+    fn default() -> Self {
+        Self {
+            ports: Vec::new(),
+            intro_points: DEFAULT_INTRO_POINTS,
+            max_streams_per_circuit: 64,
+            single_onion: false,
+        }
+    }
+}
+
+impl OnionServiceConfig {
+    /// Accept streams to `port`; once a port is given, others are refused
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.ports.push(port);
+        self
+    }
+
+    /// Keep `count` introduction points, between 1 and [`MAX_INTRO_POINTS`]
+    pub fn with_intro_points(mut self, count: usize) -> Self {
+        self.intro_points = count.clamp(1, MAX_INTRO_POINTS);
+        self
+    }
+
+    pub fn with_max_streams_per_circuit(mut self, max: usize) -> Self {
+        self.max_streams_per_circuit = max;
+        self
+    }
+
+    /// Build introduction and rendezvous circuits without a middle hop
+    ///
+    /// This makes the service faster to reach, but the bridge and the
+    /// relays it meets clients at can tell where it runs: only for
+    /// services whose operator is public anyway.
+    pub fn with_single_onion(mut self, single_onion: bool) -> Self {
+        self.single_onion = single_onion;
+        self
+    }
+
+    /// A circuit ending at `relay`, direct in single onion mode
+    async fn circuit_to(
+        &self,
+        circuit_manager: &CircuitManager,
+        relay: &Relay,
+        purpose: CircuitPurpose,
+    ) -> Result<Arc<RwLock<Circuit>>> {
+        if self.single_onion {
+            circuit_manager
+                .create_direct_circuit_to(relay, purpose)
+                .await
+        } else {
+            circuit_manager.get_circuit_to(relay, purpose).await
+        }
+    }
+
+    fn allows_port(&self, port: u16) -> bool {
+        self.ports.is_empty() || self.ports.contains(&port)
+    }
+}
+
+/// An onion service hosted by this client; dropping it takes the service
+/// down
+pub struct OnionService {
+    hsid: HsId,
+    requests: mpsc::Receiver<OnionStreamRequest>,
+    shutdown: CancellationToken,
+}
+
+impl OnionService {
+    /// Generate an identity, establish introduction points and publish the
+    /// descriptor, then answer introductions until the handle is dropped
+    pub(crate) async fn launch(
+        circuit_manager: Arc<RwLock<CircuitManager>>,
+        config: OnionServiceConfig,
+    ) -> Result<Self> {
+        let identity = HsIdKeypair::from(ed25519::ExpandedKeypair::from(
+            &ed25519::Keypair::generate(&mut CautiousRng),
+        ));
+        let hsid = HsIdKey::from(&identity).id();
+        let (introductions_tx, introductions) = mpsc::channel(INTRODUCTION_QUEUE);
+        let (requests_tx, requests) = mpsc::channel(REQUEST_QUEUE);
+        let mut host = ServiceHost {
+            circuit_manager,
+            config,
+            identity,
+            intro_points: Vec::new(),
+            introductions: introductions_tx,
+            requests: requests_tx,
+            subcredentials: Vec::new(),
+            published: None,
+        };
+
+        let result = async {
+            if host.establish_missing().await == 0 {
+                return Err(TorError::OnionService(
+                    "No introduction point could be established".to_string(),
+                ));
+            }
+            host.publish().await
+        }
+        .await;
+        if let Err(e) = result {
+            host.close().await;
+            return Err(e);
+        }
+
+        let shutdown = CancellationToken::new();
+        if host.config.single_onion {
+            warn!(
+                "Onion service {} is a single onion service; its location is not hidden",
+                hsid.display_redacted()
+            );
+        }
+        info!("Onion service {} is up", hsid.display_redacted());
+        spawn(host.run(introductions, shutdown.clone()));
+        Ok(Self {
+            hsid,
+            requests,
+            shutdown,
+        })
+    }
+
+    pub fn hsid(&self) -> HsId {
+        self.hsid
+    }
+
+    /// The service's `.onion` address
+    pub fn address(&self) -> OnionAddress {
+        OnionAddress::from(self.hsid)
+    }
+
+    /// The next stream a client asks for, or `None` once the service is
+    /// down
+    pub async fn next_request(&mut self) -> Option<OnionStreamRequest> {
+        self.requests.next().await
+    }
+
+    /// Take the service down: introduction points are closed, and clients
+    /// can no longer reach it once their descriptors expire
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+}
+
+impl Drop for OnionService {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+impl std::fmt::Debug for OnionService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OnionService")
+            .field("hsid", &self.hsid.display_redacted().to_string())
+            .finish()
+    }
+}
+
+/// A client's request for a stream to the service
+pub struct OnionStreamRequest {
+    port: u16,
+    incoming: IncomingStream,
+    circuit: Arc<RwLock<Circuit>>,
+}
+
+impl OnionStreamRequest {
+    /// The virtual port the client asked for
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Accept the stream, telling the client it is connected
+    pub async fn accept(self) -> Result<TorStream> {
+        let stream = self
+            .incoming
+            .accept_data(Connected::new_empty())
+            .await
+            .map_err(|e| TorError::circuit_closed(format!("Failed to accept stream: {}", e)))?;
+        Ok(CountedStream::new(
+            stream,
+            &self.circuit.read().await.traffic,
+        ))
+    }
+
+    /// Refuse the stream
+    pub async fn reject(self) -> Result<()> {
+        refuse(self.incoming).await
+    }
+}
+
+impl std::fmt::Debug for OnionStreamRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OnionStreamRequest")
+            .field("port", &self.port)
+            .finish()
+    }
+}
+
+async fn refuse(incoming: IncomingStream) -> Result<()> {
+    incoming
+        .reject(End::new_with_reason(EndReason::CONNECTREFUSED))
+        .await
+        .map_err(|e| TorError::circuit_closed(format!("Failed to refuse stream: {}", e)))
+}
+
+/// The keys of one introduction point
+struct IntroKeys {
+    /// Identifies the service at the introduction point (`KP_hs_ipt_sid`)
+    session_key: HsIntroPtSessionIdKeypair,
+    /// Clients encrypt their introductions to this (`KP_hss_ntor`)
+    ntor_key: HsSvcNtorKeypair,
+}
+
+impl IntroKeys {
+    fn generate() -> Self {
+        let secret = curve25519::StaticSecret::random_from_rng(&mut CautiousRng);
+        Self {
+            session_key: ed25519::Keypair::generate(&mut CautiousRng).into(),
+            ntor_key: HsSvcNtorKeypair::from_secret_key(secret.into()),
+        }
+    }
+
+    fn session_id(&self) -> HsIntroPtSessionIdKey {
+        self.session_key.as_ref().verifying_key().into()
+    }
+}
+
+struct IntroPoint {
+    relay: Relay,
+    circuit: Arc<RwLock<Circuit>>,
+    keys: Arc<IntroKeys>,
+}
+
+impl IntroPoint {
+    /// How the descriptor lists this introduction point
+    fn descriptor_entry(&self) -> Result<IntroPointDesc> {
+        let target = self.relay.as_circ_target()?;
+        let link_specifiers = target
+            .linkspecs()
+            .map_err(|e| TorError::Internal(format!("Failed to encode link specifiers: {}", e)))?;
+        IntroPointDesc::builder()
+            .link_specifiers(link_specifiers)
+            .ipt_kp_ntor(*target.ntor_onion_key())
+            .kp_hs_ipt_sid(self.keys.session_id())
+            .kp_hss_ntor(self.keys.ntor_key.public().clone())
+            .build()
+            .map_err(|e| TorError::Internal(format!("Invalid introduction point: {}", e)))
+    }
+
+    async fn is_open(&self) -> bool {
+        let circuit = self.circuit.read().await;
+        circuit.is_ready() && !circuit.tunnel_closed()
+    }
+}
+
+/// An INTRODUCE2 and the keys of the introduction point it came through
+struct Introduction {
+    message: Introduce2,
+    keys: Arc<IntroKeys>,
+}
+
+/// When the descriptor was last uploaded, and for which time period
+struct Published {
+    period: u64,
+    at: Instant,
+}
+
+/// What the background task of a service owns
+struct ServiceHost {
+    circuit_manager: Arc<RwLock<CircuitManager>>,
+    config: OnionServiceConfig,
+    identity: HsIdKeypair,
+    intro_points: Vec<IntroPoint>,
+    introductions: mpsc::Sender<Introduction>,
+    requests: mpsc::Sender<OnionStreamRequest>,
+    /// Of the current time period and the one before, which clients with
+    /// an older consensus may still use
+    subcredentials: Vec<Subcredential>,
+    published: Option<Published>,
+}
+
+impl ServiceHost {
+    async fn run(
+        mut self,
+        mut introductions: mpsc::Receiver<Introduction>,
+        shutdown: CancellationToken,
+    ) {
+        let mut maintained = Instant::now();
+        loop {
+            let event = with_cancellation(&shutdown, async {
+                let introduction = pin!(introductions.next());
+                let tick = pin!(sleep(MAINTENANCE_INTERVAL));
+                Ok(match select(introduction, tick).await {
+                    Either::Left((introduction, _)) => introduction,
+                    Either::Right(_) => None,
+                })
+            })
+            .await;
+            match event {
+                Ok(Some(introduction)) => self.answer(introduction),
+                Ok(None) => {}
+                Err(_) => break,
+            }
+            if maintained.elapsed() >= MAINTENANCE_INTERVAL {
+                self.maintain().await;
+                maintained = Instant::now();
+            }
+        }
+        self.close().await;
+        info!("Onion service shut down");
+    }
+
+    /// Meet the client of `introduction` at its rendezvous point, in the
+    /// background
+    fn answer(&self, introduction: Introduction) {
+        let circuit_manager = self.circuit_manager.clone();
+        let subcredentials = self.subcredentials.clone();
+        let config = self.config.clone();
+        let requests = self.requests.clone();
+        spawn(async move {
+            if let Err(e) = rendezvous(
+                circuit_manager,
+                introduction,
+                subcredentials,
+                config,
+                requests,
+            )
+            .await
+            {
+                warn!("Failed to answer introduction: {}", e);
+            }
+        });
+    }
+
+    /// Replace closed introduction points, and publish the descriptor if
+    /// they changed or it is due
+    async fn maintain(&mut self) {
+        let mut open = Vec::with_capacity(self.intro_points.len());
+        let mut changed = false;
+        for point in std::mem::take(&mut self.intro_points) {
+            if point.is_open().await {
+                open.push(point);
+            } else {
+                warn!("Introduction point {} closed", point.relay.nickname);
+                changed = true;
+            }
+        }
+        self.intro_points = open;
+        if self.intro_points.len() < self.config.intro_points {
+            changed |= self.establish_missing().await > 0;
+        }
+
+        let due = match (&self.published, self.time_period(system_time_now()).await) {
+            (Some(published), Ok(period)) => {
+                published.period != period.interval_num()
+                    || published.at.elapsed() >= REPUBLISH_INTERVAL
+            }
+            _ => true,
+        };
+        if (changed || due) && !self.intro_points.is_empty() {
+            if let Err(e) = self.publish().await {
+                warn!("Failed to publish onion service descriptor: {}", e);
+                self.published = None;
+            }
+        }
+    }
+
+    /// Establish introduction points until there are as many as
+    /// configured, giving each missing one two tries; returns how many
+    /// were added
+    async fn establish_missing(&mut self) -> usize {
+        let missing = self
+            .config
+            .intro_points
+            .saturating_sub(self.intro_points.len());
+        let mut added = 0;
+        for _ in 0..missing * 2 {
+            if added == missing {
+                break;
+            }
+            let in_use = self
+                .intro_points
+                .iter()
+                .map(|point| point.relay.fingerprint.clone())
+                .collect();
+            match self.establish(in_use).await {
+                Ok(point) => {
+                    debug!("Established introduction point {}", point.relay.nickname);
+                    self.intro_points.push(point);
+                    added += 1;
+                }
+                Err(e) => warn!("Failed to establish introduction point: {}", e),
+            }
+        }
+        added
+    }
+
+    /// Send ESTABLISH_INTRO to a relay not in `in_use`, over a circuit
+    /// ending there
+    async fn establish(&self, in_use: Vec<String>) -> Result<IntroPoint> {
+        let circuit_manager = self.circuit_manager.read().await;
+        let relay = circuit_manager
+            .relay_manager()
+            .read()
+            .await
+            .select_relay(&selection::middle_relays().without_fingerprints(in_use))?;
+        let circuit = self
+            .config
+            .circuit_to(&circuit_manager, &relay, CircuitPurpose::Introduction)
+            .await?;
+        let keys = Arc::new(IntroKeys::generate());
+        let established = async {
+            let circuit = circuit.read().await;
+            let tunnel = tunnel(&circuit)?;
+            let binding = tunnel
+                .as_single_circ()
+                .map_err(|e| {
+                    TorError::Internal(format!("Introduction circuit is multi-path: {}", e))
+                })?
+                .binding_key(TargetHop::LastHop)
+                .await
+                .map_err(|e| {
+                    TorError::circuit_closed(format!("Introduction circuit closed: {}", e))
+                })?
+                .ok_or_else(|| {
+                    TorError::Internal("No binding key for introduction point".into())
+                })?;
+            let session_key = keys.session_key.as_ref();
+            let body = EstablishIntroDetails::new(session_key.verifying_key().into())
+                .sign_and_encode(session_key, binding.hs_mac())
+                .map_err(|e| {
+                    TorError::Internal(format!("Failed to sign ESTABLISH_INTRO: {}", e))
+                })?;
+
+            let (established_tx, established_rx) = oneshot::channel();
+            tunnel
+                .start_conversation(
+                    Some(AnyRelayMsg::Unrecognized(Unrecognized::new(
+                        RelayCmd::ESTABLISH_INTRO,
+                        body,
+                    ))),
+                    IntroPointHandler {
+                        established: Some(established_tx),
+                        keys: keys.clone(),
+                        introductions: self.introductions.clone(),
+                        seen: HashSet::new(),
+                    },
+                    TargetHop::LastHop,
+                )
+                .await
+                .map_err(|e| {
+                    TorError::circuit_closed(format!("Failed to send ESTABLISH_INTRO: {}", e))
+                })?;
+            with_timeout(ESTABLISH_TIMEOUT, "Introduction point", async {
+                established_rx.await.map_err(|_| {
+                    TorError::circuit_closed("Introduction circuit closed before INTRO_ESTABLISHED")
+                })
+            })
+            .await
+        }
+        .await;
+
+        match established {
+            Ok(()) => Ok(IntroPoint {
+                relay,
+                circuit,
+                keys,
+            }),
+            Err(e) => {
+                circuit_manager
+                    .close_circuit(&circuit, "introduction point refused")
+                    .await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Sign a descriptor listing the current introduction points and
+    /// upload it for the current time period
+    async fn publish(&mut self) -> Result<()> {
+        let now = system_time_now();
+        let period = self.time_period(now).await?;
+        let blind = |period: TimePeriod| {
+            self.identity.compute_blinded_key(period).map_err(|e| {
+                TorError::Internal(format!("Failed to blind onion service key: {}", e))
+            })
+        };
+        let (blinded_key, blinded_keypair, subcredential) = blind(period)?;
+        let mut subcredentials = vec![subcredential];
+        if let Some(previous) = period.prev() {
+            subcredentials.push(blind(previous)?.2);
+        }
+
+        let intro_points = self
+            .intro_points
+            .iter()
+            .map(IntroPoint::descriptor_entry)
+            .collect::<Result<Vec<_>>>()?;
+        let text = sign_descriptor(
+            &blinded_key,
+            &blinded_keypair,
+            subcredential,
+            period,
+            now,
+            &intro_points,
+            self.config.single_onion,
+        )?;
+
+        let circuit_manager = self.circuit_manager.read().await;
+        hsdir::publish_descriptor(&circuit_manager, &blinded_key.id(), period, &text).await?;
+        self.subcredentials = subcredentials;
+        self.published = Some(Published {
+            period: period.interval_num(),
+            at: Instant::now(),
+        });
+        Ok(())
+    }
+
+    async fn time_period(&self, now: SystemTime) -> Result<TimePeriod> {
+        let circuit_manager = self.circuit_manager.read().await;
+        let relay_manager = circuit_manager.relay_manager().read().await;
+        relay_manager
+            .hsdir_params
+            .as_ref()
+            .ok_or_else(|| {
+                TorError::HsDescriptor("No consensus with HSDir parameters yet".to_string())
+            })?
+            .time_period(now)
+    }
+
+    /// Close every introduction circuit
+    async fn close(&mut self) {
+        let circuit_manager = self.circuit_manager.read().await;
+        for point in self.intro_points.drain(..) {
+            circuit_manager
+                .close_circuit(&point.circuit, "onion service shut down")
+                .await;
+        }
+    }
+}
+
+/// A descriptor for `period` listing `intro_points`, encrypted with
+/// `subcredential` and signed with a fresh key certified by the blinded key
+fn sign_descriptor(
+    blinded_key: &HsBlindIdKey,
+    blinded_keypair: &HsBlindIdKeypair,
+    subcredential: Subcredential,
+    period: TimePeriod,
+    now: SystemTime,
+    intro_points: &[IntroPointDesc],
+    single_onion: bool,
+) -> Result<String> {
+    let signing_key = ed25519::Keypair::generate(&mut CautiousRng);
+    // Certificates outlive the descriptor, so it never fails to verify
+    // while a client may still use it
+    let expiry = now + DESCRIPTOR_LIFETIME + REPUBLISH_INTERVAL;
+    let certificate =
+        create_desc_sign_key_cert(&signing_key.verifying_key(), blinded_keypair, expiry)
+            .map_err(|e| TorError::Internal(format!("Failed to certify signing key: {}", e)))?;
+    HsDescBuilder::default()
+        .blinded_id(blinded_key)
+        .hs_desc_sign(&signing_key)
+        .hs_desc_sign_cert(certificate)
+        .create2_formats(&[HandshakeType::NTOR])
+        .auth_required(None)
+        .is_single_onion_service(single_onion)
+        .intro_points(intro_points)
+        .intro_auth_key_cert_expiry(expiry)
+        .intro_enc_key_cert_expiry(expiry)
+        .lifetime(((DESCRIPTOR_LIFETIME.as_secs() / 60) as u16).into())
+        .revision_counter(revision_counter(period, now).into())
+        .subcredential(subcredential)
+        .build_sign(&mut CautiousRng)
+        .map_err(|e| TorError::Internal(format!("Failed to sign descriptor: {}", e)))
+}
+
+/// Seconds into `period` at `now`, which only grows for the period's
+/// blinded key
+fn revision_counter(period: TimePeriod, now: SystemTime) -> u64 {
+    period
+        .range()
+        .ok()
+        .and_then(|range| now.duration_since(range.start).ok())
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Complete the handshake of `introduction`, join the client at its
+/// rendezvous point, and pass its stream requests on until the circuit
+/// closes
+async fn rendezvous(
+    circuit_manager: Arc<RwLock<CircuitManager>>,
+    introduction: Introduction,
+    subcredentials: Vec<Subcredential>,
+    config: OnionServiceConfig,
+    mut requests: mpsc::Sender<OnionStreamRequest>,
+) -> Result<()> {
+    let Introduction { message, keys } = introduction;
+    let (keys_generator, handshake_info, plaintext) = hs_ntor::server_receive_intro(
+        &mut CautiousRng,
+        &keys.ntor_key,
+        &keys.session_id(),
+        &subcredentials,
+        message.encoded_header(),
+        message.encrypted_body(),
+    )
+    .map_err(|e| TorError::OnionService(format!("Undecryptable introduction: {}", e)))?;
+    // The plaintext is padded, so it's not read to the end
+    let payload: IntroduceHandshakePayload = Reader::from_slice(&plaintext)
+        .extract()
+        .map_err(|e| TorError::OnionService(format!("Malformed introduction: {}", e)))?;
+    let OnionKey::NtorOnionKey(ntor_key) = payload.onion_key() else {
+        return Err(TorError::OnionService(
+            "Rendezvous point has no ntor key".to_string(),
+        ));
+    };
+
+    let (circuit, stream_requests) = {
+        let circuit_manager = circuit_manager.read().await;
+        let point =
+            relay_from_link_specifiers(payload.link_specifiers(), ntor_key, "rendezvous-point")?;
+        let point = listed_relay(&circuit_manager, point).await;
+        let circuit = config
+            .circuit_to(&circuit_manager, &point, CircuitPurpose::Rendezvous)
+            .await?;
+        let joined = async {
+            let circuit = circuit.read().await;
+            let tunnel = tunnel(&circuit)?.clone();
+            let hop_error = |e: tor_proto::Error| {
+                TorError::circuit_closed(format!("Rendezvous circuit closed: {}", e))
+            };
+            let rendezvous_point = tunnel.last_hop().map_err(hop_error)?;
+            tunnel
+                .as_single_circ()
+                .map_err(|e| {
+                    TorError::Internal(format!("Rendezvous circuit is multi-path: {}", e))
+                })?
+                .extend_virtual(
+                    RelayProtocol::HsV3,
+                    HandshakeRole::Responder,
+                    keys_generator,
+                    &circ_params(false)?,
+                    &Default::default(),
+                )
+                .await
+                .map_err(hop_error)?;
+            let client = tunnel.last_hop().map_err(hop_error)?;
+            let stream_requests = tunnel
+                .allow_stream_requests(
+                    ALLOWED_COMMANDS,
+                    client,
+                    StreamLimit(config.max_streams_per_circuit),
+                )
+                .await
+                .map_err(hop_error)?;
+            tunnel
+                .send_raw_msg(
+                    Rendezvous1::new(*payload.cookie(), handshake_info).into(),
+                    rendezvous_point,
+                )
+                .await
+                .map_err(hop_error)?;
+            Ok(stream_requests)
+        }
+        .await;
+        match joined {
+            Ok(stream_requests) => (circuit, stream_requests),
+            Err(e) => {
+                circuit_manager
+                    .close_circuit(&circuit, "rendezvous failed")
+                    .await;
+                return Err(e);
+            }
+        }
+    };
+    debug!("Joined client at rendezvous point");
+
+    let mut stream_requests = Box::pin(stream_requests);
+    while let Some(incoming) = stream_requests.next().await {
+        let port = match incoming.request() {
+            IncomingStreamRequest::Begin(begin) => begin.port(),
+            _ => continue,
+        };
+        if !config.allows_port(port) {
+            debug!("Refusing stream to unlisted port {}", port);
+            let _ = refuse(incoming).await;
+            continue;
+        }
+        let request = OnionStreamRequest {
+            port,
+            incoming,
+            circuit: circuit.clone(),
+        };
+        if requests.send(request).await.is_err() {
+            break;
+        }
+    }
+    circuit_manager
+        .read()
+        .await
+        .close_circuit(&circuit, "client left")
+        .await;
+    Ok(())
+}
+
+/// Expects INTRO_ESTABLISHED, then passes on INTRODUCE2 messages
+struct IntroPointHandler {
+    established: Option<oneshot::Sender<()>>,
+    keys: Arc<IntroKeys>,
+    introductions: mpsc::Sender<Introduction>,
+    /// Digests of the introductions relayed so far
+    seen: HashSet<[u8; 32]>,
+}
+
+impl MsgHandler for IntroPointHandler {
+    fn handle_msg(&mut self, msg: AnyRelayMsg) -> tor_proto::Result<MetaCellDisposition> {
+        match msg {
+            AnyRelayMsg::IntroEstablished(_) if self.established.is_some() => {
+                if let Some(established) = self.established.take() {
+                    let _ = established.send(());
+                }
+                Ok(MetaCellDisposition::Consumed)
+            }
+            AnyRelayMsg::Introduce2(message) if self.established.is_none() => {
+                // Each introduction is answered once, however often the
+                // introduction point relays it
+                let digest = Sha3_256::digest(message.encrypted_body()).into();
+                if !self.seen.insert(digest) {
+                    debug!("Dropping replayed introduction");
+                    return Ok(MetaCellDisposition::Consumed);
+                }
+                if self.seen.len() > MAX_INTRODUCTIONS_PER_POINT {
+                    // New keys come with a new introduction point
+                    return Ok(MetaCellDisposition::CloseCirc);
+                }
+                let introduction = Introduction {
+                    message,
+                    keys: self.keys.clone(),
+                };
+                if self.introductions.try_send(introduction).is_err() {
+                    debug!("Too many introductions waiting; dropping one");
+                }
+                Ok(MetaCellDisposition::Consumed)
+            }
+            other => Err(tor_proto::Error::CircProto(format!(
+                "Unexpected {} on introduction circuit",
+                other.cmd()
+            ))),
+        }
+    }
+}
+
+/// Closes a client circuit that opens more streams than allowed
+struct StreamLimit(usize);
+
+impl IncomingStreamRequestFilter for StreamLimit {
+    fn disposition(
+        &mut self,
+        _request: &IncomingStreamRequestContext<'_>,
+        circuit: &CircSyncView<'_>,
+    ) -> tor_proto::Result<IncomingStreamRequestDisposition> {
+        Ok(if circuit.n_open_streams() >= self.0 {
+            IncomingStreamRequestDisposition::CloseCircuit
+        } else {
+            IncomingStreamRequestDisposition::Accept
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn spawn(task: impl Future<Output = ()> + 'static) {
+    wasm_bindgen_futures::spawn_local(task);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn(task: impl Future<Output = ()> + Send + 'static) {
+    tokio::spawn(task);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+    use tor_hscrypto::pk::HsBlindId;
+    use tor_netdoc::doc::hsdesc::HsDesc;
+
+    #[test]
+    fn test_descriptor_round_trip() {
+        let identity = HsIdKeypair::from(ed25519::ExpandedKeypair::from(
+            &ed25519::Keypair::generate(&mut CautiousRng),
+        ));
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let period = TimePeriod::new(
+            Duration::from_secs(86_400),
+            now,
+            Duration::from_secs(43_200),
+        )
+        .unwrap();
+        let (blinded_key, blinded_keypair, subcredential) =
+            identity.compute_blinded_key(period).unwrap();
+
+        let mut relay = Relay::new(
+            "aa".repeat(20),
+            "intro".to_string(),
+            "192.0.2.1".to_string(),
+            9001,
+            HashSet::new(),
+            "11".repeat(32),
+        );
+        relay.ed25519_identity = Some("22".repeat(32));
+        let keys = Arc::new(IntroKeys::generate());
+        let point = IntroPointDesc::builder()
+            .link_specifiers(relay.as_circ_target().unwrap().linkspecs().unwrap())
+            .ipt_kp_ntor([0x11; 32].into())
+            .kp_hs_ipt_sid(keys.session_id())
+            .kp_hss_ntor(keys.ntor_key.public().clone())
+            .build()
+            .unwrap();
+
+        let sign = |single_onion| {
+            sign_descriptor(
+                &blinded_key,
+                &blinded_keypair,
+                subcredential,
+                period,
+                now,
+                std::slice::from_ref(&point),
+                single_onion,
+            )
+            .unwrap()
+        };
+        let text = sign(false);
+
+        // What a client would find, knowing only the address
+        let hsid = HsIdKey::from(&identity).id();
+        let (blinded_id, client_subcredential): (HsBlindId, Subcredential) =
+            hsdir::blind(hsid, period).unwrap();
+        assert_eq!(blinded_id, blinded_key.id());
+        let parse = |text: &str| {
+            let descriptor =
+                HsDesc::parse_decrypt_validate(text, &blinded_id, now, &client_subcredential, None)
+                    .unwrap();
+            tor_checkable::Timebound::check_valid_at(descriptor, &now).unwrap()
+        };
+        let descriptor = parse(&text);
+        assert!(!descriptor.is_single_onion_service());
+        assert!(parse(&sign(true)).is_single_onion_service());
+        let listed = &descriptor.intro_points()[0];
+        assert_eq!(listed.ipt_sid_key(), &keys.session_id());
+        let relay_back =
+            relay_from_link_specifiers(listed.link_specifiers(), listed.ipt_ntor_key(), "intro")
+                .unwrap();
+        assert_eq!(relay_back.fingerprint, relay.fingerprint);
+        assert!(
+            revision_counter(period, now + Duration::from_secs(60)) > revision_counter(period, now)
+        );
+    }
+}
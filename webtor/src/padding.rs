@@ -0,0 +1,205 @@
+//! Channel padding (netflow resistance)
+//!
+//! A regular Tor client never lets its channel to the guard fall silent for
+//! long: both ends send a PADDING cell once no cell has gone out for a
+//! random timeout, and the client tells the guard with PADDING_NEGOTIATE how
+//! it wants to be padded in return (padding-spec section 2). A bridge
+//! channel without padding has idle gaps a regular client's doesn't, which
+//! shows up in the netflow records routers keep.
+//!
+//! The timeouts are the consensus defaults, since the bridge channel comes
+//! up before there is a consensus to read them from. The padding itself is
+//! sent by the channel reactor once [`engage`] has configured it.
+
+use crate::error::{Result, TorError};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use tor_cell::chancell::msg::PaddingNegotiate;
+use tor_proto::channel::padding::Parameters;
+use tor_proto::channel::params::ChannelPaddingInstructionsUpdates;
+use tor_proto::channel::Channel;
+use tor_proto::ChannelPaddingInstructions;
+
+/// Padding timeout range in milliseconds at the normal level (`nf_ito_low`,
+/// `nf_ito_high`)
+pub const NORMAL_PADDING_MS: (u32, u32) = (1_500, 9_500);
+
+/// Padding timeout range in milliseconds at the reduced level
+/// (`nf_ito_low_reduced`, `nf_ito_high_reduced`)
+pub const REDUCED_PADDING_MS: (u32, u32) = (9_000, 14_000);
+
+/// How much padding is kept up on channels to the bridge or guard
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelPadding {
+    /// Pad like a regular Tor client, and have the peer pad too
+    #[default]
+    Normal,
+    /// Pad less often and ask the peer not to pad, as mobile Tor clients do
+    Reduced,
+    /// Neither pad nor have the peer pad
+    None,
+}
+
+impl ChannelPadding {
+    /// Timeouts for the padding sent to the peer, if any is sent
+    pub fn send_parameters(self) -> Option<Parameters> {
+        let (low, high) = match self {
+            Self::Normal => NORMAL_PADDING_MS,
+            Self::Reduced => REDUCED_PADDING_MS,
+            Self::None => return None,
+        };
+        Parameters::builder()
+            .low(low.into())
+            .high(high.into())
+            .build()
+            .ok()
+    }
+
+    /// The PADDING_NEGOTIATE cell asking the peer for the padding we want
+    ///
+    /// At the normal level this is the peer's default, which the channel
+    /// doesn't bother sending.
+    pub fn negotiate_cell(self) -> PaddingNegotiate {
+        match self {
+            Self::Normal => PaddingNegotiate::start_default(),
+            Self::Reduced | Self::None => PaddingNegotiate::stop(),
+        }
+    }
+}
+
+impl FromStr for ChannelPadding {
+    type Err = TorError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "normal" => Ok(Self::Normal),
+            "reduced" => Ok(Self::Reduced),
+            "none" => Ok(Self::None),
+            other => Err(TorError::configuration(format!(
+                "Unknown channel padding level: {} (expected normal, reduced or none)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Configure `padding` on a channel that just opened: start the padding
+/// timer and send PADDING_NEGOTIATE if the peer's default isn't wanted
+pub fn engage(chan: &Channel, padding: ChannelPadding) -> Result<()> {
+    let mut instructions = ChannelPaddingInstructions::default();
+    if let Some(update) = configure(&mut instructions, padding) {
+        reparameterize(chan, update)?;
+    }
+    chan.engage_padding_activities();
+    Ok(())
+}
+
+/// Stop the padding `engage` set up while the app is in the background:
+/// nothing is sent just to keep the channel busy, and the peer is asked to
+/// stop padding too
+pub fn suspend(chan: &Channel, padding: ChannelPadding) -> Result<()> {
+    match suspend_update(padding) {
+        Some(update) => reparameterize(chan, update),
+        None => Ok(()),
+    }
+}
+
+/// Restore `padding` on a channel paused with [`suspend`]
+pub fn resume(chan: &Channel, padding: ChannelPadding) -> Result<()> {
+    match resume_update(padding) {
+        Some(update) => reparameterize(chan, update),
+        None => Ok(()),
+    }
+}
+
+/// Apply `padding` to `instructions`, returning what changed
+fn configure(
+    instructions: &mut ChannelPaddingInstructions,
+    padding: ChannelPadding,
+) -> Option<ChannelPaddingInstructionsUpdates> {
+    let send = padding.send_parameters();
+    let mut update = instructions
+        .start_update()
+        .padding_enable(send.is_some())
+        .padding_negotiate(padding.negotiate_cell());
+    if let Some(params) = send {
+        update = update.padding_parameters(params);
+    }
+    update.finish()
+}
+
+/// Instructions a channel follows once paused at `padding`
+fn suspended(padding: ChannelPadding) -> ChannelPaddingInstructions {
+    let mut instructions = ChannelPaddingInstructions::default();
+    configure(&mut instructions, padding);
+    configure(&mut instructions, ChannelPadding::None);
+    instructions
+}
+
+fn suspend_update(padding: ChannelPadding) -> Option<ChannelPaddingInstructionsUpdates> {
+    let mut engaged = ChannelPaddingInstructions::default();
+    configure(&mut engaged, padding);
+    configure(&mut engaged, ChannelPadding::None)
+}
+
+fn resume_update(padding: ChannelPadding) -> Option<ChannelPaddingInstructionsUpdates> {
+    configure(&mut suspended(padding), padding)
+}
+
+fn reparameterize(chan: &Channel, update: ChannelPaddingInstructionsUpdates) -> Result<()> {
+    chan.reparameterize(Arc::new(update))
+        .map_err(|e| TorError::Network(format!("Failed to configure padding: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(
+            ChannelPadding::Normal.send_parameters(),
+            Some(Parameters::default_padding())
+        );
+        assert!(ChannelPadding::Reduced.send_parameters().is_some());
+        assert_eq!(ChannelPadding::None.send_parameters(), None);
+
+        assert_eq!(
+            ChannelPadding::Normal.negotiate_cell(),
+            PaddingNegotiate::start_default()
+        );
+        assert_eq!(
+            ChannelPadding::Reduced.negotiate_cell(),
+            PaddingNegotiate::stop()
+        );
+    }
+
+    #[test]
+    fn test_suspend_and_resume() {
+        assert!(suspend_update(ChannelPadding::Normal).is_some());
+        assert!(suspend_update(ChannelPadding::Reduced).is_some());
+        // Nothing to pause without padding
+        assert_eq!(suspend_update(ChannelPadding::None), None);
+        assert_eq!(resume_update(ChannelPadding::None), None);
+
+        // Resuming restores exactly what engaging set up
+        for padding in [ChannelPadding::Normal, ChannelPadding::Reduced] {
+            let mut engaged = ChannelPaddingInstructions::default();
+            configure(&mut engaged, padding);
+            let mut resumed = suspended(padding);
+            assert!(configure(&mut resumed, padding).is_some());
+            assert_eq!(resumed, engaged);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "reduced".parse::<ChannelPadding>().unwrap(),
+            ChannelPadding::Reduced
+        );
+        assert!("lots".parse::<ChannelPadding>().is_err());
+    }
+}
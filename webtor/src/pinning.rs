@@ -0,0 +1,168 @@
+//! Certificate pinning for TLS through exits
+//!
+//! An exit sees the TLS handshake of every HTTPS request it carries. A
+//! malicious one holding a certificate some trusted CA mis-issued could
+//! intercept the connection; a [`CertPin`] closes that gap for hosts whose
+//! keys or certificates are known ahead of time. The chain the server
+//! presents must contain a pinned key or certificate, or the connection is
+//! dropped before anything is sent and fails with
+//! [`TorError::CertificatePinMismatch`].
+
+use crate::error::{Result, TorError};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use x509_parser::pem::Pem;
+
+/// A key or certificate a server's chain must contain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CertPin {
+    /// SHA-256 of a certificate's DER `SubjectPublicKeyInfo`; survives
+    /// certificate renewals that keep the key
+    Spki([u8; 32]),
+    /// A whole DER certificate
+    Certificate(Vec<u8>),
+}
+
+impl CertPin {
+    /// Parse `sha256/<base64 SPKI hash>` (the form of curl's
+    /// `--pinnedpubkey` and HPKP) or a PEM certificate
+    pub fn parse(pin: &str) -> Result<Self> {
+        let pin = pin.trim();
+        if let Some(hash) = pin.strip_prefix("sha256/") {
+            let hash = base64::engine::general_purpose::STANDARD
+                .decode(hash)
+                .ok()
+                .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+                .ok_or_else(|| TorError::configuration(format!("Invalid SPKI pin {:?}", pin)))?;
+            return Ok(Self::Spki(hash));
+        }
+        let mut certificates = pem_certificates(pin.as_bytes())?;
+        match (certificates.pop(), certificates.is_empty()) {
+            (Some(der), true) => Ok(Self::Certificate(der)),
+            _ => Err(TorError::configuration(
+                "A certificate pin must hold exactly one PEM certificate",
+            )),
+        }
+    }
+
+    /// Pin the public key of the DER certificate `der`
+    pub fn spki_of(der: &[u8]) -> Result<Self> {
+        spki_sha256(der)
+            .map(Self::Spki)
+            .ok_or_else(|| TorError::configuration("Not a DER X.509 certificate"))
+    }
+
+    /// Whether the DER certificate `der` is pinned by this
+    pub fn matches(&self, der: &[u8]) -> bool {
+        match self {
+            Self::Spki(hash) => spki_sha256(der).as_ref() == Some(hash),
+            Self::Certificate(pinned) => pinned == der,
+        }
+    }
+}
+
+impl std::fmt::Display for CertPin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Spki(hash) => write!(
+                f,
+                "sha256/{}",
+                base64::engine::general_purpose::STANDARD.encode(hash)
+            ),
+            Self::Certificate(der) => write!(f, "certificate ({} bytes)", der.len()),
+        }
+    }
+}
+
+/// Fail unless `pins` is empty or pins a certificate of `chain`, the DER
+/// chain `host` presented
+pub(crate) fn check_chain<C: AsRef<[u8]>>(host: &str, pins: &[CertPin], chain: &[C]) -> Result<()> {
+    if pins.is_empty()
+        || chain
+            .iter()
+            .any(|cert| pins.iter().any(|pin| pin.matches(cert.as_ref())))
+    {
+        return Ok(());
+    }
+    Err(TorError::CertificatePinMismatch {
+        host: host.to_string(),
+    })
+}
+
+/// The DER certificates in a PEM bundle, skipping other blocks
+pub(crate) fn pem_certificates(pem: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut certificates = Vec::new();
+    for block in Pem::iter_from_buffer(pem) {
+        let block = block.map_err(|e| TorError::configuration(format!("Invalid PEM: {}", e)))?;
+        if block.label == "CERTIFICATE" {
+            certificates.push(block.contents);
+        }
+    }
+    if certificates.is_empty() {
+        return Err(TorError::configuration("No PEM certificate found"));
+    }
+    Ok(certificates)
+}
+
+fn spki_sha256(der: &[u8]) -> Option<[u8; 32]> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    Some(Sha256::digest(cert.public_key().raw).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PINNED_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIIBiTCCAS+gAwIBAgIUReB4HiaRj8BvLtEyFQYivAzX+McwCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOcGlubmVkLmV4YW1wbGUwIBcNMjYxMDE2MTg1OTQ1WhgPMjEy
+NjA5MjIxODU5NDVaMBkxFzAVBgNVBAMMDnBpbm5lZC5leGFtcGxlMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAE4goNhl004VYAyuDjaRtglbJDMXoU8fxXMVqhwGJe
+1FyaCV/rjyzE8hzYbBZScoiAL8hYdJYLskuVRmOMPZxkpaNTMFEwHQYDVR0OBBYE
+FMn79g2C7Js3Jz4Ln+jyQaxJGD3KMB8GA1UdIwQYMBaAFMn79g2C7Js3Jz4Ln+jy
+QaxJGD3KMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIga/+Qc0jv
+SMmwqMwXjyHuFvbRuGdgBHalKMgl7aYzj+4CIQCNldKbAWvcuEmG9XGTCayP2/fg
+HWlpRRP8dfRkX5c9Jg==
+-----END CERTIFICATE-----
+";
+
+    const OTHER_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIIBhzCCAS2gAwIBAgIUWtFhSWJOjnBBJfwsg9JWkeqEBQcwCgYIKoZIzj0EAwIw
+GDEWMBQGA1UEAwwNb3RoZXIuZXhhbXBsZTAgFw0yNjEwMTYxODU5NDZaGA8yMTI2
+MDkyMjE4NTk0NlowGDEWMBQGA1UEAwwNb3RoZXIuZXhhbXBsZTBZMBMGByqGSM49
+AgEGCCqGSM49AwEHA0IABF8VXxRY+lwG0j6qKudqCVKpBVA9S6FbVQ0twKjMF9+K
+nD5DNkXXy7aLQM4ebIlq5E/D/tSoaKFWb0MhBFyicrijUzBRMB0GA1UdDgQWBBQc
+5ZGENni6emuI65GPQ7L9Ab8t9TAfBgNVHSMEGDAWgBQc5ZGENni6emuI65GPQ7L9
+Ab8t9TAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIQDS6AqhnUxL
+nP2Afe+B413RePQhlUisfIuv/zvpXmtKzgIgY3MT7VMWaV+RWKSoWdy60UWNIMu6
+eoaS/zdAeFaKQdE=
+-----END CERTIFICATE-----
+";
+
+    /// `openssl x509 -pubkey | openssl pkey -pubin -outform der | sha256`
+    const PINNED_SPKI: &str = "sha256/yAQ/6pbjnj8zusljxKRjfx9lKkYY61iv+MP9/CvB93A=";
+
+    #[test]
+    fn test_pins_match_the_chain() {
+        let pinned = pem_certificates(PINNED_PEM.as_bytes()).unwrap().remove(0);
+        let other = pem_certificates(OTHER_PEM.as_bytes()).unwrap().remove(0);
+
+        let spki = CertPin::parse(PINNED_SPKI).unwrap();
+        assert_eq!(spki, CertPin::spki_of(&pinned).unwrap());
+        assert_eq!(spki.to_string(), PINNED_SPKI);
+        assert!(spki.matches(&pinned) && !spki.matches(&other));
+        let cert = CertPin::parse(PINNED_PEM).unwrap();
+        assert!(cert.matches(&pinned) && !cert.matches(&other));
+
+        // Any certificate in the chain may carry the pin
+        assert!(check_chain("a.example", std::slice::from_ref(&spki), &[&other, &pinned]).is_ok());
+        assert!(check_chain("a.example", &[], &[&other]).is_ok());
+        let err = check_chain("a.example", &[spki, cert], &[&other]).unwrap_err();
+        assert_eq!(err.code(), "CERT_PIN_MISMATCH");
+        assert!(err.to_string().contains("a.example"));
+
+        assert!(CertPin::parse("sha256/tooshort").is_err());
+        assert!(CertPin::parse(&format!("{}{}", PINNED_PEM, OTHER_PEM)).is_err());
+        assert!(CertPin::spki_of(b"junk").is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Request profiles: what HTTP requests look like to destinations and exits
+//!
+//! Everyone using Tor Browser sends the same headers in the same order, so
+//! a site can't tell its users apart by them. A request from webtor with
+//! its own User-Agent, a `*/*` Accept and headers in hash-map order stands
+//! out from that crowd. [`RequestProfile::TorBrowser`] writes requests the
+//! way Tor Browser does on a top-level navigation:
+//!
+//! - its User-Agent, Accept, Accept-Language and fetch metadata headers
+//!   unless the caller set them,
+//! - every header in Firefox's order, caller headers it doesn't know
+//!   after those sorted by name, with known names in Firefox's case,
+//! - no TLS session resumption, since Tor Browser never resumes.
+//!
+//! Some differences remain. The connection is closed after each response,
+//! so `Connection: close` is sent where Firefox sends `keep-alive`; only
+//! HTTP/1.1 is offered over ALPN, where Firefox also offers h2; and zstd
+//! is not among the accepted encodings, as it can't be decoded here.
+
+use crate::error::{Result, TorError};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// User-Agent of Tor Browser 15 (Firefox ESR 140), the same on every
+/// platform
+pub const TOR_BROWSER_USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; rv:140.0) Gecko/20100101 Firefox/140.0";
+
+/// Headers Tor Browser sends on a navigation, in the order it sends them,
+/// besides those the client writes itself
+const TOR_BROWSER_HEADERS: &[(&str, &str)] = &[
+    ("User-Agent", TOR_BROWSER_USER_AGENT),
+    (
+        "Accept",
+        "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+    ),
+    ("Accept-Language", "en-US,en;q=0.5"),
+    ("Upgrade-Insecure-Requests", "1"),
+    ("Sec-Fetch-Dest", "document"),
+    ("Sec-Fetch-Mode", "navigate"),
+    ("Sec-Fetch-Site", "none"),
+    ("Sec-Fetch-User", "?1"),
+    ("Priority", "u=0, i"),
+];
+
+/// The order Firefox writes headers in
+const FIREFOX_HEADER_ORDER: &[&str] = &[
+    "Host",
+    "User-Agent",
+    "Accept",
+    "Accept-Language",
+    "Accept-Encoding",
+    "Range",
+    "If-Range",
+    "Content-Type",
+    "Content-Length",
+    "Authorization",
+    "Origin",
+    "Connection",
+    "Referer",
+    "Cookie",
+    "Upgrade-Insecure-Requests",
+    "Sec-Fetch-Dest",
+    "Sec-Fetch-Mode",
+    "Sec-Fetch-Site",
+    "Sec-Fetch-User",
+    "If-Modified-Since",
+    "If-None-Match",
+    "Priority",
+    "Pragma",
+    "Cache-Control",
+];
+
+/// How HTTP requests are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RequestProfile {
+    /// webtor's own User-Agent and `Accept: */*`, headers in no set order
+    #[default]
+    Webtor,
+    /// Look like Tor Browser
+    TorBrowser,
+}
+
+impl RequestProfile {
+    /// Headers to send when the caller hasn't set them
+    pub(crate) fn default_headers(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::Webtor => &[("User-Agent", "webtor-rs/0.1.0"), ("Accept", "*/*")],
+            Self::TorBrowser => TOR_BROWSER_HEADERS,
+        }
+    }
+
+    /// Put the header lines of a request in this profile's order
+    pub(crate) fn arrange(self, headers: &mut [(String, String)]) {
+        if self == Self::Webtor {
+            return;
+        }
+        for (name, _) in headers.iter_mut() {
+            if let Some(known) = FIREFOX_HEADER_ORDER
+                .iter()
+                .find(|known| known.eq_ignore_ascii_case(name))
+            {
+                *name = known.to_string();
+            }
+        }
+        headers.sort_by_cached_key(|(name, _)| {
+            let rank = FIREFOX_HEADER_ORDER.iter().position(|known| known == name);
+            (
+                rank.unwrap_or(FIREFOX_HEADER_ORDER.len()),
+                name.to_ascii_lowercase(),
+            )
+        });
+    }
+
+    /// Whether TLS sessions may be resumed
+    pub(crate) fn resumes_tls(self) -> bool {
+        self == Self::Webtor
+    }
+}
+
+impl FromStr for RequestProfile {
+    type Err = TorError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "webtor" => Ok(Self::Webtor),
+            "tor-browser" => Ok(Self::TorBrowser),
+            other => Err(TorError::configuration(format!(
+                "Unknown request profile: {} (expected webtor or tor-browser)",
+                other
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tor_browser_order() {
+        let mut headers: Vec<(String, String)> = [
+            ("x-custom", "1"),
+            ("cookie", "a=b"),
+            ("Host", "example.com"),
+            ("Accept-Language", "en-US,en;q=0.5"),
+            ("Connection", "close"),
+            ("User-Agent", TOR_BROWSER_USER_AGENT),
+            ("X-Another", "2"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        let unchanged = headers.clone();
+        RequestProfile::Webtor.arrange(&mut headers);
+        assert_eq!(headers, unchanged);
+
+        RequestProfile::TorBrowser.arrange(&mut headers);
+        let names: Vec<&str> = headers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Host",
+                "User-Agent",
+                "Accept-Language",
+                "Connection",
+                "Cookie",
+                "X-Another",
+                "x-custom"
+            ]
+        );
+
+        assert_eq!(
+            "tor-browser".parse::<RequestProfile>().unwrap(),
+            RequestProfile::TorBrowser
+        );
+        assert!("firefox".parse::<RequestProfile>().is_err());
+    }
+}
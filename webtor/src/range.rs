@@ -0,0 +1,255 @@
+//! Range requests and resumable downloads
+//!
+//! A download cut off by a circuit dying keeps the bytes that arrived.
+//! [`PartialDownload`] picks it up from there: it asks for the rest with a
+//! `Range` on a new circuit, guarded by `If-Range` so that a resource that
+//! changed meanwhile is sent whole rather than spliced onto stale bytes.
+//! [`TorClient::download`](crate::TorClient::download) runs this loop.
+
+use crate::error::{Result, TorError};
+use crate::http::{HttpRequest, HttpResponse};
+use http::header::{CONTENT_LENGTH, CONTENT_RANGE};
+use http::HeaderValue;
+use tracing::{debug, info};
+
+/// The `Content-Range` of a 206 response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    /// First byte sent
+    pub start: u64,
+    /// Last byte sent, inclusive
+    pub end: u64,
+    /// Length of the whole resource, if the server knows it
+    pub total: Option<u64>,
+}
+
+impl ContentRange {
+    /// Parse a `bytes <start>-<end>/<total or *>` value
+    pub fn parse(value: &str) -> Option<Self> {
+        let (range, total) = value.trim().strip_prefix("bytes ")?.split_once('/')?;
+        let (start, end) = range.trim().split_once('-')?;
+        let range = Self {
+            start: start.parse().ok()?,
+            end: end.parse().ok()?,
+            total: match total.trim() {
+                "*" => None,
+                total => Some(total.parse().ok()?),
+            },
+        };
+        (range.start <= range.end && range.total.is_none_or(|total| range.end < total))
+            .then_some(range)
+    }
+}
+
+/// A download and the bytes of it received so far
+#[derive(Debug, Clone)]
+pub struct PartialDownload {
+    /// The response the download started with, body and all received bytes
+    response: HttpResponse,
+    /// Length of the whole resource, if known
+    total: Option<u64>,
+    /// Strong ETag or Last-Modified the rest must match
+    validator: Option<String>,
+}
+
+impl PartialDownload {
+    /// Start from a 200 response to the full resource
+    pub fn new(response: HttpResponse) -> Result<Self> {
+        if response.status != 200 {
+            return Err(TorError::http_request(format!(
+                "Cannot resume a download that got {} {}",
+                response.status, response.status_text
+            )));
+        }
+        let total = response
+            .header(CONTENT_LENGTH.as_str())
+            .and_then(|len| len.trim().parse().ok());
+        Ok(Self {
+            validator: validator(&response),
+            total,
+            response,
+        })
+    }
+
+    /// Bytes received so far
+    pub fn received(&self) -> u64 {
+        self.response.body.len() as u64
+    }
+
+    /// Length of the whole resource, if the server said
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Whether every byte has arrived; a resource of unknown length is
+    /// complete once it has ended
+    pub fn is_complete(&self) -> bool {
+        self.total.is_none_or(|total| self.received() >= total)
+    }
+
+    /// The request for the rest of the resource, based on `request`
+    ///
+    /// It goes on a new circuit, as the old one may be what failed.
+    pub fn resume_request(&self, request: &HttpRequest) -> HttpRequest {
+        let mut request = request.clone();
+        request.url = self.response.url.clone();
+        let request = request
+            .with_range(self.received(), None)
+            .with_new_circuit(true);
+        match &self.validator {
+            Some(validator) => request.with_if_range(validator),
+            None => request,
+        }
+    }
+
+    /// Add `response` to a [`resume_request`](Self::resume_request)
+    ///
+    /// A 200 means the resource changed (or ranges aren't supported) and
+    /// replaces what was received.
+    pub fn apply(&mut self, response: HttpResponse) -> Result<()> {
+        match response.status {
+            206 => {
+                let range = response.content_range().ok_or_else(|| {
+                    TorError::http_request("Partial response without a valid Content-Range")
+                })?;
+                if range.start != self.received() {
+                    return Err(TorError::http_request(format!(
+                        "Server resumed at byte {} instead of {}",
+                        range.start,
+                        self.received()
+                    )));
+                }
+                if self.validator.is_some() && validator(&response) != self.validator {
+                    return Err(TorError::http_request(
+                        "Resource changed while it was being downloaded",
+                    ));
+                }
+                debug!(
+                    "Resumed {} at byte {} ({} more)",
+                    self.response.url,
+                    range.start,
+                    response.body.len()
+                );
+                self.total = range.total.or(self.total);
+                self.response.body.extend_from_slice(&response.body);
+                self.response.retries += response.retries + 1;
+                Ok(())
+            }
+            200 => {
+                info!(
+                    "{} was sent whole instead of resumed; starting over",
+                    self.response.url
+                );
+                let retries = self.response.retries + response.retries + 1;
+                *self = Self::new(response)?;
+                self.response.retries = retries;
+                Ok(())
+            }
+            // Nothing left past what we have
+            416 if self.total == Some(self.received()) => Ok(()),
+            status => Err(TorError::http_request(format!(
+                "Resuming the download failed: {} {}",
+                status, response.status_text
+            ))),
+        }
+    }
+
+    /// The whole download as a 200 response
+    pub fn into_response(mut self) -> HttpResponse {
+        self.response.headers.remove(CONTENT_RANGE);
+        self.response
+            .headers
+            .insert(CONTENT_LENGTH, HeaderValue::from(self.response.body.len()));
+        self.response
+    }
+}
+
+/// What `If-Range` can name: a strong ETag, or else the modification date
+fn validator(response: &HttpResponse) -> Option<String> {
+    match response.header("etag") {
+        Some(etag) if !etag.starts_with("W/") => Some(etag.to_string()),
+        _ => response.header("last-modified").map(str::to_string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{HeaderMap, Version};
+    use url::Url;
+
+    fn response(status: u16, headers: &[(&str, &str)], body: &[u8]) -> HttpResponse {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        HttpResponse {
+            status,
+            status_text: String::new(),
+            version: Version::HTTP_11,
+            headers: map,
+            trailers: HeaderMap::new(),
+            body: body.to_vec(),
+            retries: 0,
+            url: Url::parse("https://files.example/big.bin").unwrap(),
+            request_id: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_content_range() {
+        assert_eq!(
+            ContentRange::parse("bytes 10-19/100"),
+            Some(ContentRange {
+                start: 10,
+                end: 19,
+                total: Some(100)
+            })
+        );
+        assert_eq!(ContentRange::parse("bytes 0-0/*").unwrap().total, None);
+        assert!(ContentRange::parse("bytes */100").is_none());
+        assert!(ContentRange::parse("bytes 20-10/100").is_none());
+        assert!(ContentRange::parse("bytes 0-100/100").is_none());
+        assert!(ContentRange::parse("items 0-1/2").is_none());
+    }
+
+    #[test]
+    fn test_resume() {
+        let etag = ("ETag", "\"v1\"");
+        let cut_off = response(200, &[etag, ("Content-Length", "10")], b"0123");
+        let mut download = PartialDownload::new(cut_off).unwrap();
+        assert!(!download.is_complete());
+
+        let request = HttpRequest::new(Url::parse("https://files.example/big.bin").unwrap());
+        let resume = download.resume_request(&request);
+        assert_eq!(resume.header("Range"), Some("bytes=4-"));
+        assert_eq!(resume.header("If-Range"), Some("\"v1\""));
+        assert!(resume.new_circuit);
+
+        // A range that doesn't continue the download, or of another version
+        let gap = response(206, &[etag, ("Content-Range", "bytes 5-9/10")], b"56789");
+        assert!(download.apply(gap).is_err());
+        let other = response(
+            206,
+            &[("ETag", "\"v2\""), ("Content-Range", "bytes 4-9/10")],
+            b"456789",
+        );
+        assert!(download.apply(other).is_err());
+
+        let part = response(206, &[etag, ("Content-Range", "bytes 4-6/10")], b"456");
+        download.apply(part).unwrap();
+        assert_eq!(download.received(), 7);
+
+        // The resource changed: the server sends it whole
+        let changed = response(200, &[("ETag", "\"v2\""), ("Content-Length", "3")], b"abc");
+        download.apply(changed).unwrap();
+        assert!(download.is_complete());
+        let whole = download.into_response();
+        assert_eq!(whole.body, b"abc");
+        assert_eq!(whole.retries, 2);
+        assert_eq!(whole.header("Content-Length"), Some("3"));
+    }
+}
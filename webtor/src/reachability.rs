@@ -0,0 +1,244 @@
+//! Relay reachability tracking and temporary blacklisting
+//!
+//! Some relays in the consensus can't be reached from the bridge (overloaded,
+//! firewalled, or just gone), and picking them again fails the circuit the
+//! same way every time. [`ReachabilityTracker`] counts consecutive extension
+//! failures per relay; once a relay reaches the failure threshold it is
+//! blacklisted for a while, and each repeat offence doubles the period up to
+//! a cap. A successful extension clears the relay's record, and failures
+//! older than the base period are forgotten.
+//!
+//! The tracker is shared by the circuit builder, which reports outcomes, and
+//! the [`RelayManager`](crate::relay::RelayManager), which skips blacklisted
+//! relays during selection.
+
+use crate::time::Instant;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tracing::info;
+
+/// Thresholds for blacklisting relays that fail to extend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReachabilityConfig {
+    /// Consecutive failures after which a relay is blacklisted (0 disables tracking)
+    pub failure_threshold: u32,
+    /// First blacklist period in milliseconds; also how long a failure is remembered
+    pub blacklist_ms: u64,
+    /// Longest blacklist period in milliseconds
+    pub max_blacklist_ms: u64,
+}
+
+impl Default for ReachabilityConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 2,
+            blacklist_ms: 5 * 60_000,      // 5 minutes
+            max_blacklist_ms: 60 * 60_000, // 1 hour
+        }
+    }
+}
+
+impl ReachabilityConfig {
+    /// Blacklist period for a relay blacklisted `strikes` times before
+    fn blacklist_period(&self, strikes: u32) -> Duration {
+        let ms = self
+            .blacklist_ms
+            .saturating_mul(1u64 << strikes.min(32))
+            .min(self.max_blacklist_ms.max(self.blacklist_ms));
+        Duration::from_millis(ms)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RelayRecord {
+    consecutive_failures: u32,
+    last_failure: Instant,
+    /// How many times the relay has been blacklisted since its last success
+    strikes: u32,
+    blacklisted_until: Option<Instant>,
+}
+
+/// Shared record of relays that recently failed to extend
+#[derive(Debug, Clone, Default)]
+pub struct ReachabilityTracker {
+    config: ReachabilityConfig,
+    records: Arc<Mutex<HashMap<String, RelayRecord>>>,
+}
+
+impl ReachabilityTracker {
+    pub fn new(config: ReachabilityConfig) -> Self {
+        Self {
+            config,
+            records: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &ReachabilityConfig {
+        &self.config
+    }
+
+    /// Record a failed extension to `fingerprint`
+    pub fn record_failure(&self, fingerprint: &str) {
+        self.record_failure_at(fingerprint, Instant::now());
+    }
+
+    fn record_failure_at(&self, fingerprint: &str, now: Instant) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        let forget_after = Duration::from_millis(self.config.blacklist_ms);
+        let mut records = self.lock();
+        let record = records
+            .entry(normalize(fingerprint))
+            .or_insert(RelayRecord {
+                consecutive_failures: 0,
+                last_failure: now,
+                strikes: 0,
+                blacklisted_until: None,
+            });
+
+        if now.duration_since(record.last_failure) >= forget_after {
+            record.consecutive_failures = 0;
+        }
+        record.consecutive_failures += 1;
+        record.last_failure = now;
+
+        if record.consecutive_failures >= self.config.failure_threshold {
+            let period = self.config.blacklist_period(record.strikes);
+            info!(
+                "Blacklisting relay {} for {:?} after {} failures",
+                fingerprint, period, record.consecutive_failures
+            );
+            record.blacklisted_until = Some(now + period);
+            record.strikes += 1;
+            record.consecutive_failures = 0;
+        }
+    }
+
+    /// Record a successful extension to `fingerprint`, clearing its history
+    pub fn record_success(&self, fingerprint: &str) {
+        self.lock().remove(&normalize(fingerprint));
+    }
+
+    /// Whether `fingerprint` is currently blacklisted
+    pub fn is_blacklisted(&self, fingerprint: &str) -> bool {
+        self.is_blacklisted_at(fingerprint, Instant::now())
+    }
+
+    fn is_blacklisted_at(&self, fingerprint: &str, now: Instant) -> bool {
+        self.lock()
+            .get(&normalize(fingerprint))
+            .is_some_and(|record| is_active(record, now))
+    }
+
+    /// Fingerprints (upper-case hex) of the currently blacklisted relays
+    pub fn blacklisted(&self) -> HashSet<String> {
+        let now = Instant::now();
+        self.lock()
+            .iter()
+            .filter(|(_, record)| is_active(record, now))
+            .map(|(fingerprint, _)| fingerprint.clone())
+            .collect()
+    }
+
+    /// Drop records that no longer affect selection, returning how many were removed
+    ///
+    /// A record is kept while its relay is blacklisted or its last failure is
+    /// still remembered; after that, a fresh failure starts from scratch.
+    pub fn prune(&self) -> usize {
+        self.prune_at(Instant::now())
+    }
+
+    fn prune_at(&self, now: Instant) -> usize {
+        let forget_after = Duration::from_millis(self.config.blacklist_ms);
+        let mut records = self.lock();
+        let before = records.len();
+        records.retain(|_, record| {
+            is_active(record, now) || now.duration_since(record.last_failure) < forget_after
+        });
+        before - records.len()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, RelayRecord>> {
+        self.records.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn is_active(record: &RelayRecord, now: Instant) -> bool {
+    record
+        .blacklisted_until
+        .is_some_and(|until| until.duration_since(now) > Duration::ZERO)
+}
+
+fn normalize(fingerprint: &str) -> String {
+    fingerprint.trim_start_matches('$').to_uppercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FP: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+
+    fn tracker() -> ReachabilityTracker {
+        ReachabilityTracker::new(ReachabilityConfig {
+            failure_threshold: 2,
+            blacklist_ms: 1_000,
+            max_blacklist_ms: 3_000,
+        })
+    }
+
+    #[test]
+    fn test_blacklist_after_threshold_and_expiry() {
+        let tracker = tracker();
+        let t0 = Instant::now();
+
+        tracker.record_failure_at(FP, t0);
+        assert!(!tracker.is_blacklisted_at(FP, t0));
+        tracker.record_failure_at(FP, t0);
+        assert!(tracker.is_blacklisted_at(FP, t0));
+        assert!(tracker.is_blacklisted_at(&FP.to_uppercase(), t0));
+        assert!(!tracker.is_blacklisted_at(FP, t0 + Duration::from_millis(1_000)));
+
+        // A success wipes the record
+        tracker.record_failure_at(FP, t0);
+        tracker.record_success(FP);
+        tracker.record_failure_at(FP, t0);
+        assert!(!tracker.is_blacklisted_at(FP, t0));
+    }
+
+    #[test]
+    fn test_repeat_offences_back_off_up_to_cap() {
+        let tracker = tracker();
+        let mut now = Instant::now();
+        let mut periods = Vec::new();
+        for _ in 0..4 {
+            tracker.record_failure_at(FP, now);
+            tracker.record_failure_at(FP, now);
+            let start = now;
+            while tracker.is_blacklisted_at(FP, now) {
+                now = now + Duration::from_millis(500);
+            }
+            periods.push(now.duration_since(start).as_millis());
+        }
+        assert_eq!(periods, vec![1_000, 2_000, 3_000, 3_000]);
+    }
+
+    #[test]
+    fn test_old_failures_decay_and_prune() {
+        let tracker = tracker();
+        let t0 = Instant::now();
+
+        tracker.record_failure_at(FP, t0);
+        // The first failure is forgotten before the second one arrives
+        let later = t0 + Duration::from_millis(1_500);
+        tracker.record_failure_at(FP, later);
+        assert!(!tracker.is_blacklisted_at(FP, later));
+
+        assert_eq!(tracker.prune_at(later), 0);
+        assert_eq!(tracker.prune_at(later + Duration::from_millis(1_000)), 1);
+    }
+}
@@ -0,0 +1,78 @@
+//! Reconnecting after the bridge drops the channel
+//!
+//! A WebTunnel WebSocket or a Snowflake proxy can go away at any moment,
+//! taking the channel and every circuit on it. With reconnection enabled the
+//! client notices the closed channel straight away, not on the next
+//! request: it connects again through the configured bridges after a delay
+//! that doubles with each failed attempt, and rebuilds as many circuits as
+//! were lost. Each step is reported as a [`ChannelEvent`](crate::events::ChannelEvent).
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// When and how often to reconnect after losing the channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// Reconnect as soon as the channel closes; otherwise the next request
+    /// reconnects
+    pub enabled: bool,
+    /// Delay before the first attempt in milliseconds
+    pub initial_delay_ms: u64,
+    /// Longest delay between attempts in milliseconds
+    pub max_delay_ms: u64,
+    /// Attempts before giving up, or null to keep trying
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            initial_delay_ms: 1_000,
+            max_delay_ms: 60_000, // 1 minute
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Delay before attempt `attempt` (counting from 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let ms = self
+            .initial_delay_ms
+            .saturating_mul(1u64 << attempt.saturating_sub(1).min(32))
+            .min(self.max_delay_ms.max(self.initial_delay_ms));
+        Duration::from_millis(ms)
+    }
+
+    /// Whether attempt `attempt` (counting from 1) may be made
+    pub fn allows(&self, attempt: u32) -> bool {
+        self.max_attempts.is_none_or(|max| attempt <= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_doubles_up_to_cap() {
+        let config = ReconnectConfig::default();
+        assert_eq!(config.delay(1), Duration::from_secs(1));
+        assert_eq!(config.delay(2), Duration::from_secs(2));
+        assert_eq!(config.delay(4), Duration::from_secs(8));
+        assert_eq!(config.delay(100), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_max_attempts() {
+        assert!(ReconnectConfig::default().allows(u32::MAX));
+        let config = ReconnectConfig {
+            max_attempts: Some(3),
+            ..ReconnectConfig::default()
+        };
+        assert!(config.allows(3));
+        assert!(!config.allows(4));
+    }
+}
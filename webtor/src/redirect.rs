@@ -0,0 +1,230 @@
+//! Redirect handling for HTTP requests
+//!
+//! [`RedirectPolicy`] decides whether a 301, 302, 303, 307 or 308 response is
+//! followed and what the next request looks like. Methods are rewritten the
+//! way browsers do: 303 turns anything but HEAD into GET, 301 and 302 turn
+//! POST into GET, and 307/308 repeat the request unchanged. A body dropped
+//! by a rewrite takes its `Content-*` headers with it, and credentials are
+//! not sent on to another origin.
+//!
+//! Redirects from HTTPS to HTTP are refused unless explicitly allowed, since
+//! the rest of the exchange would then be readable by the exit.
+
+use crate::error::{Result, TorError};
+use crate::http::{HttpRequest, HttpResponse};
+use http::Method;
+use serde::{Deserialize, Serialize};
+
+/// Default limit on redirects followed for one request, as in browsers
+pub const DEFAULT_MAX_REDIRECTS: u32 = 20;
+
+/// Headers that carry credentials and are dropped on cross-origin redirects
+const CREDENTIAL_HEADERS: &[&str] = &["Authorization", "Cookie", "Proxy-Authorization"];
+
+/// How redirect responses are handled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RedirectPolicy {
+    /// Follow redirects; when false the redirect response itself is returned
+    pub follow: bool,
+    /// Redirects followed before the request fails
+    pub max_redirects: u32,
+    /// Follow redirects to another origin; when false the redirect response
+    /// is returned instead
+    pub allow_cross_origin: bool,
+    /// Follow redirects from HTTPS to plain HTTP instead of failing
+    pub allow_downgrade: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self {
+            follow: true,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            allow_cross_origin: true,
+            allow_downgrade: false,
+        }
+    }
+}
+
+impl RedirectPolicy {
+    /// Return every redirect response as is
+    pub fn none() -> Self {
+        Self {
+            follow: false,
+            ..Self::default()
+        }
+    }
+
+    /// Follow at most `max_redirects` redirects
+    pub fn limited(max_redirects: u32) -> Self {
+        Self {
+            max_redirects,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_cross_origin(mut self, allowed: bool) -> Self {
+        self.allow_cross_origin = allowed;
+        self
+    }
+
+    pub fn with_downgrade(mut self, allowed: bool) -> Self {
+        self.allow_downgrade = allowed;
+        self
+    }
+
+    /// The request to send after `request` got `response`, having already
+    /// followed `redirects` redirects
+    ///
+    /// `None` means `response` is the final response.
+    pub fn next_request(
+        &self,
+        request: &HttpRequest,
+        response: &HttpResponse,
+        redirects: u32,
+    ) -> Result<Option<HttpRequest>> {
+        if !self.follow || !is_redirect(response.status) {
+            return Ok(None);
+        }
+        let Some(location) = response.header("location") else {
+            return Ok(None);
+        };
+
+        let target = request
+            .url
+            .join(location)
+            .map_err(|e| TorError::http_request(format!("Invalid redirect location: {}", e)))?;
+        if !matches!(target.scheme(), "http" | "https") {
+            return Err(TorError::http_request(format!(
+                "Refusing redirect to {} URL",
+                target.scheme()
+            )));
+        }
+        if request.url.scheme() == "https" && target.scheme() == "http" && !self.allow_downgrade {
+            return Err(TorError::http_request(format!(
+                "Refusing redirect from HTTPS to {}",
+                target
+            )));
+        }
+        let cross_origin = target.origin() != request.url.origin();
+        if cross_origin && !self.allow_cross_origin {
+            return Ok(None);
+        }
+        if redirects >= self.max_redirects {
+            return Err(TorError::http_request(format!(
+                "Too many redirects (limit {})",
+                self.max_redirects
+            )));
+        }
+
+        let mut next = request.clone();
+        next.url = target;
+        let becomes_get = match response.status {
+            303 => request.method != Method::HEAD,
+            301 | 302 => request.method == Method::POST,
+            _ => false,
+        };
+        if becomes_get {
+            next.method = Method::GET;
+            next.body = None;
+            next.headers
+                .retain(|key, _| !key.to_ascii_lowercase().starts_with("content-"));
+        }
+        if cross_origin {
+            for header in CREDENTIAL_HEADERS {
+                next = next.without_header(header);
+            }
+        }
+        Ok(Some(next))
+    }
+}
+
+/// Whether `status` is a redirect this module follows
+pub fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    fn redirect(status: u16, location: &str) -> HttpResponse {
+        let mut headers = http::HeaderMap::new();
+        headers.insert("location", location.parse().unwrap());
+        HttpResponse {
+            status,
+            status_text: String::new(),
+            version: http::Version::HTTP_11,
+            headers,
+            trailers: http::HeaderMap::new(),
+            body: Vec::new(),
+            retries: 0,
+            url: Url::parse("https://a.example/form").unwrap(),
+            request_id: String::new(),
+        }
+    }
+
+    fn post() -> HttpRequest {
+        HttpRequest::new(Url::parse("https://a.example/form").unwrap())
+            .with_method(Method::POST)
+            .with_header("Authorization", "Bearer t")
+            .with_json_body(&serde_json::json!({"a": 1}))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_methods_are_rewritten_like_browsers() {
+        let policy = RedirectPolicy::default();
+
+        let next = policy
+            .next_request(&post(), &redirect(303, "/done"), 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(next.url.as_str(), "https://a.example/done");
+        assert_eq!(next.method, Method::GET);
+        assert!(next.body.is_none() && next.header("content-type").is_none());
+        assert_eq!(next.header("authorization"), Some("Bearer t"));
+
+        let next = policy
+            .next_request(&post(), &redirect(307, "https://b.example/form"), 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(next.method, Method::POST);
+        assert!(next.body.is_some());
+        assert!(next.header("authorization").is_none());
+
+        assert!(policy
+            .next_request(&post(), &redirect(200, "/elsewhere"), 0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_policy_limits() {
+        let response = redirect(302, "https://b.example/");
+        assert!(RedirectPolicy::none()
+            .next_request(&post(), &response, 0)
+            .unwrap()
+            .is_none());
+        assert!(RedirectPolicy::default()
+            .with_cross_origin(false)
+            .next_request(&post(), &response, 0)
+            .unwrap()
+            .is_none());
+        assert!(RedirectPolicy::limited(2)
+            .next_request(&post(), &response, 2)
+            .is_err());
+
+        let downgrade = redirect(301, "http://a.example/");
+        assert!(RedirectPolicy::default()
+            .next_request(&post(), &downgrade, 0)
+            .is_err());
+        assert!(RedirectPolicy::default()
+            .with_downgrade(true)
+            .next_request(&post(), &downgrade, 0)
+            .unwrap()
+            .is_some());
+    }
+}
@@ -1,15 +1,23 @@
 //! Tor relay management and selection
 
 use crate::error::{Result, TorError};
+use crate::geoip::{GeoIpDb, UNKNOWN_COUNTRY};
+use crate::hsdir::HsDirParams;
+use crate::reachability::ReachabilityTracker;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
 use tor_linkspec::OwnedCircTarget;
 use tor_llcrypto::pk::{
     curve25519::PublicKey as Curve25519PublicKey, ed25519::Ed25519Identity, rsa::RsaIdentity,
 };
+use tor_netdoc::types::policy::PortPolicy;
 use tor_protover::Protocols;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 /// Tor relay information
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +38,26 @@ pub struct Relay {
     pub ed25519_identity: Option<String>, // Hex encoded
     #[serde(default)]
     pub ntor_onion_key: Option<String>, // Hex encoded
+
+    /// IPv4 exit policy summary from the microdescriptor (None if unknown)
+    #[serde(default, with = "port_policy_serde")]
+    pub exit_policy: Option<Arc<PortPolicy>>,
+
+    /// Hex RSA fingerprints of relays this relay declares as family
+    #[serde(default)]
+    pub family: HashSet<String>,
+
+    /// Family identifiers (happy families, e.g. `ed25519:...`)
+    #[serde(default)]
+    pub family_ids: HashSet<String>,
+
+    /// Upper-case country code from GeoIP (None if no table is loaded or the address is unlisted)
+    #[serde(default)]
+    pub country: Option<String>,
+
+    /// Subprotocol versions from the consensus `pr` line (None if unknown)
+    #[serde(default)]
+    pub protocols: Option<String>,
 }
 
 impl Relay {
@@ -55,9 +83,117 @@ impl Relay {
             microdescriptor_hash: String::new(),
             ed25519_identity: None,
             ntor_onion_key: Some(ntor_onion_key),
+            exit_policy: None,
+            family: HashSet::new(),
+            family_ids: HashSet::new(),
+            country: None,
+            protocols: None,
         }
     }
 
+    /// Whether both relays are in the same /16 (IPv4) or /32 (IPv6) network
+    ///
+    /// Relays with unknown (unspecified or unparsable) addresses never match.
+    pub fn same_subnet(&self, other: &Relay) -> bool {
+        use std::net::IpAddr;
+        match (
+            self.address.parse::<IpAddr>(),
+            other.address.parse::<IpAddr>(),
+        ) {
+            (Ok(a), Ok(b)) if a.is_unspecified() || b.is_unspecified() => false,
+            (Ok(IpAddr::V4(a)), Ok(IpAddr::V4(b))) => a.octets()[..2] == b.octets()[..2],
+            (Ok(IpAddr::V6(a)), Ok(IpAddr::V6(b))) => a.segments()[..2] == b.segments()[..2],
+            _ => false,
+        }
+    }
+
+    /// Whether both relays belong to the same family
+    ///
+    /// Per the path spec, that means they list each other as family members,
+    /// or they share a family identifier.
+    pub fn same_family(&self, other: &Relay) -> bool {
+        let lists = |a: &Relay, b: &Relay| a.family.contains(&b.fingerprint.to_lowercase());
+        (lists(self, other) && lists(other, self))
+            || !self.family_ids.is_disjoint(&other.family_ids)
+    }
+
+    /// Whether two relays must not appear in the same circuit
+    pub fn is_related_to(&self, other: &Relay) -> bool {
+        self.fingerprint.eq_ignore_ascii_case(&other.fingerprint)
+            || self.same_subnet(other)
+            || self.same_family(other)
+    }
+
+    /// Advertised subprotocol versions; unknown or unparsable ones count as none
+    pub fn protovers(&self) -> Protocols {
+        match self.protocols.as_deref().map(str::parse::<Protocols>) {
+            Some(Ok(protocols)) => protocols,
+            Some(Err(e)) => {
+                debug!("Ignoring unparsable protocols of {}: {}", self.nickname, e);
+                Protocols::default()
+            }
+            None => Protocols::default(),
+        }
+    }
+
+    /// Whether this relay accepts the ntor v3 handshake (`Relay=4`)
+    ///
+    /// Circuit creation and extension use ntor v3 with such relays, so
+    /// handshake extensions like the congestion control request can be sent.
+    pub fn supports_ntor_v3(&self) -> bool {
+        self.protovers()
+            .supports_named_subver(tor_protover::named::RELAY_NTORV3)
+    }
+
+    /// Name of the handshake used to add this relay to a circuit
+    pub fn handshake_name(&self) -> &'static str {
+        if self.supports_ntor_v3() {
+            "ntor v3"
+        } else {
+            "ntor"
+        }
+    }
+
+    /// Whether this relay can negotiate congestion control (`FlowCtrl=2`)
+    ///
+    /// The request travels in an ntor v3 extension, so the relay must accept
+    /// that handshake too.
+    pub fn supports_congestion_control(&self) -> bool {
+        self.supports_ntor_v3()
+            && self
+                .protovers()
+                .supports_named_subver(tor_protover::named::FLOWCTRL_CC)
+    }
+
+    /// Whether this relay accepts stream data before it has sent CONNECTED
+    ///
+    /// Optimistic data arrived in Tor 0.2.3.1; advertising `Relay=2` (ntor)
+    /// means a newer version, so it stands in for the version check.
+    pub fn supports_optimistic_data(&self) -> bool {
+        self.protovers()
+            .supports_named_subver(tor_protover::named::RELAY_NTOR)
+    }
+
+    /// Whether this relay can join conflux (multi-path) circuits as the exit
+    pub fn supports_conflux(&self) -> bool {
+        self.protovers()
+            .supports_named_subver(tor_protover::named::CONFLUX_BASE)
+    }
+
+    /// Country code, or `"??"` when unknown
+    pub fn country_or_unknown(&self) -> &str {
+        self.country.as_deref().unwrap_or(UNKNOWN_COUNTRY)
+    }
+
+    /// Whether this relay's exit policy summary allows connections to `port`
+    ///
+    /// Relays without a known policy are treated as rejecting every port.
+    pub fn allows_exit_port(&self, port: u16) -> bool {
+        self.exit_policy
+            .as_ref()
+            .is_some_and(|policy| policy.allows_port(port))
+    }
+
     /// Convert to OwnedCircTarget for circuit creation
     pub fn as_circ_target(&self) -> Result<OwnedCircTarget> {
         let mut builder = OwnedCircTarget::builder();
@@ -125,8 +261,9 @@ impl Relay {
             return Err(TorError::Configuration("Invalid ntor key hex".to_string()));
         }
 
-        // Protocols
-        builder.protocols(Protocols::default());
+        // Protocols decide the handshake (ntor v3 with Relay=4) and whether
+        // congestion control can be negotiated (FlowCtrl=2)
+        builder.protocols(self.protovers());
 
         builder
             .build()
@@ -142,6 +279,16 @@ pub struct RelayCriteria {
     pub exclude_fingerprints: HashSet<String>,
     pub min_bandwidth: u64,
     pub max_selection: usize,
+    /// Destination port the relay's exit policy must allow
+    pub exit_port: Option<u16>,
+    /// Country codes to avoid; `"??"` excludes relays whose country is unknown
+    pub exclude_countries: HashSet<String>,
+    /// Relays already in the path; candidates related to any of them are skipped
+    pub exclude_related: Vec<Relay>,
+    /// If set, only these fingerprints (upper-case hex) may be selected
+    pub only_fingerprints: Option<HashSet<String>>,
+    /// If set, only relays in this node list may be selected
+    pub only_nodes: Option<NodeSet>,
 }
 
 impl Default for RelayCriteria {
@@ -152,6 +299,11 @@ impl Default for RelayCriteria {
             exclude_fingerprints: HashSet::new(),
             min_bandwidth: 0,
             max_selection: 10,
+            exit_port: None,
+            exclude_countries: HashSet::new(),
+            exclude_related: Vec::new(),
+            only_fingerprints: None,
+            only_nodes: None,
         }
     }
 }
@@ -192,47 +344,331 @@ impl RelayCriteria {
         self.max_selection = max;
         self
     }
+
+    pub fn with_exit_port(mut self, port: u16) -> Self {
+        self.exit_port = Some(port);
+        self
+    }
+
+    /// Only select among the given relays (such as pinned layer-2 guards)
+    pub fn among_fingerprints<I, S>(mut self, fingerprints: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.only_fingerprints = Some(
+            fingerprints
+                .into_iter()
+                .map(|fp| fp.as_ref().to_uppercase())
+                .collect(),
+        );
+        self
+    }
+
+    /// Only select relays in `nodes` (such as `ExitNodes`)
+    pub fn within_nodes(mut self, nodes: NodeSet) -> Self {
+        self.only_nodes = Some(nodes);
+        self
+    }
+
+    /// Skip relays sharing a subnet or family with `relay`
+    pub fn not_related_to(mut self, relay: &Relay) -> Self {
+        self.exclude_related.push(relay.clone());
+        self
+    }
+
+    pub fn without_countries<I, S>(mut self, countries: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.exclude_countries.extend(
+            countries
+                .into_iter()
+                .map(|cc| crate::geoip::normalize_country_code(cc.as_ref())),
+        );
+        self
+    }
+}
+
+/// Filter for inspecting the relay list (see [`RelayManager::query`])
+///
+/// Unlike [`RelayCriteria`], which drives path selection, a query only
+/// narrows down relays for display; every condition left unset matches all.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RelayQuery {
+    /// Exact fingerprint, case-insensitive, with or without a leading `$`
+    pub fingerprint: Option<String>,
+    /// Case-insensitive substring of the nickname
+    pub nickname: Option<String>,
+    /// Flags the relay must all have
+    pub flags: HashSet<String>,
+    pub min_bandwidth: u64,
+    /// Country code; `"??"` matches relays whose country is unknown
+    pub country: Option<String>,
+    /// Maximum number of results
+    pub limit: Option<usize>,
+}
+
+impl RelayQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_fingerprint(mut self, fingerprint: &str) -> Self {
+        self.fingerprint = Some(fingerprint.to_string());
+        self
+    }
+
+    pub fn with_nickname(mut self, nickname: &str) -> Self {
+        self.nickname = Some(nickname.to_string());
+        self
+    }
+
+    pub fn with_flag(mut self, flag: &str) -> Self {
+        self.flags.insert(flag.to_string());
+        self
+    }
+
+    pub fn with_min_bandwidth(mut self, bandwidth: u64) -> Self {
+        self.min_bandwidth = bandwidth;
+        self
+    }
+
+    pub fn with_country(mut self, country: &str) -> Self {
+        self.country = Some(country.to_string());
+        self
+    }
+
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Whether `relay` satisfies every condition of the query
+    pub fn matches(&self, relay: &Relay) -> bool {
+        if let Some(fingerprint) = &self.fingerprint {
+            if !relay
+                .fingerprint
+                .eq_ignore_ascii_case(fingerprint.trim_start_matches('$'))
+            {
+                return false;
+            }
+        }
+        if let Some(nickname) = &self.nickname {
+            if !relay
+                .nickname
+                .to_lowercase()
+                .contains(&nickname.to_lowercase())
+            {
+                return false;
+            }
+        }
+        if let Some(country) = &self.country {
+            if relay.country_or_unknown() != crate::geoip::normalize_country_code(country) {
+                return false;
+            }
+        }
+        self.flags.iter().all(|flag| relay.flags.contains(flag))
+            && relay.bandwidth >= self.min_bandwidth
+    }
+}
+
+/// Relays named in a torrc-style node list, as in `ExitNodes` or
+/// `ExcludeNodes`
+///
+/// Entries are fingerprints (with or without `$`, any `~nickname` or
+/// `=nickname` suffix ignored), `{cc}` country codes (matched against GeoIP,
+/// `{??}` for unknown) and nicknames, compared case-insensitively. Address
+/// patterns are not supported.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeSet {
+    fingerprints: HashSet<String>,
+    countries: HashSet<String>,
+    nicknames: HashSet<String>,
+}
+
+impl NodeSet {
+    /// Parse node list entries; each may itself be a comma-separated list
+    pub fn parse<I, S>(entries: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut nodes = Self::default();
+        for entry in entries {
+            for spec in entry.as_ref().split(',').map(str::trim) {
+                if spec.is_empty() {
+                    continue;
+                }
+                nodes.insert(spec)?;
+            }
+        }
+        Ok(nodes)
+    }
+
+    fn insert(&mut self, spec: &str) -> Result<()> {
+        if spec.starts_with('{') && spec.ends_with('}') {
+            self.countries
+                .insert(crate::geoip::normalize_country_code(spec));
+            return Ok(());
+        }
+        let id = spec
+            .trim_start_matches('$')
+            .split(['~', '='])
+            .next()
+            .unwrap_or_default();
+        if id.len() == 40 && id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            self.fingerprints.insert(id.to_ascii_uppercase());
+        } else if !spec.starts_with('$')
+            && (1..=19).contains(&spec.len())
+            && spec.bytes().all(|b| b.is_ascii_alphanumeric())
+        {
+            self.nicknames.insert(spec.to_ascii_lowercase());
+        } else {
+            return Err(TorError::configuration(format!(
+                "Unsupported node specification: {}",
+                spec
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fingerprints.is_empty() && self.countries.is_empty() && self.nicknames.is_empty()
+    }
+
+    /// Whether any entry names `relay`
+    pub fn contains(&self, relay: &Relay) -> bool {
+        self.fingerprints
+            .contains(&relay.fingerprint.to_ascii_uppercase())
+            || self.countries.contains(relay.country_or_unknown())
+            || self
+                .nicknames
+                .contains(&relay.nickname.to_ascii_lowercase())
+    }
+}
+
+/// Shared random source for path selection
+///
+/// Seeding it makes relay choices reproducible for a given relay list, so
+/// integration tests and simulations can replay exact circuits.
+#[derive(Clone)]
+pub struct SelectionRng(Arc<Mutex<Box<dyn RngCore + Send>>>);
+
+impl SelectionRng {
+    /// Deterministic generator seeded with `seed`
+    pub fn from_seed(seed: u64) -> Self {
+        Self::from_rng(StdRng::seed_from_u64(seed))
+    }
+
+    /// Use a caller-provided generator
+    pub fn from_rng<R: RngCore + Send + 'static>(rng: R) -> Self {
+        Self(Arc::new(Mutex::new(Box::new(rng))))
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+        let mut rng = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        f(rng.as_mut())
+    }
+}
+
+impl fmt::Debug for SelectionRng {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SelectionRng")
+    }
 }
 
 /// Relay manager for selecting appropriate relays
 pub struct RelayManager {
     pub relays: Vec<Relay>,
+    /// HSDir ring parameters from the consensus the relays came from
+    pub hsdir_params: Option<HsDirParams>,
+    geoip: Option<Arc<GeoIpDb>>,
+    reachability: Option<ReachabilityTracker>,
+    rng: Option<SelectionRng>,
+    /// Relays never selected for any position (`ExcludeNodes`)
+    excluded: NodeSet,
 }
 
 impl RelayManager {
     pub fn new(relays: Vec<Relay>) -> Self {
-        Self { relays }
+        Self {
+            relays,
+            hsdir_params: None,
+            geoip: None,
+            reachability: None,
+            rng: None,
+            excluded: NodeSet::default(),
+        }
     }
 
-    /// Select relays matching the given criteria
-    pub fn select_relays(&self, criteria: &RelayCriteria) -> Result<Vec<Relay>> {
-        let mut candidates: Vec<&Relay> = self
-            .relays
-            .iter()
-            .filter(|relay| {
-                // Check excluded fingerprints
-                if criteria.exclude_fingerprints.contains(&relay.fingerprint) {
-                    return false;
-                }
+    /// Draw relay choices from `rng` instead of the thread RNG
+    pub fn with_rng(mut self, rng: SelectionRng) -> Self {
+        self.rng = Some(rng);
+        self
+    }
 
-                // Check required flags
-                for flag in &criteria.need_flags {
-                    if !relay.flags.contains(flag) {
-                        return false;
-                    }
-                }
+    /// Skip relays that `tracker` has blacklisted after repeated extension failures
+    pub fn with_reachability(mut self, tracker: ReachabilityTracker) -> Self {
+        self.reachability = Some(tracker);
+        self
+    }
 
-                // Check excluded flags
-                for flag in &criteria.exclude_flags {
-                    if relay.flags.contains(flag) {
-                        return false;
-                    }
-                }
+    /// Never select relays in `nodes`, whatever the criteria
+    pub fn with_excluded_nodes(mut self, nodes: NodeSet) -> Self {
+        self.excluded = nodes;
+        self
+    }
 
-                // Check bandwidth
-                relay.bandwidth >= criteria.min_bandwidth
-            })
-            .collect();
+    /// Whether `relay` is on the exclusion list
+    pub fn is_excluded(&self, relay: &Relay) -> bool {
+        self.excluded.contains(relay)
+    }
+
+    /// Annotate relays (current and future) with countries from `geoip`
+    pub fn with_geoip(mut self, geoip: Arc<GeoIpDb>) -> Self {
+        self.geoip = Some(geoip);
+        self.annotate_countries();
+        self
+    }
+
+    fn annotate_countries(&mut self) {
+        let Some(geoip) = &self.geoip else {
+            return;
+        };
+        for relay in &mut self.relays {
+            relay.country = geoip.country_for_address(&relay.address);
+        }
+    }
+
+    /// Select relays matching the given criteria
+    ///
+    /// Blacklisted relays are skipped unless nothing else matches, so a burst
+    /// of failures can't leave the client with no path at all.
+    pub fn select_relays(&self, criteria: &RelayCriteria) -> Result<Vec<Relay>> {
+        let blacklisted = self
+            .reachability
+            .as_ref()
+            .map(|tracker| tracker.blacklisted())
+            .unwrap_or_default();
+        let mut candidates = self.candidates(criteria);
+        if !blacklisted.is_empty() {
+            let reachable: Vec<&Relay> = candidates
+                .iter()
+                .copied()
+                .filter(|relay| !blacklisted.contains(&relay.fingerprint.to_uppercase()))
+                .collect();
+            if reachable.is_empty() && !candidates.is_empty() {
+                warn!(
+                    "All {} matching relays are blacklisted; ignoring the blacklist",
+                    candidates.len()
+                );
+            } else {
+                candidates = reachable;
+            }
+        }
 
         if candidates.is_empty() {
             return Err(TorError::relay_selection(
@@ -260,6 +696,76 @@ impl RelayManager {
         Ok(selected)
     }
 
+    /// Relays satisfying `criteria`, in consensus order
+    fn candidates(&self, criteria: &RelayCriteria) -> Vec<&Relay> {
+        self.relays
+            .iter()
+            .filter(|relay| {
+                // Check excluded fingerprints
+                if criteria.exclude_fingerprints.contains(&relay.fingerprint)
+                    || self.is_excluded(relay)
+                {
+                    return false;
+                }
+
+                // Check allowed node list
+                if let Some(nodes) = &criteria.only_nodes {
+                    if !nodes.contains(relay) {
+                        return false;
+                    }
+                }
+
+                // Check allowed fingerprints
+                if let Some(allowed) = &criteria.only_fingerprints {
+                    if !allowed.contains(&relay.fingerprint.to_uppercase()) {
+                        return false;
+                    }
+                }
+
+                // Check required flags
+                for flag in &criteria.need_flags {
+                    if !relay.flags.contains(flag) {
+                        return false;
+                    }
+                }
+
+                // Check excluded flags
+                for flag in &criteria.exclude_flags {
+                    if relay.flags.contains(flag) {
+                        return false;
+                    }
+                }
+
+                // Check subnet and family against relays already in the path
+                if criteria
+                    .exclude_related
+                    .iter()
+                    .any(|other| relay.is_related_to(other))
+                {
+                    return false;
+                }
+
+                // Check country
+                if criteria
+                    .exclude_countries
+                    .contains(relay.country_or_unknown())
+                {
+                    return false;
+                }
+
+                // Check exit policy
+                if let Some(port) = criteria.exit_port {
+                    if !relay.allows_exit_port(port) {
+                        return false;
+                    }
+                }
+
+                // Check bandwidth
+                relay.bandwidth >= criteria.min_bandwidth
+            })
+            .collect()
+    }
+
     /// Select a single relay randomly from candidates
     pub fn select_relay(&self, criteria: &RelayCriteria) -> Result<Relay> {
         use rand::seq::SliceRandom;
@@ -272,11 +778,17 @@ impl RelayManager {
 
         // Select a random relay from the top candidates to avoid deterministic paths
         // and ensure load balancing
-        let mut rng = rand::thread_rng();
-        candidates
-            .choose(&mut rng)
-            .cloned()
-            .ok_or_else(|| TorError::relay_selection("Failed to choose random relay"))
+        let chosen = match &self.rng {
+            Some(rng) => rng.with(|rng| candidates.choose(rng).cloned()),
+            None => candidates.choose(&mut rand::thread_rng()).cloned(),
+        };
+        chosen.ok_or_else(|| TorError::relay_selection("Failed to choose random relay"))
+    }
+
+    /// Whether any usable exit relay allows connections to `port`
+    pub fn has_exit_for_port(&self, port: u16) -> bool {
+        self.select_relays(&selection::exit_relays().with_exit_port(port))
+            .is_ok()
     }
 
     /// Get relay by fingerprint
@@ -286,6 +798,29 @@ impl RelayManager {
             .find(|relay| relay.fingerprint == fingerprint)
     }
 
+    /// Relays matching `query`, highest bandwidth first
+    pub fn query(&self, query: &RelayQuery) -> Vec<&Relay> {
+        let mut matches: Vec<&Relay> = self
+            .relays
+            .iter()
+            .filter(|relay| query.matches(relay))
+            .collect();
+        matches.sort_by_key(|relay| std::cmp::Reverse(relay.bandwidth));
+        if let Some(limit) = query.limit {
+            matches.truncate(limit);
+        }
+        matches
+    }
+
+    /// Number of relays carrying each flag
+    pub fn count_by_flag(&self) -> BTreeMap<String, usize> {
+        let mut counts = BTreeMap::new();
+        for flag in self.relays.iter().flat_map(|relay| &relay.flags) {
+            *counts.entry(flag.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
     /// Update relay list from consensus
     pub fn update_relays(&mut self, new_relays: Vec<Relay>) {
         info!(
@@ -294,6 +829,7 @@ impl RelayManager {
             new_relays.len()
         );
         self.relays = new_relays;
+        self.annotate_countries();
     }
 }
 
@@ -312,6 +848,33 @@ pub mod flags {
     pub const V2DIR: &str = "V2Dir";
 }
 
+/// Serialize exit policies as their summary string (e.g. "accept 80,443")
+mod port_policy_serde {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+    use std::result::Result;
+
+    pub fn serialize<S>(policy: &Option<Arc<PortPolicy>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        policy.as_ref().map(|p| p.to_string()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Arc<PortPolicy>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| {
+                s.parse::<PortPolicy>()
+                    .map(PortPolicy::intern)
+                    .map_err(serde::de::Error::custom)
+            })
+            .transpose()
+    }
+}
+
 /// Helper functions for common relay selections
 pub mod selection {
     use super::*;
@@ -357,6 +920,34 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_protocols_from_consensus_reach_circ_target() {
+        use tor_linkspec::CircTarget;
+        use tor_protover::named;
+
+        let mut relay = create_test_relay(&"AB".repeat(20), vec!["Exit"]);
+        assert!(!relay.supports_congestion_control());
+        assert!(!relay.supports_optimistic_data());
+        assert_eq!(relay.handshake_name(), "ntor");
+
+        // Congestion control can't be negotiated without ntor v3
+        relay.protocols = Some("FlowCtrl=1-2 Relay=1-3".to_string());
+        assert!(relay.supports_optimistic_data());
+        assert!(!relay.supports_ntor_v3());
+        assert!(!relay.supports_congestion_control());
+
+        relay.protocols = Some("FlowCtrl=1-2 Relay=1-4".to_string());
+        assert!(relay.supports_congestion_control());
+        assert_eq!(relay.handshake_name(), "ntor v3");
+        let target = relay.as_circ_target().unwrap();
+        assert!(target
+            .protovers()
+            .supports_named_subver(named::RELAY_NTORV3));
+
+        relay.protocols = Some("not a protocol list".to_string());
+        assert!(!relay.supports_congestion_control());
+    }
+
     #[test]
     fn test_relay_selection() {
         let relays = vec![
@@ -384,6 +975,250 @@ mod tests {
         assert!(!exit_relays[0].flags.contains(flags::BAD_EXIT));
     }
 
+    #[test]
+    fn test_exit_selection_by_port() {
+        let mut web = create_test_relay("web", vec![flags::FAST, flags::STABLE, flags::EXIT]);
+        web.exit_policy = Some(Arc::new("accept 80,443".parse().unwrap()));
+        let mut irc = create_test_relay("irc", vec![flags::FAST, flags::STABLE, flags::EXIT]);
+        irc.exit_policy = Some(Arc::new("reject 1-6666,6668-65535".parse().unwrap()));
+        let unknown = create_test_relay("unknown", vec![flags::FAST, flags::STABLE, flags::EXIT]);
+
+        let manager = RelayManager::new(vec![web, irc, unknown]);
+
+        let https = manager
+            .select_relays(&selection::exit_relays().with_exit_port(443))
+            .unwrap();
+        assert_eq!(https.len(), 1);
+        assert_eq!(https[0].fingerprint, "web");
+
+        let ircd = manager
+            .select_relay(&selection::exit_relays().with_exit_port(6667))
+            .unwrap();
+        assert_eq!(ircd.fingerprint, "irc");
+
+        assert!(!manager.has_exit_for_port(22));
+        assert!(manager.has_exit_for_port(80));
+    }
+
+    #[test]
+    fn test_exit_policy_serde_round_trip() {
+        let mut relay = create_test_relay("web", vec![flags::EXIT]);
+        relay.exit_policy = Some(Arc::new("accept 80,443".parse().unwrap()));
+
+        let json = serde_json::to_string(&relay).unwrap();
+        assert!(json.contains("\"exit_policy\":\"accept 80,443\""));
+        let decoded: Relay = serde_json::from_str(&json).unwrap();
+        assert!(decoded.allows_exit_port(443));
+        assert!(!decoded.allows_exit_port(22));
+    }
+
+    #[test]
+    fn test_relatedness() {
+        let relay = |fp: &str, addr: &str| {
+            let mut r = create_test_relay(fp, vec![]);
+            r.address = addr.to_string();
+            r
+        };
+
+        assert!(relay("a", "10.1.2.3").same_subnet(&relay("b", "10.1.200.1")));
+        assert!(!relay("a", "10.1.2.3").same_subnet(&relay("b", "10.2.2.3")));
+        assert!(relay("a", "2001:db8::1").same_subnet(&relay("b", "2001:db8:9::1")));
+        assert!(!relay("a", "2001:db8::1").same_subnet(&relay("b", "2001:db9::1")));
+        assert!(!relay("a", "0.0.0.0").same_subnet(&relay("b", "0.0.0.1")));
+        assert!(!relay("a", "10.1.2.3").same_subnet(&relay("b", "::ffff:10.1.2.3")));
+
+        let mut a = relay("aa", "10.0.0.1");
+        let mut b = relay("BB", "10.9.0.1");
+        a.family.insert("bb".to_string());
+        assert!(!a.same_family(&b));
+        b.family.insert("aa".to_string());
+        assert!(a.same_family(&b));
+        assert!(a.is_related_to(&relay("AA", "192.0.2.1")));
+    }
+
+    #[test]
+    fn test_exit_selection_excludes_countries() {
+        let exit = vec![flags::FAST, flags::STABLE, flags::EXIT];
+        let mut au = create_test_relay("au", exit.clone());
+        au.address = "1.0.0.1".to_string();
+        let mut us = create_test_relay("us", exit.clone());
+        us.address = "8.8.8.8".to_string();
+        let unlisted = create_test_relay("unlisted", exit);
+
+        let geoip = GeoIpDb::parse(
+            "16777216,16777471,AU
+134744064,134744319,US",
+        )
+        .unwrap();
+        let manager = RelayManager::new(vec![au, us, unlisted]).with_geoip(Arc::new(geoip));
+        assert_eq!(
+            manager.get_relay("us").unwrap().country.as_deref(),
+            Some("US")
+        );
+        assert_eq!(manager.get_relay("unlisted").unwrap().country, None);
+
+        let fingerprints = |criteria: RelayCriteria| {
+            let mut fps: Vec<String> = manager
+                .select_relays(&criteria)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.fingerprint)
+                .collect();
+            fps.sort();
+            fps
+        };
+        assert_eq!(
+            fingerprints(selection::exit_relays().without_countries(["{us}"])),
+            vec!["au", "unlisted"]
+        );
+        assert_eq!(
+            fingerprints(selection::exit_relays().without_countries(["US", "??"])),
+            vec!["au"]
+        );
+    }
+
+    #[test]
+    fn test_node_sets() {
+        let exit = vec![flags::FAST, flags::STABLE, flags::EXIT];
+        let listed = create_test_relay(&"AB".repeat(20), exit.clone());
+        let mut au = create_test_relay("au", exit.clone());
+        au.country = Some("AU".to_string());
+        let mut named = create_test_relay("named", exit);
+        named.nickname = "Named".to_string();
+
+        let nodes = NodeSet::parse([format!("${}~nick, {{au}}", "ab".repeat(20))]).unwrap();
+        assert!(nodes.contains(&listed) && nodes.contains(&au));
+        assert!(!nodes.contains(&named));
+        assert!(NodeSet::parse(["TEST_named"]).is_err());
+        assert!(NodeSet::parse(["10.0.0.0/8"]).is_err());
+        assert!(NodeSet::parse(["", " , "]).unwrap().is_empty());
+
+        let manager = RelayManager::new(vec![listed, au, named])
+            .with_excluded_nodes(NodeSet::parse(["{au}"]).unwrap());
+        let selected = |criteria: RelayCriteria| {
+            let mut fps: Vec<String> = manager
+                .select_relays(&criteria)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.fingerprint)
+                .collect();
+            fps.sort();
+            fps
+        };
+        assert_eq!(
+            selected(selection::exit_relays()),
+            vec!["AB".repeat(20), "named".to_string()]
+        );
+        let within = NodeSet::parse(["named", "{au}"]).unwrap();
+        assert_eq!(
+            selected(selection::exit_relays().within_nodes(within)),
+            vec!["named"]
+        );
+    }
+
+    #[test]
+    fn test_selection_skips_blacklisted_relays() {
+        use crate::reachability::{ReachabilityConfig, ReachabilityTracker};
+
+        let middle = vec![flags::FAST, flags::STABLE, flags::V2DIR];
+        let relays = vec![
+            create_test_relay("aa", middle.clone()),
+            create_test_relay("bb", middle),
+        ];
+        let tracker = ReachabilityTracker::new(ReachabilityConfig {
+            failure_threshold: 1,
+            ..Default::default()
+        });
+        let manager = RelayManager::new(relays).with_reachability(tracker.clone());
+
+        tracker.record_failure("aa");
+        let selected = manager.select_relays(&selection::middle_relays()).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].fingerprint, "bb");
+
+        // With every candidate blacklisted, fall back to the full set
+        tracker.record_failure("bb");
+        let selected = manager.select_relays(&selection::middle_relays()).unwrap();
+        assert_eq!(selected.len(), 2);
+    }
+
+    #[test]
+    fn test_seeded_selection_is_reproducible() {
+        let relays: Vec<Relay> = (0..8)
+            .map(|i| create_test_relay(&format!("{:02}", i), vec![flags::FAST, flags::STABLE]))
+            .collect();
+        let picks = |rng: SelectionRng| {
+            let manager = RelayManager::new(relays.clone()).with_rng(rng);
+            (0..20)
+                .map(|_| {
+                    manager
+                        .select_relay(&RelayCriteria::new().with_flag(flags::FAST))
+                        .unwrap()
+                        .fingerprint
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let first = picks(SelectionRng::from_seed(7));
+        assert_eq!(first, picks(SelectionRng::from_seed(7)));
+        assert_ne!(first, picks(SelectionRng::from_seed(8)));
+    }
+
+    #[test]
+    fn test_query_and_flag_counts() {
+        let mut fast = create_test_relay("AB12", vec![flags::FAST, flags::STABLE]);
+        fast.nickname = "FastRelay".to_string();
+        fast.bandwidth = 5000;
+        fast.address = "1.0.0.1".to_string();
+        let mut exit = create_test_relay("cd34", vec![flags::FAST, flags::EXIT]);
+        exit.nickname = "MyExit".to_string();
+        exit.bandwidth = 9000;
+        let slow = create_test_relay("ef56", vec![flags::STABLE]);
+
+        let geoip = GeoIpDb::parse("16777216,16777471,AU").unwrap();
+        let manager = RelayManager::new(vec![fast, exit, slow]).with_geoip(Arc::new(geoip));
+        let nicknames = |query: RelayQuery| {
+            manager
+                .query(&query)
+                .into_iter()
+                .map(|r| r.nickname.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            nicknames(RelayQuery::new().with_fingerprint("$ab12")),
+            vec!["FastRelay"]
+        );
+        assert_eq!(
+            nicknames(RelayQuery::new().with_nickname("exi")),
+            vec!["MyExit"]
+        );
+        // Highest bandwidth first
+        assert_eq!(
+            nicknames(RelayQuery::new().with_flag(flags::FAST)),
+            vec!["MyExit", "FastRelay"]
+        );
+        assert_eq!(
+            nicknames(RelayQuery::new().with_min_bandwidth(6000)),
+            vec!["MyExit"]
+        );
+        assert_eq!(
+            nicknames(RelayQuery::new().with_country("au")),
+            vec!["FastRelay"]
+        );
+        assert_eq!(
+            manager.query(&RelayQuery::new().with_country("??")).len(),
+            2
+        );
+        assert_eq!(manager.query(&RelayQuery::new().with_limit(1)).len(), 1);
+
+        let counts = manager.count_by_flag();
+        assert_eq!(counts[flags::FAST], 2);
+        assert_eq!(counts[flags::STABLE], 2);
+        assert_eq!(counts[flags::EXIT], 1);
+        assert!(!counts.contains_key(flags::GUARD));
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     mod proptests {
         use super::*;
@@ -454,6 +1289,11 @@ mod tests {
                             exclude_fingerprints,
                             min_bandwidth,
                             max_selection,
+                            exit_port: None,
+                            exclude_countries: HashSet::new(),
+                            exclude_related: Vec::new(),
+                            only_fingerprints: None,
+                            only_nodes: None,
                         }
                     },
                 )
@@ -2,7 +2,9 @@
 
 use crate::error::{Result, TorError};
 use std::future::Future;
+#[cfg(target_arch = "wasm32")]
 use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(target_arch = "wasm32")]
 use std::sync::Arc;
 use std::time::Duration;
 use tracing::{debug, warn};
@@ -445,6 +447,54 @@ where
     }
 }
 
+/// Run `attempt` on each candidate in order, happy-eyeballs style: each
+/// attempt starts `stagger` after the previous one, or immediately when an
+/// attempt fails. The first success wins and the attempts still running are
+/// dropped, which cancels them
+///
+/// Fails only if every attempt fails, with the last error.
+pub async fn race_staggered<C, T, F, Fut>(
+    candidates: impl IntoIterator<Item = C>,
+    stagger: Duration,
+    mut attempt: F,
+) -> Result<T>
+where
+    F: FnMut(C) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    use futures::future::{select, Either};
+    use futures::stream::{FuturesUnordered, StreamExt};
+    use std::pin::pin;
+
+    let mut pending = candidates.into_iter().peekable();
+    let mut running = FuturesUnordered::new();
+    let mut last_error = None;
+    if let Some(candidate) = pending.next() {
+        running.push(attempt(candidate));
+    }
+
+    while !running.is_empty() {
+        let finished = if pending.peek().is_some() {
+            match select(running.next(), pin!(sleep(stagger))).await {
+                Either::Left((finished, _)) => finished,
+                Either::Right(_) => None,
+            }
+        } else {
+            running.next().await
+        };
+        match finished {
+            Some(Ok(value)) => return Ok(value),
+            Some(Err(e)) => last_error = Some(e),
+            None => {}
+        }
+        if let Some(candidate) = pending.next() {
+            running.push(attempt(candidate));
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| TorError::configuration("Nothing to race")))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -707,4 +757,42 @@ mod tests {
         assert!(result.is_err());
         assert!(matches!(result.unwrap_err(), TorError::Cancelled));
     }
+
+    #[tokio::test]
+    async fn race_keeps_the_first_success() {
+        // The first candidate is slow, so the second overtakes it once started
+        let result = race_staggered(
+            [200u64, 10, 500],
+            Duration::from_millis(20),
+            |ms| async move {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+                Ok::<_, TorError>(ms)
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn race_starts_the_next_attempt_when_one_fails() {
+        let started = std::time::Instant::now();
+        let result = race_staggered([0u32, 1], Duration::from_secs(10), |n| async move {
+            match n {
+                0 => Err(TorError::network("refused")),
+                _ => Ok(n),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 1);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn race_fails_with_the_last_error() {
+        let result = race_staggered([1u32, 2], Duration::from_millis(1), |n| async move {
+            Err::<(), _>(TorError::network(format!("attempt {}", n)))
+        })
+        .await;
+        assert!(matches!(result, Err(TorError::Network(msg)) if msg == "attempt 2"));
+    }
 }
@@ -369,7 +369,7 @@ impl<S: AsyncRead + AsyncWrite + Unpin> SmuxStream<S> {
         }
 
         // Process all complete segments
-        while let Some(_) = self.process_next_segment().await? {}
+        while self.process_next_segment().await?.is_some() {}
 
         Ok(())
     }
@@ -0,0 +1,145 @@
+//! Client state snapshots
+//!
+//! [`TorClient::export_state`](crate::TorClient::export_state) gathers what a
+//! client has learned into a [`ClientState`]: its sampled guards and layer-2
+//! guards, bridge health, circuit build time history, and the relays of the
+//! network consensus it uses while that is still valid.
+//! [`TorClient::import_state`](crate::TorClient::import_state) gives them to
+//! another client, so a reloaded page or restarted process starts warm
+//! rather than learning it all again. Importing before the first request
+//! also skips the consensus download.
+//!
+//! The state serializes with serde. Most of its size is the directory, a
+//! few megabytes when present; relays from the static cache are left out
+//! because it can be fetched again quickly. A snapshot is trusted like a
+//! [`StateStore`], so keep it where the store would be.
+
+use crate::directory::{ConsensusLifetime, DirectorySnapshot};
+use crate::error::{Result, TorError};
+use crate::guard::now_secs;
+use crate::storage::StateStore;
+use crate::{bridges, build_timeout, guard, vanguards};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Format version written by this build
+pub const STATE_VERSION: u32 = 1;
+
+/// Store keys of the state a snapshot carries
+const KEYS: [&str; 4] = [
+    guard::STATE_KEY,
+    vanguards::STATE_KEY,
+    bridges::STATE_KEY,
+    build_timeout::STATE_KEY,
+];
+
+/// What a client has learned, from
+/// [`TorClient::export_state`](crate::TorClient::export_state)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientState {
+    pub version: u32,
+    /// Unix time of the export, in seconds
+    pub exported_at: u64,
+    /// Persisted state by store key
+    #[serde(default)]
+    entries: BTreeMap<String, serde_json::Value>,
+    /// Relays of the network consensus in use, if it hadn't expired
+    #[serde(default)]
+    pub directory: Option<DirectorySnapshot>,
+}
+
+impl ClientState {
+    /// Read the state kept in `store`
+    pub(crate) fn read(
+        store: &dyn StateStore,
+        directory: Option<DirectorySnapshot>,
+    ) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        for key in KEYS {
+            if let Some(json) = store.load(key)? {
+                entries.insert(key.to_string(), serde_json::from_str(&json)?);
+            }
+        }
+        Ok(Self {
+            version: STATE_VERSION,
+            exported_at: now_secs(),
+            entries,
+            directory,
+        })
+    }
+
+    /// Write the state into `store`, replacing what it holds under the same
+    /// keys; state the snapshot lacks is left alone
+    pub(crate) fn write(&self, store: &dyn StateStore) -> Result<()> {
+        if self.version > STATE_VERSION {
+            return Err(TorError::configuration(format!(
+                "Client state version {} is newer than supported ({})",
+                self.version, STATE_VERSION
+            )));
+        }
+        for key in KEYS {
+            if let Some(value) = self.entries.get(key) {
+                store.store(key, &serde_json::to_string(value)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lifetime of the consensus the saved relays came from
+    pub fn consensus_lifetime(&self) -> Option<ConsensusLifetime> {
+        self.directory.as_ref().map(|directory| directory.lifetime)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_timeout::BuildTimeoutEstimator;
+    use crate::guard::{GuardCandidate, GuardManager};
+    use crate::storage::MemoryStore;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_round_trip() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        let mut guards = GuardManager::load(store.clone());
+        guards.update_sample(&[GuardCandidate::bridge(
+            "0123456789ABCDEF0123456789ABCDEF01234567",
+        )]);
+        let timeouts = BuildTimeoutEstimator::default().with_store(store.clone());
+        for ms in [800, 1200, 1500] {
+            timeouts.record_success(Duration::from_millis(ms));
+        }
+
+        let json = ClientState::read(&*store, None).unwrap().to_json().unwrap();
+        let state = ClientState::from_json(&json).unwrap();
+        assert!(state.consensus_lifetime().is_none());
+
+        let restored: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        state.write(&*restored).unwrap();
+        assert_eq!(
+            GuardManager::load(restored.clone()).guards().sampled.len(),
+            1
+        );
+        let restored_timeouts = BuildTimeoutEstimator::default().with_store(restored);
+        assert_eq!(restored_timeouts.sample_count(), 3);
+        assert_eq!(restored_timeouts.timeout(), timeouts.timeout());
+    }
+
+    #[test]
+    fn test_newer_version_is_refused() {
+        let store = MemoryStore::new();
+        let mut state = ClientState::read(&store, None).unwrap();
+        state.version = STATE_VERSION + 1;
+        assert!(state.write(&store).is_err());
+    }
+}
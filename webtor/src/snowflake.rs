@@ -16,35 +16,42 @@
 //! Note: Direct WebSocket to wss://snowflake.torproject.net/ is for volunteer
 //! proxies, not clients. Clients must use WebRTC via the broker.
 
-use crate::error::Result;
-#[cfg(target_arch = "wasm32")]
-use crate::kcp_stream::{KcpConfig, KcpStream};
 #[cfg(target_arch = "wasm32")]
-use crate::smux::SmuxStream;
-use crate::snowflake_broker::{BROKER_URL, DEFAULT_BRIDGE_FINGERPRINT};
-#[cfg(target_arch = "wasm32")]
-use crate::turbo::TurboStream;
+use crate::datachannel::{establish_tunnel, DataChannelConfig, ReliableStack};
+use crate::error::Result;
+use crate::snowflake_broker::{BrokerClient, Rendezvous, BROKER_URL, DEFAULT_BRIDGE_FINGERPRINT};
+use crate::transport::{LinkStream, Transport};
 use futures::{AsyncRead, AsyncWrite};
+use serde::{Deserialize, Serialize};
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::time::Duration;
-use tracing::{info, warn};
+use tracing::info;
+#[cfg(target_arch = "wasm32")]
+use tracing::warn;
 
 #[cfg(target_arch = "wasm32")]
 use crate::webrtc_stream::WebRtcStream;
 
 #[cfg(target_arch = "wasm32")]
-use subtle_tls::{TlsConfig, TlsConnector, TlsStream};
+use subtle_tls::TlsStream;
 
 /// Snowflake bridge configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SnowflakeConfig {
     /// Broker URL for WebRTC signaling
     pub broker_url: String,
+    /// Front domains for domain-fronting the broker (native only; empty = direct)
+    pub front_domains: Vec<String>,
+    /// Rendezvous methods tried, in order, when the broker can't be reached
+    pub fallbacks: Vec<Rendezvous>,
     /// Bridge fingerprint for verification
     pub fingerprint: String,
-    /// Connection timeout
+    /// Timeout for each WebRTC rendezvous with a volunteer proxy
+    /// (milliseconds when serialized)
+    #[serde(with = "crate::config::duration_ms")]
     pub connection_timeout: Duration,
     /// KCP conversation ID (0 for Snowflake)
     pub kcp_conv: Option<u32>,
@@ -57,6 +64,8 @@ impl SnowflakeConfig {
     pub fn new() -> Self {
         Self {
             broker_url: BROKER_URL.to_string(),
+            front_domains: Vec::new(),
+            fallbacks: Vec::new(),
             fingerprint: DEFAULT_BRIDGE_FINGERPRINT.to_string(),
             connection_timeout: Duration::from_secs(60),
             kcp_conv: None,
@@ -78,6 +87,18 @@ impl SnowflakeConfig {
         self
     }
 
+    /// Domain-front broker requests through these front domains
+    pub fn with_front_domains(mut self, fronts: Vec<String>) -> Self {
+        self.front_domains = fronts;
+        self
+    }
+
+    /// Add a rendezvous method to fail over to (e.g. [`Rendezvous::amp_cache`])
+    pub fn with_fallback(mut self, rendezvous: Rendezvous) -> Self {
+        self.fallbacks.push(rendezvous);
+        self
+    }
+
     /// Set connection timeout
     pub fn with_timeout(mut self, timeout: Duration) -> Self {
         self.connection_timeout = timeout;
@@ -91,6 +112,17 @@ impl SnowflakeConfig {
     }
 }
 
+impl SnowflakeConfig {
+    /// Broker client for the configured broker, fronts, fallbacks and bridge
+    pub fn broker(&self) -> BrokerClient {
+        let primary = Rendezvous::http(&self.broker_url).with_fronts(self.front_domains.clone());
+        let rendezvous = std::iter::once(primary)
+            .chain(self.fallbacks.iter().cloned())
+            .collect();
+        BrokerClient::with_rendezvous(rendezvous).with_fingerprint(self.fingerprint.clone())
+    }
+}
+
 impl Default for SnowflakeConfig {
     fn default() -> Self {
         Self::new()
@@ -120,6 +152,7 @@ impl SnowflakeBridge {
     #[cfg(target_arch = "wasm32")]
     pub async fn connect(&self) -> Result<SnowflakeStream> {
         use crate::error::TorError;
+        use crate::retry::with_timeout;
 
         const MAX_WEBRTC_RETRIES: u32 = 3;
 
@@ -127,6 +160,8 @@ impl SnowflakeBridge {
         info!("Broker: {}", self.config.broker_url);
         info!("Fingerprint: {}", self.config.fingerprint);
 
+        let broker = self.config.broker();
+
         // 1. Establish WebRTC connection via broker (with retry for unreliable proxies)
         let mut webrtc = None;
         let mut last_error = None;
@@ -137,7 +172,12 @@ impl SnowflakeBridge {
                 attempt, MAX_WEBRTC_RETRIES
             );
 
-            match WebRtcStream::connect(&self.config.broker_url, &self.config.fingerprint).await {
+            let rendezvous = with_timeout(
+                self.config.connection_timeout,
+                "Snowflake WebRTC rendezvous",
+                WebRtcStream::connect(&broker),
+            );
+            match rendezvous.await {
                 Ok(stream) => {
                     info!("WebRTC DataChannel established on attempt {}", attempt);
                     webrtc = Some(stream);
@@ -146,10 +186,12 @@ impl SnowflakeBridge {
                 Err(e) => {
                     let err_str = e.to_string();
                     warn!("WebRTC connection attempt {} failed: {}", attempt, err_str);
+                    let timed_out =
+                        matches!(e, TorError::Timeout(_)) || err_str.contains("timeout");
                     last_error = Some(e);
 
                     // Only retry on timeout errors (proxy didn't respond)
-                    if !err_str.contains("timeout") {
+                    if !timed_out {
                         return Err(last_error.unwrap());
                     }
 
@@ -167,44 +209,11 @@ impl SnowflakeBridge {
         })?;
         info!("WebRTC DataChannel established");
 
-        // 2. Wrap with Turbo framing
-        info!("Initializing Turbo layer...");
-        let mut turbo = TurboStream::new(webrtc);
-        turbo.initialize().await?;
-        info!("Turbo layer initialized");
-
-        // 3. Wrap with KCP for reliability
-        info!("Initializing KCP layer...");
-        let kcp_config = KcpConfig {
-            conv: self.config.kcp_conv.unwrap_or(0),
-            ..Default::default()
-        };
-        let kcp = KcpStream::new(turbo, kcp_config);
-        info!("KCP layer initialized");
-
-        // 4. Wrap with SMUX for multiplexing
-        info!("Initializing SMUX layer...");
-        let stream_id = self.config.smux_stream_id.unwrap_or(3);
-        let mut smux = SmuxStream::with_stream_id(kcp, stream_id);
-        smux.initialize().await?;
-        info!("SMUX layer initialized");
-
-        // 5. Wrap with TLS for Tor link encryption
-        // Tor relays use self-signed certificates, so skip verification
-        // (authentication happens via CERTS cells in the Tor protocol)
-        info!("Establishing TLS over SMUX...");
-        let tls_config = TlsConfig {
-            skip_verification: true, // Tor uses self-signed certs, validated via CERTS cells
-            alpn_protocols: vec![],
-            ..Default::default()
-        };
-        let connector = TlsConnector::with_config(tls_config);
-        // Use a placeholder server name since Tor doesn't use SNI
-        let tls_stream = connector
-            .connect(smux, "www.example.com")
-            .await
-            .map_err(|e| crate::error::TorError::tls(format!("TLS handshake failed: {}", e)))?;
-        info!("TLS layer established over SMUX");
+        // 2. Turbo → KCP → SMUX → TLS
+        let tunnel_config = DataChannelConfig::new()
+            .with_kcp_conv(self.config.kcp_conv.unwrap_or(0))
+            .with_stream_id(self.config.smux_stream_id.unwrap_or(3));
+        let tls_stream = establish_tunnel(webrtc, &tunnel_config).await?;
 
         info!("Snowflake connection established: WebRTC → Turbo → KCP → SMUX → TLS");
 
@@ -234,9 +243,17 @@ impl Default for SnowflakeBridge {
     }
 }
 
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl Transport for SnowflakeBridge {
+    async fn connect(&self) -> Result<Box<dyn LinkStream>> {
+        Ok(Box::new(SnowflakeBridge::connect(self).await?))
+    }
+}
+
 /// Inner stream type (WebRTC on WASM, wrapped with TLS)
 #[cfg(target_arch = "wasm32")]
-type SnowflakeSmuxStack = SmuxStream<KcpStream<TurboStream<WebRtcStream>>>;
+type SnowflakeSmuxStack = ReliableStack<WebRtcStream>;
 
 #[cfg(target_arch = "wasm32")]
 enum SnowflakeInner {
@@ -263,6 +280,7 @@ impl tor_rtcompat::StreamOps for SnowflakeStream {
     // Use default implementation
 }
 
+#[cfg_attr(not(target_arch = "wasm32"), allow(unused_variables))]
 impl tor_rtcompat::CertifiedConn for SnowflakeStream {
     fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
         match &self.inner {
@@ -390,6 +408,31 @@ mod tests {
         assert_eq!(config.broker_url, BROKER_URL);
         assert_eq!(config.fingerprint, DEFAULT_BRIDGE_FINGERPRINT);
         assert_eq!(config.connection_timeout, Duration::from_secs(60));
+        assert!(config.front_domains.is_empty());
+    }
+
+    #[test]
+    fn test_snowflake_config_with_front_domains() {
+        let config = SnowflakeConfig::with_broker("https://broker.example/".to_string())
+            .with_front_domains(vec!["front.example".to_string()]);
+        assert_eq!(config.front_domains, vec!["front.example".to_string()]);
+        assert_eq!(
+            config.broker().rendezvous(),
+            &[Rendezvous::http("https://broker.example/")
+                .with_fronts(vec!["front.example".to_string()])]
+        );
+    }
+
+    #[test]
+    fn test_snowflake_config_fallbacks() {
+        let config = SnowflakeConfig::new()
+            .with_fallback(Rendezvous::fronted())
+            .with_fallback(Rendezvous::amp_cache());
+        let broker = config.broker();
+        let methods = broker.rendezvous();
+        assert_eq!(methods[0], Rendezvous::http(BROKER_URL));
+        assert_eq!(methods[1], Rendezvous::fronted());
+        assert_eq!(methods[2], Rendezvous::amp_cache());
     }
 
     #[test]
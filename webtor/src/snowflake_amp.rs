@@ -0,0 +1,263 @@
+//! AMP cache rendezvous encoding for the Snowflake broker
+//!
+//! When the broker can't be reached directly or through a domain front, the
+//! client can ask an AMP cache to fetch the broker on its behalf. The client
+//! poll is carried in the URL path of a GET request (`amp/client/<encoded>`
+//! on the broker), rewritten into the cache's URL format, and the broker
+//! answers with an AMP-valid HTML page whose `<pre>` elements hold the
+//! base64 of the usual JSON response ("armor").
+//!
+//! This mirrors the `amp` package of the Go Snowflake client:
+//! - [`encode_path`]: a cache-breaking nonce plus the URL-safe base64 of the
+//!   request, so every poll is a distinct URL the cache must fetch
+//! - [`cache_url`]: the AMP cache URL format, including the domain prefix
+//!   subdomain derived from the publisher (broker) host
+//! - [`decode_armor`]: the inverse of the broker's armor encoder
+
+use crate::error::{Result, TorError};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Public Google AMP cache
+pub const AMP_CACHE_URL: &str = "https://cdn.ampproject.org/";
+
+/// Front domain for the Google AMP cache
+pub const AMP_CACHE_FRONT: &str = "www.google.com";
+
+/// Content type path component for generic content (`/c/`)
+const CONTENT_TYPE: &str = "c";
+
+/// Version prefix of both the path encoding and the armor encoding
+const VERSION: char = '0';
+
+/// Longest DNS label the domain prefix may produce before falling back
+const MAX_LABEL_LEN: usize = 63;
+
+/// Encode `data` as a URL path suffix: `0<nonce>/<data>`, both URL-safe base64
+pub fn encode_path(data: &[u8]) -> String {
+    let cache_breaker: [u8; 9] = rand::random();
+    let b64 = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    format!(
+        "{}{}/{}",
+        VERSION,
+        b64.encode(cache_breaker),
+        b64.encode(data)
+    )
+}
+
+/// Broker URL for an AMP client poll carrying `poll` (an encoded client request)
+pub fn client_url(broker_url: &str, poll: &[u8]) -> Result<Url> {
+    let broker = Url::parse(broker_url)
+        .map_err(|e| TorError::Configuration(format!("Invalid broker URL: {}", e)))?;
+    broker
+        .join(&format!("amp/client/{}", encode_path(poll)))
+        .map_err(|e| TorError::Configuration(format!("Invalid broker URL: {}", e)))
+}
+
+/// Rewrite `publisher` into the URL under which the AMP cache at `cache` serves it
+pub fn cache_url(publisher: &Url, cache: &Url) -> Result<Url> {
+    let scheme_component = match publisher.scheme() {
+        "https" => "/s",
+        "http" => "",
+        other => {
+            return Err(TorError::Configuration(format!(
+                "AMP cache can't fetch {} URLs",
+                other
+            )))
+        }
+    };
+    if !publisher.username().is_empty() || publisher.password().is_some() {
+        return Err(TorError::Configuration(
+            "AMP cache URLs can't carry credentials".to_string(),
+        ));
+    }
+    let publisher_host = publisher
+        .host_str()
+        .ok_or_else(|| TorError::Configuration("Broker URL has no host".to_string()))?;
+    let cache_host = cache
+        .host_str()
+        .ok_or_else(|| TorError::Configuration("AMP cache URL has no host".to_string()))?;
+
+    let mut publisher_authority = publisher_host.to_string();
+    if let Some(port) = publisher.port() {
+        publisher_authority.push_str(&format!(":{}", port));
+    }
+
+    let mut result = cache.clone();
+    result
+        .set_host(Some(&format!(
+            "{}.{}",
+            domain_prefix(publisher_host),
+            cache_host
+        )))
+        .map_err(|e| TorError::Configuration(format!("Invalid AMP cache host: {}", e)))?;
+    result.set_path(&format!(
+        "{}/{}{}/{}/{}",
+        cache.path().trim_end_matches('/'),
+        CONTENT_TYPE,
+        scheme_component,
+        publisher_authority,
+        publisher.path().trim_start_matches('/')
+    ));
+    result.set_query(publisher.query());
+    Ok(result)
+}
+
+/// AMP cache subdomain for `domain` (the "basic algorithm", or its hashed fallback)
+pub fn domain_prefix(domain: &str) -> String {
+    match domain_prefix_basic(domain) {
+        Some(prefix) if prefix.len() <= MAX_LABEL_LEN => prefix,
+        _ => domain_prefix_fallback(domain),
+    }
+}
+
+fn domain_prefix_basic(domain: &str) -> Option<String> {
+    let (unicode, result) = idna::domain_to_unicode(domain);
+    result.ok()?;
+    let mut prefix = unicode.replace('-', "--").replace('.', "-");
+    if prefix.get(2..4) == Some("--") {
+        prefix = format!("0-{}-0", prefix);
+    }
+    idna::domain_to_ascii(&prefix).ok()
+}
+
+fn domain_prefix_fallback(domain: &str) -> String {
+    let digest = Sha256::digest(domain.as_bytes());
+    let mut prefix = data_encoding::BASE32_NOPAD
+        .encode(&digest)
+        .to_ascii_lowercase();
+    prefix.truncate(52);
+    prefix
+}
+
+/// Extract the payload from an armored AMP page
+///
+/// The payload is the concatenated text of every `<pre>` element, less
+/// whitespace: a `0` version marker followed by standard base64.
+pub fn decode_armor(html: &[u8]) -> Result<Vec<u8>> {
+    let text = std::str::from_utf8(html)
+        .map_err(|e| TorError::Protocol(format!("Invalid UTF-8 in AMP response: {}", e)))?;
+
+    let mut armored = String::new();
+    let mut rest = text;
+    while let Some(start) = find_tag(rest, "<pre") {
+        let after_tag = &rest[start..];
+        let open_end = after_tag
+            .find('>')
+            .ok_or_else(|| TorError::Protocol("Unterminated <pre> in AMP response".to_string()))?;
+        let content = &after_tag[open_end + 1..];
+        let close = find_tag(content, "</pre")
+            .ok_or_else(|| TorError::Protocol("Unclosed <pre> in AMP response".to_string()))?;
+        armored.extend(
+            content[..close]
+                .chars()
+                .filter(|c| !c.is_ascii_whitespace()),
+        );
+        rest = &content[close..];
+    }
+
+    let payload = armored
+        .strip_prefix(VERSION)
+        .ok_or_else(|| TorError::Protocol("AMP response has no armored payload".to_string()))?;
+    base64::engine::general_purpose::STANDARD
+        .decode(payload)
+        .map_err(|e| TorError::Protocol(format!("Invalid base64 in AMP response: {}", e)))
+}
+
+/// Byte offset of the next `tag` (case-insensitive) that ends at a tag boundary
+fn find_tag(haystack: &str, tag: &str) -> Option<usize> {
+    let lower = haystack.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(offset) = lower[from..].find(tag) {
+        let at = from + offset;
+        match lower.as_bytes().get(at + tag.len()) {
+            Some(b'>') | Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') => return Some(at),
+            _ => from = at + tag.len(),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_domain_prefix() {
+        assert_eq!(domain_prefix("example.com"), "example-com");
+        assert_eq!(
+            domain_prefix("snowflake-broker.torproject.net"),
+            "snowflake--broker-torproject-net"
+        );
+        assert_eq!(
+            domain_prefix("xn--bcher-kva.ch"),
+            idna::domain_to_ascii("bücher-ch").unwrap()
+        );
+        // Hyphens at positions 3 and 4 are wrapped so the label isn't read as punycode
+        assert_eq!(domain_prefix("ab-cd.example"), "0-ab--cd-example-0");
+
+        let long = format!("{}.example", "a".repeat(60));
+        let fallback = domain_prefix(&long);
+        assert_eq!(fallback.len(), 52);
+        assert!(fallback
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit()));
+    }
+
+    #[test]
+    fn test_cache_url() {
+        let publisher =
+            Url::parse("https://snowflake-broker.torproject.net/amp/client/0abc/def").unwrap();
+        let cache = Url::parse(AMP_CACHE_URL).unwrap();
+        assert_eq!(
+            cache_url(&publisher, &cache).unwrap().as_str(),
+            "https://snowflake--broker-torproject-net.cdn.ampproject.org/c/s/snowflake-broker.torproject.net/amp/client/0abc/def"
+        );
+
+        let plain = Url::parse("http://example.com:8080/path?q=1").unwrap();
+        let prefixed = Url::parse("https://amp.example/cache/").unwrap();
+        assert_eq!(
+            cache_url(&plain, &prefixed).unwrap().as_str(),
+            "https://example-com.amp.example/cache/c/example.com:8080/path?q=1"
+        );
+
+        let ftp = Url::parse("ftp://example.com/").unwrap();
+        assert!(cache_url(&ftp, &cache).is_err());
+    }
+
+    #[test]
+    fn test_client_url_encodes_poll() {
+        let url = client_url("https://broker.example/", b"1.0\n{}").unwrap();
+        let path = url.path();
+        let encoded = path.strip_prefix("/amp/client/0").unwrap();
+        let (nonce, data) = encoded.split_once('/').unwrap();
+        assert_eq!(nonce.len(), 12);
+        let b64 = base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        assert_eq!(b64.decode(data).unwrap(), b"1.0\n{}");
+
+        // A fresh nonce per poll keeps the cache from answering with a stale page
+        assert_ne!(
+            url,
+            client_url("https://broker.example/", b"1.0\n{}").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decode_armor() {
+        let payload = br#"{"answer":"sdp","error":""}"#;
+        let b64 = base64::engine::general_purpose::STANDARD.encode(payload);
+        let (first, second) = b64.split_at(10);
+        let html = format!(
+            "<!doctype html>\n<html amp>\n<head><meta charset=\"utf-8\"></head>\n<body>\n\
+             <pre>\n0{}\n</pre>\n<PRE class=\"x\">{}\n</PRE>\n</body>\n</html>\n",
+            first, second
+        );
+        assert_eq!(decode_armor(html.as_bytes()).unwrap(), payload);
+
+        // <preload> isn't a <pre> element
+        assert!(decode_armor(b"<preload>0abcd</preload>").is_err());
+        assert!(decode_armor(b"<html><body>blocked</body></html>").is_err());
+        assert!(decode_armor(b"<pre>1AAAA</pre>").is_err());
+    }
+}
@@ -9,16 +9,31 @@
 //! 3. Broker matches with available proxy
 //! 4. Proxy responds with SDP answer via broker
 //! 5. Client receives answer and completes WebRTC connection
+//!
+//! Several rendezvous methods can be configured ([`Rendezvous`]); they are
+//! tried in order, so a blocked broker front falls back to the next one,
+//! e.g. the AMP cache (see [`crate::snowflake_amp`]).
+//!
+//! Outside the browser the request can be domain-fronted: the TLS connection
+//! (and its SNI) goes to an innocuous front domain on the same CDN while the
+//! `Host` header names the broker (or cache). Browsers don't let a page
+//! choose the `Host` header, so WASM builds always contact the broker or
+//! cache URL directly.
 
 use crate::error::{Result, TorError};
+use crate::fronted::{self, FORM_CONTENT_TYPE};
 use crate::retry::{retry_with_backoff, RetryPolicy};
+use crate::snowflake_amp::{self, AMP_CACHE_FRONT, AMP_CACHE_URL};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 /// Snowflake broker URL (direct - has CORS support)
 pub const BROKER_URL: &str = "https://snowflake-broker.torproject.net/";
 
-/// Front domains for domain fronting (CDN77) - kept for reference but not used with CORS proxy
+/// Broker URL on the CDN77 front (used with [`BROKER_FRONT_DOMAINS`])
+pub const BROKER_URL_FRONTED: &str = "https://1098762253.rsc.cdn77.org/";
+
+/// Front domains for domain fronting the broker (CDN77)
 pub const BROKER_FRONT_DOMAINS: &[&str] = &["www.cdn77.com", "www.phpmyadmin.net"];
 
 /// Direct broker URL (doesn't work from browsers due to CORS)
@@ -119,22 +134,136 @@ impl ClientPollResponse {
     }
 }
 
+/// One way of reaching the broker
+///
+/// A [`BrokerClient`] tries its methods in order and fails over to the next
+/// when one can't produce an answer, so a blocked domain front doesn't stop
+/// bootstrap while another route still works.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rendezvous {
+    /// POST the client poll to `{url}/client`
+    Http {
+        url: String,
+        /// Front domains to connect through instead of the broker host (native only)
+        #[serde(default)]
+        fronts: Vec<String>,
+    },
+    /// GET the client poll through an AMP cache, which fetches it from the broker
+    AmpCache {
+        broker_url: String,
+        cache_url: String,
+        /// Front domains to connect through instead of the cache host (native only)
+        #[serde(default)]
+        fronts: Vec<String>,
+    },
+}
+
+impl Rendezvous {
+    /// Direct HTTPS to `url`
+    pub fn http(url: &str) -> Self {
+        Rendezvous::Http {
+            url: url.to_string(),
+            fronts: Vec::new(),
+        }
+    }
+
+    /// The Tor Project broker behind its CDN77 fronts
+    pub fn fronted() -> Self {
+        Rendezvous::Http {
+            url: BROKER_URL_FRONTED.to_string(),
+            fronts: to_strings(BROKER_FRONT_DOMAINS),
+        }
+    }
+
+    /// The Tor Project broker through the Google AMP cache, fronted by
+    /// [`AMP_CACHE_FRONT`](crate::snowflake_amp::AMP_CACHE_FRONT)
+    pub fn amp_cache() -> Self {
+        Rendezvous::AmpCache {
+            broker_url: BROKER_URL.to_string(),
+            cache_url: AMP_CACHE_URL.to_string(),
+            fronts: vec![AMP_CACHE_FRONT.to_string()],
+        }
+    }
+
+    /// Replace the front domains
+    pub fn with_fronts(mut self, new_fronts: Vec<String>) -> Self {
+        match &mut self {
+            Rendezvous::Http { fronts, .. } | Rendezvous::AmpCache { fronts, .. } => {
+                *fronts = new_fronts
+            }
+        }
+        self
+    }
+
+    /// Front domains of this method
+    pub fn fronts(&self) -> &[String] {
+        match self {
+            Rendezvous::Http { fronts, .. } | Rendezvous::AmpCache { fronts, .. } => fronts,
+        }
+    }
+
+    /// Short description for logs
+    fn describe(&self) -> String {
+        match self {
+            Rendezvous::Http { url, .. } => url.clone(),
+            Rendezvous::AmpCache {
+                broker_url,
+                cache_url,
+                ..
+            } => format!("{} via AMP cache {}", broker_url, cache_url),
+        }
+    }
+}
+
+fn to_strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|item| item.to_string()).collect()
+}
+
 /// Snowflake broker client
 pub struct BrokerClient {
-    broker_url: String,
+    rendezvous: Vec<Rendezvous>,
     fingerprint: String,
     nat_type: NatType,
 }
 
 impl BrokerClient {
     pub fn new(broker_url: &str) -> Self {
+        Self::with_rendezvous(vec![Rendezvous::http(broker_url)])
+    }
+
+    /// Client trying each rendezvous method in order until one yields an answer
+    pub fn with_rendezvous(rendezvous: Vec<Rendezvous>) -> Self {
         Self {
-            broker_url: broker_url.to_string(),
+            rendezvous,
             fingerprint: DEFAULT_BRIDGE_FINGERPRINT.to_string(),
             nat_type: NatType::Unknown,
         }
     }
 
+    /// Client for the fronted Tor Project broker ([`BROKER_URL_FRONTED`]
+    /// behind [`BROKER_FRONT_DOMAINS`])
+    pub fn fronted() -> Self {
+        Self::with_rendezvous(vec![Rendezvous::fronted()])
+    }
+
+    /// Domain-front requests of the primary (first) method through one of `fronts`
+    ///
+    /// Each request connects to a randomly picked front and names the
+    /// broker only in the `Host` header. Ignored in WASM, where the browser
+    /// controls `Host`.
+    pub fn with_front_domains(mut self, fronts: Vec<String>) -> Self {
+        if let Some(primary) = self.rendezvous.first_mut() {
+            *primary = primary.clone().with_fronts(fronts);
+        }
+        self
+    }
+
+    /// Add a method to try after the existing ones fail
+    pub fn with_fallback(mut self, rendezvous: Rendezvous) -> Self {
+        self.rendezvous.push(rendezvous);
+        self
+    }
+
     pub fn with_fingerprint(mut self, fingerprint: String) -> Self {
         self.fingerprint = fingerprint;
         self
@@ -145,9 +274,15 @@ impl BrokerClient {
         self
     }
 
+    /// Rendezvous methods in the order they are tried
+    pub fn rendezvous(&self) -> &[Rendezvous] {
+        &self.rendezvous
+    }
+
     /// Exchange SDP offer for SDP answer via broker
     /// Returns the SDP answer from a volunteer proxy
-    /// Retries using RetryPolicy::network() if no proxy is available
+    /// Retries each rendezvous method using RetryPolicy::network() if no
+    /// proxy is available, then fails over to the next method
     pub async fn negotiate(&self, sdp_offer: &str) -> Result<String> {
         let mut request = ClientPollRequest::new(sdp_offer.to_string())
             .with_fingerprint(self.fingerprint.clone());
@@ -157,206 +292,114 @@ impl BrokerClient {
         }
 
         let body = request.encode()?;
-        let proxy_url = format!("{}/client", self.broker_url.trim_end_matches('/'));
-
-        retry_with_backoff(
-            "snowflake_broker_negotiate",
-            RetryPolicy::network(),
-            |e| e.is_retryable(),
-            |attempt| {
-                let body = body.clone();
-                let proxy_url = proxy_url.clone();
-                async move {
-                    info!("Contacting Snowflake broker (attempt {})", attempt);
-                    debug!("Broker URL: {}", proxy_url);
-
-                    #[cfg(target_arch = "wasm32")]
-                    let response_bytes = self.fetch_wasm(&proxy_url, &body).await?;
 
-                    #[cfg(not(target_arch = "wasm32"))]
-                    let response_bytes = self.fetch_native(&proxy_url, &body).await?;
+        let mut last_error = None;
+        for (index, rendezvous) in self.rendezvous.iter().enumerate() {
+            if index > 0 {
+                warn!(
+                    "Failing over to Snowflake rendezvous {}/{}: {}",
+                    index + 1,
+                    self.rendezvous.len(),
+                    rendezvous.describe()
+                );
+            }
 
-                    let response = ClientPollResponse::decode(&response_bytes)?;
+            #[cfg(target_arch = "wasm32")]
+            if !rendezvous.fronts().is_empty() {
+                warn!("Browsers can't domain-front; contacting the broker directly");
+            }
 
-                    debug!(
-                        "Broker response: answer={} bytes, error='{}'",
-                        response.answer.len(),
-                        response.error
+            match self.negotiate_with(rendezvous, &body).await {
+                Ok(answer) => return Ok(answer),
+                Err(e) => {
+                    warn!(
+                        "Snowflake rendezvous {} failed: {}",
+                        rendezvous.describe(),
+                        e
                     );
+                    last_error = Some(e);
+                }
+            }
+        }
 
-                    if !response.error.is_empty() {
-                        let is_retryable_error = response.error.contains("timed out")
-                            || response.error.contains("no proxies")
-                            || response.error.contains("match");
-
-                        if is_retryable_error {
-                            return Err(TorError::network(format!(
-                                "No Snowflake proxy available: {}",
-                                response.error
-                            )));
-                        } else {
-                            return Err(TorError::tor_protocol(format!(
-                                "Snowflake broker error: {}",
-                                response.error
-                            )));
-                        }
-                    }
+        Err(last_error
+            .unwrap_or_else(|| TorError::Configuration("No Snowflake broker configured".into())))
+    }
 
-                    if response.answer.is_empty() {
-                        return Err(TorError::network("Broker returned empty answer"));
+    /// Run the poll against one rendezvous method, retrying while no proxy is available
+    async fn negotiate_with(&self, rendezvous: &Rendezvous, body: &[u8]) -> Result<String> {
+        retry_with_backoff(
+            "snowflake_broker_negotiate",
+            RetryPolicy::network(),
+            |e| e.is_retryable(),
+            |attempt| async move {
+                info!("Contacting Snowflake broker (attempt {})", attempt);
+                debug!("Broker: {}", rendezvous.describe());
+
+                let response_bytes = self.poll(rendezvous, body).await?;
+                let response = ClientPollResponse::decode(&response_bytes)?;
+
+                debug!(
+                    "Broker response: answer={} bytes, error='{}'",
+                    response.answer.len(),
+                    response.error
+                );
+
+                if !response.error.is_empty() {
+                    let is_retryable_error = response.error.contains("timed out")
+                        || response.error.contains("no proxies")
+                        || response.error.contains("match");
+
+                    if is_retryable_error {
+                        return Err(TorError::network(format!(
+                            "No Snowflake proxy available: {}",
+                            response.error
+                        )));
+                    } else {
+                        return Err(TorError::tor_protocol(format!(
+                            "Snowflake broker error: {}",
+                            response.error
+                        )));
                     }
-
-                    info!(
-                        "Got SDP answer from broker ({} bytes)",
-                        response.answer.len()
-                    );
-                    Ok(response.answer)
                 }
-            },
-        )
-        .await
-    }
 
-    /// Fetch via CORS proxy
-    #[cfg(target_arch = "wasm32")]
-    async fn fetch_wasm(&self, url: &str, body: &[u8]) -> Result<Vec<u8>> {
-        use wasm_bindgen::JsCast;
-        use wasm_bindgen_futures::JsFuture;
-        use web_sys::{Request, RequestInit, RequestMode, Response};
-
-        let opts = RequestInit::new();
-        opts.set_method("POST");
-        opts.set_mode(RequestMode::Cors);
-
-        // Convert body to Uint8Array
-        let body_array = js_sys::Uint8Array::from(body);
-        opts.set_body(&body_array.into());
-
-        let request = Request::new_with_str_and_init(url, &opts)
-            .map_err(|e| TorError::Network(format!("Failed to create request: {:?}", e)))?;
-
-        // Set Content-Type header
-        request
-            .headers()
-            .set("Content-Type", "application/x-www-form-urlencoded")
-            .map_err(|e| {
-                TorError::Network(format!("Failed to set Content-Type header: {:?}", e))
-            })?;
-
-        let window =
-            web_sys::window().ok_or_else(|| TorError::Internal("No window object".to_string()))?;
-
-        let resp_value = JsFuture::from(window.fetch_with_request(&request))
-            .await
-            .map_err(|e| TorError::Network(format!("Fetch failed: {:?}", e)))?;
-
-        let resp: Response = resp_value
-            .dyn_into()
-            .map_err(|_| TorError::Internal("Response cast failed".to_string()))?;
-
-        if !resp.ok() {
-            return Err(TorError::Network(format!(
-                "Broker returned HTTP {}",
-                resp.status()
-            )));
-        }
+                if response.answer.is_empty() {
+                    return Err(TorError::network("Broker returned empty answer"));
+                }
 
-        let array_buffer = JsFuture::from(
-            resp.array_buffer()
-                .map_err(|e| TorError::Network(format!("Failed to get body: {:?}", e)))?,
+                info!(
+                    "Got SDP answer from broker ({} bytes)",
+                    response.answer.len()
+                );
+                Ok(response.answer)
+            },
         )
         .await
-        .map_err(|e| TorError::Network(format!("Failed to read body: {:?}", e)))?;
-
-        let uint8_array = js_sys::Uint8Array::new(&array_buffer);
-        Ok(uint8_array.to_vec())
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
-    async fn fetch_native(&self, url: &str, body: &[u8]) -> Result<Vec<u8>> {
-        use rustls_pki_types::ServerName;
-        use tokio::io::{AsyncReadExt, AsyncWriteExt};
-        use tokio::net::TcpStream;
-        use tokio_rustls::TlsConnector;
-
-        let parsed = url::Url::parse(url)
-            .map_err(|e| TorError::Configuration(format!("Invalid URL: {}", e)))?;
-
-        let host = parsed
-            .host_str()
-            .ok_or_else(|| TorError::Configuration("URL has no host".to_string()))?;
-        let port = parsed.port().unwrap_or(443);
-
-        let addr = format!("{}:{}", host, port);
-        let stream = TcpStream::connect(&addr)
-            .await
-            .map_err(|e| TorError::Network(format!("Failed to connect to broker: {}", e)))?;
-
-        // Setup TLS
-        let mut root_store = rustls::RootCertStore::empty();
-        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-
-        let config = rustls::ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
-
-        let connector = TlsConnector::from(std::sync::Arc::new(config));
-        let server_name = ServerName::try_from(host.to_string())
-            .map_err(|_| TorError::Configuration("Invalid server name".to_string()))?;
-
-        let mut tls_stream = connector
-            .connect(server_name, stream)
-            .await
-            .map_err(|e| TorError::Network(format!("TLS handshake failed: {}", e)))?;
-
-        // Build HTTP request
-        let path = parsed.path();
-        let request = format!(
-            "POST {} HTTP/1.1\r\n\
-             Host: {}\r\n\
-             Content-Type: application/x-www-form-urlencoded\r\n\
-             Content-Length: {}\r\n\
-             Connection: close\r\n\
-             \r\n",
-            path,
-            host,
-            body.len()
-        );
-
-        tls_stream
-            .write_all(request.as_bytes())
-            .await
-            .map_err(|e| TorError::Network(format!("Failed to send request: {}", e)))?;
-        tls_stream
-            .write_all(body)
-            .await
-            .map_err(|e| TorError::Network(format!("Failed to send body: {}", e)))?;
-        tls_stream
-            .flush()
-            .await
-            .map_err(|e| TorError::Network(format!("Failed to flush: {}", e)))?;
-
-        // Read response
-        let mut response = Vec::new();
-        tls_stream
-            .read_to_end(&mut response)
-            .await
-            .map_err(|e| TorError::Network(format!("Failed to read response: {}", e)))?;
-
-        // Parse HTTP response (simple parser)
-        let response_str = String::from_utf8_lossy(&response);
-
-        // Find body after \r\n\r\n
-        if let Some(idx) = response_str.find("\r\n\r\n") {
-            let body_start = idx + 4;
-            if body_start < response.len() {
-                return Ok(response[body_start..].to_vec());
+    /// Send one encoded client poll and return the broker's JSON response
+    async fn poll(&self, rendezvous: &Rendezvous, body: &[u8]) -> Result<Vec<u8>> {
+        match rendezvous {
+            Rendezvous::Http { url, fronts } => {
+                let url = format!("{}/client", url.trim_end_matches('/'));
+                fronted::fetch(&url, fronts, Some(body), FORM_CONTENT_TYPE).await
+            }
+            Rendezvous::AmpCache {
+                broker_url,
+                cache_url,
+                fronts,
+            } => {
+                let cache = url::Url::parse(cache_url).map_err(|e| {
+                    TorError::Configuration(format!("Invalid AMP cache URL: {}", e))
+                })?;
+                let url = snowflake_amp::cache_url(
+                    &snowflake_amp::client_url(broker_url, body)?,
+                    &cache,
+                )?;
+                let page = fronted::fetch(url.as_str(), fronts, None, FORM_CONTENT_TYPE).await?;
+                snowflake_amp::decode_armor(&page)
             }
         }
-
-        Err(TorError::Protocol(
-            "Invalid HTTP response from broker".to_string(),
-        ))
     }
 }
 
@@ -395,4 +438,32 @@ mod tests {
         assert_eq!(response.error, "no proxies available");
         assert!(!response.is_success());
     }
+
+    #[test]
+    fn test_rendezvous_order() {
+        let client = BrokerClient::new("https://broker.example/")
+            .with_front_domains(vec!["front.example".to_string()])
+            .with_fallback(Rendezvous::fronted())
+            .with_fallback(Rendezvous::amp_cache());
+
+        let methods = client.rendezvous();
+        assert_eq!(methods.len(), 3);
+        assert_eq!(
+            methods[0],
+            Rendezvous::http("https://broker.example/")
+                .with_fronts(vec!["front.example".to_string()])
+        );
+        assert_eq!(methods[1].fronts(), &to_strings(BROKER_FRONT_DOMAINS)[..]);
+        assert!(matches!(methods[2], Rendezvous::AmpCache { .. }));
+    }
+
+    #[test]
+    fn test_rendezvous_serde() {
+        let json = r#"{"AmpCache":{"broker_url":"https://broker.example/","cache_url":"https://cdn.ampproject.org/"}}"#;
+        let rendezvous: Rendezvous = serde_json::from_str(json).unwrap();
+        assert!(rendezvous.fronts().is_empty());
+        let back: Rendezvous =
+            serde_json::from_str(&serde_json::to_string(&rendezvous).unwrap()).unwrap();
+        assert_eq!(back, rendezvous);
+    }
 }
@@ -18,11 +18,14 @@
 //!   Tor protocol
 
 use crate::error::{Result, TorError};
+use crate::transport::{LinkStream, Transport};
+#[cfg(target_arch = "wasm32")]
 use crate::websocket::WebSocketStream;
 use futures::{AsyncRead, AsyncWrite};
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+#[cfg(target_arch = "wasm32")]
 use tracing::info;
 
 #[cfg(target_arch = "wasm32")]
@@ -168,8 +171,17 @@ impl SnowflakeWsStream {
     }
 }
 
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl Transport for SnowflakeWsConfig {
+    async fn connect(&self) -> Result<Box<dyn LinkStream>> {
+        Ok(Box::new(SnowflakeWsStream::connect(self.clone()).await?))
+    }
+}
+
 impl tor_rtcompat::StreamOps for SnowflakeWsStream {}
 
+#[cfg_attr(not(target_arch = "wasm32"), allow(unused_variables))]
 impl tor_rtcompat::CertifiedConn for SnowflakeWsStream {
     fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
         match &self.inner {
@@ -200,6 +212,7 @@ impl tor_rtcompat::CertifiedConn for SnowflakeWsStream {
     }
 }
 
+#[cfg_attr(not(target_arch = "wasm32"), allow(unused_variables))]
 impl AsyncRead for SnowflakeWsStream {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -215,6 +228,7 @@ impl AsyncRead for SnowflakeWsStream {
     }
 }
 
+#[cfg_attr(not(target_arch = "wasm32"), allow(unused_variables))]
 impl AsyncWrite for SnowflakeWsStream {
     fn poll_write(
         mut self: Pin<&mut Self>,
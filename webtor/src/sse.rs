@@ -0,0 +1,778 @@
+//! Server-Sent Events over Tor streams
+//!
+//! [`TorEventSource`] is an `EventSource` through a Tor exit: a GET asking
+//! for `text/event-stream` that stays open, with events parsed as they
+//! arrive. When the stream ends or breaks it reconnects after the server's
+//! `retry` delay, sending `Last-Event-ID` so the server can pick up where
+//! it left off. A 204 response or [`close`](TorEventSource::close) ends it
+//! for good. Open one with
+//! [`TorClient::event_source`](crate::TorClient::event_source).
+
+use crate::client::TorClient;
+use crate::error::{Result, TorError};
+use crate::http::{is_protected_header, parse_head};
+use crate::isolation::IsolationToken;
+use crate::pinning::CertPin;
+use crate::retry::{sleep, with_cancellation, CancellationToken};
+use crate::ws::Transport;
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::lock::Mutex;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Largest response head we wait for
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Wait before reconnecting until the server sets its own `retry`
+pub const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(3);
+
+/// An event stream to open
+#[derive(Debug, Clone)]
+pub struct EventSourceRequest {
+    /// An `http://` or `https://` URL
+    pub url: Url,
+    /// Extra request headers (`Authorization`, ...)
+    pub headers: HashMap<String, String>,
+    /// Limit on getting a circuit, opening the stream, TLS and the response
+    /// head, for the first connection and each reconnection
+    pub timeout: Duration,
+    /// Caller tag; connections with different tokens never share a circuit
+    pub isolation_token: Option<IsolationToken>,
+    /// Overrides the client's size cap for a single event
+    pub max_event_size: Option<usize>,
+    /// Keys or certificates the server's TLS chain must contain (`https://`)
+    pub cert_pins: Vec<CertPin>,
+    /// `Last-Event-ID` for the first connection, to resume an earlier stream
+    pub last_event_id: Option<String>,
+    /// Reconnection attempts in a row that may fail before the event
+    /// source gives up
+    pub max_reconnects: u32,
+}
+
+impl EventSourceRequest {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            headers: HashMap::new(),
+            timeout: Duration::from_secs(30),
+            isolation_token: None,
+            max_event_size: None,
+            cert_pins: Vec::new(),
+            last_event_id: None,
+            max_reconnects: 5,
+        }
+    }
+
+    /// Send `key: value` with each connection; headers that frame the
+    /// exchange (`Host`, `Connection`, ...) are ignored
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keep this event source on circuits reserved for `token`
+    pub fn with_isolation(mut self, token: impl Into<IsolationToken>) -> Self {
+        self.isolation_token = Some(token.into());
+        self
+    }
+
+    /// Cap a single event (and any line) at `max` bytes; 0 means no cap
+    pub fn with_max_event_size(mut self, max: usize) -> Self {
+        self.max_event_size = Some(max);
+        self
+    }
+
+    /// Require the server's TLS certificate chain to contain `pin`; with
+    /// several pins, any of them will do
+    pub fn with_cert_pin(mut self, pin: CertPin) -> Self {
+        self.cert_pins.push(pin);
+        self
+    }
+
+    /// Resume after the event with ID `id`
+    pub fn with_last_event_id(mut self, id: impl Into<String>) -> Self {
+        self.last_event_id = Some(id.into());
+        self
+    }
+
+    pub fn with_max_reconnects(mut self, attempts: u32) -> Self {
+        self.max_reconnects = attempts;
+        self
+    }
+
+    /// Whether the URL asks for TLS (`https://`)
+    pub(crate) fn is_secure(&self) -> Result<bool> {
+        match self.url.scheme() {
+            "http" => Ok(false),
+            "https" => Ok(true),
+            other => Err(TorError::configuration(format!(
+                "Event source URLs must be http:// or https://, not {}://",
+                other
+            ))),
+        }
+    }
+
+    /// Host and port to open the stream to
+    pub(crate) fn target(&self) -> Result<(&str, u16)> {
+        let host = self
+            .url
+            .host_str()
+            .ok_or_else(|| TorError::configuration("Event source URL has no host"))?;
+        let port = self
+            .url
+            .port_or_known_default()
+            .ok_or_else(|| TorError::configuration("Event source URL has no port"))?;
+        Ok((host, port))
+    }
+
+    /// The GET for one connection, resuming after `last_event_id`
+    fn build_request(&self, last_event_id: &str) -> Result<Vec<u8>> {
+        let (host, _) = self.target()?;
+        let host = match self.url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let query = self
+            .url
+            .query()
+            .map(|q| format!("?{}", q))
+            .unwrap_or_default();
+
+        let mut request = format!(
+            "GET {}{} HTTP/1.1\r\nHost: {}\r\nAccept: text/event-stream\r\n\
+             Cache-Control: no-cache\r\n",
+            self.url.path(),
+            query,
+            host
+        );
+        if !last_event_id.is_empty() {
+            request.push_str(&format!("Last-Event-ID: {}\r\n", last_event_id));
+        }
+        for (key, value) in &self.headers {
+            http::HeaderName::from_bytes(key.as_bytes())
+                .map_err(|_| TorError::configuration(format!("Invalid header name {:?}", key)))?;
+            http::HeaderValue::from_bytes(value.as_bytes()).map_err(|_| {
+                TorError::configuration(format!("Invalid value for header {}", key))
+            })?;
+            let set_here = ["Accept", "Cache-Control", "Last-Event-ID"]
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(key));
+            if is_protected_header(key) || set_here {
+                debug!("Ignoring caller-set {} header", key);
+                continue;
+            }
+            request.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        request.push_str("\r\n");
+        Ok(request.into_bytes())
+    }
+}
+
+/// An event from the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The `event` field, `message` if the server sent none
+    pub event: String,
+    /// The `data` lines, joined with newlines
+    pub data: String,
+    /// The last event ID the server set, at or before this event
+    pub last_event_id: String,
+}
+
+/// Where an event source is, as in `EventSource.readyState`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadyState {
+    /// Opening the first connection or reconnecting
+    Connecting = 0,
+    Open = 1,
+    /// Closed by the caller or the server, or failed; no more events
+    Closed = 2,
+}
+
+impl ReadyState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            0 => ReadyState::Connecting,
+            1 => ReadyState::Open,
+            _ => ReadyState::Closed,
+        }
+    }
+}
+
+/// A Server-Sent Events stream through Tor that reconnects by itself
+///
+/// [`recv`](Self::recv) takes `&self`, so the event source can be shared
+/// with a task that calls [`close`](Self::close).
+pub struct TorEventSource {
+    client: TorClient,
+    request: EventSourceRequest,
+    state: Mutex<State>,
+    ready_state: AtomicU8,
+    /// Cancelled on closing, which interrupts a waiting `recv`
+    closed: CancellationToken,
+}
+
+struct State {
+    connection: Option<EventStream>,
+    parser: EventParser,
+    /// Parsed events not returned yet
+    pending: VecDeque<SseEvent>,
+    /// Failed connection attempts since the last one that opened
+    failures: u32,
+}
+
+impl std::fmt::Debug for TorEventSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TorEventSource")
+            .field("url", &self.request.url.as_str())
+            .field("ready_state", &self.ready_state())
+            .finish_non_exhaustive()
+    }
+}
+
+impl TorEventSource {
+    /// Open the first connection for `request` through `client`
+    pub(crate) async fn open(
+        client: TorClient,
+        request: EventSourceRequest,
+        max_event_size: usize,
+    ) -> Result<Self> {
+        let mut parser = EventParser::new(max_event_size);
+        parser.last_event_id = request.last_event_id.clone().unwrap_or_default();
+        let connection = client
+            .open_event_stream(&request, &parser.last_event_id)
+            .await?;
+        let ready_state = match connection {
+            Some(_) => ReadyState::Open,
+            None => ReadyState::Closed,
+        };
+        Ok(Self {
+            client,
+            request,
+            state: Mutex::new(State {
+                connection,
+                parser,
+                pending: VecDeque::new(),
+                failures: 0,
+            }),
+            ready_state: AtomicU8::new(ready_state as u8),
+            closed: CancellationToken::new(),
+        })
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.request.url
+    }
+
+    pub fn ready_state(&self) -> ReadyState {
+        ReadyState::from_u8(self.ready_state.load(Ordering::SeqCst))
+    }
+
+    /// Stop the event source; [`recv`](Self::recv) then returns `None`
+    pub fn close(&self) {
+        self.set_ready_state(ReadyState::Closed);
+        self.closed.cancel();
+    }
+
+    /// Wait for the next event, reconnecting as needed
+    ///
+    /// Returns `None` once the event source is closed, and fails when a
+    /// response isn't an event stream or reconnecting keeps failing.
+    pub async fn recv(&self) -> Result<Option<SseEvent>> {
+        match with_cancellation(&self.closed, self.next_event()).await {
+            Err(TorError::Cancelled) => Ok(None),
+            result => result,
+        }
+    }
+
+    async fn next_event(&self) -> Result<Option<SseEvent>> {
+        let mut state = self.state.lock().await;
+        let mut chunk = vec![0u8; READ_CHUNK];
+        loop {
+            if self.ready_state() == ReadyState::Closed {
+                state.connection = None;
+                return Ok(None);
+            }
+            if let Some(event) = state.pending.pop_front() {
+                return Ok(Some(event));
+            }
+
+            let State {
+                connection,
+                parser,
+                pending,
+                failures,
+            } = &mut *state;
+            let Some(stream) = connection.as_mut() else {
+                self.set_ready_state(ReadyState::Connecting);
+                sleep(parser.retry).await;
+                if self.ready_state() == ReadyState::Closed {
+                    continue;
+                }
+                match self
+                    .client
+                    .open_event_stream(&self.request, &parser.last_event_id)
+                    .await
+                {
+                    Ok(Some(stream)) => {
+                        *failures = 0;
+                        *connection = Some(stream);
+                        self.set_ready_state(ReadyState::Open);
+                    }
+                    Ok(None) => self.set_ready_state(ReadyState::Closed),
+                    Err(e) if e.is_retryable() && *failures < self.request.max_reconnects => {
+                        *failures += 1;
+                        warn!(
+                            "Reconnecting to {} failed ({}/{}): {}",
+                            self.request.url, failures, self.request.max_reconnects, e
+                        );
+                    }
+                    Err(e) => {
+                        self.set_ready_state(ReadyState::Closed);
+                        return Err(e);
+                    }
+                }
+                continue;
+            };
+
+            match stream.read(&mut chunk).await {
+                Ok(Some(data)) => pending.extend(parser.feed(&data)?),
+                Ok(None) => {
+                    debug!("Event stream from {} ended", self.request.url);
+                    *connection = None;
+                    parser.reset();
+                }
+                Err(e) if e.is_retryable() => {
+                    debug!("Event stream from {} broke: {}", self.request.url, e);
+                    *connection = None;
+                    parser.reset();
+                }
+                Err(e) => {
+                    self.set_ready_state(ReadyState::Closed);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    fn set_ready_state(&self, state: ReadyState) {
+        // Closed is final
+        let _ = self
+            .ready_state
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                (current != ReadyState::Closed as u8).then_some(state as u8)
+            });
+    }
+}
+
+/// One connection's response body
+pub(crate) struct EventStream {
+    stream: Box<dyn Transport>,
+    /// Body bytes read but not decoded yet
+    buffer: Vec<u8>,
+    framing: Framing,
+}
+
+enum Framing {
+    Chunked(ChunkedDecoder),
+    /// A body with a `Content-Length`, and how much of it is left
+    Length(usize),
+    UntilClose,
+}
+
+impl EventStream {
+    /// Send the GET for `request` over `stream` and read the response head
+    ///
+    /// `None` is a 204, with which the server says not to reconnect.
+    pub(crate) async fn open(
+        stream: impl Transport + 'static,
+        request: &EventSourceRequest,
+        last_event_id: &str,
+    ) -> Result<Option<Self>> {
+        let mut stream: Box<dyn Transport> = Box::new(stream);
+        stream
+            .write_all(&request.build_request(last_event_id)?)
+            .await?;
+        stream.flush().await?;
+
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 1024];
+        let head_end = loop {
+            if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos;
+            }
+            if buffer.len() > MAX_HEAD_SIZE {
+                return Err(TorError::Protocol(
+                    "Event stream response head too large".to_string(),
+                ));
+            }
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(TorError::http_request(
+                    "Connection closed before the event stream response",
+                ));
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+        };
+        let head = parse_head(&buffer[..head_end])?;
+        buffer.drain(..head_end + 4);
+
+        if head.status == 204 {
+            info!("{} asked not to reconnect (204)", request.url);
+            return Ok(None);
+        }
+        if head.status != 200 {
+            return Err(TorError::http_request(format!(
+                "Event stream refused: {} {}",
+                head.status, head.status_text
+            )));
+        }
+        let header = |name| head.headers.get(name).and_then(|v| v.to_str().ok());
+        let media_type = header(CONTENT_TYPE)
+            .and_then(|value| value.split(';').next())
+            .unwrap_or_default()
+            .trim();
+        if !media_type.eq_ignore_ascii_case("text/event-stream") {
+            return Err(TorError::Protocol(format!(
+                "Event stream has content type {:?}, not text/event-stream",
+                media_type
+            )));
+        }
+        let chunked = head
+            .headers
+            .get_all(TRANSFER_ENCODING)
+            .iter()
+            .filter_map(|te| te.to_str().ok())
+            .flat_map(|te| te.split(','))
+            .last()
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+        let framing = match header(CONTENT_LENGTH).and_then(|len| len.parse().ok()) {
+            _ if chunked => Framing::Chunked(ChunkedDecoder::default()),
+            Some(len) => Framing::Length(len),
+            None => Framing::UntilClose,
+        };
+
+        info!("Event stream open from {}", request.url);
+        Ok(Some(Self {
+            stream,
+            buffer,
+            framing,
+        }))
+    }
+
+    /// The next body bytes, or `None` at the end of the body
+    async fn read(&mut self, chunk: &mut [u8]) -> Result<Option<Vec<u8>>> {
+        loop {
+            let data = match &mut self.framing {
+                Framing::Chunked(decoder) => {
+                    let (data, used) = decoder.decode(&self.buffer)?;
+                    self.buffer.drain(..used);
+                    if decoder.done {
+                        return Ok((!data.is_empty()).then_some(data));
+                    }
+                    data
+                }
+                Framing::Length(0) => return Ok(None),
+                Framing::Length(left) => {
+                    let take = self.buffer.len().min(*left);
+                    *left -= take;
+                    self.buffer.drain(..take).collect()
+                }
+                Framing::UntilClose => std::mem::take(&mut self.buffer),
+            };
+            if !data.is_empty() {
+                return Ok(Some(data));
+            }
+
+            let n = self.stream.read(chunk).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// Incremental decoder for a chunked body
+#[derive(Debug, Default)]
+struct ChunkedDecoder {
+    /// Bytes left in the current chunk
+    remaining: usize,
+    /// Whether the CRLF after a finished chunk is still to come
+    in_chunk_end: bool,
+    done: bool,
+}
+
+impl ChunkedDecoder {
+    /// Decode what `data` holds of the body, returning it and the bytes of
+    /// `data` used; the rest is kept for when more has arrived
+    fn decode(&mut self, data: &[u8]) -> Result<(Vec<u8>, usize)> {
+        let mut body = Vec::new();
+        let mut i = 0;
+        while !self.done {
+            if self.remaining > 0 {
+                let take = (data.len() - i).min(self.remaining);
+                body.extend_from_slice(&data[i..i + take]);
+                i += take;
+                self.remaining -= take;
+                if self.remaining > 0 {
+                    break;
+                }
+                self.in_chunk_end = true;
+            }
+            if self.in_chunk_end {
+                match data.get(i..i + 2) {
+                    Some(b"\r\n") => i += 2,
+                    Some(_) => {
+                        return Err(TorError::http_request("Chunk not followed by CRLF"));
+                    }
+                    None => break,
+                }
+                self.in_chunk_end = false;
+            }
+            let Some(len) = data[i..].windows(2).position(|w| w == b"\r\n") else {
+                break;
+            };
+            let line = String::from_utf8_lossy(&data[i..i + len]);
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|e| {
+                TorError::http_request(format!("Invalid chunk size '{}': {}", size, e))
+            })?;
+            i += len + 2;
+            // Trailers after the last chunk don't matter to an event stream
+            self.done = size == 0;
+            self.remaining = size;
+        }
+        Ok((body, i))
+    }
+}
+
+/// The `text/event-stream` parser, fed bytes as they arrive
+#[derive(Debug)]
+struct EventParser {
+    /// The line read so far
+    line: Vec<u8>,
+    /// Whether the last line ended in CR, so an LF next finishes it
+    after_cr: bool,
+    /// Whether the start of the stream (and any BOM) has been passed
+    started: bool,
+    data: String,
+    event: String,
+    last_event_id: String,
+    /// Reconnection delay, as last set by the server
+    retry: Duration,
+    max_size: usize,
+}
+
+impl EventParser {
+    fn new(max_size: usize) -> Self {
+        Self {
+            line: Vec::new(),
+            after_cr: false,
+            started: false,
+            data: String::new(),
+            event: String::new(),
+            last_event_id: String::new(),
+            retry: DEFAULT_RECONNECT_DELAY,
+            max_size,
+        }
+    }
+
+    /// Drop a partly received event for a new connection; the last event
+    /// ID and the reconnection delay carry over
+    fn reset(&mut self) {
+        self.line.clear();
+        self.after_cr = false;
+        self.started = false;
+        self.data.clear();
+        self.event.clear();
+    }
+
+    /// The events `bytes` completes
+    fn feed(&mut self, mut bytes: &[u8]) -> Result<Vec<SseEvent>> {
+        let mut events = Vec::new();
+        while let Some((&byte, rest)) = bytes.split_first() {
+            bytes = rest;
+            if std::mem::take(&mut self.after_cr) && byte == b'\n' {
+                continue;
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    self.after_cr = byte == b'\r';
+                    let line = std::mem::take(&mut self.line);
+                    events.extend(self.process_line(&line)?);
+                }
+                _ => {
+                    self.line.push(byte);
+                    self.check_size(self.line.len())?;
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Result<Option<SseEvent>> {
+        let line = String::from_utf8_lossy(line);
+        let mut line = line.as_ref();
+        if !std::mem::replace(&mut self.started, true) {
+            line = line.strip_prefix('\u{feff}').unwrap_or(line);
+        }
+        if line.is_empty() {
+            return Ok(self.dispatch());
+        }
+        if line.starts_with(':') {
+            return Ok(None);
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+                self.check_size(self.data.len())?;
+            }
+            "id" if !value.contains('\0') => self.last_event_id = value.to_string(),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                if let Ok(ms) = value.parse() {
+                    self.retry = Duration::from_millis(ms);
+                }
+            }
+            _ => debug!("Ignoring event stream field {:?}", field),
+        }
+        Ok(None)
+    }
+
+    /// The event a blank line ends, unless it has no data
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let mut data = std::mem::take(&mut self.data);
+        let event = std::mem::take(&mut self.event);
+        if data.is_empty() {
+            return None;
+        }
+        data.pop();
+        Some(SseEvent {
+            event: if event.is_empty() {
+                "message".to_string()
+            } else {
+                event
+            },
+            data,
+            last_event_id: self.last_event_id.clone(),
+        })
+    }
+
+    fn check_size(&self, size: usize) -> Result<()> {
+        if self.max_size > 0 && size > self.max_size {
+            return Err(TorError::ResponseTooLarge {
+                limit: self.max_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_stream::Scripted;
+
+    fn event(event: &str, data: &str, id: &str) -> SseEvent {
+        SseEvent {
+            event: event.to_string(),
+            data: data.to_string(),
+            last_event_id: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_event_parsing() {
+        let mut parser = EventParser::new(0);
+        let stream = "\u{feff}: comment\r\ndata: first\r\ndata:second\r\n\r\n\
+                      event: add\rid: 7\rdata\r\r\
+                      id: 8\nretry: 1500\nretry: soon\nevent: empty\n\n\
+                      data: unfinished";
+        let mut events = Vec::new();
+        // Fed a byte at a time, so CRLFs and the BOM are split too
+        for byte in stream.as_bytes() {
+            events.extend(parser.feed(std::slice::from_ref(byte)).unwrap());
+        }
+        assert_eq!(
+            events,
+            vec![event("message", "first\nsecond", ""), event("add", "", "7"),]
+        );
+        assert_eq!(parser.last_event_id, "8");
+        assert_eq!(parser.retry, Duration::from_millis(1500));
+
+        // A new connection drops the half-received event but keeps the ID
+        parser.reset();
+        assert_eq!(
+            parser.feed(b"data: again\n\n").unwrap(),
+            vec![event("message", "again", "8")]
+        );
+        assert!(matches!(
+            EventParser::new(8).feed(b"data: too long"),
+            Err(TorError::ResponseTooLarge { limit: 8 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_event_stream() {
+        let request = EventSourceRequest::new(Url::parse("https://feed.example/events").unwrap())
+            .with_header("Authorization", "Bearer t")
+            .with_header("Accept", "text/html");
+        let connect = |input: &[u8]| {
+            // A few bytes at a time, so events arrive split
+            let (stream, written) = Scripted::new(input);
+            (
+                EventStream::open(stream.with_max_read(5), &request, "42"),
+                written,
+            )
+        };
+
+        let (stream, written) = connect(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream; charset=utf-8\r\n\
+              Transfer-Encoding: chunked\r\n\r\n\
+              c\r\ndata: hello\n\r\n3;ext\r\n\nda\r\n9\r\nta: bye\n\n\r\n0\r\n\r\n",
+        );
+        let mut stream = stream.await.unwrap().unwrap();
+        let sent = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        assert!(sent.starts_with(
+            "GET /events HTTP/1.1\r\nHost: feed.example\r\nAccept: text/event-stream\r\n"
+        ));
+        assert!(sent.contains("Last-Event-ID: 42\r\nAuthorization: Bearer t\r\n"));
+        assert!(!sent.contains("text/html"));
+
+        let mut parser = EventParser::new(0);
+        let mut events = Vec::new();
+        let mut chunk = [0u8; 64];
+        while let Some(data) = stream.read(&mut chunk).await.unwrap() {
+            events.extend(parser.feed(&data).unwrap());
+        }
+        assert_eq!(
+            events,
+            vec![event("message", "hello", ""), event("message", "bye", "")]
+        );
+
+        // 204 means stop; anything but an event stream is an error
+        let (stream, _) = connect(b"HTTP/1.1 204 No Content\r\n\r\n");
+        assert!(stream.await.unwrap().is_none());
+        let (stream, _) = connect(b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n");
+        assert!(matches!(stream.await, Err(TorError::Protocol(_))));
+        let (stream, _) = connect(b"HTTP/1.1 503 Busy\r\n\r\n");
+        assert!(stream.await.is_err());
+    }
+}
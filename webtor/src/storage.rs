@@ -0,0 +1,209 @@
+//! Persistent state storage
+//!
+//! State that should survive across sessions (entry and layer-2 guards) is
+//! written through a [`StateStore`]. The client defaults to [`MemoryStore`],
+//! which keeps nothing once the client is dropped; native builds can use
+//! [`FileStore`] and browsers [`LocalStorageStore`].
+
+use crate::error::{Result, TorError};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Key-value store for small pieces of serialized client state
+pub trait StateStore: Send + Sync {
+    /// Load the value stored under `key`, if any
+    fn load(&self, key: &str) -> Result<Option<String>>;
+
+    /// Store `value` under `key`, replacing any previous value
+    fn store(&self, key: &str, value: &str) -> Result<()>;
+
+    /// Remove the value stored under `key`
+    fn remove(&self, key: &str) -> Result<()>;
+}
+
+/// Shared handle to a state store, usable in `TorClientOptions`
+#[derive(Clone)]
+pub struct StateStoreHandle(pub Arc<dyn StateStore>);
+
+impl fmt::Debug for StateStoreHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StateStoreHandle")
+    }
+}
+
+/// In-memory store; state is lost when the store is dropped
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn load(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.entries().get(key).cloned())
+    }
+
+    fn store(&self, key: &str, value: &str) -> Result<()> {
+        self.entries().insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        self.entries().remove(key);
+        Ok(())
+    }
+}
+
+/// Store that keeps one file per key in a directory
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileStore {
+    /// Use `dir` for state files, creating it if needed
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &str) -> Result<std::path::PathBuf> {
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(TorError::Configuration(format!(
+                "Invalid state key: {:?}",
+                key
+            )));
+        }
+        Ok(self.dir.join(format!("{}.json", key)))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StateStore for FileStore {
+    fn load(&self, key: &str) -> Result<Option<String>> {
+        match std::fs::read_to_string(self.path(key)?) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn store(&self, key: &str, value: &str) -> Result<()> {
+        // Write to a temporary file first so a crash never leaves a torn state file
+        let path = self.path(key)?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, value)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        match std::fs::remove_file(self.path(key)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Store backed by the browser's `localStorage`
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug, Clone)]
+pub struct LocalStorageStore {
+    prefix: String,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl LocalStorageStore {
+    /// Keys are stored as `<prefix><key>` so several clients can share an origin
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    fn storage(&self) -> Result<web_sys::Storage> {
+        web_sys::window()
+            .ok_or_else(|| TorError::wasm("No window object"))?
+            .local_storage()
+            .map_err(|e| TorError::wasm(format!("localStorage unavailable: {:?}", e)))?
+            .ok_or_else(|| TorError::wasm("localStorage unavailable"))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl StateStore for LocalStorageStore {
+    fn load(&self, key: &str) -> Result<Option<String>> {
+        self.storage()?
+            .get_item(&format!("{}{}", self.prefix, key))
+            .map_err(|e| TorError::wasm(format!("localStorage read failed: {:?}", e)))
+    }
+
+    fn store(&self, key: &str, value: &str) -> Result<()> {
+        self.storage()?
+            .set_item(&format!("{}{}", self.prefix, key), value)
+            .map_err(|e| TorError::wasm(format!("localStorage write failed: {:?}", e)))
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        self.storage()?
+            .remove_item(&format!("{}{}", self.prefix, key))
+            .map_err(|e| TorError::wasm(format!("localStorage remove failed: {:?}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_store_round_trip() {
+        let store = MemoryStore::new();
+        assert_eq!(store.load("guards").unwrap(), None);
+
+        store.store("guards", "[]").unwrap();
+        assert_eq!(store.load("guards").unwrap().as_deref(), Some("[]"));
+
+        store.remove("guards").unwrap();
+        assert_eq!(store.load("guards").unwrap(), None);
+    }
+
+    #[test]
+    fn test_file_store_round_trip() {
+        let dir = std::env::temp_dir().join(format!("webtor-state-{}", uuid::Uuid::new_v4()));
+        let store = FileStore::new(&dir).unwrap();
+
+        store.store("guards", "{\"v\":1}").unwrap();
+        let reopened = FileStore::new(&dir).unwrap();
+        assert_eq!(
+            reopened.load("guards").unwrap().as_deref(),
+            Some("{\"v\":1}")
+        );
+
+        reopened.remove("guards").unwrap();
+        reopened.remove("guards").unwrap();
+        assert_eq!(reopened.load("guards").unwrap(), None);
+        assert!(store.store("../escape", "x").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+//! Scripted byte stream for protocol tests
+
+use futures::io::{AsyncRead, AsyncWrite, Cursor};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Replays `input` as the server and records what the client writes
+pub(crate) struct Scripted {
+    input: Cursor<Vec<u8>>,
+    written: Arc<Mutex<Vec<u8>>>,
+    max_read: usize,
+}
+
+impl Scripted {
+    /// A stream serving `input`, with the buffer the client's writes go to
+    pub(crate) fn new(input: impl Into<Vec<u8>>) -> (Self, Arc<Mutex<Vec<u8>>>) {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let stream = Self {
+            input: Cursor::new(input.into()),
+            written: written.clone(),
+            max_read: usize::MAX,
+        };
+        (stream, written)
+    }
+
+    /// Serve at most `max_read` bytes per read, so messages arrive split
+    pub(crate) fn with_max_read(mut self, max_read: usize) -> Self {
+        self.max_read = max_read;
+        self
+    }
+}
+
+impl AsyncRead for Scripted {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let len = buf.len().min(self.max_read);
+        Pin::new(&mut self.input).poll_read(cx, &mut buf[..len])
+    }
+}
+
+impl AsyncWrite for Scripted {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
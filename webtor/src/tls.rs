@@ -7,40 +7,470 @@
 //! Note: In WASM, TLS is handled by the browser's native WebSocket (wss://)
 //! and fetch API. The TLS functions here are only for native builds.
 
+use crate::ech::EchConfigs;
 use crate::error::{Result, TorError};
+use crate::isolation::IsolationKey;
+use crate::maintenance::TtlCache;
+use crate::pinning::{check_chain, CertPin};
+use crate::traffic::TorStream;
 use futures::io::{AsyncRead, AsyncWrite};
+use serde::{Deserialize, Serialize};
 use std::io;
 use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 #[cfg(not(target_arch = "wasm32"))]
-use futures_rustls::rustls::{ClientConfig, RootCertStore};
+use crate::error::{is_connection_reset, StreamEndReason};
 #[cfg(not(target_arch = "wasm32"))]
-use futures_rustls::TlsConnector;
+use futures_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use futures_rustls::rustls::client::WebPkiServerVerifier;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_rustls::rustls::client::{ClientSessionMemoryCache, EchConfig, EchMode, Resumption};
 #[cfg(not(target_arch = "wasm32"))]
-use rustls_pki_types::ServerName;
+use futures_rustls::rustls::crypto::CryptoProvider;
+#[cfg(not(target_arch = "wasm32"))]
+use futures_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, PeerIncompatible, RootCertStore, SignatureScheme,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use futures_rustls::TlsConnector;
 #[cfg(not(target_arch = "wasm32"))]
-use std::sync::Arc;
+use rustls_pki_types::{CertificateDer, EchConfigListBytes, ServerName, UnixTime};
 #[cfg(not(target_arch = "wasm32"))]
 use tracing::{debug, info};
 
+/// Trust anchors for TLS through exits, on top of or instead of the
+/// built-in webpki roots
+///
+/// Private deployments and corporate endpoints often chain to a CA no
+/// public root store carries; adding it here lets their certificates verify.
+/// With [`with_replace_builtin`](Self::with_replace_builtin) only these roots
+/// are trusted, which also rules out a mis-issued public certificate.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsRoots {
+    /// DER certificates of the extra roots
+    #[serde(default)]
+    pub certificates: Vec<Vec<u8>>,
+    /// Trust only `certificates`, not the built-in roots
+    #[serde(default)]
+    pub replace_builtin: bool,
+}
+
+impl TlsRoots {
+    /// No extra roots; the built-in ones alone are trusted
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add every certificate of a PEM bundle
+    pub fn with_pem(mut self, pem: &str) -> Result<Self> {
+        self.certificates
+            .extend(crate::pinning::pem_certificates(pem.as_bytes())?);
+        Ok(self)
+    }
+
+    /// Add a DER certificate
+    pub fn with_der(mut self, der: impl Into<Vec<u8>>) -> Result<Self> {
+        let der = der.into();
+        x509_parser::parse_x509_certificate(&der)
+            .map_err(|e| TorError::configuration(format!("Invalid root certificate: {}", e)))?;
+        self.certificates.push(der);
+        Ok(self)
+    }
+
+    /// Trust only the roots added here
+    pub fn with_replace_builtin(mut self, replace: bool) -> Self {
+        self.replace_builtin = replace;
+        self
+    }
+
+    /// Whether the built-in roots are used unchanged
+    pub fn is_empty(&self) -> bool {
+        self.certificates.is_empty() && !self.replace_builtin
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.replace_builtin && self.certificates.is_empty() {
+            return Err(TorError::configuration(
+                "Replacing the built-in TLS roots needs at least one root certificate",
+            ));
+        }
+        Ok(())
+    }
+
+    /// subtle-tls settings for an HTTP/1.1 connection trusting these roots
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn subtle_config(&self, version: subtle_tls::TlsVersion) -> subtle_tls::TlsConfig {
+        subtle_tls::TlsConfig {
+            skip_verification: false,
+            alpn_protocols: vec!["http/1.1".to_string()],
+            version,
+            root_certificates: self.certificates.clone(),
+            only_custom_roots: self.replace_builtin,
+            session_cache: None,
+            ech_config_list: None,
+        }
+    }
+}
+
+/// Isolation partitions sessions are kept for; beyond this the oldest is dropped
+const MAX_SESSION_PARTITIONS: usize = 256;
+
+/// How long a partition's sessions are kept after its first connection
+///
+/// Servers rarely issue tickets that last longer; RFC 8446 caps them at a
+/// week.
+pub const SESSION_PARTITION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Sessions kept per partition for rustls, across all servers
+#[cfg(not(target_arch = "wasm32"))]
+const SESSIONS_PER_PARTITION: usize = 64;
+
+type Partitions = TtlCache<Option<IsolationKey>, TlsSessions>;
+
+/// TLS 1.3 sessions kept for resumption, partitioned by isolation key
+///
+/// A resumed session tells the server it is talking to the client of the
+/// earlier one, so sessions are only resumed under the isolation key that
+/// created them, like cookies in the [`CookieJar`](crate::cookies::CookieJar).
+/// Partitions expire after [`SESSION_PARTITION_TTL`]. Cloning shares the
+/// sessions.
+#[derive(Clone)]
+pub struct TlsSessionCache {
+    partitions: Arc<Mutex<Partitions>>,
+}
+
+impl Default for TlsSessionCache {
+    fn default() -> Self {
+        Self {
+            partitions: Arc::new(Mutex::new(TtlCache::new(SESSION_PARTITION_TTL))),
+        }
+    }
+}
+
+impl TlsSessionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The sessions connections under `isolation_key` may resume
+    pub fn partition(&self, isolation_key: Option<&IsolationKey>) -> TlsSessions {
+        let mut partitions = self
+            .partitions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(sessions) = partitions.get(&isolation_key.cloned()) {
+            return sessions.clone();
+        }
+        if partitions.len() >= MAX_SESSION_PARTITIONS && partitions.purge_expired() == 0 {
+            partitions.pop_oldest();
+        }
+        let sessions = TlsSessions::new();
+        partitions.insert(isolation_key.cloned(), sessions.clone());
+        sessions
+    }
+
+    /// Forget every session
+    pub fn clear(&self) {
+        self.partitions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// The partitions, for [`Maintenance::register_cache`](crate::maintenance::Maintenance::register_cache)
+    pub(crate) fn partitions(&self) -> Arc<Mutex<Partitions>> {
+        self.partitions.clone()
+    }
+}
+
+/// Resumable TLS sessions of one isolation partition, by server name
+#[derive(Clone)]
+pub struct TlsSessions {
+    #[cfg(not(target_arch = "wasm32"))]
+    store: Arc<ClientSessionMemoryCache>,
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) cache: subtle_tls::SessionCache,
+}
+
+impl TlsSessions {
+    fn new() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            store: Arc::new(ClientSessionMemoryCache::new(SESSIONS_PER_PARTITION)),
+            #[cfg(target_arch = "wasm32")]
+            cache: subtle_tls::SessionCache::new(),
+        }
+    }
+
+    #[cfg(all(test, not(target_arch = "wasm32")))]
+    fn same_as(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.store, &other.store)
+    }
+}
+
 /// Create a TLS connector with the default root certificates
 #[cfg(not(target_arch = "wasm32"))]
 pub fn create_tls_connector() -> Result<TlsConnector> {
+    create_tls_connector_with(&TlsRoots::default(), &[], None, None)
+}
+
+/// Create a TLS connector trusting `roots` and offering `alpn_protocols`
+/// (most preferred first; none sends no ALPN extension), resuming and
+/// keeping sessions in `sessions` if given, and encrypting the ClientHello
+/// to `ech_config_list` if given (which limits it to TLS 1.3)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn create_tls_connector_with(
+    roots: &TlsRoots,
+    alpn_protocols: &[String],
+    sessions: Option<&TlsSessions>,
+    ech_config_list: Option<&[u8]>,
+) -> Result<TlsConnector> {
+    roots.validate()?;
     let mut root_store = RootCertStore::empty();
 
     // Add webpki root certificates
-    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if !roots.replace_builtin {
+        root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    }
+    for der in &roots.certificates {
+        root_store
+            .add(CertificateDer::from(der.clone()))
+            .map_err(|e| TorError::configuration(format!("Invalid root certificate: {}", e)))?;
+    }
 
     debug!("Loaded {} root certificates", root_store.len());
 
-    let config = ClientConfig::builder()
+    let builder = ClientConfig::builder_with_provider(Arc::new(firefox_provider()));
+    let builder = match ech_config_list {
+        Some(list) => {
+            let ech = EchConfig::new(EchConfigListBytes::from(list), crate::ech::hpke::SUITES)
+                .map_err(|e| TorError::configuration(format!("Unusable ECH config list: {}", e)))?;
+            builder
+                .with_ech(EchMode::Enable(ech))
+                .map_err(|e| TorError::tls(format!("ECH setup failed: {}", e)))?
+        }
+        None => builder
+            .with_safe_default_protocol_versions()
+            .map_err(|e| TorError::tls(format!("TLS setup failed: {}", e)))?,
+    };
+    let mut config = builder
         .with_root_certificates(root_store)
         .with_no_client_auth();
+    config.alpn_protocols = alpn_protocols
+        .iter()
+        .map(|protocol| protocol.as_bytes().to_vec())
+        .collect();
+    config.resumption = match sessions {
+        Some(sessions) => Resumption::store(sessions.store.clone()),
+        None => Resumption::disabled(),
+    };
 
     Ok(TlsConnector::from(Arc::new(config)))
 }
 
+/// rustls's ring provider with cipher suites and key exchange groups in
+/// the order Firefox lists them (subtle-tls offers them in this order too)
+#[cfg(not(target_arch = "wasm32"))]
+fn firefox_provider() -> CryptoProvider {
+    use futures_rustls::rustls::crypto::ring::{cipher_suite, default_provider, kx_group};
+    CryptoProvider {
+        cipher_suites: vec![
+            cipher_suite::TLS13_AES_128_GCM_SHA256,
+            cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+            cipher_suite::TLS13_AES_256_GCM_SHA384,
+            cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+            cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+            cipher_suite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+            cipher_suite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+            cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+            cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+        ],
+        kx_groups: vec![kx_group::X25519, kx_group::SECP256R1, kx_group::SECP384R1],
+        ..default_provider()
+    }
+}
+
+/// rustls's ring provider with cipher suites and key exchange groups in
+/// the order Chrome lists them
+#[cfg(not(target_arch = "wasm32"))]
+fn chrome_provider() -> CryptoProvider {
+    use futures_rustls::rustls::crypto::ring::{cipher_suite, default_provider, kx_group};
+    CryptoProvider {
+        cipher_suites: vec![
+            cipher_suite::TLS13_AES_128_GCM_SHA256,
+            cipher_suite::TLS13_AES_256_GCM_SHA384,
+            cipher_suite::TLS13_CHACHA20_POLY1305_SHA256,
+            cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+            cipher_suite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+            cipher_suite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+            cipher_suite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+            cipher_suite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+            cipher_suite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+        ],
+        kx_groups: vec![kx_group::X25519, kx_group::SECP256R1, kx_group::SECP384R1],
+        ..default_provider()
+    }
+}
+
+/// Ordering of the ClientHello sent on a connection a censor can watch,
+/// such as the one to a WebTunnel bridge
+///
+/// Besides rustls's defaults, there are two orderings: cipher suites, key
+/// exchange groups and signature algorithms in the order Chrome or Firefox
+/// lists them, with `http/1.1` advertised over ALPN. That is all they change.
+/// The ClientHello is still rustls's. It has rustls's extensions in rustls's
+/// order, no GREASE values and no padding, and only the algorithms rustls
+/// implements. So a fingerprint such as JA3 or JA4 still tells it from a
+/// browser's, and these orderings don't make the connection look like one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TlsFingerprint {
+    /// rustls's defaults
+    #[default]
+    Rustls,
+    /// Algorithms in Chrome's order; not Chrome's ClientHello
+    ChromeOrder,
+    /// Algorithms in Firefox's order; not Firefox's ClientHello
+    FirefoxOrder,
+}
+
+impl TlsFingerprint {
+    /// Signature algorithms to offer, most preferred first
+    #[cfg(not(target_arch = "wasm32"))]
+    fn signature_schemes(self) -> Option<Vec<SignatureScheme>> {
+        use SignatureScheme::*;
+        match self {
+            Self::Rustls => None,
+            Self::ChromeOrder => Some(vec![
+                ECDSA_NISTP256_SHA256,
+                RSA_PSS_SHA256,
+                RSA_PKCS1_SHA256,
+                ECDSA_NISTP384_SHA384,
+                RSA_PSS_SHA384,
+                RSA_PKCS1_SHA384,
+                RSA_PSS_SHA512,
+                RSA_PKCS1_SHA512,
+            ]),
+            Self::FirefoxOrder => Some(vec![
+                ECDSA_NISTP256_SHA256,
+                ECDSA_NISTP384_SHA384,
+                RSA_PSS_SHA256,
+                RSA_PSS_SHA384,
+                RSA_PSS_SHA512,
+                RSA_PKCS1_SHA256,
+                RSA_PKCS1_SHA384,
+                RSA_PKCS1_SHA512,
+            ]),
+        }
+    }
+
+    /// Client configuration verifying servers against `roots` and ordering
+    /// its ClientHello this way
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn client_config(self, roots: RootCertStore) -> Result<ClientConfig> {
+        let provider = Arc::new(match self {
+            Self::Rustls => futures_rustls::rustls::crypto::ring::default_provider(),
+            Self::ChromeOrder => chrome_provider(),
+            Self::FirefoxOrder => firefox_provider(),
+        });
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| TorError::tls(format!("TLS setup failed: {}", e)))?;
+        let Some(schemes) = self.signature_schemes() else {
+            return Ok(builder.with_root_certificates(roots).with_no_client_auth());
+        };
+
+        let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()
+            .map_err(|e| TorError::tls(format!("TLS setup failed: {}", e)))?;
+        let mut config = builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(OrderedSchemes {
+                inner: verifier,
+                schemes,
+            }))
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+impl FromStr for TlsFingerprint {
+    type Err = TorError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rustls" => Ok(Self::Rustls),
+            "chrome-order" => Ok(Self::ChromeOrder),
+            "firefox-order" => Ok(Self::FirefoxOrder),
+            other => Err(TorError::configuration(format!(
+                "Unknown TLS fingerprint: {} (expected rustls, chrome-order or firefox-order)",
+                other
+            ))),
+        }
+    }
+}
+
+/// WebPKI verification offering signature algorithms in a browser's order
+///
+/// The ClientHello lists the algorithms the verifier reports, so this only
+/// changes the order, never what is accepted.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+struct OrderedSchemes {
+    inner: Arc<WebPkiServerVerifier>,
+    schemes: Vec<SignatureScheme>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ServerCertVerifier for OrderedSchemes {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, futures_rustls::rustls::Error> {
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, futures_rustls::rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, futures_rustls::rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        let supported = self.inner.supported_verify_schemes();
+        self.schemes
+            .iter()
+            .copied()
+            .filter(|scheme| supported.contains(scheme))
+            .collect()
+    }
+}
+
 /// Wrap an async stream with TLS encryption
 #[cfg(not(target_arch = "wasm32"))]
 pub async fn wrap_with_tls<S>(
@@ -50,23 +480,200 @@ pub async fn wrap_with_tls<S>(
 where
     S: AsyncRead + AsyncWrite + Unpin + Send,
 {
-    info!("Initiating TLS handshake with {}", domain);
+    handshake(create_tls_connector()?, stream, domain, None).await
+}
 
-    let connector = create_tls_connector()?;
+#[cfg(not(target_arch = "wasm32"))]
+async fn handshake<S>(
+    connector: TlsConnector,
+    stream: S,
+    domain: &str,
+    ech: Option<&EchConfigs>,
+) -> Result<futures_rustls::client::TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    info!("Initiating TLS handshake with {}", domain);
 
     let server_name = ServerName::try_from(domain.to_string())
         .map_err(|e| TorError::tls(format!("Invalid server name '{}': {}", domain, e)))?;
 
-    let tls_stream = connector
-        .connect(server_name, stream)
-        .await
-        .map_err(|e| TorError::tls(format!("TLS handshake failed: {}", e)))?;
+    // A handshake cut off by the exit or the server is kept as an I/O
+    // error, so it's told apart from one the server or certificate failed
+    let tls_stream = connector.connect(server_name, stream).await.map_err(|e| {
+        if is_connection_reset(&e) || StreamEndReason::from_io_error(&e).is_some() {
+            TorError::from(e)
+        } else if let (Some(ech), Some(retry_configs)) = (ech, ech_rejection(&e)) {
+            ech.rejected(domain, retry_configs)
+        } else {
+            TorError::tls(format!("TLS handshake failed: {}", e))
+        }
+    })?;
 
     info!("TLS handshake completed with {}", domain);
 
     Ok(tls_stream)
 }
 
+/// The retry configurations (if any) of a server that rejected ECH, as an
+/// ECHConfigList, when that is what failed the handshake
+#[cfg(not(target_arch = "wasm32"))]
+fn ech_rejection(err: &io::Error) -> Option<Option<Vec<u8>>> {
+    use futures_rustls::rustls::internal::msgs::codec::Codec;
+    match err.get_ref()?.downcast_ref()? {
+        futures_rustls::rustls::Error::PeerIncompatible(
+            PeerIncompatible::ServerRejectedEncryptedClientHello(retry_configs),
+        ) => Some(retry_configs.as_ref().map(|configs| configs.get_encoding())),
+        _ => None,
+    }
+}
+
+/// Like [`wrap_with_tls`], but verify against `roots`, offer
+/// `alpn_protocols`, resume from `sessions`, and fail unless the chain
+/// `domain` presents contains one of `pins` (any chain if there are none)
+///
+/// If `ech` has a configuration for `domain` the ClientHello is encrypted
+/// to it, and a server rejecting that fails with
+/// [`TorError::EchRejected`].
+///
+/// A mismatched stream is dropped before any application data is sent.
+/// A resumed session is checked against the chain of the handshake that
+/// created it.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn wrap_with_tls_pinned<S>(
+    stream: S,
+    domain: &str,
+    roots: &TlsRoots,
+    alpn_protocols: &[String],
+    pins: &[CertPin],
+    sessions: Option<&TlsSessions>,
+    ech: &EchConfigs,
+) -> Result<futures_rustls::client::TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let ech_config_list = ech.get(domain);
+    let connector =
+        create_tls_connector_with(roots, alpn_protocols, sessions, ech_config_list.as_deref())?;
+    let tls_stream = handshake(connector, stream, domain, Some(ech)).await?;
+    let chain = tls_stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .unwrap_or_default();
+    check_chain(domain, pins, chain)?;
+    Ok(tls_stream)
+}
+
+/// The error for a failed subtle-tls handshake with `domain`, keeping any
+/// ECH retry configurations in `ech`
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn subtle_error(domain: &str, ech: &EchConfigs, err: subtle_tls::TlsError) -> TorError {
+    match err {
+        subtle_tls::TlsError::EchRejected { retry_configs } => ech.rejected(domain, retry_configs),
+        e => TorError::tls(format!("TLS handshake failed: {}", e)),
+    }
+}
+
+/// A TLS connection to a server through an exit, from
+/// [`TorClient::connect_tls`](crate::TorClient::connect_tls)
+///
+/// In WASM this is subtle-tls's TLS 1.3 client; servers that only speak
+/// TLS 1.2 are refused.
+pub struct TorTlsStream {
+    #[cfg(not(target_arch = "wasm32"))]
+    inner: futures_rustls::client::TlsStream<TorStream>,
+    #[cfg(target_arch = "wasm32")]
+    inner: subtle_tls::TlsStream<TorStream>,
+}
+
+impl TorTlsStream {
+    /// Handshake with `domain` over `stream`, offering `alpn_protocols`,
+    /// verifying the chain against `roots` and `pins`, resuming from
+    /// `sessions` if given, and encrypting the ClientHello to `domain`'s
+    /// configuration in `ech` if it has one
+    pub(crate) async fn connect(
+        stream: TorStream,
+        domain: &str,
+        roots: &TlsRoots,
+        alpn_protocols: &[String],
+        pins: &[CertPin],
+        sessions: Option<&TlsSessions>,
+        ech: &EchConfigs,
+    ) -> Result<Self> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let inner =
+                wrap_with_tls_pinned(stream, domain, roots, alpn_protocols, pins, sessions, ech)
+                    .await?;
+            Ok(Self { inner })
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            use subtle_tls::{TlsConfig, TlsConnector, TlsVersion};
+
+            // Only subtle-tls's TLS 1.3 stream is an AsyncRead/AsyncWrite
+            roots.validate()?;
+            let connector = TlsConnector::with_config(TlsConfig {
+                alpn_protocols: alpn_protocols.to_vec(),
+                session_cache: sessions.map(|sessions| sessions.cache.clone()),
+                ech_config_list: ech.get(domain),
+                ..roots.subtle_config(TlsVersion::Tls13)
+            });
+            let inner = connector
+                .connect(stream, domain)
+                .await
+                .map_err(|e| subtle_error(domain, ech, e))?;
+            check_chain(domain, pins, inner.peer_certificates())?;
+            Ok(Self { inner })
+        }
+    }
+
+    /// The protocol the server selected by ALPN, if it selected one
+    pub fn alpn_protocol(&self) -> Option<&str> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.inner
+                .get_ref()
+                .1
+                .alpn_protocol()
+                .and_then(|protocol| std::str::from_utf8(protocol).ok())
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.inner.alpn_protocol()
+        }
+    }
+}
+
+impl AsyncRead for TorTlsStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TorTlsStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
 /// TLS stream for direct connections (e.g., WebTunnel bridge)
 /// This wraps a native TCP+TLS connection, not a Tor stream.
 #[cfg(not(target_arch = "wasm32"))]
@@ -259,10 +866,138 @@ impl AsyncWrite for TlsStream {
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn test_create_tls_connector() {
         let connector = create_tls_connector();
         assert!(connector.is_ok());
     }
+
+    const ROOT_PEM: &str = "-----BEGIN CERTIFICATE-----
+MIIBiTCCAS+gAwIBAgIUReB4HiaRj8BvLtEyFQYivAzX+McwCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOcGlubmVkLmV4YW1wbGUwIBcNMjYxMDE2MTg1OTQ1WhgPMjEy
+NjA5MjIxODU5NDVaMBkxFzAVBgNVBAMMDnBpbm5lZC5leGFtcGxlMFkwEwYHKoZI
+zj0CAQYIKoZIzj0DAQcDQgAE4goNhl004VYAyuDjaRtglbJDMXoU8fxXMVqhwGJe
+1FyaCV/rjyzE8hzYbBZScoiAL8hYdJYLskuVRmOMPZxkpaNTMFEwHQYDVR0OBBYE
+FMn79g2C7Js3Jz4Ln+jyQaxJGD3KMB8GA1UdIwQYMBaAFMn79g2C7Js3Jz4Ln+jy
+QaxJGD3KMA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSAAwRQIga/+Qc0jv
+SMmwqMwXjyHuFvbRuGdgBHalKMgl7aYzj+4CIQCNldKbAWvcuEmG9XGTCayP2/fg
+HWlpRRP8dfRkX5c9Jg==
+-----END CERTIFICATE-----
+";
+
+    #[test]
+    fn test_custom_roots() {
+        let roots = TlsRoots::new().with_pem(ROOT_PEM).unwrap();
+        assert_eq!(roots.certificates.len(), 1);
+        assert!(!roots.is_empty());
+        let der = roots.certificates[0].clone();
+        assert_eq!(TlsRoots::new().with_der(der).unwrap(), roots);
+        let replaced = roots.clone().with_replace_builtin(true);
+        assert!(create_tls_connector_with(&replaced, &[], None, None).is_ok());
+
+        // Replacing the built-in roots with nothing would trust no server
+        let replaced = TlsRoots::new().with_replace_builtin(true);
+        let err = create_tls_connector_with(&replaced, &[], None, None);
+        assert_eq!(err.err().unwrap().code(), "CONFIGURATION");
+        assert!(TlsRoots::new().with_der(b"junk".to_vec()).is_err());
+        assert!(TlsRoots::new().with_pem("no certificates").is_err());
+    }
+
+    #[test]
+    fn test_sessions_are_partitioned_by_isolation_key() {
+        use crate::isolation::StreamIsolationPolicy;
+
+        let cache = TlsSessionCache::new();
+        let a = IsolationKey::from_host("a.example", 443, StreamIsolationPolicy::PerDomain);
+        let b = IsolationKey::from_host("b.example", 443, StreamIsolationPolicy::PerDomain);
+        let sessions = cache.partition(a.as_ref());
+        assert!(sessions.same_as(&cache.partition(a.as_ref())));
+        assert!(!sessions.same_as(&cache.partition(b.as_ref())));
+        assert!(!sessions.same_as(&cache.partition(None)));
+
+        // Clearing starts every partition afresh
+        cache.clear();
+        assert!(!sessions.same_as(&cache.partition(a.as_ref())));
+
+        // A full cache drops the oldest partition for a new one
+        for i in 0..MAX_SESSION_PARTITIONS {
+            std::thread::sleep(std::time::Duration::from_micros(10));
+            cache.partition(Some(&IsolationKey::from_string(format!("key{}", i))));
+        }
+        let partitions = cache.partitions();
+        assert_eq!(partitions.lock().unwrap().len(), MAX_SESSION_PARTITIONS);
+        assert!(partitions.lock().unwrap().get(&a).is_none());
+    }
+
+    /// Cipher suites and extensions (by type) of the ClientHello `config` sends
+    fn client_hello(config: ClientConfig) -> (Vec<u16>, HashMap<u16, Vec<u8>>) {
+        let name = ServerName::try_from("bridge.example").unwrap();
+        let mut conn =
+            futures_rustls::rustls::ClientConnection::new(Arc::new(config), name).unwrap();
+        let mut record = Vec::new();
+        conn.write_tls(&mut record).unwrap();
+
+        let u16_at = |pos: usize| u16::from_be_bytes([record[pos], record[pos + 1]]);
+        // Record and handshake headers, version and random
+        let mut pos = 5 + 4 + 2 + 32;
+        pos += 1 + record[pos] as usize;
+        let suites_len = u16_at(pos) as usize;
+        let suites = (0..suites_len / 2)
+            .map(|i| u16_at(pos + 2 + 2 * i))
+            .collect();
+        pos += 2 + suites_len;
+        pos += 1 + record[pos] as usize;
+        let end = pos + 2 + u16_at(pos) as usize;
+        pos += 2;
+        let mut extensions = HashMap::new();
+        while pos < end {
+            let len = u16_at(pos + 2) as usize;
+            extensions.insert(u16_at(pos), record[pos + 4..pos + 4 + len].to_vec());
+            pos += 4 + len;
+        }
+        (suites, extensions)
+    }
+
+    fn roots() -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        roots
+    }
+
+    #[test]
+    fn test_browser_orderings() {
+        const ALPN: u16 = 0x0010;
+        const SIGNATURE_ALGORITHMS: u16 = 0x000d;
+
+        let config = TlsFingerprint::ChromeOrder.client_config(roots()).unwrap();
+        let (suites, extensions) = client_hello(config);
+        assert_eq!(suites[..5], [0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f]);
+        assert_eq!(extensions[&ALPN], b"\x00\x09\x08http/1.1");
+        // ecdsa_secp256r1_sha256, rsa_pss_rsae_sha256, rsa_pkcs1_sha256
+        assert_eq!(extensions[&SIGNATURE_ALGORITHMS][2..8], [4, 3, 8, 4, 4, 1]);
+
+        let config = TlsFingerprint::FirefoxOrder.client_config(roots()).unwrap();
+        let (suites, extensions) = client_hello(config);
+        assert_eq!(suites[..5], [0x1301, 0x1303, 0x1302, 0xc02b, 0xc02f]);
+        // ecdsa_secp256r1_sha256, ecdsa_secp384r1_sha384, rsa_pss_rsae_sha256
+        assert_eq!(extensions[&SIGNATURE_ALGORITHMS][2..8], [4, 3, 5, 3, 8, 4]);
+
+        let config = TlsFingerprint::Rustls.client_config(roots()).unwrap();
+        let (_, extensions) = client_hello(config);
+        assert!(!extensions.contains_key(&ALPN));
+
+        assert_eq!(
+            "chrome-order".parse::<TlsFingerprint>().unwrap(),
+            TlsFingerprint::ChromeOrder
+        );
+        assert_eq!(
+            serde_json::to_value(TlsFingerprint::FirefoxOrder).unwrap(),
+            "firefox-order"
+        );
+        // The old names promised a browser's ClientHello
+        assert!("chrome".parse::<TlsFingerprint>().is_err());
+        assert!("safari".parse::<TlsFingerprint>().is_err());
+    }
 }
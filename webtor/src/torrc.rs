@@ -0,0 +1,296 @@
+//! torrc-style configuration
+//!
+//! Reads the subset of tor's configuration file that maps onto webtor, so a
+//! setup made for little-t tor carries over:
+//!
+//! | Directive | Effect |
+//! |---|---|
+//! | `Bridge <bridge line>` | a bridge; later ones are fallbacks for the first |
+//! | `UseBridges 0\|1` | 1 requires a `Bridge`; 0 connects straight to guards (native only) |
+//! | `ExitNodes <nodes>` | [`exit_nodes`](TorClientOptions::exit_nodes) |
+//! | `ExcludeNodes <nodes>` | [`exclude_nodes`](TorClientOptions::exclude_nodes) |
+//! | `MaxCircuitDirtiness <interval>` | [`max_circuit_dirtiness`](TorClientOptions::max_circuit_dirtiness) |
+//! | `SocksPort [addr:]port` | [`Torrc::socks_ports`] (native only) |
+//!
+//! Keywords are case-insensitive, `#` starts a comment and a line ending in
+//! `\` continues on the next. Node lists are as in [`NodeSet`], and repeated
+//! `ExitNodes` or `ExcludeNodes` lines add to the list. Intervals are a number
+//! with an optional unit (`msec`, `seconds`, `minutes`, `hours`, `days`,
+//! `weeks`, singular or abbreviated), seconds by default.
+//!
+//! Unlike tor, bridges given without `UseBridges` are used; with neither, the
+//! client keeps its default Snowflake bridge. Other directives, such as
+//! `ClientTransportPlugin`, are listed in [`Torrc::ignored`] instead of
+//! failing the parse.
+
+use crate::config::TorClientOptions;
+use crate::error::{Result, TorError};
+use crate::relay::NodeSet;
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Result of parsing a torrc
+#[derive(Debug, Clone, Default)]
+pub struct Torrc {
+    /// Client options the directives describe
+    pub options: TorClientOptions,
+    /// Addresses `SocksPort` asks to listen on; opening the listeners is up
+    /// to the application
+    #[cfg(not(target_arch = "wasm32"))]
+    pub socks_ports: Vec<SocketAddr>,
+    /// Keywords of the directives that were skipped, in file order
+    pub ignored: Vec<String>,
+}
+
+impl FromStr for Torrc {
+    type Err = TorError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let mut torrc = Torrc::default();
+        let mut bridges = Vec::new();
+        let mut use_bridges = None;
+        let mut exit_nodes = Vec::new();
+        let mut exclude_nodes = Vec::new();
+        let mut max_dirtiness = None;
+
+        for (number, line) in logical_lines(text) {
+            let (keyword, value) = match line.split_once(char::is_whitespace) {
+                Some((keyword, value)) => (keyword, value.trim()),
+                None => (line.as_str(), ""),
+            };
+            let at_line = |e: TorError| match e {
+                TorError::Configuration(msg) => {
+                    TorError::configuration(format!("torrc line {}: {}", number, msg))
+                }
+                other => other,
+            };
+            match keyword.to_ascii_lowercase().as_str() {
+                "bridge" => bridges.push(value.to_string()),
+                "usebridges" => use_bridges = Some(parse_bool(value).map_err(at_line)?),
+                "exitnodes" => exit_nodes.extend(node_list(value).map_err(at_line)?),
+                "excludenodes" => exclude_nodes.extend(node_list(value).map_err(at_line)?),
+                "maxcircuitdirtiness" => {
+                    max_dirtiness = Some(parse_interval(value).map_err(at_line)?)
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                "socksport" => {
+                    if let Some(addr) = parse_socks_port(value).map_err(at_line)? {
+                        torrc.socks_ports.push(addr);
+                    }
+                }
+                _ => torrc.ignored.push(keyword.to_string()),
+            }
+        }
+
+        let mut options = match (use_bridges, bridges.is_empty()) {
+            (Some(true), true) => {
+                return Err(TorError::configuration(
+                    "UseBridges 1 needs at least one Bridge line",
+                ))
+            }
+            (Some(false), _) => TorClientOptions::default().with_direct(true),
+            (_, false) => TorClientOptions::from_bridge_lines(&bridges)?,
+            (None, true) => TorClientOptions::default(),
+        };
+        options.exit_nodes = exit_nodes;
+        options.exclude_nodes = exclude_nodes;
+        if let Some(dirtiness) = max_dirtiness {
+            options.max_circuit_dirtiness = dirtiness.as_millis() as u64;
+        }
+        torrc.options = options;
+        Ok(torrc)
+    }
+}
+
+impl TorClientOptions {
+    /// Create options from a torrc (see the [`torrc`](crate::torrc) module
+    /// for the directives understood)
+    pub fn from_torrc(text: &str) -> Result<Self> {
+        Ok(text.parse::<Torrc>()?.options)
+    }
+}
+
+/// Non-empty lines with comments removed and continuations joined, with
+/// the number of the line each starts on
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (index, raw) in text.lines().enumerate() {
+        let content = raw.split('#').next().unwrap_or_default().trim_end();
+        let (content, continues) = match content.strip_suffix('\\') {
+            Some(content) => (content, true),
+            None => (content, false),
+        };
+        let (number, mut line) = pending.take().unwrap_or((index + 1, String::new()));
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(content.trim());
+        if continues {
+            pending = Some((number, line));
+        } else if !line.trim().is_empty() {
+            lines.push((number, line.trim().to_string()));
+        }
+    }
+    if let Some((number, line)) = pending.filter(|(_, line)| !line.trim().is_empty()) {
+        lines.push((number, line.trim().to_string()));
+    }
+    lines
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        other => Err(TorError::configuration(format!(
+            "Expected 0 or 1, got {:?}",
+            other
+        ))),
+    }
+}
+
+/// Entries of a node list, checked up front so mistakes point at the line
+fn node_list(value: &str) -> Result<Vec<String>> {
+    NodeSet::parse([value])?;
+    Ok(value
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// A torrc time interval: a number and an optional unit, seconds by default
+fn parse_interval(value: &str) -> Result<Duration> {
+    let invalid = || TorError::configuration(format!("Invalid interval: {:?}", value));
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let unit_ms: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "msec" | "msecs" | "millisecond" | "milliseconds" => 1,
+        "" | "sec" | "secs" | "second" | "seconds" => 1000,
+        "min" | "mins" | "minute" | "minutes" => 60 * 1000,
+        "hour" | "hours" => 60 * 60 * 1000,
+        "day" | "days" => 24 * 60 * 60 * 1000,
+        "week" | "weeks" => 7 * 24 * 60 * 60 * 1000,
+        _ => return Err(invalid()),
+    };
+    number
+        .checked_mul(unit_ms)
+        .map(Duration::from_millis)
+        .ok_or_else(invalid)
+}
+
+/// The listen address of a `SocksPort` line, or `None` for `0` (disabled);
+/// a bare port listens on localhost and flags after the address are ignored
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_socks_port(value: &str) -> Result<Option<SocketAddr>> {
+    let addr = value.split_whitespace().next().unwrap_or_default();
+    let localhost = |port| Ok(Some(SocketAddr::from((Ipv4Addr::LOCALHOST, port))));
+    match addr {
+        "0" => Ok(None),
+        "auto" => localhost(0),
+        _ => {
+            if let Ok(port) = addr.parse::<u16>() {
+                return localhost(port);
+            }
+            if let Some(port) = addr.strip_prefix("localhost:") {
+                if let Ok(port) = port.parse::<u16>() {
+                    return localhost(port);
+                }
+            }
+            addr.parse().map(Some).map_err(|_| {
+                TorError::configuration(format!("Unsupported SocksPort address: {:?}", addr))
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+
+    #[test]
+    fn test_bridges_and_nodes() {
+        let text = format!(
+            "# Bridges from BridgeDB\n\
+             UseBridges 1\n\
+             ClientTransportPlugin webtunnel exec /usr/bin/webtunnel-client\n\
+             Bridge webtunnel 192.0.2.1:443 {fp} url=https://example.com/path\n\
+             bridge obfs4 192.0.2.2:443 {fp} cert=abc iat-mode=0  # fallback\n\
+             ExitNodes {{de}}, ${fp}~relay,\\\n  nickname\n\
+             ExcludeNodes {{ru}}\n\
+             ExcludeNodes {{by}}\n\
+             MaxCircuitDirtiness 5 minutes\n",
+            fp = FINGERPRINT
+        );
+        let torrc: Torrc = text.parse().unwrap();
+        let options = &torrc.options;
+        assert_eq!(options.bridge.transport(), "webtunnel");
+        assert_eq!(options.bridge_fingerprint.as_deref(), Some(FINGERPRINT));
+        assert_eq!(options.fallback_bridges.len(), 1);
+        assert_eq!(
+            options.exit_nodes,
+            ["{de}", &format!("${}~relay", FINGERPRINT), "nickname"]
+        );
+        assert_eq!(options.exclude_nodes, ["{ru}", "{by}"]);
+        assert_eq!(options.max_circuit_dirtiness, 5 * 60 * 1000);
+        assert_eq!(torrc.ignored, ["ClientTransportPlugin"]);
+    }
+
+    #[test]
+    fn test_use_bridges() {
+        let direct = TorClientOptions::from_torrc("UseBridges 0").unwrap();
+        assert!(direct.direct);
+
+        let default = TorClientOptions::from_torrc("").unwrap();
+        assert!(!default.direct);
+        assert_eq!(default.bridge, TorClientOptions::default().bridge);
+
+        assert!(TorClientOptions::from_torrc("UseBridges 1").is_err());
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        let err = TorClientOptions::from_torrc("UseBridges 1\n\nExitNodes 10.0.0.0/8").unwrap_err();
+        assert!(err.to_string().contains("torrc line 3"), "{}", err);
+        assert!(TorClientOptions::from_torrc("MaxCircuitDirtiness soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("600").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_interval("10 min").unwrap(), Duration::from_secs(600));
+        assert_eq!(
+            parse_interval("2 Hours").unwrap(),
+            Duration::from_secs(7200)
+        );
+        assert_eq!(
+            parse_interval("250 msec").unwrap(),
+            Duration::from_millis(250)
+        );
+        assert!(parse_interval("3 fortnights").is_err());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_socks_ports() {
+        let torrc: Torrc = "SocksPort 9050\nSocksPort 0.0.0.0:9150 IsolateDestAddr\nSocksPort 0"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            torrc.socks_ports,
+            [
+                "127.0.0.1:9050".parse::<SocketAddr>().unwrap(),
+                "0.0.0.0:9150".parse().unwrap()
+            ]
+        );
+        assert!("SocksPort unix:/run/tor/socks".parse::<Torrc>().is_err());
+    }
+}
@@ -0,0 +1,314 @@
+//! Bandwidth accounting
+//!
+//! Every stream opened through the client is wrapped in a [`CountedStream`]
+//! that counts the bytes read from and written to it. Counters form a tree:
+//! a stream's [`TrafficCounter`] is a child of its circuit's, which is a
+//! child of the client's, so each byte is added once at every level and
+//! the totals stay consistent without any bookkeeping when streams or
+//! circuits go away.
+//!
+//! Counts are of stream payload as the application sees it (TLS records
+//! included for HTTPS), not of cells on the wire. The same tree tracks how
+//! many streams are open, which caps streams per circuit.
+
+use futures::io::{AsyncRead, AsyncWrite};
+use serde::Serialize;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tor_proto::client::stream::DataStream;
+
+/// A Tor stream that counts its traffic
+pub type TorStream = CountedStream<DataStream>;
+
+/// Bytes read and written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Traffic {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// Traffic of one circuit, streams included
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitTraffic {
+    pub circuit_id: String,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub open_streams: usize,
+}
+
+/// Client-wide traffic, with a breakdown over the current circuits
+///
+/// Circuits that have since closed count towards the totals only.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrafficStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub open_streams: usize,
+    pub circuits: Vec<CircuitTraffic>,
+}
+
+#[derive(Default)]
+struct Node {
+    read: AtomicU64,
+    written: AtomicU64,
+    open_streams: AtomicUsize,
+    parent: Option<TrafficCounter>,
+}
+
+/// Shared byte counter, adding into its parents as well
+#[derive(Clone, Default)]
+pub struct TrafficCounter {
+    node: Arc<Node>,
+}
+
+impl std::fmt::Debug for TrafficCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TrafficCounter")
+            .field(&self.snapshot())
+            .finish()
+    }
+}
+
+impl TrafficCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A new counter whose bytes are also counted by this one
+    pub fn child(&self) -> Self {
+        Self {
+            node: Arc::new(Node {
+                parent: Some(self.clone()),
+                ..Node::default()
+            }),
+        }
+    }
+
+    pub fn record_read(&self, bytes: usize) {
+        self.for_each_level(|node| {
+            node.read.fetch_add(bytes as u64, Ordering::Relaxed);
+        });
+    }
+
+    pub fn record_written(&self, bytes: usize) {
+        self.for_each_level(|node| {
+            node.written.fetch_add(bytes as u64, Ordering::Relaxed);
+        });
+    }
+
+    /// Streams counted here, directly or through children, that are still open
+    pub fn open_streams(&self) -> usize {
+        self.node.open_streams.load(Ordering::Relaxed)
+    }
+
+    /// Count the caller as an open stream here and in every parent until
+    /// the returned guard is dropped
+    fn open_stream(&self) -> OpenStream {
+        self.for_each_level(|node| {
+            node.open_streams.fetch_add(1, Ordering::Relaxed);
+        });
+        OpenStream(self.clone())
+    }
+
+    /// Apply `f` to this counter and each of its parents
+    fn for_each_level(&self, f: impl Fn(&Node)) {
+        let mut counter = Some(self);
+        while let Some(current) = counter {
+            f(&current.node);
+            counter = current.node.parent.as_ref();
+        }
+    }
+
+    pub fn snapshot(&self) -> Traffic {
+        Traffic {
+            bytes_read: self.node.read.load(Ordering::Relaxed),
+            bytes_written: self.node.written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// An open stream, counted until dropped
+#[derive(Debug)]
+struct OpenStream(TrafficCounter);
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        self.0.for_each_level(|node| {
+            node.open_streams.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+/// Stream wrapper counting bytes into a [`TrafficCounter`]
+///
+/// The stream counts as open in its parents until it's dropped or
+/// [`into_inner`](Self::into_inner) is called.
+#[derive(Debug)]
+pub struct CountedStream<S> {
+    inner: S,
+    counter: TrafficCounter,
+    write_closed: bool,
+    _open: OpenStream,
+}
+
+impl<S> CountedStream<S> {
+    /// Count `inner`'s traffic into a new child of `parent`
+    pub fn new(inner: S, parent: &TrafficCounter) -> Self {
+        Self {
+            inner,
+            counter: parent.child(),
+            write_closed: false,
+            _open: parent.open_stream(),
+        }
+    }
+
+    /// Bytes moved over this stream so far
+    pub fn traffic(&self) -> Traffic {
+        self.counter.snapshot()
+    }
+
+    /// This stream's counter, which stays readable after the stream is consumed
+    pub fn counter(&self) -> TrafficCounter {
+        self.counter.clone()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// The wrapped stream; traffic on it is no longer counted
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Whether [`close_write`](Self::close_write) was called
+    pub fn is_write_closed(&self) -> bool {
+        self.write_closed
+    }
+}
+
+impl<S: AsyncWrite + Unpin> CountedStream<S> {
+    /// Finish writing but keep reading, like `shutdown(SHUT_WR)`
+    ///
+    /// Tor has no half-open streams, so nothing is sent to the exit: it keeps
+    /// relaying the destination's data until the destination closes and the
+    /// exit sends END. Buffered data is flushed and later writes fail with
+    /// `BrokenPipe`. `close` or dropping the stream ends it in both directions.
+    pub async fn close_write(&mut self) -> io::Result<()> {
+        futures::io::AsyncWriteExt::flush(&mut self.inner).await?;
+        self.write_closed = true;
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.counter.record_read(n);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.write_closed {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "stream closed for writing",
+            )));
+        }
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = poll {
+            self.counter.record_written(n);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::{AsyncReadExt, AsyncWriteExt, Cursor};
+
+    #[tokio::test]
+    async fn test_stream_bytes_add_up_the_tree() {
+        let client = TrafficCounter::new();
+        let circuit = client.child();
+        let other_circuit = client.child();
+
+        let mut stream = CountedStream::new(Cursor::new(b"HTTP/1.1 200 OK".to_vec()), &circuit);
+        let mut buf = [0u8; 8];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(b"GET").await.unwrap();
+        assert_eq!(
+            stream.traffic(),
+            Traffic {
+                bytes_read: 8,
+                bytes_written: 3
+            }
+        );
+
+        let mut other = CountedStream::new(Cursor::new(Vec::new()), &other_circuit);
+        other.write_all(b"hello").await.unwrap();
+        assert_eq!(client.open_streams(), 2);
+        drop(other);
+        assert_eq!(
+            (client.open_streams(), other_circuit.open_streams()),
+            (1, 0)
+        );
+
+        assert_eq!(circuit.snapshot().bytes_read, 8);
+        assert_eq!(other_circuit.snapshot().bytes_written, 5);
+        assert_eq!(
+            client.snapshot(),
+            Traffic {
+                bytes_read: 8,
+                bytes_written: 8
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_half_closed_stream_keeps_reading() {
+        let mut stream =
+            CountedStream::new(Cursor::new(b"response".to_vec()), &TrafficCounter::new());
+        stream.close_write().await.unwrap();
+        assert!(stream.is_write_closed());
+
+        let err = stream.write_all(b"more").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        assert_eq!(response, b"response");
+        assert_eq!(stream.traffic().bytes_written, 0);
+    }
+}
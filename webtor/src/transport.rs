@@ -0,0 +1,486 @@
+//! Pluggable transports
+//!
+//! A [`Transport`] opens a stream carrying Tor link TLS to one bridge. The
+//! built-in bridges ([`WebTunnelBridge`](crate::webtunnel::WebTunnelBridge),
+//! [`SnowflakeBridge`](crate::snowflake::SnowflakeBridge),
+//! [`MeekBridge`](crate::meek::MeekBridge) and the obfs4 bridge) implement
+//! it, and so can an application: register the transport under a name with
+//! [`TorClientOptions::with_transport`](crate::TorClientOptions::with_transport)
+//! and select it with [`BridgeType::Custom`](crate::config::BridgeType::Custom).
+//!
+//! A transport that only moves bytes (a WebTransport stream, a proprietary
+//! relay) puts Tor link TLS on top with [`TorLinkStream::handshake`]. In the
+//! browser, [`JsTransport`] does this for a JavaScript object whose
+//! `connect()` resolves to a pair of `readable` and `writable` streams.
+
+use crate::error::{Result, TorError};
+use futures::{AsyncRead, AsyncWrite};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tracing::info;
+
+/// A stream the Tor channel handshake can run over
+pub trait LinkStream:
+    AsyncRead + AsyncWrite + tor_rtcompat::StreamOps + tor_rtcompat::CertifiedConn + Send + Unpin
+{
+}
+
+impl<T> LinkStream for T where
+    T: AsyncRead
+        + AsyncWrite
+        + tor_rtcompat::StreamOps
+        + tor_rtcompat::CertifiedConn
+        + Send
+        + Unpin
+{
+}
+
+impl tor_rtcompat::StreamOps for Box<dyn LinkStream> {
+    fn set_tcp_notsent_lowat(&self, notsent_lowat: u32) -> io::Result<()> {
+        (**self).set_tcp_notsent_lowat(notsent_lowat)
+    }
+
+    fn new_handle(&self) -> Box<dyn tor_rtcompat::StreamOps + Send + Unpin> {
+        (**self).new_handle()
+    }
+}
+
+impl tor_rtcompat::CertifiedConn for Box<dyn LinkStream> {
+    fn export_keying_material(
+        &self,
+        len: usize,
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> io::Result<Vec<u8>> {
+        (**self).export_keying_material(len, label, context)
+    }
+
+    fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
+        (**self).peer_certificate()
+    }
+}
+
+/// A way of reaching a bridge
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+pub trait Transport: Send + Sync {
+    /// Open a stream carrying Tor link TLS to the bridge
+    async fn connect(&self) -> Result<Box<dyn LinkStream>>;
+}
+
+/// Application transports by the name [`BridgeType::Custom`](crate::config::BridgeType::Custom) selects them with
+#[derive(Clone, Default)]
+pub struct TransportRegistry {
+    transports: BTreeMap<String, Arc<dyn Transport>>,
+}
+
+impl TransportRegistry {
+    /// Register `transport` as `name`, replacing any earlier one
+    pub fn register(&mut self, name: impl Into<String>, transport: Arc<dyn Transport>) {
+        self.transports.insert(name.into(), transport);
+    }
+
+    /// The transport registered as `name`
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Transport>> {
+        self.transports
+            .get(name)
+            .cloned()
+            .ok_or_else(|| TorError::configuration(format!("No transport registered as {}", name)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transports.is_empty()
+    }
+}
+
+impl fmt::Debug for TransportRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.transports.keys()).finish()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type TorLinkTls<S> = futures_rustls::client::TlsStream<S>;
+
+#[cfg(target_arch = "wasm32")]
+type TorLinkTls<S> = subtle_tls::TlsStream<S>;
+
+/// Tor link TLS over a byte stream to a bridge's ORPort
+///
+/// Relay certificates are self-signed; the channel handshake authenticates
+/// them against the CERTS cell.
+pub struct TorLinkStream<S> {
+    inner: TorLinkTls<S>,
+}
+
+// Safety: WASM is single-threaded
+#[cfg(target_arch = "wasm32")]
+unsafe impl<S> Send for TorLinkStream<S> {}
+
+impl<S> TorLinkStream<S>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    /// Run the TLS handshake over `conn`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn handshake(conn: S) -> Result<Self> {
+        use crate::webtunnel::TorCertVerifier;
+        use futures_rustls::rustls::pki_types::ServerName;
+        use futures_rustls::rustls::ClientConfig;
+
+        let config = ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(TorCertVerifier))
+            .with_no_client_auth();
+        let sni = ServerName::try_from("www.example.com".to_string())
+            .map_err(|e| TorError::Configuration(format!("Invalid SNI: {}", e)))?;
+        let inner = futures_rustls::TlsConnector::from(Arc::new(config))
+            .connect(sni, conn)
+            .await
+            .map_err(|e| TorError::Network(format!("Tor link TLS handshake failed: {}", e)))?;
+        Ok(Self { inner })
+    }
+
+    /// Run the TLS handshake over `conn`
+    #[cfg(target_arch = "wasm32")]
+    pub async fn handshake(conn: S) -> Result<Self> {
+        use subtle_tls::{TlsConfig, TlsConnector};
+
+        let config = TlsConfig {
+            skip_verification: true, // Tor uses self-signed certs, validated via CERTS cells
+            alpn_protocols: vec![],
+            ..Default::default()
+        };
+        let inner = TlsConnector::with_config(config)
+            .connect(conn, "www.example.com")
+            .await
+            .map_err(|e| TorError::tls(format!("TLS handshake failed: {}", e)))?;
+        Ok(Self { inner })
+    }
+
+    /// Close the stream
+    pub async fn close(&mut self) -> io::Result<()> {
+        info!("Closing Tor link stream");
+        futures::AsyncWriteExt::close(self).await
+    }
+}
+
+impl<S> tor_rtcompat::StreamOps for TorLinkStream<S> {
+    // Default implementation
+}
+
+impl<S> tor_rtcompat::CertifiedConn for TorLinkStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
+        let (_, session) = self.inner.get_ref();
+        Ok(session
+            .peer_certificates()
+            .and_then(|certs| certs.first().map(|c| Vec::from(c.as_ref()))))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.inner.peer_certificate().map(|cert| cert.to_vec()))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn export_keying_material(
+        &self,
+        len: usize,
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> io::Result<Vec<u8>> {
+        let (_, session) = self.inner.get_ref();
+        session
+            .export_keying_material(Vec::with_capacity(len), label, context)
+            .map_err(io::Error::other)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn export_keying_material(
+        &self,
+        len: usize,
+        _label: &[u8],
+        _context: Option<&[u8]>,
+    ) -> io::Result<Vec<u8>> {
+        // Same placeholder as the Snowflake stream; RFC 5705 export is not implemented
+        tracing::warn!("export_keying_material called but not fully implemented");
+        Ok(vec![0u8; len])
+    }
+}
+
+impl<S> AsyncRead for TorLinkStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for TorLinkStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub use js::JsTransport;
+
+#[cfg(target_arch = "wasm32")]
+mod js {
+    use super::{LinkStream, TorLinkStream, Transport};
+    use crate::error::{Result, TorError};
+    use futures::{AsyncRead, AsyncWrite, Future};
+    use js_sys::{Function, Promise, Reflect, Uint8Array};
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use wasm_bindgen::{JsCast, JsValue};
+    use wasm_bindgen_futures::JsFuture;
+
+    /// A transport implemented in JavaScript
+    ///
+    /// The object's `connect()` returns (a promise of) an object with a
+    /// `readable` stream of `Uint8Array` chunks from the bridge and a
+    /// `writable` stream taking chunks to it, like a WebTransport
+    /// bidirectional stream. The bytes go to the bridge's ORPort; Tor link
+    /// TLS runs on top.
+    pub struct JsTransport {
+        object: JsValue,
+    }
+
+    // Safety: WASM is single-threaded
+    unsafe impl Send for JsTransport {}
+    unsafe impl Sync for JsTransport {}
+
+    impl JsTransport {
+        /// Wrap `object`, which must have a `connect` method
+        pub fn new(object: JsValue) -> Result<Self> {
+            method(&object, "connect")?;
+            Ok(Self { object })
+        }
+    }
+
+    #[async_trait::async_trait(?Send)]
+    impl Transport for JsTransport {
+        async fn connect(&self) -> Result<Box<dyn LinkStream>> {
+            let conn = call(&self.object, "connect", &[]).await?;
+            let get = |name: &str| {
+                Reflect::get(&conn, &JsValue::from_str(name))
+                    .ok()
+                    .filter(|value| value.is_object())
+                    .ok_or_else(|| {
+                        TorError::configuration(format!(
+                            "Transport connection has no {} stream",
+                            name
+                        ))
+                    })
+            };
+            let reader = call(&get("readable")?, "getReader", &[]).await?;
+            let writer = call(&get("writable")?, "getWriter", &[]).await?;
+            let stream = JsByteStream {
+                reader,
+                writer,
+                read: None,
+                write: None,
+                buf: Vec::new(),
+                pos: 0,
+            };
+            Ok(Box::new(TorLinkStream::handshake(stream).await?))
+        }
+    }
+
+    fn method(object: &JsValue, name: &str) -> Result<Function> {
+        Reflect::get(object, &JsValue::from_str(name))
+            .ok()
+            .and_then(|value| value.dyn_into::<Function>().ok())
+            .ok_or_else(|| TorError::configuration(format!("Transport object has no {}()", name)))
+    }
+
+    /// Call `object.name(args)` and await the result if it is a promise
+    async fn call(object: &JsValue, name: &str, args: &[JsValue]) -> Result<JsValue> {
+        let value = call_sync(object, name, args)?;
+        match value.dyn_into::<Promise>() {
+            Ok(promise) => JsFuture::from(promise).await.map_err(js_error),
+            Err(value) => Ok(value),
+        }
+    }
+
+    fn call_sync(object: &JsValue, name: &str, args: &[JsValue]) -> Result<JsValue> {
+        let args: js_sys::Array = args.iter().collect();
+        method(object, name)?.apply(object, &args).map_err(js_error)
+    }
+
+    fn js_error(e: JsValue) -> TorError {
+        TorError::Network(format!("Transport failed: {:?}", e))
+    }
+
+    fn io_error(e: TorError) -> io::Error {
+        io::Error::new(io::ErrorKind::BrokenPipe, e.to_string())
+    }
+
+    /// Pending promise of a stream reader or writer
+    fn pending(result: Result<JsValue>) -> Result<JsFuture> {
+        Ok(JsFuture::from(Promise::resolve(&result?)))
+    }
+
+    /// Bytes over the reader and writer of a JavaScript stream pair
+    struct JsByteStream {
+        reader: JsValue,
+        writer: JsValue,
+        read: Option<JsFuture>,
+        write: Option<JsFuture>,
+        buf: Vec<u8>,
+        pos: usize,
+    }
+
+    // Safety: WASM is single-threaded
+    unsafe impl Send for JsByteStream {}
+
+    impl JsByteStream {
+        /// Wait for the previous write to be taken by the stream
+        fn poll_write_done(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            if let Some(write) = &mut self.write {
+                let result = futures::ready!(Pin::new(write).poll(cx));
+                self.write = None;
+                result.map_err(|e| io_error(js_error(e)))?;
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncRead for JsByteStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = &mut *self;
+            while this.pos == this.buf.len() {
+                if this.read.is_none() {
+                    this.read =
+                        Some(pending(call_sync(&this.reader, "read", &[])).map_err(io_error)?);
+                }
+                let read = this.read.as_mut().expect("read just started");
+                let result = futures::ready!(Pin::new(read).poll(cx));
+                this.read = None;
+                let chunk = result.map_err(|e| io_error(js_error(e)))?;
+                let done = Reflect::get(&chunk, &JsValue::from_str("done"))
+                    .map(|done| done.is_truthy())
+                    .unwrap_or(true);
+                if done {
+                    return Poll::Ready(Ok(0));
+                }
+                let value = Reflect::get(&chunk, &JsValue::from_str("value"))
+                    .map_err(|e| io_error(js_error(e)))?;
+                this.buf = Uint8Array::new(&value).to_vec();
+                this.pos = 0;
+            }
+            let n = buf.len().min(this.buf.len() - this.pos);
+            buf[..n].copy_from_slice(&this.buf[this.pos..this.pos + n]);
+            this.pos += n;
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncWrite for JsByteStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            futures::ready!(self.poll_write_done(cx))?;
+            let chunk: JsValue = Uint8Array::from(buf).into();
+            self.write =
+                Some(pending(call_sync(&self.writer, "write", &[chunk])).map_err(io_error)?);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            self.poll_write_done(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            futures::ready!(self.poll_write_done(cx))?;
+            // The writer's close() settles once the far end has everything;
+            // the reader is cancelled so the far end sees the close too
+            let _ = call_sync(&self.reader, "cancel", &[]);
+            let _ = call_sync(&self.writer, "close", &[]);
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BridgeConfig, BridgeType};
+
+    struct Unreachable;
+
+    #[async_trait::async_trait]
+    impl Transport for Unreachable {
+        async fn connect(&self) -> Result<Box<dyn LinkStream>> {
+            Err(TorError::Network("unreachable".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = TransportRegistry::default();
+        assert!(registry.get("webtransport").is_err());
+
+        registry.register("webtransport", Arc::new(Unreachable));
+        assert!(registry.get("webtransport").is_ok());
+        assert!(registry.get("relay").is_err());
+        assert_eq!(format!("{:?}", registry), r#"{"webtransport"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_custom_bridge() {
+        let bridge = BridgeType::Custom {
+            name: "webtransport".to_string(),
+        };
+        assert_eq!(bridge.transport(), "webtransport");
+        assert_eq!(bridge.endpoint(), "");
+        assert!(BridgeConfig::new(bridge, None).identity().is_err());
+
+        let options = crate::TorClientOptions::custom(
+            "webtransport".to_string(),
+            "2B280B23E1107BB62ABFC40DDCC8824814F80A72".to_string(),
+        )
+        .with_transport("webtransport", Arc::new(Unreachable));
+        let transport = options.transports.get("webtransport").unwrap();
+        assert!(matches!(
+            transport.connect().await,
+            Err(TorError::Network(_))
+        ));
+    }
+}
@@ -0,0 +1,443 @@
+//! Per-transport traffic and latency statistics
+//!
+//! Slowness can come from the bridge transport (a congested Snowflake
+//! proxy, a distant WebTunnel server) or from the relays further along the
+//! circuit. [`MeteredStream`] sits between a transport and the Tor channel
+//! and records, per transport, the bytes and cells moving in each
+//! direction and the round-trip time to the bridge.
+//!
+//! The stream carries plain Tor cells (link TLS is inside the transport),
+//! so the RTT comes from cells the bridge answers itself, with no extra
+//! traffic: the VERSIONS exchange that starts every channel and each
+//! CREATE_FAST/CREATE2 for a circuit's first hop. Cells travelling through
+//! circuits are answered by later relays and are not timed.
+
+use crate::time::Instant;
+use futures::{AsyncRead, AsyncWrite};
+use serde::Serialize;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+const CMD_CREATE: u8 = 1;
+const CMD_CREATED: u8 = 2;
+const CMD_DESTROY: u8 = 4;
+const CMD_CREATE_FAST: u8 = 5;
+const CMD_CREATED_FAST: u8 = 6;
+const CMD_VERSIONS: u8 = 7;
+const CMD_CREATE2: u8 = 10;
+const CMD_CREATED2: u8 = 11;
+
+/// Body length of a fixed-length cell
+const CELL_BODY_LEN: usize = 509;
+
+/// First-hop handshakes awaiting a reply, beyond which new ones aren't timed
+const MAX_PENDING: usize = 64;
+
+/// Traffic and latency counters for one transport, shared by its channels
+#[derive(Clone, Default)]
+pub struct TransportStats {
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    connections: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    cells_sent: AtomicU64,
+    cells_received: AtomicU64,
+    rtt_samples: AtomicU64,
+    /// Microseconds; 0 until the first sample
+    srtt_us: AtomicU64,
+    min_rtt_us: AtomicU64,
+    last_rtt_us: AtomicU64,
+}
+
+/// Point-in-time copy of one transport's counters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportStatsSnapshot {
+    /// Transport name, as in bridge lines
+    pub transport: String,
+    /// Channels opened over the transport
+    pub connections: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub cells_sent: u64,
+    pub cells_received: u64,
+    pub rtt_samples: u64,
+    /// Smoothed round-trip time to the bridge (as TCP's SRTT)
+    pub srtt_ms: Option<u64>,
+    pub min_rtt_ms: Option<u64>,
+    pub last_rtt_ms: Option<u64>,
+}
+
+impl TransportStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record_connection(&self) {
+        self.counters.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_sent(&self, bytes: usize, cells: u64) {
+        let c = &self.counters;
+        c.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        c.cells_sent.fetch_add(cells, Ordering::Relaxed);
+    }
+
+    fn record_received(&self, bytes: usize, cells: u64) {
+        let c = &self.counters;
+        c.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+        c.cells_received.fetch_add(cells, Ordering::Relaxed);
+    }
+
+    /// Add a round-trip time sample
+    pub fn record_rtt(&self, rtt: Duration) {
+        let c = &self.counters;
+        let sample = (rtt.as_micros() as u64).max(1);
+        c.rtt_samples.fetch_add(1, Ordering::Relaxed);
+        c.last_rtt_us.store(sample, Ordering::Relaxed);
+        let _ = c
+            .srtt_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |srtt| {
+                Some(if srtt == 0 {
+                    sample
+                } else {
+                    (srtt * 7 + sample) / 8
+                })
+            });
+        let _ = c
+            .min_rtt_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |min| {
+                (min == 0 || sample < min).then_some(sample)
+            });
+    }
+
+    pub fn snapshot(&self, transport: &str) -> TransportStatsSnapshot {
+        let c = &self.counters;
+        let ms = |us: &AtomicU64| match us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(us / 1000),
+        };
+        TransportStatsSnapshot {
+            transport: transport.to_string(),
+            connections: c.connections.load(Ordering::Relaxed),
+            bytes_sent: c.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: c.bytes_received.load(Ordering::Relaxed),
+            cells_sent: c.cells_sent.load(Ordering::Relaxed),
+            cells_received: c.cells_received.load(Ordering::Relaxed),
+            rtt_samples: c.rtt_samples.load(Ordering::Relaxed),
+            srtt_ms: ms(&c.srtt_us),
+            min_rtt_ms: ms(&c.min_rtt_us),
+            last_rtt_ms: ms(&c.last_rtt_us),
+        }
+    }
+}
+
+/// Splits one direction of a channel's byte stream into cells
+#[derive(Debug, Default)]
+struct CellScanner {
+    /// The first cell (VERSIONS) has a 2-byte circuit ID, later ones 4
+    past_versions: bool,
+    header: Vec<u8>,
+    /// Body bytes of the current cell still to skip
+    remaining: usize,
+}
+
+impl CellScanner {
+    /// Consume `bytes`, calling `on_cell(circ_id, command)` for each cell
+    /// whose header completes
+    fn scan(&mut self, mut bytes: &[u8], mut on_cell: impl FnMut(u32, u8)) {
+        loop {
+            let skip = self.remaining.min(bytes.len());
+            self.remaining -= skip;
+            bytes = &bytes[skip..];
+            if bytes.is_empty() {
+                return;
+            }
+
+            let circ_id_len = if self.past_versions { 4 } else { 2 };
+            let header_len = match self.header.get(circ_id_len) {
+                Some(&command) if is_var_cell(command) => circ_id_len + 3,
+                _ => circ_id_len + 1,
+            };
+            let take = (header_len - self.header.len()).min(bytes.len());
+            self.header.extend_from_slice(&bytes[..take]);
+            bytes = &bytes[take..];
+            let command = match self.header.get(circ_id_len) {
+                Some(&command) if self.header.len() == header_len => command,
+                _ => continue,
+            };
+            // A variable-length command just read still needs its length
+            if is_var_cell(command) && header_len == circ_id_len + 1 {
+                continue;
+            }
+
+            self.remaining = if is_var_cell(command) {
+                u16::from_be_bytes([self.header[circ_id_len + 1], self.header[circ_id_len + 2]])
+                    as usize
+            } else {
+                CELL_BODY_LEN
+            };
+            let circ_id = self.header[..circ_id_len]
+                .iter()
+                .fold(0u32, |id, byte| (id << 8) | u32::from(*byte));
+            on_cell(circ_id, command);
+            self.header.clear();
+            self.past_versions = true;
+        }
+    }
+}
+
+fn is_var_cell(command: u8) -> bool {
+    command == CMD_VERSIONS || command >= 128
+}
+
+/// Cell counting and RTT timing for one channel
+#[derive(Debug, Default)]
+struct ChannelMeter {
+    sent: CellScanner,
+    received: CellScanner,
+    versions_sent: Option<Instant>,
+    versions_timed: bool,
+    /// First-hop handshakes sent and not yet answered, by circuit ID
+    pending: Vec<(u32, Instant)>,
+}
+
+impl ChannelMeter {
+    /// Returns the number of cells started in `bytes`
+    fn on_sent(&mut self, bytes: &[u8], now: Instant) -> u64 {
+        let mut cells = 0;
+        let (versions_sent, pending) = (&mut self.versions_sent, &mut self.pending);
+        self.sent.scan(bytes, |circ_id, command| {
+            cells += 1;
+            match command {
+                CMD_VERSIONS if versions_sent.is_none() => *versions_sent = Some(now),
+                CMD_CREATE | CMD_CREATE_FAST | CMD_CREATE2 if pending.len() < MAX_PENDING => {
+                    pending.push((circ_id, now))
+                }
+                _ => {}
+            }
+        });
+        cells
+    }
+
+    /// Returns the number of cells started in `bytes` and the round-trip
+    /// times they complete
+    fn on_received(&mut self, bytes: &[u8], now: Instant) -> (u64, Vec<Duration>) {
+        let mut cells = 0;
+        let mut rtts = Vec::new();
+        let (versions_sent, versions_timed, pending) = (
+            &self.versions_sent,
+            &mut self.versions_timed,
+            &mut self.pending,
+        );
+        self.received.scan(bytes, |circ_id, command| {
+            cells += 1;
+            match command {
+                CMD_VERSIONS if !*versions_timed => {
+                    if let Some(sent) = versions_sent {
+                        rtts.push(now.duration_since(*sent));
+                        *versions_timed = true;
+                    }
+                }
+                CMD_CREATED | CMD_CREATED_FAST | CMD_CREATED2 | CMD_DESTROY => {
+                    if let Some(index) = pending.iter().position(|(id, _)| *id == circ_id) {
+                        let (_, sent) = pending.swap_remove(index);
+                        if command != CMD_DESTROY {
+                            rtts.push(now.duration_since(sent));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        });
+        (cells, rtts)
+    }
+}
+
+/// A transport stream that records its traffic into [`TransportStats`]
+pub struct MeteredStream<S> {
+    inner: S,
+    stats: TransportStats,
+    meter: ChannelMeter,
+}
+
+impl<S> MeteredStream<S> {
+    /// Meter `inner`, a newly opened channel's stream
+    pub fn new(inner: S, stats: TransportStats) -> Self {
+        stats.record_connection();
+        Self {
+            inner,
+            stats,
+            meter: ChannelMeter::default(),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MeteredStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = &result {
+            let (cells, rtts) = this.meter.on_received(&buf[..*n], Instant::now());
+            this.stats.record_received(*n, cells);
+            for rtt in rtts {
+                this.stats.record_rtt(rtt);
+            }
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MeteredStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = &result {
+            let cells = this.meter.on_sent(&buf[..*n], Instant::now());
+            this.stats.record_sent(*n, cells);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl<S: tor_rtcompat::StreamOps> tor_rtcompat::StreamOps for MeteredStream<S> {
+    fn set_tcp_notsent_lowat(&self, notsent_lowat: u32) -> io::Result<()> {
+        self.inner.set_tcp_notsent_lowat(notsent_lowat)
+    }
+
+    fn new_handle(&self) -> Box<dyn tor_rtcompat::StreamOps + Send + Unpin> {
+        self.inner.new_handle()
+    }
+}
+
+impl<S: tor_rtcompat::CertifiedConn> tor_rtcompat::CertifiedConn for MeteredStream<S> {
+    fn export_keying_material(
+        &self,
+        len: usize,
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> io::Result<Vec<u8>> {
+        self.inner.export_keying_material(len, label, context)
+    }
+
+    fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
+        self.inner.peer_certificate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions_cell() -> Vec<u8> {
+        vec![0, 0, CMD_VERSIONS, 0, 4, 0, 4, 0, 5]
+    }
+
+    fn fixed_cell(circ_id: u32, command: u8) -> Vec<u8> {
+        let mut cell = circ_id.to_be_bytes().to_vec();
+        cell.push(command);
+        cell.resize(4 + 1 + CELL_BODY_LEN, 0);
+        cell
+    }
+
+    fn var_cell(circ_id: u32, command: u8, body_len: u16) -> Vec<u8> {
+        let mut cell = circ_id.to_be_bytes().to_vec();
+        cell.push(command);
+        cell.extend_from_slice(&body_len.to_be_bytes());
+        cell.resize(cell.len() + body_len as usize, 0);
+        cell
+    }
+
+    #[test]
+    fn test_scanner_handles_split_cells() {
+        let stream = [
+            versions_cell(),
+            var_cell(0, 129, 300),
+            fixed_cell(0x8000_0001, CMD_CREATE_FAST),
+        ]
+        .concat();
+
+        let mut cells = Vec::new();
+        let mut scanner = CellScanner::default();
+        // Feed a byte at a time, the worst case for header reassembly
+        for byte in &stream {
+            scanner.scan(std::slice::from_ref(byte), |circ_id, command| {
+                cells.push((circ_id, command))
+            });
+        }
+        assert_eq!(
+            cells,
+            vec![(0, CMD_VERSIONS), (0, 129), (0x8000_0001, CMD_CREATE_FAST)]
+        );
+        assert_eq!(scanner.remaining, 0);
+        assert!(scanner.header.is_empty());
+    }
+
+    #[test]
+    fn test_meter_times_bridge_replies() {
+        let mut meter = ChannelMeter::default();
+        let start = Instant::now();
+
+        assert_eq!(meter.on_sent(&versions_cell(), start), 1);
+        let (cells, rtts) = meter.on_received(&versions_cell(), start + Duration::from_millis(80));
+        assert_eq!(cells, 1);
+        assert_eq!(rtts, vec![Duration::from_millis(80)]);
+
+        let sent = [
+            fixed_cell(0x8000_0001, CMD_CREATE_FAST),
+            fixed_cell(0x8000_0002, CMD_CREATE2),
+        ]
+        .concat();
+        assert_eq!(meter.on_sent(&sent, start), 2);
+
+        let received = [
+            fixed_cell(0x8000_0002, CMD_DESTROY),
+            fixed_cell(0x8000_0001, CMD_CREATED_FAST),
+            // Answered by a later hop, so not timed
+            fixed_cell(0x8000_0001, 3),
+        ]
+        .concat();
+        let (cells, rtts) = meter.on_received(&received, start + Duration::from_millis(120));
+        assert_eq!(cells, 3);
+        assert_eq!(rtts, vec![Duration::from_millis(120)]);
+        assert!(meter.pending.is_empty());
+    }
+
+    #[test]
+    fn test_stats_smooth_rtt() {
+        let stats = TransportStats::new();
+        assert_eq!(stats.snapshot("webtunnel").srtt_ms, None);
+
+        stats.record_rtt(Duration::from_millis(80));
+        stats.record_rtt(Duration::from_millis(160));
+        let snapshot = stats.snapshot("webtunnel");
+        assert_eq!(snapshot.rtt_samples, 2);
+        assert_eq!(snapshot.srtt_ms, Some(90));
+        assert_eq!(snapshot.min_rtt_ms, Some(80));
+        assert_eq!(snapshot.last_rtt_ms, Some(160));
+    }
+}
@@ -0,0 +1,231 @@
+//! Vanguards-lite: pinned second-layer guards
+//!
+//! An adversary who can make the client build many circuits (for example to
+//! an onion service, or by keeping a long-lived circuit busy) can run a few
+//! middle relays and wait to be picked as the second hop, which tells them the
+//! first hop. Vanguards-lite (proposal 333) closes that gap by choosing the
+//! second hop of such circuits from a small pinned set of layer-2 guards
+//! instead of the whole network.
+//!
+//! Each of the [`NUM_LAYER2_GUARDS`] guards is kept for a random lifetime of
+//! one to twelve days (skewed towards the longer end), and is replaced early
+//! if it leaves the consensus or loses the Guard flag. The set is persisted
+//! through a [`StateStore`] so it survives across sessions like the entry
+//! guards do.
+
+use crate::error::Result;
+use crate::guard::{now_secs, GuardCandidate};
+use crate::storage::StateStore;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Number of layer-2 guards kept in the set
+pub const NUM_LAYER2_GUARDS: usize = 4;
+
+/// Shortest layer-2 guard lifetime in seconds
+pub const LAYER2_MIN_LIFETIME_SECS: u64 = 24 * 60 * 60;
+
+/// Longest layer-2 guard lifetime in seconds
+pub const LAYER2_MAX_LIFETIME_SECS: u64 = 12 * 24 * 60 * 60;
+
+/// Storage key for the persisted layer-2 guard set
+pub(crate) const STATE_KEY: &str = "vanguards";
+
+/// A pinned second-hop relay (times are Unix seconds)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layer2Guard {
+    pub fingerprint: String,
+    pub nickname: String,
+    pub added_at: u64,
+    pub expires_at: u64,
+}
+
+/// The current layer-2 guards
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layer2GuardSet {
+    pub guards: Vec<Layer2Guard>,
+}
+
+impl Layer2GuardSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop expired or delisted guards and refill the set from `candidates`
+    ///
+    /// `candidates` are the relays currently eligible as layer-2 guards
+    /// (Fast, Stable and Guard). Returns whether the set changed.
+    pub fn update(&mut self, candidates: &[GuardCandidate], now: u64) -> bool {
+        let before = self.guards.len();
+        self.guards.retain(|guard| {
+            now < guard.expires_at
+                && candidates
+                    .iter()
+                    .any(|c| c.fingerprint.eq_ignore_ascii_case(&guard.fingerprint))
+        });
+        let rotated = before - self.guards.len();
+        if rotated > 0 {
+            info!("Rotated out {} layer-2 guards", rotated);
+        }
+
+        let mut pool: Vec<&GuardCandidate> = candidates
+            .iter()
+            .filter(|c| !self.contains(&c.fingerprint))
+            .collect();
+        let mut rng = rand::thread_rng();
+        let mut added = 0;
+        while self.guards.len() < NUM_LAYER2_GUARDS && !pool.is_empty() {
+            let idx = {
+                let indices: Vec<usize> = (0..pool.len()).collect();
+                indices
+                    .choose_weighted(&mut rng, |&i| pool[i].weight)
+                    .ok()
+                    .or_else(|| indices.choose(&mut rng))
+                    .copied()
+                    .unwrap_or(0)
+            };
+            let candidate = pool.swap_remove(idx);
+            debug!("Picked layer-2 guard {}", candidate.nickname);
+            self.guards.push(Layer2Guard {
+                fingerprint: candidate.fingerprint.to_uppercase(),
+                nickname: candidate.nickname.clone(),
+                added_at: now,
+                expires_at: now + random_lifetime(&mut rng),
+            });
+            added += 1;
+        }
+
+        rotated > 0 || added > 0
+    }
+
+    pub fn contains(&self, fingerprint: &str) -> bool {
+        self.guards
+            .iter()
+            .any(|g| g.fingerprint.eq_ignore_ascii_case(fingerprint))
+    }
+
+    /// Fingerprints (upper-case hex) of the layer-2 guards
+    pub fn fingerprints(&self) -> Vec<String> {
+        self.guards.iter().map(|g| g.fingerprint.clone()).collect()
+    }
+}
+
+/// Lifetime of a new layer-2 guard: the larger of two uniform draws, as in
+/// proposal 333, so short lifetimes are less likely than long ones
+fn random_lifetime<R: Rng>(rng: &mut R) -> u64 {
+    let a = rng.gen_range(LAYER2_MIN_LIFETIME_SECS..=LAYER2_MAX_LIFETIME_SECS);
+    let b = rng.gen_range(LAYER2_MIN_LIFETIME_SECS..=LAYER2_MAX_LIFETIME_SECS);
+    a.max(b)
+}
+
+/// Layer-2 guard set bound to the store it is persisted in
+pub struct VanguardManager {
+    guards: Layer2GuardSet,
+    store: Arc<dyn StateStore>,
+}
+
+impl VanguardManager {
+    /// Load the persisted set, starting empty if none is stored or it is unreadable
+    pub fn load(store: Arc<dyn StateStore>) -> Self {
+        let guards = match store.load(STATE_KEY) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Discarding unreadable layer-2 guard state: {}", e);
+                Layer2GuardSet::new()
+            }),
+            Ok(None) => Layer2GuardSet::new(),
+            Err(e) => {
+                warn!("Failed to load layer-2 guard state: {}", e);
+                Layer2GuardSet::new()
+            }
+        };
+        debug!("Loaded {} layer-2 guards", guards.guards.len());
+        Self { guards, store }
+    }
+
+    pub fn guards(&self) -> &Layer2GuardSet {
+        &self.guards
+    }
+
+    /// Refresh the set against the current candidates, persisting any change
+    pub fn update(&mut self, candidates: &[GuardCandidate]) {
+        if self.guards.update(candidates, now_secs()) {
+            self.persist();
+        }
+    }
+
+    /// Write the set to the store
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(&self.guards)?;
+        self.store.store(STATE_KEY, &json)
+    }
+
+    fn persist(&self) {
+        // A lost set is simply re-drawn, so a failing store shouldn't fail circuits
+        if let Err(e) = self.save() {
+            warn!("Failed to persist layer-2 guard state: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+
+    fn candidates(n: usize) -> Vec<GuardCandidate> {
+        (0..n)
+            .map(|i| GuardCandidate {
+                fingerprint: format!("{:040X}", i),
+                nickname: format!("relay{}", i),
+                weight: 100,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_set_is_pinned_until_expiry_or_delisting() {
+        let pool = candidates(20);
+        let mut set = Layer2GuardSet::new();
+        assert!(set.update(&pool, 0));
+        assert_eq!(set.guards.len(), NUM_LAYER2_GUARDS);
+        for guard in &set.guards {
+            let lifetime = guard.expires_at - guard.added_at;
+            assert!((LAYER2_MIN_LIFETIME_SECS..=LAYER2_MAX_LIFETIME_SECS).contains(&lifetime));
+        }
+
+        // Unchanged while every guard is listed and unexpired
+        let before = set.clone();
+        assert!(!set.update(&pool, LAYER2_MIN_LIFETIME_SECS - 1));
+        assert_eq!(set, before);
+
+        // A delisted guard is replaced straight away
+        let gone = set.guards[0].fingerprint.clone();
+        let remaining: Vec<GuardCandidate> = pool
+            .iter()
+            .filter(|c| c.fingerprint != gone)
+            .cloned()
+            .collect();
+        assert!(set.update(&remaining, 10));
+        assert!(!set.contains(&gone));
+        assert_eq!(set.guards.len(), NUM_LAYER2_GUARDS);
+
+        // Everything has rotated once the longest lifetime has passed
+        let later = 10 + LAYER2_MAX_LIFETIME_SECS;
+        set.update(&pool, later);
+        assert!(set.guards.iter().all(|g| g.added_at == later));
+    }
+
+    #[test]
+    fn test_set_persists_across_sessions() {
+        let store: Arc<dyn StateStore> = Arc::new(MemoryStore::new());
+        let mut manager = VanguardManager::load(store.clone());
+        manager.update(&candidates(10));
+
+        let reloaded = VanguardManager::load(store);
+        assert_eq!(reloaded.guards(), manager.guards());
+        assert_eq!(reloaded.guards().fingerprints().len(), NUM_LAYER2_GUARDS);
+    }
+}
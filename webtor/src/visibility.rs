@@ -0,0 +1,116 @@
+//! Page visibility
+//!
+//! Browsers throttle the timers of hidden tabs and may freeze them outright,
+//! so a client in a background tab keeps padding a channel it can barely
+//! service. Following the Page Visibility API, a client can suspend itself
+//! while its page is hidden (see [`VisibilityPolicy`]) and, once the page
+//! is visible again, check the channel and circuits survived before the
+//! next request needs them. [`TorClient::suspend`](crate::TorClient::suspend)
+//! and [`TorClient::resume`](crate::TorClient::resume) do the same for
+//! apps that learn about backgrounding some other way.
+
+use crate::error::{Result, TorError};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// What the client does while its page is hidden
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VisibilityPolicy {
+    /// Carry on as if visible
+    #[default]
+    Ignore,
+    /// Pause channel padding and bridge probing
+    Suspend,
+    /// Also close the channel and circuits as in dormant mode, unless
+    /// streams are open
+    Dormant,
+}
+
+impl FromStr for VisibilityPolicy {
+    type Err = TorError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "suspend" => Ok(Self::Suspend),
+            "dormant" => Ok(Self::Dormant),
+            other => Err(TorError::configuration(format!(
+                "Unknown visibility policy: {} (expected ignore, suspend or dormant)",
+                other
+            ))),
+        }
+    }
+}
+
+/// `visibilitychange` events of the page's document, as whether the page
+/// is now hidden; the listener is removed on drop
+#[cfg(target_arch = "wasm32")]
+pub(crate) struct VisibilityListener {
+    document: web_sys::Document,
+    callback: wasm_bindgen::closure::Closure<dyn FnMut()>,
+    changes: futures::channel::mpsc::UnboundedReceiver<bool>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl VisibilityListener {
+    /// Listen on the current document; `None` outside a page (e.g. in a worker)
+    pub(crate) fn new() -> Option<Self> {
+        use wasm_bindgen::JsCast;
+
+        let document = web_sys::window()?.document()?;
+        let (sender, changes) = futures::channel::mpsc::unbounded();
+        let target = document.clone();
+        let callback = wasm_bindgen::closure::Closure::<dyn FnMut()>::new(move || {
+            let _ = sender.unbounded_send(target.hidden());
+        });
+        document
+            .add_event_listener_with_callback("visibilitychange", callback.as_ref().unchecked_ref())
+            .ok()?;
+        Some(Self {
+            document,
+            callback,
+            changes,
+        })
+    }
+
+    /// Whether the page is hidden right now
+    pub(crate) fn is_hidden(&self) -> bool {
+        self.document.hidden()
+    }
+
+    /// The next change, as whether the page is now hidden
+    pub(crate) async fn next(&mut self) -> Option<bool> {
+        use futures::StreamExt;
+        self.changes.next().await
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for VisibilityListener {
+    fn drop(&mut self) {
+        use wasm_bindgen::JsCast;
+        let _ = self.document.remove_event_listener_with_callback(
+            "visibilitychange",
+            self.callback.as_ref().unchecked_ref(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "dormant".parse::<VisibilityPolicy>().unwrap(),
+            VisibilityPolicy::Dormant
+        );
+        assert_eq!(
+            serde_json::from_str::<VisibilityPolicy>("\"suspend\"").unwrap(),
+            VisibilityPolicy::Suspend
+        );
+        assert!("asleep".parse::<VisibilityPolicy>().is_err());
+    }
+}
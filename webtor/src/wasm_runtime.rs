@@ -12,6 +12,12 @@ pub struct WasmRuntime {
     coarse: RealCoarseTimeProvider,
 }
 
+impl Default for WasmRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl WasmRuntime {
     pub fn new() -> Self {
         Self {
@@ -74,7 +80,6 @@ impl WasmSleep {
 
         #[cfg(not(target_arch = "wasm32"))]
         {
-            let duration = duration.clone();
             std::thread::spawn(move || {
                 std::thread::sleep(duration);
                 let _ = tx.send(());
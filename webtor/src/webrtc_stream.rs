@@ -2,8 +2,10 @@
 //!
 //! This module provides WebRTC connectivity for the Snowflake client.
 //! It uses the browser's native WebRTC API via web-sys bindings.
+//! [`WebRtcStream::from_data_channel`] wraps a channel the application
+//! negotiated itself instead (see [`crate::datachannel`]).
 //!
-//! Flow:
+//! Snowflake flow:
 //! 1. Create RTCPeerConnection with STUN servers
 //! 2. Create DataChannel (ordered, reliable)
 //! 3. Generate SDP offer
@@ -52,10 +54,10 @@ mod wasm {
         RtcIceGatheringState, RtcPeerConnection, RtcSdpType, RtcSessionDescriptionInit,
     };
 
-    /// WebRTC stream wrapper for Snowflake
+    /// Byte stream over an RTCDataChannel
     pub struct WebRtcStream {
-        #[allow(dead_code)]
-        peer_connection: RtcPeerConnection,
+        /// Set when this stream created the connection and so must close it
+        peer_connection: Option<RtcPeerConnection>,
         data_channel: RtcDataChannel,
         rx: mpsc::UnboundedReceiver<io::Result<Vec<u8>>>,
         buffer: Vec<u8>,
@@ -69,8 +71,8 @@ mod wasm {
     }
 
     impl WebRtcStream {
-        /// Connect to a Snowflake proxy via the broker
-        pub async fn connect(broker_url: &str, fingerprint: &str) -> Result<Self> {
+        /// Connect to a Snowflake proxy, exchanging SDP through `broker`
+        pub async fn connect(broker: &BrokerClient) -> Result<Self> {
             info!("Creating WebRTC connection for Snowflake");
 
             // 1. Create RTCPeerConnection with STUN servers
@@ -89,6 +91,61 @@ mod wasm {
             debug!("DataChannel created: {}", DATA_CHANNEL_LABEL);
 
             // 3. Setup channel for receiving messages
+            let stream = Self::attach(Some(pc.clone()), dc);
+
+            // 4. Wait for ICE gathering to complete
+            let offer_sdp = create_and_gather_offer(&pc).await?;
+            info!("SDP offer created ({} bytes)", offer_sdp.len());
+
+            // 5. Exchange offer/answer via broker
+            let answer_json = broker.negotiate(&offer_sdp).await?;
+            info!("Got SDP answer from broker");
+
+            // 6. Parse and set remote description
+            // Answer is JSON: {"type":"answer","sdp":"..."}
+            let answer_sdp = parse_sdp_answer(&answer_json)?;
+            let answer_init = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
+            answer_init.set_sdp(&answer_sdp);
+
+            let set_remote = pc.set_remote_description(&answer_init);
+            wasm_bindgen_futures::JsFuture::from(set_remote)
+                .await
+                .map_err(|e| {
+                    TorError::Network(format!("Failed to set remote description: {:?}", e))
+                })?;
+
+            debug!("Remote description set");
+
+            // 7. Wait for DataChannel to open
+            wait_for_channel_open(&stream.data_channel).await?;
+            info!("WebRTC DataChannel opened!");
+
+            Ok(stream)
+        }
+
+        /// Wrap a DataChannel the application created and negotiated itself
+        ///
+        /// Waits for the channel to open if it is still connecting. The
+        /// application keeps ownership of the peer connection; dropping the
+        /// stream closes only the channel.
+        pub async fn from_data_channel(dc: RtcDataChannel) -> Result<Self> {
+            match dc.ready_state() {
+                RtcDataChannelState::Closing | RtcDataChannelState::Closed => {
+                    return Err(TorError::Network("DataChannel is closed".to_string()));
+                }
+                _ => {}
+            }
+            let stream = Self::attach(None, dc);
+            wait_for_channel_open(&stream.data_channel).await?;
+            info!(
+                "Application DataChannel {} is open",
+                stream.data_channel.label()
+            );
+            Ok(stream)
+        }
+
+        /// Install message, error and close handlers that feed the read side
+        fn attach(peer_connection: Option<RtcPeerConnection>, dc: RtcDataChannel) -> Self {
             let (tx, rx) = mpsc::unbounded();
             let tx_msg = tx.clone();
             let tx_err = tx.clone();
@@ -126,43 +183,15 @@ mod wasm {
             }) as Box<dyn FnMut(web_sys::Event)>);
             dc.set_onclose(Some(on_close.as_ref().unchecked_ref()));
 
-            // 4. Wait for ICE gathering to complete
-            let offer_sdp = create_and_gather_offer(&pc).await?;
-            info!("SDP offer created ({} bytes)", offer_sdp.len());
-
-            // 5. Exchange offer/answer via broker
-            let broker = BrokerClient::new(broker_url).with_fingerprint(fingerprint.to_string());
-            let answer_json = broker.negotiate(&offer_sdp).await?;
-            info!("Got SDP answer from broker");
-
-            // 6. Parse and set remote description
-            // Answer is JSON: {"type":"answer","sdp":"..."}
-            let answer_sdp = parse_sdp_answer(&answer_json)?;
-            let answer_init = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
-            answer_init.set_sdp(&answer_sdp);
-
-            let set_remote = pc.set_remote_description(&answer_init);
-            wasm_bindgen_futures::JsFuture::from(set_remote)
-                .await
-                .map_err(|e| {
-                    TorError::Network(format!("Failed to set remote description: {:?}", e))
-                })?;
-
-            debug!("Remote description set");
-
-            // 7. Wait for DataChannel to open
-            wait_for_channel_open(&dc).await?;
-            info!("WebRTC DataChannel opened!");
-
-            Ok(Self {
-                peer_connection: pc,
+            Self {
+                peer_connection,
                 data_channel: dc,
                 rx,
                 buffer: Vec::new(),
                 _on_message: on_message,
                 _on_error: on_error,
                 _on_close: on_close,
-            })
+            }
         }
 
         /// Send data over the DataChannel
@@ -359,9 +388,11 @@ mod wasm {
             self.data_channel.set_onerror(None);
             self.data_channel.set_onclose(None);
             self.data_channel.set_onopen(None);
-            // Also close the connection
+            // Also close the connection, unless the application owns it
             self.data_channel.close();
-            self.peer_connection.close();
+            if let Some(pc) = &self.peer_connection {
+                pc.close();
+            }
         }
     }
 
@@ -426,6 +457,7 @@ mod wasm {
 #[cfg(not(target_arch = "wasm32"))]
 mod native {
     use super::*;
+    use crate::snowflake_broker::BrokerClient;
 
     /// Native WebRTC stream (stub for now - would use webrtc-rs crate)
     pub struct WebRtcStream {
@@ -433,7 +465,7 @@ mod native {
     }
 
     impl WebRtcStream {
-        pub async fn connect(_broker_url: &str, _fingerprint: &str) -> Result<Self> {
+        pub async fn connect(_broker: &BrokerClient) -> Result<Self> {
             // For native, we would use the webrtc-rs crate
             // For now, return an error since native Snowflake is less common
             Err(TorError::Internal(
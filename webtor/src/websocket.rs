@@ -2,10 +2,114 @@
 
 use crate::error::{Result, TorError};
 use futures::{AsyncRead, AsyncWrite};
+use serde::{Deserialize, Serialize};
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+/// Headers the handshake sets itself, which callers may not override
+const HANDSHAKE_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "upgrade",
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-protocol",
+    "sec-websocket-extensions",
+];
+
+/// Extra settings for the WebSocket opening handshake
+///
+/// Some servers only upgrade requests carrying a particular header (an
+/// access token, say) or offering a particular subprotocol.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebSocketOptions {
+    /// Headers added to the handshake request; native only, as browsers
+    /// don't let pages set them
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// Subprotocols offered in `Sec-WebSocket-Protocol`, most preferred first
+    #[serde(default)]
+    pub subprotocols: Vec<String>,
+}
+
+impl WebSocketOptions {
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_subprotocol(mut self, protocol: impl Into<String>) -> Self {
+        self.subprotocols.push(protocol.into());
+        self
+    }
+
+    /// Check that the headers and subprotocols can go into a request as given
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in &self.headers {
+            if !is_token(name) {
+                return Err(TorError::configuration(format!(
+                    "Invalid WebSocket header name: {:?}",
+                    name
+                )));
+            }
+            if HANDSHAKE_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                return Err(TorError::configuration(format!(
+                    "WebSocket header {} is set by the handshake itself",
+                    name
+                )));
+            }
+            if value.bytes().any(|b| b == b'\r' || b == b'\n' || b == 0) {
+                return Err(TorError::configuration(format!(
+                    "Invalid value for WebSocket header {}",
+                    name
+                )));
+            }
+        }
+        if let Some(protocol) = self.subprotocols.iter().find(|p| !is_token(p)) {
+            return Err(TorError::configuration(format!(
+                "Invalid WebSocket subprotocol: {:?}",
+                protocol
+            )));
+        }
+        Ok(())
+    }
+
+    /// The extra request headers, `Sec-WebSocket-Protocol` last if any
+    /// subprotocols are offered
+    pub fn request_headers(&self) -> Vec<(String, String)> {
+        let mut headers = self.headers.clone();
+        if !self.subprotocols.is_empty() {
+            headers.push((
+                "Sec-WebSocket-Protocol".to_string(),
+                self.subprotocols.join(", "),
+            ));
+        }
+        headers
+    }
+
+    /// Fail if the server picked a subprotocol that wasn't offered
+    pub fn check_subprotocol(&self, chosen: Option<&str>) -> Result<()> {
+        match chosen {
+            Some(chosen) if !self.subprotocols.iter().any(|p| p == chosen) => {
+                Err(TorError::Network(format!(
+                    "Server chose WebSocket subprotocol {} which wasn't offered",
+                    chosen
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether `s` is an HTTP token (RFC 9110), as header names and
+/// subprotocols must be
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
 #[cfg(target_arch = "wasm32")]
 mod wasm {
     use super::*;
@@ -32,8 +136,31 @@ mod wasm {
     impl WebSocketStream {
         /// Connect to a WebSocket URL
         pub async fn connect(url: &str) -> Result<Self> {
-            let socket = WebSocket::new(url)
-                .map_err(|e| TorError::Network(format!("Failed to create WebSocket: {:?}", e)))?;
+            Self::connect_with(url, &WebSocketOptions::default()).await
+        }
+
+        /// Connect to a WebSocket URL, offering `options.subprotocols`
+        ///
+        /// Browsers don't let pages add handshake headers, so any in
+        /// `options.headers` are a configuration error.
+        pub async fn connect_with(url: &str, options: &WebSocketOptions) -> Result<Self> {
+            options.validate()?;
+            if !options.headers.is_empty() {
+                return Err(TorError::configuration(
+                    "Browsers don't allow custom WebSocket handshake headers",
+                ));
+            }
+            let socket = if options.subprotocols.is_empty() {
+                WebSocket::new(url)
+            } else {
+                let protocols: js_sys::Array = options
+                    .subprotocols
+                    .iter()
+                    .map(|p| JsValue::from_str(p))
+                    .collect();
+                WebSocket::new_with_str_sequence(url, &protocols)
+            }
+            .map_err(|e| TorError::Network(format!("Failed to create WebSocket: {:?}", e)))?;
 
             socket.set_binary_type(BinaryType::Arraybuffer);
 
@@ -217,6 +344,8 @@ mod native {
     use futures::stream::{SplitSink, SplitStream};
     use futures::{Sink, Stream};
     use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
     use tokio_tungstenite::tungstenite::Message;
     use tokio_tungstenite::{MaybeTlsStream, WebSocketStream as TungsteniteStream};
     use tracing::{debug, info, trace};
@@ -230,9 +359,29 @@ mod native {
 
     impl WebSocketStream {
         pub async fn connect(url: &str) -> Result<Self> {
+            Self::connect_with(url, &WebSocketOptions::default()).await
+        }
+
+        /// Connect to a WebSocket URL, adding `options`' headers and
+        /// subprotocols to the handshake
+        pub async fn connect_with(url: &str, options: &WebSocketOptions) -> Result<Self> {
+            options.validate()?;
             info!("Connecting to WebSocket: {}", url);
 
-            let (ws_stream, _response) = tokio_tungstenite::connect_async(url)
+            let mut request = url
+                .into_client_request()
+                .map_err(|e| TorError::configuration(format!("Invalid WebSocket URL: {}", e)))?;
+            for (name, value) in options.request_headers() {
+                let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+                    TorError::configuration(format!("Invalid WebSocket header: {}", e))
+                })?;
+                let value = HeaderValue::from_str(&value).map_err(|e| {
+                    TorError::configuration(format!("Invalid WebSocket header: {}", e))
+                })?;
+                request.headers_mut().append(name, value);
+            }
+
+            let (ws_stream, _response) = tokio_tungstenite::connect_async(request)
                 .await
                 .map_err(|e| TorError::Network(format!("WebSocket connection failed: {}", e)))?;
 
@@ -376,3 +525,40 @@ pub use wasm::*;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub use native::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_validate() {
+        let options = WebSocketOptions::default()
+            .with_header("Authorization", "Bearer abc")
+            .with_subprotocol("tor.v1");
+        assert!(options.validate().is_ok());
+        assert_eq!(
+            options.request_headers().last().unwrap(),
+            &("Sec-WebSocket-Protocol".to_string(), "tor.v1".to_string())
+        );
+
+        for bad in [
+            WebSocketOptions::default().with_header("X-Token", "a\r\nHost: evil"),
+            WebSocketOptions::default().with_header("Bad Name", "x"),
+            WebSocketOptions::default().with_header("host", "example.com"),
+            WebSocketOptions::default().with_subprotocol("a,b"),
+        ] {
+            assert!(bad.validate().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_check_subprotocol() {
+        let options = WebSocketOptions::default().with_subprotocol("tor.v1");
+        assert!(options.check_subprotocol(Some("tor.v1")).is_ok());
+        assert!(options.check_subprotocol(None).is_ok());
+        assert!(options.check_subprotocol(Some("chat")).is_err());
+        assert!(WebSocketOptions::default()
+            .check_subprotocol(Some("chat"))
+            .is_err());
+    }
+}
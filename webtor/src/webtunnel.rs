@@ -18,12 +18,17 @@
 //! Reference: https://gitlab.torproject.org/tpo/anti-censorship/pluggable-transports/webtunnel
 
 use crate::error::{Result, TorError};
+use crate::proxy::{self, UpstreamProxy};
+use crate::tls::TlsFingerprint;
+use crate::transport::{LinkStream, Transport};
+use crate::websocket::WebSocketOptions;
 use futures::{AsyncRead, AsyncWrite};
 use futures_rustls::rustls::client::danger::{
     HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
 };
 use futures_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
 use futures_rustls::rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use serde::{Deserialize, Serialize};
 use std::io;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -34,16 +39,31 @@ use tracing::{debug, info};
 use url::Url;
 
 /// WebTunnel bridge configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WebTunnelConfig {
     /// Full URL to the WebTunnel endpoint (e.g., https://example.com/secret-path)
     pub url: String,
     /// Bridge fingerprint (RSA identity, 40 hex chars)
     pub fingerprint: String,
     /// Optional: Override server name for TLS SNI
+    #[serde(default)]
     pub server_name: Option<String>,
-    /// Connection timeout
+    /// Connection timeout (milliseconds when serialized)
+    #[serde(default = "default_timeout", with = "crate::config::duration_ms")]
     pub connection_timeout: Duration,
+    /// Ordering of the ClientHello sent to the bridge
+    #[serde(default)]
+    pub tls_fingerprint: TlsFingerprint,
+    /// Extra headers and subprotocols for the upgrade request
+    #[serde(default)]
+    pub handshake: WebSocketOptions,
+    /// Proxy the TCP connection is made through
+    #[serde(default)]
+    pub proxy: Option<UpstreamProxy>,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(30)
 }
 
 impl WebTunnelConfig {
@@ -52,7 +72,10 @@ impl WebTunnelConfig {
             url,
             fingerprint,
             server_name: None,
-            connection_timeout: Duration::from_secs(30),
+            connection_timeout: default_timeout(),
+            tls_fingerprint: TlsFingerprint::default(),
+            handshake: WebSocketOptions::default(),
+            proxy: None,
         }
     }
 
@@ -65,6 +88,33 @@ impl WebTunnelConfig {
         self.server_name = Some(name);
         self
     }
+
+    pub fn with_tls_fingerprint(mut self, fingerprint: TlsFingerprint) -> Self {
+        self.tls_fingerprint = fingerprint;
+        self
+    }
+
+    pub fn with_handshake(mut self, handshake: WebSocketOptions) -> Self {
+        self.handshake = handshake;
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: Option<UpstreamProxy>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Add a header to the upgrade request
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.handshake = self.handshake.with_header(name, value);
+        self
+    }
+
+    /// Offer a subprotocol in the upgrade request
+    pub fn with_subprotocol(mut self, protocol: impl Into<String>) -> Self {
+        self.handshake = self.handshake.with_subprotocol(protocol);
+        self
+    }
 }
 
 /// WebTunnel bridge connection manager
@@ -87,10 +137,10 @@ impl WebTunnelBridge {
     pub async fn connect(&self) -> Result<WebTunnelStream> {
         use rustls_pki_types::ServerName;
         use tokio::io::{AsyncReadExt, AsyncWriteExt};
-        use tokio::net::TcpStream;
 
         let url = Url::parse(&self.config.url)
             .map_err(|e| TorError::Configuration(format!("Invalid URL: {}", e)))?;
+        self.config.handshake.validate()?;
 
         let host = url
             .host_str()
@@ -104,9 +154,7 @@ impl WebTunnelBridge {
         );
 
         // 1. Connect TCP
-        let tcp_stream = TcpStream::connect(format!("{}:{}", host, port))
-            .await
-            .map_err(|e| TorError::Network(format!("TCP connection failed: {}", e)))?;
+        let tcp_stream = proxy::connect_tcp(self.config.proxy.as_ref(), host, port).await?;
 
         debug!("TCP connected to {}:{}", host, port);
 
@@ -114,9 +162,7 @@ impl WebTunnelBridge {
         let mut root_store = rustls::RootCertStore::empty();
         root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
 
-        let config = rustls::ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth();
+        let config = self.config.tls_fingerprint.client_config(root_store)?;
 
         let connector = tokio_rustls::TlsConnector::from(std::sync::Arc::new(config));
 
@@ -137,19 +183,22 @@ impl WebTunnelBridge {
         // may not validate or return Sec-WebSocket-Accept (WebTunnel doesn't require it)
         let ws_key = base64::Engine::encode(
             &base64::engine::general_purpose::STANDARD,
-            &rand::random::<[u8; 16]>(),
+            rand::random::<[u8; 16]>(),
         );
 
-        let request = format!(
+        let mut request = format!(
             "GET {} HTTP/1.1\r\n\
             Host: {}\r\n\
             Connection: Upgrade\r\n\
             Upgrade: websocket\r\n\
             Sec-WebSocket-Key: {}\r\n\
-            Sec-WebSocket-Version: 13\r\n\
-            \r\n",
+            Sec-WebSocket-Version: 13\r\n",
             path, host, ws_key
         );
+        for (name, value) in self.config.handshake.request_headers() {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
 
         debug!("Sending HTTP Upgrade request");
 
@@ -206,6 +255,10 @@ impl WebTunnelBridge {
             )));
         }
 
+        self.config
+            .handshake
+            .check_subprotocol(response_header(&response_str, "sec-websocket-protocol"))?;
+
         info!("WebTunnel HTTP Upgrade complete, establishing Tor link TLS");
 
         // 5. Establish Tor link TLS over the tunneled connection
@@ -250,7 +303,7 @@ impl WebTunnelBridge {
 /// via CERTS cells during the Tor channel handshake, not via the TLS layer.
 /// This verifier accepts any certificate, leaving validation to the Tor protocol.
 #[derive(Debug)]
-struct TorCertVerifier;
+pub(crate) struct TorCertVerifier;
 
 impl ServerCertVerifier for TorCertVerifier {
     fn verify_server_cert(
@@ -375,7 +428,7 @@ impl tor_rtcompat::CertifiedConn for WebTunnelStream {
         let (_, session) = self.inner.get_ref();
         session
             .export_keying_material(Vec::with_capacity(len), label, context)
-            .map_err(|e| io::Error::other(e))
+            .map_err(io::Error::other)
     }
 }
 
@@ -412,6 +465,21 @@ impl AsyncWrite for WebTunnelStream {
     }
 }
 
+#[async_trait::async_trait]
+impl Transport for WebTunnelBridge {
+    async fn connect(&self) -> Result<Box<dyn LinkStream>> {
+        Ok(Box::new(WebTunnelBridge::connect(self).await?))
+    }
+}
+
+/// Value of the header `name` (lowercase) in an HTTP response head
+fn response_header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    response.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
 /// Create a WebTunnel stream (convenience function)
 pub async fn create_webtunnel_stream(config: WebTunnelConfig) -> Result<WebTunnelStream> {
     let bridge = WebTunnelBridge::new(config);
@@ -422,6 +490,19 @@ pub async fn create_webtunnel_stream(config: WebTunnelConfig) -> Result<WebTunne
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_config_from_json() {
+        let config: WebTunnelConfig = serde_json::from_str(
+            r#"{"url": "https://example.com/path", "fingerprint": "AAAA", "connection_timeout": 5000}"#,
+        )
+        .unwrap();
+        assert_eq!(config.connection_timeout, Duration::from_secs(5));
+        assert_eq!(config.server_name, None);
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["connection_timeout"], 5000);
+    }
+
     #[test]
     fn test_config_creation() {
         let config = WebTunnelConfig::new(
@@ -450,4 +531,16 @@ mod tests {
 
         assert_eq!(config.server_name, Some("custom.example.com".to_string()));
     }
+
+    #[test]
+    fn test_response_header() {
+        let response = "HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Protocol:  tor.v1 \r\n\r\n";
+        assert_eq!(
+            response_header(response, "sec-websocket-protocol"),
+            Some("tor.v1")
+        );
+        assert_eq!(response_header(response, "connection"), None);
+    }
 }
@@ -0,0 +1,747 @@
+//! WebSocket client over Tor streams
+//!
+//! [`TorWebSocket`] runs the RFC 6455 opening handshake and framing over a
+//! stream through a Tor exit, with TLS to the server for `wss://` URLs, so
+//! the server sees the exit rather than the client. (The browser WebSocket
+//! wrapper in [`websocket`](crate::websocket) is the bridge transport, not
+//! this.) Open one with [`TorClient::websocket`](crate::TorClient::websocket).
+//!
+//! Pings are answered as they arrive, fragmented messages are reassembled,
+//! and `permessage-deflate` and other extensions are never offered.
+
+use crate::ech::EchConfigs;
+use crate::error::{Result, TorError};
+use crate::http::{is_protected_header, parse_head};
+use crate::isolation::IsolationToken;
+use crate::pinning::CertPin;
+use crate::tls::{TlsRoots, TlsSessions, TorTlsStream};
+use crate::traffic::TorStream;
+use base64::Engine;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use futures::lock::Mutex;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{debug, info};
+use url::Url;
+
+/// Appended to `Sec-WebSocket-Key` to form the expected `Sec-WebSocket-Accept`
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest handshake response head we wait for
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+const READ_CHUNK: usize = 16 * 1024;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Close code for a normal closure
+pub const CLOSE_NORMAL: u16 = 1000;
+
+/// Streams a WebSocket can run over: a Tor stream, or TLS on top of one
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+/// Streams a WebSocket can run over: a Tor stream, or TLS on top of one
+#[cfg(target_arch = "wasm32")]
+pub(crate) trait Transport: AsyncRead + AsyncWrite + Unpin {}
+#[cfg(target_arch = "wasm32")]
+impl<T: AsyncRead + AsyncWrite + Unpin> Transport for T {}
+
+/// A WebSocket connection to open
+#[derive(Debug, Clone)]
+pub struct WebSocketRequest {
+    /// A `ws://` or `wss://` URL
+    pub url: Url,
+    /// Subprotocols offered in `Sec-WebSocket-Protocol`, in preference order
+    pub protocols: Vec<String>,
+    /// Extra handshake headers (`Origin`, `Authorization`, ...)
+    pub headers: HashMap<String, String>,
+    /// Limit on getting a circuit, opening the stream, TLS and the handshake
+    pub timeout: Duration,
+    /// Caller tag; connections with different tokens never share a circuit
+    pub isolation_token: Option<IsolationToken>,
+    /// Overrides the client's size cap for a single message
+    pub max_message_size: Option<usize>,
+    /// Keys or certificates the server's TLS chain must contain (`wss://`)
+    pub cert_pins: Vec<CertPin>,
+}
+
+impl WebSocketRequest {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            protocols: Vec::new(),
+            headers: HashMap::new(),
+            timeout: Duration::from_secs(30),
+            isolation_token: None,
+            max_message_size: None,
+            cert_pins: Vec::new(),
+        }
+    }
+
+    /// Offer subprotocol `protocol`; the server picks at most one
+    pub fn with_protocol(mut self, protocol: impl Into<String>) -> Self {
+        self.protocols.push(protocol.into());
+        self
+    }
+
+    /// Send `key: value` with the handshake; headers the handshake itself
+    /// needs (`Host`, `Upgrade`, `Sec-WebSocket-*`, ...) are ignored
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keep this connection on circuits reserved for `token`
+    pub fn with_isolation(mut self, token: impl Into<IsolationToken>) -> Self {
+        self.isolation_token = Some(token.into());
+        self
+    }
+
+    /// Cap a single (reassembled) message at `max` bytes; 0 means no cap
+    pub fn with_max_message_size(mut self, max: usize) -> Self {
+        self.max_message_size = Some(max);
+        self
+    }
+
+    /// Require the server's TLS certificate chain to contain `pin`; with
+    /// several pins, any of them will do
+    pub fn with_cert_pin(mut self, pin: CertPin) -> Self {
+        self.cert_pins.push(pin);
+        self
+    }
+
+    /// Whether the URL asks for TLS (`wss://`)
+    pub(crate) fn is_secure(&self) -> Result<bool> {
+        match self.url.scheme() {
+            "ws" => Ok(false),
+            "wss" => Ok(true),
+            other => Err(TorError::configuration(format!(
+                "WebSocket URLs must be ws:// or wss://, not {}://",
+                other
+            ))),
+        }
+    }
+
+    /// Host and port to open the stream to
+    pub(crate) fn target(&self) -> Result<(&str, u16)> {
+        let host = self
+            .url
+            .host_str()
+            .ok_or_else(|| TorError::configuration("WebSocket URL has no host"))?;
+        let port = self
+            .url
+            .port_or_known_default()
+            .ok_or_else(|| TorError::configuration("WebSocket URL has no port"))?;
+        Ok((host, port))
+    }
+
+    /// The opening handshake, offering `key`
+    fn build_handshake(&self, key: &str) -> Result<Vec<u8>> {
+        let (host, _) = self.target()?;
+        let host = match self.url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let query = self
+            .url
+            .query()
+            .map(|q| format!("?{}", q))
+            .unwrap_or_default();
+
+        let mut request = format!(
+            "GET {}{} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n",
+            self.url.path(),
+            query,
+            host,
+            key
+        );
+        if !self.protocols.is_empty() {
+            request.push_str(&format!(
+                "Sec-WebSocket-Protocol: {}\r\n",
+                self.protocols.join(", ")
+            ));
+        }
+        for (key, value) in &self.headers {
+            http::HeaderName::from_bytes(key.as_bytes())
+                .map_err(|_| TorError::configuration(format!("Invalid header name {:?}", key)))?;
+            http::HeaderValue::from_bytes(value.as_bytes()).map_err(|_| {
+                TorError::configuration(format!("Invalid value for header {}", key))
+            })?;
+            let handshake_header = key
+                .get(..14)
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case("Sec-WebSocket-"));
+            if is_protected_header(key) || handshake_header {
+                debug!("Ignoring caller-set {} header", key);
+                continue;
+            }
+            request.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        request.push_str("\r\n");
+        Ok(request.into_bytes())
+    }
+}
+
+/// A message received from the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+    /// The server closed the connection, with its code and reason if it
+    /// gave one; the close has been answered
+    Close(Option<CloseFrame>),
+}
+
+/// Status carried by a close frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+/// A WebSocket connection through Tor
+///
+/// Sending and receiving take `&self` and may run at the same time, e.g.
+/// with the socket in an `Arc` and a task looping on [`recv`](Self::recv).
+pub struct TorWebSocket {
+    reader: Mutex<Reader>,
+    writer: Mutex<WriteHalf<Box<dyn Transport>>>,
+    /// The subprotocol the server picked
+    protocol: Option<String>,
+    /// Whether we've sent our close frame; nothing may follow it
+    close_sent: AtomicBool,
+    max_message_size: usize,
+}
+
+/// A frame from the server, unmasked
+#[derive(Debug, PartialEq, Eq)]
+struct Frame {
+    /// Whether this is the message's last frame
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+struct Reader {
+    half: ReadHalf<Box<dyn Transport>>,
+    buffer: Vec<u8>,
+    /// Opcode and data of a fragmented message still being received
+    partial: Option<(u8, Vec<u8>)>,
+    /// Whether the server's close frame has arrived
+    closed: bool,
+}
+
+impl std::fmt::Debug for TorWebSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TorWebSocket")
+            .field("protocol", &self.protocol)
+            .field("close_sent", &self.close_sent)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TorWebSocket {
+    /// Run the handshake for `request` over `stream`, adding TLS for `wss://`
+    pub(crate) async fn connect(
+        stream: TorStream,
+        request: &WebSocketRequest,
+        roots: &TlsRoots,
+        sessions: Option<&TlsSessions>,
+        ech: &EchConfigs,
+        max_message_size: usize,
+    ) -> Result<Self> {
+        let (host, _) = request.target()?;
+        let key = base64::engine::general_purpose::STANDARD.encode(rand::random::<[u8; 16]>());
+        if !request.is_secure()? {
+            return Self::handshake(stream, request, &key, max_message_size).await;
+        }
+
+        // The upgrade is an HTTP/1.1 exchange, so that's the only protocol offered
+        let alpn = ["http/1.1".to_string()];
+        let pins = &request.cert_pins;
+        let tls_stream =
+            TorTlsStream::connect(stream, host, roots, &alpn, pins, sessions, ech).await?;
+        Self::handshake(tls_stream, request, &key, max_message_size).await
+    }
+
+    /// Open the WebSocket over `stream`, offering `key`
+    async fn handshake(
+        stream: impl Transport + 'static,
+        request: &WebSocketRequest,
+        key: &str,
+        max_message_size: usize,
+    ) -> Result<Self> {
+        let mut stream: Box<dyn Transport> = Box::new(stream);
+        stream.write_all(&request.build_handshake(key)?).await?;
+        stream.flush().await?;
+
+        // Read the response head; anything after it is already framed data
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 1024];
+        let head_end = loop {
+            if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos;
+            }
+            if buffer.len() > MAX_HEAD_SIZE {
+                return Err(TorError::Protocol(
+                    "WebSocket handshake response head too large".to_string(),
+                ));
+            }
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Err(TorError::websocket(
+                    "Connection closed during the WebSocket handshake",
+                ));
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+        };
+        let head = parse_head(&buffer[..head_end])?;
+        buffer.drain(..head_end + 4);
+
+        if head.status != 101 {
+            return Err(TorError::http_request(format!(
+                "WebSocket upgrade refused: {} {}",
+                head.status, head.status_text
+            )));
+        }
+        let header = |name: &str| head.headers.get(name).and_then(|v| v.to_str().ok());
+        let upgrade = header("Upgrade").unwrap_or_default();
+        let connection = header("Connection").unwrap_or_default();
+        if !upgrade.eq_ignore_ascii_case("websocket")
+            || !connection
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+        {
+            return Err(TorError::Protocol(
+                "Server did not upgrade the connection to a WebSocket".to_string(),
+            ));
+        }
+        if header("Sec-WebSocket-Accept") != Some(accept_key(key).as_str()) {
+            return Err(TorError::Protocol(
+                "Server sent the wrong Sec-WebSocket-Accept".to_string(),
+            ));
+        }
+        if header("Sec-WebSocket-Extensions").is_some() {
+            return Err(TorError::Protocol(
+                "Server enabled WebSocket extensions that were not offered".to_string(),
+            ));
+        }
+        let protocol = header("Sec-WebSocket-Protocol").map(str::to_string);
+        if let Some(protocol) = &protocol {
+            if !request.protocols.contains(protocol) {
+                return Err(TorError::Protocol(format!(
+                    "Server picked subprotocol {} which was not offered",
+                    protocol
+                )));
+            }
+        }
+
+        info!("WebSocket open to {}", request.url);
+        let (half, writer) = stream.split();
+        Ok(Self {
+            reader: Mutex::new(Reader {
+                half,
+                buffer,
+                partial: None,
+                closed: false,
+            }),
+            writer: Mutex::new(writer),
+            protocol,
+            close_sent: AtomicBool::new(false),
+            max_message_size,
+        })
+    }
+
+    /// The subprotocol the server picked, if any
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    pub async fn send_text(&self, text: &str) -> Result<()> {
+        self.send_frame(OP_TEXT, text.as_bytes()).await
+    }
+
+    pub async fn send_binary(&self, data: &[u8]) -> Result<()> {
+        self.send_frame(OP_BINARY, data).await
+    }
+
+    /// Ask the server to close the connection with `code` and `reason`
+    ///
+    /// [`recv`](Self::recv) returns [`WsMessage::Close`] once the server
+    /// answers; nothing can be sent after this.
+    pub async fn close(&self, code: u16, reason: &str) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        if self.close_sent.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        write_frame(&mut writer, OP_CLOSE, &close_payload(code, reason)).await
+    }
+
+    /// Wait for the next text or binary message, or the server's close
+    ///
+    /// Fails once the close has been received, and if the stream ends
+    /// without one.
+    pub async fn recv(&self) -> Result<WsMessage> {
+        let mut reader = self.reader.lock().await;
+        if reader.closed {
+            return Err(TorError::websocket("WebSocket is closed"));
+        }
+        loop {
+            let Frame {
+                fin,
+                opcode,
+                payload,
+            } = reader.next_frame(self.max_message_size).await?;
+            match opcode {
+                OP_PING => {
+                    let mut writer = self.writer.lock().await;
+                    if !self.close_sent.load(Ordering::SeqCst) {
+                        write_frame(&mut writer, OP_PONG, &payload).await?;
+                    }
+                }
+                OP_PONG => {}
+                OP_CLOSE => {
+                    reader.closed = true;
+                    let close = parse_close(&payload)?;
+                    let mut writer = self.writer.lock().await;
+                    if !self.close_sent.swap(true, Ordering::SeqCst) {
+                        let code = close.as_ref().map_or(CLOSE_NORMAL, |c| c.code);
+                        write_frame(&mut writer, OP_CLOSE, &close_payload(code, "")).await?;
+                    }
+                    debug!("WebSocket closed by server: {:?}", close);
+                    return Ok(WsMessage::Close(close));
+                }
+                OP_TEXT | OP_BINARY if reader.partial.is_some() => {
+                    return Err(TorError::Protocol(
+                        "WebSocket message started inside a fragmented one".to_string(),
+                    ));
+                }
+                OP_TEXT | OP_BINARY if fin => return into_message(opcode, payload),
+                OP_TEXT | OP_BINARY => reader.partial = Some((opcode, payload)),
+                OP_CONTINUATION => {
+                    let Some((_, data)) = reader.partial.as_mut() else {
+                        return Err(TorError::Protocol(
+                            "WebSocket continuation frame without a message".to_string(),
+                        ));
+                    };
+                    data.extend_from_slice(&payload);
+                    check_size(data.len(), self.max_message_size)?;
+                    if fin {
+                        let (opcode, data) = reader.partial.take().unwrap_or_default();
+                        return into_message(opcode, data);
+                    }
+                }
+                other => {
+                    return Err(TorError::Protocol(format!(
+                        "Unknown WebSocket opcode {:#x}",
+                        other
+                    )))
+                }
+            }
+        }
+    }
+
+    async fn send_frame(&self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut writer = self.writer.lock().await;
+        if self.close_sent.load(Ordering::SeqCst) {
+            return Err(TorError::websocket("WebSocket is closed"));
+        }
+        write_frame(&mut writer, opcode, payload).await
+    }
+}
+
+impl Reader {
+    /// The next frame's FIN bit, opcode and unmasked payload
+    async fn next_frame(&mut self, max_message_size: usize) -> Result<Frame> {
+        let mut chunk = vec![0u8; READ_CHUNK];
+        loop {
+            if let Some((frame, used)) = decode_frame(&self.buffer, max_message_size)? {
+                self.buffer.drain(..used);
+                return Ok(frame);
+            }
+            let n = self.half.read(&mut chunk).await?;
+            if n == 0 {
+                self.closed = true;
+                return Err(TorError::websocket(
+                    "WebSocket stream ended without a close frame",
+                ));
+            }
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+async fn write_frame(
+    writer: &mut WriteHalf<Box<dyn Transport>>,
+    opcode: u8,
+    payload: &[u8],
+) -> Result<()> {
+    writer
+        .write_all(&encode_frame(opcode, payload, rand::random()))
+        .await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// The `Sec-WebSocket-Accept` a server must answer `key` with
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(sha1.finalize())
+}
+
+/// A single final client frame; clients must mask every frame
+fn encode_frame(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+    frame
+}
+
+/// Decode the server frame at the start of `data`, if it's all there
+///
+/// Returns the frame and how many bytes it took up.
+fn decode_frame(data: &[u8], max_size: usize) -> Result<Option<(Frame, usize)>> {
+    if data.len() < 2 {
+        return Ok(None);
+    }
+    let fin = data[0] & 0x80 != 0;
+    let opcode = data[0] & 0x0F;
+    if data[0] & 0x70 != 0 {
+        return Err(TorError::Protocol(
+            "WebSocket frame uses reserved bits".to_string(),
+        ));
+    }
+    if data[1] & 0x80 != 0 {
+        return Err(TorError::Protocol(
+            "Server sent a masked WebSocket frame".to_string(),
+        ));
+    }
+    let (len, mut offset) = match data[1] & 0x7F {
+        126 if data.len() >= 4 => (u16::from_be_bytes([data[2], data[3]]) as u64, 4),
+        127 if data.len() >= 10 => {
+            let mut len = [0u8; 8];
+            len.copy_from_slice(&data[2..10]);
+            (u64::from_be_bytes(len), 10)
+        }
+        126 | 127 => return Ok(None),
+        len => (len as u64, 2),
+    };
+    let is_control = opcode & 0x8 != 0;
+    if is_control && (!fin || len > 125) {
+        return Err(TorError::Protocol(
+            "WebSocket control frames must be whole and at most 125 bytes".to_string(),
+        ));
+    }
+    let len = usize::try_from(len).unwrap_or(usize::MAX);
+    check_size(len, max_size)?;
+    if data.len() - offset < len {
+        return Ok(None);
+    }
+    let payload = data[offset..offset + len].to_vec();
+    offset += len;
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        offset,
+    )))
+}
+
+fn check_size(size: usize, max_size: usize) -> Result<()> {
+    if max_size > 0 && size > max_size {
+        return Err(TorError::ResponseTooLarge { limit: max_size });
+    }
+    Ok(())
+}
+
+fn into_message(opcode: u8, data: Vec<u8>) -> Result<WsMessage> {
+    if opcode == OP_BINARY {
+        return Ok(WsMessage::Binary(data));
+    }
+    String::from_utf8(data)
+        .map(WsMessage::Text)
+        .map_err(|_| TorError::Protocol("WebSocket text message is not UTF-8".to_string()))
+}
+
+fn close_payload(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    // Control frames carry at most 125 bytes
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    payload.extend_from_slice(&reason.as_bytes()[..end]);
+    payload
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>> {
+    match payload {
+        [] => Ok(None),
+        [hi, lo, reason @ ..] => Ok(Some(CloseFrame {
+            code: u16::from_be_bytes([*hi, *lo]),
+            reason: String::from_utf8_lossy(reason).into_owned(),
+        })),
+        _ => Err(TorError::Protocol(
+            "WebSocket close frame has a one-byte payload".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_stream::Scripted;
+
+    fn server_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode, payload.len() as u8];
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_frame_encoding() {
+        // RFC 6455 section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        // RFC 6455 section 5.7: a masked "Hello"
+        let hello = encode_frame(OP_TEXT, b"Hello", [0x37, 0xfa, 0x21, 0x3d]);
+        assert_eq!(
+            hello,
+            [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+        );
+        assert_eq!(
+            &encode_frame(OP_BINARY, &[0; 300], [0; 4])[..4],
+            [0x82, 0xFE, 1, 44]
+        );
+
+        let frame = server_frame(true, OP_TEXT, b"Hello");
+        assert_eq!(decode_frame(&frame[..4], 0).unwrap(), None);
+        assert_eq!(
+            decode_frame(&frame, 0).unwrap(),
+            Some((
+                Frame {
+                    fin: true,
+                    opcode: OP_TEXT,
+                    payload: b"Hello".to_vec()
+                },
+                7
+            ))
+        );
+        let mut long = vec![0x82, 126, 1, 0];
+        long.extend_from_slice(&[7; 256]);
+        assert_eq!(
+            decode_frame(&long, 0).unwrap().unwrap().0.payload.len(),
+            256
+        );
+        assert!(matches!(
+            decode_frame(&long, 100),
+            Err(TorError::ResponseTooLarge { limit: 100 })
+        ));
+        // Servers must not mask
+        assert!(decode_frame(&hello, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_and_messages() {
+        let request =
+            WebSocketRequest::new(Url::parse("wss://echo.example:8443/chat?room=1").unwrap())
+                .with_protocol("chat")
+                .with_header("Origin", "https://example.com")
+                .with_header("Sec-WebSocket-Extensions", "permessage-deflate");
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        let head = |accept: &str| {
+            format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\
+                 Sec-WebSocket-Protocol: chat\r\n\r\n",
+                accept
+            )
+            .into_bytes()
+        };
+        let connect = |input: Vec<u8>| {
+            let (stream, written) = Scripted::new(input);
+            (TorWebSocket::handshake(stream, &request, key, 0), written)
+        };
+
+        // A server that doesn't prove it read the key is refused
+        let (ws, _) = connect(head("bogus"));
+        assert!(matches!(ws.await, Err(TorError::Protocol(_))));
+
+        let mut input = head(&accept_key(key));
+        input.extend(server_frame(false, OP_TEXT, b"Hel"));
+        input.extend(server_frame(true, OP_PING, b"p"));
+        input.extend(server_frame(true, OP_CONTINUATION, b"lo"));
+        input.extend(server_frame(true, OP_BINARY, &[1, 2]));
+        input.extend(server_frame(
+            true,
+            OP_CLOSE,
+            &[0x03, 0xE9, b'b', b'y', b'e'],
+        ));
+        let (ws, written) = connect(input);
+        let ws = ws.await.unwrap();
+        assert_eq!(ws.protocol(), Some("chat"));
+
+        let sent = String::from_utf8(written.lock().unwrap().clone()).unwrap();
+        assert!(sent.starts_with("GET /chat?room=1 HTTP/1.1\r\nHost: echo.example:8443\r\n"));
+        assert!(sent.contains("Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n"));
+        assert!(sent.contains("Sec-WebSocket-Protocol: chat\r\nOrigin: https://example.com\r\n"));
+        assert!(!sent.contains("permessage-deflate"));
+        written.lock().unwrap().clear();
+
+        // The ping in the middle of the fragmented message is answered
+        assert_eq!(
+            ws.recv().await.unwrap(),
+            WsMessage::Text("Hello".to_string())
+        );
+        let pong = written.lock().unwrap().clone();
+        assert_eq!((pong[0], pong[1]), (0x80 | OP_PONG, 0x81));
+        assert_eq!(ws.recv().await.unwrap(), WsMessage::Binary(vec![1, 2]));
+
+        // The server's close is echoed and ends the connection
+        assert_eq!(
+            ws.recv().await.unwrap(),
+            WsMessage::Close(Some(CloseFrame {
+                code: 1001,
+                reason: "bye".to_string()
+            }))
+        );
+        let close = written.lock().unwrap()[pong.len()..].to_vec();
+        assert_eq!((close[0], close[1]), (0x80 | OP_CLOSE, 0x82));
+        assert!(ws.recv().await.is_err());
+        assert!(ws.send_text("late").await.is_err());
+    }
+}
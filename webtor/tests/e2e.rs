@@ -5,8 +5,10 @@
 //!
 //! Run with: cargo test -p webtor --test e2e -- --ignored --nocapture
 
+use http::Method;
+use webtor::http::HttpRequest;
 use webtor::snowflake::{SnowflakeBridge, SnowflakeConfig};
-use webtor::{TorClient, TorClientOptions};
+use webtor::{TorClient, TorClientOptions, Url};
 
 /// WebTunnel bridge from community bridges list
 /// These are real bridges - they may go offline. Try a different one if it fails.
@@ -143,6 +145,69 @@ async fn test_webtunnel_connection_only() {
     println!(" WebTunnel connection test passed!");
 }
 
+#[tokio::test]
+#[ignore]
+async fn test_webtunnel_request_bodies() {
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter("webtor=info")
+        .try_init();
+
+    println!("=== E2E Test: request bodies echoed by httpbin.org ===");
+
+    let options =
+        TorClientOptions::webtunnel(WEBTUNNEL_URL.to_string(), WEBTUNNEL_FINGERPRINT.to_string())
+            .with_create_circuit_early(true)
+            .with_connection_timeout(30_000)
+            .with_circuit_timeout(120_000);
+
+    let client = TorClient::new(options)
+        .await
+        .expect("Failed to create Tor client");
+
+    // httpbin echoes the body as "data" and the request headers as "headers"
+    let response = client
+        .post("https://httpbin.org/post", "hello through tor")
+        .await
+        .expect("POST failed");
+    assert_eq!(response.status, 200);
+    let echo: serde_json::Value = response.json().expect("POST echo is not JSON");
+    assert_eq!(echo["data"], "hello through tor");
+    assert_eq!(echo["headers"]["Content-Length"], "17");
+
+    let request = HttpRequest::new(Url::parse("https://httpbin.org/put").unwrap())
+        .with_method(Method::PUT)
+        .with_text_body("put body");
+    let echo: serde_json::Value = client
+        .send(request)
+        .await
+        .expect("PUT failed")
+        .json()
+        .unwrap();
+    assert_eq!(echo["data"], "put body");
+    assert_eq!(echo["headers"]["Content-Type"], "text/plain;charset=UTF-8");
+
+    let request = HttpRequest::new(Url::parse("https://httpbin.org/patch").unwrap())
+        .with_method(Method::PATCH)
+        .with_json_body(&serde_json::json!({"patched": true}))
+        .unwrap();
+    let echo: serde_json::Value = client
+        .send(request)
+        .await
+        .expect("PATCH failed")
+        .json()
+        .unwrap();
+    assert_eq!(echo["json"]["patched"], true);
+
+    let response = client
+        .delete("https://httpbin.org/delete")
+        .await
+        .expect("DELETE failed");
+    assert_eq!(response.status, 200);
+
+    client.close().await;
+    println!(" Request body test passed!");
+}
+
 #[tokio::test]
 #[ignore]
 async fn test_try_multiple_bridges() {
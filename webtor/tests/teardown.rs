@@ -0,0 +1,267 @@
+//! Teardown leak tests
+//!
+//! Create and destroy many clients and assert that they leave nothing behind:
+//! no background tasks still alive, no circuits still tracked and no steady
+//! growth in heap usage. The offline tests run by default; the network test
+//! needs a bridge, like `integration_test.rs`:
+//!
+//!   WEBTUNNEL_URL='https://...' WEBTUNNEL_FINGERPRINT='...' cargo test -p webtor --test teardown
+//!
+//! The browser counterpart lives in `webtor-wasm/tests/teardown.rs`.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use webtor::{TorClient, TorClientOptions};
+
+/// Allocator that tracks live heap bytes
+struct CountingAlloc;
+
+static LIVE_BYTES: AtomicIsize = AtomicIsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            LIVE_BYTES.fetch_add(layout.size() as isize, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size() as isize, Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            LIVE_BYTES.fetch_add(
+                new_size as isize - layout.size() as isize,
+                Ordering::Relaxed,
+            );
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const CLIENTS: usize = 200;
+
+/// Task and heap counts are process-wide, so the tests must not overlap
+static SERIAL: Mutex<()> = Mutex::const_new(());
+
+/// Options for clients that never touch the network on their own
+fn offline_options() -> TorClientOptions {
+    TorClientOptions::snowflake()
+        .with_create_circuit_early(false)
+        .with_circuit_update_interval(None)
+        .with_maintenance_interval(Some(10))
+}
+
+fn alive_tasks() -> usize {
+    tokio::runtime::Handle::current()
+        .metrics()
+        .num_alive_tasks()
+}
+
+/// Wait for background tasks to wind down to `baseline`, failing after a deadline
+async fn assert_tasks_settle(baseline: usize, what: &str) {
+    for _ in 0..200 {
+        if alive_tasks() <= baseline {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!(
+        "{}: {} tasks still alive, expected at most {}",
+        what,
+        alive_tasks(),
+        baseline
+    );
+}
+
+/// Transport whose peer hangs up immediately, so the channel handshake fails
+struct ClosedStream;
+
+impl futures::AsyncRead for ClosedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(0))
+    }
+}
+
+impl futures::AsyncWrite for ClosedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl tor_rtcompat::StreamOps for ClosedStream {}
+
+impl tor_rtcompat::CertifiedConn for ClosedStream {
+    fn peer_certificate(&self) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn export_keying_material(
+        &self,
+        _len: usize,
+        _label: &[u8],
+        _context: Option<&[u8]>,
+    ) -> io::Result<Vec<u8>> {
+        Err(io::Error::other("no TLS session"))
+    }
+}
+
+#[tokio::test]
+async fn test_dropped_clients_stop_background_tasks() {
+    let _serial = SERIAL.lock().await;
+    let baseline = alive_tasks();
+
+    for _ in 0..CLIENTS {
+        let client = TorClient::new(offline_options()).await.unwrap();
+        // Dropping a clone must not tear down the client, dropping the last one must
+        let clone = client.clone();
+        drop(clone);
+        assert!(!client.is_aborted());
+        drop(client);
+    }
+
+    assert_tasks_settle(baseline, "after dropping clients").await;
+}
+
+#[tokio::test]
+async fn test_closed_clients_release_state() {
+    let _serial = SERIAL.lock().await;
+    let baseline = alive_tasks();
+
+    let clients =
+        futures::future::join_all((0..CLIENTS).map(|_| TorClient::new(offline_options())))
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+    assert!(alive_tasks() >= baseline + CLIENTS);
+
+    // Let the maintenance tasks run a few passes while every client is alive
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    for client in &clients {
+        client.close().await;
+        assert_eq!(client.get_circuit_status().await.total_circuits, 0);
+        assert_eq!(client.run_maintenance().await.total(), 0);
+    }
+    drop(clients);
+
+    assert_tasks_settle(baseline, "after closing clients").await;
+}
+
+#[tokio::test]
+async fn test_failed_and_aborted_operations_do_not_leak() {
+    let _serial = SERIAL.lock().await;
+    let baseline = alive_tasks();
+
+    for i in 0..CLIENTS {
+        if i % 2 == 0 {
+            // Transport dies during the channel handshake
+            let options = offline_options()
+                .with_bridge_fingerprint(webtor::config::SNOWFLAKE_FINGERPRINT_PRIMARY.to_string());
+            assert!(TorClient::with_stream(options, ClosedStream).await.is_err());
+        } else {
+            // Requests on an aborted client without relays fail before reaching the network
+            let client = TorClient::new(offline_options()).await.unwrap();
+            client.abort();
+            let err = client.fetch("https://example.com/").await.unwrap_err();
+            assert!(err.request_id().is_some());
+        }
+    }
+
+    assert_tasks_settle(baseline, "after failed operations").await;
+}
+
+#[tokio::test]
+async fn test_client_churn_keeps_memory_stable() {
+    let _serial = SERIAL.lock().await;
+    async fn churn(rounds: usize) {
+        for _ in 0..rounds {
+            let client = TorClient::new(offline_options()).await.unwrap();
+            client.run_maintenance().await;
+            client.close().await;
+        }
+        // Give the per-client cleanup tasks a chance to finish
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    // Warm up lazily initialized globals (tracing callsites, runtime pools)
+    churn(20).await;
+    let before = LIVE_BYTES.load(Ordering::Relaxed);
+    churn(CLIENTS).await;
+    let after = LIVE_BYTES.load(Ordering::Relaxed);
+
+    // A leak of even a few hundred bytes per client would exceed this
+    let growth = after - before;
+    assert!(
+        growth < 64 * 1024,
+        "heap grew by {} bytes over {} clients",
+        growth,
+        CLIENTS
+    );
+}
+
+#[tokio::test]
+async fn test_fetch_churn_through_bridge_does_not_leak() {
+    let _serial = SERIAL.lock().await;
+    let (Ok(url), Ok(fingerprint)) = (
+        std::env::var("WEBTUNNEL_URL"),
+        std::env::var("WEBTUNNEL_FINGERPRINT"),
+    ) else {
+        println!(
+            "Skipping teardown network test (set WEBTUNNEL_URL and WEBTUNNEL_FINGERPRINT to run)"
+        );
+        return;
+    };
+
+    let baseline = alive_tasks();
+    for _ in 0..5 {
+        let options = TorClientOptions::webtunnel(url.clone(), fingerprint.clone())
+            .with_connection_timeout(30_000)
+            .with_circuit_timeout(90_000);
+        let client = TorClient::new(options).await.unwrap();
+
+        // Several concurrent requests open streams on shared and isolated circuits
+        let fetches = (0..4).map(|_| client.fetch("https://api.ipify.org?format=json"));
+        for result in futures::future::join_all(fetches).await {
+            let response = result.unwrap();
+            assert_eq!(response.status, 200);
+        }
+
+        client.close().await;
+        assert_eq!(client.get_circuit_status().await.total_circuits, 0);
+    }
+
+    assert_tasks_settle(baseline, "after closing networked clients").await;
+}